@@ -0,0 +1,74 @@
+//! This module provides an AEGIS-256 AEAD that is compatible with the `aead` traits used throughout `dexios-core`
+//!
+//! AEGIS-256 natively takes a 32-byte nonce, which wouldn't fit within the space reserved for nonces in a header.
+//!
+//! To keep the header layout identical across AEADs, we use a 24-byte nonce (the same length as XChaCha20-Poly1305) and zero-pad it to 32 bytes internally. 192 random bits are more than enough to make nonce collisions negligible.
+//!
+//! This also means stream mode nonces are 20 bytes, as `aead::StreamLE31` uses the last 4 bytes.
+
+use aead::consts::{U0, U16, U24, U32};
+use aead::generic_array::GenericArray;
+use aead::{AeadCore, AeadInPlace, KeyInit, KeySizeUser};
+use zeroize::Zeroize;
+
+/// The length of the nonce that AEGIS-256 itself expects
+const NATIVE_NONCE_LEN: usize = 32;
+
+/// AEGIS-256, with a 128-bit tag
+pub struct Aegis256 {
+    key: [u8; 32],
+}
+
+impl Aegis256 {
+    fn cipher(&self, nonce: &aead::Nonce<Self>) -> aegis::aegis256::Aegis256<16> {
+        let mut native_nonce = [0u8; NATIVE_NONCE_LEN];
+        native_nonce[..nonce.len()].copy_from_slice(nonce);
+        aegis::aegis256::Aegis256::<16>::new(&self.key, &native_nonce)
+    }
+}
+
+impl Drop for Aegis256 {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl KeySizeUser for Aegis256 {
+    type KeySize = U32;
+}
+
+impl KeyInit for Aegis256 {
+    fn new(key: &aead::Key<Self>) -> Self {
+        Self { key: (*key).into() }
+    }
+}
+
+impl AeadCore for Aegis256 {
+    type NonceSize = U24;
+    type TagSize = U16;
+    type CiphertextOverhead = U0;
+}
+
+impl AeadInPlace for Aegis256 {
+    fn encrypt_in_place_detached(
+        &self,
+        nonce: &aead::Nonce<Self>,
+        associated_data: &[u8],
+        buffer: &mut [u8],
+    ) -> aead::Result<aead::Tag<Self>> {
+        let tag = self.cipher(nonce).encrypt_in_place(buffer, associated_data);
+        Ok(GenericArray::from(tag))
+    }
+
+    fn decrypt_in_place_detached(
+        &self,
+        nonce: &aead::Nonce<Self>,
+        associated_data: &[u8],
+        buffer: &mut [u8],
+        tag: &aead::Tag<Self>,
+    ) -> aead::Result<()> {
+        self.cipher(nonce)
+            .decrypt_in_place(buffer, &(*tag).into(), associated_data)
+            .map_err(|_| aead::Error)
+    }
+}
@@ -0,0 +1,203 @@
+//! This module allows library consumers to plug in their own AEADs, without needing to fork `dexios-core`
+//!
+//! A custom AEAD is described by an `AeadBackend`, and registered with `register()`. Once registered, it may be used anywhere an `Algorithm` is expected with `Algorithm::Custom(id)`.
+//!
+//! Custom AEADs are stored in the header as `[0x0F, id]`, so the same backend must be registered before the data is decrypted.
+//!
+//! Backends must:
+//! * accept a 32-byte key
+//! * use a 128-bit tag (the encrypted master key and stream blocks are a fixed size)
+//! * use a nonce between 5 and 24 bytes long (4 bytes are reserved for the stream counter in stream mode)
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! struct MyBackend;
+//!
+//! impl AeadBackend for MyBackend {
+//!     fn id(&self) -> u8 {
+//!         0x01
+//!     }
+//!
+//!     fn name(&self) -> &str {
+//!         "My-AEAD"
+//!     }
+//!
+//!     fn nonce_len(&self) -> usize {
+//!         24
+//!     }
+//!
+//!     fn initialize(&self, key: Protected<[u8; 32]>) -> anyhow::Result<Box<dyn AeadCipher>> {
+//!         Ok(Box::new(MyAead::new_from_slice(key.expose())?))
+//!     }
+//! }
+//!
+//! register(Arc::new(MyBackend)).unwrap();
+//!
+//! let header_type = HeaderType {
+//!     version: HEADER_VERSION,
+//!     algorithm: Algorithm::Custom(0x01),
+//!     mode: Mode::StreamMode,
+//! };
+//! ```
+
+use std::sync::{Arc, RwLock};
+
+use aead::generic_array::{typenum::Unsigned, GenericArray};
+use aead::{Aead, Payload};
+
+use crate::protected::Protected;
+
+/// The first byte of a custom algorithm's identifier, within the header
+pub const CUSTOM_ALGORITHM_PREFIX: u8 = 0x0F;
+
+/// This is an initialized AEAD, that can be used for encrypting and decrypting
+///
+/// It's implemented for every type that implements `aead::Aead`, so most `RustCrypto` AEADs may be returned from `AeadBackend::initialize()` as-is.
+pub trait AeadCipher {
+    /// `nonce` will always be the length that was returned from `AeadBackend::nonce_len()`
+    fn encrypt(&self, nonce: &[u8], payload: Payload<'_, '_>) -> aead::Result<Vec<u8>>;
+
+    /// `nonce` will always be the length that was returned from `AeadBackend::nonce_len()`
+    fn decrypt(&self, nonce: &[u8], payload: Payload<'_, '_>) -> aead::Result<Vec<u8>>;
+}
+
+impl<A: Aead> AeadCipher for A {
+    fn encrypt(&self, nonce: &[u8], payload: Payload<'_, '_>) -> aead::Result<Vec<u8>> {
+        if nonce.len() != A::NonceSize::to_usize() {
+            return Err(aead::Error);
+        }
+        Aead::encrypt(self, GenericArray::from_slice(nonce), payload)
+    }
+
+    fn decrypt(&self, nonce: &[u8], payload: Payload<'_, '_>) -> aead::Result<Vec<u8>> {
+        if nonce.len() != A::NonceSize::to_usize() {
+            return Err(aead::Error);
+        }
+        Aead::decrypt(self, GenericArray::from_slice(nonce), payload)
+    }
+}
+
+/// This describes a custom AEAD, so that it may be registered with `register()`
+pub trait AeadBackend: Send + Sync {
+    /// This is stored in the header, and must be unique across all registered backends
+    fn id(&self) -> u8;
+
+    /// This is used when displaying the algorithm
+    fn name(&self) -> &str;
+
+    /// This is the full length of the nonce, as used in memory mode
+    fn nonce_len(&self) -> usize;
+
+    /// This initializes the AEAD with a hashed key/master key
+    fn initialize(&self, key: Protected<[u8; 32]>) -> anyhow::Result<Box<dyn AeadCipher>>;
+}
+
+// the nonce length is read once (when the backend is registered), so that the header layout can't change afterwards
+static BACKENDS: RwLock<Vec<(Arc<dyn AeadBackend>, usize)>> = RwLock::new(Vec::new());
+
+/// This registers a custom AEAD, so that it can be used with `Algorithm::Custom(id)`
+///
+/// It will return an error if the backend's ID has already been registered, or if its nonce length isn't supported.
+pub fn register(backend: Arc<dyn AeadBackend>) -> anyhow::Result<()> {
+    let nonce_len = backend.nonce_len();
+    if !(5..=24).contains(&nonce_len) {
+        return Err(anyhow::anyhow!(
+            "Nonce length of custom AEAD must be between 5 and 24 bytes"
+        ));
+    }
+
+    let mut backends = BACKENDS
+        .write()
+        .map_err(|_| anyhow::anyhow!("Unable to access registered AEADs"))?;
+
+    if backends.iter().any(|(b, _)| b.id() == backend.id()) {
+        return Err(anyhow::anyhow!(
+            "A custom AEAD with ID {:#04x} has already been registered",
+            backend.id()
+        ));
+    }
+
+    backends.push((backend, nonce_len));
+    Ok(())
+}
+
+/// This returns the registered backend with the specified ID, if there is one
+#[must_use]
+pub fn get(id: u8) -> Option<Arc<dyn AeadBackend>> {
+    BACKENDS.read().ok().and_then(|backends| {
+        backends
+            .iter()
+            .find(|(b, _)| b.id() == id)
+            .map(|(b, _)| b.clone())
+    })
+}
+
+/// This returns the nonce length that the backend with the specified ID had when it was registered
+pub(crate) fn nonce_len(id: u8) -> Option<usize> {
+    BACKENDS.read().ok().and_then(|backends| {
+        backends
+            .iter()
+            .find(|(b, _)| b.id() == id)
+            .map(|(_, len)| *len)
+    })
+}
+
+/// This initializes a registered backend, and is used by `Ciphers` and the streams
+pub(crate) fn initialize(id: u8, key: Protected<[u8; 32]>) -> anyhow::Result<Box<dyn AeadCipher>> {
+    get(id)
+        .ok_or_else(|| anyhow::anyhow!("Custom AEAD {:#04x} hasn't been registered", id))?
+        .initialize(key)
+}
+
+/// This mirrors `aead::stream::StreamLE31`, so that custom AEADs may be used in stream mode
+///
+/// The last 4 bytes of each nonce contain a 31-bit little endian counter, and a 1-bit "last block" flag
+pub struct CustomStream {
+    cipher: Box<dyn AeadCipher>,
+    nonce: Vec<u8>,
+    position: u32,
+}
+
+impl CustomStream {
+    const COUNTER_MAX: u32 = 0x7fff_ffff;
+
+    pub(crate) fn new(cipher: Box<dyn AeadCipher>, nonce: &[u8]) -> Self {
+        Self {
+            cipher,
+            nonce: nonce.to_vec(),
+            position: 0,
+        }
+    }
+
+    fn next_nonce(&mut self, last_block: bool) -> aead::Result<Vec<u8>> {
+        if self.position > Self::COUNTER_MAX {
+            return Err(aead::Error);
+        }
+
+        let position_with_flag = self.position | (u32::from(last_block) << 31);
+        let mut nonce = self.nonce.clone();
+        nonce.extend_from_slice(&position_with_flag.to_le_bytes());
+
+        self.position = self.position.checked_add(1).ok_or(aead::Error)?;
+        Ok(nonce)
+    }
+
+    pub(crate) fn encrypt(
+        &mut self,
+        payload: Payload<'_, '_>,
+        last_block: bool,
+    ) -> aead::Result<Vec<u8>> {
+        let nonce = self.next_nonce(last_block)?;
+        self.cipher.encrypt(&nonce, payload)
+    }
+
+    pub(crate) fn decrypt(
+        &mut self,
+        payload: Payload<'_, '_>,
+        last_block: bool,
+    ) -> aead::Result<Vec<u8>> {
+        let nonce = self.next_nonce(last_block)?;
+        self.cipher.decrypt(&nonce, payload)
+    }
+}
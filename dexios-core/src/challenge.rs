@@ -0,0 +1,86 @@
+//! This module allows a raw key to be bound to a hardware token, through HMAC challenge-response (such as a YubiKey's HMAC-SHA1 slot)
+//!
+//! Once a responder has been registered for a raw key, the salt is sent to it as a challenge whenever that exact key is hashed. The response is appended to the raw key before it's passed to the KDF, so the token is needed to derive the key for every keyslot.
+//!
+//! Any other raw keys (such as extra keyfiles, or a recovery code) are hashed as usual, so they don't depend on the token.
+//!
+//! Responses are cached for each salt, so that a token which requires a touch is only challenged once per keyslot.
+//!
+//! The registration is process-wide, and only one raw key may be registered at a time.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! let raw_key = Protected::new(b"token".to_vec());
+//! register(&raw_key, Box::new(responder));
+//!
+//! // the responder is challenged with the salt, and the response is mixed into the key
+//! let key = hashing_algorithm.hash(raw_key, &salt).unwrap();
+//! ```
+
+use std::sync::Mutex;
+
+use crate::primitives::SALT_LEN;
+use crate::protected::Protected;
+
+/// This is implemented by anything that can compute a keyed response to a challenge
+pub trait Responder: Send {
+    fn respond(&mut self, challenge: &[u8]) -> anyhow::Result<Protected<Vec<u8>>>;
+}
+
+struct Registration {
+    key_hash: blake3::Hash,
+    responder: Box<dyn Responder>,
+    responses: Vec<([u8; SALT_LEN], Protected<Vec<u8>>)>,
+}
+
+static REGISTRATION: Mutex<Option<Registration>> = Mutex::new(None);
+
+/// This registers a responder for the provided raw key, replacing any previous registration
+///
+/// Only a hash of the raw key is kept, and it's used to recognise the key when it's hashed.
+pub fn register(raw_key: &Protected<Vec<u8>>, responder: Box<dyn Responder>) {
+    let registration = Registration {
+        key_hash: blake3::hash(raw_key.expose()),
+        responder,
+        responses: Vec::new(),
+    };
+
+    *REGISTRATION
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner) = Some(registration);
+}
+
+/// This appends the response to the salt to the raw key, if a responder was registered for it
+///
+/// Any other raw key is returned as-is.
+pub(crate) fn mix(
+    raw_key: Protected<Vec<u8>>,
+    salt: &[u8; SALT_LEN],
+) -> anyhow::Result<Protected<Vec<u8>>> {
+    let mut guard = REGISTRATION
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+    let registration = match guard.as_mut() {
+        Some(registration) if registration.key_hash == blake3::hash(raw_key.expose()) => {
+            registration
+        }
+        _ => return Ok(raw_key),
+    };
+
+    let response = match registration.responses.iter().find(|(s, _)| s == salt) {
+        Some((_, response)) => response.clone(),
+        None => {
+            let response = registration.responder.respond(salt)?;
+            registration.responses.push((*salt, response.clone()));
+            response
+        }
+    };
+
+    let mut mixed = raw_key.expose().clone();
+    mixed.extend_from_slice(response.expose());
+    drop(raw_key);
+
+    Ok(Protected::new(mixed))
+}
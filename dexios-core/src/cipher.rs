@@ -22,10 +22,16 @@
 
 use aead::{Aead, AeadInPlace, KeyInit, Payload};
 use aes_gcm::Aes256Gcm;
-use chacha20poly1305::XChaCha20Poly1305;
+use ascon_aead::Ascon128a;
+use chacha20poly1305::{ChaCha20Poly1305, XChaCha20Poly1305};
 use deoxys::DeoxysII256;
 
-use crate::primitives::Algorithm;
+use anyhow::Result;
+use zeroize::Zeroize;
+
+use crate::aegis::Aegis256;
+use crate::header::Header;
+use crate::primitives::{Algorithm, Mode, ASCON_KEY_LEN, MASTER_KEY_LEN};
 use crate::protected::Protected;
 
 /// This `enum` defines all possible cipher types, for each AEAD that is supported by `dexios-core`
@@ -33,6 +39,10 @@ pub enum Ciphers {
     Aes256Gcm(Box<Aes256Gcm>),
     XChaCha(Box<XChaCha20Poly1305>),
     DeoxysII(Box<DeoxysII256>),
+    Aegis256(Box<Aegis256>),
+    ChaCha(Box<ChaCha20Poly1305>),
+    Ascon128a(Box<Ascon128a>),
+    Custom(Box<dyn crate::backend::AeadCipher>),
 }
 
 impl Ciphers {
@@ -54,6 +64,10 @@ impl Ciphers {
     pub fn initialize(key: Protected<[u8; 32]>, algorithm: &Algorithm) -> anyhow::Result<Self> {
         let cipher = match algorithm {
             Algorithm::Aes256Gcm => {
+                if let Some(cipher) = crate::os_crypto::aes256gcm(&key)? {
+                    return Ok(Ciphers::Custom(cipher));
+                }
+
                 let cipher = Aes256Gcm::new_from_slice(key.expose())
                     .map_err(|_| anyhow::anyhow!("Unable to create cipher with hashed key."))?;
 
@@ -71,6 +85,27 @@ impl Ciphers {
 
                 Ciphers::DeoxysII(Box::new(cipher))
             }
+            Algorithm::Aegis256 => {
+                let cipher = Aegis256::new_from_slice(key.expose())
+                    .map_err(|_| anyhow::anyhow!("Unable to create cipher with hashed key."))?;
+
+                Ciphers::Aegis256(Box::new(cipher))
+            }
+            Algorithm::ChaCha20Poly1305 => {
+                let cipher = ChaCha20Poly1305::new_from_slice(key.expose())
+                    .map_err(|_| anyhow::anyhow!("Unable to create cipher with hashed key."))?;
+
+                Ciphers::ChaCha(Box::new(cipher))
+            }
+            Algorithm::Ascon128a => {
+                let cipher = Ascon128a::new_from_slice(&key.expose()[..ASCON_KEY_LEN])
+                    .map_err(|_| anyhow::anyhow!("Unable to create cipher with hashed key."))?;
+
+                Ciphers::Ascon128a(Box::new(cipher))
+            }
+            Algorithm::Custom(id) => {
+                return Ok(Ciphers::Custom(crate::backend::initialize(*id, key)?));
+            }
         };
 
         drop(key);
@@ -89,6 +124,10 @@ impl Ciphers {
             Ciphers::Aes256Gcm(c) => c.encrypt(nonce.as_ref().into(), plaintext),
             Ciphers::XChaCha(c) => c.encrypt(nonce.as_ref().into(), plaintext),
             Ciphers::DeoxysII(c) => c.encrypt(nonce.as_ref().into(), plaintext),
+            Ciphers::Aegis256(c) => c.encrypt(nonce.as_ref().into(), plaintext),
+            Ciphers::ChaCha(c) => c.encrypt(nonce.as_ref().into(), plaintext),
+            Ciphers::Ascon128a(c) => c.encrypt(nonce.as_ref().into(), plaintext),
+            Ciphers::Custom(c) => c.encrypt(nonce, plaintext.into()),
         }
     }
 
@@ -102,6 +141,20 @@ impl Ciphers {
             Ciphers::Aes256Gcm(c) => c.encrypt_in_place(nonce.as_ref().into(), aad, buffer),
             Ciphers::XChaCha(c) => c.encrypt_in_place(nonce.as_ref().into(), aad, buffer),
             Ciphers::DeoxysII(c) => c.encrypt_in_place(nonce.as_ref().into(), aad, buffer),
+            Ciphers::Aegis256(c) => c.encrypt_in_place(nonce.as_ref().into(), aad, buffer),
+            Ciphers::ChaCha(c) => c.encrypt_in_place(nonce.as_ref().into(), aad, buffer),
+            Ciphers::Ascon128a(c) => c.encrypt_in_place(nonce.as_ref().into(), aad, buffer),
+            Ciphers::Custom(c) => {
+                let encrypted = c.encrypt(
+                    nonce,
+                    Payload {
+                        msg: buffer.as_ref(),
+                        aad,
+                    },
+                )?;
+                buffer.truncate(0);
+                buffer.extend_from_slice(&encrypted)
+            }
         }
     }
 
@@ -119,6 +172,101 @@ impl Ciphers {
             Ciphers::Aes256Gcm(c) => c.decrypt(nonce.as_ref().into(), ciphertext),
             Ciphers::XChaCha(c) => c.decrypt(nonce.as_ref().into(), ciphertext),
             Ciphers::DeoxysII(c) => c.decrypt(nonce.as_ref().into(), ciphertext),
+            Ciphers::Aegis256(c) => c.decrypt(nonce.as_ref().into(), ciphertext),
+            Ciphers::ChaCha(c) => c.decrypt(nonce.as_ref().into(), ciphertext),
+            Ciphers::Ascon128a(c) => c.decrypt(nonce.as_ref().into(), ciphertext),
+            Ciphers::Custom(c) => c.decrypt(nonce, ciphertext.into()),
+        }
+    }
+
+    /// This can be used to decrypt data in-place with a given `Ciphers` object
+    ///
+    /// The buffer must contain the ciphertext (including the tag), and it'll contain the plaintext once decrypted
+    pub fn decrypt_in_place(
+        &self,
+        nonce: &[u8],
+        aad: &[u8],
+        buffer: &mut dyn aead::Buffer,
+    ) -> Result<(), aead::Error> {
+        match self {
+            Ciphers::Aes256Gcm(c) => c.decrypt_in_place(nonce.as_ref().into(), aad, buffer),
+            Ciphers::XChaCha(c) => c.decrypt_in_place(nonce.as_ref().into(), aad, buffer),
+            Ciphers::DeoxysII(c) => c.decrypt_in_place(nonce.as_ref().into(), aad, buffer),
+            Ciphers::Aegis256(c) => c.decrypt_in_place(nonce.as_ref().into(), aad, buffer),
+            Ciphers::ChaCha(c) => c.decrypt_in_place(nonce.as_ref().into(), aad, buffer),
+            Ciphers::Ascon128a(c) => c.decrypt_in_place(nonce.as_ref().into(), aad, buffer),
+            Ciphers::Custom(c) => {
+                let mut decrypted = c.decrypt(
+                    nonce,
+                    Payload {
+                        msg: buffer.as_ref(),
+                        aad,
+                    },
+                )?;
+                buffer.truncate(0);
+                let result = buffer.extend_from_slice(&decrypted);
+                decrypted.zeroize();
+                result
+            }
         }
     }
 }
+
+/// This decrypts memory mode data into a buffer that's provided by the caller
+///
+/// It requires the header (V3+) that the data was encrypted with, and the master key (see `crate::key::decrypt_master_key()`)
+///
+/// The data is decrypted in-place within `out`, so no intermediate buffers that contain plaintext are allocated. `out` is zeroized before it's used, and if decryption fails.
+///
+/// The caller is responsible for zeroizing `out` once they're finished with the plaintext.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let (header, _) = Header::deserialize(&mut reader).unwrap();
+/// let master_key = decrypt_master_key(raw_key, &header).unwrap();
+///
+/// let mut plaintext = Vec::new();
+/// decrypt_in_memory(&header, &master_key, &ciphertext, &mut plaintext).unwrap();
+/// ```
+///
+pub fn decrypt_in_memory(
+    header: &Header,
+    key: &Protected<[u8; MASTER_KEY_LEN]>,
+    ciphertext: &[u8],
+    out: &mut Vec<u8>,
+) -> Result<()> {
+    if header.header_type.mode != Mode::MemoryMode {
+        return Err(anyhow::anyhow!(
+            "Only memory mode data may be decrypted in memory"
+        ));
+    }
+
+    let aad = header.create_aad()?;
+    let cipher = Ciphers::initialize(key.clone(), &header.header_type.algorithm)?;
+
+    // this is done before reserving, so no plaintext is left behind if `out` is reallocated
+    out.zeroize();
+    out.reserve(ciphertext.len());
+    out.extend_from_slice(ciphertext);
+
+    cipher
+        .decrypt_in_place(&header.nonce, &aad, out)
+        .map_err(|_| {
+            out.zeroize();
+            anyhow::anyhow!("Unable to decrypt the data (maybe the key is wrong?)")
+        })
+}
+
+/// This decrypts memory mode data, and returns the plaintext within a `Protected` wrapper
+///
+/// This is identical to `decrypt_in_memory()`, but the plaintext is zeroized automatically once it's dropped
+pub fn decrypt_in_memory_protected(
+    header: &Header,
+    key: &Protected<[u8; MASTER_KEY_LEN]>,
+    ciphertext: &[u8],
+) -> Result<Protected<Vec<u8>>> {
+    let mut out = Vec::with_capacity(ciphertext.len());
+    decrypt_in_memory(header, key, ciphertext, &mut out)?;
+    Ok(Protected::new(out))
+}
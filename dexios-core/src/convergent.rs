@@ -0,0 +1,80 @@
+//! This module contains the derivation used for convergent (deterministic) encryption
+//!
+//! Normally, the master key, salt and nonces are randomly generated for every encryption. In convergent mode, they're all derived from a keyed `BLAKE3` hash of the plaintext instead, so encrypting the same data with the same key will always produce identical output. This allows storage backends to deduplicate encrypted files.
+//!
+//! **WARNING:** this leaks whether two files are identical, and anyone who knows the key can confirm whether a file contains a guessed plaintext. Only use this if deduplication is worth that trade-off.
+//!
+//! The hash is keyed with a key that's derived from the stretched (hashed) key, so files encrypted with different keys are unrelated, and guessing the key is as slow as it is for any other file.
+//!
+//! The salt that's stored in the header is derived from the plaintext too, so the key is stretched with `CONVERGENT_SALT` for this instead.
+
+use std::io::Read;
+
+use anyhow::Context;
+use zeroize::Zeroize;
+
+use crate::primitives::{get_nonce_len, Algorithm, Mode, MASTER_KEY_LEN, SALT_LEN};
+use crate::protected::Protected;
+
+/// This is used to derive the `BLAKE3` key from the stretched key
+const CONVERGENT_CONTEXT: &str = "dexios convergent encryption v2";
+
+/// This is the salt that the raw key is stretched with, before the `BLAKE3` key is derived from it
+pub const CONVERGENT_SALT: [u8; SALT_LEN] = *b"dexiosconvergent";
+
+/// These replace the values that are randomly generated while encrypting
+pub struct ConvergentSecrets {
+    pub master_key: Protected<[u8; MASTER_KEY_LEN]>,
+    pub salt: [u8; SALT_LEN],
+    pub master_key_nonce: Vec<u8>,
+    pub nonce: Vec<u8>,
+}
+
+impl ConvergentSecrets {
+    /// This hashes everything from `reader`, and derives the master key, salt and nonces from the hash
+    ///
+    /// `key` must be the raw key, after it's been hashed with `CONVERGENT_SALT`.
+    ///
+    /// The nonces will be the correct length for the algorithm and mode.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// let key = hashing_algorithm.hash(raw_key, &CONVERGENT_SALT).unwrap();
+    /// let secrets = ConvergentSecrets::derive(&key, &mut input_file, &Algorithm::XChaCha20Poly1305, &Mode::StreamMode).unwrap();
+    /// ```
+    ///
+    pub fn derive(
+        key: &Protected<[u8; 32]>,
+        reader: &mut impl Read,
+        algorithm: &Algorithm,
+        mode: &Mode,
+    ) -> anyhow::Result<Self> {
+        let mut key = blake3::derive_key(CONVERGENT_CONTEXT, key.expose());
+        let mut hasher = blake3::Hasher::new_keyed(&key);
+        key.zeroize();
+
+        std::io::copy(reader, &mut hasher).context("Unable to hash the plaintext")?;
+        let mut output = hasher.finalize_xof();
+        hasher.reset();
+
+        let mut master_key = [0u8; MASTER_KEY_LEN];
+        output.fill(&mut master_key);
+
+        let mut salt = [0u8; SALT_LEN];
+        output.fill(&mut salt);
+
+        let mut master_key_nonce = vec![0u8; get_nonce_len(algorithm, &Mode::MemoryMode)];
+        output.fill(&mut master_key_nonce);
+
+        let mut nonce = vec![0u8; get_nonce_len(algorithm, mode)];
+        output.fill(&mut nonce);
+
+        Ok(Self {
+            master_key: Protected::new(master_key),
+            salt,
+            master_key_nonce,
+            nonce,
+        })
+    }
+}
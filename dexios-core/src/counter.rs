@@ -0,0 +1,167 @@
+//! This module contains the stream used by `StreamCounter::Be64`
+//!
+//! `aead::stream::StreamLE31` limits a stream to 2^31 blocks, which is 2 PiB with the default block size, but only 128 TiB with the smallest one. This is a variant with far more headroom.
+//!
+//! Each nonce ends with a 64-bit big endian counter, followed by a byte that's set to 1 on the final block (like `aead::stream::StreamBE32`). These 9 bytes replace the final 5 bytes of the header's nonce, so the header's nonce keeps the same length as it does with `StreamCounter::Le31`.
+//!
+//! The rest of the nonce is random, and it's all that separates one file's nonces from another's (as every file has its own key, unless `--convergent` or a detached master key is used). It needs to be at least `MIN_PREFIX_LEN` bytes, so AEADs with shorter nonces (e.g. AES-256-GCM's 96-bit nonce, which would leave just 3 random bytes) can't use this counter.
+
+use aead::Payload;
+
+use crate::cipher::Ciphers;
+use crate::primitives::{get_nonce_len, Algorithm, Mode, MASTER_KEY_LEN};
+use crate::protected::Protected;
+
+/// This is how much of the nonce is taken up by the counter and the "last block" flag, in addition to the 4 bytes that LE31 uses
+const EXTRA_COUNTER_LEN: usize = 5;
+
+/// This is the shortest random prefix that's allowed, which matches what `StreamCounter::Le31` leaves with a 96-bit nonce
+pub const MIN_PREFIX_LEN: usize = 8;
+
+/// This returns whether the AEAD's nonce is long enough for a 64-bit counter (see `MIN_PREFIX_LEN`)
+#[must_use]
+pub fn is_supported(algorithm: &Algorithm) -> bool {
+    get_nonce_len(algorithm, &Mode::StreamMode)
+        .checked_sub(EXTRA_COUNTER_LEN)
+        .is_some_and(|len| len >= MIN_PREFIX_LEN)
+}
+
+pub struct Be64Stream {
+    cipher: Ciphers,
+    prefix: Vec<u8>,
+    position: u64,
+}
+
+impl Be64Stream {
+    /// This requires the header's nonce, which is the same length as it would be with `StreamCounter::Le31`
+    pub(crate) fn new(
+        key: Protected<[u8; MASTER_KEY_LEN]>,
+        nonce: &[u8],
+        algorithm: &Algorithm,
+    ) -> anyhow::Result<Self> {
+        if nonce.len() != get_nonce_len(algorithm, &Mode::StreamMode) {
+            return Err(anyhow::anyhow!("Nonce is not the correct length"));
+        }
+
+        if !is_supported(algorithm) {
+            return Err(anyhow::anyhow!(
+                "{}'s nonce is too short for a 64-bit counter",
+                algorithm
+            ));
+        }
+
+        let prefix_len = nonce.len() - EXTRA_COUNTER_LEN;
+
+        Ok(Self {
+            cipher: Ciphers::initialize(key, algorithm)?,
+            prefix: nonce[..prefix_len].to_vec(),
+            position: 0,
+        })
+    }
+
+    /// This builds the nonce for the block at `position`
+    fn nonce_at(&self, position: u64, last_block: bool) -> Vec<u8> {
+        let mut nonce = self.prefix.clone();
+        nonce.extend_from_slice(&position.to_be_bytes());
+        nonce.push(u8::from(last_block));
+        nonce
+    }
+
+    fn next_nonce(&mut self, last_block: bool) -> aead::Result<Vec<u8>> {
+        let nonce = self.nonce_at(self.position, last_block);
+        self.position = self.position.checked_add(1).ok_or(aead::Error)?;
+        Ok(nonce)
+    }
+
+    pub(crate) fn encrypt(
+        &mut self,
+        payload: Payload<'_, '_>,
+        last_block: bool,
+    ) -> aead::Result<Vec<u8>> {
+        let nonce = self.next_nonce(last_block)?;
+        self.cipher.encrypt(&nonce, payload)
+    }
+
+    pub(crate) fn decrypt(
+        &mut self,
+        payload: Payload<'_, '_>,
+        last_block: bool,
+    ) -> aead::Result<Vec<u8>> {
+        let nonce = self.next_nonce(last_block)?;
+        self.cipher.decrypt(&nonce, payload)
+    }
+
+    /// This decrypts the block at `position`, without affecting the stream's position (see `crate::seekable`)
+    pub(crate) fn decrypt_at(
+        &self,
+        position: u64,
+        payload: Payload<'_, '_>,
+        last_block: bool,
+    ) -> aead::Result<Vec<u8>> {
+        self.cipher
+            .decrypt(&self.nonce_at(position, last_block), payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(algorithm: &Algorithm, nonce: &[u8]) -> Be64Stream {
+        Be64Stream::new(Protected::new([7u8; MASTER_KEY_LEN]), nonce, algorithm).unwrap()
+    }
+
+    #[test]
+    fn should_replace_the_end_of_the_nonce_with_the_counter() {
+        let nonce = vec![9u8; get_nonce_len(&Algorithm::XChaCha20Poly1305, &Mode::StreamMode)];
+        let stream = stream(&Algorithm::XChaCha20Poly1305, &nonce);
+
+        let block_nonce = stream.nonce_at(0x0102, true);
+        assert_eq!(block_nonce.len(), nonce.len() + 4);
+        assert_eq!(
+            block_nonce[..nonce.len() - EXTRA_COUNTER_LEN],
+            nonce[..nonce.len() - EXTRA_COUNTER_LEN]
+        );
+        assert_eq!(
+            block_nonce[nonce.len() - EXTRA_COUNTER_LEN..],
+            [0, 0, 0, 0, 0, 0, 0x01, 0x02, 0x01]
+        );
+    }
+
+    #[test]
+    fn should_refuse_nonces_without_room_for_a_prefix() {
+        let key = || Protected::new([7u8; MASTER_KEY_LEN]);
+
+        // the nonce must be the same length as it would be in regular stream mode
+        assert!(Be64Stream::new(key(), &[9u8; 24], &Algorithm::XChaCha20Poly1305).is_err());
+
+        // an unregistered backend has no nonce, so nothing would be left for the prefix
+        assert!(Be64Stream::new(key(), &[], &Algorithm::Custom(0xFF)).is_err());
+    }
+
+    #[test]
+    fn should_decrypt_blocks_in_order_or_by_position() {
+        let algorithm = Algorithm::XChaCha20Poly1305;
+        let nonce = vec![9u8; get_nonce_len(&algorithm, &Mode::StreamMode)];
+        let payload = |msg| Payload { msg, aad: b"aad" };
+
+        let mut encryptor = stream(&algorithm, &nonce);
+        let first = encryptor.encrypt(payload(b"first"), false).unwrap();
+        let last = encryptor.encrypt(payload(b"last"), true).unwrap();
+
+        let mut decryptor = stream(&algorithm, &nonce);
+        assert_eq!(decryptor.decrypt(payload(&first), false).unwrap(), b"first");
+        assert_eq!(decryptor.decrypt(payload(&last), true).unwrap(), b"last");
+
+        let decryptor = stream(&algorithm, &nonce);
+        assert_eq!(
+            decryptor.decrypt_at(1, payload(&last), true).unwrap(),
+            b"last"
+        );
+
+        // blocks can't be reordered, or have their final flag changed
+        assert!(decryptor.decrypt_at(0, payload(&last), true).is_err());
+        assert!(decryptor.decrypt_at(1, payload(&last), false).is_err());
+        assert!(decryptor.decrypt_at(0, payload(&first), true).is_err());
+    }
+}
@@ -0,0 +1,141 @@
+//! This module contains the stream used by `Mode::DerivedStreamMode`
+//!
+//! Regular stream mode relies on the header's nonce never being reused with the same master key. If a header is cloned (or a nonce is reused by a buggy implementation), two files could be encrypted with the same key/nonce pairs.
+//!
+//! In derived stream mode, every block is encrypted with its own subkey. Each subkey is derived with HKDF-SHA256, from the master key and a fresh random salt that's stored in front of the block, along with the header's nonce and the block's position.
+//!
+//! This means that reusing a header can't result in key/nonce reuse, at the cost of `DERIVED_SALT_LEN` bytes per block.
+//!
+//! The nonces still follow the `aead::stream::StreamLE31` layout, so blocks can't be reordered, dropped or truncated.
+
+use aead::Payload;
+use hkdf::Hkdf;
+use rand::{prelude::ThreadRng, RngCore};
+use sha2::Sha256;
+use zeroize::Zeroize;
+
+use crate::cipher::Ciphers;
+use crate::primitives::{Algorithm, MASTER_KEY_LEN};
+use crate::protected::Protected;
+
+/// This is the length of the random salt that's stored in front of every block
+pub const DERIVED_SALT_LEN: usize = 32;
+
+/// This is used as the HKDF info, along with the header's nonce and the block's position
+const DERIVED_STREAM_CONTEXT: &[u8] = b"dexios derived stream mode v1";
+
+pub struct DerivedStream {
+    master_key: Protected<[u8; MASTER_KEY_LEN]>,
+    nonce: Vec<u8>,
+    algorithm: Algorithm,
+    position: u32,
+}
+
+impl DerivedStream {
+    const COUNTER_MAX: u32 = 0x7fff_ffff;
+
+    pub(crate) fn new(
+        master_key: Protected<[u8; MASTER_KEY_LEN]>,
+        nonce: &[u8],
+        algorithm: Algorithm,
+    ) -> Self {
+        Self {
+            master_key,
+            nonce: nonce.to_vec(),
+            algorithm,
+            position: 0,
+        }
+    }
+
+    /// This derives the cipher and nonce for the block at `position`
+    fn cipher_at(
+        &self,
+        position: u32,
+        salt: &[u8],
+        last_block: bool,
+    ) -> aead::Result<(Ciphers, Vec<u8>)> {
+        if position > Self::COUNTER_MAX {
+            return Err(aead::Error);
+        }
+
+        let position_with_flag = (position | (u32::from(last_block) << 31)).to_le_bytes();
+
+        let mut info = DERIVED_STREAM_CONTEXT.to_vec();
+        info.extend_from_slice(&self.nonce);
+        info.extend_from_slice(&position_with_flag);
+
+        let mut subkey = [0u8; MASTER_KEY_LEN];
+        let expanded =
+            Hkdf::<Sha256>::new(Some(salt), self.master_key.expose()).expand(&info, &mut subkey);
+
+        // the array is copied into `Protected`, so the copy on the stack needs zeroizing too
+        let protected_subkey = Protected::new(subkey);
+        subkey.zeroize();
+        expanded.map_err(|_| aead::Error)?;
+
+        let cipher =
+            Ciphers::initialize(protected_subkey, &self.algorithm).map_err(|_| aead::Error)?;
+
+        let mut nonce = self.nonce.clone();
+        nonce.extend_from_slice(&position_with_flag);
+
+        Ok((cipher, nonce))
+    }
+
+    /// This derives the cipher and nonce for the next block, and advances the position
+    fn next_cipher(&mut self, salt: &[u8], last_block: bool) -> aead::Result<(Ciphers, Vec<u8>)> {
+        let cipher = self.cipher_at(self.position, salt, last_block)?;
+        self.position = self.position.checked_add(1).ok_or(aead::Error)?;
+        Ok(cipher)
+    }
+
+    /// The returned block contains the salt, followed by the ciphertext
+    pub(crate) fn encrypt(
+        &mut self,
+        payload: Payload<'_, '_>,
+        last_block: bool,
+    ) -> aead::Result<Vec<u8>> {
+        let mut salt = [0u8; DERIVED_SALT_LEN];
+        ThreadRng::default().fill_bytes(&mut salt);
+
+        let (cipher, nonce) = self.next_cipher(&salt, last_block)?;
+
+        let mut block = salt.to_vec();
+        block.extend_from_slice(&cipher.encrypt(&nonce, payload)?);
+        Ok(block)
+    }
+
+    /// This expects the salt, followed by the ciphertext (as returned by `encrypt()`)
+    pub(crate) fn decrypt(
+        &mut self,
+        payload: Payload<'_, '_>,
+        last_block: bool,
+    ) -> aead::Result<Vec<u8>> {
+        let plaintext = self.decrypt_at(self.position, payload, last_block)?;
+        self.position = self.position.checked_add(1).ok_or(aead::Error)?;
+        Ok(plaintext)
+    }
+
+    /// This decrypts the block at `position`, without affecting the stream's position (see `crate::seekable`)
+    pub(crate) fn decrypt_at(
+        &self,
+        position: u32,
+        payload: Payload<'_, '_>,
+        last_block: bool,
+    ) -> aead::Result<Vec<u8>> {
+        if payload.msg.len() < DERIVED_SALT_LEN {
+            return Err(aead::Error);
+        }
+
+        let (salt, msg) = payload.msg.split_at(DERIVED_SALT_LEN);
+        let (cipher, nonce) = self.cipher_at(position, salt, last_block)?;
+
+        cipher.decrypt(
+            &nonce,
+            Payload {
+                msg,
+                aad: payload.aad,
+            },
+        )
+    }
+}
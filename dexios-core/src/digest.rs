@@ -0,0 +1,133 @@
+//! This module contains the plaintext digest that may be stored in a V6 header
+//!
+//! The AEAD only proves that each block is what the encryptor produced. If the plaintext was already corrupted while it was being read (e.g. by faulty storage or memory), it'll still decrypt without any errors.
+//!
+//! A `BLAKE3` hash of the plaintext is calculated while it's being encrypted, and it's stored in the header once encryption has finished. After decrypting, the output is hashed again and compared against it.
+//!
+//! The digest is encrypted with a key that's derived from the master key, as a plain hash would allow anyone to confirm whether a file contains a guessed plaintext. That key is only ever used once, so the nonce is fixed.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! let digest_key = derive_key(&master_key);
+//!
+//! let mut reader = DigestReader::new(&mut input_file);
+//! encrypt_stream.encrypt_file(&mut reader, &mut output_file, &aad, header.block_size).unwrap();
+//!
+//! let encrypted_digest = encrypt(digest_key, &header.header_type.algorithm, &reader.finalize()).unwrap();
+//! ```
+
+use std::io::{Read, Write};
+
+use crate::cipher::Ciphers;
+use crate::key::vec_to_arr;
+use crate::primitives::{get_nonce_len, Algorithm, Mode, MASTER_KEY_LEN};
+use crate::protected::Protected;
+
+/// This is the length of the encrypted digest that's stored in the header (the hash, and the AEAD's tag)
+pub const ENCRYPTED_DIGEST_LEN: usize = 48;
+
+/// This is used to derive the digest's key from the master key
+const DIGEST_CONTEXT: &str = "dexios plaintext digest v1";
+
+/// This derives the key that the digest is encrypted with
+///
+/// It needs to be called before the master key is handed to the streams, as they take ownership of it.
+#[must_use]
+pub fn derive_key(master_key: &Protected<[u8; MASTER_KEY_LEN]>) -> Protected<[u8; 32]> {
+    Protected::new(blake3::derive_key(DIGEST_CONTEXT, master_key.expose()))
+}
+
+// the key is unique to each file, and it only ever encrypts one message
+fn nonce(algorithm: &Algorithm) -> Vec<u8> {
+    vec![0u8; get_nonce_len(algorithm, &Mode::MemoryMode)]
+}
+
+/// This encrypts the digest, so that it can be stored in the header
+pub fn encrypt(
+    key: Protected<[u8; 32]>,
+    algorithm: &Algorithm,
+    digest: &blake3::Hash,
+) -> anyhow::Result<[u8; ENCRYPTED_DIGEST_LEN]> {
+    Ciphers::initialize(key, algorithm)?
+        .encrypt(&nonce(algorithm), digest.as_bytes().as_slice())
+        .map(vec_to_arr)
+        .map_err(|_| anyhow::anyhow!("Unable to encrypt the plaintext digest"))
+}
+
+/// This decrypts a digest that was read from the header
+///
+/// This will fail if the digest (or the key) is incorrect.
+pub fn decrypt(
+    key: Protected<[u8; 32]>,
+    algorithm: &Algorithm,
+    encrypted_digest: &[u8; ENCRYPTED_DIGEST_LEN],
+) -> anyhow::Result<blake3::Hash> {
+    Ciphers::initialize(key, algorithm)?
+        .decrypt(&nonce(algorithm), encrypted_digest.as_slice())
+        .map(vec_to_arr)
+        .map(blake3::Hash::from)
+        .map_err(|_| anyhow::anyhow!("Unable to decrypt the plaintext digest"))
+}
+
+/// This hashes everything that's read through it
+pub struct DigestReader<R: Read> {
+    inner: R,
+    hasher: blake3::Hasher,
+}
+
+impl<R: Read> DigestReader<R> {
+    #[must_use]
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: blake3::Hasher::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn finalize(&self) -> blake3::Hash {
+        self.hasher.finalize()
+    }
+}
+
+impl<R: Read> Read for DigestReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// This hashes everything that's written through it
+pub struct DigestWriter<W: Write> {
+    inner: W,
+    hasher: blake3::Hasher,
+}
+
+impl<W: Write> DigestWriter<W> {
+    #[must_use]
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: blake3::Hasher::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn finalize(&self) -> blake3::Hash {
+        self.hasher.finalize()
+    }
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,253 @@
+//! This module contains the file info that may be stored in a V6 header
+//!
+//! It records the original file's name, when it was last modified and its unix permissions, so they can be restored once it's decrypted. Unlike the `Metadata`, none of it is visible without a key.
+//!
+//! It's encrypted with a key that's derived from the header key (see `crate::subkeys`), just like the manifest (see `crate::manifest`), and stored in the header's `FILE_INFO_FIELD`.
+//!
+//! The key is unique to each file and it only ever encrypts one message, so the nonce is fixed (just like the digest's).
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! let info = FileInfo { name: "hello.txt".to_string(), modified: Some(Duration::from_secs(1_700_000_000)), mode: Some(0o644) };
+//! let value = encrypt(&subkeys.header, &header.header_type.algorithm, &info).unwrap();
+//!
+//! let header_key = decrypt_header_key(raw_key, &header).unwrap();
+//! let info = decrypt(&header_key, &header.header_type.algorithm, &value).unwrap();
+//! ```
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::cipher::Ciphers;
+use crate::primitives::{get_nonce_len, Algorithm, Mode};
+use crate::protected::Protected;
+
+/// This is the longest name that may be stored (in bytes)
+pub const MAX_NAME_LEN: usize = 1024;
+
+/// This is used to derive the file info's key from the header key
+const FILE_INFO_CONTEXT: &str = "dexios file info v1";
+
+const MODIFIED_FLAG: u8 = 0x01;
+const MODE_FLAG: u8 = 0x02;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FileInfo {
+    /// The file's name, without any of the directories that it was in
+    pub name: String,
+    /// When the file was last modified, since the unix epoch
+    pub modified: Option<Duration>,
+    /// The file's unix permissions (e.g. `0o644`), which aren't stored on other platforms
+    pub mode: Option<u32>,
+}
+
+impl FileInfo {
+    /// This checks that the name is a single, ordinary path component
+    ///
+    /// It's checked before encrypting and after decrypting, so a crafted header can't be used to write outside of the directory that the output is placed in.
+    pub fn validate(&self) -> Result<()> {
+        let valid = !self.name.is_empty()
+            && self.name.len() <= MAX_NAME_LEN
+            && self.name != "."
+            && self.name != ".."
+            && !self.name.contains(['/', '\\', '\0']);
+
+        if !valid {
+            return Err(anyhow::anyhow!(
+                "The file name within the header isn't a valid file name"
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+fn derive_key(header_key: &Protected<[u8; 32]>) -> Protected<[u8; 32]> {
+    Protected::new(blake3::derive_key(FILE_INFO_CONTEXT, header_key.expose()))
+}
+
+fn nonce(algorithm: &Algorithm) -> Vec<u8> {
+    vec![0u8; get_nonce_len(algorithm, &Mode::MemoryMode)]
+}
+
+/// This is stored as the name's length (u16 LE), the name and a byte of flags, followed by the modification time (u64 LE seconds and u32 LE nanoseconds) and the mode (u32 LE) if they're flagged
+fn serialize(info: &FileInfo) -> Vec<u8> {
+    let mut flags = 0u8;
+    if info.modified.is_some() {
+        flags |= MODIFIED_FLAG;
+    }
+    if info.mode.is_some() {
+        flags |= MODE_FLAG;
+    }
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&(info.name.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(info.name.as_bytes());
+    bytes.push(flags);
+    if let Some(modified) = info.modified {
+        bytes.extend_from_slice(&modified.as_secs().to_le_bytes());
+        bytes.extend_from_slice(&modified.subsec_nanos().to_le_bytes());
+    }
+    if let Some(mode) = info.mode {
+        bytes.extend_from_slice(&mode.to_le_bytes());
+    }
+    bytes
+}
+
+fn deserialize(mut bytes: &[u8]) -> Result<FileInfo> {
+    fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+        if bytes.len() < len {
+            return Err(anyhow::anyhow!("The file info is truncated"));
+        }
+        let (taken, rest) = bytes.split_at(len);
+        *bytes = rest;
+        Ok(taken)
+    }
+
+    let name_len = u16::from_le_bytes(take(&mut bytes, 2)?.try_into()?) as usize;
+    let name = String::from_utf8(take(&mut bytes, name_len)?.to_vec())
+        .context("The file name within the header isn't valid UTF-8")?;
+    let flags = take(&mut bytes, 1)?[0];
+
+    let modified = if flags & MODIFIED_FLAG != 0 {
+        let secs = u64::from_le_bytes(take(&mut bytes, 8)?.try_into()?);
+        let nanos = u32::from_le_bytes(take(&mut bytes, 4)?.try_into()?);
+        if nanos >= 1_000_000_000 {
+            return Err(anyhow::anyhow!("The modification time is invalid"));
+        }
+        Some(Duration::new(secs, nanos))
+    } else {
+        None
+    };
+
+    let mode = if flags & MODE_FLAG != 0 {
+        Some(u32::from_le_bytes(take(&mut bytes, 4)?.try_into()?))
+    } else {
+        None
+    };
+
+    if !bytes.is_empty() || flags & !(MODIFIED_FLAG | MODE_FLAG) != 0 {
+        return Err(anyhow::anyhow!("The file info contains unknown data"));
+    }
+
+    let info = FileInfo {
+        name,
+        modified,
+        mode,
+    };
+    info.validate()?;
+
+    Ok(info)
+}
+
+/// This encrypts the file info, so that it can be stored in the header's `FILE_INFO_FIELD`
+pub fn encrypt(
+    header_key: &Protected<[u8; 32]>,
+    algorithm: &Algorithm,
+    info: &FileInfo,
+) -> Result<Vec<u8>> {
+    info.validate()?;
+
+    Ciphers::initialize(derive_key(header_key), algorithm)?
+        .encrypt(&nonce(algorithm), serialize(info).as_slice())
+        .map_err(|_| anyhow::anyhow!("Unable to encrypt the file info"))
+}
+
+/// This decrypts file info that was read from the header
+///
+/// This will fail if the file info (or the key) is incorrect, or if the name isn't a valid file name.
+pub fn decrypt(
+    header_key: &Protected<[u8; 32]>,
+    algorithm: &Algorithm,
+    encrypted_info: &[u8],
+) -> Result<FileInfo> {
+    let bytes = Ciphers::initialize(derive_key(header_key), algorithm)?
+        .decrypt(&nonce(algorithm), encrypted_info)
+        .map_err(|_| anyhow::anyhow!("Unable to decrypt the file info"))?;
+
+    deserialize(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> FileInfo {
+        FileInfo {
+            name: "hello.txt".to_string(),
+            modified: Some(Duration::new(1_700_000_000, 500)),
+            mode: Some(0o644),
+        }
+    }
+
+    #[test]
+    fn should_round_trip_file_info() {
+        let key = Protected::new([7u8; 32]);
+        let algorithm = Algorithm::XChaCha20Poly1305;
+
+        let encrypted = encrypt(&key, &algorithm, &info()).unwrap();
+        assert_eq!(decrypt(&key, &algorithm, &encrypted).unwrap(), info());
+
+        // the modification time and mode are optional
+        let name_only = FileInfo {
+            modified: None,
+            mode: None,
+            ..info()
+        };
+        let encrypted = encrypt(&key, &algorithm, &name_only).unwrap();
+        assert_eq!(decrypt(&key, &algorithm, &encrypted).unwrap(), name_only);
+    }
+
+    #[test]
+    fn should_refuse_tampered_file_info_or_the_wrong_key() {
+        let key = Protected::new([7u8; 32]);
+        let algorithm = Algorithm::XChaCha20Poly1305;
+
+        let mut encrypted = encrypt(&key, &algorithm, &info()).unwrap();
+        assert!(decrypt(&Protected::new([8u8; 32]), &algorithm, &encrypted).is_err());
+
+        encrypted[0] ^= 1;
+        assert!(decrypt(&key, &algorithm, &encrypted).is_err());
+    }
+
+    #[test]
+    fn should_refuse_names_that_leave_the_directory() {
+        for name in [
+            "",
+            ".",
+            "..",
+            "../hello.txt",
+            "dir/hello.txt",
+            "dir\\hello.txt",
+        ] {
+            let info = FileInfo {
+                name: name.to_string(),
+                ..info()
+            };
+            assert!(info.validate().is_err());
+
+            // a crafted header is refused when it's read, too
+            assert!(deserialize(&serialize(&info)).is_err());
+        }
+    }
+
+    #[test]
+    fn should_refuse_truncated_or_unknown_data() {
+        let bytes = serialize(&info());
+        assert_eq!(deserialize(&bytes).unwrap(), info());
+
+        for len in [1, 4, 11, bytes.len() - 1] {
+            assert!(deserialize(&bytes[..len]).is_err());
+        }
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(deserialize(&trailing).is_err());
+
+        let mut flags = bytes;
+        flags[2 + "hello.txt".len()] |= 0x80;
+        assert!(deserialize(&flags).is_err());
+    }
+}
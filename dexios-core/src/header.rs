@@ -6,6 +6,22 @@
 //! * nonce
 //! * encryption algorithm
 //! * whether the file was encrypted in "memory" or stream mode
+//! * whether each block was compressed before encryption (V6+)
+//! * the stream block size, if it isn't the default (V6+)
+//! * whether the plaintext was padded to hide its length (V6+)
+//! * whether the data was encrypted convergently (V6+)
+//! * when the data was encrypted, and by which program (V6+, optional)
+//! * whether the ciphertext is followed by a MAC footer (V6+, see `crate::mac`)
+//! * an encrypted digest of the plaintext (V6+, optional, see `crate::digest`)
+//! * the KDF parameters of each keyslot, if they were chosen by the user (V6+, see `crate::kdf`)
+//! * whether keyfiles were hashed before being used as keys (V6+, see `crate::key::hash_keyfile`)
+//! * which keyslots are unlocked by a hardware token, rather than a key (V6+, see `crate::token`)
+//! * whether the stream uses a 64-bit counter (V6+, see `crate::counter`)
+//! * whether the payload, header and MAC keys are derived separately (V6+, see `crate::subkeys`)
+//! * whether the key was made from both a keyfile and a password (V6+, see `crate::key::combine_factors`)
+//! * which keyslots only unlock the header key, and an encrypted manifest of packed files (V6+, optional, see `crate::manifest`)
+//! * a section of tagged, length-prefixed fields, so that new fields don't need new offsets (V6+, see `Field`)
+//! * the original file's name, modification time and permissions, encrypted (V6+, optional, see `crate::file_info`)
 //!
 //! It allows for serialization, deserialization, and has a convenience function for quickly writing the header to a file.
 //!
@@ -33,28 +49,42 @@
 //!
 
 use crate::{
-    key::{argon2id_hash, balloon_hash},
+    backend::CUSTOM_ALGORITHM_PREFIX,
+    digest::ENCRYPTED_DIGEST_LEN,
+    kdf::{
+        Argon2id, Argon2idParams, BalloonParams, Blake3Balloon, Kdf, KdfParams, KeyDerivation,
+        Scrypt, ScryptParams, ARGON2ID_CUSTOM_ID, BLAKE3BALLOON_CUSTOM_ID, KDF_PARAMS_LEN,
+        SCRYPT_CUSTOM_ID,
+    },
+    manifest::MAX_MANIFEST_LEN,
     protected::Protected,
+    recipient::{ENCAPSULATED_KEY_LEN, X25519_ENCAPSULATED_KEY_LEN},
 };
 
-use super::primitives::{get_nonce_len, Algorithm, Mode, ENCRYPTED_MASTER_KEY_LEN, SALT_LEN};
+use super::primitives::{
+    get_nonce_len, Algorithm, Compression, Mode, Padding, StreamCounter, BLOCK_SIZE,
+    ENCRYPTED_MASTER_KEY_LEN, MAX_BLOCK_SIZE, MIN_BLOCK_SIZE, SALT_LEN,
+};
 use anyhow::{Context, Result};
+use rand::{Rng, RngCore};
 use std::io::{Cursor, Read, Seek, Write};
 
 /// This defines the latest header version, so program's using this can easily stay up to date.
 ///
 /// It's also here to just help users keep track
-pub const HEADER_VERSION: HeaderVersion = HeaderVersion::V5;
+pub const HEADER_VERSION: HeaderVersion = HeaderVersion::V6;
 
 /// This stores all possible versions of the header
 #[allow(clippy::module_name_repetitions)]
 #[derive(PartialEq, Eq, Clone, Copy, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum HeaderVersion {
     V1,
     V2,
     V3,
     V4,
     V5,
+    V6,
 }
 
 impl std::fmt::Display for HeaderVersion {
@@ -65,6 +95,7 @@ impl std::fmt::Display for HeaderVersion {
             HeaderVersion::V3 => write!(f, "V3"),
             HeaderVersion::V4 => write!(f, "V4"),
             HeaderVersion::V5 => write!(f, "V5"),
+            HeaderVersion::V6 => write!(f, "V6"),
         }
     }
 }
@@ -75,12 +106,115 @@ impl std::fmt::Display for HeaderVersion {
 ///
 /// This needs to be manually created for encrypting data
 #[allow(clippy::module_name_repetitions)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct HeaderType {
     pub version: HeaderVersion,
     pub algorithm: Algorithm,
     pub mode: Mode,
 }
 
+impl HeaderType {
+    /// This returns the raw bytes that identify the header type (the first 6 bytes of every header)
+    ///
+    /// It's useful for repairing a header whose first bytes have been damaged, as the rest of it can still be parsed once they're restored.
+    #[must_use]
+    pub fn serialize(&self) -> [u8; 6] {
+        let mut bytes = [0u8; 6];
+        bytes[..2].copy_from_slice(&self.serialize_version());
+        bytes[2..4].copy_from_slice(&self.serialize_algorithm());
+        bytes[4..].copy_from_slice(&self.serialize_mode());
+        bytes
+    }
+
+    /// This is a private function used for serialization
+    ///
+    /// It converts a `HeaderVersion` into the associated raw bytes
+    fn serialize_version(&self) -> [u8; 2] {
+        match self.version {
+            HeaderVersion::V1 => {
+                let info: [u8; 2] = [0xDE, 0x01];
+                info
+            }
+            HeaderVersion::V2 => {
+                let info: [u8; 2] = [0xDE, 0x02];
+                info
+            }
+            HeaderVersion::V3 => {
+                let info: [u8; 2] = [0xDE, 0x03];
+                info
+            }
+            HeaderVersion::V4 => {
+                let info: [u8; 2] = [0xDE, 0x04];
+                info
+            }
+            HeaderVersion::V5 => {
+                let info: [u8; 2] = [0xDE, 0x05];
+                info
+            }
+            HeaderVersion::V6 => {
+                let info: [u8; 2] = [0xDE, 0x06];
+                info
+            }
+        }
+    }
+
+    /// This is a private function used for serialization
+    ///
+    /// It converts an `Algorithm` into the associated raw bytes
+    fn serialize_algorithm(&self) -> [u8; 2] {
+        match self.algorithm {
+            Algorithm::XChaCha20Poly1305 => {
+                let info: [u8; 2] = [0x0E, 0x01];
+                info
+            }
+            Algorithm::Aes256Gcm => {
+                let info: [u8; 2] = [0x0E, 0x02];
+                info
+            }
+            Algorithm::DeoxysII256 => {
+                let info: [u8; 2] = [0x0E, 0x03];
+                info
+            }
+            Algorithm::Aegis256 => {
+                let info: [u8; 2] = [0x0E, 0x04];
+                info
+            }
+            Algorithm::ChaCha20Poly1305 => {
+                let info: [u8; 2] = [0x0E, 0x05];
+                info
+            }
+            Algorithm::Ascon128a => {
+                let info: [u8; 2] = [0x0E, 0x06];
+                info
+            }
+            Algorithm::Custom(id) => {
+                let info: [u8; 2] = [CUSTOM_ALGORITHM_PREFIX, id];
+                info
+            }
+        }
+    }
+
+    /// This is a private function used for serialization
+    ///
+    /// It converts a `Mode` into the associated raw bytes
+    fn serialize_mode(&self) -> [u8; 2] {
+        match self.mode {
+            Mode::StreamMode => {
+                let info: [u8; 2] = [0x0C, 0x01];
+                info
+            }
+            Mode::MemoryMode => {
+                let info: [u8; 2] = [0x0C, 0x02];
+                info
+            }
+            Mode::DerivedStreamMode => {
+                let info: [u8; 2] = [0x0C, 0x03];
+                info
+            }
+        }
+    }
+}
+
 /// This is the `HeaderType` struct, but in the format of raw bytes
 ///
 /// This does not need to be used outside of this core library
@@ -95,88 +229,498 @@ struct HeaderTag {
 /// It contains the `HeaderType`, the nonce, and the salt
 ///
 /// This needs to be manually created for encrypting data
+///
+/// With the `serde` feature, it can be (de)serialized with any serde format (e.g. JSON or CBOR), as a structure rather than its byte format. Use `Header::serialize()` for the bytes that are stored with the data.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Header {
     pub header_type: HeaderType,
     pub nonce: Vec<u8>,
     pub salt: Option<[u8; SALT_LEN]>, // option as v4+ use the keyslots
     pub keyslots: Option<Vec<Keyslot>>,
+    pub fields: Vec<Field>, // only V6+ headers may contain fields, and these are the ones that aren't stored in another member (e.g. `compression`)
+    pub compression: Compression, // only V6+ headers may contain a compression flag
+    pub block_size: usize,  // only V6+ headers may use a block size other than `BLOCK_SIZE`
+    pub padding: Padding,   // only V6+ headers may contain a padding flag
+    pub convergent: bool, // only V6+ headers may contain a convergent flag (see `crate::convergent`)
+    pub metadata: Option<Metadata>, // only V6+ headers may contain metadata
+    pub mac: bool, // only V6+ headers in stream mode may flag a MAC footer (see `crate::mac`)
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_arrays::option"))]
+    pub digest: Option<[u8; ENCRYPTED_DIGEST_LEN]>, // only V6+ headers in stream mode may contain a digest (see `crate::digest`)
+    pub seekable: bool, // only V6+ headers in stream mode may flag a chunk table (see `crate::seekable`)
+    pub keyfile_hash: bool, // only V6+ headers in stream mode may flag that keyfiles were hashed first (see `crate::key::hash_keyfile`)
+    pub counter: StreamCounter, // only V6+ headers in stream mode may use a 64-bit counter (see `crate::counter`)
+    pub subkeys: bool, // only V6+ headers in stream mode may separate the payload, header and MAC keys (see `crate::subkeys`)
+    pub two_factor: bool, // only V6+ headers in stream mode may flag that both a keyfile and a password are needed (see `crate::key::combine_factors`)
+    pub manifest: Option<Vec<u8>>, // only V6+ headers with separate subkeys may contain an encrypted manifest (see `crate::manifest`)
+}
+
+/// This is the maximum length of the program version that's stored in the metadata (in bytes)
+pub const METADATA_VERSION_LEN: usize = 24;
+
+/// This records when the data was encrypted, and which program did so
+///
+/// It's stored in a V6 header's metadata field, so it's covered by the AAD and can't be changed without decryption failing. It isn't encrypted, so `header details` can display it without a key.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Metadata {
+    /// This is the number of seconds since the UNIX epoch
+    pub created: u64,
+    /// This is the name and version of the program (e.g. "dexios 8.8.1"), and it's truncated to `METADATA_VERSION_LEN` bytes
+    pub version: String,
+}
+
+impl Metadata {
+    /// This creates metadata for data that's being encrypted right now
+    #[must_use]
+    pub fn new(version: &str) -> Self {
+        let created = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        let mut len = version.len().min(METADATA_VERSION_LEN);
+        while !version.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        Self {
+            created,
+            version: version[..len].to_string(),
+        }
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let version = self.version.as_bytes();
+        let len = version.len().min(METADATA_VERSION_LEN);
+
+        let mut bytes = self.created.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&version[..len]);
+        bytes
+    }
+
+    fn deserialize(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 8 || bytes.len() > 8 + METADATA_VERSION_LEN {
+            return Err(anyhow::anyhow!(
+                "The header's metadata field has an invalid length"
+            ));
+        }
+
+        let mut created = [0u8; 8];
+        created.copy_from_slice(&bytes[..8]);
+
+        let version = std::str::from_utf8(&bytes[8..])
+            .context("Unable to read the program version from the header's metadata")?;
+
+        Ok(Self {
+            created: u64::from_le_bytes(created),
+            version: version.to_string(),
+        })
+    }
+}
+
+/// This identifies the field that stores how a V6 header's data was encrypted, beyond its algorithm and mode
+///
+/// Its value has a byte for each option, in the order that they were added to the format (the compression flag, the block size, the padding flag, the convergent flag, the MAC flag, then the extension flags). Trailing bytes that hold the default are left out, and the field is only stored if one of the options isn't the default, so a header only has one valid encoding.
+///
+/// It's critical, as the data can't be decrypted correctly by anything that ignores it.
+pub const OPTIONS_FIELD: u16 = CRITICAL_FIELD | 0x0001;
+
+/// This is the number of options that may be stored in the options field
+pub const OPTIONS_LEN: usize = 6;
+
+/// These flag which extensions a V6 header uses (they share a single option)
+const DIGEST_FLAG: u8 = 0x01;
+const SEEKABLE_FLAG: u8 = 0x02;
+// this one doesn't add anything to the header, it just changes how keyfiles are turned into raw keys
+const KEYFILE_HASH_FLAG: u8 = 0x04;
+// neither does this one, it changes how each block's nonce is built
+const COUNTER_BE64_FLAG: u8 = 0x08;
+// or this one, it changes which keys are derived from the master key
+const SUBKEYS_FLAG: u8 = 0x10;
+// and this one tells decryption to ask for a password, as well as the keyfile
+const TWO_FACTOR_FLAG: u8 = 0x20;
+
+/// This identifies the field that stores a V6 header's `Metadata`
+///
+/// It isn't critical, as it's only informational.
+pub const METADATA_FIELD: u16 = 0x0002;
+
+/// This identifies the field that stores a V6 header's encrypted manifest (see `crate::manifest`)
+///
+/// It isn't critical either, as the data can be decrypted without it.
+pub const MANIFEST_FIELD: u16 = 0x0003;
+
+/// This identifies a field of random bytes, which only hides the exact size of a V6 header (e.g. how many recipients it has)
+///
+/// It's kept in `Header::fields`, as nothing needs to read it back.
+pub const PADDING_FIELD: u16 = 0x0004;
+
+/// This identifies a field that's only stored in a detached header, when the data starts with random bytes in its place (as many as the header's size)
+///
+/// It's critical, as the data can't be decrypted by anything that doesn't know to skip those bytes.
+pub const PLACEHOLDER_FIELD: u16 = CRITICAL_FIELD | 0x0005;
+
+/// This identifies a field that's stored when a copy of the header is appended to the end of the data, so it can be recovered if the header at the start is damaged
+///
+/// It's critical, as the copy would otherwise be mistaken for the end of the ciphertext.
+pub const BACKUP_HEADER_FIELD: u16 = CRITICAL_FIELD | 0x0006;
+
+/// This identifies a field that stores the original file's name, modification time and permissions, encrypted with the header key (see `crate::file_info`)
+pub const FILE_INFO_FIELD: u16 = 0x0007;
+
+/// These are the fields that are stored in their own `Header` members, rather than in `Header::fields`
+const MEMBER_FIELDS: [u16; 3] = [OPTIONS_FIELD, METADATA_FIELD, MANIFEST_FIELD];
+
+/// Fields with this bit set in their tag are critical, so a header containing one that isn't recognised can't be read
+///
+/// Any other field that isn't recognised is kept as it is, so it survives the header being rewritten (e.g. when a key is changed)
+pub const CRITICAL_FIELD: u16 = 0x8000;
+
+/// This is the maximum length of a V6 header's field section (excluding its length prefix)
+///
+/// It leaves room for the largest manifest, along with the other fields.
+pub const MAX_FIELDS_LEN: usize = MAX_MANIFEST_LEN + 64 * 1024;
+
+/// This is a field within the section that follows a V6 header's keyslots
+///
+/// The section is prefixed with its length (a little-endian `u32`), and each field is stored as its tag (a little-endian `u16`), the length of its value (a little-endian `u32`), and then the value.
+///
+/// Fields are stored in ascending order of their tags, and each tag may only appear once, so a section only has one valid encoding. The whole section (including its length) is covered by the AAD.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Field {
+    pub tag: u16,
+    pub value: Vec<u8>,
+}
+
+impl Field {
+    /// This creates a padding field of a random length (up to `max_len`), filled with random bytes
+    #[must_use]
+    pub fn random_padding(max_len: usize) -> Self {
+        let mut rng = rand::thread_rng();
+        let mut value = vec![0u8; rng.gen_range(0..=max_len)];
+        rng.fill_bytes(&mut value);
+
+        Field {
+            tag: PADDING_FIELD,
+            value,
+        }
+    }
+
+    #[must_use]
+    pub fn is_critical(&self) -> bool {
+        self.tag & CRITICAL_FIELD != 0
+    }
+}
+
+/// This parses a field section (without its length prefix), and checks that it's in the canonical order
+fn deserialize_fields(section: &[u8]) -> Result<Vec<Field>> {
+    let mut fields: Vec<Field> = Vec::new();
+    let mut cursor = Cursor::new(section);
+
+    while (cursor.position() as usize) < section.len() {
+        let mut tag_bytes = [0u8; 2];
+        let mut len_bytes = [0u8; 4];
+        cursor
+            .read_exact(&mut tag_bytes)
+            .context("Unable to read a field's tag from header")?;
+        cursor
+            .read_exact(&mut len_bytes)
+            .context("Unable to read a field's length from header")?;
+
+        let tag = u16::from_le_bytes(tag_bytes);
+        let len = u32::from_le_bytes(len_bytes) as usize;
+        if len > section.len() - cursor.position() as usize {
+            return Err(anyhow::anyhow!(
+                "Field {:#06x} extends past the end of the header's field section",
+                tag
+            ));
+        }
+
+        if fields.last().is_some_and(|last| last.tag >= tag) {
+            return Err(anyhow::anyhow!(
+                "The header's fields are out of order, or repeated"
+            ));
+        }
+
+        let mut value = vec![0u8; len];
+        cursor
+            .read_exact(&mut value)
+            .context("Unable to read a field's value from header")?;
+        fields.push(Field { tag, value });
+    }
+
+    Ok(fields)
 }
 
 pub const ARGON2ID_LATEST: i32 = 3;
 pub const BLAKE3BALLOON_LATEST: i32 = 5;
+pub const SCRYPT_LATEST: i32 = 1;
+pub const HASHING_ALGORITHMS_LEN: usize = 6;
 
 /// This is in place to make `Keyslot` handling a **lot** easier
-/// You may use the constants `ARGON2ID_LATEST` and `BLAKE3BALLOON_LATEST` for defining versions
+/// You may use the constants `ARGON2ID_LATEST`, `BLAKE3BALLOON_LATEST` and `SCRYPT_LATEST` for defining versions
+///
+/// The custom variants carry parameters chosen by the user, which are stored within the keyslot (V6+ only)
+///
+/// `scrypt` is only supported by V6 headers
 #[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum HashingAlgorithm {
     Argon2id(i32),
     Blake3Balloon(i32),
+    Argon2idCustom(Argon2idParams),
+    Blake3BalloonCustom(BalloonParams),
+    Scrypt(i32),
+    ScryptCustom(ScryptParams),
 }
 
+/// This is an array containing every hashing algorithm (and parameter version) supported by `dexios-core`.
+///
+/// It can be used by an end-user application to list or benchmark the available KDFs
+pub static HASHING_ALGORITHMS: [HashingAlgorithm; HASHING_ALGORITHMS_LEN] = [
+    HashingAlgorithm::Argon2id(1),
+    HashingAlgorithm::Argon2id(2),
+    HashingAlgorithm::Argon2id(3),
+    HashingAlgorithm::Blake3Balloon(4),
+    HashingAlgorithm::Blake3Balloon(5),
+    HashingAlgorithm::Scrypt(1),
+];
+
 impl std::fmt::Display for HashingAlgorithm {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             HashingAlgorithm::Argon2id(i) => write!(f, "Argon2id (param v{})", i),
             HashingAlgorithm::Blake3Balloon(i) => write!(f, "BLAKE3-Balloon (param v{})", i),
+            HashingAlgorithm::Argon2idCustom(params) => match Argon2id::custom(*params) {
+                Ok(kdf) => kdf.fmt(f),
+                Err(_) => write!(f, "Argon2id (invalid custom parameters)"),
+            },
+            HashingAlgorithm::Blake3BalloonCustom(params) => match Blake3Balloon::custom(*params) {
+                Ok(kdf) => kdf.fmt(f),
+                Err(_) => write!(f, "BLAKE3-Balloon (invalid custom parameters)"),
+            },
+            HashingAlgorithm::Scrypt(i) => write!(f, "scrypt (param v{})", i),
+            HashingAlgorithm::ScryptCustom(params) => match Scrypt::custom(*params) {
+                Ok(kdf) => kdf.fmt(f),
+                Err(_) => write!(f, "scrypt (invalid custom parameters)"),
+            },
         }
     }
 }
 
 impl HashingAlgorithm {
+    /// This looks up a registered hashing algorithm by the bytes that identify it within a keyslot
+    #[must_use]
+    pub fn from_id(id: [u8; 2]) -> Option<Self> {
+        HASHING_ALGORITHMS
+            .iter()
+            .find(|h| h.kdf().is_ok_and(|kdf| kdf.id() == id))
+            .copied()
+    }
+
+    /// This looks up the hashing algorithm for a keyslot, reading custom parameters from the keyslot's reserved bytes if needed
+    #[must_use]
+    pub fn from_keyslot(id: [u8; 2], params: &[u8; KDF_PARAMS_LEN]) -> Option<Self> {
+        match id {
+            ARGON2ID_CUSTOM_ID => Some(HashingAlgorithm::Argon2idCustom(
+                Argon2idParams::deserialize(params),
+            )),
+            BLAKE3BALLOON_CUSTOM_ID => Some(HashingAlgorithm::Blake3BalloonCustom(
+                BalloonParams::deserialize(params),
+            )),
+            SCRYPT_CUSTOM_ID => Some(HashingAlgorithm::ScryptCustom(ScryptParams::deserialize(
+                params,
+            ))),
+            _ => Self::from_id(id),
+        }
+    }
+
+    /// This returns true if the parameters were chosen by the user (and need to be stored in the keyslot)
+    #[must_use]
+    pub fn is_custom(&self) -> bool {
+        matches!(
+            self,
+            HashingAlgorithm::Argon2idCustom(_)
+                | HashingAlgorithm::Blake3BalloonCustom(_)
+                | HashingAlgorithm::ScryptCustom(_)
+        )
+    }
+
+    /// This selects a KDF, with either custom parameters or the latest version's
+    ///
+    /// The parameters must be for the selected KDF
+    pub fn from_kdf(kdf: Kdf, params: Option<KdfParams>) -> Result<Self> {
+        let hashing_algorithm = match (kdf, params) {
+            (Kdf::Argon2id, None) => HashingAlgorithm::Argon2id(ARGON2ID_LATEST),
+            (Kdf::Blake3Balloon, None) => HashingAlgorithm::Blake3Balloon(BLAKE3BALLOON_LATEST),
+            (Kdf::Scrypt, None) => HashingAlgorithm::Scrypt(SCRYPT_LATEST),
+            (Kdf::Argon2id, Some(KdfParams::Argon2id(params))) => {
+                HashingAlgorithm::Argon2idCustom(params)
+            }
+            (Kdf::Blake3Balloon, Some(KdfParams::Blake3Balloon(params))) => {
+                HashingAlgorithm::Blake3BalloonCustom(params)
+            }
+            (Kdf::Scrypt, Some(KdfParams::Scrypt(params))) => {
+                HashingAlgorithm::ScryptCustom(params)
+            }
+            (kdf, Some(params)) => {
+                return Err(anyhow::anyhow!(
+                    "The parameters provided are for {}, not {}",
+                    params.kdf(),
+                    kdf
+                ))
+            }
+        };
+
+        // this validates the parameters before anything is done
+        hashing_algorithm.kdf()?;
+
+        Ok(hashing_algorithm)
+    }
+
+    /// This returns the KDF family, without the parameters
+    #[must_use]
+    pub fn family(&self) -> Kdf {
+        match self {
+            HashingAlgorithm::Argon2id(_) | HashingAlgorithm::Argon2idCustom(_) => Kdf::Argon2id,
+            HashingAlgorithm::Blake3Balloon(_) | HashingAlgorithm::Blake3BalloonCustom(_) => {
+                Kdf::Blake3Balloon
+            }
+            HashingAlgorithm::Scrypt(_) | HashingAlgorithm::ScryptCustom(_) => Kdf::Scrypt,
+        }
+    }
+
+    /// This returns the `KeyDerivation` implementation (and its parameters) for this algorithm/version
+    pub fn kdf(&self) -> Result<Box<dyn KeyDerivation>> {
+        Ok(match self {
+            HashingAlgorithm::Argon2id(i) => Box::new(Argon2id::from_version(*i)?),
+            HashingAlgorithm::Blake3Balloon(i) => Box::new(Blake3Balloon::from_version(*i)?),
+            HashingAlgorithm::Argon2idCustom(params) => Box::new(Argon2id::custom(*params)?),
+            HashingAlgorithm::Blake3BalloonCustom(params) => {
+                Box::new(Blake3Balloon::custom(*params)?)
+            }
+            HashingAlgorithm::Scrypt(i) => Box::new(Scrypt::from_version(*i)?),
+            HashingAlgorithm::ScryptCustom(params) => Box::new(Scrypt::custom(*params)?),
+        })
+    }
+
     /// A simple helper function that will hash a value with the appropriate algorithm and version
     pub fn hash(
         &self,
         raw_key: Protected<Vec<u8>>,
         salt: &[u8; SALT_LEN],
     ) -> Result<Protected<[u8; 32]>, anyhow::Error> {
-        match self {
-            HashingAlgorithm::Argon2id(i) => match i {
-                1 => argon2id_hash(raw_key, salt, &HeaderVersion::V1),
-                2 => argon2id_hash(raw_key, salt, &HeaderVersion::V2),
-                3 => argon2id_hash(raw_key, salt, &HeaderVersion::V3),
-                _ => Err(anyhow::anyhow!(
-                    "argon2id is not supported with the parameters provided."
-                )),
-            },
-            HashingAlgorithm::Blake3Balloon(i) => match i {
-                4 => balloon_hash(raw_key, salt, &HeaderVersion::V4),
-                5 => balloon_hash(raw_key, salt, &HeaderVersion::V5),
-                _ => Err(anyhow::anyhow!(
-                    "Balloon hashing is not supported with the parameters provided."
-                )),
-            },
-        }
+        // this is a no-op, unless the raw key is bound to a hardware token (see `crate::challenge`)
+        let raw_key = crate::challenge::mix(raw_key, salt)?;
+        self.kdf()?.derive(raw_key, salt)
     }
 }
 
+/// This is the number of keyslots that V5 and V6 headers have room for
+///
+/// Every keyslot unlocks the same master key, so each one may hold a different password/keyfile (or recipient)
+pub const MAX_KEYSLOTS: usize = 4;
+
+/// This identifies a recipient keyslot (see `crate::recipient`)
+pub const RECIPIENT_KEYSLOT_ID: [u8; 2] = [0xDF, 0xC1];
+
+/// This identifies a plain X25519 recipient keyslot (see `crate::recipient`)
+pub const X25519_RECIPIENT_KEYSLOT_ID: [u8; 2] = [0xDF, 0xC2];
+
+/// This identifies a token keyslot (see `crate::token`)
+pub const TOKEN_KEYSLOT_ID: [u8; 2] = [0xDF, 0xC3];
+
+/// This identifies a metadata-only keyslot (see `crate::key::decrypt_header_key`)
+pub const METADATA_KEYSLOT_ID: [u8; 2] = [0xDF, 0xC4];
+
 /// This defines a keyslot that is used with header V4 and above.
 /// A keyslot contains information about the key, and the encrypted key itself
+///
+/// Recipient keyslots (V6+) wrap the master key to a public key instead of a password. They store the recipient's fingerprint in place of the salt, and their `hash_algorithm` is unused. Hybrid and plain X25519 recipients have separate identifiers, as their encapsulated keys differ in length.
+///
+/// Token keyslots (V6+) wrap the master key with a key derived by a hardware token. Their salt is the token's challenge, and their `hash_algorithm` is unused too.
+///
+/// Metadata-only keyslots (V6+) wrap the header key instead of the master key (see `crate::subkeys`), so they can read the digest and manifest, but not the payload. They're always hashed with the latest BLAKE3-Balloon parameters.
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Keyslot {
     pub hash_algorithm: HashingAlgorithm,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_arrays"))]
     pub encrypted_key: [u8; ENCRYPTED_MASTER_KEY_LEN],
     pub nonce: Vec<u8>,
     pub salt: [u8; SALT_LEN],
+    pub encapsulated_key: Option<Vec<u8>>, // only recipient keyslots contain this
+    pub token: bool,
+    pub metadata_only: bool,
 }
 
 impl Keyslot {
     /// This is used to convert a keyslot into bytes - ideal for writing headers
     #[must_use]
     pub fn serialize(&self) -> [u8; 2] {
-        match self.hash_algorithm {
-            HashingAlgorithm::Argon2id(i) => match i {
-                1 => [0xDF, 0xA1],
-                2 => [0xDF, 0xA2],
-                3 => [0xDF, 0xA3],
-                _ => [0x00, 0x00],
-            },
-            HashingAlgorithm::Blake3Balloon(i) => match i {
-                4 => [0xDF, 0xB4],
-                5 => [0xDF, 0xB5],
-                _ => [0x00, 0x00],
-            },
+        if let Some(encapsulated_key) = &self.encapsulated_key {
+            if encapsulated_key.len() == X25519_ENCAPSULATED_KEY_LEN {
+                return X25519_RECIPIENT_KEYSLOT_ID;
+            }
+
+            return RECIPIENT_KEYSLOT_ID;
+        }
+
+        if self.is_token() {
+            return TOKEN_KEYSLOT_ID;
+        }
+
+        if self.is_metadata_only() {
+            return METADATA_KEYSLOT_ID;
+        }
+
+        self.hash_algorithm
+            .kdf()
+            .map_or([0x00, 0x00], |kdf| kdf.id())
+    }
+
+    /// This returns the KDF parameters that are stored in the keyslot's reserved bytes (V6+)
+    ///
+    /// These are zeroed unless the parameters were chosen by the user
+    #[must_use]
+    pub fn serialize_params(&self) -> [u8; KDF_PARAMS_LEN] {
+        if self.is_recipient() || self.is_token() || self.is_metadata_only() {
+            return [0u8; KDF_PARAMS_LEN];
         }
+
+        self.hash_algorithm
+            .kdf()
+            .map_or([0u8; KDF_PARAMS_LEN], |kdf| kdf.params())
     }
+
+    #[must_use]
+    pub fn is_recipient(&self) -> bool {
+        self.encapsulated_key.is_some()
+    }
+
+    #[must_use]
+    pub fn is_token(&self) -> bool {
+        self.token
+    }
+
+    #[must_use]
+    pub fn is_metadata_only(&self) -> bool {
+        self.metadata_only
+    }
+}
+
+/// This is a private function that checks the nonces fit within the header, as each one is followed by padding up to a fixed length
+///
+/// Custom AEADs are checked when they're registered, so this should only fail if the algorithm doesn't match the header version
+fn check_nonce_len(header_type: &HeaderType) -> Result<()> {
+    if get_nonce_len(&header_type.algorithm, &header_type.mode) > 26
+        || get_nonce_len(&header_type.algorithm, &Mode::MemoryMode) > 24
+    {
+        return Err(anyhow::anyhow!(
+            "The nonce of this AEAD is too long to be stored in the header"
+        ));
+    }
+    Ok(())
 }
 
 impl Header {
@@ -184,9 +728,9 @@ impl Header {
     ///
     /// It's used for serialization, and has it's own dedicated function as it will be used often
     fn get_tag(&self) -> HeaderTag {
-        let version = self.serialize_version();
-        let algorithm = self.serialize_algorithm();
-        let mode = self.serialize_mode();
+        let version = self.header_type.serialize_version();
+        let algorithm = self.header_type.serialize_algorithm();
+        let mode = self.header_type.serialize_mode();
         HeaderTag {
             version,
             algorithm,
@@ -194,34 +738,6 @@ impl Header {
         }
     }
 
-    /// This is a private function used for serialization
-    ///
-    /// It converts a `HeaderVersion` into the associated raw bytes
-    fn serialize_version(&self) -> [u8; 2] {
-        match self.header_type.version {
-            HeaderVersion::V1 => {
-                let info: [u8; 2] = [0xDE, 0x01];
-                info
-            }
-            HeaderVersion::V2 => {
-                let info: [u8; 2] = [0xDE, 0x02];
-                info
-            }
-            HeaderVersion::V3 => {
-                let info: [u8; 2] = [0xDE, 0x03];
-                info
-            }
-            HeaderVersion::V4 => {
-                let info: [u8; 2] = [0xDE, 0x04];
-                info
-            }
-            HeaderVersion::V5 => {
-                let info: [u8; 2] = [0xDE, 0x05];
-                info
-            }
-        }
-    }
-
     /// This is used for deserializing raw bytes from a reader into a `Header` struct
     ///
     /// This also returns the AAD, read from the header. AAD is only generated in `HeaderVersion::V3` and above - it will be blank in older versions.
@@ -264,13 +780,14 @@ impl Header {
             [0xDE, 0x03] => HeaderVersion::V3,
             [0xDE, 0x04] => HeaderVersion::V4,
             [0xDE, 0x05] => HeaderVersion::V5,
+            [0xDE, 0x06] => HeaderVersion::V6,
             _ => return Err(anyhow::anyhow!("Error getting version from header")),
         };
 
         let header_length: usize = match version {
             HeaderVersion::V1 | HeaderVersion::V2 | HeaderVersion::V3 => 64,
             HeaderVersion::V4 => 128,
-            HeaderVersion::V5 => 416,
+            HeaderVersion::V5 | HeaderVersion::V6 => 416,
         };
 
         let mut full_header_bytes = vec![0u8; header_length];
@@ -292,6 +809,18 @@ impl Header {
             [0x0E, 0x01] => Algorithm::XChaCha20Poly1305,
             [0x0E, 0x02] => Algorithm::Aes256Gcm,
             [0x0E, 0x03] => Algorithm::DeoxysII256,
+            [0x0E, 0x04] if version >= HeaderVersion::V6 => Algorithm::Aegis256,
+            [0x0E, 0x05] if version >= HeaderVersion::V6 => Algorithm::ChaCha20Poly1305,
+            [0x0E, 0x06] if version >= HeaderVersion::V6 => Algorithm::Ascon128a,
+            [CUSTOM_ALGORITHM_PREFIX, id] if version >= HeaderVersion::V6 => {
+                if crate::backend::get(id).is_none() {
+                    return Err(anyhow::anyhow!(
+                        "Custom AEAD {:#04x} hasn't been registered",
+                        id
+                    ));
+                }
+                Algorithm::Custom(id)
+            }
             _ => return Err(anyhow::anyhow!("Error getting encryption mode from header")),
         };
 
@@ -303,6 +832,7 @@ impl Header {
         let mode = match mode_bytes {
             [0x0C, 0x01] => Mode::StreamMode,
             [0x0C, 0x02] => Mode::MemoryMode,
+            [0x0C, 0x03] if version >= HeaderVersion::V6 => Mode::DerivedStreamMode,
             _ => return Err(anyhow::anyhow!("Error getting cipher mode from header")),
         };
 
@@ -312,6 +842,7 @@ impl Header {
             mode,
         };
 
+        check_nonce_len(&header_type)?;
         let nonce_len = get_nonce_len(&header_type.algorithm, &header_type.mode);
         let mut salt = [0u8; 16];
         let mut nonce = vec![0u8; nonce_len];
@@ -377,22 +908,31 @@ impl Header {
                     hash_algorithm: HashingAlgorithm::Blake3Balloon(4),
                     nonce: master_key_nonce.clone(),
                     salt,
+                    encapsulated_key: None,
+                    token: false,
+                    metadata_only: false,
                 };
                 let keyslots = vec![keyslot];
                 Some(keyslots)
             }
-            HeaderVersion::V5 => {
+            HeaderVersion::V5 | HeaderVersion::V6 => {
                 cursor
                     .read_exact(&mut nonce)
                     .context("Unable to read nonce from header")?;
+                let mut padding = vec![0u8; 26 - nonce_len];
                 cursor
-                    .read_exact(&mut vec![0u8; 26 - nonce_len])
+                    .read_exact(&mut padding)
                     .context("Unable to read padding from header")?; // here we reach the 32 bytes
 
+                // these are reserved in V6 headers, as anything new is stored in a field
+                if version >= HeaderVersion::V6 && padding.iter().any(|b| *b != 0) {
+                    return Err(anyhow::anyhow!("The header's reserved bytes aren't empty"));
+                }
+
                 let keyslot_nonce_len = get_nonce_len(&algorithm, &Mode::MemoryMode);
 
                 let mut keyslots: Vec<Keyslot> = Vec::new();
-                for _ in 0..4 {
+                for _ in 0..MAX_KEYSLOTS {
                     let mut identifier = [0u8; 2];
                     cursor
                         .read_exact(&mut identifier)
@@ -423,17 +963,44 @@ impl Header {
                         .read_exact(&mut salt)
                         .context("Unable to read keyslot salt from header")?;
 
+                    // these are only used for storing custom KDF parameters (V6+)
+                    let mut params = [0u8; KDF_PARAMS_LEN];
                     cursor
-                        .read_exact(&mut [0u8; 6])
-                        .context("Unable to read keyslot padding from header")?;
-
-                    let hash_algorithm = match identifier {
-                        [0xDF, 0xA1] => HashingAlgorithm::Argon2id(1),
-                        [0xDF, 0xA2] => HashingAlgorithm::Argon2id(2),
-                        [0xDF, 0xA3] => HashingAlgorithm::Argon2id(3),
-                        [0xDF, 0xB4] => HashingAlgorithm::Blake3Balloon(4),
-                        [0xDF, 0xB5] => HashingAlgorithm::Blake3Balloon(5),
-                        _ => return Err(anyhow::anyhow!("Key hashing algorithm not identified")),
+                        .read_exact(&mut params)
+                        .context("Unable to read keyslot parameters from header")?;
+
+                    let (hash_algorithm, encapsulated_key) = if identifier == RECIPIENT_KEYSLOT_ID
+                        && version >= HeaderVersion::V6
+                    {
+                        // the encapsulated key is read from after the keyslots, once they've all been read
+                        (
+                            HashingAlgorithm::Blake3Balloon(BLAKE3BALLOON_LATEST),
+                            Some(vec![0u8; ENCAPSULATED_KEY_LEN]),
+                        )
+                    } else if identifier == X25519_RECIPIENT_KEYSLOT_ID
+                        && version >= HeaderVersion::V6
+                    {
+                        (
+                            HashingAlgorithm::Blake3Balloon(BLAKE3BALLOON_LATEST),
+                            Some(vec![0u8; X25519_ENCAPSULATED_KEY_LEN]),
+                        )
+                    } else if (identifier == TOKEN_KEYSLOT_ID || identifier == METADATA_KEYSLOT_ID)
+                        && version >= HeaderVersion::V6
+                    {
+                        (HashingAlgorithm::Blake3Balloon(BLAKE3BALLOON_LATEST), None)
+                    } else {
+                        // custom parameters and scrypt are only supported by V6 headers
+                        let hash_algorithm = if version >= HeaderVersion::V6 {
+                            HashingAlgorithm::from_keyslot(identifier, &params)
+                        } else {
+                            HashingAlgorithm::from_id(identifier)
+                                .filter(|h| h.family() != Kdf::Scrypt)
+                        }
+                        .context("Key hashing algorithm not identified")?;
+                        hash_algorithm
+                            .kdf()
+                            .context("The keyslot's KDF parameters are invalid")?;
+                        (hash_algorithm, None)
                     };
 
                     let keyslot = Keyslot {
@@ -441,15 +1008,158 @@ impl Header {
                         encrypted_key,
                         nonce,
                         salt,
+                        encapsulated_key,
+                        token: identifier == TOKEN_KEYSLOT_ID,
+                        metadata_only: identifier == METADATA_KEYSLOT_ID,
                     };
 
                     keyslots.push(keyslot);
                 }
 
+                // encapsulated keys are too large to fit in a keyslot, so they're appended to the keyslots (in keyslot order)
+                for encapsulated_key in keyslots
+                    .iter_mut()
+                    .filter_map(|k| k.encapsulated_key.as_mut())
+                {
+                    reader
+                        .read_exact(encapsulated_key)
+                        .context("Unable to read encapsulated key from header")?;
+                }
+
                 Some(keyslots)
             }
         };
 
+        // V6 headers have a field section after the keyslots (and any encapsulated keys)
+        let mut fields_section = None;
+        let mut fields = Vec::new();
+        if version >= HeaderVersion::V6 {
+            let mut len_bytes = [0u8; 4];
+            reader
+                .read_exact(&mut len_bytes)
+                .context("Unable to read the field section's length from header")?;
+            let len = u32::from_le_bytes(len_bytes) as usize;
+            if len > MAX_FIELDS_LEN {
+                return Err(anyhow::anyhow!(
+                    "The field section within the header is too large"
+                ));
+            }
+
+            let mut section = len_bytes.to_vec();
+            section.resize(4 + len, 0);
+            reader
+                .read_exact(&mut section[4..])
+                .context("Unable to read the field section from header")?;
+            fields = deserialize_fields(&section[4..])?;
+            fields_section = Some(section);
+        }
+
+        let mut options = [0u8; OPTIONS_LEN];
+        if let Some(index) = fields.iter().position(|f| f.tag == OPTIONS_FIELD) {
+            let value = fields.remove(index).value;
+            if value.len() > OPTIONS_LEN {
+                return Err(anyhow::anyhow!(
+                    "The header contains options that aren't supported by this version of Dexios"
+                ));
+            }
+
+            // trailing defaults are left out, so they'd only be present in a header that wasn't written by Dexios
+            if value.last().is_none_or(|b| *b == 0) {
+                return Err(anyhow::anyhow!(
+                    "The header's options field contains trailing defaults"
+                ));
+            }
+            options[..value.len()].copy_from_slice(&value);
+        }
+
+        let [compression, block_size, padding, convergent, mac, extensions] = options;
+
+        let compression = match compression {
+            0x00 => Compression::None,
+            0x01 => Compression::Zstd(0),
+            0x02 => Compression::ZstdPadded(0),
+            _ => return Err(anyhow::anyhow!("Error getting compression from header")),
+        };
+
+        // the block size is stored as a power of two (0 is the default)
+        let block_size = match block_size {
+            0x00 => BLOCK_SIZE,
+            exponent @ 16..=26 => 1 << exponent,
+            _ => return Err(anyhow::anyhow!("Error getting block size from header")),
+        };
+
+        let padding = match padding {
+            0x00 => Padding::None,
+            0x01 => Padding::Padme,
+            _ => return Err(anyhow::anyhow!("Error getting padding from header")),
+        };
+
+        let convergent = match convergent {
+            0x00 => false,
+            0x01 => true,
+            _ => return Err(anyhow::anyhow!("Error getting convergent flag from header")),
+        };
+
+        let mac = match mac {
+            0x00 => false,
+            0x01 => true,
+            _ => return Err(anyhow::anyhow!("Error getting MAC flag from header")),
+        };
+
+        if extensions
+            & !(DIGEST_FLAG
+                | SEEKABLE_FLAG
+                | KEYFILE_HASH_FLAG
+                | COUNTER_BE64_FLAG
+                | SUBKEYS_FLAG
+                | TWO_FACTOR_FLAG)
+            != 0
+        {
+            return Err(anyhow::anyhow!("Error getting extension flags from header"));
+        }
+
+        let seekable = extensions & SEEKABLE_FLAG != 0;
+        let keyfile_hash = extensions & KEYFILE_HASH_FLAG != 0;
+        let counter = if extensions & COUNTER_BE64_FLAG != 0 {
+            StreamCounter::Be64
+        } else {
+            StreamCounter::Le31
+        };
+        let subkeys = extensions & SUBKEYS_FLAG != 0;
+        let two_factor = extensions & TWO_FACTOR_FLAG != 0;
+
+        // the digest is filled in once the data has been encrypted, so it comes after the field section and isn't part of the AAD (it's authenticated by its own encryption instead)
+        let digest = if extensions & DIGEST_FLAG != 0 {
+            let mut bytes = [0u8; ENCRYPTED_DIGEST_LEN];
+            reader
+                .read_exact(&mut bytes)
+                .context("Unable to read the plaintext digest from header")?;
+            Some(bytes)
+        } else {
+            None
+        };
+
+        let metadata = fields
+            .iter()
+            .position(|f| f.tag == METADATA_FIELD)
+            .map(|index| Metadata::deserialize(&fields.remove(index).value))
+            .transpose()?;
+
+        let manifest = fields
+            .iter()
+            .position(|f| f.tag == MANIFEST_FIELD)
+            .map(|index| fields.remove(index).value);
+
+        if let Some(field) = fields
+            .iter()
+            .find(|f| f.is_critical() && f.tag != PLACEHOLDER_FIELD && f.tag != BACKUP_HEADER_FIELD)
+        {
+            return Err(anyhow::anyhow!(
+                "The header contains a critical field ({:#06x}) that isn't supported by this version of Dexios",
+                field.tag
+            ));
+        }
+
         let aad = match header_type.version {
             HeaderVersion::V1 | HeaderVersion::V2 => Vec::<u8>::new(),
             HeaderVersion::V3 => full_header_bytes.clone(),
@@ -472,53 +1182,414 @@ impl Header {
                 aad.extend_from_slice(&full_header_bytes[..32]);
                 aad
             }
+            HeaderVersion::V6 => {
+                let mut aad = Vec::new();
+                aad.extend_from_slice(&full_header_bytes[..32]);
+                if let Some(section) = &fields_section {
+                    aad.extend_from_slice(section);
+                }
+                aad
+            }
         };
 
-        Ok((
-            Header {
-                header_type,
-                nonce,
-                salt: Some(salt),
-                keyslots,
-            },
-            aad,
-        ))
+        let header = Header {
+            header_type,
+            nonce,
+            salt: Some(salt),
+            keyslots,
+            fields,
+            compression,
+            block_size,
+            padding,
+            convergent,
+            metadata,
+            mac,
+            digest,
+            seekable,
+            keyfile_hash,
+            counter,
+            subkeys,
+            two_factor,
+            manifest,
+        };
+
+        // this refuses options that don't make sense together (e.g. in memory mode), as they'd have been refused when the header was written
+        if header.header_type.version >= HeaderVersion::V6 {
+            header.check_capabilities()?;
+        }
+
+        Ok((header, aad))
     }
 
     /// This is a private function used for serialization
     ///
-    /// It converts an `Algorithm` into the associated raw bytes
-    fn serialize_algorithm(&self) -> [u8; 2] {
-        match self.header_type.algorithm {
-            Algorithm::XChaCha20Poly1305 => {
-                let info: [u8; 2] = [0x0E, 0x01];
-                info
-            }
-            Algorithm::Aes256Gcm => {
-                let info: [u8; 2] = [0x0E, 0x02];
-                info
-            }
-            Algorithm::DeoxysII256 => {
-                let info: [u8; 2] = [0x0E, 0x03];
-                info
-            }
+    /// It converts a `Compression` into the flag stored in the options field
+    fn serialize_compression(&self) -> u8 {
+        match self.compression {
+            Compression::None => 0x00,
+            Compression::Zstd(_) => 0x01,
+            Compression::ZstdPadded(_) => 0x02,
         }
     }
 
     /// This is a private function used for serialization
     ///
-    /// It converts a `Mode` into the associated raw bytes
-    fn serialize_mode(&self) -> [u8; 2] {
-        match self.header_type.mode {
-            Mode::StreamMode => {
-                let info: [u8; 2] = [0x0C, 0x01];
-                info
+    /// It converts the block size into the exponent stored in the options field
+    fn serialize_block_size(&self) -> u8 {
+        if self.block_size == BLOCK_SIZE {
+            0x00
+        } else {
+            // `check_capabilities()` ensures that this is a power of two, and fits in a `u8`
+            self.block_size.trailing_zeros() as u8
+        }
+    }
+
+    /// This is a private function used for serialization
+    ///
+    /// It converts a `Padding` into the flag stored in the options field
+    fn serialize_padding(&self) -> u8 {
+        match self.padding {
+            Padding::None => 0x00,
+            Padding::Padme => 0x01,
+        }
+    }
+
+    /// This is a private function used for serialization
+    ///
+    /// It converts the extensions that are in use into the flags stored in the options field
+    fn serialize_extensions(&self) -> u8 {
+        let mut extensions = 0;
+        if self.digest.is_some() {
+            extensions |= DIGEST_FLAG;
+        }
+        if self.seekable {
+            extensions |= SEEKABLE_FLAG;
+        }
+        if self.keyfile_hash {
+            extensions |= KEYFILE_HASH_FLAG;
+        }
+        if self.counter == StreamCounter::Be64 {
+            extensions |= COUNTER_BE64_FLAG;
+        }
+        if self.subkeys {
+            extensions |= SUBKEYS_FLAG;
+        }
+        if self.two_factor {
+            extensions |= TWO_FACTOR_FLAG;
+        }
+        extensions
+    }
+
+    /// This is a private function used for serialization
+    ///
+    /// It returns the value of the options field, without any trailing defaults (so it's empty if every option is the default, and the field isn't stored)
+    fn serialize_options(&self) -> Vec<u8> {
+        let mut options = vec![
+            self.serialize_compression(),
+            self.serialize_block_size(),
+            self.serialize_padding(),
+            u8::from(self.convergent),
+            u8::from(self.mac),
+            self.serialize_extensions(),
+        ];
+        while options.last() == Some(&0) {
+            options.pop();
+        }
+        options
+    }
+
+    /// This is a private function used for serialization
+    ///
+    /// It converts the fields of a V6 header (including the options, which are stored as a field) into the field section, prefixed with its length
+    fn serialize_fields(&self) -> Vec<u8> {
+        let options = Some(self.serialize_options()).filter(|o| !o.is_empty());
+        let metadata = self.metadata.as_ref().map(Metadata::serialize);
+
+        let mut fields: Vec<(u16, &[u8])> = self
+            .fields
+            .iter()
+            .map(|f| (f.tag, f.value.as_slice()))
+            .chain(options.as_ref().map(|o| (OPTIONS_FIELD, o.as_slice())))
+            .chain(metadata.as_ref().map(|m| (METADATA_FIELD, m.as_slice())))
+            .chain(
+                self.manifest
+                    .as_ref()
+                    .map(|m| (MANIFEST_FIELD, m.as_slice())),
+            )
+            .collect();
+        fields.sort_by_key(|(tag, _)| *tag);
+
+        let mut section = vec![0u8; 4];
+        for (tag, value) in fields {
+            section.extend_from_slice(&tag.to_le_bytes());
+            section.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            section.extend_from_slice(value);
+        }
+
+        let len = (section.len() - 4) as u32;
+        section[..4].copy_from_slice(&len.to_le_bytes());
+        section
+    }
+
+    /// This is a private function used for checking that the header version supports everything that's been requested
+    fn check_capabilities(&self) -> Result<()> {
+        // V1-V3 headers hash the key straight into the data's key, and V4 headers only have room for one keyslot
+        match self.header_type.version {
+            HeaderVersion::V1 | HeaderVersion::V2 | HeaderVersion::V3
+                if self.salt.is_none() || self.keyslots.is_some() =>
+            {
+                return Err(anyhow::anyhow!(
+                    "{} headers must have a salt, and no keyslots",
+                    self.header_type.version
+                ));
             }
-            Mode::MemoryMode => {
-                let info: [u8; 2] = [0x0C, 0x02];
-                info
+            HeaderVersion::V4 if self.keyslots.as_ref().map_or(0, Vec::len) != 1 => {
+                return Err(anyhow::anyhow!("V4 headers must have exactly one keyslot"));
+            }
+            _ => (),
+        }
+
+        check_nonce_len(&self.header_type)?;
+
+        if self.header_type.version < HeaderVersion::V6
+            && !matches!(
+                self.header_type.algorithm,
+                Algorithm::XChaCha20Poly1305 | Algorithm::Aes256Gcm | Algorithm::DeoxysII256
+            )
+        {
+            return Err(anyhow::anyhow!(
+                "{} is only supported by V6 headers",
+                self.header_type.algorithm
+            ));
+        }
+
+        if self.compression != Compression::None
+            && (self.header_type.version < HeaderVersion::V6
+                || self.header_type.mode == Mode::MemoryMode)
+        {
+            return Err(anyhow::anyhow!(
+                "Compression is only supported by V6 headers in stream mode"
+            ));
+        }
+
+        if self.header_type.mode == Mode::DerivedStreamMode
+            && self.header_type.version < HeaderVersion::V6
+        {
+            return Err(anyhow::anyhow!(
+                "Derived stream mode is only supported by V6 headers"
+            ));
+        }
+
+        if self.block_size != BLOCK_SIZE {
+            if self.header_type.version < HeaderVersion::V6
+                || self.header_type.mode == Mode::MemoryMode
+            {
+                return Err(anyhow::anyhow!(
+                    "Custom block sizes are only supported by V6 headers in stream mode"
+                ));
+            }
+
+            if !self.block_size.is_power_of_two()
+                || !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&self.block_size)
+            {
+                return Err(anyhow::anyhow!(
+                    "The block size must be a power of two between 64 KiB and 64 MiB"
+                ));
             }
         }
+
+        if self.convergent
+            && (self.header_type.version < HeaderVersion::V6
+                || self.header_type.mode != Mode::StreamMode)
+        {
+            return Err(anyhow::anyhow!(
+                "Convergent encryption is only supported by V6 headers in stream mode"
+            ));
+        }
+
+        if self.padding != Padding::None
+            && (self.header_type.version < HeaderVersion::V6
+                || self.header_type.mode == Mode::MemoryMode
+                || self.compression != Compression::None)
+        {
+            return Err(anyhow::anyhow!(
+                "Padding is only supported by V6 headers in stream mode, without compression"
+            ));
+        }
+
+        let custom_kdf = self
+            .keyslots
+            .as_ref()
+            .is_some_and(|k| k.iter().any(|k| k.hash_algorithm.is_custom()));
+        if custom_kdf && self.header_type.version < HeaderVersion::V6 {
+            return Err(anyhow::anyhow!(
+                "Custom KDF parameters are only supported by V6 headers"
+            ));
+        }
+
+        let scrypt = self.keyslots.as_ref().is_some_and(|k| {
+            k.iter().any(|k| {
+                !k.is_recipient() && !k.is_token() && k.hash_algorithm.family() == Kdf::Scrypt
+            })
+        });
+        if scrypt && self.header_type.version < HeaderVersion::V6 {
+            return Err(anyhow::anyhow!("scrypt is only supported by V6 headers"));
+        }
+
+        if self.recipient_count() > 0 && self.header_type.version < HeaderVersion::V6 {
+            return Err(anyhow::anyhow!(
+                "Recipient keyslots are only supported by V6 headers"
+            ));
+        }
+
+        let tokens = self
+            .keyslots
+            .as_ref()
+            .is_some_and(|k| k.iter().any(Keyslot::is_token));
+        if tokens && self.header_type.version < HeaderVersion::V6 {
+            return Err(anyhow::anyhow!(
+                "Token keyslots are only supported by V6 headers"
+            ));
+        }
+
+        if self.mac
+            && (self.header_type.version < HeaderVersion::V6
+                || self.header_type.mode == Mode::MemoryMode)
+        {
+            return Err(anyhow::anyhow!(
+                "MAC footers are only supported by V6 headers in stream mode"
+            ));
+        }
+
+        if self.digest.is_some()
+            && (self.header_type.version < HeaderVersion::V6
+                || self.header_type.mode == Mode::MemoryMode)
+        {
+            return Err(anyhow::anyhow!(
+                "Plaintext digests are only supported by V6 headers in stream mode"
+            ));
+        }
+
+        if self.seekable
+            && (self.header_type.version < HeaderVersion::V6
+                || self.header_type.mode == Mode::MemoryMode)
+        {
+            return Err(anyhow::anyhow!(
+                "The seekable format is only supported by V6 headers in stream mode"
+            ));
+        }
+
+        if self.keyfile_hash
+            && (self.header_type.version < HeaderVersion::V6
+                || self.header_type.mode == Mode::MemoryMode)
+        {
+            return Err(anyhow::anyhow!(
+                "Hashed keyfiles are only supported by V6 headers in stream mode"
+            ));
+        }
+
+        if self.counter == StreamCounter::Be64
+            && (self.header_type.version < HeaderVersion::V6
+                || self.header_type.mode != Mode::StreamMode)
+        {
+            return Err(anyhow::anyhow!(
+                "64-bit stream counters are only supported by V6 headers in stream mode"
+            ));
+        }
+
+        if self.counter == StreamCounter::Be64
+            && !crate::counter::is_supported(&self.header_type.algorithm)
+        {
+            return Err(anyhow::anyhow!(
+                "64-bit stream counters aren't supported by {}, as its nonce is too short",
+                self.header_type.algorithm
+            ));
+        }
+
+        if self.subkeys
+            && (self.header_type.version < HeaderVersion::V6
+                || self.header_type.mode == Mode::MemoryMode)
+        {
+            return Err(anyhow::anyhow!(
+                "Separate subkeys are only supported by V6 headers in stream mode"
+            ));
+        }
+
+        if self.two_factor
+            && (self.header_type.version < HeaderVersion::V6
+                || self.header_type.mode == Mode::MemoryMode)
+        {
+            return Err(anyhow::anyhow!(
+                "Two-factor keys are only supported by V6 headers in stream mode"
+            ));
+        }
+
+        // the header key is only independent of the payload key when the subkeys are separated
+        let metadata_only = self
+            .keyslots
+            .as_ref()
+            .is_some_and(|k| k.iter().any(Keyslot::is_metadata_only));
+        if (metadata_only || self.manifest.is_some()) && !self.subkeys {
+            return Err(anyhow::anyhow!(
+                "Metadata-only keyslots and manifests are only supported by headers with separate subkeys"
+            ));
+        }
+
+        if self
+            .manifest
+            .as_ref()
+            .is_some_and(|m| m.len() > MAX_MANIFEST_LEN)
+        {
+            return Err(anyhow::anyhow!(
+                "The manifest is too large to store in the header"
+            ));
+        }
+
+        if self.metadata.is_some() && self.header_type.version < HeaderVersion::V6 {
+            return Err(anyhow::anyhow!("Metadata is only supported by V6 headers"));
+        }
+
+        if !self.fields.is_empty() && self.header_type.version < HeaderVersion::V6 {
+            return Err(anyhow::anyhow!("Fields are only supported by V6 headers"));
+        }
+
+        let mut tags = std::collections::HashSet::new();
+        if self
+            .fields
+            .iter()
+            .any(|f| MEMBER_FIELDS.contains(&f.tag) || !tags.insert(f.tag))
+        {
+            return Err(anyhow::anyhow!(
+                "Each field may only appear once, and the options, metadata and manifest must be stored in their own members"
+            ));
+        }
+
+        if self.header_type.version >= HeaderVersion::V6
+            && self.serialize_fields().len() - 4 > MAX_FIELDS_LEN
+        {
+            return Err(anyhow::anyhow!(
+                "The fields are too large to store in the header"
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// This is a private function (called by `serialize()`)
+    ///
+    /// It serializes V2 headers, which are only written for compatibility with older versions of dexios (they aren't authenticated)
+    fn serialize_v2(&self, tag: &HeaderTag) -> Vec<u8> {
+        let padding =
+            vec![0u8; 26 - get_nonce_len(&self.header_type.algorithm, &self.header_type.mode)];
+        let mut header_bytes = Vec::<u8>::new();
+        header_bytes.extend_from_slice(&tag.version);
+        header_bytes.extend_from_slice(&tag.algorithm);
+        header_bytes.extend_from_slice(&tag.mode);
+        header_bytes.extend_from_slice(&self.salt.unwrap());
+        header_bytes.extend_from_slice(&self.nonce);
+        header_bytes.extend_from_slice(&padding);
+        header_bytes.extend_from_slice(&[0; 16]);
+        header_bytes
     }
 
     /// This is a private function (called by `serialize()`)
@@ -564,7 +1635,7 @@ impl Header {
 
     /// This is a private function (called by `serialize()`)
     ///
-    /// It serializes V5 headers
+    /// It serializes V5 and V6 headers (which share a layout, besides V6 appending a field section)
     fn serialize_v5(&self, tag: &HeaderTag) -> Vec<u8> {
         let padding =
             vec![0u8; 26 - get_nonce_len(&self.header_type.algorithm, &self.header_type.mode)];
@@ -589,13 +1660,25 @@ impl Header {
             header_bytes.extend_from_slice(&keyslot.nonce);
             header_bytes.extend_from_slice(&vec![0u8; 24 - keyslot_nonce_len]);
             header_bytes.extend_from_slice(&keyslot.salt);
-            header_bytes.extend_from_slice(&[0u8; 6]);
+            header_bytes.extend_from_slice(&keyslot.serialize_params());
         }
 
-        for _ in 0..(4 - keyslots.len()) {
+        for _ in 0..(MAX_KEYSLOTS - keyslots.len()) {
             header_bytes.extend_from_slice(&[0u8; 96]);
         }
 
+        for encapsulated_key in keyslots.iter().filter_map(|k| k.encapsulated_key.as_ref()) {
+            header_bytes.extend_from_slice(encapsulated_key);
+        }
+
+        if self.header_type.version >= HeaderVersion::V6 {
+            header_bytes.extend_from_slice(&self.serialize_fields());
+        }
+
+        if let Some(digest) = &self.digest {
+            header_bytes.extend_from_slice(digest);
+        }
+
         header_bytes
     }
 
@@ -605,7 +1688,7 @@ impl Header {
     ///
     /// NOTE: This should **NOT** be used for validating or creating AAD.
     ///
-    /// It only has support for V3 headers and above
+    /// It only has support for V2 headers and above, as V1 headers' KDF parameters are too weak to be used for new files
     ///
     /// Create AAD with `create_aad()`.
     ///
@@ -618,45 +1701,73 @@ impl Header {
     /// ```
     ///
     pub fn serialize(&self) -> Result<Vec<u8>> {
+        self.check_capabilities()?;
         let tag = self.get_tag();
         match self.header_type.version {
             HeaderVersion::V1 => Err(anyhow::anyhow!(
                 "Serializing V1 headers has been deprecated"
             )),
-            HeaderVersion::V2 => Err(anyhow::anyhow!(
-                "Serializing V2 headers has been deprecated"
-            )),
+            HeaderVersion::V2 => Ok(self.serialize_v2(&tag)),
             HeaderVersion::V3 => Ok(self.serialize_v3(&tag)),
             HeaderVersion::V4 => Ok(self.serialize_v4(&tag)),
-            HeaderVersion::V5 => Ok(self.serialize_v5(&tag)),
+            HeaderVersion::V5 | HeaderVersion::V6 => Ok(self.serialize_v5(&tag)),
         }
     }
 
+    /// This checks whether the header contains a field (only V6 headers have any)
+    #[must_use]
+    pub fn has_field(&self, tag: u16) -> bool {
+        self.fields.iter().any(|f| f.tag == tag)
+    }
+
+    /// This returns the full size of the header, including any encapsulated keys, its field section, and the plaintext digest
     #[must_use]
     pub fn get_size(&self) -> u64 {
         match self.header_type.version {
             HeaderVersion::V1 | HeaderVersion::V2 | HeaderVersion::V3 => 64,
             HeaderVersion::V4 => 128,
             HeaderVersion::V5 => 416,
+            HeaderVersion::V6 => {
+                let digest_len = if self.digest.is_some() {
+                    ENCRYPTED_DIGEST_LEN
+                } else {
+                    0
+                };
+                let encapsulated_len: usize = self.keyslots.as_ref().map_or(0, |keyslots| {
+                    keyslots
+                        .iter()
+                        .filter_map(|k| k.encapsulated_key.as_ref())
+                        .map(Vec::len)
+                        .sum()
+                });
+                (416 + encapsulated_len + self.serialize_fields().len() + digest_len) as u64
+            }
         }
     }
 
+    /// This returns the number of recipient keyslots within the header
+    #[must_use]
+    pub fn recipient_count(&self) -> usize {
+        self.keyslots.as_ref().map_or(0, |keyslots| {
+            keyslots.iter().filter(|k| k.is_recipient()).count()
+        })
+    }
+
     /// This is for creating AAD
     ///
-    /// It only has support for V3 headers and above
+    /// It only has support for V2 headers and above, and V2 headers have no AAD (so the returned bytes are empty)
     ///
     /// It will return the bytes used for AAD
     ///
     /// You may view more about what is used as AAD [here](https://brxken128.github.io/dexios/dexios-core/Headers.html#authenticating-the-header-with-aad-v840).
     pub fn create_aad(&self) -> Result<Vec<u8>> {
+        self.check_capabilities()?;
         let tag = self.get_tag();
         match self.header_type.version {
             HeaderVersion::V1 => Err(anyhow::anyhow!(
                 "Serializing V1 headers has been deprecated"
             )),
-            HeaderVersion::V2 => Err(anyhow::anyhow!(
-                "Serializing V2 headers has been deprecated"
-            )),
+            HeaderVersion::V2 => Ok(Vec::new()),
             HeaderVersion::V3 => Ok(self.serialize_v3(&tag)),
             HeaderVersion::V4 => {
                 let padding =
@@ -684,7 +1795,7 @@ impl Header {
                 header_bytes.extend_from_slice(&padding2);
                 Ok(header_bytes)
             }
-            HeaderVersion::V5 => {
+            HeaderVersion::V5 | HeaderVersion::V6 => {
                 let mut header_bytes = Vec::<u8>::new();
                 header_bytes.extend_from_slice(&tag.version);
                 header_bytes.extend_from_slice(&tag.algorithm);
@@ -697,11 +1808,41 @@ impl Header {
                         &self.header_type.mode
                     )
                 ]);
+                if self.header_type.version >= HeaderVersion::V6 {
+                    header_bytes.extend_from_slice(&self.serialize_fields());
+                }
                 Ok(header_bytes)
             }
         }
     }
 
+    /// This returns a copy of the header in the latest layout (`HEADER_VERSION`)
+    ///
+    /// Only V5 headers (and above) can be migrated, as older versions store their keyslots differently (they need to be decrypted with their own parameters).
+    ///
+    /// The AAD changes along with the layout, so the data must be encrypted again with the returned header (and new nonces), e.g. by `key rotate`. Its keyslots still unlock the same master key.
+    pub fn migrate(self) -> Result<Self> {
+        match self.header_type.version {
+            HeaderVersion::V1 | HeaderVersion::V2 | HeaderVersion::V3 | HeaderVersion::V4 => {
+                Err(anyhow::anyhow!(
+                    "Only V5 headers and above can be migrated to {}",
+                    HEADER_VERSION
+                ))
+            }
+            HeaderVersion::V5 | HeaderVersion::V6 => {
+                let header = Header {
+                    header_type: HeaderType {
+                        version: HEADER_VERSION,
+                        ..self.header_type
+                    },
+                    ..self
+                };
+                header.check_capabilities()?;
+                Ok(header)
+            }
+        }
+    }
+
     /// This is a convenience function for writing a header to a writer
     ///
     /// # Examples
@@ -721,3 +1862,632 @@ impl Header {
         Ok(())
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn header(
+        version: HeaderVersion,
+        algorithm: Algorithm,
+        fields: Vec<Field>,
+    ) -> Header {
+        Header {
+            header_type: HeaderType {
+                version,
+                algorithm,
+                mode: Mode::StreamMode,
+            },
+            nonce: vec![1u8; get_nonce_len(&algorithm, &Mode::StreamMode)],
+            salt: None,
+            keyslots: Some(vec![Keyslot {
+                hash_algorithm: HashingAlgorithm::Blake3Balloon(5),
+                encrypted_key: [2u8; ENCRYPTED_MASTER_KEY_LEN],
+                nonce: vec![3u8; get_nonce_len(&algorithm, &Mode::MemoryMode)],
+                salt: [4u8; SALT_LEN],
+                encapsulated_key: None,
+                token: false,
+                metadata_only: false,
+            }]),
+            fields,
+            compression: Compression::None,
+            block_size: BLOCK_SIZE,
+            padding: Padding::None,
+            convergent: false,
+            metadata: None,
+            mac: false,
+            digest: None,
+            seekable: false,
+            keyfile_hash: false,
+            counter: StreamCounter::Le31,
+            subkeys: false,
+            two_factor: false,
+            manifest: None,
+        }
+    }
+
+    fn field_bytes(tag: u16, value: &[u8]) -> Vec<u8> {
+        let mut bytes = tag.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(value);
+        bytes
+    }
+
+    #[test]
+    fn should_round_trip_fields_in_tag_order() {
+        let header = header(
+            HeaderVersion::V6,
+            Algorithm::XChaCha20Poly1305,
+            vec![
+                Field {
+                    tag: 0x0200,
+                    value: b"second".to_vec(),
+                },
+                Field {
+                    tag: 0x0100,
+                    value: b"first".to_vec(),
+                },
+            ],
+        );
+
+        let bytes = header.serialize().unwrap();
+        assert_eq!(bytes.len() as u64, header.get_size());
+
+        let (deserialized, aad) = Header::deserialize(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(aad, header.create_aad().unwrap());
+        assert_eq!(
+            deserialized
+                .fields
+                .iter()
+                .map(|f| f.tag)
+                .collect::<Vec<_>>(),
+            vec![0x0100, 0x0200]
+        );
+        assert!(deserialized.has_field(0x0200));
+    }
+
+    #[test]
+    fn should_refuse_unknown_critical_fields() {
+        let header = header(
+            HeaderVersion::V6,
+            Algorithm::XChaCha20Poly1305,
+            vec![Field {
+                tag: CRITICAL_FIELD | 0x0100,
+                value: Vec::new(),
+            }],
+        );
+
+        let bytes = header.serialize().unwrap();
+        assert!(Header::deserialize(&mut Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn should_refuse_truncated_fields() {
+        let mut section = field_bytes(0x0100, b"value");
+        section.truncate(section.len() - 1);
+        assert!(deserialize_fields(&section).is_err());
+
+        // a tag without a length
+        assert!(deserialize_fields(&[0x00, 0x01, 0x05]).is_err());
+    }
+
+    #[test]
+    fn should_cap_the_field_section() {
+        let field = Field {
+            tag: 0x0100,
+            value: vec![0u8; MAX_FIELDS_LEN],
+        };
+        assert!(
+            header(HeaderVersion::V6, Algorithm::XChaCha20Poly1305, vec![field])
+                .serialize()
+                .is_err()
+        );
+
+        // a length prefix beyond the cap is refused before anything is allocated for it
+        let mut bytes = header(HeaderVersion::V6, Algorithm::XChaCha20Poly1305, Vec::new())
+            .serialize()
+            .unwrap();
+        bytes.truncate(416);
+        bytes.extend_from_slice(&(MAX_FIELDS_LEN as u32 + 1).to_le_bytes());
+        assert!(Header::deserialize(&mut Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn should_cover_the_field_section_with_the_aad() {
+        let field = |value: &[u8]| Field {
+            tag: 0x0100,
+            value: value.to_vec(),
+        };
+        let mut header = header(
+            HeaderVersion::V6,
+            Algorithm::XChaCha20Poly1305,
+            vec![field(b"value")],
+        );
+        let aad = header.create_aad().unwrap();
+
+        // the AAD read back from the header is the one it was encrypted with
+        let (_, deserialized_aad) =
+            Header::deserialize(&mut Cursor::new(header.serialize().unwrap())).unwrap();
+        assert_eq!(aad, deserialized_aad);
+
+        header.fields = vec![field(b"other")];
+        assert_ne!(aad, header.create_aad().unwrap());
+
+        header.fields = Vec::new();
+        assert_ne!(aad, header.create_aad().unwrap());
+    }
+
+    #[test]
+    fn should_refuse_repeated_or_unordered_fields() {
+        let first = field_bytes(0x0100, b"a");
+        let second = field_bytes(0x0200, b"b");
+
+        assert!(deserialize_fields(&[first.clone(), second.clone()].concat()).is_ok());
+        assert!(deserialize_fields(&[second, first.clone()].concat()).is_err());
+        assert!(deserialize_fields(&[first.clone(), first].concat()).is_err());
+    }
+
+    #[test]
+    fn should_refuse_v6_headers_with_reserved_bytes_set() {
+        let header = header(HeaderVersion::V6, Algorithm::XChaCha20Poly1305, Vec::new());
+
+        let mut bytes = header.serialize().unwrap();
+        bytes[31] = 1;
+        assert!(Header::deserialize(&mut Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn should_store_options_in_a_canonical_field() {
+        let mut header = header(HeaderVersion::V6, Algorithm::XChaCha20Poly1305, Vec::new());
+        assert_eq!(header.serialize_fields(), vec![0u8; 4]);
+
+        header.compression = Compression::Zstd(3);
+        let bytes = header.serialize().unwrap();
+        let (deserialized, _) = Header::deserialize(&mut Cursor::new(bytes)).unwrap();
+        assert!(deserialized.compression == Compression::Zstd(0));
+        assert!(deserialized.fields.is_empty());
+
+        // options that aren't the default must be stored in their own members, rather than as a field
+        header.fields.push(Field {
+            tag: OPTIONS_FIELD,
+            value: vec![0x01],
+        });
+        assert!(header.serialize().is_err());
+
+        // an options field may not end with a default, or contain options that aren't known
+        for value in [vec![], vec![0x01, 0x00], vec![0x01; OPTIONS_LEN + 1]] {
+            header.compression = Compression::None;
+            header.fields = Vec::new();
+            let mut bytes = header.serialize().unwrap();
+            bytes.truncate(bytes.len() - 4);
+
+            let section = field_bytes(OPTIONS_FIELD, &value);
+            bytes.extend_from_slice(&(section.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&section);
+            assert!(Header::deserialize(&mut Cursor::new(bytes)).is_err());
+        }
+    }
+
+    #[test]
+    fn should_store_block_sizes_as_powers_of_two() {
+        let mut header = header(HeaderVersion::V6, Algorithm::XChaCha20Poly1305, Vec::new());
+        header.block_size = MIN_BLOCK_SIZE;
+
+        // the compression flag is still stored, as it's followed by an option that isn't the default
+        assert_eq!(header.serialize_options(), vec![0x00, 16]);
+        let bytes = header.serialize().unwrap();
+        let (deserialized, _) = Header::deserialize(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(deserialized.block_size, MIN_BLOCK_SIZE);
+
+        header.block_size = MIN_BLOCK_SIZE + 1;
+        assert!(header.serialize().is_err());
+        header.block_size = MAX_BLOCK_SIZE * 2;
+        assert!(header.serialize().is_err());
+    }
+
+    #[test]
+    fn should_only_set_options_in_v6_headers_in_stream_mode() {
+        // each option's name, how it's set, how it's serialized, and whether a header has it
+        type Case = (&'static str, fn(&mut Header), Vec<u8>, fn(&Header) -> bool);
+        let options: [Case; 5] = [
+            (
+                "padding",
+                |h| h.padding = Padding::Padme,
+                vec![0, 0, 1],
+                |h| h.padding == Padding::Padme,
+            ),
+            (
+                "keyfile hash",
+                |h| h.keyfile_hash = true,
+                vec![0, 0, 0, 0, 0, KEYFILE_HASH_FLAG],
+                |h| h.keyfile_hash,
+            ),
+            (
+                "64-bit counter",
+                |h| h.counter = StreamCounter::Be64,
+                vec![0, 0, 0, 0, 0, COUNTER_BE64_FLAG],
+                |h| h.counter == StreamCounter::Be64,
+            ),
+            (
+                "subkeys",
+                |h| h.subkeys = true,
+                vec![0, 0, 0, 0, 0, SUBKEYS_FLAG],
+                |h| h.subkeys,
+            ),
+            (
+                "two factor",
+                |h| h.two_factor = true,
+                vec![0, 0, 0, 0, 0, TWO_FACTOR_FLAG],
+                |h| h.two_factor,
+            ),
+        ];
+
+        for (name, set, serialized_options, is_set) in options {
+            let mut header = header(HeaderVersion::V6, Algorithm::XChaCha20Poly1305, Vec::new());
+            set(&mut header);
+            assert_eq!(header.serialize_options(), serialized_options, "{name}");
+
+            let bytes = header.serialize().unwrap();
+            let (deserialized, _) = Header::deserialize(&mut Cursor::new(bytes)).unwrap();
+            assert!(is_set(&deserialized), "{name}");
+
+            header.header_type.mode = Mode::MemoryMode;
+            assert!(header.serialize().is_err(), "{name} in memory mode");
+
+            header.header_type.mode = Mode::StreamMode;
+            header.header_type.version = HeaderVersion::V5;
+            assert!(header.serialize().is_err(), "{name} in a V5 header");
+        }
+    }
+
+    #[test]
+    fn should_not_pad_compressed_blocks() {
+        let mut header = header(HeaderVersion::V6, Algorithm::XChaCha20Poly1305, Vec::new());
+        header.padding = Padding::Padme;
+        header.compression = Compression::Zstd(3);
+        assert!(header.serialize().is_err());
+    }
+
+    #[test]
+    fn should_store_the_manifest_in_its_own_field() {
+        let mut header = header(HeaderVersion::V6, Algorithm::XChaCha20Poly1305, Vec::new());
+        header.manifest = Some(vec![7u8; 40]);
+        header.keyslots.as_mut().unwrap()[0].metadata_only = true;
+
+        // the manifest (and metadata-only keyslots) need a header key that's independent of the payload key
+        assert!(header.serialize().is_err());
+        header.subkeys = true;
+
+        let bytes = header.serialize().unwrap();
+        assert_eq!(bytes.len() as u64, header.get_size());
+        assert_eq!(&bytes[32..34], &METADATA_KEYSLOT_ID);
+
+        let (deserialized, aad) = Header::deserialize(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(deserialized.manifest, Some(vec![7u8; 40]));
+        assert!(deserialized.keyslots.unwrap()[0].is_metadata_only());
+        assert!(deserialized.fields.is_empty());
+
+        // unlike the digest, it's authenticated by the AAD
+        header.manifest = Some(vec![8u8; 40]);
+        assert_ne!(header.create_aad().unwrap(), aad);
+
+        header.fields.push(Field {
+            tag: MANIFEST_FIELD,
+            value: vec![7u8; 40],
+        });
+        assert!(header.serialize().is_err());
+    }
+
+    #[test]
+    fn should_identify_v6_token_keyslots() {
+        let mut header = header(HeaderVersion::V6, Algorithm::XChaCha20Poly1305, Vec::new());
+        header.keyslots.as_mut().unwrap()[0].token = true;
+
+        let bytes = header.serialize().unwrap();
+        assert_eq!(&bytes[32..34], &TOKEN_KEYSLOT_ID);
+
+        let (deserialized, _) = Header::deserialize(&mut Cursor::new(bytes)).unwrap();
+        assert!(deserialized.keyslots.unwrap()[0].is_token());
+
+        header.header_type.version = HeaderVersion::V5;
+        assert!(header.serialize().is_err());
+    }
+
+    #[test]
+    fn should_append_encapsulated_keys_to_v6_keyslots() {
+        let mut header = header(HeaderVersion::V6, Algorithm::XChaCha20Poly1305, Vec::new());
+        let mut keyslot = header.keyslots.as_ref().unwrap()[0].clone();
+        keyslot.encapsulated_key = Some(vec![5u8; ENCAPSULATED_KEY_LEN]);
+        header.keyslots.as_mut().unwrap().push(keyslot.clone());
+        keyslot.encapsulated_key = Some(vec![6u8; X25519_ENCAPSULATED_KEY_LEN]);
+        header.keyslots.as_mut().unwrap().push(keyslot);
+
+        let bytes = header.serialize().unwrap();
+        assert_eq!(bytes.len() as u64, header.get_size());
+        assert_eq!(&bytes[128..130], &RECIPIENT_KEYSLOT_ID);
+        assert_eq!(&bytes[224..226], &X25519_RECIPIENT_KEYSLOT_ID);
+
+        let (deserialized, _) = Header::deserialize(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(deserialized.recipient_count(), 2);
+        let keyslots = deserialized.keyslots.unwrap();
+        assert_eq!(
+            keyslots[1].encapsulated_key,
+            Some(vec![5u8; ENCAPSULATED_KEY_LEN])
+        );
+        assert_eq!(
+            keyslots[2].encapsulated_key,
+            Some(vec![6u8; X25519_ENCAPSULATED_KEY_LEN])
+        );
+
+        header.header_type.version = HeaderVersion::V5;
+        assert!(header.serialize().is_err());
+    }
+
+    #[test]
+    fn should_store_metadata_in_its_own_field() {
+        let mut header = header(HeaderVersion::V6, Algorithm::XChaCha20Poly1305, Vec::new());
+        header.metadata = Some(Metadata {
+            created: 1_664_546_700,
+            version: "dexios 8.8.1".to_string(),
+        });
+
+        let bytes = header.serialize().unwrap();
+        assert_eq!(bytes.len() as u64, header.get_size());
+        let (deserialized, _) = Header::deserialize(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(deserialized.metadata, header.metadata);
+        assert!(deserialized.fields.is_empty());
+
+        // metadata that's too short (or too long) to have been written by Dexios is refused
+        for value in [vec![0u8; 7], vec![0x61; 8 + METADATA_VERSION_LEN + 1]] {
+            let mut bytes = header.serialize().unwrap()[..416].to_vec();
+            bytes.extend_from_slice(&((value.len() + 6) as u32).to_le_bytes());
+            bytes.extend_from_slice(&field_bytes(METADATA_FIELD, &value));
+            assert!(Header::deserialize(&mut Cursor::new(bytes)).is_err());
+        }
+
+        header.header_type.version = HeaderVersion::V5;
+        assert!(header.serialize().is_err());
+    }
+
+    #[test]
+    fn should_leave_the_digest_out_of_the_aad() {
+        let mut header = header(HeaderVersion::V6, Algorithm::XChaCha20Poly1305, Vec::new());
+        header.digest = Some([0u8; ENCRYPTED_DIGEST_LEN]);
+        let aad = header.create_aad().unwrap();
+
+        // the digest is only filled in once encryption has finished, so changing it mustn't change the AAD
+        header.digest = Some([9u8; ENCRYPTED_DIGEST_LEN]);
+        assert_eq!(header.create_aad().unwrap(), aad);
+
+        let bytes = header.serialize().unwrap();
+        assert_eq!(bytes.len() as u64, header.get_size());
+        assert!(bytes.ends_with(&[9u8; ENCRYPTED_DIGEST_LEN]));
+
+        let (deserialized, deserialized_aad) =
+            Header::deserialize(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(deserialized.digest, header.digest);
+        assert_eq!(deserialized_aad, aad);
+
+        header.header_type.mode = Mode::MemoryMode;
+        assert!(header.serialize().is_err());
+    }
+
+    #[test]
+    fn should_only_store_custom_kdf_params_in_v6_keyslots() {
+        let mut header = header(HeaderVersion::V6, Algorithm::XChaCha20Poly1305, Vec::new());
+        let params = crate::kdf::Argon2idParams {
+            m_cost: 65_536,
+            t_cost: 3,
+            p_cost: 2,
+        };
+        header.keyslots.as_mut().unwrap()[0].hash_algorithm =
+            HashingAlgorithm::Argon2idCustom(params);
+
+        let bytes = header.serialize().unwrap();
+        let (deserialized, _) = Header::deserialize(&mut Cursor::new(bytes.clone())).unwrap();
+        assert!(
+            deserialized.keyslots.unwrap()[0].hash_algorithm
+                == HashingAlgorithm::Argon2idCustom(params)
+        );
+
+        // V5 keyslots don't have room for the parameters, so the identifier isn't recognised
+        let mut bytes = bytes;
+        bytes[..2].copy_from_slice(&[0xDE, 0x05]);
+        assert!(Header::deserialize(&mut Cursor::new(bytes)).is_err());
+
+        header.header_type.version = HeaderVersion::V5;
+        assert!(header.serialize().is_err());
+    }
+
+    #[test]
+    fn should_only_compress_v6_headers_in_stream_mode() {
+        let mut v5 = header(HeaderVersion::V5, Algorithm::XChaCha20Poly1305, Vec::new());
+        v5.compression = Compression::Zstd(3);
+        assert!(v5.serialize().is_err());
+
+        let mut memory = header(HeaderVersion::V6, Algorithm::XChaCha20Poly1305, Vec::new());
+        memory.header_type.mode = Mode::MemoryMode;
+        memory.nonce = vec![1u8; get_nonce_len(&Algorithm::XChaCha20Poly1305, &Mode::MemoryMode)];
+        memory.compression = Compression::Zstd(3);
+        assert!(memory.serialize().is_err());
+    }
+
+    #[test]
+    fn should_only_store_derived_stream_mode_in_v6_headers() {
+        let mut header = header(HeaderVersion::V5, Algorithm::XChaCha20Poly1305, Vec::new());
+        header.header_type.mode = Mode::DerivedStreamMode;
+        assert!(header.serialize().is_err());
+
+        // the mode is read from V5 headers as if it were unknown
+        header.header_type.version = HeaderVersion::V6;
+        let mut bytes = header.serialize().unwrap();
+        assert!(Header::deserialize(&mut Cursor::new(bytes.clone())).is_ok());
+        bytes[1] = 0x05;
+        bytes.truncate(416);
+        assert!(Header::deserialize(&mut Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn should_only_store_aegis_and_fields_in_v6_headers() {
+        assert!(header(HeaderVersion::V5, Algorithm::Aegis256, Vec::new())
+            .serialize()
+            .is_err());
+        assert!(header(HeaderVersion::V6, Algorithm::Aegis256, Vec::new())
+            .serialize()
+            .is_ok());
+
+        let field = Field {
+            tag: 0x0100,
+            value: Vec::new(),
+        };
+        assert!(
+            header(HeaderVersion::V5, Algorithm::XChaCha20Poly1305, vec![field])
+                .serialize()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn should_migrate_v5_headers_to_the_latest_version() {
+        let v5 = header(HeaderVersion::V5, Algorithm::XChaCha20Poly1305, Vec::new());
+        let migrated = v5.migrate().unwrap();
+        assert!(migrated.header_type.version == HEADER_VERSION);
+
+        // the keyslots are carried over as they are, so they still unlock the same master key
+        let bytes = migrated.serialize().unwrap();
+        let (deserialized, _) = Header::deserialize(&mut Cursor::new(bytes)).unwrap();
+        assert!(deserialized.header_type.version == HEADER_VERSION);
+        assert_eq!(
+            deserialized.keyslots.unwrap()[0].encrypted_key,
+            [2u8; ENCRYPTED_MASTER_KEY_LEN]
+        );
+
+        let v4 = header(HeaderVersion::V4, Algorithm::XChaCha20Poly1305, Vec::new());
+        assert!(v4.migrate().is_err());
+    }
+
+    #[test]
+    fn should_keep_random_padding_in_the_fields() {
+        let padding = Field::random_padding(64);
+        assert!(padding.value.len() <= 64);
+        assert!(!padding.is_critical());
+
+        let bytes = header(
+            HeaderVersion::V6,
+            Algorithm::XChaCha20Poly1305,
+            vec![padding.clone()],
+        )
+        .serialize()
+        .unwrap();
+        let (deserialized, _) = Header::deserialize(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(deserialized.fields, vec![padding]);
+    }
+
+    #[test]
+    fn should_recognise_the_placeholder_field() {
+        let placeholder = Field {
+            tag: PLACEHOLDER_FIELD,
+            value: Vec::new(),
+        };
+        assert!(placeholder.is_critical());
+
+        let bytes = header(
+            HeaderVersion::V6,
+            Algorithm::XChaCha20Poly1305,
+            vec![placeholder],
+        )
+        .serialize()
+        .unwrap();
+        let (deserialized, _) = Header::deserialize(&mut Cursor::new(bytes)).unwrap();
+        assert!(deserialized.has_field(PLACEHOLDER_FIELD));
+        assert!(!deserialized.has_field(PADDING_FIELD));
+    }
+
+    #[test]
+    fn should_recognise_the_backup_header_field() {
+        let backup = Field {
+            tag: BACKUP_HEADER_FIELD,
+            value: Vec::new(),
+        };
+        assert!(backup.is_critical());
+
+        let bytes = header(
+            HeaderVersion::V6,
+            Algorithm::XChaCha20Poly1305,
+            vec![backup],
+        )
+        .serialize()
+        .unwrap();
+        let (deserialized, _) = Header::deserialize(&mut Cursor::new(bytes)).unwrap();
+        assert!(deserialized.has_field(BACKUP_HEADER_FIELD));
+    }
+
+    #[test]
+    fn should_serialize_legacy_headers_for_older_readers() {
+        for version in [HeaderVersion::V2, HeaderVersion::V3] {
+            let legacy = Header {
+                salt: Some([4u8; SALT_LEN]),
+                keyslots: None,
+                ..header(version, Algorithm::XChaCha20Poly1305, Vec::new())
+            };
+            let bytes = legacy.serialize().unwrap();
+            assert_eq!(bytes.len(), 64);
+
+            let (deserialized, aad) = Header::deserialize(&mut Cursor::new(&bytes)).unwrap();
+            assert!(deserialized.header_type.version == version);
+            assert_eq!(deserialized.salt, Some([4u8; SALT_LEN]));
+
+            // V2 headers aren't authenticated at all
+            match version {
+                HeaderVersion::V2 => assert!(aad.is_empty()),
+                _ => assert_eq!(aad, bytes),
+            }
+
+            // the hashed key encrypts the data directly, so there's nowhere to put a keyslot
+            assert!(header(version, Algorithm::XChaCha20Poly1305, Vec::new())
+                .serialize()
+                .is_err());
+        }
+
+        let mut v4 = header(HeaderVersion::V4, Algorithm::XChaCha20Poly1305, Vec::new());
+        assert!(v4.serialize().is_ok());
+        let keyslot = v4.keyslots.as_ref().unwrap()[0].clone();
+        v4.keyslots.as_mut().unwrap().push(keyslot);
+        assert!(v4.serialize().is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn should_round_trip_a_header_through_serde() {
+        let header = Header {
+            compression: Compression::Zstd(3),
+            metadata: Some(Metadata {
+                created: 1_700_000_000,
+                version: "dexios 8.8.1".to_string(),
+            }),
+            mac: true,
+            digest: Some([5u8; ENCRYPTED_DIGEST_LEN]),
+            keyfile_hash: true,
+            subkeys: true,
+            ..header(
+                HeaderVersion::V6,
+                Algorithm::XChaCha20Poly1305,
+                vec![Field {
+                    tag: 0x0100,
+                    value: b"kept as it is".to_vec(),
+                }],
+            )
+        };
+
+        let json = serde_json::to_string(&header).unwrap();
+        let round_tripped: Header = serde_json::from_str(&json).unwrap();
+
+        // the byte format covers every member, so both headers are the same if their bytes are
+        assert_eq!(
+            round_tripped.serialize().unwrap(),
+            header.serialize().unwrap()
+        );
+        assert!(round_tripped.digest == header.digest);
+        assert!(round_tripped.metadata == header.metadata);
+    }
+}
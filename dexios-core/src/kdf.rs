@@ -0,0 +1,695 @@
+//! This module contains the key derivation functions (KDFs) supported by `dexios-core`
+//!
+//! Every KDF implements the `KeyDerivation` trait, which exposes the bytes that identify it within a header, and a way to derive a 32-byte key from a raw key and a salt.
+//!
+//! Each implementation carries its own parameters struct, so the costs are always explicit, rather than being inferred from a header version.
+//!
+//! `scrypt` is also supported on V6 headers, for users that would rather use it over BLAKE3-Balloon or `argon2id`.
+//!
+//! Parameters may also be chosen by the user (see `Argon2id::custom()`, `Blake3Balloon::custom()` and `Scrypt::custom()`). These are stored in the keyslot alongside the KDF's identifier (V6+), so decryption doesn't need to guess them.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! let salt = gen_salt();
+//! let raw_key = Protected::new(b"secure key".to_vec());
+//! let kdf = HashingAlgorithm::Blake3Balloon(5).kdf().unwrap();
+//! let key = kdf.derive(raw_key, &salt).unwrap();
+//! ```
+
+use anyhow::Result;
+
+use crate::primitives::SALT_LEN;
+use crate::protected::Protected;
+
+/// This is the length of the custom parameters that are stored within a keyslot
+pub const KDF_PARAMS_LEN: usize = 6;
+
+/// This is the most memory (in KiB) that custom parameters may use
+///
+/// Custom parameters are read from the header before the key is checked, so this stops a crafted header from making decryption allocate an unbounded amount of memory
+pub const MAX_KDF_MEMORY: u64 = 4_194_304; // 4GiB
+
+/// This identifies `argon2id` with custom parameters within a keyslot
+pub const ARGON2ID_CUSTOM_ID: [u8; 2] = [0xDF, 0xAF];
+
+/// This identifies BLAKE3-Balloon with custom parameters within a keyslot
+pub const BLAKE3BALLOON_CUSTOM_ID: [u8; 2] = [0xDF, 0xBF];
+
+/// This identifies `scrypt` with custom parameters within a keyslot
+pub const SCRYPT_CUSTOM_ID: [u8; 2] = [0xDF, 0xDF];
+
+/// This is a KDF "family", without any parameters attached
+///
+/// It's used for selecting a KDF (e.g. from the command line), see `crate::key::derive_key()`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kdf {
+    Argon2id,
+    Blake3Balloon,
+    Scrypt,
+}
+
+/// This is an array containing every KDF family supported by `dexios-core`
+pub static KDFS: [Kdf; 3] = [Kdf::Argon2id, Kdf::Blake3Balloon, Kdf::Scrypt];
+
+impl std::fmt::Display for Kdf {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Kdf::Argon2id => write!(f, "argon2id"),
+            Kdf::Blake3Balloon => write!(f, "blake3-balloon"),
+            Kdf::Scrypt => write!(f, "scrypt"),
+        }
+    }
+}
+
+impl std::str::FromStr for Kdf {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        KDFS.iter()
+            .find(|kdf| kdf.to_string().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("Unknown KDF: {}", s))
+    }
+}
+
+/// This holds custom parameters for any of the supported KDFs
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KdfParams {
+    Argon2id(Argon2idParams),
+    Blake3Balloon(BalloonParams),
+    Scrypt(ScryptParams),
+}
+
+impl KdfParams {
+    /// This returns the KDF family that these parameters are for
+    #[must_use]
+    pub fn kdf(&self) -> Kdf {
+        match self {
+            KdfParams::Argon2id(_) => Kdf::Argon2id,
+            KdfParams::Blake3Balloon(_) => Kdf::Blake3Balloon,
+            KdfParams::Scrypt(_) => Kdf::Scrypt,
+        }
+    }
+}
+
+/// This is implemented by every key derivation function that `dexios-core` supports
+///
+/// It's object-safe, so implementations may be stored/iterated over as `Box<dyn KeyDerivation>`
+pub trait KeyDerivation: std::fmt::Display {
+    /// The bytes used to identify this KDF (and its parameters) within a keyslot
+    fn id(&self) -> [u8; 2];
+
+    /// This derives a 32-byte key from the raw key and salt
+    ///
+    /// Implementations must ensure that `raw_key` is securely erased once hashed
+    fn derive(
+        &self,
+        raw_key: Protected<Vec<u8>>,
+        salt: &[u8; SALT_LEN],
+    ) -> Result<Protected<[u8; 32]>>;
+
+    /// The parameters that are stored within a keyslot, alongside the identifier
+    ///
+    /// These are only needed when the identifier doesn't imply the parameters, so this is zeroed by default
+    fn params(&self) -> [u8; KDF_PARAMS_LEN] {
+        [0u8; KDF_PARAMS_LEN]
+    }
+}
+
+// custom parameters are stored as the memory/space cost (a little-endian `u32`), followed by the time and parallelism costs (a byte each)
+fn serialize_params(cost: u32, t_cost: u32, p_cost: u32) -> [u8; KDF_PARAMS_LEN] {
+    let mut bytes = [0u8; KDF_PARAMS_LEN];
+    bytes[..4].copy_from_slice(&cost.to_le_bytes());
+    // `custom()` ensures that these fit in a byte
+    bytes[4] = u8::try_from(t_cost).unwrap_or(u8::MAX);
+    bytes[5] = u8::try_from(p_cost).unwrap_or(u8::MAX);
+    bytes
+}
+
+fn deserialize_params(bytes: &[u8; KDF_PARAMS_LEN]) -> (u32, u32, u32) {
+    let cost = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    (cost, u32::from(bytes[4]), u32::from(bytes[5]))
+}
+
+/// The parameters used for `argon2id`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Argon2idParams {
+    /// Memory cost, in KiB
+    pub m_cost: u32,
+    /// Number of iterations
+    pub t_cost: u32,
+    /// Degree of parallelism
+    pub p_cost: u32,
+}
+
+/// The parameters used for BLAKE3-Balloon
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BalloonParams {
+    /// Space cost, in blocks
+    pub s_cost: u32,
+    /// Number of rounds
+    pub t_cost: u32,
+    /// Degree of parallelism
+    pub p_cost: u32,
+}
+
+/// The parameters used for `scrypt`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScryptParams {
+    /// The base-2 logarithm of the CPU/memory cost (`N`)
+    pub log_n: u8,
+    /// The block size (`r`)
+    pub r: u32,
+    /// Degree of parallelism (`p`)
+    pub p: u32,
+}
+
+impl Argon2idParams {
+    /// This converts the parameters into the bytes that are stored within a keyslot
+    #[must_use]
+    pub fn serialize(&self) -> [u8; KDF_PARAMS_LEN] {
+        serialize_params(self.m_cost, self.t_cost, self.p_cost)
+    }
+
+    #[must_use]
+    pub fn deserialize(bytes: &[u8; KDF_PARAMS_LEN]) -> Self {
+        let (m_cost, t_cost, p_cost) = deserialize_params(bytes);
+        Self {
+            m_cost,
+            t_cost,
+            p_cost,
+        }
+    }
+}
+
+impl BalloonParams {
+    /// This converts the parameters into the bytes that are stored within a keyslot
+    #[must_use]
+    pub fn serialize(&self) -> [u8; KDF_PARAMS_LEN] {
+        serialize_params(self.s_cost, self.t_cost, self.p_cost)
+    }
+
+    #[must_use]
+    pub fn deserialize(bytes: &[u8; KDF_PARAMS_LEN]) -> Self {
+        let (s_cost, t_cost, p_cost) = deserialize_params(bytes);
+        Self {
+            s_cost,
+            t_cost,
+            p_cost,
+        }
+    }
+}
+
+impl ScryptParams {
+    /// This converts the parameters into the bytes that are stored within a keyslot
+    #[must_use]
+    pub fn serialize(&self) -> [u8; KDF_PARAMS_LEN] {
+        serialize_params(u32::from(self.log_n), self.r, self.p)
+    }
+
+    #[must_use]
+    pub fn deserialize(bytes: &[u8; KDF_PARAMS_LEN]) -> Self {
+        let (log_n, r, p) = deserialize_params(bytes);
+        Self {
+            log_n: u8::try_from(log_n).unwrap_or(u8::MAX),
+            r,
+            p,
+        }
+    }
+}
+
+/// `argon2id`, along with the parameter version it's tied to
+///
+/// The version is 0 if the parameters were chosen by the user
+pub struct Argon2id {
+    pub version: i32,
+    pub params: Argon2idParams,
+}
+
+/// BLAKE3-Balloon, along with the parameter version it's tied to
+///
+/// The version is 0 if the parameters were chosen by the user
+pub struct Blake3Balloon {
+    pub version: i32,
+    pub params: BalloonParams,
+}
+
+/// `scrypt`, along with the parameter version it's tied to
+///
+/// The version is 0 if the parameters were chosen by the user
+pub struct Scrypt {
+    pub version: i32,
+    pub params: ScryptParams,
+}
+
+impl Argon2id {
+    /// This returns `argon2id` with the parameters tied to a specific version
+    pub fn from_version(version: i32) -> Result<Self> {
+        let params = match version {
+            // 8MiB of memory, 8 iterations, 4 levels of parallelism
+            1 => Argon2idParams {
+                m_cost: 8192,
+                t_cost: 8,
+                p_cost: 4,
+            },
+            // 256MiB of memory, 8 iterations, 4 levels of parallelism
+            2 => Argon2idParams {
+                m_cost: 262_144,
+                t_cost: 8,
+                p_cost: 4,
+            },
+            // 256MiB of memory, 10 iterations, 4 levels of parallelism
+            3 => Argon2idParams {
+                m_cost: 262_144,
+                t_cost: 10,
+                p_cost: 4,
+            },
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "argon2id is not supported with the parameters provided."
+                ))
+            }
+        };
+
+        Ok(Self { version, params })
+    }
+
+    /// This returns `argon2id` with parameters chosen by the user
+    ///
+    /// The iterations and parallelism must each fit in a byte, as that's how they're stored within the keyslot
+    pub fn custom(params: Argon2idParams) -> Result<Self> {
+        if !(1..=255).contains(&params.t_cost) || !(1..=255).contains(&params.p_cost) {
+            return Err(anyhow::anyhow!(
+                "argon2id's iterations and parallelism must be between 1 and 255"
+            ));
+        }
+
+        if u64::from(params.m_cost) > MAX_KDF_MEMORY {
+            return Err(anyhow::anyhow!(
+                "argon2id may use at most {} KiB of memory",
+                MAX_KDF_MEMORY
+            ));
+        }
+
+        argon2::Params::new(params.m_cost, params.t_cost, params.p_cost, None)
+            .map_err(|e| anyhow::anyhow!("Invalid argon2id parameters: {}", e))?;
+
+        Ok(Self { version: 0, params })
+    }
+}
+
+impl Blake3Balloon {
+    /// This returns BLAKE3-Balloon with the parameters tied to a specific version
+    pub fn from_version(version: i32) -> Result<Self> {
+        let params = match version {
+            4 => BalloonParams {
+                s_cost: 262_144,
+                t_cost: 1,
+                p_cost: 1,
+            },
+            5 => BalloonParams {
+                s_cost: 278_528,
+                t_cost: 1,
+                p_cost: 1,
+            },
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Balloon hashing is not supported with the parameters provided."
+                ))
+            }
+        };
+
+        Ok(Self { version, params })
+    }
+
+    /// This returns BLAKE3-Balloon with parameters chosen by the user
+    ///
+    /// The rounds and parallelism must each fit in a byte, as that's how they're stored within the keyslot
+    pub fn custom(params: BalloonParams) -> Result<Self> {
+        if !(1..=255).contains(&params.t_cost) || !(1..=255).contains(&params.p_cost) {
+            return Err(anyhow::anyhow!(
+                "Balloon hashing's rounds and parallelism must be between 1 and 255"
+            ));
+        }
+
+        // each block is the size of a BLAKE3 hash (32 bytes)
+        if u64::from(params.s_cost) * 32 / 1024 > MAX_KDF_MEMORY {
+            return Err(anyhow::anyhow!(
+                "Balloon hashing may use at most {} KiB of memory",
+                MAX_KDF_MEMORY
+            ));
+        }
+
+        balloon_hash::Params::new(params.s_cost, params.t_cost, params.p_cost)
+            .map_err(|_| anyhow::anyhow!("Invalid balloon hashing parameters"))?;
+
+        Ok(Self { version: 0, params })
+    }
+}
+
+impl Scrypt {
+    /// This returns `scrypt` with the parameters tied to a specific version
+    pub fn from_version(version: i32) -> Result<Self> {
+        let params = match version {
+            // 128MiB of memory (2^17 * 8 * 128 bytes), no parallelism
+            1 => ScryptParams {
+                log_n: 17,
+                r: 8,
+                p: 1,
+            },
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "scrypt is not supported with the parameters provided."
+                ))
+            }
+        };
+
+        Ok(Self { version, params })
+    }
+
+    /// This returns `scrypt` with parameters chosen by the user
+    ///
+    /// The block size and parallelism must each fit in a byte, as that's how they're stored within the keyslot
+    pub fn custom(params: ScryptParams) -> Result<Self> {
+        if !(1..=255).contains(&params.r) || !(1..=255).contains(&params.p) {
+            return Err(anyhow::anyhow!(
+                "scrypt's block size and parallelism must be between 1 and 255"
+            ));
+        }
+
+        // scrypt uses 128 * r * N bytes
+        let memory = 1u64
+            .checked_shl(params.log_n.into())
+            .and_then(|n| n.checked_mul(128 * u64::from(params.r)));
+        if memory.is_none_or(|bytes| bytes / 1024 > MAX_KDF_MEMORY) {
+            return Err(anyhow::anyhow!(
+                "scrypt may use at most {} KiB of memory",
+                MAX_KDF_MEMORY
+            ));
+        }
+
+        scrypt::Params::new(params.log_n, params.r, params.p)
+            .map_err(|_| anyhow::anyhow!("Invalid scrypt parameters"))?;
+
+        Ok(Self { version: 0, params })
+    }
+}
+
+impl std::fmt::Display for Argon2id {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.version == 0 {
+            write!(
+                f,
+                "Argon2id (custom: {} KiB, {} iterations, {} lanes)",
+                self.params.m_cost, self.params.t_cost, self.params.p_cost
+            )
+        } else {
+            write!(f, "Argon2id (param v{})", self.version)
+        }
+    }
+}
+
+impl std::fmt::Display for Blake3Balloon {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.version == 0 {
+            write!(
+                f,
+                "BLAKE3-Balloon (custom: {} blocks, {} rounds, {} lanes)",
+                self.params.s_cost, self.params.t_cost, self.params.p_cost
+            )
+        } else {
+            write!(f, "BLAKE3-Balloon (param v{})", self.version)
+        }
+    }
+}
+
+impl std::fmt::Display for Scrypt {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.version == 0 {
+            write!(
+                f,
+                "scrypt (custom: N = 2^{}, r = {}, p = {})",
+                self.params.log_n, self.params.r, self.params.p
+            )
+        } else {
+            write!(f, "scrypt (param v{})", self.version)
+        }
+    }
+}
+
+impl KeyDerivation for Argon2id {
+    fn id(&self) -> [u8; 2] {
+        match self.version {
+            0 => ARGON2ID_CUSTOM_ID,
+            1 => [0xDF, 0xA1],
+            2 => [0xDF, 0xA2],
+            3 => [0xDF, 0xA3],
+            _ => [0x00, 0x00],
+        }
+    }
+
+    fn params(&self) -> [u8; KDF_PARAMS_LEN] {
+        if self.version == 0 {
+            self.params.serialize()
+        } else {
+            [0u8; KDF_PARAMS_LEN]
+        }
+    }
+
+    fn derive(
+        &self,
+        raw_key: Protected<Vec<u8>>,
+        salt: &[u8; SALT_LEN],
+    ) -> Result<Protected<[u8; 32]>> {
+        use argon2::Argon2;
+        use argon2::Params;
+
+        let params = Params::new(
+            self.params.m_cost,
+            self.params.t_cost,
+            self.params.p_cost,
+            Some(Params::DEFAULT_OUTPUT_LEN),
+        )
+        .map_err(|_| anyhow::anyhow!("Error initialising argon2id parameters"))?;
+
+        let mut key = [0u8; 32];
+        let argon2 = Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+        let result = argon2.hash_password_into(raw_key.expose(), salt, &mut key);
+        drop(raw_key);
+
+        if result.is_err() {
+            return Err(anyhow::anyhow!("Error while hashing your key"));
+        }
+
+        Ok(Protected::new(key))
+    }
+}
+
+impl KeyDerivation for Blake3Balloon {
+    fn id(&self) -> [u8; 2] {
+        match self.version {
+            0 => BLAKE3BALLOON_CUSTOM_ID,
+            4 => [0xDF, 0xB4],
+            5 => [0xDF, 0xB5],
+            _ => [0x00, 0x00],
+        }
+    }
+
+    fn params(&self) -> [u8; KDF_PARAMS_LEN] {
+        if self.version == 0 {
+            self.params.serialize()
+        } else {
+            [0u8; KDF_PARAMS_LEN]
+        }
+    }
+
+    fn derive(
+        &self,
+        raw_key: Protected<Vec<u8>>,
+        salt: &[u8; SALT_LEN],
+    ) -> Result<Protected<[u8; 32]>> {
+        use balloon_hash::Balloon;
+
+        let params =
+            balloon_hash::Params::new(self.params.s_cost, self.params.t_cost, self.params.p_cost)
+                .map_err(|_| anyhow::anyhow!("Error initialising balloon hashing parameters"))?;
+
+        let mut key = [0u8; 32];
+        let balloon =
+            Balloon::<blake3::Hasher>::new(balloon_hash::Algorithm::Balloon, params, None);
+        let result = balloon.hash_into(raw_key.expose(), salt, &mut key);
+        drop(raw_key);
+
+        if result.is_err() {
+            return Err(anyhow::anyhow!("Error while hashing your key"));
+        }
+
+        Ok(Protected::new(key))
+    }
+}
+
+impl KeyDerivation for Scrypt {
+    fn id(&self) -> [u8; 2] {
+        match self.version {
+            0 => SCRYPT_CUSTOM_ID,
+            1 => [0xDF, 0xD1],
+            _ => [0x00, 0x00],
+        }
+    }
+
+    fn params(&self) -> [u8; KDF_PARAMS_LEN] {
+        if self.version == 0 {
+            self.params.serialize()
+        } else {
+            [0u8; KDF_PARAMS_LEN]
+        }
+    }
+
+    fn derive(
+        &self,
+        raw_key: Protected<Vec<u8>>,
+        salt: &[u8; SALT_LEN],
+    ) -> Result<Protected<[u8; 32]>> {
+        let params = scrypt::Params::new(self.params.log_n, self.params.r, self.params.p)
+            .map_err(|_| anyhow::anyhow!("Error initialising scrypt parameters"))?;
+
+        let mut key = [0u8; 32];
+        let result = scrypt::scrypt(raw_key.expose(), salt, &params, &mut key);
+        drop(raw_key);
+
+        if result.is_err() {
+            return Err(anyhow::anyhow!("Error while hashing your key"));
+        }
+
+        Ok(Protected::new(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARGON2ID_PARAMS: Argon2idParams = Argon2idParams {
+        m_cost: 64,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    #[test]
+    fn should_round_trip_custom_params() {
+        let params = Argon2idParams {
+            m_cost: 1_048_576,
+            t_cost: 12,
+            p_cost: 255,
+        };
+        assert_eq!(Argon2idParams::deserialize(&params.serialize()), params);
+
+        let kdf = Argon2id::custom(params).unwrap();
+        assert_eq!(kdf.id(), ARGON2ID_CUSTOM_ID);
+        assert_eq!(kdf.params(), params.serialize());
+
+        let params = BalloonParams {
+            s_cost: 1024,
+            t_cost: 3,
+            p_cost: 2,
+        };
+        assert_eq!(BalloonParams::deserialize(&params.serialize()), params);
+        assert_eq!(
+            Blake3Balloon::custom(params).unwrap().id(),
+            BLAKE3BALLOON_CUSTOM_ID
+        );
+
+        let params = ScryptParams {
+            log_n: 20,
+            r: 8,
+            p: 4,
+        };
+        assert_eq!(ScryptParams::deserialize(&params.serialize()), params);
+        assert_eq!(Scrypt::custom(params).unwrap().id(), SCRYPT_CUSTOM_ID);
+    }
+
+    #[test]
+    fn should_only_store_params_that_were_chosen_by_the_user() {
+        for version in 1..=3 {
+            let kdf = Argon2id::from_version(version).unwrap();
+            assert_eq!(kdf.params(), [0u8; KDF_PARAMS_LEN]);
+            assert_ne!(kdf.id(), ARGON2ID_CUSTOM_ID);
+        }
+
+        for version in 4..=5 {
+            let kdf = Blake3Balloon::from_version(version).unwrap();
+            assert_eq!(kdf.params(), [0u8; KDF_PARAMS_LEN]);
+            assert_ne!(kdf.id(), BLAKE3BALLOON_CUSTOM_ID);
+        }
+
+        let kdf = Scrypt::from_version(1).unwrap();
+        assert_eq!(kdf.params(), [0u8; KDF_PARAMS_LEN]);
+        assert_ne!(kdf.id(), SCRYPT_CUSTOM_ID);
+    }
+
+    #[test]
+    fn should_refuse_params_that_cant_be_stored() {
+        for (t_cost, p_cost) in [(0, 1), (256, 1), (1, 0), (1, 256)] {
+            assert!(Argon2id::custom(Argon2idParams {
+                t_cost,
+                p_cost,
+                ..ARGON2ID_PARAMS
+            })
+            .is_err());
+            assert!(Blake3Balloon::custom(BalloonParams {
+                s_cost: 1024,
+                t_cost,
+                p_cost,
+            })
+            .is_err());
+            assert!(Scrypt::custom(ScryptParams {
+                log_n: 10,
+                r: t_cost,
+                p: p_cost,
+            })
+            .is_err());
+        }
+
+        // argon2id needs at least 8 KiB of memory per lane
+        assert!(Argon2id::custom(Argon2idParams {
+            m_cost: 7,
+            ..ARGON2ID_PARAMS
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn should_derive_different_keys_for_different_salts() {
+        let kdfs: [Box<dyn KeyDerivation>; 2] = [
+            Box::new(Argon2id::custom(ARGON2ID_PARAMS).unwrap()),
+            Box::new(
+                Scrypt::custom(ScryptParams {
+                    log_n: 4,
+                    r: 8,
+                    p: 1,
+                })
+                .unwrap(),
+            ),
+        ];
+
+        for kdf in kdfs {
+            let derive = |salt: &[u8; SALT_LEN]| {
+                kdf.derive(Protected::new(b"password".to_vec()), salt)
+                    .unwrap()
+            };
+
+            assert_eq!(
+                derive(&[1u8; SALT_LEN]).expose(),
+                derive(&[1u8; SALT_LEN]).expose()
+            );
+            assert_ne!(
+                derive(&[1u8; SALT_LEN]).expose(),
+                derive(&[2u8; SALT_LEN]).expose()
+            );
+        }
+    }
+}
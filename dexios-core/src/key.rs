@@ -2,6 +2,8 @@
 //!
 //! It contains methods for `argon2id` and `balloon` hashing, and securely generating a salt
 //!
+//! `derive_key()` may be used for hashing with any of the supported KDFs (including `scrypt`)
+//!
 //! # Examples
 //!
 //! ```rust,ignore
@@ -15,9 +17,12 @@ use rand::{prelude::StdRng, Rng, SeedableRng};
 use zeroize::Zeroize;
 
 use crate::cipher::Ciphers;
+use crate::header::HashingAlgorithm;
 use crate::header::{Header, HeaderVersion};
+use crate::kdf::{Argon2id, Blake3Balloon, Kdf, KdfParams, KeyDerivation};
 use crate::primitives::{MASTER_KEY_LEN, SALT_LEN};
 use crate::protected::Protected;
+use crate::recipient::RecipientSecretKey;
 
 /// This handles `argon2id` hashing of a raw key
 ///
@@ -43,42 +48,18 @@ pub fn argon2id_hash(
     salt: &[u8; SALT_LEN],
     version: &HeaderVersion,
 ) -> Result<Protected<[u8; 32]>> {
-    use argon2::Argon2;
-    use argon2::Params;
-
-    let params = match version {
-        HeaderVersion::V1 => {
-            // 8MiB of memory, 8 iterations, 4 levels of parallelism
-            Params::new(8192, 8, 4, Some(Params::DEFAULT_OUTPUT_LEN))
-                .map_err(|_| anyhow::anyhow!("Error initialising argon2id parameters"))?
-        }
-        HeaderVersion::V2 => {
-            // 256MiB of memory, 8 iterations, 4 levels of parallelism
-            Params::new(262_144, 8, 4, Some(Params::DEFAULT_OUTPUT_LEN))
-                .map_err(|_| anyhow::anyhow!("Error initialising argon2id parameters"))?
-        }
-        HeaderVersion::V3 => {
-            // 256MiB of memory, 10 iterations, 4 levels of parallelism
-            Params::new(262_144, 10, 4, Some(Params::DEFAULT_OUTPUT_LEN))
-                .map_err(|_| anyhow::anyhow!("Error initialising argon2id parameters"))?
-        }
-        HeaderVersion::V4 | HeaderVersion::V5 => {
+    let kdf = match version {
+        HeaderVersion::V1 => Argon2id::from_version(1)?,
+        HeaderVersion::V2 => Argon2id::from_version(2)?,
+        HeaderVersion::V3 => Argon2id::from_version(3)?,
+        HeaderVersion::V4 | HeaderVersion::V5 | HeaderVersion::V6 => {
             return Err(anyhow::anyhow!(
                 "argon2id is not supported on header versions above V3."
             ))
         }
     };
 
-    let mut key = [0u8; 32];
-    let argon2 = Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
-    let result = argon2.hash_password_into(raw_key.expose(), salt, &mut key);
-    drop(raw_key);
-
-    if result.is_err() {
-        return Err(anyhow::anyhow!("Error while hashing your key"));
-    }
-
-    Ok(Protected::new(key))
+    kdf.derive(raw_key, salt)
 }
 
 /// This handles BLAKE3-Balloon hashing of a raw key
@@ -107,30 +88,43 @@ pub fn balloon_hash(
     salt: &[u8; SALT_LEN],
     version: &HeaderVersion,
 ) -> Result<Protected<[u8; 32]>> {
-    use balloon_hash::Balloon;
-
-    let params = match version {
+    let kdf = match version {
         HeaderVersion::V1 | HeaderVersion::V2 | HeaderVersion::V3 => {
             return Err(anyhow::anyhow!(
                 "Balloon hashing is not supported in header versions below V4."
             ));
         }
-        HeaderVersion::V4 => balloon_hash::Params::new(262_144, 1, 1)
-            .map_err(|_| anyhow::anyhow!("Error initialising balloon hashing parameters"))?,
-        HeaderVersion::V5 => balloon_hash::Params::new(278_528, 1, 1)
-            .map_err(|_| anyhow::anyhow!("Error initialising balloon hashing parameters"))?,
+        HeaderVersion::V4 => Blake3Balloon::from_version(4)?,
+        HeaderVersion::V5 | HeaderVersion::V6 => Blake3Balloon::from_version(5)?,
     };
 
-    let mut key = [0u8; 32];
-    let balloon = Balloon::<blake3::Hasher>::new(balloon_hash::Algorithm::Balloon, params, None);
-    let result = balloon.hash_into(raw_key.expose(), salt, &mut key);
-    drop(raw_key);
-
-    if result.is_err() {
-        return Err(anyhow::anyhow!("Error while hashing your key"));
-    }
+    kdf.derive(raw_key, salt)
+}
 
-    Ok(Protected::new(key))
+/// This hashes a raw key with the chosen KDF
+///
+/// If no parameters are provided, the KDF's latest parameter version is used. Otherwise, they must be for the chosen KDF.
+///
+/// It returns a `Protected<[u8; 32]>` - `Protected` wrappers are used for all sensitive information within `dexios-core`
+///
+/// This function ensures that `raw_key` is securely erased from memory once hashed
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let salt = gen_salt();
+/// let raw_key = Protected::new(b"secure key".to_vec());
+/// let params = KdfParams::Scrypt(ScryptParams { log_n: 15, r: 8, p: 1 });
+/// let key = derive_key(Kdf::Scrypt, Some(params), raw_key, &salt).unwrap();
+/// ```
+///
+pub fn derive_key(
+    kdf: Kdf,
+    params: Option<KdfParams>,
+    raw_key: Protected<Vec<u8>>,
+    salt: &[u8; SALT_LEN],
+) -> Result<Protected<[u8; 32]>> {
+    HashingAlgorithm::from_kdf(kdf, params)?.hash(raw_key, salt)
 }
 
 /// This is a helper function for retrieving the key used for encrypting the data
@@ -140,6 +134,8 @@ pub fn balloon_hash(
 /// In header versions >= V4, this is a cryptographically-secure random value
 ///
 /// In header versions >= V4, this function will iterate through all available keyslots, looking for a match. If it finds a match, it will return the decrypted master key.
+///
+/// In header versions >= V6, token keyslots are tried first if a token was registered (see `crate::token`).
 #[allow(clippy::module_name_repetitions)]
 pub fn decrypt_master_key(
     raw_key: Protected<Vec<u8>>,
@@ -147,11 +143,18 @@ pub fn decrypt_master_key(
     // TODO: use custom error instead of anyhow
 ) -> Result<Protected<[u8; MASTER_KEY_LEN]>> {
     match header.header_type.version {
-        HeaderVersion::V1 | HeaderVersion::V2 | HeaderVersion::V3 => {
-            argon2id_hash(raw_key, &header.salt.ok_or_else(|| anyhow::anyhow!("Missing salt within the header!"))?, &header.header_type.version)
-        }
+        HeaderVersion::V1 | HeaderVersion::V2 | HeaderVersion::V3 => argon2id_hash(
+            raw_key,
+            &header
+                .salt
+                .ok_or_else(|| anyhow::anyhow!("Missing salt within the header!"))?,
+            &header.header_type.version,
+        ),
         HeaderVersion::V4 => {
-            let keyslots = header.keyslots.as_ref().ok_or_else(|| anyhow::anyhow!("Unable to find a keyslot!"))?;
+            let keyslots = header
+                .keyslots
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("Unable to find a keyslot!"))?;
             let keyslot = keyslots.first().ok_or_else(|| anyhow::anyhow!("Unable to find a match with the key you provided (maybe you supplied the wrong key?)"))?;
             let key = keyslot.hash_algorithm.hash(raw_key, &keyslot.salt)?;
 
@@ -162,12 +165,24 @@ pub fn decrypt_master_key(
                 .map(Protected::new)
                 .map_err(|_| anyhow::anyhow!("Cannot decrypt master key"))
         }
-        HeaderVersion::V5 => {
-            header
+        HeaderVersion::V5 | HeaderVersion::V6 => {
+            let keyslots = header
                 .keyslots
                 .as_ref()
-                .ok_or_else(|| anyhow::anyhow!("Unable to find a keyslot!"))?
+                .ok_or_else(|| anyhow::anyhow!("Unable to find a keyslot!"))?;
+
+            // token keyslots are tried first, as they don't need the raw key to be hashed
+            if let Some(master_key) =
+                crate::token::decrypt_master_key(keyslots, &header.header_type.algorithm)?
+            {
+                return Ok(master_key);
+            }
+
+            keyslots
                 .iter()
+                .filter(|keyslot| {
+                    !keyslot.is_recipient() && !keyslot.is_token() && !keyslot.is_metadata_only()
+                })
                 .find_map(|keyslot| {
                     let key = keyslot.hash_algorithm.hash(raw_key.clone(), &keyslot.salt).ok()?;
 
@@ -183,6 +198,71 @@ pub fn decrypt_master_key(
     }
 }
 
+/// This is used for retrieving the header key (see `crate::subkeys`), which is all that's needed to read the digest and manifest
+///
+/// Metadata-only keyslots are tried first, as they unlock the header key directly. If none of them match, the master key is decrypted (with any other keyslot) and the header key is derived from it.
+pub fn decrypt_header_key(
+    raw_key: Protected<Vec<u8>>,
+    header: &Header,
+) -> Result<Protected<[u8; 32]>> {
+    let header_key = header
+        .keyslots
+        .iter()
+        .flatten()
+        .filter(|keyslot| keyslot.is_metadata_only())
+        .find_map(|keyslot| {
+            let key = keyslot
+                .hash_algorithm
+                .hash(raw_key.clone(), &keyslot.salt)
+                .ok()?;
+
+            let cipher = Ciphers::initialize(key, &header.header_type.algorithm).ok()?;
+            cipher
+                .decrypt(&keyslot.nonce, keyslot.encrypted_key.as_slice())
+                .map(vec_to_arr)
+                .map(Protected::new)
+                .ok()
+        });
+
+    match header_key {
+        Some(header_key) => Ok(header_key),
+        None => {
+            let master_key = decrypt_master_key(raw_key, header)?;
+            Ok(crate::subkeys::Subkeys::derive(master_key, header).header)
+        }
+    }
+}
+
+/// This is used for retrieving the master key with a recipient's secret key, rather than a password
+///
+/// It only checks the recipient keyslots that match the secret key's fingerprint.
+pub fn decrypt_master_key_with_identity(
+    identity: &RecipientSecretKey,
+    header: &Header,
+) -> Result<Protected<[u8; MASTER_KEY_LEN]>> {
+    let fingerprint = identity.public_key().fingerprint();
+
+    header
+        .keyslots
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("Unable to find a keyslot!"))?
+        .iter()
+        .filter(|keyslot| keyslot.salt == fingerprint)
+        .find_map(|keyslot| {
+            let key = identity
+                .decapsulate(keyslot.encapsulated_key.as_ref()?)
+                .ok()?;
+
+            let cipher = Ciphers::initialize(key, &header.header_type.algorithm).ok()?;
+            cipher
+                .decrypt(&keyslot.nonce, keyslot.encrypted_key.as_slice())
+                .map(vec_to_arr)
+                .map(Protected::new)
+                .ok()
+        })
+        .ok_or_else(|| anyhow::anyhow!("Unable to find a recipient keyslot for this identity"))
+}
+
 // TODO: choose better place for this util
 /// This is a simple helper function, used for converting the 32-byte master key `Vec<u8>`s to `[u8; 32]`
 #[must_use]
@@ -209,7 +289,7 @@ pub fn generate_passphrase(total_words: &i32) -> Protected<String> {
     let mut passphrase = String::new();
 
     for i in 0..*total_words {
-        let index = StdRng::from_entropy().gen_range(0..=words.len());
+        let index = StdRng::from_entropy().gen_range(0..words.len());
         let word = words[index];
         passphrase.push_str(word);
         if i < total_words - 1 {
@@ -219,3 +299,241 @@ pub fn generate_passphrase(total_words: &i32) -> Protected<String> {
 
     Protected::new(passphrase)
 }
+
+// this is Crockford's base32 alphabet, which leaves out letters that are easily mistaken for others
+const RECOVERY_CODE_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// This is the amount of characters within a recovery code (excluding separators), which provides 160 bits of entropy
+pub const RECOVERY_CODE_LEN: usize = 32;
+
+/// This function is used for generating a recovery code
+///
+/// It consists of `RECOVERY_CODE_LEN` random characters from Crockford's base32 alphabet, in groups of 4 that are separated with `-`.
+///
+/// It has enough entropy that it doesn't rely on the KDF for its security, but it's still hashed like any other key.
+///
+/// Codes should be passed through `normalize_recovery_code()` before they're used as a key, so the separators and case don't matter.
+#[must_use]
+pub fn generate_recovery_code() -> Protected<String> {
+    let mut rng = StdRng::from_entropy();
+    let mut code = String::with_capacity(RECOVERY_CODE_LEN + RECOVERY_CODE_LEN / 4);
+
+    for i in 0..RECOVERY_CODE_LEN {
+        if i > 0 && i % 4 == 0 {
+            code.push('-');
+        }
+        let index = rng.gen_range(0..RECOVERY_CODE_ALPHABET.len());
+        code.push(char::from(RECOVERY_CODE_ALPHABET[index]));
+    }
+
+    Protected::new(code)
+}
+
+/// This is used to derive a raw key from a keyfile's hash
+const KEYFILE_HASH_CONTEXT: &str = "dexios keyfile hash v1";
+
+/// This hashes a keyfile with BLAKE3 as it's read, and returns the hash as the raw key
+///
+/// This bounds the memory used by (and the KDF input of) arbitrarily large keyfiles. As the raw key differs from the keyfile's contents, headers flag when it's been used (`Header::keyfile_hash`).
+pub fn hash_keyfile(reader: &mut impl std::io::Read) -> Result<Protected<Vec<u8>>> {
+    let mut hasher = blake3::Hasher::new_derive_key(KEYFILE_HASH_CONTEXT);
+    let len = std::io::copy(reader, &mut hasher)?;
+
+    if len == 0 {
+        return Err(anyhow::anyhow!("The keyfile is empty"));
+    }
+
+    let key = Protected::new(hasher.finalize().as_bytes().to_vec());
+    hasher.reset();
+    Ok(key)
+}
+
+/// This is used to derive a raw key from both a keyfile and a password
+const TWO_FACTOR_CONTEXT: &str = "dexios two-factor key v1";
+
+/// This combines a keyfile and a password into a single raw key, so that neither is enough on its own
+///
+/// Each factor is prefixed with its length, so bytes can't be moved from one to the other. Headers flag when this has been used (`Header::two_factor`), so that both are asked for during decryption.
+#[must_use]
+pub fn combine_factors(
+    keyfile: &Protected<Vec<u8>>,
+    password: &Protected<Vec<u8>>,
+) -> Protected<Vec<u8>> {
+    let mut hasher = blake3::Hasher::new_derive_key(TWO_FACTOR_CONTEXT);
+    for factor in [keyfile, password] {
+        hasher.update(&(factor.len() as u64).to_le_bytes());
+        hasher.update(factor.expose());
+    }
+
+    let key = Protected::new(hasher.finalize().as_bytes().to_vec());
+    hasher.reset();
+    key
+}
+
+/// This is used to derive a raw key from several keyfiles
+const KEYFILES_CONTEXT: &str = "dexios combined keyfiles v1";
+
+/// This combines several keyfiles into a single raw key, so that all of them are needed (e.g. fragments that are held by different people)
+///
+/// The keyfiles are sorted first, so the order that they're given in doesn't matter. Each one is prefixed with its length, so bytes can't be moved from one to another.
+#[must_use]
+pub fn combine_keyfiles(keyfiles: &[Protected<Vec<u8>>]) -> Protected<Vec<u8>> {
+    let mut sorted: Vec<&[u8]> = keyfiles.iter().map(|k| k.expose().as_slice()).collect();
+    sorted.sort_unstable();
+
+    let mut hasher = blake3::Hasher::new_derive_key(KEYFILES_CONTEXT);
+    hasher.update(&(sorted.len() as u64).to_le_bytes());
+    for keyfile in sorted {
+        hasher.update(&(keyfile.len() as u64).to_le_bytes());
+        hasher.update(keyfile);
+    }
+
+    let key = Protected::new(hasher.finalize().as_bytes().to_vec());
+    hasher.reset();
+    key
+}
+
+/// This converts a recovery code, as the user entered it, into the key that it represents
+///
+/// Separators and whitespace are ignored, lowercase is accepted, and the letters `O`, `I` and `L` are read as the digits they resemble.
+///
+/// It returns an error if the code contains anything else, or if it's the wrong length.
+pub fn normalize_recovery_code(code: &str) -> Result<Protected<Vec<u8>>> {
+    let mut key = Vec::with_capacity(RECOVERY_CODE_LEN);
+
+    for c in code.chars() {
+        let c = match c.to_ascii_uppercase() {
+            '-' => continue,
+            c if c.is_whitespace() => continue,
+            'O' => '0',
+            'I' | 'L' => '1',
+            c => c,
+        };
+
+        if !c.is_ascii() || !RECOVERY_CODE_ALPHABET.contains(&(c as u8)) {
+            key.zeroize();
+            return Err(anyhow::anyhow!(
+                "The recovery code contains an invalid character"
+            ));
+        }
+        key.push(c as u8);
+    }
+
+    if key.len() != RECOVERY_CODE_LEN {
+        key.zeroize();
+        return Err(anyhow::anyhow!(
+            "The recovery code should be {} characters long",
+            RECOVERY_CODE_LEN
+        ));
+    }
+
+    Ok(Protected::new(key))
+}
+
+/// This is the amount of words within a master key's mnemonic (each one encodes 11 bits, including an 8-bit checksum)
+pub const MNEMONIC_WORDS: usize = 24;
+
+/// This encodes the master key as a BIP39 mnemonic phrase (with the English wordlist), so that it may be written down as a paper backup
+///
+/// The master key is all that's needed to decrypt the file, regardless of its keyslots - changing or deleting a key doesn't revoke the mnemonic.
+#[must_use]
+pub fn master_key_to_mnemonic(master_key: &Protected<[u8; MASTER_KEY_LEN]>) -> Protected<String> {
+    // 32 bytes of entropy is always valid for BIP39
+    let mnemonic = bip39::Mnemonic::from_entropy(master_key.expose())
+        .expect("32 bytes is a valid BIP39 entropy length");
+
+    let mut phrase = String::new();
+    for (i, word) in mnemonic.words().enumerate() {
+        if i > 0 {
+            phrase.push(' ');
+        }
+        phrase.push_str(word);
+    }
+
+    Protected::new(phrase)
+}
+
+/// This decodes a mnemonic phrase from `master_key_to_mnemonic()` back into the master key
+///
+/// Case and whitespace don't matter. It returns an error if a word isn't within the wordlist, if there aren't `MNEMONIC_WORDS` words, or if the checksum doesn't match.
+pub fn mnemonic_to_master_key(phrase: &str) -> Result<Protected<[u8; MASTER_KEY_LEN]>> {
+    let normalized = Protected::new(
+        phrase
+            .split_whitespace()
+            .map(str::to_ascii_lowercase)
+            .collect::<Vec<_>>()
+            .join(" "),
+    );
+
+    let words = normalized.expose().split(' ').count();
+    if words != MNEMONIC_WORDS {
+        return Err(anyhow::anyhow!(
+            "The mnemonic should be {} words long, but it's {} words long",
+            MNEMONIC_WORDS,
+            words
+        ));
+    }
+
+    let mnemonic = bip39::Mnemonic::parse_normalized(normalized.expose())
+        .map_err(|e| anyhow::anyhow!("The mnemonic is invalid ({})", e))?;
+
+    let (mut entropy, len) = mnemonic.to_entropy_array();
+    let master_key = Protected::new(vec_to_arr(entropy[..len].to_vec()));
+    entropy.zeroize();
+
+    Ok(master_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyfiles(contents: &[&[u8]]) -> Vec<Protected<Vec<u8>>> {
+        contents
+            .iter()
+            .map(|keyfile| Protected::new(keyfile.to_vec()))
+            .collect()
+    }
+
+    fn combined(contents: &[&[u8]]) -> Vec<u8> {
+        combine_keyfiles(&keyfiles(contents)).expose().clone()
+    }
+
+    #[test]
+    fn should_combine_keyfiles_in_any_order() {
+        assert_eq!(
+            combined(&[b"first", b"second", b"third"]),
+            combined(&[b"third", b"first", b"second"])
+        );
+    }
+
+    #[test]
+    fn should_combine_different_keyfiles_into_different_keys() {
+        let key = combined(&[b"first", b"second"]);
+        assert_ne!(key, combined(&[b"first", b"other"]));
+        assert_ne!(key, combined(&[b"first"]));
+        assert_ne!(key, combined(&[b"first", b"second", b"third"]));
+    }
+
+    #[test]
+    fn should_not_combine_keyfiles_whose_bytes_have_moved_into_the_same_key() {
+        assert_ne!(combined(&[b"ab", b"c"]), combined(&[b"a", b"bc"]));
+        assert_ne!(combined(&[b"abc", b""]), combined(&[b"ab", b"c"]));
+    }
+
+    #[test]
+    fn should_not_combine_a_single_keyfile_into_its_plain_key() {
+        let keyfile: &[u8] = b"keyfile contents";
+        let key = combined(&[keyfile]);
+
+        // a keyfile is used as it is, or hashed with `hash_keyfile()`, when it's provided on its own
+        assert_ne!(key, keyfile.to_vec());
+        assert_ne!(
+            key,
+            hash_keyfile(&mut std::io::Cursor::new(keyfile))
+                .unwrap()
+                .expose()
+                .clone()
+        );
+    }
+}
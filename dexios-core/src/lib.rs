@@ -8,7 +8,7 @@
 //!
 //! You may find the audits for both AES-256-GCM and XChaCha20-Poly1305 on [the NCC Group's website](https://research.nccgroup.com/2020/02/26/public-report-rustcrypto-aes-gcm-and-chacha20poly1305-implementation-review/).
 //!
-//! <sup>1</sup> Deoxys-II-256 does not have an official audit, so use it at your own risk
+//! <sup>1</sup> Deoxys-II-256, AEGIS-256 and Ascon-128a do not have an official audit, so use them at your own risk
 //!
 //! ## Who uses Dexios-Core?
 //!
@@ -37,14 +37,45 @@
 
 pub const CORE_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// This contains the names of the optional features that `dexios-core` was compiled with
+pub const CORE_FEATURES: &[&str] = &[
+    #[cfg(feature = "visual")]
+    "visual",
+    #[cfg(feature = "serde")]
+    "serde",
+];
+
+pub mod aegis;
+pub mod backend;
+pub mod challenge;
 pub mod cipher;
+pub mod convergent;
+pub mod counter;
+pub mod derived;
+pub mod digest;
+pub mod file_info;
 pub mod header;
+pub mod kdf;
 pub mod key;
+pub mod mac;
+pub mod manifest;
+pub mod observer;
+pub mod os_crypto;
+pub mod padding;
+pub mod prelude;
 pub mod primitives;
 pub mod protected;
+pub mod recipient;
+pub mod seekable;
+pub mod signature;
 pub mod stream;
+pub mod subkeys;
+pub mod token;
+pub use aead;
 pub use aead::Payload;
-pub use zeroize::Zeroize;
+pub use zeroize::{Zeroize, Zeroizing};
 
+#[cfg(feature = "serde")]
+mod serde_arrays;
 #[cfg(feature = "visual")]
 pub mod visual;
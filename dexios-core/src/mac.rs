@@ -0,0 +1,183 @@
+//! This module contains the keyed `BLAKE3` MAC that may be appended to data encrypted in stream mode
+//!
+//! Each block in stream mode is authenticated on its own, so truncation or reordering is only noticed once the affected block is reached - by which point the blocks before it have already been decrypted and written out.
+//!
+//! The MAC covers the header's AAD and the entire ciphertext, and it's stored as a 32-byte footer. It can be verified before anything is decrypted, so no plaintext is ever released from a file that has been modified.
+//!
+//! The MAC key is derived from the master key, so anyone who can decrypt the file can also verify the footer.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! let mac_key = derive_key(&master_key);
+//!
+//! let mut writer = MacWriter::new(&mut output_file, &mac_key, &aad);
+//! encrypt_stream.encrypt_file(&mut input_file, &mut writer, &aad, header.block_size).unwrap();
+//! writer.finish().unwrap();
+//! ```
+
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use anyhow::Context;
+
+use crate::primitives::MASTER_KEY_LEN;
+use crate::protected::Protected;
+
+/// This is the length of the footer that's appended to the ciphertext
+pub const MAC_LEN: usize = 32;
+
+/// This is used to derive the MAC key from the master key
+const MAC_CONTEXT: &str = "dexios ciphertext mac v1";
+
+/// This derives the MAC key from the master key
+///
+/// It needs to be called before the master key is handed to the streams, as they take ownership of it.
+#[must_use]
+pub fn derive_key(master_key: &Protected<[u8; MASTER_KEY_LEN]>) -> Protected<[u8; 32]> {
+    Protected::new(blake3::derive_key(MAC_CONTEXT, master_key.expose()))
+}
+
+fn hasher(key: &Protected<[u8; 32]>, aad: &[u8]) -> blake3::Hasher {
+    let mut hasher = blake3::Hasher::new_keyed(key.expose());
+    hasher.update(aad);
+    hasher
+}
+
+/// This passes everything through to the inner writer, while calculating the MAC of it
+pub struct MacWriter<W: Write> {
+    inner: W,
+    hasher: blake3::Hasher,
+}
+
+impl<W: Write> MacWriter<W> {
+    #[must_use]
+    pub fn new(inner: W, key: &Protected<[u8; 32]>, aad: &[u8]) -> Self {
+        Self {
+            inner,
+            hasher: hasher(key, aad),
+        }
+    }
+
+    /// This writes the footer, and it must be called once all of the ciphertext has been written
+    pub fn finish(mut self) -> anyhow::Result<W> {
+        let mac = self.hasher.finalize();
+        self.hasher.reset();
+
+        self.inner
+            .write_all(mac.as_bytes())
+            .context("Unable to write the MAC")?;
+        self.inner.flush().context("Unable to flush the output")?;
+
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for MacWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// This checks the footer against everything from the reader's current position onwards
+///
+/// The reader is returned to where it started, and the length of the ciphertext (excluding the footer) is returned. Only that many bytes should be decrypted.
+pub fn verify(
+    key: &Protected<[u8; 32]>,
+    aad: &[u8],
+    reader: &mut (impl Read + Seek),
+) -> anyhow::Result<u64> {
+    let start = reader
+        .stream_position()
+        .context("Unable to get the reader's position")?;
+    let end = reader
+        .seek(SeekFrom::End(0))
+        .context("Unable to seek to the end of the reader")?;
+
+    let len = end
+        .checked_sub(start)
+        .and_then(|len| len.checked_sub(MAC_LEN as u64))
+        .ok_or_else(|| anyhow::anyhow!("The data is too short to contain a MAC"))?;
+
+    reader
+        .seek(SeekFrom::Start(start))
+        .context("Unable to seek to the start of the ciphertext")?;
+
+    let mut hasher = hasher(key, aad);
+    std::io::copy(&mut (&mut *reader).take(len), &mut hasher)
+        .context("Unable to read the ciphertext")?;
+
+    let mut footer = [0u8; MAC_LEN];
+    reader
+        .read_exact(&mut footer)
+        .context("Unable to read the MAC")?;
+
+    // `blake3::Hash` compares in constant time
+    if hasher.finalize() != blake3::Hash::from(footer) {
+        return Err(anyhow::anyhow!(
+            "The MAC doesn't match - the file has been truncated or modified"
+        ));
+    }
+
+    reader
+        .seek(SeekFrom::Start(start))
+        .context("Unable to seek to the start of the ciphertext")?;
+
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const AAD: &[u8] = b"header";
+
+    fn key() -> Protected<[u8; 32]> {
+        derive_key(&Protected::new([7u8; MASTER_KEY_LEN]))
+    }
+
+    fn write(ciphertext: &[u8]) -> Vec<u8> {
+        let mut writer = MacWriter::new(Vec::new(), &key(), AAD);
+        writer.write_all(ciphertext).unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn should_verify_the_footer() {
+        let mut reader = Cursor::new(write(b"ciphertext"));
+        reader.set_position(0);
+
+        assert_eq!(verify(&key(), AAD, &mut reader).unwrap(), 10);
+        assert_eq!(reader.position(), 0);
+    }
+
+    #[test]
+    fn should_refuse_a_tampered_header() {
+        let mut reader = Cursor::new(write(b"ciphertext"));
+        assert!(verify(&key(), b"headers", &mut reader).is_err());
+    }
+
+    #[test]
+    fn should_refuse_tampered_or_truncated_ciphertext() {
+        let mut bytes = write(b"ciphertext");
+        bytes[0] ^= 1;
+        assert!(verify(&key(), AAD, &mut Cursor::new(bytes)).is_err());
+
+        let bytes = write(b"ciphertext");
+        assert!(verify(&key(), AAD, &mut Cursor::new(&bytes[1..])).is_err());
+        assert!(verify(&key(), AAD, &mut Cursor::new(&bytes[..MAC_LEN - 1])).is_err());
+    }
+
+    #[test]
+    fn should_derive_a_key_for_each_master_key() {
+        let other = derive_key(&Protected::new([8u8; MASTER_KEY_LEN]));
+        assert_ne!(key().expose(), other.expose());
+        assert_ne!(key().expose(), &[7u8; 32]);
+    }
+}
@@ -0,0 +1,177 @@
+//! This module contains the manifest that may be stored in a V6 header
+//!
+//! A manifest lists the files that were packed into an archive (their paths, sizes and `BLAKE3` hashes), so they can be catalogued without decrypting the archive itself.
+//!
+//! It's encrypted with a key that's derived from the header key (see `crate::subkeys`), rather than the payload key. Metadata-only keyslots unlock the header key alone (see `crate::key::decrypt_header_key`), so they can read the manifest, but nothing else.
+//!
+//! The key is unique to each file and it only ever encrypts one message, so the nonce is fixed (just like the digest's).
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! let entries = vec![Entry { path: "hello.txt".to_string(), size: 5, hash: blake3::hash(b"hello") }];
+//! header.manifest = Some(encrypt(&subkeys.header, &header.header_type.algorithm, &entries).unwrap());
+//!
+//! let header_key = decrypt_header_key(raw_key, &header).unwrap();
+//! let entries = decrypt(&header_key, &header.header_type.algorithm, header.manifest.as_ref().unwrap()).unwrap();
+//! ```
+
+use anyhow::{Context, Result};
+
+use crate::cipher::Ciphers;
+use crate::primitives::{get_nonce_len, Algorithm, Mode};
+use crate::protected::Protected;
+
+/// This is the largest (encrypted) manifest that a header may contain
+pub const MAX_MANIFEST_LEN: usize = 16 * 1024 * 1024;
+
+/// This is used to derive the manifest's key from the header key
+const MANIFEST_CONTEXT: &str = "dexios manifest v1";
+
+/// A single file within the manifest
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Entry {
+    /// The file's path within the archive
+    pub path: String,
+    pub size: u64,
+    pub hash: blake3::Hash,
+}
+
+fn derive_key(header_key: &Protected<[u8; 32]>) -> Protected<[u8; 32]> {
+    Protected::new(blake3::derive_key(MANIFEST_CONTEXT, header_key.expose()))
+}
+
+fn nonce(algorithm: &Algorithm) -> Vec<u8> {
+    vec![0u8; get_nonce_len(algorithm, &Mode::MemoryMode)]
+}
+
+/// Each entry is stored as the path's length (u32 LE), the path, the size (u64 LE) and the hash
+fn serialize(entries: &[Entry]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for entry in entries {
+        bytes.extend_from_slice(&(entry.path.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(entry.path.as_bytes());
+        bytes.extend_from_slice(&entry.size.to_le_bytes());
+        bytes.extend_from_slice(entry.hash.as_bytes());
+    }
+    bytes
+}
+
+fn deserialize(mut bytes: &[u8]) -> Result<Vec<Entry>> {
+    fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+        if bytes.len() < len {
+            return Err(anyhow::anyhow!("The manifest is truncated"));
+        }
+        let (taken, rest) = bytes.split_at(len);
+        *bytes = rest;
+        Ok(taken)
+    }
+
+    let mut entries = Vec::new();
+    while !bytes.is_empty() {
+        let path_len = u32::from_le_bytes(take(&mut bytes, 4)?.try_into()?) as usize;
+        let path = String::from_utf8(take(&mut bytes, path_len)?.to_vec())
+            .context("A path within the manifest isn't valid UTF-8")?;
+        let size = u64::from_le_bytes(take(&mut bytes, 8)?.try_into()?);
+        let hash: [u8; blake3::OUT_LEN] = take(&mut bytes, blake3::OUT_LEN)?.try_into()?;
+
+        entries.push(Entry {
+            path,
+            size,
+            hash: blake3::Hash::from(hash),
+        });
+    }
+
+    Ok(entries)
+}
+
+/// This encrypts the manifest, so that it can be stored in the header
+pub fn encrypt(
+    header_key: &Protected<[u8; 32]>,
+    algorithm: &Algorithm,
+    entries: &[Entry],
+) -> Result<Vec<u8>> {
+    let encrypted = Ciphers::initialize(derive_key(header_key), algorithm)?
+        .encrypt(&nonce(algorithm), serialize(entries).as_slice())
+        .map_err(|_| anyhow::anyhow!("Unable to encrypt the manifest"))?;
+
+    if encrypted.len() > MAX_MANIFEST_LEN {
+        return Err(anyhow::anyhow!(
+            "The manifest is too large to store in the header"
+        ));
+    }
+
+    Ok(encrypted)
+}
+
+/// This decrypts a manifest that was read from the header
+///
+/// This will fail if the manifest (or the key) is incorrect.
+pub fn decrypt(
+    header_key: &Protected<[u8; 32]>,
+    algorithm: &Algorithm,
+    encrypted_manifest: &[u8],
+) -> Result<Vec<Entry>> {
+    let bytes = Ciphers::initialize(derive_key(header_key), algorithm)?
+        .decrypt(&nonce(algorithm), encrypted_manifest)
+        .map_err(|_| anyhow::anyhow!("Unable to decrypt the manifest"))?;
+
+    deserialize(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<Entry> {
+        vec![
+            Entry {
+                path: "hello.txt".to_string(),
+                size: 5,
+                hash: blake3::hash(b"hello"),
+            },
+            Entry {
+                path: "dir/world.txt".to_string(),
+                size: 0,
+                hash: blake3::hash(b""),
+            },
+        ]
+    }
+
+    #[test]
+    fn should_round_trip_entries() {
+        let key = Protected::new([7u8; 32]);
+        let algorithm = Algorithm::XChaCha20Poly1305;
+
+        let encrypted = encrypt(&key, &algorithm, &entries()).unwrap();
+        assert_eq!(decrypt(&key, &algorithm, &encrypted).unwrap(), entries());
+
+        // the header key isn't used directly
+        assert!(Ciphers::initialize(key.clone(), &algorithm)
+            .unwrap()
+            .decrypt(&nonce(&algorithm), encrypted.as_slice())
+            .is_err());
+    }
+
+    #[test]
+    fn should_refuse_tampered_manifests_or_the_wrong_key() {
+        let key = Protected::new([7u8; 32]);
+        let algorithm = Algorithm::XChaCha20Poly1305;
+
+        let mut encrypted = encrypt(&key, &algorithm, &entries()).unwrap();
+        assert!(decrypt(&Protected::new([8u8; 32]), &algorithm, &encrypted).is_err());
+
+        encrypted[0] ^= 1;
+        assert!(decrypt(&key, &algorithm, &encrypted).is_err());
+    }
+
+    #[test]
+    fn should_refuse_truncated_entries() {
+        let bytes = serialize(&entries());
+        assert_eq!(deserialize(&bytes).unwrap(), entries());
+
+        for len in [1, 4, 12, bytes.len() - 1] {
+            assert!(deserialize(&bytes[..len]).is_err());
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! This module contains the `StreamObserver` trait, which is notified as each block passes through the stream functions
+//!
+//! GUIs (and anything else that embeds `dexios-core`) can use it to report progress, rather than wrapping the reader or writer to count bytes themselves.
+//!
+//! Every method has an empty default, so an observer only needs to implement the events that it's interested in. `()` implements it too, for when there's nothing to observe.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # use dexios_core::observer::{ChunkProgress, StreamObserver};
+//! # use dexios_core::primitives::{gen_nonce, Algorithm, Mode};
+//! # use dexios_core::protected::Protected;
+//! # use dexios_core::stream::EncryptionStreams;
+//! # use std::io::Cursor;
+//! struct Blocks(u64);
+//!
+//! impl StreamObserver for Blocks {
+//!     fn on_chunk_finished(&mut self, progress: &ChunkProgress) {
+//!         self.0 = progress.index + 1;
+//!     }
+//! }
+//!
+//! # let nonce = gen_nonce(&Algorithm::XChaCha20Poly1305, &Mode::StreamMode);
+//! # let encrypt_stream = EncryptionStreams::initialize(Protected::new([0u8; 32]), &nonce, &Algorithm::XChaCha20Poly1305).unwrap();
+//! # let (mut input_file, mut output_file, aad, block_size) = (Cursor::new(vec![0u8; 100]), Vec::new(), [], 64);
+//! let mut observer = Blocks(0);
+//! encrypt_stream.encrypt_file_observed(&mut input_file, &mut output_file, &aad, block_size, &mut observer).unwrap();
+//! # assert_eq!(observer.0, 2);
+//! ```
+
+/// This is reported once a block has been processed, and again (as the totals) once the stream is finished
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChunkProgress {
+    /// The index of the block within the stream, starting from 0
+    pub index: u64,
+    /// Whether this was the final block
+    pub last: bool,
+    /// The number of plaintext bytes that have been processed so far, including this block
+    pub plaintext_bytes: u64,
+    /// The number of ciphertext bytes that have been processed so far, including this block (and any framing)
+    pub ciphertext_bytes: u64,
+}
+
+/// This is notified by the `*_observed()` stream functions as the data is encrypted or decrypted
+pub trait StreamObserver {
+    /// This is called before block `index` is encrypted or decrypted
+    fn on_chunk_start(&mut self, _index: u64) {}
+
+    /// This is called once a block has been written to the output
+    fn on_chunk_finished(&mut self, _progress: &ChunkProgress) {}
+
+    /// This is called once the output has been flushed, with the totals for the entire stream
+    fn on_finished(&mut self, _totals: &ChunkProgress) {}
+}
+
+impl StreamObserver for () {}
+
+/// This keeps the running totals for an observer, so that the stream functions only need to report each block's lengths
+pub(crate) struct Tracker<'a> {
+    observer: &'a mut dyn StreamObserver,
+    progress: ChunkProgress,
+    started: bool,
+}
+
+impl<'a> Tracker<'a> {
+    pub fn new(observer: &'a mut dyn StreamObserver) -> Self {
+        Self {
+            observer,
+            progress: ChunkProgress::default(),
+            started: false,
+        }
+    }
+
+    pub fn start(&mut self) {
+        if self.started {
+            self.progress.index += 1;
+        }
+        self.started = true;
+        self.observer.on_chunk_start(self.progress.index);
+    }
+
+    pub fn finish(&mut self, plaintext_len: usize, ciphertext_len: usize, last: bool) {
+        self.progress.last = last;
+        self.progress.plaintext_bytes += plaintext_len as u64;
+        self.progress.ciphertext_bytes += ciphertext_len as u64;
+        self.observer.on_chunk_finished(&self.progress);
+    }
+
+    pub fn done(self) {
+        self.observer.on_finished(&self.progress);
+    }
+}
@@ -0,0 +1,367 @@
+//! This module allows AES-256-GCM to be offloaded to the operating system's crypto API, on machines where that's faster than the software implementation
+//!
+//! On Linux, the kernel crypto API (`AF_ALG`) is used, which can make use of crypto engines that aren't otherwise accessible (these are common on ARM SBCs that lack the ARMv8 crypto extensions). No other platforms are supported yet, as they don't expose AES-GCM through a public API.
+//!
+//! The output is identical to the software implementation, so this only affects speed. Only messages that fit within the socket's buffer (usually around 200 KiB) are sent to the kernel, and anything larger is encrypted in software - a 64 KiB block size ensures that every block is offloaded.
+//!
+//! The backend is process-wide, and it only applies to ciphers and streams that are initialized after it's been set.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! if is_available() {
+//!     set_backend(CryptoBackend::Kernel).unwrap();
+//! }
+//!
+//! // this now uses the kernel for AES-256-GCM
+//! let cipher = Ciphers::initialize(key, &Algorithm::Aes256Gcm).unwrap();
+//! ```
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::backend::AeadCipher;
+use crate::protected::Protected;
+
+/// This is where AES-256-GCM is computed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CryptoBackend {
+    /// The `aes-gcm` crate, which uses AES-NI/the ARMv8 crypto extensions if they're available
+    Software,
+    /// The operating system's crypto API
+    Kernel,
+}
+
+/// This is an array containing every backend, whether or not it's available on this machine
+pub static CRYPTO_BACKENDS: [CryptoBackend; 2] = [CryptoBackend::Software, CryptoBackend::Kernel];
+
+impl std::fmt::Display for CryptoBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CryptoBackend::Software => write!(f, "software"),
+            CryptoBackend::Kernel => write!(f, "kernel"),
+        }
+    }
+}
+
+impl std::str::FromStr for CryptoBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        CRYPTO_BACKENDS
+            .iter()
+            .find(|backend| backend.to_string().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("Unknown crypto backend: {}", s))
+    }
+}
+
+static KERNEL: AtomicBool = AtomicBool::new(false);
+
+/// This checks whether the operating system's crypto API can be used for AES-256-GCM
+#[must_use]
+pub fn is_available() -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        af_alg::KernelAes256Gcm::new(&Protected::new([0u8; 32])).is_ok()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        false
+    }
+}
+
+/// This selects the backend for any AES-256-GCM ciphers/streams that are initialized from now on
+///
+/// It will return an error if the kernel backend is selected, but it isn't available.
+pub fn set_backend(backend: CryptoBackend) -> anyhow::Result<()> {
+    if backend == CryptoBackend::Kernel && !is_available() {
+        return Err(anyhow::anyhow!(
+            "The kernel crypto API isn't available on this machine"
+        ));
+    }
+
+    KERNEL.store(backend == CryptoBackend::Kernel, Ordering::Relaxed);
+    Ok(())
+}
+
+/// This returns the backend that's currently used for AES-256-GCM
+#[must_use]
+pub fn backend() -> CryptoBackend {
+    if KERNEL.load(Ordering::Relaxed) {
+        CryptoBackend::Kernel
+    } else {
+        CryptoBackend::Software
+    }
+}
+
+/// This initializes AES-256-GCM with the kernel, if that's the selected backend
+///
+/// It returns `None` if the software implementation should be used instead.
+pub(crate) fn aes256gcm(key: &Protected<[u8; 32]>) -> anyhow::Result<Option<Box<dyn AeadCipher>>> {
+    if backend() == CryptoBackend::Software {
+        return Ok(None);
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        Ok(Some(Box::new(af_alg::KernelAes256Gcm::new(key)?)))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = key;
+        Err(anyhow::anyhow!(
+            "The kernel crypto API isn't available on this machine"
+        ))
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod af_alg {
+    use std::io::IoSlice;
+    use std::os::unix::io::RawFd;
+
+    use aead::{KeyInit, Payload};
+    use aes_gcm::Aes256Gcm;
+    use anyhow::Context;
+    use nix::errno::Errno;
+    use nix::libc;
+    use nix::sys::socket::{
+        accept, bind, getsockopt, sendmsg, setsockopt, socket, sockopt, AddressFamily, AlgAddr,
+        ControlMessage, MsgFlags, SockFlag, SockType,
+    };
+
+    use zeroize::Zeroize;
+
+    use crate::backend::AeadCipher;
+    use crate::protected::Protected;
+
+    const TAG_LEN: usize = 16;
+    const NONCE_LEN: usize = 12;
+    const PAGE_SIZE: usize = 4096;
+
+    // this is only a request, as the kernel caps it to `net.core.wmem_max`
+    const REQUESTED_BUFFER_LEN: usize = 4 * 1024 * 1024;
+
+    // this closes the socket once it's no longer needed
+    struct Socket(RawFd);
+
+    impl Drop for Socket {
+        fn drop(&mut self) {
+            nix::unistd::close(self.0).ok();
+        }
+    }
+
+    pub struct KernelAes256Gcm {
+        // the operation socket is only valid while the transform socket is open
+        op: Socket,
+        _tfm: Socket,
+        // this is the largest AAD + message that fits within the socket's buffer
+        limit: usize,
+        // larger messages are handled by this, as the kernel can't process them in one go
+        software: Aes256Gcm,
+    }
+
+    impl KernelAes256Gcm {
+        pub fn new(key: &Protected<[u8; 32]>) -> anyhow::Result<Self> {
+            let tfm = Socket(
+                socket(
+                    AddressFamily::Alg,
+                    SockType::SeqPacket,
+                    SockFlag::SOCK_CLOEXEC,
+                    None,
+                )
+                .context("Unable to open an AF_ALG socket")?,
+            );
+            bind(tfm.0, &AlgAddr::new("aead", "gcm(aes)"))
+                .context("The kernel doesn't provide gcm(aes)")?;
+            setsockopt(tfm.0, sockopt::AlgSetKey::default(), key.expose())
+                .context("Unable to set the kernel's AES-GCM key")?;
+            setsockopt(tfm.0, sockopt::AlgSetAeadAuthSize, &TAG_LEN)
+                .context("Unable to set the kernel's AES-GCM tag length")?;
+
+            let op = Socket(accept(tfm.0).context("Unable to start a kernel AES-GCM operation")?);
+
+            setsockopt(op.0, sockopt::SndBuf, &REQUESTED_BUFFER_LEN).ok();
+            let buffer_len = getsockopt(op.0, sockopt::SndBuf)
+                .context("Unable to get the kernel's buffer length")?;
+
+            let software = Aes256Gcm::new_from_slice(key.expose())
+                .map_err(|_| anyhow::anyhow!("Unable to create cipher with hashed key."))?;
+
+            Ok(Self {
+                op,
+                _tfm: tfm,
+                // the kernel only uses whole pages, and one is left spare
+                limit: (buffer_len / PAGE_SIZE).saturating_sub(1) * PAGE_SIZE,
+                software,
+            })
+        }
+
+        // the input is the AAD followed by the message, and the output is the AAD followed by the result
+        fn process(
+            &self,
+            operation: i32,
+            nonce: &[u8],
+            payload: &Payload<'_, '_>,
+            output_len: usize,
+        ) -> Result<Vec<u8>, Errno> {
+            let assoc_len = u32::try_from(payload.aad.len()).map_err(|_| Errno::EMSGSIZE)?;
+            let input = [IoSlice::new(payload.aad), IoSlice::new(payload.msg)];
+            let control = [
+                ControlMessage::AlgSetOp(&operation),
+                ControlMessage::AlgSetIv(nonce),
+                ControlMessage::AlgSetAeadAssoclen(&assoc_len),
+            ];
+
+            let sent = sendmsg::<()>(self.op.0, &input, &control, MsgFlags::empty(), None)?;
+            if sent != payload.aad.len() + payload.msg.len() {
+                return Err(Errno::EMSGSIZE);
+            }
+
+            let mut output = vec![0u8; payload.aad.len() + output_len];
+            let read = nix::unistd::read(self.op.0, &mut output);
+            if read != Ok(output.len()) {
+                // anything that was read may still contain plaintext
+                output.zeroize();
+                return Err(read.err().unwrap_or(Errno::EIO));
+            }
+
+            Ok(output.split_off(payload.aad.len()))
+        }
+
+        fn fits(&self, payload: &Payload<'_, '_>) -> bool {
+            payload.aad.len() + payload.msg.len() + TAG_LEN <= self.limit
+        }
+    }
+
+    impl AeadCipher for KernelAes256Gcm {
+        fn encrypt(&self, nonce: &[u8], payload: Payload<'_, '_>) -> aead::Result<Vec<u8>> {
+            if nonce.len() != NONCE_LEN {
+                return Err(aead::Error);
+            }
+
+            if !self.fits(&payload) {
+                return AeadCipher::encrypt(&self.software, nonce, payload);
+            }
+
+            self.process(
+                libc::ALG_OP_ENCRYPT,
+                nonce,
+                &payload,
+                payload.msg.len() + TAG_LEN,
+            )
+            .map_err(|_| aead::Error)
+        }
+
+        fn decrypt(&self, nonce: &[u8], payload: Payload<'_, '_>) -> aead::Result<Vec<u8>> {
+            if nonce.len() != NONCE_LEN || payload.msg.len() < TAG_LEN {
+                return Err(aead::Error);
+            }
+
+            if !self.fits(&payload) {
+                return AeadCipher::decrypt(&self.software, nonce, payload);
+            }
+
+            // the kernel returns `EBADMSG` if the tag doesn't match
+            self.process(
+                libc::ALG_OP_DECRYPT,
+                nonce,
+                &payload,
+                payload.msg.len() - TAG_LEN,
+            )
+            .map_err(|_| aead::Error)
+        }
+    }
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const KEY: [u8; 32] = [7u8; 32];
+        const NONCE: [u8; NONCE_LEN] = [9u8; NONCE_LEN];
+
+        // the kernel may not provide AF_ALG (e.g. within a container), so these are skipped there
+        fn kernel() -> Option<KernelAes256Gcm> {
+            KernelAes256Gcm::new(&Protected::new(KEY)).ok()
+        }
+
+        fn software() -> Aes256Gcm {
+            Aes256Gcm::new_from_slice(&KEY).unwrap()
+        }
+
+        fn payload<'a>(msg: &'a [u8], aad: &'a [u8]) -> Payload<'a, 'a> {
+            Payload { msg, aad }
+        }
+
+        #[test]
+        fn should_match_the_software_implementation() {
+            let Some(kernel) = kernel() else {
+                return;
+            };
+            let software = software();
+
+            for (msg, aad) in [
+                (&b""[..], &b""[..]),
+                (b"Hello world", b""),
+                (b"Hello world", b"header"),
+                (&[1u8; 65536], b"header"),
+            ] {
+                assert!(kernel.fits(&payload(msg, aad)));
+
+                let encrypted = AeadCipher::encrypt(&kernel, &NONCE, payload(msg, aad)).unwrap();
+                assert_eq!(
+                    encrypted,
+                    AeadCipher::encrypt(&software, &NONCE, payload(msg, aad)).unwrap()
+                );
+                assert_eq!(
+                    AeadCipher::decrypt(&kernel, &NONCE, payload(&encrypted, aad)).unwrap(),
+                    msg
+                );
+
+                // the kernel refuses a tag that doesn't match, as the software implementation does
+                let mut tampered = encrypted.clone();
+                tampered[0] ^= 1;
+                assert!(AeadCipher::decrypt(&kernel, &NONCE, payload(&tampered, aad)).is_err());
+                assert!(
+                    AeadCipher::decrypt(&kernel, &NONCE, payload(&encrypted, b"other")).is_err()
+                );
+            }
+        }
+
+        #[test]
+        fn should_fall_back_to_software_above_the_buffer_limit() {
+            let Some(kernel) = kernel() else {
+                return;
+            };
+            let software = software();
+
+            let msg = vec![1u8; kernel.limit + 1];
+            assert!(!kernel.fits(&payload(&msg, b"header")));
+
+            let encrypted = AeadCipher::encrypt(&kernel, &NONCE, payload(&msg, b"header")).unwrap();
+            assert_eq!(
+                encrypted,
+                AeadCipher::encrypt(&software, &NONCE, payload(&msg, b"header")).unwrap()
+            );
+            assert_eq!(
+                AeadCipher::decrypt(&kernel, &NONCE, payload(&encrypted, b"header")).unwrap(),
+                msg
+            );
+        }
+
+        #[test]
+        fn should_refuse_other_nonce_lengths() {
+            let Some(kernel) = kernel() else {
+                return;
+            };
+
+            assert!(
+                AeadCipher::encrypt(&kernel, &[0u8; 24], payload(b"Hello world", b"")).is_err()
+            );
+            assert!(
+                AeadCipher::decrypt(&kernel, &NONCE, payload(&[0u8; TAG_LEN - 1], b"")).is_err()
+            );
+        }
+    }
+}
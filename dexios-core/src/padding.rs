@@ -0,0 +1,205 @@
+//! This module contains the padding used to hide exact lengths from the ciphertext
+//!
+//! Lengths are rounded up with Padmé, which leaks at most `O(log log L)` bits of information about a length `L`, with no more than 12% overhead.
+//!
+//! You may read more about Padmé [here](https://lbarman.ch/blog/padme/).
+//!
+//! Padding may be applied to each compressed block (`Compression::ZstdPadded`), or to the entire plaintext (`Padding::Padme`). For the latter, the plaintext is prefixed with its length (as a little-endian `u64`), and padded with zeros so that the total is a Padmé bucket.
+
+use std::io::{Read, Write};
+
+use anyhow::Context;
+
+/// This rounds `len` up to the nearest Padmé bucket
+///
+/// # Examples
+///
+/// ```rust
+/// # use dexios_core::padding::padme;
+/// assert_eq!(padme(1_000_000), 1_015_808);
+/// ```
+///
+#[must_use]
+pub fn padme(len: u64) -> u64 {
+    if len < 2 {
+        return len;
+    }
+
+    // floor(log2(len)), and the number of bits needed to represent it
+    let e = 63 - len.leading_zeros();
+    let s = 32 - e.leading_zeros();
+    let last_bits = e - s;
+    let mask = (1u64 << last_bits) - 1;
+
+    (len + mask) & !mask
+}
+
+/// This prefixes `data` with its length (as a little-endian `u32`), and pads it with zeros up to the Padmé bucket
+pub(crate) fn pad_block(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let len = u32::try_from(data.len()).context("Block is too large to pad")?;
+    let padded_len = padme(data.len() as u64 + 4);
+
+    let mut block = Vec::with_capacity(padded_len as usize);
+    block.extend_from_slice(&len.to_le_bytes());
+    block.extend_from_slice(data);
+    block.resize(padded_len as usize, 0);
+    Ok(block)
+}
+
+/// This reverses `pad_block()`, and returns the original data
+pub(crate) fn unpad_block(block: &[u8]) -> anyhow::Result<&[u8]> {
+    if block.len() < 4 {
+        return Err(anyhow::anyhow!("Padded block is too small"));
+    }
+
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&block[..4]);
+    let len = u32::from_le_bytes(len) as usize;
+
+    block[4..]
+        .get(..len)
+        .context("Padded block has an invalid length")
+}
+
+/// This is the length of the prefix that's added to the plaintext by `PaddedReader`
+pub(crate) const LENGTH_PREFIX_LEN: usize = 8;
+
+/// This wraps a reader, and pads its contents for `Padding::Padme`
+///
+/// The length of the reader's contents must be known ahead of time. If the reader contains any more than `len` bytes, they'll be ignored.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let len = input_file.metadata().unwrap().len();
+/// let mut reader = PaddedReader::new(&mut input_file, len);
+///
+/// encrypt_stream.encrypt_file(&mut reader, &mut output_file, &aad, header.block_size);
+/// ```
+///
+pub struct PaddedReader<R: Read> {
+    reader: R,
+    prefix: [u8; LENGTH_PREFIX_LEN],
+    prefix_pos: usize,
+    remaining_data: u64,
+    remaining_padding: u64,
+}
+
+impl<R: Read> PaddedReader<R> {
+    pub fn new(reader: R, len: u64) -> Self {
+        let padded_len = padme(len + LENGTH_PREFIX_LEN as u64);
+
+        Self {
+            reader,
+            prefix: len.to_le_bytes(),
+            prefix_pos: 0,
+            remaining_data: len,
+            remaining_padding: padded_len - len - LENGTH_PREFIX_LEN as u64,
+        }
+    }
+}
+
+impl<R: Read> Read for PaddedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.prefix_pos < LENGTH_PREFIX_LEN {
+            let count = buf.len().min(LENGTH_PREFIX_LEN - self.prefix_pos);
+            buf[..count].copy_from_slice(&self.prefix[self.prefix_pos..self.prefix_pos + count]);
+            self.prefix_pos += count;
+            return Ok(count);
+        }
+
+        if self.remaining_data > 0 {
+            let max = buf
+                .len()
+                .min(usize::try_from(self.remaining_data).unwrap_or(usize::MAX));
+            let count = self.reader.read(&mut buf[..max])?;
+            if count == 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "The input is shorter than expected",
+                ));
+            }
+            self.remaining_data -= count as u64;
+            return Ok(count);
+        }
+
+        let count = buf
+            .len()
+            .min(usize::try_from(self.remaining_padding).unwrap_or(usize::MAX));
+        buf[..count].fill(0);
+        self.remaining_padding -= count as u64;
+        Ok(count)
+    }
+}
+
+/// This wraps a writer, and strips the padding that was added by `PaddedReader`
+///
+/// `finish()` must be called once everything has been written, to ensure that none of the original data is missing.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let mut writer = UnpaddingWriter::new(&mut output_file);
+///
+/// decrypt_stream.decrypt_file(&mut input_file, &mut writer, &aad, header.block_size);
+/// writer.finish().unwrap();
+/// ```
+///
+pub struct UnpaddingWriter<W: Write> {
+    writer: W,
+    prefix: [u8; LENGTH_PREFIX_LEN],
+    prefix_pos: usize,
+    remaining_data: u64,
+}
+
+impl<W: Write> UnpaddingWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            prefix: [0u8; LENGTH_PREFIX_LEN],
+            prefix_pos: 0,
+            remaining_data: 0,
+        }
+    }
+
+    /// This returns an error if the padded data was truncated
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        if self.prefix_pos < LENGTH_PREFIX_LEN || self.remaining_data > 0 {
+            return Err(anyhow::anyhow!(
+                "The padded data is shorter than its original length"
+            ));
+        }
+
+        self.writer.flush().context("Unable to flush the output")
+    }
+}
+
+impl<W: Write> Write for UnpaddingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut data = buf;
+
+        if self.prefix_pos < LENGTH_PREFIX_LEN {
+            let count = data.len().min(LENGTH_PREFIX_LEN - self.prefix_pos);
+            self.prefix[self.prefix_pos..self.prefix_pos + count].copy_from_slice(&data[..count]);
+            self.prefix_pos += count;
+            data = &data[count..];
+
+            if self.prefix_pos == LENGTH_PREFIX_LEN {
+                self.remaining_data = u64::from_le_bytes(self.prefix);
+            }
+        }
+
+        let count = data
+            .len()
+            .min(usize::try_from(self.remaining_data).unwrap_or(usize::MAX));
+        self.writer.write_all(&data[..count])?;
+        self.remaining_data -= count as u64;
+
+        // anything after the original data is padding, so it's discarded
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,39 @@
+//! This module re-exports the types and functions that most applications need
+//!
+//! Items are sometimes moved between modules as `dexios-core` grows, but everything in the prelude will keep its name and remain here for the rest of the major version. Downstream crates should prefer these paths over the internal module paths.
+//!
+//! An item is only removed from the prelude in a major release, and it'll be deprecated for at least one minor release beforehand. New items may be added in any minor release, so glob imports could occasionally conflict with a downstream crate's own names.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use dexios_core::prelude::*;
+//!
+//! let key = gen_master_key();
+//! let nonce = gen_nonce(&Algorithm::XChaCha20Poly1305, &Mode::MemoryMode);
+//! let cipher = Ciphers::initialize(key, &Algorithm::XChaCha20Poly1305).unwrap();
+//!
+//! let ciphertext = cipher.encrypt(&nonce, b"hello".as_slice()).unwrap();
+//! let plaintext = cipher.decrypt(&nonce, ciphertext.as_slice()).unwrap();
+//! assert_eq!(plaintext, b"hello");
+//! ```
+
+pub use crate::cipher::{decrypt_in_memory, decrypt_in_memory_protected, Ciphers};
+pub use crate::header::{
+    HashingAlgorithm, Header, HeaderType, HeaderVersion, Keyslot, ARGON2ID_LATEST,
+    BLAKE3BALLOON_LATEST, HEADER_VERSION, SCRYPT_LATEST,
+};
+pub use crate::kdf::{Argon2id, Blake3Balloon, Kdf, KdfParams, KeyDerivation, Scrypt};
+pub use crate::key::{
+    argon2id_hash, balloon_hash, decrypt_master_key, derive_key, generate_recovery_code,
+    master_key_to_mnemonic, mnemonic_to_master_key, normalize_recovery_code,
+};
+pub use crate::observer::{ChunkProgress, StreamObserver};
+pub use crate::primitives::{
+    gen_master_key, gen_nonce, gen_salt, get_nonce_len, Algorithm, Mode, ALGORITHMS, BLOCK_SIZE,
+    MASTER_KEY_LEN, SALT_LEN,
+};
+pub use crate::protected::Protected;
+pub use crate::seekable::SeekableReader;
+pub use crate::stream::{DecryptionStreams, EncryptionStreams};
+pub use crate::Zeroize;
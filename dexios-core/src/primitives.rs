@@ -2,25 +2,46 @@
 use crate::protected::Protected;
 use rand::{prelude::ThreadRng, RngCore};
 
-/// This is the streaming block size
+/// This is the default streaming block size
 ///
 /// NOTE: Stream mode can be used to encrypt files less than this size, provided the implementation
 /// is correct
 pub const BLOCK_SIZE: usize = 1_048_576; // 1024*1024 bytes
 
+/// This is the smallest block size that may be stored in a header (see `Header::block_size`)
+pub const MIN_BLOCK_SIZE: usize = 65_536; // 64 KiB
+
+/// This is the largest block size that may be stored in a header (see `Header::block_size`)
+pub const MAX_BLOCK_SIZE: usize = 67_108_864; // 64 MiB
+
 /// This is the length of the salt used for password hashing
 pub const SALT_LEN: usize = 16; // bytes
 
 pub const MASTER_KEY_LEN: usize = 32;
 pub const ENCRYPTED_MASTER_KEY_LEN: usize = 48;
-pub const ALGORITHMS_LEN: usize = 3;
+
+/// Ascon-128a only accepts 128-bit keys
+pub const ASCON_KEY_LEN: usize = 16;
+pub const ALGORITHMS_LEN: usize = 6;
 
 /// This is an `enum` containing all AEADs supported by `dexios-core`
 #[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Algorithm {
     Aes256Gcm,
     XChaCha20Poly1305,
     DeoxysII256,
+    Aegis256,
+    /// Mainly for interop with other tools - the 96-bit nonce leaves less margin for randomly generated nonces than XChaCha20-Poly1305
+    ChaCha20Poly1305,
+    /// Lightweight, and well suited to embedded targets without hardware AES
+    ///
+    /// It takes a 128-bit key, so only the first 16 bytes of the hashed/master key are used
+    Ascon128a,
+    /// An AEAD that has been registered with `backend::register()`
+    ///
+    /// It's not included in `ALGORITHMS`, as it's only available to the application that registered it
+    Custom(u8),
 }
 
 /// This is an array containing all AEADs supported by `dexios-core`.
@@ -30,6 +51,9 @@ pub static ALGORITHMS: [Algorithm; ALGORITHMS_LEN] = [
     Algorithm::XChaCha20Poly1305,
     Algorithm::Aes256Gcm,
     Algorithm::DeoxysII256,
+    Algorithm::Aegis256,
+    Algorithm::ChaCha20Poly1305,
+    Algorithm::Ascon128a,
 ];
 
 impl std::fmt::Display for Algorithm {
@@ -38,15 +62,64 @@ impl std::fmt::Display for Algorithm {
             Algorithm::Aes256Gcm => write!(f, "AES-256-GCM"),
             Algorithm::XChaCha20Poly1305 => write!(f, "XChaCha20-Poly1305"),
             Algorithm::DeoxysII256 => write!(f, "Deoxys-II-256"),
+            Algorithm::Aegis256 => write!(f, "AEGIS-256"),
+            Algorithm::ChaCha20Poly1305 => write!(f, "ChaCha20-Poly1305"),
+            Algorithm::Ascon128a => write!(f, "Ascon-128a"),
+            Algorithm::Custom(id) => match crate::backend::get(*id) {
+                Some(backend) => write!(f, "{}", backend.name()),
+                None => write!(f, "Custom ({:#04x})", id),
+            },
+        }
+    }
+}
+
+// names are compared without case, spaces or dashes (so `aes256gcm` and `AES-256-GCM` are the same)
+fn simplify(name: &str) -> String {
+    name.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+impl std::str::FromStr for Algorithm {
+    type Err = anyhow::Error;
+
+    /// This parses any of the names within `ALGORITHMS` (e.g. `aes256gcm` or `xchacha20-poly1305`)
+    ///
+    /// A name may be shortened (e.g. `xchacha`), as long as only one algorithm starts with it
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let name = match simplify(s).as_str() {
+            "deoxys2" | "deoxysii" => String::from("deoxysii256"),
+            "aes" | "aesgcm" => String::from("aes256gcm"),
+            name => name.to_string(),
+        };
+
+        if let Some(algorithm) = ALGORITHMS.iter().find(|a| simplify(&a.to_string()) == name) {
+            return Ok(*algorithm);
+        }
+
+        let matches: Vec<Algorithm> = ALGORITHMS
+            .into_iter()
+            .filter(|a| !name.is_empty() && simplify(&a.to_string()).starts_with(&name))
+            .collect();
+        match matches[..] {
+            [algorithm] => Ok(algorithm),
+            [] => Err(anyhow::anyhow!("Unknown algorithm: {}", s)),
+            _ => Err(anyhow::anyhow!("Ambiguous algorithm: {}", s)),
         }
     }
 }
 
 /// This defines the possible modes used for encrypting/decrypting
 #[derive(PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Mode {
     MemoryMode,
     StreamMode,
+    /// Stream mode, but every block is encrypted with its own subkey (see `crate::derived`)
+    ///
+    /// This is only supported by `HeaderVersion::V6` and above
+    DerivedStreamMode,
 }
 
 impl std::fmt::Display for Mode {
@@ -54,6 +127,89 @@ impl std::fmt::Display for Mode {
         match self {
             Mode::MemoryMode => write!(f, "Memory Mode"),
             Mode::StreamMode => write!(f, "Stream Mode"),
+            Mode::DerivedStreamMode => write!(f, "Derived Stream Mode"),
+        }
+    }
+}
+
+impl std::str::FromStr for Mode {
+    type Err = anyhow::Error;
+
+    /// This parses `memory`, `stream` or `derived-stream` (the `mode` suffix is optional)
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let name = simplify(s);
+        match name.strip_suffix("mode").unwrap_or(&name) {
+            "memory" => Ok(Mode::MemoryMode),
+            "stream" => Ok(Mode::StreamMode),
+            "derived" | "derivedstream" | "misuseresistant" => Ok(Mode::DerivedStreamMode),
+            _ => Err(anyhow::anyhow!(
+                "Unknown mode: {} (it may be memory, stream or derived-stream)",
+                s
+            )),
+        }
+    }
+}
+
+/// This defines how each block is compressed before it's encrypted
+///
+/// Compression is only supported by `HeaderVersion::V6` and above, and requires `Mode::StreamMode`
+///
+/// The level is only used while encrypting, it isn't stored in the header (deserialized headers will contain level 0, zstd's default)
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Compression {
+    None,
+    Zstd(i32),
+    /// Each compressed block is padded (with Padmé) before it's encrypted, so that the block sizes don't reveal how compressible the data is
+    ZstdPadded(i32),
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Compression::None => write!(f, "None"),
+            Compression::Zstd(_) => write!(f, "zstd"),
+            Compression::ZstdPadded(_) => write!(f, "zstd (padded)"),
+        }
+    }
+}
+
+/// This defines how the entire plaintext is padded before it's encrypted, to hide its exact length (see `crate::padding`)
+///
+/// Padding is only supported by `HeaderVersion::V6` and above, and can't be combined with compression
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Padding {
+    None,
+    Padme,
+}
+
+impl std::fmt::Display for Padding {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Padding::None => write!(f, "None"),
+            Padding::Padme => write!(f, "Padmé"),
+        }
+    }
+}
+
+/// This defines the counter that's used to build each block's nonce in stream mode
+///
+/// `Be64` is only supported by `HeaderVersion::V6` and above, and requires `Mode::StreamMode` (see `crate::counter`)
+#[derive(Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StreamCounter {
+    /// A 31-bit little endian counter, and a 1-bit "last block" flag (`aead::stream::StreamLE31`)
+    Le31,
+    /// A 64-bit big endian counter, and a 1-byte "last block" flag
+    Be64,
+}
+
+impl std::fmt::Display for StreamCounter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StreamCounter::Le31 => write!(f, "LE31"),
+            StreamCounter::Be64 => write!(f, "BE64"),
         }
     }
 }
@@ -79,19 +235,24 @@ pub fn gen_nonce(algorithm: &Algorithm, mode: &Mode) -> Vec<u8> {
 
 /// This function calculates the length of the nonce, depending on the data provided
 ///
-/// Stream mode nonces are 4 bytes less than their "memory" mode counterparts, due to `aead::StreamLE31`
+/// Stream mode nonces (including `Mode::DerivedStreamMode`) are 4 bytes less than their "memory" mode counterparts, due to `aead::StreamLE31`
 ///
 /// `StreamLE31` contains a 31-bit little endian counter, and a 1-bit "last block" flag, stored as the last 4 bytes of the nonce, this is done to prevent nonce-reuse
 #[must_use]
 pub fn get_nonce_len(algorithm: &Algorithm, mode: &Mode) -> usize {
     let mut nonce_len = match algorithm {
-        Algorithm::Aes256Gcm => 12,
+        Algorithm::Aes256Gcm | Algorithm::ChaCha20Poly1305 => 12,
         Algorithm::XChaCha20Poly1305 => 24,
         Algorithm::DeoxysII256 => 15,
+        Algorithm::Ascon128a => 16,
+        // see `crate::aegis` for why this isn't 32
+        Algorithm::Aegis256 => 24,
+        // this is 0 if the backend isn't registered, and initializing the AEAD will fail
+        Algorithm::Custom(id) => crate::backend::nonce_len(*id).unwrap_or(0),
     };
 
-    if mode == &Mode::StreamMode {
-        nonce_len -= 4;
+    if mode != &Mode::MemoryMode {
+        nonce_len = nonce_len.saturating_sub(4);
     }
 
     nonce_len
@@ -134,3 +295,75 @@ pub fn gen_salt() -> [u8; SALT_LEN] {
     ThreadRng::default().fill_bytes(&mut salt);
     salt
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_every_algorithm_from_its_name() {
+        for algorithm in ALGORITHMS {
+            let name = algorithm.to_string();
+            for name in [name.clone(), name.to_lowercase(), simplify(&name)] {
+                assert!(name.parse::<Algorithm>().unwrap() == algorithm, "{name}");
+            }
+        }
+    }
+
+    #[test]
+    fn should_parse_shortened_algorithms() {
+        for (name, algorithm) in [
+            ("xchacha", Algorithm::XChaCha20Poly1305),
+            ("chacha", Algorithm::ChaCha20Poly1305),
+            ("chacha20", Algorithm::ChaCha20Poly1305),
+            ("aegis", Algorithm::Aegis256),
+            ("ascon", Algorithm::Ascon128a),
+            ("deoxys", Algorithm::DeoxysII256),
+            ("deoxys2", Algorithm::DeoxysII256),
+            ("Deoxys-II", Algorithm::DeoxysII256),
+            ("aes", Algorithm::Aes256Gcm),
+            ("AES-GCM", Algorithm::Aes256Gcm),
+        ] {
+            assert!(name.parse::<Algorithm>().unwrap() == algorithm, "{name}");
+        }
+    }
+
+    #[test]
+    fn should_refuse_ambiguous_or_unknown_algorithms() {
+        for (name, error) in [
+            ("a", "Ambiguous"),
+            ("A-", "Ambiguous"),
+            ("", "Unknown"),
+            ("-", "Unknown"),
+            ("aes128gcm", "Unknown"),
+            ("xchacha20poly1305x", "Unknown"),
+        ] {
+            let err = name.parse::<Algorithm>().err().unwrap().to_string();
+            assert!(err.starts_with(error), "{name}: {err}");
+        }
+    }
+
+    #[test]
+    fn should_parse_modes() {
+        for (name, mode) in [
+            ("memory", Mode::MemoryMode),
+            ("stream", Mode::StreamMode),
+            ("derived-stream", Mode::DerivedStreamMode),
+            ("derived", Mode::DerivedStreamMode),
+            ("misuse-resistant", Mode::DerivedStreamMode),
+            ("Memory Mode", Mode::MemoryMode),
+            ("stream-mode", Mode::StreamMode),
+            ("Derived Stream Mode", Mode::DerivedStreamMode),
+        ] {
+            assert!(name.parse::<Mode>().unwrap() == mode, "{name}");
+        }
+
+        for mode in [Mode::MemoryMode, Mode::StreamMode, Mode::DerivedStreamMode] {
+            assert!(mode.to_string().parse::<Mode>().unwrap() == mode);
+        }
+
+        for name in ["", "mode", "streams", "memorymodemode"] {
+            assert!(name.parse::<Mode>().is_err(), "{name}");
+        }
+    }
+}
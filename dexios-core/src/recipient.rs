@@ -0,0 +1,405 @@
+//! This module contains the hybrid key encapsulation that's used for recipient keyslots
+//!
+//! A recipient keyslot wraps the master key to a recipient's public key, rather than to a password, so the data can be decrypted with the matching secret key (an "identity").
+//!
+//! Both X25519 and ML-KEM-768 are used by default, and the wrapping key is derived from both of their shared secrets. This means that the keyslot remains secure as long as either of them does - ML-KEM hedges against future quantum computers, and X25519 hedges against any weaknesses being found in the much newer ML-KEM.
+//!
+//! Plain X25519 keys are supported too. They're far smaller (a public key is 40 bytes rather than 1224), but they offer no protection against quantum computers. The keyslot stores an ephemeral X25519 public key, and the wrapping key is derived from the ECDH shared secret.
+//!
+//! Keys are stored in binary files, which start with an 8-byte identifier so that a public key can't be mistaken for a secret key (or vice versa).
+
+use anyhow::Context;
+use ml_kem::kem::{Decapsulate, DecapsulationKey, Encapsulate, EncapsulationKey};
+use ml_kem::{EncodedSizeUser, KemCore, MlKem768, MlKem768Params};
+use rand::rngs::OsRng;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+use zeroize::Zeroize;
+
+use crate::protected::Protected;
+
+const X25519_KEY_LEN: usize = 32;
+const MLKEM_ENCAPSULATION_KEY_LEN: usize = 1184;
+const MLKEM_DECAPSULATION_KEY_LEN: usize = 2400;
+const MLKEM_CIPHERTEXT_LEN: usize = 1088;
+
+pub const PUBLIC_KEY_MAGIC: [u8; 8] = *b"DXHYBPK1";
+pub const SECRET_KEY_MAGIC: [u8; 8] = *b"DXHYBSK1";
+
+pub const X25519_PUBLIC_KEY_MAGIC: [u8; 8] = *b"DXX25PK1";
+pub const X25519_SECRET_KEY_MAGIC: [u8; 8] = *b"DXX25SK1";
+
+pub const PUBLIC_KEY_LEN: usize = 8 + X25519_KEY_LEN + MLKEM_ENCAPSULATION_KEY_LEN;
+pub const SECRET_KEY_LEN: usize = 8 + X25519_KEY_LEN + MLKEM_DECAPSULATION_KEY_LEN;
+
+pub const X25519_PUBLIC_KEY_LEN: usize = 8 + X25519_KEY_LEN;
+pub const X25519_SECRET_KEY_LEN: usize = 8 + X25519_KEY_LEN;
+
+/// This is the length of the data that's stored alongside each recipient keyslot (the ephemeral X25519 public key, and the ML-KEM ciphertext)
+pub const ENCAPSULATED_KEY_LEN: usize = X25519_KEY_LEN + MLKEM_CIPHERTEXT_LEN;
+
+/// This is the length of the data that's stored alongside each plain X25519 recipient keyslot (just the ephemeral public key)
+pub const X25519_ENCAPSULATED_KEY_LEN: usize = X25519_KEY_LEN;
+
+/// This is the length of a recipient's fingerprint, which is stored in the keyslot's salt field
+pub const FINGERPRINT_LEN: usize = 16;
+
+/// This is used to derive the wrapping key from both shared secrets
+const COMBINER_CONTEXT: &str = "dexios hybrid recipient keyslot v1";
+
+/// This is used to derive the wrapping key from the X25519 shared secret, for plain X25519 recipients
+const X25519_COMBINER_CONTEXT: &str = "dexios x25519 recipient keyslot v1";
+
+/// A recipient's public key, which data may be encrypted to
+///
+/// `mlkem` is `None` for plain X25519 keys.
+pub struct RecipientPublicKey {
+    x25519: PublicKey,
+    mlkem: Option<EncapsulationKey<MlKem768Params>>,
+}
+
+/// A recipient's secret key, which is required for decrypting anything that was encrypted to the matching public key
+pub struct RecipientSecretKey {
+    x25519: StaticSecret,
+    mlkem: Option<DecapsulationKey<MlKem768Params>>,
+}
+
+impl RecipientPublicKey {
+    /// This parses a public key that was previously written with `to_bytes()`
+    ///
+    /// Both hybrid and plain X25519 public keys are accepted.
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let hybrid = match (bytes.len(), bytes.get(..8)) {
+            (PUBLIC_KEY_LEN, Some(magic)) if magic == PUBLIC_KEY_MAGIC => true,
+            (X25519_PUBLIC_KEY_LEN, Some(magic)) if magic == X25519_PUBLIC_KEY_MAGIC => false,
+            _ => return Err(anyhow::anyhow!("This is not a valid recipient public key")),
+        };
+
+        let mut x25519 = [0u8; X25519_KEY_LEN];
+        x25519.copy_from_slice(&bytes[8..8 + X25519_KEY_LEN]);
+
+        let mlkem = if hybrid {
+            let key = (&bytes[8 + X25519_KEY_LEN..])
+                .try_into()
+                .map(EncapsulationKey::from_bytes)
+                .map_err(|_| anyhow::anyhow!("Unable to read the ML-KEM public key"))?;
+            Some(key)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            x25519: PublicKey::from(x25519),
+            mlkem,
+        })
+    }
+
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(PUBLIC_KEY_LEN);
+        if let Some(mlkem) = &self.mlkem {
+            bytes.extend_from_slice(&PUBLIC_KEY_MAGIC);
+            bytes.extend_from_slice(self.x25519.as_bytes());
+            bytes.extend_from_slice(&mlkem.as_bytes());
+        } else {
+            bytes.extend_from_slice(&X25519_PUBLIC_KEY_MAGIC);
+            bytes.extend_from_slice(self.x25519.as_bytes());
+        }
+        bytes
+    }
+
+    /// This returns whether the key is a plain X25519 key, rather than a hybrid one
+    #[must_use]
+    pub fn is_x25519_only(&self) -> bool {
+        self.mlkem.is_none()
+    }
+
+    /// This identifies the public key, and it's what gets stored in the keyslot
+    ///
+    /// It allows for finding the correct keyslot without attempting to decapsulate every one of them.
+    #[must_use]
+    pub fn fingerprint(&self) -> [u8; FINGERPRINT_LEN] {
+        let hash = blake3::hash(&self.to_bytes());
+        let mut fingerprint = [0u8; FINGERPRINT_LEN];
+        fingerprint.copy_from_slice(&hash.as_bytes()[..FINGERPRINT_LEN]);
+        fingerprint
+    }
+
+    /// This generates a fresh wrapping key for this recipient
+    ///
+    /// It returns the wrapping key, and the encapsulated key that needs to be stored alongside the keyslot.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// let (wrapping_key, encapsulated_key) = public_key.encapsulate().unwrap();
+    /// ```
+    ///
+    pub fn encapsulate(&self) -> anyhow::Result<(Protected<[u8; 32]>, Vec<u8>)> {
+        let ephemeral_secret = EphemeralSecret::random_from_rng(OsRng);
+        let ephemeral_public = PublicKey::from(&ephemeral_secret);
+
+        let x25519_shared = ephemeral_secret.diffie_hellman(&self.x25519);
+        if !x25519_shared.was_contributory() {
+            return Err(anyhow::anyhow!(
+                "The recipient's X25519 public key is invalid"
+            ));
+        }
+
+        let Some(mlkem) = &self.mlkem else {
+            let encapsulated_key = ephemeral_public.as_bytes().to_vec();
+            let wrapping_key = combine(
+                X25519_COMBINER_CONTEXT,
+                &[x25519_shared.as_bytes()],
+                &encapsulated_key,
+                self.x25519.as_bytes(),
+            );
+
+            return Ok((wrapping_key, encapsulated_key));
+        };
+
+        let (ciphertext, mlkem_shared) = mlkem
+            .encapsulate(&mut OsRng)
+            .map_err(|_| anyhow::anyhow!("Unable to encapsulate with ML-KEM"))?;
+
+        let mut encapsulated_key = Vec::with_capacity(ENCAPSULATED_KEY_LEN);
+        encapsulated_key.extend_from_slice(ephemeral_public.as_bytes());
+        encapsulated_key.extend_from_slice(&ciphertext);
+
+        let wrapping_key = combine(
+            COMBINER_CONTEXT,
+            &[&mlkem_shared, x25519_shared.as_bytes()],
+            &encapsulated_key,
+            self.x25519.as_bytes(),
+        );
+
+        Ok((wrapping_key, encapsulated_key))
+    }
+}
+
+impl RecipientSecretKey {
+    /// This generates a new hybrid keypair, using the OS' CSPRNG
+    #[must_use]
+    pub fn generate() -> Self {
+        let (mlkem, _) = MlKem768::generate(&mut OsRng);
+
+        Self {
+            x25519: StaticSecret::random_from_rng(OsRng),
+            mlkem: Some(mlkem),
+        }
+    }
+
+    /// This generates a new plain X25519 keypair, using the OS' CSPRNG
+    #[must_use]
+    pub fn generate_x25519() -> Self {
+        Self {
+            x25519: StaticSecret::random_from_rng(OsRng),
+            mlkem: None,
+        }
+    }
+
+    /// This parses a secret key that was previously written with `to_bytes()`
+    ///
+    /// Both hybrid and plain X25519 secret keys are accepted.
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let hybrid = match (bytes.len(), bytes.get(..8)) {
+            (SECRET_KEY_LEN, Some(magic)) if magic == SECRET_KEY_MAGIC => true,
+            (X25519_SECRET_KEY_LEN, Some(magic)) if magic == X25519_SECRET_KEY_MAGIC => false,
+            _ => return Err(anyhow::anyhow!("This is not a valid recipient secret key")),
+        };
+
+        let mut x25519_bytes = [0u8; X25519_KEY_LEN];
+        x25519_bytes.copy_from_slice(&bytes[8..8 + X25519_KEY_LEN]);
+        let x25519 = StaticSecret::from(x25519_bytes);
+        x25519_bytes.zeroize();
+
+        let mlkem = if hybrid {
+            let key = (&bytes[8 + X25519_KEY_LEN..])
+                .try_into()
+                .map(DecapsulationKey::from_bytes)
+                .map_err(|_| anyhow::anyhow!("Unable to read the ML-KEM secret key"))?;
+            Some(key)
+        } else {
+            None
+        };
+
+        Ok(Self { x25519, mlkem })
+    }
+
+    #[must_use]
+    pub fn to_bytes(&self) -> Protected<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(SECRET_KEY_LEN);
+        if let Some(mlkem) = &self.mlkem {
+            bytes.extend_from_slice(&SECRET_KEY_MAGIC);
+            bytes.extend_from_slice(self.x25519.as_bytes());
+            bytes.extend_from_slice(&mlkem.as_bytes());
+        } else {
+            bytes.extend_from_slice(&X25519_SECRET_KEY_MAGIC);
+            bytes.extend_from_slice(self.x25519.as_bytes());
+        }
+        Protected::new(bytes)
+    }
+
+    #[must_use]
+    pub fn public_key(&self) -> RecipientPublicKey {
+        RecipientPublicKey {
+            x25519: PublicKey::from(&self.x25519),
+            mlkem: self
+                .mlkem
+                .as_ref()
+                .map(|mlkem| mlkem.encapsulation_key().clone()),
+        }
+    }
+
+    /// This recovers the wrapping key from the data that was stored alongside a recipient keyslot
+    ///
+    /// ML-KEM decapsulation never fails outright, so a wrong secret key will just produce the wrong wrapping key (and the master key will fail to decrypt).
+    pub fn decapsulate(&self, encapsulated_key: &[u8]) -> anyhow::Result<Protected<[u8; 32]>> {
+        let expected_len = if self.mlkem.is_some() {
+            ENCAPSULATED_KEY_LEN
+        } else {
+            X25519_ENCAPSULATED_KEY_LEN
+        };
+
+        if encapsulated_key.len() != expected_len {
+            return Err(anyhow::anyhow!(
+                "The encapsulated key has an invalid length"
+            ));
+        }
+
+        let mut ephemeral_public = [0u8; X25519_KEY_LEN];
+        ephemeral_public.copy_from_slice(&encapsulated_key[..X25519_KEY_LEN]);
+
+        let x25519_shared = self
+            .x25519
+            .diffie_hellman(&PublicKey::from(ephemeral_public));
+        if !x25519_shared.was_contributory() {
+            return Err(anyhow::anyhow!(
+                "The encapsulated X25519 public key is invalid"
+            ));
+        }
+
+        let Some(mlkem) = &self.mlkem else {
+            return Ok(combine(
+                X25519_COMBINER_CONTEXT,
+                &[x25519_shared.as_bytes()],
+                encapsulated_key,
+                PublicKey::from(&self.x25519).as_bytes(),
+            ));
+        };
+
+        let ciphertext = (&encapsulated_key[X25519_KEY_LEN..])
+            .try_into()
+            .context("Unable to read the ML-KEM ciphertext")?;
+        let mlkem_shared = mlkem
+            .decapsulate(ciphertext)
+            .map_err(|_| anyhow::anyhow!("Unable to decapsulate with ML-KEM"))?;
+
+        Ok(combine(
+            COMBINER_CONTEXT,
+            &[&mlkem_shared, x25519_shared.as_bytes()],
+            encapsulated_key,
+            PublicKey::from(&self.x25519).as_bytes(),
+        ))
+    }
+}
+
+/// This derives the wrapping key from the shared secrets (ML-KEM's first, for hybrid keys)
+///
+/// The encapsulated key and the recipient's X25519 public key are included too, as X25519 on its own doesn't bind the shared secret to either of them.
+fn combine(
+    context: &str,
+    shared_secrets: &[&[u8]],
+    encapsulated_key: &[u8],
+    recipient_x25519: &[u8],
+) -> Protected<[u8; 32]> {
+    let mut hasher = blake3::Hasher::new_derive_key(context);
+    for shared_secret in shared_secrets {
+        hasher.update(shared_secret);
+    }
+    hasher.update(encapsulated_key);
+    hasher.update(recipient_x25519);
+
+    let key = Protected::new(*hasher.finalize().as_bytes());
+    hasher.reset();
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_decapsulate_the_encapsulated_key() {
+        for (identity, encapsulated_key_len) in [
+            (RecipientSecretKey::generate(), ENCAPSULATED_KEY_LEN),
+            (
+                RecipientSecretKey::generate_x25519(),
+                X25519_ENCAPSULATED_KEY_LEN,
+            ),
+        ] {
+            let (key, encapsulated_key) = identity.public_key().encapsulate().unwrap();
+            assert_eq!(encapsulated_key.len(), encapsulated_key_len);
+
+            let decapsulated = identity.decapsulate(&encapsulated_key).unwrap();
+            assert_eq!(key.expose(), decapsulated.expose());
+        }
+
+        // a different identity ends up with a different wrapping key
+        let (key, encapsulated_key) = RecipientSecretKey::generate()
+            .public_key()
+            .encapsulate()
+            .unwrap();
+        let decapsulated = RecipientSecretKey::generate()
+            .decapsulate(&encapsulated_key)
+            .unwrap();
+        assert_ne!(key.expose(), decapsulated.expose());
+    }
+
+    #[test]
+    fn should_round_trip_keys() {
+        for (identity, public_key_len) in [
+            (RecipientSecretKey::generate(), PUBLIC_KEY_LEN),
+            (RecipientSecretKey::generate_x25519(), X25519_PUBLIC_KEY_LEN),
+        ] {
+            let public_bytes = identity.public_key().to_bytes();
+            assert_eq!(public_bytes.len(), public_key_len);
+
+            let restored = RecipientSecretKey::from_bytes(identity.to_bytes().expose()).unwrap();
+            assert_eq!(restored.public_key().to_bytes(), public_bytes);
+            assert_eq!(
+                restored.public_key().is_x25519_only(),
+                public_key_len == X25519_PUBLIC_KEY_LEN
+            );
+            assert_eq!(
+                RecipientPublicKey::from_bytes(&public_bytes)
+                    .unwrap()
+                    .fingerprint(),
+                identity.public_key().fingerprint()
+            );
+        }
+    }
+
+    #[test]
+    fn should_refuse_mismatched_keys() {
+        let identity = RecipientSecretKey::generate();
+
+        // a public key can't be read as a secret key, or vice versa
+        assert!(RecipientPublicKey::from_bytes(identity.to_bytes().expose()).is_err());
+        assert!(RecipientSecretKey::from_bytes(&identity.public_key().to_bytes()).is_err());
+        assert!(identity
+            .decapsulate(&[0u8; ENCAPSULATED_KEY_LEN - 1])
+            .is_err());
+
+        // hybrid and plain X25519 encapsulated keys can't be used in place of each other
+        let (_, encapsulated_key) = RecipientSecretKey::generate_x25519()
+            .public_key()
+            .encapsulate()
+            .unwrap();
+        assert!(identity.decapsulate(&encapsulated_key).is_err());
+
+        let (_, encapsulated_key) = identity.public_key().encapsulate().unwrap();
+        assert!(RecipientSecretKey::generate_x25519()
+            .decapsulate(&encapsulated_key)
+            .is_err());
+    }
+}
@@ -0,0 +1,641 @@
+//! This module contains the seekable format, which allows for reading arbitrary ranges of data encrypted in stream mode
+//!
+//! Every block in stream mode is encrypted on its own, and its position is part of the nonce, so any block can be decrypted without the ones before it. The only thing that's missing is where each block starts, as compressed blocks vary in size.
+//!
+//! Seekable data has a chunk table appended to the ciphertext (before the MAC footer, if there is one). It contains the encrypted length of every block (as a little-endian `u32`), followed by the number of blocks (as a little-endian `u64`) and `TABLE_MAGIC`.
+//!
+//! The table isn't authenticated on its own, but it doesn't need to be - a block that's read from the wrong offset (or with the wrong position) will fail to decrypt.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! let mut writer = ChunkTableWriter::new(&mut output_file, &header);
+//! encrypt_stream.encrypt_file(&mut input_file, &mut writer, &aad, header.block_size).unwrap();
+//! writer.finish().unwrap();
+//!
+//! // the reader should be positioned at the start of the ciphertext (just after the header)
+//! let mut reader = SeekableReader::new(input_file, &header, aad, master_key).unwrap();
+//! reader.seek(SeekFrom::Start(1_000_000)).unwrap();
+//! reader.read_exact(&mut buffer).unwrap();
+//! ```
+
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use aead::Payload;
+use anyhow::Context;
+
+use crate::cipher::Ciphers;
+use crate::counter::Be64Stream;
+use crate::derived::{DerivedStream, DERIVED_SALT_LEN};
+use crate::header::Header;
+use crate::mac::MAC_LEN;
+use crate::padding::{unpad_block, LENGTH_PREFIX_LEN};
+use crate::primitives::{get_nonce_len, Compression, Mode, Padding, StreamCounter, MASTER_KEY_LEN};
+use crate::protected::Protected;
+use crate::stream::LAST_BLOCK_FLAG;
+use crate::subkeys::Subkeys;
+
+/// This marks the end of a chunk table
+pub const TABLE_MAGIC: [u8; 8] = *b"DXSEEK01";
+
+/// This is the length of the block count and `TABLE_MAGIC`
+const TRAILER_LEN: u64 = 16;
+
+/// This is the length of each entry in the table
+const ENTRY_LEN: u64 = 4;
+
+/// This is the length of each block's tag
+const TAG_LEN: usize = 16;
+
+/// In compressed streams, each block is prefixed with its length
+const FRAME_LEN: usize = 4;
+
+/// This contains the encrypted length of every block, exactly as it's stored
+pub struct ChunkTable {
+    lens: Vec<u32>,
+}
+
+impl ChunkTable {
+    #[must_use]
+    pub fn blocks(&self) -> usize {
+        self.lens.len()
+    }
+
+    /// This is the length of all of the blocks combined
+    #[must_use]
+    pub fn ciphertext_len(&self) -> u64 {
+        self.lens.iter().map(|l| u64::from(*l)).sum()
+    }
+
+    /// This is the length of the table once it's been serialized
+    #[must_use]
+    pub fn serialized_len(&self) -> u64 {
+        self.lens.len() as u64 * ENTRY_LEN + TRAILER_LEN
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.lens.len() * ENTRY_LEN as usize);
+        for len in &self.lens {
+            bytes.extend_from_slice(&len.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(self.lens.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&TABLE_MAGIC);
+        bytes
+    }
+
+    /// This reads the table from the end of the data that starts at the reader's current position
+    ///
+    /// If `len` is provided, the data is assumed to end there (e.g. to exclude a MAC footer), otherwise it ends at the end of the reader.
+    ///
+    /// The reader is returned to where it started.
+    pub fn read(reader: &mut (impl Read + Seek), len: Option<u64>) -> anyhow::Result<Self> {
+        let start = reader
+            .stream_position()
+            .context("Unable to get the reader's position")?;
+        let len = match len {
+            Some(len) => len,
+            None => reader
+                .seek(SeekFrom::End(0))
+                .context("Unable to seek to the end of the reader")?
+                .checked_sub(start)
+                .context("The reader is before the start of the data")?,
+        };
+
+        let trailer_start = len
+            .checked_sub(TRAILER_LEN)
+            .context("The data is too short to contain a chunk table")?;
+        reader
+            .seek(SeekFrom::Start(start + trailer_start))
+            .context("Unable to seek to the chunk table")?;
+
+        let mut trailer = [0u8; TRAILER_LEN as usize];
+        reader
+            .read_exact(&mut trailer)
+            .context("Unable to read the chunk table")?;
+        if trailer[8..] != TABLE_MAGIC {
+            return Err(anyhow::anyhow!("The data doesn't end with a chunk table"));
+        }
+
+        let mut blocks = [0u8; 8];
+        blocks.copy_from_slice(&trailer[..8]);
+        let blocks = u64::from_le_bytes(blocks);
+
+        let table_start = blocks
+            .checked_mul(ENTRY_LEN)
+            .and_then(|table_len| trailer_start.checked_sub(table_len))
+            .context("The chunk table is larger than the data")?;
+        reader
+            .seek(SeekFrom::Start(start + table_start))
+            .context("Unable to seek to the chunk table")?;
+
+        let mut entries = vec![0u8; usize::try_from(trailer_start - table_start)?];
+        reader
+            .read_exact(&mut entries)
+            .context("Unable to read the chunk table")?;
+
+        let lens = entries
+            .chunks_exact(ENTRY_LEN as usize)
+            .map(|entry| u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]))
+            .collect::<Vec<_>>();
+
+        let table = Self { lens };
+        if table.ciphertext_len() != table_start {
+            return Err(anyhow::anyhow!(
+                "The chunk table doesn't match the length of the data"
+            ));
+        }
+
+        reader
+            .seek(SeekFrom::Start(start))
+            .context("Unable to seek to the start of the data")?;
+
+        Ok(table)
+    }
+}
+
+/// This passes everything through to the inner writer, while recording where each block ends
+///
+/// It understands the layout that the streams write, so it doesn't matter how the writes are split up.
+pub struct ChunkTableWriter<W: Write> {
+    inner: W,
+    // this is `None` for compressed streams, as their blocks are framed with their length instead
+    block_len: Option<u64>,
+    lens: Vec<u32>,
+    // this is how much of the current block has been written
+    written: u64,
+    frame: [u8; FRAME_LEN],
+    frame_written: usize,
+}
+
+impl<W: Write> ChunkTableWriter<W> {
+    #[must_use]
+    pub fn new(inner: W, header: &Header) -> Self {
+        let overhead = match header.header_type.mode {
+            Mode::DerivedStreamMode => DERIVED_SALT_LEN,
+            _ => 0,
+        };
+
+        let block_len = match header.compression {
+            Compression::None => Some((header.block_size + TAG_LEN + overhead) as u64),
+            Compression::Zstd(_) | Compression::ZstdPadded(_) => None,
+        };
+
+        Self {
+            inner,
+            block_len,
+            lens: Vec::new(),
+            written: 0,
+            frame: [0u8; FRAME_LEN],
+            frame_written: 0,
+        }
+    }
+
+    fn end_block(&mut self) -> std::io::Result<()> {
+        let len = u32::try_from(self.written).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "Block is too large")
+        })?;
+        self.lens.push(len);
+        self.written = 0;
+        self.frame_written = 0;
+        Ok(())
+    }
+
+    fn record(&mut self, mut data: &[u8]) -> std::io::Result<()> {
+        while !data.is_empty() {
+            let remaining = match self.block_len {
+                Some(block_len) => block_len - self.written,
+                None if self.frame_written < FRAME_LEN => {
+                    let n = (FRAME_LEN - self.frame_written).min(data.len());
+                    self.frame[self.frame_written..self.frame_written + n]
+                        .copy_from_slice(&data[..n]);
+                    self.frame_written += n;
+                    self.written += n as u64;
+                    data = &data[n..];
+                    continue;
+                }
+                None => {
+                    let frame = u32::from_le_bytes(self.frame) & !LAST_BLOCK_FLAG;
+                    (FRAME_LEN as u64 + u64::from(frame)) - self.written
+                }
+            };
+
+            let n = usize::try_from(remaining).map_or(data.len(), |r| r.min(data.len()));
+            self.written += n as u64;
+            data = &data[n..];
+
+            if n as u64 == remaining {
+                self.end_block()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// This writes the chunk table, and it must be called once all of the ciphertext has been written
+    pub fn finish(mut self) -> anyhow::Result<W> {
+        // the final block is always shorter than the others in uncompressed streams
+        if self.block_len.is_some() && self.written > 0 {
+            self.end_block()?;
+        }
+
+        if self.written > 0 || self.lens.is_empty() {
+            return Err(anyhow::anyhow!("The final block hasn't been written"));
+        }
+
+        self.inner
+            .write_all(&ChunkTable { lens: self.lens }.serialize())
+            .context("Unable to write the chunk table")?;
+        self.inner.flush().context("Unable to flush the output")?;
+
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkTableWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.record(&buf[..written])?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// this decrypts individual blocks, with the same nonces that the streams use (see `aead::stream::StreamLE31` and `crate::counter`)
+enum BlockCipher {
+    Stream { cipher: Ciphers, nonce: Vec<u8> },
+    Derived(DerivedStream),
+    Be64(Be64Stream),
+}
+
+impl BlockCipher {
+    fn decrypt(
+        &self,
+        position: u64,
+        payload: Payload<'_, '_>,
+        last_block: bool,
+    ) -> aead::Result<Vec<u8>> {
+        match self {
+            BlockCipher::Stream { cipher, nonce } => {
+                let position = u32::try_from(position).map_err(|_| aead::Error)?;
+                let mut nonce = nonce.clone();
+                nonce.extend_from_slice(&(position | (u32::from(last_block) << 31)).to_le_bytes());
+                cipher.decrypt(&nonce, payload)
+            }
+            BlockCipher::Derived(stream) => stream.decrypt_at(
+                u32::try_from(position).map_err(|_| aead::Error)?,
+                payload,
+                last_block,
+            ),
+            BlockCipher::Be64(stream) => stream.decrypt_at(position, payload, last_block),
+        }
+    }
+}
+
+/// This provides random access to seekable data, by only decrypting the blocks that are read from
+///
+/// The most recently decrypted block is kept, so small sequential reads don't decrypt the same block repeatedly.
+///
+/// This doesn't verify the MAC footer (if there is one), as that requires reading everything. Use `crate::mac::verify()` beforehand if that's needed.
+pub struct SeekableReader<R: Read + Seek> {
+    inner: R,
+    cipher: BlockCipher,
+    aad: Vec<u8>,
+    block_size: usize,
+    compression: Compression,
+    decompressor: zstd::bulk::Decompressor<'static>,
+    // these are the absolute offsets/lengths of each block within `inner`
+    offsets: Vec<u64>,
+    lens: Vec<u32>,
+    // this is the length prefix that's added by `Padding::Padme`
+    skip: u64,
+    len: u64,
+    position: u64,
+    cached: Option<(usize, Protected<Vec<u8>>)>,
+}
+
+impl<R: Read + Seek> SeekableReader<R> {
+    /// This reads the chunk table, and prepares the cipher
+    ///
+    /// `inner` must be positioned at the start of the ciphertext (just after the header), and the AAD should be the one returned by `Header::deserialize()`.
+    pub fn new(
+        mut inner: R,
+        header: &Header,
+        aad: Vec<u8>,
+        master_key: Protected<[u8; MASTER_KEY_LEN]>,
+    ) -> anyhow::Result<Self> {
+        if !header.seekable {
+            return Err(anyhow::anyhow!(
+                "This data wasn't encrypted in the seekable format"
+            ));
+        }
+
+        let algorithm = &header.header_type.algorithm;
+        if header.nonce.len() != get_nonce_len(algorithm, &header.header_type.mode) {
+            return Err(anyhow::anyhow!("Nonce is not the correct length"));
+        }
+
+        let payload_key = Subkeys::derive(master_key, header).payload;
+        let cipher = match header.header_type.mode {
+            Mode::StreamMode if header.counter == StreamCounter::Be64 => {
+                BlockCipher::Be64(Be64Stream::new(payload_key, &header.nonce, algorithm)?)
+            }
+            Mode::StreamMode => BlockCipher::Stream {
+                cipher: Ciphers::initialize(payload_key, algorithm)?,
+                nonce: header.nonce.clone(),
+            },
+            Mode::DerivedStreamMode => {
+                BlockCipher::Derived(DerivedStream::new(payload_key, &header.nonce, *algorithm))
+            }
+            Mode::MemoryMode => {
+                return Err(anyhow::anyhow!(
+                    "Only data encrypted in stream mode can be seekable"
+                ))
+            }
+        };
+
+        let start = inner
+            .stream_position()
+            .context("Unable to get the reader's position")?;
+
+        // the MAC footer comes after the table
+        let len = if header.mac {
+            let end = inner
+                .seek(SeekFrom::End(0))
+                .context("Unable to seek to the end of the reader")?;
+            inner
+                .seek(SeekFrom::Start(start))
+                .context("Unable to seek to the start of the ciphertext")?;
+            Some(
+                end.checked_sub(start + MAC_LEN as u64)
+                    .context("The data is too short to contain a MAC")?,
+            )
+        } else {
+            None
+        };
+
+        let table = ChunkTable::read(&mut inner, len)?;
+        let max_blocks = match header.counter {
+            StreamCounter::Le31 => 1 << 31,
+            StreamCounter::Be64 => usize::MAX,
+        };
+        if table.lens.is_empty() || table.blocks() > max_blocks {
+            return Err(anyhow::anyhow!(
+                "The chunk table contains an invalid number of blocks"
+            ));
+        }
+
+        let offsets = table
+            .lens
+            .iter()
+            .scan(start, |offset, len| {
+                let block_offset = *offset;
+                *offset += u64::from(*len);
+                Some(block_offset)
+            })
+            .collect();
+
+        let mut reader = Self {
+            inner,
+            cipher,
+            aad,
+            block_size: header.block_size,
+            compression: header.compression,
+            decompressor: zstd::bulk::Decompressor::new()
+                .context("Unable to initialize the decompressor")?,
+            offsets,
+            lens: table.lens,
+            skip: 0,
+            len: 0,
+            position: 0,
+            cached: None,
+        };
+
+        reader.len = match header.padding {
+            // the real length is stored at the very start of the plaintext
+            Padding::Padme => {
+                let block = reader.block(0)?;
+                let prefix = block
+                    .get(..LENGTH_PREFIX_LEN)
+                    .context("The padded data is too short")?;
+                let mut len = [0u8; LENGTH_PREFIX_LEN];
+                len.copy_from_slice(prefix);
+
+                reader.skip = LENGTH_PREFIX_LEN as u64;
+                u64::from_le_bytes(len)
+            }
+            Padding::None => {
+                let last = reader.lens.len() - 1;
+                let last_len = reader.block(last)?.len() as u64;
+                last as u64 * reader.block_size as u64 + last_len
+            }
+        };
+
+        Ok(reader)
+    }
+
+    /// This is the length of the plaintext
+    #[must_use]
+    pub fn plaintext_len(&self) -> u64 {
+        self.len
+    }
+
+    /// This decrypts (and decompresses) the block at `index`, or returns it from the cache
+    fn block(&mut self, index: usize) -> anyhow::Result<&[u8]> {
+        if !matches!(&self.cached, Some((cached, _)) if *cached == index) {
+            let mut buffer = vec![0u8; self.lens[index] as usize];
+            self.inner
+                .seek(SeekFrom::Start(self.offsets[index]))
+                .context("Unable to seek to the block")?;
+            self.inner
+                .read_exact(&mut buffer)
+                .context("Unable to read the block")?;
+
+            let msg = match self.compression {
+                Compression::None => buffer.as_slice(),
+                Compression::Zstd(_) | Compression::ZstdPadded(_) => {
+                    buffer.get(FRAME_LEN..).context("The block is too short")?
+                }
+            };
+
+            let last_block = index == self.lens.len() - 1;
+            let position = u64::try_from(index)?;
+            let decrypted = Protected::new(
+                self.cipher
+                    .decrypt(
+                        position,
+                        Payload {
+                            aad: &self.aad,
+                            msg,
+                        },
+                        last_block,
+                    )
+                    .map_err(|_| {
+                        anyhow::anyhow!("Unable to decrypt the block. This means either: you're using the wrong key, or the file has been tampered with.")
+                    })?,
+            );
+
+            let plaintext = match self.compression {
+                Compression::None => decrypted,
+                Compression::Zstd(_) => Protected::new(
+                    self.decompressor
+                        .decompress(decrypted.expose(), self.block_size)
+                        .context("Unable to decompress the block")?,
+                ),
+                Compression::ZstdPadded(_) => Protected::new(
+                    self.decompressor
+                        .decompress(unpad_block(decrypted.expose())?, self.block_size)
+                        .context("Unable to decompress the block")?,
+                ),
+            };
+
+            // every block except the last must be full, or the offsets won't line up
+            if !last_block && plaintext.expose().len() != self.block_size {
+                return Err(anyhow::anyhow!("The block is shorter than the block size"));
+            }
+
+            self.cached = Some((index, plaintext));
+        }
+
+        Ok(self
+            .cached
+            .as_ref()
+            .map_or(&[], |(_, b)| b.expose().as_slice()))
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let padded_position = self.position + self.skip;
+        let block_size = self.block_size as u64;
+        let index = usize::try_from(padded_position / block_size).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "Position is too large")
+        })?;
+        // this is always less than the block size
+        #[allow(clippy::cast_possible_truncation)]
+        let offset = (padded_position % block_size) as usize;
+        let remaining = self.len - self.position;
+
+        let block = self
+            .block(index)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?;
+
+        let available = block.len().saturating_sub(offset);
+        let n = usize::try_from(remaining)
+            .map_or(available, |r| r.min(available))
+            .min(buf.len());
+        if n == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "The data is shorter than expected",
+            ));
+        }
+
+        buf[..n].copy_from_slice(&block[offset..offset + n]);
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(n) => i128::from(n),
+            SeekFrom::End(n) => i128::from(self.len) + i128::from(n),
+            SeekFrom::Current(n) => i128::from(self.position) + i128::from(n),
+        };
+
+        self.position = u64::try_from(position).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Unable to seek before the start of the data",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn writer(block_len: Option<u64>) -> ChunkTableWriter<Vec<u8>> {
+        ChunkTableWriter {
+            inner: Vec::new(),
+            block_len,
+            lens: Vec::new(),
+            written: 0,
+            frame: [0u8; FRAME_LEN],
+            frame_written: 0,
+        }
+    }
+
+    #[test]
+    fn should_record_fixed_size_blocks_however_they_are_written() {
+        let mut writer = writer(Some(10));
+        for chunk in [&[0u8; 3][..], &[0u8; 12], &[0u8; 5]] {
+            writer.write_all(chunk).unwrap();
+        }
+        assert_eq!(writer.lens, vec![10, 10]);
+
+        // the final block is shorter than the others
+        writer.write_all(&[0u8; 4]).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let table = ChunkTable::read(&mut Cursor::new(bytes), None).unwrap();
+        assert_eq!(table.lens, vec![10, 10, 4]);
+        assert_eq!(table.ciphertext_len(), 24);
+        assert_eq!(table.serialized_len(), 3 * ENTRY_LEN + TRAILER_LEN);
+    }
+
+    #[test]
+    fn should_record_framed_blocks() {
+        let mut writer = writer(None);
+        let mut data = 5u32.to_le_bytes().to_vec();
+        data.extend_from_slice(&[0u8; 5]);
+        data.extend_from_slice(&(2u32 | LAST_BLOCK_FLAG).to_le_bytes());
+        data.extend_from_slice(&[0u8; 2]);
+
+        // this splits the second block's length prefix across writes
+        writer.write_all(&data[..11]).unwrap();
+        writer.write_all(&data[11..]).unwrap();
+        assert_eq!(writer.lens, vec![9, 6]);
+        assert!(writer.finish().is_ok());
+
+        // a partially-written block can't be recorded
+        let mut writer = self::writer(None);
+        writer.write_all(&data[..7]).unwrap();
+        assert!(writer.finish().is_err());
+    }
+
+    #[test]
+    fn should_refuse_invalid_tables() {
+        let table = ChunkTable { lens: vec![4, 4] }.serialize();
+
+        // the table doesn't account for the ciphertext before it
+        assert!(ChunkTable::read(&mut Cursor::new(table.clone()), None).is_err());
+
+        let mut bytes = vec![0u8; 8];
+        bytes.extend_from_slice(&table);
+        assert!(ChunkTable::read(&mut Cursor::new(bytes.clone()), None).is_ok());
+
+        // something other than a table is at the end (e.g. a MAC footer that wasn't excluded)
+        let mut footer = bytes.clone();
+        footer.extend_from_slice(&[0u8; MAC_LEN]);
+        assert!(ChunkTable::read(&mut Cursor::new(footer.clone()), None).is_err());
+        let len = Some(bytes.len() as u64);
+        assert!(ChunkTable::read(&mut Cursor::new(footer), len).is_ok());
+
+        // the block count claims more entries than there's space for
+        let count = bytes.len() - TRAILER_LEN as usize;
+        bytes[count..count + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(ChunkTable::read(&mut Cursor::new(bytes), None).is_err());
+    }
+}
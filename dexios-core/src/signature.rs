@@ -0,0 +1,195 @@
+//! This module contains the Ed25519 keys and detached signatures that are used for signing encrypted files
+//!
+//! A password only proves that someone knew the password - anyone who can decrypt a file can also create a new one with the same password. Signing the encrypted output with an Ed25519 key allows the recipient to check who actually created it.
+//!
+//! The signature covers a BLAKE3 digest of the header and the ciphertext (see `signed_digest()`), so files of any size may be signed without being read into memory.
+//!
+//! Keys and signatures are stored in binary files, which start with an 8-byte identifier so that they can't be mistaken for one another.
+
+use ed25519_dalek::{Signer, Verifier};
+use rand::rngs::OsRng;
+use zeroize::Zeroize;
+
+use crate::protected::Protected;
+
+pub const PUBLIC_KEY_MAGIC: [u8; 8] = *b"DXEDSPK1";
+pub const SECRET_KEY_MAGIC: [u8; 8] = *b"DXEDSSK1";
+pub const SIGNATURE_MAGIC: [u8; 8] = *b"DXEDSIG1";
+
+const KEY_LEN: usize = 32;
+const ED25519_SIGNATURE_LEN: usize = 64;
+
+pub const PUBLIC_KEY_LEN: usize = 8 + KEY_LEN;
+pub const SECRET_KEY_LEN: usize = 8 + KEY_LEN;
+
+/// A signature file contains the signer's public key, so that it can be identified even if it isn't trusted
+pub const SIGNATURE_LEN: usize = 8 + KEY_LEN + ED25519_SIGNATURE_LEN;
+
+/// This is the length of a signer's fingerprint
+pub const FINGERPRINT_LEN: usize = 16;
+
+/// This is the context that the signed digest is derived with
+const DIGEST_CONTEXT: &str = "dexios detached signature v1";
+
+/// A signer's public key, which is used to verify their signatures
+#[derive(Clone, PartialEq, Eq)]
+pub struct SigningPublicKey {
+    inner: ed25519_dalek::VerifyingKey,
+}
+
+/// A signer's secret key
+pub struct SigningSecretKey {
+    inner: ed25519_dalek::SigningKey,
+}
+
+/// A detached signature, along with the public key of whoever created it
+pub struct Signature {
+    signer: SigningPublicKey,
+    inner: ed25519_dalek::Signature,
+}
+
+/// This creates the hasher that produces the digest which gets signed
+///
+/// The detached header (if there is one) should be written first, followed by the encrypted file.
+#[must_use]
+pub fn signed_digest() -> blake3::Hasher {
+    blake3::Hasher::new_derive_key(DIGEST_CONTEXT)
+}
+
+impl SigningPublicKey {
+    /// This parses a public key that was previously written with `to_bytes()`
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        if bytes.len() != PUBLIC_KEY_LEN || bytes[..8] != PUBLIC_KEY_MAGIC {
+            return Err(anyhow::anyhow!("This is not a valid signing public key"));
+        }
+
+        Self::from_raw(&bytes[8..])
+    }
+
+    fn from_raw(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut key = [0u8; KEY_LEN];
+        key.copy_from_slice(bytes);
+
+        let inner = ed25519_dalek::VerifyingKey::from_bytes(&key)
+            .map_err(|_| anyhow::anyhow!("The Ed25519 public key is invalid"))?;
+
+        Ok(Self { inner })
+    }
+
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(PUBLIC_KEY_LEN);
+        bytes.extend_from_slice(&PUBLIC_KEY_MAGIC);
+        bytes.extend_from_slice(self.inner.as_bytes());
+        bytes
+    }
+
+    /// This identifies the public key, so that it's easy for users to compare
+    #[must_use]
+    pub fn fingerprint(&self) -> [u8; FINGERPRINT_LEN] {
+        let hash = blake3::hash(&self.to_bytes());
+        let mut fingerprint = [0u8; FINGERPRINT_LEN];
+        fingerprint.copy_from_slice(&hash.as_bytes()[..FINGERPRINT_LEN]);
+        fingerprint
+    }
+
+    /// This checks that the signature was created by this key, over the provided digest
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// public_key.verify(&digest, &signature)?;
+    /// ```
+    ///
+    pub fn verify(&self, digest: &blake3::Hash, signature: &Signature) -> anyhow::Result<()> {
+        if signature.signer != *self {
+            return Err(anyhow::anyhow!("The file was signed by a different key"));
+        }
+
+        self.inner
+            .verify(digest.as_bytes(), &signature.inner)
+            .map_err(|_| {
+                anyhow::anyhow!("The signature is invalid - the file may have been modified")
+            })
+    }
+}
+
+impl SigningSecretKey {
+    /// This generates a new keypair, using the OS' CSPRNG
+    #[must_use]
+    pub fn generate() -> Self {
+        Self {
+            inner: ed25519_dalek::SigningKey::generate(&mut OsRng),
+        }
+    }
+
+    /// This parses a secret key that was previously written with `to_bytes()`
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        if bytes.len() != SECRET_KEY_LEN || bytes[..8] != SECRET_KEY_MAGIC {
+            return Err(anyhow::anyhow!("This is not a valid signing secret key"));
+        }
+
+        let mut key = [0u8; KEY_LEN];
+        key.copy_from_slice(&bytes[8..]);
+        let inner = ed25519_dalek::SigningKey::from_bytes(&key);
+        key.zeroize();
+
+        Ok(Self { inner })
+    }
+
+    #[must_use]
+    pub fn to_bytes(&self) -> Protected<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(SECRET_KEY_LEN);
+        bytes.extend_from_slice(&SECRET_KEY_MAGIC);
+        bytes.extend_from_slice(self.inner.as_bytes());
+        Protected::new(bytes)
+    }
+
+    #[must_use]
+    pub fn public_key(&self) -> SigningPublicKey {
+        SigningPublicKey {
+            inner: self.inner.verifying_key(),
+        }
+    }
+
+    /// This signs the provided digest (see `signed_digest()`)
+    #[must_use]
+    pub fn sign(&self, digest: &blake3::Hash) -> Signature {
+        Signature {
+            signer: self.public_key(),
+            inner: self.inner.sign(digest.as_bytes()),
+        }
+    }
+}
+
+impl Signature {
+    /// This parses a signature that was previously written with `to_bytes()`
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        if bytes.len() != SIGNATURE_LEN || bytes[..8] != SIGNATURE_MAGIC {
+            return Err(anyhow::anyhow!("This is not a valid signature"));
+        }
+
+        let signer = SigningPublicKey::from_raw(&bytes[8..8 + KEY_LEN])?;
+        let inner = ed25519_dalek::Signature::from_slice(&bytes[8 + KEY_LEN..])
+            .map_err(|_| anyhow::anyhow!("Unable to read the Ed25519 signature"))?;
+
+        Ok(Self { signer, inner })
+    }
+
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(SIGNATURE_LEN);
+        bytes.extend_from_slice(&SIGNATURE_MAGIC);
+        bytes.extend_from_slice(self.signer.inner.as_bytes());
+        bytes.extend_from_slice(&self.inner.to_bytes());
+        bytes
+    }
+
+    /// This is the public key that the signature claims to be from
+    ///
+    /// It should only be trusted once the signature has been verified with a public key that was obtained separately.
+    #[must_use]
+    pub fn signer(&self) -> &SigningPublicKey {
+        &self.signer
+    }
+}
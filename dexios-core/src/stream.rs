@@ -1,4 +1,4 @@
-//! This module contains all of the LE31 STREAM objects and functionality
+//! This module contains all of the STREAM objects and functionality (LE31 by default, or BE64 with `StreamCounter::Be64`)
 //!
 //! This is where streaming mode encryption, decryption and initialization is handled.
 //!
@@ -23,7 +23,7 @@
 //! // aad should be retrieved from the `Header` (with `Header::deserialize()`)
 //! let aad = Vec::new();
 //!
-//! decrypt_stream.decrypt_file(&mut input_file, &mut output_file, &aad);
+//! decrypt_stream.decrypt_file(&mut input_file, &mut output_file, &aad, BLOCK_SIZE);
 //! ```
 
 use std::io::{Read, Write};
@@ -34,14 +34,42 @@ use aead::{
 };
 use aes_gcm::Aes256Gcm;
 use anyhow::Context;
-use chacha20poly1305::XChaCha20Poly1305;
+use ascon_aead::Ascon128a;
+use chacha20poly1305::{ChaCha20Poly1305, XChaCha20Poly1305};
 use deoxys::DeoxysII256;
 // use rand::{prelude::StdRng, Rng, SeedableRng, RngCore};
 use zeroize::Zeroize;
 
-use crate::primitives::{Algorithm, BLOCK_SIZE};
+use crate::aegis::Aegis256;
+use crate::backend::CustomStream;
+use crate::counter::Be64Stream;
+use crate::derived::{DerivedStream, DERIVED_SALT_LEN};
+use crate::observer::{StreamObserver, Tracker};
+use crate::padding::{pad_block, padme, unpad_block};
+use crate::primitives::{get_nonce_len, Algorithm, Mode, ASCON_KEY_LEN};
 use crate::protected::Protected;
 
+/// In compressed streams, each encrypted block is prefixed with its length as a little-endian `u32`
+///
+/// The highest bit of the length is set on the final block, so that the decryptor knows when to call `decrypt_last()`
+pub(crate) const LAST_BLOCK_FLAG: u32 = 1 << 31;
+
+/// This fills `buffer` from `reader`, only stopping early if the end of the reader is hit
+///
+/// A single `read()` may return less than requested (e.g. pipes or sockets), which would otherwise be mistaken for the last block
+fn read_block(reader: &mut impl Read, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut read_count = 0;
+    while read_count < buffer.len() {
+        match reader.read(&mut buffer[read_count..]) {
+            Ok(0) => break,
+            Ok(n) => read_count += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read_count)
+}
+
 /// This `enum` contains streams for that are used solely for encryption
 ///
 /// It has definitions for all AEADs supported by `dexios-core`
@@ -49,6 +77,12 @@ pub enum EncryptionStreams {
     Aes256Gcm(Box<EncryptorLE31<Aes256Gcm>>),
     XChaCha20Poly1305(Box<EncryptorLE31<XChaCha20Poly1305>>),
     DeoxysII256(Box<EncryptorLE31<DeoxysII256>>),
+    Aegis256(Box<EncryptorLE31<Aegis256>>),
+    ChaCha20Poly1305(Box<EncryptorLE31<ChaCha20Poly1305>>),
+    Ascon128a(Box<EncryptorLE31<Ascon128a>>),
+    Custom(Box<CustomStream>),
+    Derived(Box<DerivedStream>),
+    Be64(Box<Be64Stream>),
 }
 
 /// This `enum` contains streams for that are used solely for decryption
@@ -58,6 +92,12 @@ pub enum DecryptionStreams {
     Aes256Gcm(Box<DecryptorLE31<Aes256Gcm>>),
     XChaCha20Poly1305(Box<DecryptorLE31<XChaCha20Poly1305>>),
     DeoxysII256(Box<DecryptorLE31<DeoxysII256>>),
+    Aegis256(Box<DecryptorLE31<Aegis256>>),
+    ChaCha20Poly1305(Box<DecryptorLE31<ChaCha20Poly1305>>),
+    Ascon128a(Box<DecryptorLE31<Ascon128a>>),
+    Custom(Box<CustomStream>),
+    Derived(Box<DerivedStream>),
+    Be64(Box<Be64Stream>),
 }
 
 impl EncryptionStreams {
@@ -124,12 +164,87 @@ impl EncryptionStreams {
                 let stream = EncryptorLE31::from_aead(cipher, nonce.into());
                 EncryptionStreams::DeoxysII256(Box::new(stream))
             }
+            Algorithm::Aegis256 => {
+                if nonce.len() != 20 {
+                    return Err(anyhow::anyhow!("Nonce is not the correct length"));
+                }
+
+                let cipher = Aegis256::new_from_slice(key.expose())
+                    .map_err(|_| anyhow::anyhow!("Unable to create cipher with hashed key."))?;
+
+                let stream = EncryptorLE31::from_aead(cipher, nonce.into());
+                EncryptionStreams::Aegis256(Box::new(stream))
+            }
+            Algorithm::ChaCha20Poly1305 => {
+                if nonce.len() != 8 {
+                    return Err(anyhow::anyhow!("Nonce is not the correct length"));
+                }
+
+                let cipher = ChaCha20Poly1305::new_from_slice(key.expose())
+                    .map_err(|_| anyhow::anyhow!("Unable to create cipher with hashed key."))?;
+
+                let stream = EncryptorLE31::from_aead(cipher, nonce.into());
+                EncryptionStreams::ChaCha20Poly1305(Box::new(stream))
+            }
+            Algorithm::Ascon128a => {
+                if nonce.len() != 12 {
+                    return Err(anyhow::anyhow!("Nonce is not the correct length"));
+                }
+
+                let cipher = Ascon128a::new_from_slice(&key.expose()[..ASCON_KEY_LEN])
+                    .map_err(|_| anyhow::anyhow!("Unable to create cipher with hashed key."))?;
+
+                let stream = EncryptorLE31::from_aead(cipher, nonce.into());
+                EncryptionStreams::Ascon128a(Box::new(stream))
+            }
+            Algorithm::Custom(id) => {
+                if nonce.is_empty() || nonce.len() != get_nonce_len(algorithm, &Mode::StreamMode) {
+                    return Err(anyhow::anyhow!("Nonce is not the correct length"));
+                }
+
+                let cipher = crate::backend::initialize(*id, key)?;
+                return Ok(EncryptionStreams::Custom(Box::new(CustomStream::new(
+                    cipher, nonce,
+                ))));
+            }
         };
 
         drop(key);
         Ok(streams)
     }
 
+    /// This creates a stream for `Mode::DerivedStreamMode`, where every block is encrypted with its own subkey
+    ///
+    /// The key is retained (rather than dropped) as it's needed for deriving each subkey
+    ///
+    /// The nonce is the same length as it would be in regular stream mode
+    pub fn initialize_derived(
+        key: Protected<[u8; 32]>,
+        nonce: &[u8],
+        algorithm: &Algorithm,
+    ) -> anyhow::Result<Self> {
+        if nonce.is_empty() || nonce.len() != get_nonce_len(algorithm, &Mode::DerivedStreamMode) {
+            return Err(anyhow::anyhow!("Nonce is not the correct length"));
+        }
+
+        Ok(EncryptionStreams::Derived(Box::new(DerivedStream::new(
+            key, nonce, *algorithm,
+        ))))
+    }
+
+    /// This creates a stream with a 64-bit counter, for `StreamCounter::Be64` (see `crate::counter`)
+    ///
+    /// The nonce is the same length as it would be in regular stream mode
+    pub fn initialize_be64(
+        key: Protected<[u8; 32]>,
+        nonce: &[u8],
+        algorithm: &Algorithm,
+    ) -> anyhow::Result<Self> {
+        Ok(EncryptionStreams::Be64(Box::new(Be64Stream::new(
+            key, nonce, algorithm,
+        )?)))
+    }
+
     /// This is used for encrypting the *next* block of data in streaming mode
     ///
     /// It requires either some plaintext, or an `aead::Payload` (that contains the plaintext and the AAD)
@@ -141,6 +256,12 @@ impl EncryptionStreams {
             EncryptionStreams::Aes256Gcm(s) => s.encrypt_next(payload),
             EncryptionStreams::XChaCha20Poly1305(s) => s.encrypt_next(payload),
             EncryptionStreams::DeoxysII256(s) => s.encrypt_next(payload),
+            EncryptionStreams::Aegis256(s) => s.encrypt_next(payload),
+            EncryptionStreams::ChaCha20Poly1305(s) => s.encrypt_next(payload),
+            EncryptionStreams::Ascon128a(s) => s.encrypt_next(payload),
+            EncryptionStreams::Custom(s) => s.encrypt(payload.into(), false),
+            EncryptionStreams::Derived(s) => s.encrypt(payload.into(), false),
+            EncryptionStreams::Be64(s) => s.encrypt(payload.into(), false),
         }
     }
 
@@ -155,6 +276,12 @@ impl EncryptionStreams {
             EncryptionStreams::Aes256Gcm(s) => s.encrypt_last(payload),
             EncryptionStreams::XChaCha20Poly1305(s) => s.encrypt_last(payload),
             EncryptionStreams::DeoxysII256(s) => s.encrypt_last(payload),
+            EncryptionStreams::Aegis256(s) => s.encrypt_last(payload),
+            EncryptionStreams::ChaCha20Poly1305(s) => s.encrypt_last(payload),
+            EncryptionStreams::Ascon128a(s) => s.encrypt_last(payload),
+            EncryptionStreams::Custom(mut s) => s.encrypt(payload.into(), true),
+            EncryptionStreams::Derived(mut s) => s.encrypt(payload.into(), true),
+            EncryptionStreams::Be64(mut s) => s.encrypt(payload.into(), true),
         }
     }
 
@@ -166,6 +293,8 @@ impl EncryptionStreams {
     ///
     /// You are free to use a custom AAD, just ensure that it is present for decryption, or else you will receive an error.
     ///
+    /// The data is read in blocks of `block_size` bytes, which should be stored in the header (`Header::block_size`) so that it's available for decryption. Use `BLOCK_SIZE` if you don't need anything else.
+    ///
     /// This does not handle writing the header.
     ///
     /// # Examples
@@ -178,24 +307,38 @@ impl EncryptionStreams {
     /// let aad = header.serialize().unwrap();
     ///
     /// let encrypt_stream = EncryptionStreams::initialize(key, &nonce, &Algorithm::XChaCha20Poly1305).unwrap();
-    /// encrypt_stream.encrypt_file(&mut input_file, &mut output_file, &aad);
+    /// encrypt_stream.encrypt_file(&mut input_file, &mut output_file, &aad, header.block_size);
     /// ```
     ///
     pub fn encrypt_file(
+        self,
+        reader: &mut impl Read,
+        writer: &mut impl Write,
+        aad: &[u8],
+        block_size: usize,
+    ) -> anyhow::Result<()> {
+        self.encrypt_file_observed(reader, writer, aad, block_size, &mut ())
+    }
+
+    /// This is the same as `encrypt_file()`, but `observer` is notified as each block is encrypted (see `crate::observer`)
+    pub fn encrypt_file_observed(
         mut self,
         reader: &mut impl Read,
         writer: &mut impl Write,
         aad: &[u8],
+        block_size: usize,
+        observer: &mut dyn StreamObserver,
     ) -> anyhow::Result<()> {
         #[cfg(feature = "visual")]
         let pb = crate::visual::create_spinner();
 
-        let mut read_buffer = vec![0u8; BLOCK_SIZE].into_boxed_slice();
+        let mut tracker = Tracker::new(observer);
+        let mut read_buffer = vec![0u8; block_size].into_boxed_slice();
         loop {
-            let read_count = reader
-                .read(&mut read_buffer)
-                .context("Unable to read from the reader")?;
-            if read_count == BLOCK_SIZE {
+            let read_count =
+                read_block(reader, &mut read_buffer).context("Unable to read from the reader")?;
+            tracker.start();
+            if read_count == block_size {
                 // aad is just empty bytes normally
                 // create_aad returns empty bytes if the header isn't V3+
                 // this means we don't need to do anything special in regards to older versions
@@ -211,8 +354,9 @@ impl EncryptionStreams {
                 writer
                     .write_all(&encrypted_data)
                     .context("Unable to write to the output")?;
+                tracker.finish(read_count, encrypted_data.len(), false);
             } else {
-                // if we read something less than BLOCK_SIZE, and have hit the end of the file
+                // if we read something less than the block size, and have hit the end of the file
                 let payload = Payload {
                     aad,
                     msg: &read_buffer[..read_count],
@@ -225,11 +369,140 @@ impl EncryptionStreams {
                 writer
                     .write_all(&encrypted_data)
                     .context("Unable to write to the output")?;
+                tracker.finish(read_count, encrypted_data.len(), true);
                 break;
             }
         }
         read_buffer.zeroize();
         writer.flush().context("Unable to flush the output")?;
+        tracker.done();
+
+        #[cfg(feature = "visual")]
+        pb.finish_and_clear();
+
+        Ok(())
+    }
+
+    /// This is a variant of `encrypt_file()` that compresses each block with zstd before it's encrypted
+    ///
+    /// As compressed blocks vary in size, each encrypted block is framed with its length (see `decrypt_file_compressed()`)
+    ///
+    /// If `pad` is set, each compressed block is padded to a Padmé bucket before it's encrypted (for `Compression::ZstdPadded`).
+    ///
+    /// This should only be used if the header contains `Compression::Zstd`/`Compression::ZstdPadded`, as that's how the decryptor knows to expect framed blocks.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// let mut input_file = File::open("input").unwrap();
+    /// let mut output_file = File::create("output.encrypted").unwrap();
+    ///
+    /// let encrypt_stream = EncryptionStreams::initialize(key, &nonce, &Algorithm::XChaCha20Poly1305).unwrap();
+    /// encrypt_stream.encrypt_file_compressed(&mut input_file, &mut output_file, &aad, header.block_size, 3, false);
+    /// ```
+    ///
+    pub fn encrypt_file_compressed(
+        self,
+        reader: &mut impl Read,
+        writer: &mut impl Write,
+        aad: &[u8],
+        block_size: usize,
+        level: i32,
+        pad: bool,
+    ) -> anyhow::Result<()> {
+        self.encrypt_file_compressed_observed(reader, writer, aad, block_size, level, pad, &mut ())
+    }
+
+    /// This is the same as `encrypt_file_compressed()`, but `observer` is notified as each block is encrypted (see `crate::observer`)
+    ///
+    /// The ciphertext lengths that are reported include each block's length prefix.
+    #[allow(clippy::too_many_arguments)]
+    pub fn encrypt_file_compressed_observed(
+        mut self,
+        reader: &mut impl Read,
+        writer: &mut impl Write,
+        aad: &[u8],
+        block_size: usize,
+        level: i32,
+        pad: bool,
+        observer: &mut dyn StreamObserver,
+    ) -> anyhow::Result<()> {
+        #[cfg(feature = "visual")]
+        let pb = crate::visual::create_spinner();
+
+        let mut tracker = Tracker::new(observer);
+
+        let mut compressor =
+            zstd::bulk::Compressor::new(level).context("Unable to initialize the compressor")?;
+
+        let mut read_buffer = vec![0u8; block_size].into_boxed_slice();
+        let mut write_block = |encrypted_data: Vec<u8>, last: bool| -> anyhow::Result<()> {
+            let mut frame =
+                u32::try_from(encrypted_data.len()).context("Compressed block is too large")?;
+            if last {
+                frame |= LAST_BLOCK_FLAG;
+            }
+
+            writer
+                .write_all(&frame.to_le_bytes())
+                .context("Unable to write to the output")?;
+            writer
+                .write_all(&encrypted_data)
+                .context("Unable to write to the output")
+        };
+
+        let (mut compressed_data, last_read_count) = loop {
+            let read_count =
+                read_block(reader, &mut read_buffer).context("Unable to read from the reader")?;
+            tracker.start();
+
+            let mut compressed_data = compressor
+                .compress(&read_buffer[..read_count])
+                .context("Unable to compress the data")?;
+
+            if pad {
+                let padded_data = pad_block(&compressed_data)?;
+                compressed_data.zeroize();
+                compressed_data = padded_data;
+            }
+
+            // if we read something less than the block size, and have hit the end of the file
+            if read_count != block_size {
+                break (compressed_data, read_count);
+            }
+
+            let payload = Payload {
+                aad,
+                msg: compressed_data.as_ref(),
+            };
+
+            let encrypted_data = self
+                .encrypt_next(payload)
+                .map_err(|_| anyhow::anyhow!("Unable to encrypt the data"))?;
+            compressed_data.zeroize();
+
+            let encrypted_len = encrypted_data.len() + 4;
+            write_block(encrypted_data, false)?;
+            tracker.finish(read_count, encrypted_len, false);
+        };
+
+        let payload = Payload {
+            aad,
+            msg: compressed_data.as_ref(),
+        };
+
+        let encrypted_data = self
+            .encrypt_last(payload)
+            .map_err(|_| anyhow::anyhow!("Unable to encrypt the data"))?;
+        compressed_data.zeroize();
+
+        let encrypted_len = encrypted_data.len() + 4;
+        write_block(encrypted_data, true)?;
+        tracker.finish(last_read_count, encrypted_len, true);
+
+        read_buffer.zeroize();
+        writer.flush().context("Unable to flush the output")?;
+        tracker.done();
 
         #[cfg(feature = "visual")]
         pb.finish_and_clear();
@@ -270,6 +543,12 @@ impl DecryptionStreams {
     ) -> anyhow::Result<Self> {
         let streams = match algorithm {
             Algorithm::Aes256Gcm => {
+                if let Some(cipher) = crate::os_crypto::aes256gcm(&key)? {
+                    return Ok(DecryptionStreams::Custom(Box::new(CustomStream::new(
+                        cipher, nonce,
+                    ))));
+                }
+
                 let cipher = Aes256Gcm::new_from_slice(key.expose())
                     .map_err(|_| anyhow::anyhow!("Unable to create cipher with hashed key."))?;
 
@@ -290,12 +569,83 @@ impl DecryptionStreams {
                 let stream = DecryptorLE31::from_aead(cipher, nonce.into());
                 DecryptionStreams::DeoxysII256(Box::new(stream))
             }
+            Algorithm::Aegis256 => {
+                let cipher = Aegis256::new_from_slice(key.expose())
+                    .map_err(|_| anyhow::anyhow!("Unable to create cipher with hashed key."))?;
+
+                let stream = DecryptorLE31::from_aead(cipher, nonce.into());
+                DecryptionStreams::Aegis256(Box::new(stream))
+            }
+            Algorithm::ChaCha20Poly1305 => {
+                let cipher = ChaCha20Poly1305::new_from_slice(key.expose())
+                    .map_err(|_| anyhow::anyhow!("Unable to create cipher with hashed key."))?;
+
+                let stream = DecryptorLE31::from_aead(cipher, nonce.into());
+                DecryptionStreams::ChaCha20Poly1305(Box::new(stream))
+            }
+            Algorithm::Ascon128a => {
+                let cipher = Ascon128a::new_from_slice(&key.expose()[..ASCON_KEY_LEN])
+                    .map_err(|_| anyhow::anyhow!("Unable to create cipher with hashed key."))?;
+
+                let stream = DecryptorLE31::from_aead(cipher, nonce.into());
+                DecryptionStreams::Ascon128a(Box::new(stream))
+            }
+            Algorithm::Custom(id) => {
+                if nonce.is_empty() || nonce.len() != get_nonce_len(algorithm, &Mode::StreamMode) {
+                    return Err(anyhow::anyhow!("Nonce is not the correct length"));
+                }
+
+                let cipher = crate::backend::initialize(*id, key)?;
+                return Ok(DecryptionStreams::Custom(Box::new(CustomStream::new(
+                    cipher, nonce,
+                ))));
+            }
         };
 
         drop(key);
         Ok(streams)
     }
 
+    /// This creates a stream for `Mode::DerivedStreamMode`, where every block is decrypted with its own subkey
+    ///
+    /// The key is retained (rather than dropped) as it's needed for deriving each subkey
+    ///
+    /// The nonce is the same length as it would be in regular stream mode
+    pub fn initialize_derived(
+        key: Protected<[u8; 32]>,
+        nonce: &[u8],
+        algorithm: &Algorithm,
+    ) -> anyhow::Result<Self> {
+        if nonce.is_empty() || nonce.len() != get_nonce_len(algorithm, &Mode::DerivedStreamMode) {
+            return Err(anyhow::anyhow!("Nonce is not the correct length"));
+        }
+
+        Ok(DecryptionStreams::Derived(Box::new(DerivedStream::new(
+            key, nonce, *algorithm,
+        ))))
+    }
+
+    /// This creates a stream with a 64-bit counter, for `StreamCounter::Be64` (see `crate::counter`)
+    ///
+    /// The nonce is the same length as it would be in regular stream mode
+    pub fn initialize_be64(
+        key: Protected<[u8; 32]>,
+        nonce: &[u8],
+        algorithm: &Algorithm,
+    ) -> anyhow::Result<Self> {
+        Ok(DecryptionStreams::Be64(Box::new(Be64Stream::new(
+            key, nonce, algorithm,
+        )?)))
+    }
+
+    /// This returns how many extra bytes are stored alongside each block (on top of the tag)
+    fn block_overhead(&self) -> usize {
+        match self {
+            DecryptionStreams::Derived(_) => DERIVED_SALT_LEN,
+            _ => 0,
+        }
+    }
+
     /// This is used for decrypting the *next* block of data in streaming mode
     ///
     /// It requires either some plaintext, or an `aead::Payload` (that contains the plaintext and the AAD)
@@ -309,6 +659,12 @@ impl DecryptionStreams {
             DecryptionStreams::Aes256Gcm(s) => s.decrypt_next(payload),
             DecryptionStreams::XChaCha20Poly1305(s) => s.decrypt_next(payload),
             DecryptionStreams::DeoxysII256(s) => s.decrypt_next(payload),
+            DecryptionStreams::Aegis256(s) => s.decrypt_next(payload),
+            DecryptionStreams::ChaCha20Poly1305(s) => s.decrypt_next(payload),
+            DecryptionStreams::Ascon128a(s) => s.decrypt_next(payload),
+            DecryptionStreams::Custom(s) => s.decrypt(payload.into(), false),
+            DecryptionStreams::Derived(s) => s.decrypt(payload.into(), false),
+            DecryptionStreams::Be64(s) => s.decrypt(payload.into(), false),
         }
     }
 
@@ -325,6 +681,12 @@ impl DecryptionStreams {
             DecryptionStreams::Aes256Gcm(s) => s.decrypt_last(payload),
             DecryptionStreams::XChaCha20Poly1305(s) => s.decrypt_last(payload),
             DecryptionStreams::DeoxysII256(s) => s.decrypt_last(payload),
+            DecryptionStreams::Aegis256(s) => s.decrypt_last(payload),
+            DecryptionStreams::ChaCha20Poly1305(s) => s.decrypt_last(payload),
+            DecryptionStreams::Ascon128a(s) => s.decrypt_last(payload),
+            DecryptionStreams::Custom(mut s) => s.decrypt(payload.into(), true),
+            DecryptionStreams::Derived(mut s) => s.decrypt(payload.into(), true),
+            DecryptionStreams::Be64(mut s) => s.decrypt(payload.into(), true),
         }
     }
 
@@ -334,6 +696,8 @@ impl DecryptionStreams {
     ///
     /// Valid AAD must be provided if you are using `HeaderVersion::V3` and above. It must be empty if the `HeaderVersion` is lower. Whatever you provided as AAD while encrypting must be present during decryption, or else you will receive an error.
     ///
+    /// `block_size` must match the size that the data was encrypted with, and it should be retrieved from the `Header` (`Header::block_size`).
+    ///
     /// This does not handle writing the header.
     ///
     /// # Examples
@@ -346,22 +710,38 @@ impl DecryptionStreams {
     /// let aad = Vec::new();
     ///
     /// let decrypt_stream = DecryptionStreams::initialize(key, &nonce, &Algorithm::XChaCha20Poly1305).unwrap();
-    /// decrypt_stream.decrypt_file(&mut input_file, &mut output_file, &aad);
+    /// decrypt_stream.decrypt_file(&mut input_file, &mut output_file, &aad, header.block_size);
     /// ```
     ///
     pub fn decrypt_file(
+        self,
+        reader: &mut impl Read,
+        writer: &mut impl Write,
+        aad: &[u8],
+        block_size: usize,
+    ) -> anyhow::Result<()> {
+        self.decrypt_file_observed(reader, writer, aad, block_size, &mut ())
+    }
+
+    /// This is the same as `decrypt_file()`, but `observer` is notified as each block is decrypted (see `crate::observer`)
+    pub fn decrypt_file_observed(
         mut self,
         reader: &mut impl Read,
         writer: &mut impl Write,
         aad: &[u8],
+        block_size: usize,
+        observer: &mut dyn StreamObserver,
     ) -> anyhow::Result<()> {
         #[cfg(feature = "visual")]
         let pb = crate::visual::create_spinner();
 
-        let mut buffer = vec![0u8; BLOCK_SIZE + 16].into_boxed_slice();
+        let mut tracker = Tracker::new(observer);
+        let block_len = block_size + 16 + self.block_overhead();
+        let mut buffer = vec![0u8; block_len].into_boxed_slice();
         loop {
-            let read_count = reader.read(&mut buffer)?;
-            if read_count == (BLOCK_SIZE + 16) {
+            let read_count = read_block(reader, &mut buffer)?;
+            tracker.start();
+            if read_count == block_len {
                 let payload = Payload {
                     aad,
                     msg: buffer.as_ref(),
@@ -374,10 +754,11 @@ impl DecryptionStreams {
                 writer
                     .write_all(&decrypted_data)
                     .context("Unable to write to the output")?;
+                tracker.finish(decrypted_data.len(), read_count, false);
 
                 decrypted_data.zeroize();
             } else {
-                // if we read something less than BLOCK_SIZE+16, and have hit the end of the file
+                // if we read something less than the block size+16, and have hit the end of the file
                 let payload = Payload {
                     aad,
                     msg: &buffer[..read_count],
@@ -390,6 +771,7 @@ impl DecryptionStreams {
                 writer
                     .write_all(&decrypted_data)
                     .context("Unable to write to the output file")?;
+                tracker.finish(decrypted_data.len(), read_count, true);
 
                 decrypted_data.zeroize();
                 break;
@@ -397,10 +779,286 @@ impl DecryptionStreams {
         }
 
         writer.flush().context("Unable to flush the output")?;
+        tracker.done();
 
         #[cfg(feature = "visual")]
         pb.finish_and_clear();
 
         Ok(())
     }
+
+    /// This is a variant of `decrypt_file()` for data that was encrypted with `encrypt_file_compressed()`
+    ///
+    /// Each block is decrypted, and then decompressed. Blocks that would decompress to more than `block_size` are rejected.
+    ///
+    /// `padded` must be set if the header contains `Compression::ZstdPadded`, so that the padding is stripped before decompressing.
+    ///
+    /// This should be used if the header contains `Compression::Zstd`/`Compression::ZstdPadded`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// let mut input_file = File::open("input.encrypted").unwrap();
+    /// let mut output_file = File::create("output").unwrap();
+    ///
+    /// let decrypt_stream = DecryptionStreams::initialize(key, &nonce, &Algorithm::XChaCha20Poly1305).unwrap();
+    /// decrypt_stream.decrypt_file_compressed(&mut input_file, &mut output_file, &aad, header.block_size, false);
+    /// ```
+    ///
+    pub fn decrypt_file_compressed(
+        self,
+        reader: &mut impl Read,
+        writer: &mut impl Write,
+        aad: &[u8],
+        block_size: usize,
+        padded: bool,
+    ) -> anyhow::Result<()> {
+        self.decrypt_file_compressed_observed(reader, writer, aad, block_size, padded, &mut ())
+    }
+
+    /// This is the same as `decrypt_file_compressed()`, but `observer` is notified as each block is decrypted (see `crate::observer`)
+    ///
+    /// The ciphertext lengths that are reported include each block's length prefix.
+    pub fn decrypt_file_compressed_observed(
+        mut self,
+        reader: &mut impl Read,
+        writer: &mut impl Write,
+        aad: &[u8],
+        block_size: usize,
+        padded: bool,
+        observer: &mut dyn StreamObserver,
+    ) -> anyhow::Result<()> {
+        #[cfg(feature = "visual")]
+        let pb = crate::visual::create_spinner();
+
+        let mut tracker = Tracker::new(observer);
+
+        let mut decompressor =
+            zstd::bulk::Decompressor::new().context("Unable to initialize the decompressor")?;
+        let max_block_len = padme(zstd::zstd_safe::compress_bound(block_size) as u64 + 4) as usize
+            + 16
+            + self.block_overhead();
+
+        let mut read_frame = || -> anyhow::Result<(Vec<u8>, bool)> {
+            let mut frame = [0u8; 4];
+            reader.read_exact(&mut frame).context(
+                "Unable to read the length of the next block (the file may be truncated)",
+            )?;
+            let frame = u32::from_le_bytes(frame);
+            let block_len = (frame & !LAST_BLOCK_FLAG) as usize;
+
+            if block_len > max_block_len {
+                return Err(anyhow::anyhow!("Compressed block is too large"));
+            }
+
+            let mut buffer = vec![0u8; block_len];
+            reader
+                .read_exact(&mut buffer)
+                .context("Unable to read from the reader")?;
+
+            Ok((buffer, frame & LAST_BLOCK_FLAG != 0))
+        };
+
+        let mut write_block = |mut compressed_data: Vec<u8>| -> anyhow::Result<usize> {
+            let unpadded_data = if padded {
+                unpad_block(&compressed_data)?
+            } else {
+                &compressed_data
+            };
+
+            let mut decrypted_data = decompressor
+                .decompress(unpadded_data, block_size)
+                .context("Unable to decompress the data")?;
+
+            writer
+                .write_all(&decrypted_data)
+                .context("Unable to write to the output")?;
+
+            let decrypted_len = decrypted_data.len();
+            compressed_data.zeroize();
+            decrypted_data.zeroize();
+            Ok(decrypted_len)
+        };
+
+        let buffer = loop {
+            let (buffer, last) = read_frame()?;
+            tracker.start();
+            if last {
+                break buffer;
+            }
+
+            let payload = Payload {
+                aad,
+                msg: buffer.as_ref(),
+            };
+
+            let compressed_data = self.decrypt_next(payload).map_err(|_| {
+                anyhow::anyhow!("Unable to decrypt the data. This means either: you're using the wrong key, this isn't an encrypted file, or the header has been tampered with.")
+            })?;
+
+            let decrypted_len = write_block(compressed_data)?;
+            tracker.finish(decrypted_len, buffer.len() + 4, false);
+        };
+
+        let payload = Payload {
+            aad,
+            msg: buffer.as_ref(),
+        };
+
+        let compressed_data = self.decrypt_last(payload).map_err(|_| {
+            anyhow::anyhow!("Unable to decrypt the final block of data. This means either: you're using the wrong key, this isn't an encrypted file, or the header has been tampered with.")
+        })?;
+
+        let decrypted_len = write_block(compressed_data)?;
+        tracker.finish(decrypted_len, buffer.len() + 4, true);
+
+        writer.flush().context("Unable to flush the output")?;
+        tracker.done();
+
+        #[cfg(feature = "visual")]
+        pb.finish_and_clear();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observer::ChunkProgress;
+    use crate::primitives::gen_nonce;
+    use std::io::Cursor;
+
+    const BLOCK_SIZE: usize = 64;
+
+    #[derive(Default)]
+    struct Recorder {
+        started: Vec<u64>,
+        finished: Vec<ChunkProgress>,
+        totals: Vec<ChunkProgress>,
+    }
+
+    impl StreamObserver for Recorder {
+        fn on_chunk_start(&mut self, index: u64) {
+            self.started.push(index);
+        }
+
+        fn on_chunk_finished(&mut self, progress: &ChunkProgress) {
+            self.finished.push(*progress);
+        }
+
+        fn on_finished(&mut self, totals: &ChunkProgress) {
+            self.totals.push(*totals);
+        }
+    }
+
+    impl Recorder {
+        fn check(&self, blocks: usize, plaintext_len: usize, ciphertext_len: usize) {
+            let indexes = (0..blocks as u64).collect::<Vec<_>>();
+            assert_eq!(self.started, indexes);
+            assert_eq!(
+                self.finished.iter().map(|p| p.index).collect::<Vec<_>>(),
+                indexes
+            );
+
+            // only the final block is marked as the last one, and the totals only ever grow
+            for (i, progress) in self.finished.iter().enumerate() {
+                assert_eq!(progress.last, i == blocks - 1);
+            }
+            assert!(self.finished.windows(2).all(|w| {
+                w[0].plaintext_bytes <= w[1].plaintext_bytes
+                    && w[0].ciphertext_bytes < w[1].ciphertext_bytes
+            }));
+
+            assert_eq!(
+                self.totals,
+                [ChunkProgress {
+                    index: blocks as u64 - 1,
+                    last: true,
+                    plaintext_bytes: plaintext_len as u64,
+                    ciphertext_bytes: ciphertext_len as u64,
+                }]
+            );
+            assert_eq!(self.finished.last(), self.totals.first());
+        }
+    }
+
+    #[test]
+    fn should_report_every_block_to_the_observer() {
+        let algorithm = Algorithm::XChaCha20Poly1305;
+        let nonce = gen_nonce(&algorithm, &Mode::StreamMode);
+
+        // a partial final block, an empty final block, and a single (empty) block
+        for (plaintext_len, blocks) in [(200, 4), (2 * BLOCK_SIZE, 3), (0, 1)] {
+            let plaintext = vec![7u8; plaintext_len];
+
+            let mut encrypted = Vec::new();
+            let mut observer = Recorder::default();
+            EncryptionStreams::initialize(Protected::new([1u8; 32]), &nonce, &algorithm)
+                .unwrap()
+                .encrypt_file_observed(
+                    &mut Cursor::new(plaintext.clone()),
+                    &mut encrypted,
+                    &[],
+                    BLOCK_SIZE,
+                    &mut observer,
+                )
+                .unwrap();
+            observer.check(blocks, plaintext_len, encrypted.len());
+
+            let mut decrypted = Vec::new();
+            let mut observer = Recorder::default();
+            DecryptionStreams::initialize(Protected::new([1u8; 32]), &nonce, &algorithm)
+                .unwrap()
+                .decrypt_file_observed(
+                    &mut Cursor::new(encrypted.clone()),
+                    &mut decrypted,
+                    &[],
+                    BLOCK_SIZE,
+                    &mut observer,
+                )
+                .unwrap();
+            observer.check(blocks, plaintext_len, encrypted.len());
+            assert_eq!(decrypted, plaintext);
+        }
+    }
+
+    #[test]
+    fn should_report_compressed_blocks_to_the_observer() {
+        let algorithm = Algorithm::XChaCha20Poly1305;
+        let nonce = gen_nonce(&algorithm, &Mode::StreamMode);
+        let plaintext = vec![7u8; 200];
+
+        let mut encrypted = Vec::new();
+        let mut observer = Recorder::default();
+        EncryptionStreams::initialize(Protected::new([1u8; 32]), &nonce, &algorithm)
+            .unwrap()
+            .encrypt_file_compressed_observed(
+                &mut Cursor::new(plaintext.clone()),
+                &mut encrypted,
+                &[],
+                BLOCK_SIZE,
+                3,
+                false,
+                &mut observer,
+            )
+            .unwrap();
+        observer.check(4, plaintext.len(), encrypted.len());
+
+        let mut decrypted = Vec::new();
+        let mut observer = Recorder::default();
+        DecryptionStreams::initialize(Protected::new([1u8; 32]), &nonce, &algorithm)
+            .unwrap()
+            .decrypt_file_compressed_observed(
+                &mut Cursor::new(encrypted.clone()),
+                &mut decrypted,
+                &[],
+                BLOCK_SIZE,
+                false,
+                &mut observer,
+            )
+            .unwrap();
+        observer.check(4, plaintext.len(), encrypted.len());
+        assert_eq!(decrypted, plaintext);
+    }
 }
@@ -0,0 +1,122 @@
+//! This module contains the key hierarchy that's used by V6 headers in stream mode
+//!
+//! Older headers encrypt the payload with the master key itself, and derive the MAC and digest keys from it with BLAKE3. Anyone holding a key that's derived from the master key is therefore only ever one step away from the payload.
+//!
+//! If `Header::subkeys` is set, the master key is never used directly. Three independent subkeys are derived from it with HKDF-SHA256, each with its own context:
+//! * the payload key, which the streams are initialized with
+//! * the header key, which encrypts what's stored within the header (such as the plaintext digest, see `crate::digest`)
+//! * the MAC key, which authenticates the ciphertext (see `crate::mac`)
+//!
+//! None of them can be derived from another, so the header key may be handed out to grant access to what's stored within the header, without granting access to the payload.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! let subkeys = Subkeys::derive(master_key, &header);
+//!
+//! let mac_key = header.mac.then_some(subkeys.mac);
+//! let streams = DecryptionStreams::initialize(subkeys.payload, &header.nonce, &header.header_type.algorithm).unwrap();
+//! ```
+
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use crate::header::Header;
+use crate::primitives::MASTER_KEY_LEN;
+use crate::protected::Protected;
+use crate::{digest, mac};
+
+/// These are used as the HKDF info for each subkey
+const PAYLOAD_CONTEXT: &[u8] = b"dexios payload key v1";
+const HEADER_CONTEXT: &[u8] = b"dexios header key v1";
+const MAC_CONTEXT: &[u8] = b"dexios mac key v1";
+
+pub struct Subkeys {
+    pub payload: Protected<[u8; MASTER_KEY_LEN]>,
+    pub header: Protected<[u8; 32]>,
+    pub mac: Protected<[u8; 32]>,
+}
+
+impl Subkeys {
+    /// This derives the subkeys from the master key, in the way that the header specifies
+    ///
+    /// If the header doesn't flag the key hierarchy, the payload key is the master key, and the others are derived with `digest::derive_key()` and `mac::derive_key()` (as they always have been).
+    #[must_use]
+    pub fn derive(master_key: Protected<[u8; MASTER_KEY_LEN]>, header: &Header) -> Self {
+        if !header.subkeys {
+            return Self {
+                header: digest::derive_key(&master_key),
+                mac: mac::derive_key(&master_key),
+                payload: master_key,
+            };
+        }
+
+        let hkdf = Hkdf::<Sha256>::new(None, master_key.expose());
+
+        Self {
+            payload: expand(&hkdf, PAYLOAD_CONTEXT),
+            header: expand(&hkdf, HEADER_CONTEXT),
+            mac: expand(&hkdf, MAC_CONTEXT),
+        }
+    }
+}
+
+fn expand(hkdf: &Hkdf<Sha256>, context: &[u8]) -> Protected<[u8; 32]> {
+    let mut key = [0u8; 32];
+    // 32 bytes is well within HKDF-SHA256's limit, so this can't fail
+    hkdf.expand(context, &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    Protected::new(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::tests::header;
+    use crate::header::HeaderVersion;
+    use crate::primitives::Algorithm;
+
+    fn master_key() -> Protected<[u8; MASTER_KEY_LEN]> {
+        Protected::new([7u8; MASTER_KEY_LEN])
+    }
+
+    #[test]
+    fn should_derive_independent_subkeys() {
+        let mut header = header(HeaderVersion::V6, Algorithm::XChaCha20Poly1305, Vec::new());
+        header.subkeys = true;
+
+        let master_key = master_key();
+        let subkeys = Subkeys::derive(master_key.clone(), &header);
+        let keys = [
+            subkeys.payload.expose(),
+            subkeys.header.expose(),
+            subkeys.mac.expose(),
+            master_key.expose(),
+        ];
+        for (i, key) in keys.iter().enumerate() {
+            assert!(keys[i + 1..].iter().all(|other| other != key));
+        }
+
+        // they're deterministic, but unique to the master key
+        let again = Subkeys::derive(master_key, &header);
+        assert_eq!(subkeys.payload.expose(), again.payload.expose());
+        let other = Subkeys::derive(Protected::new([8u8; MASTER_KEY_LEN]), &header);
+        assert_ne!(subkeys.payload.expose(), other.payload.expose());
+    }
+
+    #[test]
+    fn should_use_the_master_key_without_the_flag() {
+        let header = header(HeaderVersion::V5, Algorithm::XChaCha20Poly1305, Vec::new());
+
+        let subkeys = Subkeys::derive(master_key(), &header);
+        assert_eq!(subkeys.payload.expose(), master_key().expose());
+        assert_eq!(
+            subkeys.header.expose(),
+            digest::derive_key(&master_key()).expose()
+        );
+        assert_eq!(
+            subkeys.mac.expose(),
+            mac::derive_key(&master_key()).expose()
+        );
+    }
+}
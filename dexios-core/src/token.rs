@@ -0,0 +1,97 @@
+//! This module contains token keyslots, which wrap the master key with a key that's held on a hardware token (such as a PKCS#11 smartcard or HSM)
+//!
+//! The keyslot's random salt is sent to the token as a challenge, and the wrapping key is derived from the token's keyed response (e.g. an HMAC made with a secret key that can't be exported). No password is involved, so the token alone unlocks the keyslot.
+//!
+//! A token is registered process-wide for decryption, so that `decrypt_master_key()` can try any token keyslots before hashing the raw key.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! // creating a new keyslot
+//! let token_key = TokenKey::generate(&mut responder).unwrap();
+//!
+//! // unlocking it later on
+//! register(Box::new(responder));
+//! let master_key = decrypt_master_key(raw_key, &header).unwrap();
+//! ```
+
+use std::sync::Mutex;
+
+use crate::challenge::Responder;
+use crate::cipher::Ciphers;
+use crate::header::Keyslot;
+use crate::key::vec_to_arr;
+use crate::primitives::{gen_salt, Algorithm, MASTER_KEY_LEN, SALT_LEN};
+use crate::protected::Protected;
+
+/// This is used to derive the wrapping key from the token's response
+const WRAPPING_CONTEXT: &str = "dexios token keyslot v1";
+
+static TOKEN: Mutex<Option<Box<dyn Responder>>> = Mutex::new(None);
+
+/// A wrapping key for a new token keyslot, along with the salt that the token was challenged with
+pub struct TokenKey {
+    pub salt: [u8; SALT_LEN],
+    pub key: Protected<[u8; 32]>,
+}
+
+impl TokenKey {
+    /// This challenges the token with a fresh salt
+    pub fn generate(responder: &mut dyn Responder) -> anyhow::Result<Self> {
+        let salt = gen_salt();
+        let key = wrapping_key(responder, &salt)?;
+
+        Ok(Self { salt, key })
+    }
+}
+
+/// This derives a token keyslot's wrapping key from the token's response to its salt
+pub fn wrapping_key(
+    responder: &mut dyn Responder,
+    salt: &[u8; SALT_LEN],
+) -> anyhow::Result<Protected<[u8; 32]>> {
+    let response = responder.respond(salt)?;
+
+    let mut hasher = blake3::Hasher::new_derive_key(WRAPPING_CONTEXT);
+    hasher.update(response.expose());
+    hasher.update(salt);
+
+    let key = Protected::new(*hasher.finalize().as_bytes());
+    hasher.reset();
+    Ok(key)
+}
+
+/// This registers the token that's used for unlocking token keyslots, replacing any previous registration
+pub fn register(responder: Box<dyn Responder>) {
+    *TOKEN
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner) = Some(responder);
+}
+
+/// This tries to unlock each of the token keyslots with the registered token
+///
+/// It returns `None` if no token was registered, or if none of the keyslots belong to it.
+pub(crate) fn decrypt_master_key(
+    keyslots: &[Keyslot],
+    algorithm: &Algorithm,
+) -> anyhow::Result<Option<Protected<[u8; MASTER_KEY_LEN]>>> {
+    let mut guard = TOKEN
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+    let responder = match guard.as_mut() {
+        Some(responder) => responder,
+        None => return Ok(None),
+    };
+
+    for keyslot in keyslots.iter().filter(|keyslot| keyslot.is_token()) {
+        let key = wrapping_key(responder.as_mut(), &keyslot.salt)?;
+
+        let cipher = Ciphers::initialize(key, algorithm)?;
+        if let Ok(master_key) = cipher.decrypt(&keyslot.nonce, keyslot.encrypted_key.as_slice()) {
+            return Ok(Some(Protected::new(vec_to_arr(master_key))));
+        }
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,207 @@
+//! This provides functionality for comparing two directories, such as an unpacked archive and the directory that it was packed from.
+//!
+//! Files are compared by their `BLAKE3` hash, and any files/directories that are missing, extra, or have different contents are reported.
+
+use std::collections::BTreeMap;
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use core::primitives::BLOCK_SIZE;
+
+use crate::storage::{self, Entry, Storage};
+
+#[derive(Debug)]
+pub enum Error {
+    ReadData,
+    Storage(storage::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::ReadData => f.write_str("Unable to read data"),
+            Error::Storage(inner) => write!(f, "Storage error: {inner}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub struct Request {
+    pub original_dir_path: PathBuf,
+    pub target_dir_path: PathBuf,
+}
+
+/// All paths are relative to the directories that were compared
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// These exist in the original directory, but not in the target
+    pub missing: Vec<PathBuf>,
+    /// These exist in the target directory, but not in the original
+    pub extra: Vec<PathBuf>,
+    /// These exist in both, but their contents (or types) differ
+    pub different: Vec<PathBuf>,
+}
+
+impl Report {
+    #[must_use]
+    pub fn is_identical(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.different.is_empty()
+    }
+}
+
+fn index_dir<RW>(
+    stor: &Arc<impl Storage<RW>>,
+    dir_path: &Path,
+) -> Result<BTreeMap<PathBuf, Entry<RW>>, Error>
+where
+    RW: Read + Write + Seek,
+{
+    let dir = stor.read_file(dir_path).map_err(Error::Storage)?;
+
+    let entries = stor
+        .read_dir(&dir)
+        .map_err(Error::Storage)?
+        .into_iter()
+        .filter_map(|entry| {
+            let relative_path = entry.path().strip_prefix(dir_path).ok()?.to_path_buf();
+
+            // the directory itself is included, but there's nothing to compare it with
+            if relative_path.as_os_str().is_empty() {
+                None
+            } else {
+                Some((relative_path, entry))
+            }
+        })
+        .collect();
+
+    Ok(entries)
+}
+
+fn hash_file<RW>(entry: &Entry<RW>) -> Result<blake3::Hash, Error>
+where
+    RW: Read + Write + Seek,
+{
+    let mut reader = entry.try_reader().map_err(Error::Storage)?.borrow_mut();
+    reader.rewind().map_err(|_| Error::ReadData)?;
+
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0u8; BLOCK_SIZE].into_boxed_slice();
+    loop {
+        let read_count = reader.read(&mut buffer).map_err(|_| Error::ReadData)?;
+        if read_count == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read_count]);
+    }
+
+    Ok(hasher.finalize())
+}
+
+fn is_same<RW>(original: &Entry<RW>, target: &Entry<RW>) -> Result<bool, Error>
+where
+    RW: Read + Write + Seek,
+{
+    match (original.is_dir(), target.is_dir()) {
+        (true, true) => Ok(true),
+        (false, false) => Ok(hash_file(original)? == hash_file(target)?),
+        _ => Ok(false),
+    }
+}
+
+pub fn execute<RW>(stor: Arc<impl Storage<RW>>, req: Request) -> Result<Report, Error>
+where
+    RW: Read + Write + Seek,
+{
+    // 1. Index both directories.
+    let original = index_dir(&stor, &req.original_dir_path)?;
+    let mut target = index_dir(&stor, &req.target_dir_path)?;
+
+    // 2. Compare everything from the original directory against the target.
+    let mut report = Report::default();
+    for (path, original_entry) in &original {
+        match target.remove(path) {
+            None => report.missing.push(path.clone()),
+            Some(target_entry) => {
+                if !is_same(original_entry, &target_entry)? {
+                    report.different.push(path.clone());
+                }
+            }
+        }
+    }
+
+    // 3. Anything that's left over only exists in the target.
+    report.extra = target.into_keys().collect();
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::storage::{IMFile, InMemoryFile, InMemoryStorage};
+
+    fn add_text_file(stor: &InMemoryStorage, path: &str, content: &str) {
+        stor.mut_files().insert(
+            PathBuf::from(path),
+            IMFile::File(InMemoryFile {
+                buf: content.as_bytes().to_vec(),
+                len: content.len(),
+            }),
+        );
+    }
+
+    fn add_unpacked_bar_foo_folder(stor: &InMemoryStorage) {
+        stor.mut_files()
+            .insert(PathBuf::from("out/bar/"), IMFile::Dir);
+        add_text_file(stor, "out/bar/hello.txt", "hello");
+        add_text_file(stor, "out/bar/world.txt", "world");
+        stor.mut_files()
+            .insert(PathBuf::from("out/bar/foo/"), IMFile::Dir);
+        add_text_file(stor, "out/bar/foo/hello.txt", "hello");
+        add_text_file(stor, "out/bar/foo/world.txt", "world");
+    }
+
+    fn compare(stor: InMemoryStorage) -> Report {
+        let req = Request {
+            original_dir_path: PathBuf::from("bar/"),
+            target_dir_path: PathBuf::from("out/bar/"),
+        };
+
+        match execute(Arc::new(stor), req) {
+            Ok(report) => report,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn should_report_identical_directories() {
+        let stor = InMemoryStorage::default();
+        stor.add_bar_foo_folder();
+        add_unpacked_bar_foo_folder(&stor);
+
+        assert!(compare(stor).is_identical());
+    }
+
+    #[test]
+    fn should_report_missing_extra_and_different_files() {
+        let stor = InMemoryStorage::default();
+        stor.add_bar_foo_folder();
+        add_unpacked_bar_foo_folder(&stor);
+
+        stor.mut_files().remove(Path::new("out/bar/foo/world.txt"));
+        add_text_file(&stor, "out/bar/extra.txt", "extra");
+        add_text_file(&stor, "out/bar/hello.txt", "hellp");
+
+        assert_eq!(
+            compare(stor),
+            Report {
+                missing: vec![PathBuf::from("foo/world.txt")],
+                extra: vec![PathBuf::from("extra.txt")],
+                different: vec![PathBuf::from("hello.txt")],
+            }
+        );
+    }
+}
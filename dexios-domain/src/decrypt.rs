@@ -1,25 +1,42 @@
 //! This provides functionality for decryption that adheres to the Dexios format.
 
 use std::cell::RefCell;
-use std::io::{Read, Seek, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
 
 use core::cipher::Ciphers;
-use core::header::{Header, HeaderType};
-use core::key::decrypt_master_key;
-use core::primitives::Mode;
+use core::digest::{self, DigestWriter};
+use core::file_info::{self, FileInfo};
+use core::header::{Header, HeaderType, FILE_INFO_FIELD, PLACEHOLDER_FIELD};
+use core::key::{decrypt_master_key, decrypt_master_key_with_identity};
+use core::mac;
+use core::padding::UnpaddingWriter;
+use core::primitives::{Compression, Mode, Padding, StreamCounter, MASTER_KEY_LEN};
 use core::protected::Protected;
+use core::recipient::RecipientSecretKey;
+use core::seekable::{ChunkTable, SeekableReader};
 use core::stream::DecryptionStreams;
+use core::subkeys::Subkeys;
+
+use crate::header::backup::{self, TrimmedReader};
 
 #[derive(Debug)]
 pub enum Error {
     InitializeChiphers,
     InitializeStreams,
     DeserializeHeader,
+    StrippedHeader,
     ReadEncryptedData,
     DecryptMasterKey,
     DecryptData,
     WriteData,
     RewindDataReader,
+    VerifyMac,
+    DecryptDigest,
+    VerifyDigest,
+    ReadChunkTable,
+    NotSeekable,
+    RangeOutOfBounds,
+    DecryptFileInfo,
 }
 
 impl std::fmt::Display for Error {
@@ -28,11 +45,27 @@ impl std::fmt::Display for Error {
             Error::InitializeChiphers => f.write_str("Cannot initialize chiphers"),
             Error::InitializeStreams => f.write_str("Cannot initialize streams"),
             Error::DeserializeHeader => f.write_str("Cannot deserialize header"),
+            Error::StrippedHeader => f.write_str(
+                "The header has been stripped from the file, so the header that was dumped must be provided",
+            ),
             Error::ReadEncryptedData => f.write_str("Unable to read encrypted data"),
             Error::DecryptMasterKey => f.write_str("Cannot decrypt master key"),
             Error::DecryptData => f.write_str("Unable to decrypt data"),
             Error::WriteData => f.write_str("Unable to write data"),
             Error::RewindDataReader => f.write_str("Unable to rewind the reader"),
+            Error::VerifyMac => {
+                f.write_str("The MAC doesn't match - the file has been truncated or modified")
+            }
+            Error::DecryptDigest => f.write_str("Unable to decrypt the plaintext digest"),
+            Error::VerifyDigest => f.write_str(
+                "The decrypted data doesn't match the digest that was stored when it was encrypted",
+            ),
+            Error::ReadChunkTable => f.write_str("Unable to read the chunk table"),
+            Error::NotSeekable => f.write_str(
+                "Part of the file can't be decrypted on its own, as it wasn't encrypted in the seekable format",
+            ),
+            Error::RangeOutOfBounds => f.write_str("The range starts beyond the end of the file"),
+            Error::DecryptFileInfo => f.write_str("Unable to decrypt the file info"),
         }
     }
 }
@@ -40,73 +73,92 @@ impl std::fmt::Display for Error {
 impl std::error::Error for Error {}
 
 pub type OnDecryptedHeaderFn = Box<dyn FnOnce(&HeaderType)>;
+pub type OnFileInfoFn = Box<dyn FnOnce(FileInfo)>;
 
 pub struct Request<'a, R, W>
 where
     R: Read + Seek,
-    W: Write + Seek,
+    W: Write,
 {
     pub header_reader: Option<&'a RefCell<R>>,
     pub reader: &'a RefCell<R>,
     pub writer: &'a RefCell<W>,
     pub raw_key: Protected<Vec<u8>>,
+    /// If this is set, it's used as the master key (e.g. from a mnemonic, see `key::mnemonic`), and the keyslots aren't used at all
+    pub master_key: Option<Protected<[u8; MASTER_KEY_LEN]>>,
+    /// If this is set, the master key is retrieved from a recipient keyslot instead, and `raw_key` is ignored
+    pub identity: Option<RecipientSecretKey>,
     pub on_decrypted_header: Option<OnDecryptedHeaderFn>,
+    /// If this is set and the header contains file info (see `core::file_info`), it's called with it before any data is decrypted
+    pub on_file_info: Option<OnFileInfoFn>,
 }
 
-pub fn execute<R, W>(req: Request<'_, R, W>) -> Result<(), Error>
-where
-    R: Read + Seek,
-    W: Write + Seek,
-{
-    let (header, aad) = match req.header_reader {
-        Some(header_reader) => {
-            let (header, aad) = Header::deserialize(&mut *header_reader.borrow_mut())
-                .map_err(|_| Error::DeserializeHeader)?;
+fn get_master_key(
+    raw_key: Protected<Vec<u8>>,
+    master_key: Option<Protected<[u8; MASTER_KEY_LEN]>>,
+    identity: Option<&RecipientSecretKey>,
+    header: &Header,
+) -> Result<Protected<[u8; MASTER_KEY_LEN]>, Error> {
+    if let Some(master_key) = master_key {
+        return Ok(master_key);
+    }
 
-            // Try reading an empty header from the content.
-            #[allow(clippy::cast_possible_truncation)]
-            let mut header_bytes = vec![0u8; header.get_size() as usize];
+    match identity {
+        Some(identity) => decrypt_master_key_with_identity(identity, header),
+        None => decrypt_master_key(raw_key, header),
+    }
+    .map_err(|_| Error::DecryptMasterKey)
+}
 
-            req.reader
-                .borrow_mut()
-                .read_exact(&mut header_bytes)
-                .or_else(|e| {
-                    if e.kind() == std::io::ErrorKind::UnexpectedEof {
-                        Ok(())
-                    } else {
-                        Err(e)
-                    }
-                })
-                .map_err(|_| Error::ReadEncryptedData)?;
+// the file info is only decrypted if something is waiting for it
+fn report_file_info(
+    header: &Header,
+    header_key: &Protected<[u8; 32]>,
+    on_file_info: Option<OnFileInfoFn>,
+) -> Result<(), Error> {
+    let (Some(cb), Some(field)) = (
+        on_file_info,
+        header.fields.iter().find(|f| f.tag == FILE_INFO_FIELD),
+    ) else {
+        return Ok(());
+    };
 
-            if !header_bytes.into_iter().all(|b| b == 0) {
-                // And return the cursor position to the start if it wasn't found
-                req.reader
-                    .borrow_mut()
-                    .rewind()
-                    .map_err(|_| Error::RewindDataReader)?;
-            }
+    let info = file_info::decrypt(header_key, &header.header_type.algorithm, &field.value)
+        .map_err(|_| Error::DecryptFileInfo)?;
+    cb(info);
 
-            (header, aad)
-        }
-        None => Header::deserialize(&mut *req.reader.borrow_mut())
-            .map_err(|_| Error::DeserializeHeader)?,
-    };
+    Ok(())
+}
+
+pub fn execute<R, W>(req: Request<'_, R, W>) -> Result<(), Error>
+where
+    R: Read + Seek,
+    W: Write,
+{
+    let (header, aad) = read_header(req.header_reader, req.reader)?;
 
     if let Some(cb) = req.on_decrypted_header {
         cb(&header.header_type);
     }
 
+    // a copy of the header at the end of the file isn't part of the encrypted data
+    let mut reader = req.reader.borrow_mut();
+    let mut reader =
+        TrimmedReader::new(&mut *reader, &header).map_err(|_| Error::ReadEncryptedData)?;
+
     match header.header_type.mode {
         Mode::MemoryMode => {
             let mut encrypted_data = Vec::new();
-            req.reader
-                .borrow_mut()
+            reader
                 .read_to_end(&mut encrypted_data)
                 .map_err(|_| Error::ReadEncryptedData)?;
 
             let master_key =
-                decrypt_master_key(req.raw_key, &header).map_err(|_| Error::DecryptMasterKey)?;
+                get_master_key(req.raw_key, req.master_key, req.identity.as_ref(), &header)?;
+            if req.on_file_info.is_some() {
+                let header_key = Subkeys::derive(master_key.clone(), &header).header;
+                report_file_info(&header, &header_key, req.on_file_info)?;
+            }
 
             let ciphers = Ciphers::initialize(master_key, &header.header_type.algorithm)
                 .map_err(|_| Error::InitializeChiphers)?;
@@ -125,35 +177,293 @@ where
                 .write_all(&decrypted_bytes)
                 .map_err(|_| Error::WriteData)?;
         }
-        Mode::StreamMode => {
+        Mode::StreamMode | Mode::DerivedStreamMode => {
             let master_key =
-                decrypt_master_key(req.raw_key, &header).map_err(|_| Error::DecryptMasterKey)?;
+                get_master_key(req.raw_key, req.master_key, req.identity.as_ref(), &header)?;
+            let subkeys = Subkeys::derive(master_key, &header);
+            report_file_info(&header, &subkeys.header, req.on_file_info)?;
+            let mac_key = header.mac.then_some(subkeys.mac);
+            let expected_digest = header
+                .digest
+                .as_ref()
+                .map(|encrypted_digest| {
+                    digest::decrypt(
+                        subkeys.header,
+                        &header.header_type.algorithm,
+                        encrypted_digest,
+                    )
+                })
+                .transpose()
+                .map_err(|_| Error::DecryptDigest)?;
 
-            let streams = DecryptionStreams::initialize(
-                master_key,
-                &header.nonce,
-                &header.header_type.algorithm,
-            )
+            let streams = if header.header_type.mode == Mode::DerivedStreamMode {
+                DecryptionStreams::initialize_derived(
+                    subkeys.payload,
+                    &header.nonce,
+                    &header.header_type.algorithm,
+                )
+            } else if header.counter == StreamCounter::Be64 {
+                DecryptionStreams::initialize_be64(
+                    subkeys.payload,
+                    &header.nonce,
+                    &header.header_type.algorithm,
+                )
+            } else {
+                DecryptionStreams::initialize(
+                    subkeys.payload,
+                    &header.nonce,
+                    &header.header_type.algorithm,
+                )
+            }
             .map_err(|_| Error::InitializeStreams)?;
 
-            streams
-                .decrypt_file(
-                    &mut *req.reader.borrow_mut(),
-                    &mut *req.writer.borrow_mut(),
-                    &aad,
-                )
-                .map_err(|_| Error::DecryptData)?;
+            decrypt_verified(
+                streams,
+                &header,
+                &mut reader,
+                &mut *req.writer.borrow_mut(),
+                &aad,
+                mac_key,
+                expected_digest,
+            )?;
         }
     }
 
     Ok(())
 }
 
+pub struct RangeRequest<'a, R, W>
+where
+    R: Read + Seek,
+    W: Write,
+{
+    pub header_reader: Option<&'a RefCell<R>>,
+    pub reader: &'a RefCell<R>,
+    pub writer: &'a RefCell<W>,
+    pub raw_key: Protected<Vec<u8>>,
+    /// If this is set, it's used as the master key (e.g. from a mnemonic, see `key::mnemonic`), and the keyslots aren't used at all
+    pub master_key: Option<Protected<[u8; MASTER_KEY_LEN]>>,
+    /// If this is set, the master key is retrieved from a recipient keyslot instead, and `raw_key` is ignored
+    pub identity: Option<RecipientSecretKey>,
+    /// This is the offset within the plaintext that decryption starts from
+    pub start: u64,
+    /// This is the maximum amount of plaintext to decrypt, or everything after `start` if it isn't set
+    pub len: Option<u64>,
+}
+
+/// This decrypts part of the plaintext, without decrypting anything before it
+///
+/// The data must have been encrypted in the seekable format (see `core::seekable`). Only the blocks that overlap the range are read.
+///
+/// Neither the MAC nor the plaintext digest are verified, as that would require reading everything. Each block is still authenticated on its own.
+pub fn execute_range<R, W>(req: RangeRequest<'_, R, W>) -> Result<(), Error>
+where
+    R: Read + Seek,
+    W: Write,
+{
+    let (header, aad) = read_header(req.header_reader, req.reader)?;
+    if !header.seekable {
+        return Err(Error::NotSeekable);
+    }
+
+    let master_key = get_master_key(req.raw_key, req.master_key, req.identity.as_ref(), &header)?;
+
+    let mut reader = req.reader.borrow_mut();
+    let mut reader =
+        TrimmedReader::new(&mut *reader, &header).map_err(|_| Error::ReadEncryptedData)?;
+    let mut reader = SeekableReader::new(&mut reader, &header, aad, master_key)
+        .map_err(|_| Error::ReadChunkTable)?;
+    if req.start > reader.plaintext_len() {
+        return Err(Error::RangeOutOfBounds);
+    }
+
+    reader
+        .seek(SeekFrom::Start(req.start))
+        .map_err(|_| Error::ReadEncryptedData)?;
+
+    let mut writer = req.writer.borrow_mut();
+    let copied = match req.len {
+        Some(len) => std::io::copy(&mut reader.take(len), &mut *writer),
+        None => std::io::copy(&mut reader, &mut *writer),
+    };
+
+    // the reader only fails if a block can't be decrypted, otherwise it's the writer
+    copied.map_err(|e| match e.kind() {
+        std::io::ErrorKind::InvalidData | std::io::ErrorKind::UnexpectedEof => Error::DecryptData,
+        _ => Error::WriteData,
+    })?;
+
+    Ok(())
+}
+
+// stripping a header leaves zeroes in its place (see `header::strip`), so that's reported separately
+fn deserialize_embedded(reader: &mut (impl Read + Seek)) -> Result<(Header, Vec<u8>), Error> {
+    Header::deserialize(reader).map_err(|_| {
+        let mut tag = [0u8; 6];
+        let stripped = reader.rewind().is_ok()
+            && reader.read_exact(&mut tag).is_ok()
+            && tag.iter().all(|b| *b == 0);
+        if stripped {
+            Error::StrippedHeader
+        } else {
+            Error::DeserializeHeader
+        }
+    })
+}
+
+// the header is read from the header reader if there is one (skipping an empty header in the data, if it has one), otherwise it's read from the start of the data
+fn read_header<R>(
+    header_reader: Option<&RefCell<R>>,
+    reader: &RefCell<R>,
+) -> Result<(Header, Vec<u8>), Error>
+where
+    R: Read + Seek,
+{
+    let (header, aad) = if let Some(header_reader) = header_reader {
+        let (header, aad) = Header::deserialize(&mut *header_reader.borrow_mut())
+            .map_err(|_| Error::DeserializeHeader)?;
+
+        // Try reading an empty header from the content.
+        #[allow(clippy::cast_possible_truncation)]
+        let mut header_bytes = vec![0u8; header.get_size() as usize];
+
+        // the content starts with random bytes in place of the header, so they're always skipped
+        if header.has_field(PLACEHOLDER_FIELD) {
+            reader
+                .borrow_mut()
+                .read_exact(&mut header_bytes)
+                .map_err(|_| Error::ReadEncryptedData)?;
+            return Ok((header, aad));
+        }
+
+        // a short read may leave the buffer untouched (it does for `Cursor`), so only a full
+        // read of zeros counts as an empty header
+        let has_empty_header = reader
+            .borrow_mut()
+            .read_exact(&mut header_bytes)
+            .map(|()| true)
+            .or_else(|e| {
+                if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                    Ok(false)
+                } else {
+                    Err(e)
+                }
+            })
+            .map_err(|_| Error::ReadEncryptedData)?
+            && header_bytes.into_iter().all(|b| b == 0);
+
+        if !has_empty_header {
+            // And return the cursor position to the start if it wasn't found
+            reader
+                .borrow_mut()
+                .rewind()
+                .map_err(|_| Error::RewindDataReader)?;
+        }
+
+        (header, aad)
+    } else {
+        let mut reader = reader.borrow_mut();
+        match deserialize_embedded(&mut *reader) {
+            // the header may be damaged, in which case its copy is used instead (if it has one)
+            Err(Error::DeserializeHeader) => {
+                let backup = backup::fallback(&mut *reader)
+                    .map_err(|_| Error::ReadEncryptedData)?
+                    .ok_or(Error::DeserializeHeader)?;
+                reader
+                    .seek(SeekFrom::Start(backup.header.get_size()))
+                    .map_err(|_| Error::ReadEncryptedData)?;
+                (backup.header, backup.aad)
+            }
+            result => result?,
+        }
+    };
+
+    Ok((header, aad))
+}
+
+/// This checks the MAC footer (if there is one) before anything is decrypted, and then checks the output against the plaintext digest (if there is one)
+///
+/// Only the ciphertext is decrypted, so the chunk table (if there is one) and the MAC are excluded.
+fn decrypt_verified(
+    streams: DecryptionStreams,
+    header: &Header,
+    reader: &mut (impl Read + Seek),
+    writer: &mut impl Write,
+    aad: &[u8],
+    mac_key: Option<Protected<[u8; 32]>>,
+    expected_digest: Option<blake3::Hash>,
+) -> Result<(), Error> {
+    let decrypt = |mut writer: &mut dyn Write| {
+        let len = mac_key
+            .map(|key| mac::verify(&key, aad, &mut *reader))
+            .transpose()
+            .map_err(|_| Error::VerifyMac)?;
+
+        let len = if header.seekable {
+            let table = ChunkTable::read(&mut *reader, len).map_err(|_| Error::ReadChunkTable)?;
+            Some(table.ciphertext_len())
+        } else {
+            len
+        };
+
+        match len {
+            Some(len) => decrypt_stream(streams, header, &mut reader.take(len), &mut writer, aad),
+            None => decrypt_stream(streams, header, reader, &mut writer, aad),
+        }
+    };
+
+    match expected_digest {
+        // the output can only be checked once it's all been written
+        Some(expected_digest) => {
+            let mut writer = DigestWriter::new(writer);
+            decrypt(&mut writer)?;
+            if writer.finalize() != expected_digest {
+                return Err(Error::VerifyDigest);
+            }
+            Ok(())
+        }
+        None => decrypt(writer),
+    }
+}
+
+/// This decrypts everything from `reader` with the streams, according to the header's compression and padding
+///
+/// Compressed streams frame each block, so they need decrypting differently, and padding needs stripping once decrypted.
+pub(crate) fn decrypt_stream(
+    streams: DecryptionStreams,
+    header: &Header,
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    aad: &[u8],
+) -> Result<(), Error> {
+    match (header.compression, header.padding) {
+        (Compression::None, Padding::None) => {
+            streams.decrypt_file(reader, writer, aad, header.block_size)
+        }
+        (Compression::None, Padding::Padme) => {
+            let mut writer = UnpaddingWriter::new(writer);
+            streams
+                .decrypt_file(reader, &mut writer, aad, header.block_size)
+                .and_then(|()| writer.finish())
+        }
+        (Compression::Zstd(_), _) => {
+            streams.decrypt_file_compressed(reader, writer, aad, header.block_size, false)
+        }
+        (Compression::ZstdPadded(_), _) => {
+            streams.decrypt_file_compressed(reader, writer, aad, header.block_size, true)
+        }
+    }
+    .map_err(|_| Error::DecryptData)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::io::Cursor;
 
+    use core::header::{Field, HashingAlgorithm, HeaderVersion, Metadata, CRITICAL_FIELD};
+    use core::primitives::Algorithm;
+
     use crate::encrypt::tests::{
         PASSWORD, V4_ENCRYPTED_CONTENT, V5_ENCRYPTED_CONTENT, V5_ENCRYPTED_DETACHED_CONTENT,
         V5_ENCRYPTED_DETACHED_HEADER, V5_ENCRYPTED_FULL_DETACHED_CONTENT,
@@ -172,11 +482,14 @@ mod tests {
             reader: &input_cur,
             writer: &output_cur,
             raw_key: Protected::new(PASSWORD.to_vec()),
+            master_key: None,
+            identity: None,
             on_decrypted_header: None,
+            on_file_info: None,
         };
 
         match execute(req) {
-            Ok(_) => {
+            Ok(()) => {
                 assert_eq!(output_content, "Hello world".as_bytes().to_vec());
             }
             _ => unreachable!(),
@@ -196,11 +509,14 @@ mod tests {
             reader: &input_cur,
             writer: &output_cur,
             raw_key: Protected::new(PASSWORD.to_vec()),
+            master_key: None,
+            identity: None,
             on_decrypted_header: None,
+            on_file_info: None,
         };
 
         match execute(req) {
-            Ok(_) => {
+            Ok(()) => {
                 assert_eq!(output_content, "Hello world".as_bytes().to_vec());
             }
             _ => unreachable!(),
@@ -223,17 +539,149 @@ mod tests {
             reader: &input_cur,
             writer: &output_cur,
             raw_key: Protected::new(PASSWORD.to_vec()),
+            master_key: None,
+            identity: None,
             on_decrypted_header: None,
+            on_file_info: None,
         };
 
         match execute(req) {
-            Ok(_) => {
+            Ok(()) => {
                 assert_eq!(output_content, "Hello world".as_bytes().to_vec());
             }
             _ => unreachable!(),
         }
     }
 
+    #[test]
+    fn should_need_the_header_of_stripped_content() {
+        let input_cur = RefCell::new(Cursor::new(V5_ENCRYPTED_DETACHED_CONTENT.to_vec()));
+        let output_cur = RefCell::new(Cursor::new(Vec::new()));
+
+        let req = Request {
+            header_reader: None,
+            reader: &input_cur,
+            writer: &output_cur,
+            raw_key: Protected::new(PASSWORD.to_vec()),
+            master_key: None,
+            identity: None,
+            on_decrypted_header: None,
+            on_file_info: None,
+        };
+
+        assert!(matches!(execute(req), Err(Error::StrippedHeader)));
+    }
+
+    #[test]
+    fn should_report_file_info() {
+        let info = FileInfo {
+            name: "hello.txt".to_string(),
+            modified: Some(std::time::Duration::new(1_700_000_000, 5)),
+            mode: Some(0o640),
+        };
+
+        let encrypted_cur = RefCell::new(Cursor::new(Vec::new()));
+        crate::encrypt::execute(crate::encrypt::Request {
+            file_info: Some(info.clone()),
+            ..crate::encrypt::tests::request(
+                &RefCell::new(Cursor::new(b"Hello world".to_vec())),
+                &encrypted_cur,
+            )
+        })
+        .unwrap();
+        let encrypted_content = encrypted_cur.into_inner().into_inner();
+
+        // the name isn't visible without the key
+        let (header, _) = Header::deserialize(&mut Cursor::new(&encrypted_content)).unwrap();
+        assert!(header.has_field(FILE_INFO_FIELD));
+        assert!(!encrypted_content
+            .windows(info.name.len())
+            .any(|w| w == info.name.as_bytes()));
+
+        let reported = std::rc::Rc::new(RefCell::new(None));
+        let output_cur = RefCell::new(Cursor::new(Vec::new()));
+        execute(Request {
+            header_reader: None,
+            reader: &RefCell::new(Cursor::new(encrypted_content)),
+            writer: &output_cur,
+            raw_key: Protected::new(PASSWORD.to_vec()),
+            master_key: None,
+            identity: None,
+            on_decrypted_header: None,
+            on_file_info: Some(Box::new({
+                let reported = reported.clone();
+                move |info| *reported.borrow_mut() = Some(info)
+            })),
+        })
+        .unwrap();
+
+        assert_eq!(reported.take(), Some(info));
+        assert_eq!(
+            output_cur.into_inner().into_inner(),
+            b"Hello world".to_vec()
+        );
+    }
+
+    #[test]
+    fn should_skip_placeholder_of_detached_header() {
+        let input_cur = RefCell::new(Cursor::new(b"Hello world".to_vec()));
+        let encrypted_cur = RefCell::new(Cursor::new(Vec::new()));
+        let header_cur = RefCell::new(Cursor::new(Vec::new()));
+
+        crate::encrypt::execute(crate::encrypt::Request {
+            header_writer: Some(&header_cur),
+            mac: true,
+            digest: true,
+            fields: vec![Field {
+                tag: PLACEHOLDER_FIELD,
+                value: Vec::new(),
+            }],
+            ..crate::encrypt::tests::request(&input_cur, &encrypted_cur)
+        })
+        .unwrap();
+
+        let header_content = header_cur.into_inner().into_inner();
+        let encrypted_content = encrypted_cur.into_inner().into_inner();
+
+        // the data starts with as many random bytes as the header has
+        let (header, _) = Header::deserialize(&mut Cursor::new(&header_content)).unwrap();
+        assert!(header.has_field(PLACEHOLDER_FIELD));
+        let placeholder = &encrypted_content[..header_content.len()];
+        assert_eq!(placeholder.len() as u64, header.get_size());
+        assert_ne!(placeholder, header_content.as_slice());
+        assert!(placeholder.iter().any(|b| *b != 0));
+
+        let output_cur = RefCell::new(Cursor::new(Vec::new()));
+        execute(Request {
+            header_reader: Some(&RefCell::new(Cursor::new(header_content))),
+            reader: &RefCell::new(Cursor::new(encrypted_content.clone())),
+            writer: &output_cur,
+            raw_key: Protected::new(PASSWORD.to_vec()),
+            master_key: None,
+            identity: None,
+            on_decrypted_header: None,
+            on_file_info: None,
+        })
+        .unwrap();
+        assert_eq!(
+            output_cur.into_inner().into_inner(),
+            b"Hello world".to_vec()
+        );
+
+        // without the header, there's nothing to find
+        assert!(execute(Request {
+            header_reader: None,
+            reader: &RefCell::new(Cursor::new(encrypted_content)),
+            writer: &RefCell::new(Cursor::new(Vec::new())),
+            raw_key: Protected::new(PASSWORD.to_vec()),
+            master_key: None,
+            identity: None,
+            on_decrypted_header: None,
+            on_file_info: None,
+        })
+        .is_err());
+    }
+
     #[test]
     fn should_decrypt_encrypted_full_detached_header_and_content_with_v5_version() {
         let mut input_content = V5_ENCRYPTED_FULL_DETACHED_CONTENT.to_vec();
@@ -250,14 +698,1200 @@ mod tests {
             reader: &input_cur,
             writer: &output_cur,
             raw_key: Protected::new(PASSWORD.to_vec()),
+            master_key: None,
+            identity: None,
             on_decrypted_header: None,
+            on_file_info: None,
         };
 
         match execute(req) {
-            Ok(_) => {
+            Ok(()) => {
                 assert_eq!(output_content, "Hello world".as_bytes().to_vec());
             }
             _ => unreachable!(),
         }
     }
+
+    fn encrypt_and_decrypt(algorithm: Algorithm) -> Vec<u8> {
+        let mut input_content = b"Hello world";
+        let input_cur = RefCell::new(Cursor::new(&mut input_content));
+
+        let mut encrypted_content = vec![];
+        let encrypted_cur = RefCell::new(Cursor::new(&mut encrypted_content));
+
+        crate::encrypt::execute(crate::encrypt::Request {
+            header_type: HeaderType {
+                version: HeaderVersion::V6,
+                algorithm,
+                mode: Mode::StreamMode,
+            },
+            hashing_algorithm: HashingAlgorithm::Argon2id(1),
+            ..crate::encrypt::tests::request(&input_cur, &encrypted_cur)
+        })
+        .unwrap();
+
+        encrypted_cur.borrow_mut().rewind().unwrap();
+
+        let mut output_content = vec![];
+        let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+        let req = Request {
+            header_reader: None,
+            reader: &encrypted_cur,
+            writer: &output_cur,
+            raw_key: Protected::new(PASSWORD.to_vec()),
+            master_key: None,
+            identity: None,
+            on_decrypted_header: None,
+            on_file_info: None,
+        };
+
+        match execute(req) {
+            Ok(()) => output_content,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn should_decrypt_content_encrypted_with_aegis256() {
+        assert_eq!(
+            encrypt_and_decrypt(Algorithm::Aegis256),
+            "Hello world".as_bytes().to_vec()
+        );
+    }
+
+    #[test]
+    fn should_decrypt_content_encrypted_with_chacha20poly1305() {
+        assert_eq!(
+            encrypt_and_decrypt(Algorithm::ChaCha20Poly1305),
+            "Hello world".as_bytes().to_vec()
+        );
+    }
+
+    #[test]
+    fn should_decrypt_content_encrypted_with_ascon128a() {
+        assert_eq!(
+            encrypt_and_decrypt(Algorithm::Ascon128a),
+            "Hello world".as_bytes().to_vec()
+        );
+    }
+
+    fn encrypt_and_decrypt_compressed(compression: Compression) {
+        let input_content = vec![b'a'; core::primitives::BLOCK_SIZE * 2 + 11];
+        let input_cur = RefCell::new(Cursor::new(input_content.clone()));
+
+        let mut encrypted_content = vec![];
+        let encrypted_cur = RefCell::new(Cursor::new(&mut encrypted_content));
+
+        crate::encrypt::execute(crate::encrypt::Request {
+            hashing_algorithm: HashingAlgorithm::Argon2id(1),
+            compression,
+            ..crate::encrypt::tests::request(&input_cur, &encrypted_cur)
+        })
+        .unwrap();
+
+        assert!(encrypted_cur.borrow().get_ref().len() < input_content.len() / 100);
+        encrypted_cur.borrow_mut().rewind().unwrap();
+
+        let mut output_content = vec![];
+        let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+        let req = Request {
+            header_reader: None,
+            reader: &encrypted_cur,
+            writer: &output_cur,
+            raw_key: Protected::new(PASSWORD.to_vec()),
+            master_key: None,
+            identity: None,
+            on_decrypted_header: None,
+            on_file_info: None,
+        };
+
+        match execute(req) {
+            Ok(()) => assert_eq!(output_content, input_content),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn should_decrypt_compressed_content_across_blocks() {
+        encrypt_and_decrypt_compressed(Compression::Zstd(3));
+    }
+
+    #[test]
+    fn should_decrypt_padded_compressed_content_across_blocks() {
+        encrypt_and_decrypt_compressed(Compression::ZstdPadded(3));
+    }
+
+    fn encrypt_convergently(content: &[u8]) -> Vec<u8> {
+        let input_cur = RefCell::new(Cursor::new(content.to_vec()));
+
+        let mut encrypted_content = vec![];
+        let encrypted_cur = RefCell::new(Cursor::new(&mut encrypted_content));
+
+        crate::encrypt::execute(crate::encrypt::Request {
+            hashing_algorithm: HashingAlgorithm::Argon2id(1),
+            convergent: true,
+            ..crate::encrypt::tests::request(&input_cur, &encrypted_cur)
+        })
+        .unwrap();
+
+        encrypted_content
+    }
+
+    #[test]
+    fn should_decrypt_convergently_encrypted_content() {
+        let encrypted_content = encrypt_convergently(b"Hello world");
+
+        // the same content always results in the same output, and the nonce depends on the content
+        assert_eq!(encrypted_content, encrypt_convergently(b"Hello world"));
+        assert_ne!(
+            encrypted_content[6..26],
+            encrypt_convergently(b"Hello world!")[6..26]
+        );
+
+        let input_cur = RefCell::new(Cursor::new(encrypted_content));
+
+        let mut output_content = vec![];
+        let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+        let req = Request {
+            header_reader: None,
+            reader: &input_cur,
+            writer: &output_cur,
+            raw_key: Protected::new(PASSWORD.to_vec()),
+            master_key: None,
+            identity: None,
+            on_decrypted_header: None,
+            on_file_info: None,
+        };
+
+        match execute(req) {
+            Ok(()) => assert_eq!(output_content, b"Hello world".to_vec()),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn should_decrypt_padded_content() {
+        let input_content = vec![5u8; 1000];
+        let input_cur = RefCell::new(Cursor::new(input_content.clone()));
+
+        let mut encrypted_content = vec![];
+        let encrypted_cur = RefCell::new(Cursor::new(&mut encrypted_content));
+
+        crate::encrypt::execute(crate::encrypt::Request {
+            hashing_algorithm: HashingAlgorithm::Argon2id(1),
+            padding: Padding::Padme,
+            ..crate::encrypt::tests::request(&input_cur, &encrypted_cur)
+        })
+        .unwrap();
+
+        // the 1000 bytes (and the 8-byte length) are padded to 1024, along with the header (and its 16-byte field section, which only holds the options) and the tag
+        assert_eq!(encrypted_cur.borrow().get_ref().len(), 416 + 16 + 1024 + 16);
+        encrypted_cur.borrow_mut().rewind().unwrap();
+
+        let mut output_content = vec![];
+        let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+        let req = Request {
+            header_reader: None,
+            reader: &encrypted_cur,
+            writer: &output_cur,
+            raw_key: Protected::new(PASSWORD.to_vec()),
+            master_key: None,
+            identity: None,
+            on_decrypted_header: None,
+            on_file_info: None,
+        };
+
+        match execute(req) {
+            Ok(()) => assert_eq!(output_content, input_content),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn should_decrypt_content_encrypted_to_recipients() {
+        let identity = RecipientSecretKey::generate();
+        let other_identity = RecipientSecretKey::generate_x25519();
+
+        let input_cur = RefCell::new(Cursor::new(b"Hello world".to_vec()));
+
+        let mut encrypted_content = vec![];
+        let encrypted_cur = RefCell::new(Cursor::new(&mut encrypted_content));
+
+        crate::encrypt::execute(crate::encrypt::Request {
+            hashing_algorithm: HashingAlgorithm::Argon2id(1),
+            recipients: vec![identity.public_key(), other_identity.public_key()],
+            ..crate::encrypt::tests::request(&input_cur, &encrypted_cur)
+        })
+        .unwrap();
+
+        // the encapsulated keys are appended to the keyslots, before the field section (which only holds the options)
+        assert_eq!(
+            encrypted_cur.borrow().get_ref().len(),
+            416 + core::recipient::ENCAPSULATED_KEY_LEN
+                + core::recipient::X25519_ENCAPSULATED_KEY_LEN
+                + 16
+                + 11
+                + 16
+        );
+
+        let decrypt_with = |identity: RecipientSecretKey| {
+            encrypted_cur.borrow_mut().rewind().unwrap();
+
+            let mut output_content = vec![];
+            let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+            let req = Request {
+                header_reader: None,
+                reader: &encrypted_cur,
+                writer: &output_cur,
+                raw_key: Protected::new(Vec::new()),
+                master_key: None,
+                identity: Some(identity),
+                on_decrypted_header: None,
+                on_file_info: None,
+            };
+
+            execute(req).map(|()| output_content)
+        };
+
+        for identity in [identity, other_identity] {
+            match decrypt_with(identity) {
+                Ok(output_content) => assert_eq!(output_content, b"Hello world".to_vec()),
+                _ => unreachable!(),
+            }
+        }
+
+        assert!(decrypt_with(RecipientSecretKey::generate()).is_err());
+        assert!(decrypt_with(RecipientSecretKey::generate_x25519()).is_err());
+    }
+
+    #[test]
+    fn should_decrypt_content_with_recovery_code() {
+        let code = core::key::generate_recovery_code();
+        let recovery_key = core::key::normalize_recovery_code(code.expose()).unwrap();
+
+        let input_cur = RefCell::new(Cursor::new(b"Hello world".to_vec()));
+
+        let mut encrypted_content = vec![];
+        let encrypted_cur = RefCell::new(Cursor::new(&mut encrypted_content));
+
+        crate::encrypt::execute(crate::encrypt::Request {
+            header_type: HeaderType {
+                version: HeaderVersion::V5,
+                algorithm: Algorithm::XChaCha20Poly1305,
+                mode: Mode::StreamMode,
+            },
+            extra_keys: vec![recovery_key],
+            ..crate::encrypt::tests::request(&input_cur, &encrypted_cur)
+        })
+        .unwrap();
+
+        let decrypt_with = |raw_key: Protected<Vec<u8>>| {
+            encrypted_cur.borrow_mut().rewind().unwrap();
+
+            let mut output_content = vec![];
+            let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+            let req = Request {
+                header_reader: None,
+                reader: &encrypted_cur,
+                writer: &output_cur,
+                raw_key,
+                master_key: None,
+                identity: None,
+                on_decrypted_header: None,
+                on_file_info: None,
+            };
+
+            execute(req).map(|()| output_content)
+        };
+
+        // the code should work however it's typed
+        let typed = code.expose().to_lowercase().replace('-', " ");
+        match decrypt_with(core::key::normalize_recovery_code(&typed).unwrap()) {
+            Ok(output_content) => assert_eq!(output_content, b"Hello world".to_vec()),
+            _ => unreachable!(),
+        }
+
+        let wrong_code = core::key::generate_recovery_code();
+        assert!(
+            decrypt_with(core::key::normalize_recovery_code(wrong_code.expose()).unwrap()).is_err()
+        );
+        assert!(core::key::normalize_recovery_code("not a recovery code").is_err());
+    }
+
+    // this stands in for a hardware token, with `secret` as the token's HMAC key
+    struct KeyedResponder {
+        secret: [u8; 32],
+    }
+
+    impl core::challenge::Responder for KeyedResponder {
+        fn respond(&mut self, challenge: &[u8]) -> anyhow::Result<Protected<Vec<u8>>> {
+            let response = blake3::keyed_hash(&self.secret, challenge);
+            Ok(Protected::new(response.as_bytes()[..20].to_vec()))
+        }
+    }
+
+    #[test]
+    fn should_require_the_responder_for_a_bound_key() {
+        // this mustn't match any other test's key, as the registration is process-wide
+        let token_key = b"bound to a token".to_vec();
+        core::challenge::register(
+            &Protected::new(token_key.clone()),
+            Box::new(KeyedResponder { secret: [1u8; 32] }),
+        );
+
+        let input_cur = RefCell::new(Cursor::new(b"Hello world".to_vec()));
+
+        let mut encrypted_content = vec![];
+        let encrypted_cur = RefCell::new(Cursor::new(&mut encrypted_content));
+
+        crate::encrypt::execute(crate::encrypt::Request {
+            raw_key: Protected::new(token_key.clone()),
+            header_type: HeaderType {
+                version: HeaderVersion::V5,
+                algorithm: Algorithm::XChaCha20Poly1305,
+                mode: Mode::StreamMode,
+            },
+            hashing_algorithm: HashingAlgorithm::Argon2id(1),
+            extra_keys: vec![Protected::new(PASSWORD.to_vec())],
+            ..crate::encrypt::tests::request(&input_cur, &encrypted_cur)
+        })
+        .unwrap();
+
+        let decrypt_with = |raw_key: Protected<Vec<u8>>| {
+            encrypted_cur.borrow_mut().rewind().unwrap();
+
+            let mut output_content = vec![];
+            let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+            let req = Request {
+                header_reader: None,
+                reader: &encrypted_cur,
+                writer: &output_cur,
+                raw_key,
+                master_key: None,
+                identity: None,
+                on_decrypted_header: None,
+                on_file_info: None,
+            };
+
+            execute(req).map(|()| output_content)
+        };
+
+        match decrypt_with(Protected::new(token_key.clone())) {
+            Ok(output_content) => assert_eq!(output_content, b"Hello world".to_vec()),
+            _ => unreachable!(),
+        }
+
+        // the extra key isn't bound to the token
+        assert!(decrypt_with(Protected::new(PASSWORD.to_vec())).is_ok());
+
+        // a different token can't decrypt it
+        core::challenge::register(
+            &Protected::new(token_key.clone()),
+            Box::new(KeyedResponder { secret: [2u8; 32] }),
+        );
+        assert!(decrypt_with(Protected::new(token_key)).is_err());
+    }
+
+    #[test]
+    fn should_unlock_token_keyslot_with_registered_token() {
+        let token_key =
+            core::token::TokenKey::generate(&mut KeyedResponder { secret: [3u8; 32] }).unwrap();
+
+        let input_cur = RefCell::new(Cursor::new(b"Hello world".to_vec()));
+
+        let mut encrypted_content = vec![];
+        let encrypted_cur = RefCell::new(Cursor::new(&mut encrypted_content));
+
+        crate::encrypt::execute(crate::encrypt::Request {
+            hashing_algorithm: HashingAlgorithm::Argon2id(1),
+            tokens: vec![token_key],
+            ..crate::encrypt::tests::request(&input_cur, &encrypted_cur)
+        })
+        .unwrap();
+
+        encrypted_cur.borrow_mut().rewind().unwrap();
+        let (header, _) = Header::deserialize(&mut *encrypted_cur.borrow_mut()).unwrap();
+        let keyslots = header.keyslots.unwrap();
+        assert!(!keyslots[0].is_token());
+        assert!(keyslots[1].is_token());
+
+        let decrypt_with = |raw_key: Protected<Vec<u8>>| {
+            encrypted_cur.borrow_mut().rewind().unwrap();
+
+            let mut output_content = vec![];
+            let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+            let req = Request {
+                header_reader: None,
+                reader: &encrypted_cur,
+                writer: &output_cur,
+                raw_key,
+                master_key: None,
+                identity: None,
+                on_decrypted_header: None,
+                on_file_info: None,
+            };
+
+            execute(req).map(|()| output_content)
+        };
+
+        // the registration is process-wide, but no other test creates token keyslots
+        core::token::register(Box::new(KeyedResponder { secret: [3u8; 32] }));
+        match decrypt_with(Protected::new(Vec::new())) {
+            Ok(output_content) => assert_eq!(output_content, b"Hello world".to_vec()),
+            _ => unreachable!(),
+        }
+
+        // a different token can't unlock it, but the key still can
+        core::token::register(Box::new(KeyedResponder { secret: [4u8; 32] }));
+        assert!(decrypt_with(Protected::new(Vec::new())).is_err());
+        assert!(decrypt_with(Protected::new(PASSWORD.to_vec())).is_ok());
+    }
+
+    #[test]
+    fn should_authenticate_header_metadata() {
+        let metadata = Metadata {
+            created: 1_664_546_700,
+            version: "dexios 8.8.1".to_string(),
+        };
+
+        let input_cur = RefCell::new(Cursor::new(b"Hello world".to_vec()));
+
+        let mut encrypted_content = vec![];
+        let encrypted_cur = RefCell::new(Cursor::new(&mut encrypted_content));
+
+        crate::encrypt::execute(crate::encrypt::Request {
+            hashing_algorithm: HashingAlgorithm::Argon2id(1),
+            metadata: Some(metadata.clone()),
+            ..crate::encrypt::tests::request(&input_cur, &encrypted_cur)
+        })
+        .unwrap();
+
+        let decrypt = |content: Vec<u8>| {
+            let mut output_content = vec![];
+            let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+            let req = Request {
+                header_reader: None,
+                reader: &RefCell::new(Cursor::new(content)),
+                writer: &output_cur,
+                raw_key: Protected::new(PASSWORD.to_vec()),
+                master_key: None,
+                identity: None,
+                on_decrypted_header: None,
+                on_file_info: None,
+            };
+
+            execute(req).map(|()| output_content)
+        };
+
+        let content = encrypted_cur.into_inner().into_inner().clone();
+        let (header, _) = Header::deserialize(&mut Cursor::new(content.clone())).unwrap();
+        assert_eq!(header.metadata, Some(metadata));
+
+        match decrypt(content.clone()) {
+            Ok(output_content) => assert_eq!(output_content, b"Hello world".to_vec()),
+            _ => unreachable!(),
+        }
+
+        // the metadata is covered by the AAD, so changing the timestamp should cause decryption to fail
+        // (it's the only field, so the timestamp follows the section's length, and the field's tag and length)
+        let mut tampered = content;
+        tampered[416 + 4 + 6] ^= 1;
+        assert!(decrypt(tampered).is_err());
+    }
+
+    #[test]
+    fn should_authenticate_header_fields() {
+        let metadata = Metadata {
+            created: 1_664_546_700,
+            version: "dexios 8.8.1".to_string(),
+        };
+        let padding = Field::random_padding(64);
+
+        let input_cur = RefCell::new(Cursor::new(b"Hello world".to_vec()));
+
+        let mut encrypted_content = vec![];
+        let encrypted_cur = RefCell::new(Cursor::new(&mut encrypted_content));
+
+        crate::encrypt::execute(crate::encrypt::Request {
+            metadata: Some(metadata.clone()),
+            fields: vec![padding.clone()],
+            ..crate::encrypt::tests::request(&input_cur, &encrypted_cur)
+        })
+        .unwrap();
+
+        let decrypt = |content: Vec<u8>| {
+            let mut output_content = vec![];
+            let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+            let req = Request {
+                header_reader: None,
+                reader: &RefCell::new(Cursor::new(content)),
+                writer: &output_cur,
+                raw_key: Protected::new(PASSWORD.to_vec()),
+                master_key: None,
+                identity: None,
+                on_decrypted_header: None,
+                on_file_info: None,
+            };
+
+            execute(req).map(|()| output_content)
+        };
+
+        let content = encrypted_cur.into_inner().into_inner().clone();
+        let (mut header, _) = Header::deserialize(&mut Cursor::new(content.clone())).unwrap();
+        assert_eq!(header.metadata, Some(metadata));
+        assert_eq!(header.fields, vec![padding]);
+
+        match decrypt(content.clone()) {
+            Ok(output_content) => assert_eq!(output_content, b"Hello world".to_vec()),
+            _ => unreachable!(),
+        }
+
+        // fields that aren't recognised survive the header being rewritten, but they're covered by the AAD too
+        let size = usize::try_from(header.get_size()).unwrap();
+        header.fields.push(Field {
+            tag: 0x0100,
+            value: b"unrecognised".to_vec(),
+        });
+        let mut tampered = header.serialize().unwrap();
+        tampered.extend_from_slice(&content[size..]);
+
+        let (rewritten, _) = Header::deserialize(&mut Cursor::new(tampered.clone())).unwrap();
+        assert_eq!(rewritten.fields, header.fields);
+        assert_eq!(rewritten.metadata, header.metadata);
+        assert!(decrypt(tampered).is_err());
+
+        // but a critical field that isn't recognised can't be skipped over
+        header.fields = vec![Field {
+            tag: CRITICAL_FIELD | 0x0100,
+            value: Vec::new(),
+        }];
+        let critical = header.serialize().unwrap();
+        assert!(Header::deserialize(&mut Cursor::new(critical)).is_err());
+    }
+
+    #[test]
+    fn should_decrypt_content_encrypted_with_custom_block_size() {
+        let block_size = core::primitives::MIN_BLOCK_SIZE;
+        let input_content = vec![3u8; block_size * 3 + 7];
+        let input_cur = RefCell::new(Cursor::new(input_content.clone()));
+
+        let mut encrypted_content = vec![];
+        let encrypted_cur = RefCell::new(Cursor::new(&mut encrypted_content));
+
+        crate::encrypt::execute(crate::encrypt::Request {
+            hashing_algorithm: HashingAlgorithm::Argon2id(1),
+            block_size,
+            ..crate::encrypt::tests::request(&input_cur, &encrypted_cur)
+        })
+        .unwrap();
+
+        encrypted_cur.borrow_mut().rewind().unwrap();
+        let (header, _) = Header::deserialize(&mut *encrypted_cur.borrow_mut()).unwrap();
+        assert_eq!(header.block_size, block_size);
+        encrypted_cur.borrow_mut().rewind().unwrap();
+
+        let mut output_content = vec![];
+        let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+        let req = Request {
+            header_reader: None,
+            reader: &encrypted_cur,
+            writer: &output_cur,
+            raw_key: Protected::new(PASSWORD.to_vec()),
+            master_key: None,
+            identity: None,
+            on_decrypted_header: None,
+            on_file_info: None,
+        };
+
+        match execute(req) {
+            Ok(()) => assert_eq!(output_content, input_content),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn should_decrypt_content_encrypted_with_custom_kdf_params() {
+        use core::kdf::{Argon2idParams, BalloonParams, ScryptParams};
+
+        for hashing_algorithm in [
+            HashingAlgorithm::Argon2idCustom(Argon2idParams {
+                m_cost: 1024,
+                t_cost: 2,
+                p_cost: 1,
+            }),
+            HashingAlgorithm::Blake3BalloonCustom(BalloonParams {
+                s_cost: 1024,
+                t_cost: 1,
+                p_cost: 1,
+            }),
+            HashingAlgorithm::ScryptCustom(ScryptParams {
+                log_n: 10,
+                r: 8,
+                p: 1,
+            }),
+        ] {
+            let input_cur = RefCell::new(Cursor::new(b"Hello world".to_vec()));
+
+            let mut encrypted_content = vec![];
+            let encrypted_cur = RefCell::new(Cursor::new(&mut encrypted_content));
+
+            crate::encrypt::execute(crate::encrypt::Request {
+                hashing_algorithm,
+                ..crate::encrypt::tests::request(&input_cur, &encrypted_cur)
+            })
+            .unwrap();
+
+            // the parameters should be read back from the keyslot
+            encrypted_cur.borrow_mut().rewind().unwrap();
+            let (header, _) = Header::deserialize(&mut *encrypted_cur.borrow_mut()).unwrap();
+            assert!(header.keyslots.unwrap()[0].hash_algorithm == hashing_algorithm);
+            encrypted_cur.borrow_mut().rewind().unwrap();
+
+            let mut output_content = vec![];
+            let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+            let req = Request {
+                header_reader: None,
+                reader: &encrypted_cur,
+                writer: &output_cur,
+                raw_key: Protected::new(PASSWORD.to_vec()),
+                master_key: None,
+                identity: None,
+                on_decrypted_header: None,
+                on_file_info: None,
+            };
+
+            match execute(req) {
+                Ok(()) => assert_eq!(output_content, b"Hello world"),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn should_verify_mac_before_decrypting() {
+        let block_size = core::primitives::MIN_BLOCK_SIZE;
+        let input_content = vec![5u8; block_size * 3 + 7];
+        let input_cur = RefCell::new(Cursor::new(input_content.clone()));
+
+        let mut encrypted_content = vec![];
+        let encrypted_cur = RefCell::new(Cursor::new(&mut encrypted_content));
+
+        crate::encrypt::execute(crate::encrypt::Request {
+            hashing_algorithm: HashingAlgorithm::Argon2id(1),
+            block_size,
+            mac: true,
+            ..crate::encrypt::tests::request(&input_cur, &encrypted_cur)
+        })
+        .unwrap();
+
+        let content = encrypted_cur.into_inner().into_inner().clone();
+        let (header, _) = Header::deserialize(&mut Cursor::new(content.clone())).unwrap();
+        assert!(header.mac);
+
+        let decrypt = |content: Vec<u8>| {
+            let mut output_content = vec![];
+            let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+            let req = Request {
+                header_reader: None,
+                reader: &RefCell::new(Cursor::new(content)),
+                writer: &output_cur,
+                raw_key: Protected::new(PASSWORD.to_vec()),
+                master_key: None,
+                identity: None,
+                on_decrypted_header: None,
+                on_file_info: None,
+            };
+
+            let res = execute(req);
+            (res, output_content)
+        };
+
+        match decrypt(content.clone()) {
+            (Ok(()), output_content) => assert_eq!(output_content, input_content),
+            _ => unreachable!(),
+        }
+
+        // dropping the final block (and keeping the footer) must be caught before anything is written
+        let block_len = block_size + 16;
+        let mut truncated = content[..content.len() - 32 - (7 + 16)].to_vec();
+        truncated.extend_from_slice(&content[content.len() - 32..]);
+        let (res, output_content) = decrypt(truncated);
+        assert!(matches!(res, Err(Error::VerifyMac)));
+        assert!(output_content.is_empty());
+
+        // as must swapping two blocks
+        let start = usize::try_from(header.get_size()).unwrap();
+        let mut reordered = content.clone();
+        reordered[start..start + block_len]
+            .copy_from_slice(&content[start + block_len..start + block_len * 2]);
+        reordered[start + block_len..start + block_len * 2]
+            .copy_from_slice(&content[start..start + block_len]);
+        let (res, output_content) = decrypt(reordered);
+        assert!(matches!(res, Err(Error::VerifyMac)));
+        assert!(output_content.is_empty());
+    }
+
+    #[test]
+    fn should_verify_plaintext_digest() {
+        let input_content = b"Hello world".to_vec();
+        let input_cur = RefCell::new(Cursor::new(input_content.clone()));
+
+        let mut encrypted_content = vec![];
+        let encrypted_cur = RefCell::new(Cursor::new(&mut encrypted_content));
+
+        crate::encrypt::execute(crate::encrypt::Request {
+            hashing_algorithm: HashingAlgorithm::Argon2id(1),
+            padding: Padding::Padme,
+            digest: true,
+            ..crate::encrypt::tests::request(&input_cur, &encrypted_cur)
+        })
+        .unwrap();
+
+        let decrypt = |content: Vec<u8>| {
+            let mut output_content = vec![];
+            let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+            let req = Request {
+                header_reader: None,
+                reader: &RefCell::new(Cursor::new(content)),
+                writer: &output_cur,
+                raw_key: Protected::new(PASSWORD.to_vec()),
+                master_key: None,
+                identity: None,
+                on_decrypted_header: None,
+                on_file_info: None,
+            };
+
+            execute(req).map(|()| output_content)
+        };
+
+        let content = encrypted_cur.into_inner().into_inner().clone();
+        let (mut header, _) = Header::deserialize(&mut Cursor::new(content.clone())).unwrap();
+        assert!(header.digest.is_some());
+
+        match decrypt(content.clone()) {
+            Ok(output_content) => assert_eq!(output_content, input_content),
+            _ => unreachable!(),
+        }
+
+        // a digest of different data should be caught, even though every block still authenticates
+        let master_key = decrypt_master_key(Protected::new(PASSWORD.to_vec()), &header).unwrap();
+        header.digest = Some(
+            digest::encrypt(
+                Subkeys::derive(master_key, &header).header,
+                &header.header_type.algorithm,
+                &blake3::hash(b"Goodbye world"),
+            )
+            .unwrap(),
+        );
+        let mut corrupted = header.serialize().unwrap();
+        corrupted.extend_from_slice(&content[corrupted.len()..]);
+        assert!(matches!(decrypt(corrupted), Err(Error::VerifyDigest)));
+
+        // and the digest itself can't be modified
+        let mut tampered = content;
+        let digest_offset = usize::try_from(header.get_size()).unwrap() - 1;
+        tampered[digest_offset] ^= 1;
+        assert!(matches!(decrypt(tampered), Err(Error::DecryptDigest)));
+    }
+
+    #[test]
+    fn should_need_both_factors_of_a_two_factor_key() {
+        use core::key::combine_factors;
+
+        let input_content = b"Hello world".to_vec();
+        let input_cur = RefCell::new(Cursor::new(input_content.clone()));
+
+        let keyfile = Protected::new(vec![7u8; 64]);
+        let password = Protected::new(PASSWORD.to_vec());
+
+        let mut encrypted_content = vec![];
+        let encrypted_cur = RefCell::new(Cursor::new(&mut encrypted_content));
+
+        crate::encrypt::execute(crate::encrypt::Request {
+            raw_key: combine_factors(&keyfile, &password),
+            hashing_algorithm: HashingAlgorithm::Argon2id(1),
+            keyfile_hash: true,
+            two_factor: true,
+            ..crate::encrypt::tests::request(&input_cur, &encrypted_cur)
+        })
+        .unwrap();
+
+        let content = encrypted_cur.into_inner().into_inner().clone();
+        let (header, _) = Header::deserialize(&mut Cursor::new(content.clone())).unwrap();
+        assert!(header.two_factor);
+
+        let decrypt = |raw_key: Protected<Vec<u8>>| {
+            let mut output_content = vec![];
+            let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+            let req = Request {
+                header_reader: None,
+                reader: &RefCell::new(Cursor::new(content.clone())),
+                writer: &output_cur,
+                raw_key,
+                master_key: None,
+                identity: None,
+                on_decrypted_header: None,
+                on_file_info: None,
+            };
+
+            execute(req).map(|()| output_content)
+        };
+
+        match decrypt(combine_factors(&keyfile, &password)) {
+            Ok(output_content) => assert_eq!(output_content, input_content),
+            _ => unreachable!(),
+        }
+
+        assert!(matches!(decrypt(keyfile), Err(Error::DecryptMasterKey)));
+        assert!(matches!(decrypt(password), Err(Error::DecryptMasterKey)));
+    }
+
+    struct TestBackend;
+
+    struct TestCipher(Ciphers);
+
+    impl core::backend::AeadCipher for TestCipher {
+        fn encrypt(
+            &self,
+            nonce: &[u8],
+            payload: core::Payload<'_, '_>,
+        ) -> core::aead::Result<Vec<u8>> {
+            self.0.encrypt(nonce, payload)
+        }
+
+        fn decrypt(
+            &self,
+            nonce: &[u8],
+            payload: core::Payload<'_, '_>,
+        ) -> core::aead::Result<Vec<u8>> {
+            self.0.decrypt(nonce, payload)
+        }
+    }
+
+    impl core::backend::AeadBackend for TestBackend {
+        fn id(&self) -> u8 {
+            0x01
+        }
+
+        fn name(&self) -> &'static str {
+            "Test-AEAD"
+        }
+
+        fn nonce_len(&self) -> usize {
+            24
+        }
+
+        fn initialize(
+            &self,
+            key: Protected<[u8; 32]>,
+        ) -> anyhow::Result<Box<dyn core::backend::AeadCipher>> {
+            let cipher = Ciphers::initialize(key, &Algorithm::XChaCha20Poly1305)?;
+            Ok(Box::new(TestCipher(cipher)))
+        }
+    }
+
+    #[test]
+    fn should_decrypt_content_encrypted_with_custom_backend() {
+        core::backend::register(std::sync::Arc::new(TestBackend)).unwrap();
+
+        assert_eq!(
+            encrypt_and_decrypt(Algorithm::Custom(0x01)),
+            "Hello world".as_bytes().to_vec()
+        );
+    }
+
+    #[test]
+    fn should_decrypt_content_encrypted_in_derived_stream_mode() {
+        let input_content = vec![7u8; core::primitives::BLOCK_SIZE + 5];
+        let input_cur = RefCell::new(Cursor::new(input_content.clone()));
+
+        let mut encrypted_content = vec![];
+        let encrypted_cur = RefCell::new(Cursor::new(&mut encrypted_content));
+
+        crate::encrypt::execute(crate::encrypt::Request {
+            header_type: HeaderType {
+                version: HeaderVersion::V6,
+                algorithm: Algorithm::XChaCha20Poly1305,
+                mode: Mode::DerivedStreamMode,
+            },
+            hashing_algorithm: HashingAlgorithm::Argon2id(1),
+            ..crate::encrypt::tests::request(&input_cur, &encrypted_cur)
+        })
+        .unwrap();
+
+        encrypted_cur.borrow_mut().rewind().unwrap();
+
+        let mut output_content = vec![];
+        let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+        let req = Request {
+            header_reader: None,
+            reader: &encrypted_cur,
+            writer: &output_cur,
+            raw_key: Protected::new(PASSWORD.to_vec()),
+            master_key: None,
+            identity: None,
+            on_decrypted_header: None,
+            on_file_info: None,
+        };
+
+        match execute(req) {
+            Ok(()) => assert_eq!(output_content, input_content),
+            _ => unreachable!(),
+        }
+    }
+
+    fn encrypt_seekable(
+        content: &[u8],
+        mode: Mode,
+        compression: Compression,
+        counter: StreamCounter,
+    ) -> Vec<u8> {
+        let input_cur = RefCell::new(Cursor::new(content.to_vec()));
+
+        let mut encrypted_content = vec![];
+        let encrypted_cur = RefCell::new(Cursor::new(&mut encrypted_content));
+
+        crate::encrypt::execute(crate::encrypt::Request {
+            header_type: HeaderType {
+                version: HeaderVersion::V6,
+                algorithm: Algorithm::XChaCha20Poly1305,
+                mode,
+            },
+            hashing_algorithm: HashingAlgorithm::Argon2id(1),
+            compression,
+            block_size: core::primitives::MIN_BLOCK_SIZE,
+            mac: true,
+            seekable: true,
+            counter,
+            ..crate::encrypt::tests::request(&input_cur, &encrypted_cur)
+        })
+        .unwrap();
+
+        encrypted_content
+    }
+
+    #[test]
+    fn should_decrypt_seekable_content_at_any_position() {
+        use core::seekable::SeekableReader;
+        use std::io::SeekFrom;
+
+        let block_size = core::primitives::MIN_BLOCK_SIZE;
+        let input_content = (0..block_size * 3 + 7)
+            .map(|i| u8::try_from(i % 251).unwrap())
+            .collect::<Vec<_>>();
+
+        for (mode, compression, counter) in [
+            (Mode::StreamMode, Compression::None, StreamCounter::Le31),
+            (Mode::StreamMode, Compression::Zstd(3), StreamCounter::Le31),
+            (
+                Mode::DerivedStreamMode,
+                Compression::ZstdPadded(3),
+                StreamCounter::Le31,
+            ),
+            (Mode::StreamMode, Compression::None, StreamCounter::Be64),
+            (Mode::StreamMode, Compression::Zstd(3), StreamCounter::Be64),
+        ] {
+            let encrypted_content = encrypt_seekable(&input_content, mode, compression, counter);
+
+            // the chunk table and MAC shouldn't be decrypted as if they're part of the ciphertext
+            let encrypted_cur = RefCell::new(Cursor::new(encrypted_content.clone()));
+            let mut output_content = vec![];
+            let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+            let req = Request {
+                header_reader: None,
+                reader: &encrypted_cur,
+                writer: &output_cur,
+                raw_key: Protected::new(PASSWORD.to_vec()),
+                master_key: None,
+                identity: None,
+                on_decrypted_header: None,
+                on_file_info: None,
+            };
+
+            match execute(req) {
+                Ok(()) => assert_eq!(output_content, input_content),
+                _ => unreachable!(),
+            }
+
+            let mut cursor = Cursor::new(encrypted_content);
+            let (header, aad) = Header::deserialize(&mut cursor).unwrap();
+            assert!(header.counter == counter);
+            let master_key =
+                decrypt_master_key(Protected::new(PASSWORD.to_vec()), &header).unwrap();
+            let mut reader = SeekableReader::new(cursor, &header, aad, master_key).unwrap();
+            assert_eq!(reader.plaintext_len(), input_content.len() as u64);
+
+            // this crosses the boundary between the second and third blocks
+            let start = block_size * 2 - 3;
+            let mut buffer = [0u8; 10];
+            reader.seek(SeekFrom::Start(start as u64)).unwrap();
+            reader.read_exact(&mut buffer).unwrap();
+            assert_eq!(buffer, input_content[start..start + 10]);
+
+            let mut end = vec![];
+            reader.seek(SeekFrom::End(-5)).unwrap();
+            reader.read_to_end(&mut end).unwrap();
+            assert_eq!(end, input_content[input_content.len() - 5..]);
+        }
+    }
+
+    #[test]
+    fn should_not_use_the_master_key_for_the_payload() {
+        let input_content = b"Hello world".to_vec();
+        let encrypted_content = encrypt_seekable(
+            &input_content,
+            Mode::StreamMode,
+            Compression::None,
+            StreamCounter::Le31,
+        );
+
+        let mut cursor = Cursor::new(encrypted_content);
+        let (header, aad) = Header::deserialize(&mut cursor).unwrap();
+        assert!(header.subkeys);
+
+        let master_key = decrypt_master_key(Protected::new(PASSWORD.to_vec()), &header).unwrap();
+        let subkeys = Subkeys::derive(master_key.clone(), &header);
+        assert_ne!(subkeys.payload.expose(), master_key.expose());
+        assert_ne!(subkeys.header.expose(), subkeys.mac.expose());
+
+        let start = usize::try_from(header.get_size()).unwrap();
+        let ciphertext = &cursor.into_inner()[start..];
+        // there's only one block, and it's followed by the chunk table and MAC
+        let block = &ciphertext[..input_content.len() + 16];
+        let decrypt_with = |key: Protected<[u8; MASTER_KEY_LEN]>| {
+            DecryptionStreams::initialize(key, &header.nonce, &header.header_type.algorithm)
+                .unwrap()
+                .decrypt_last(core::Payload {
+                    msg: block,
+                    aad: &aad,
+                })
+        };
+
+        assert!(decrypt_with(master_key).is_err());
+        assert_eq!(decrypt_with(subkeys.payload).unwrap(), input_content);
+    }
+
+    #[test]
+    fn should_decrypt_a_range_of_seekable_content() {
+        let block_size = core::primitives::MIN_BLOCK_SIZE;
+        let input_content = (0..block_size * 3 + 7)
+            .map(|i| u8::try_from(i % 251).unwrap())
+            .collect::<Vec<_>>();
+        let encrypted_content = encrypt_seekable(
+            &input_content,
+            Mode::StreamMode,
+            Compression::Zstd(3),
+            StreamCounter::Le31,
+        );
+
+        let decrypt_range = |start: u64, len: Option<u64>| {
+            let encrypted_cur = RefCell::new(Cursor::new(encrypted_content.clone()));
+            let mut output_content = vec![];
+            let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+            let req = RangeRequest {
+                header_reader: None,
+                reader: &encrypted_cur,
+                writer: &output_cur,
+                raw_key: Protected::new(PASSWORD.to_vec()),
+                master_key: None,
+                identity: None,
+                start,
+                len,
+            };
+
+            execute_range(req).map(|()| output_content)
+        };
+
+        // this crosses the boundary between the first and second blocks
+        let start = block_size - 3;
+        assert_eq!(
+            decrypt_range(start as u64, Some(10)).unwrap(),
+            input_content[start..start + 10]
+        );
+        // the range is cut short at the end of the plaintext
+        assert_eq!(
+            decrypt_range(block_size as u64 * 3, Some(100)).unwrap(),
+            input_content[block_size * 3..]
+        );
+        assert_eq!(decrypt_range(0, None).unwrap(), input_content);
+        assert!(matches!(
+            decrypt_range(input_content.len() as u64 + 1, None),
+            Err(Error::RangeOutOfBounds)
+        ));
+    }
+
+    #[test]
+    fn should_decrypt_memory_mode_content_into_a_buffer() {
+        use core::cipher::{decrypt_in_memory, decrypt_in_memory_protected};
+
+        use core::primitives::{gen_master_key, gen_nonce};
+
+        // memory mode isn't produced by `encrypt::execute`, so this is done by hand
+        let header = Header {
+            header_type: HeaderType {
+                version: HeaderVersion::V5,
+                algorithm: Algorithm::XChaCha20Poly1305,
+                mode: Mode::MemoryMode,
+            },
+            nonce: gen_nonce(&Algorithm::XChaCha20Poly1305, &Mode::MemoryMode),
+            salt: None,
+            keyslots: Some(vec![]),
+            compression: Compression::None,
+            block_size: core::primitives::BLOCK_SIZE,
+            padding: Padding::None,
+            convergent: false,
+            metadata: None,
+            mac: false,
+            digest: None,
+            seekable: false,
+            fields: Vec::new(),
+            keyfile_hash: false,
+            counter: StreamCounter::Le31,
+            subkeys: false,
+            two_factor: false,
+            manifest: None,
+        };
+        let master_key = gen_master_key();
+        let aad = header.create_aad().unwrap();
+        let mut ciphertext = Ciphers::initialize(master_key.clone(), &header.header_type.algorithm)
+            .unwrap()
+            .encrypt(
+                &header.nonce,
+                core::Payload {
+                    msg: b"Hello world",
+                    aad: &aad,
+                },
+            )
+            .unwrap();
+
+        // anything already within the buffer should be replaced
+        let mut out = b"stale plaintext".to_vec();
+        decrypt_in_memory(&header, &master_key, &ciphertext, &mut out).unwrap();
+        assert_eq!(out, b"Hello world");
+
+        let plaintext = decrypt_in_memory_protected(&header, &master_key, &ciphertext).unwrap();
+        assert_eq!(plaintext.expose().as_slice(), b"Hello world");
+
+        ciphertext[0] ^= 1;
+        assert!(decrypt_in_memory(&header, &master_key, &ciphertext, &mut out).is_err());
+        assert!(out.is_empty());
+    }
 }
@@ -1,13 +1,32 @@
 //! This provides functionality for encryption that adheres to the Dexios format.
 
 use std::cell::RefCell;
-use std::io::{Read, Seek, Write};
+use std::io::{Read, Seek, SeekFrom, Write};
 
 use core::cipher::Ciphers;
-use core::header::{HashingAlgorithm, Header, HeaderType, Keyslot};
-use core::primitives::{Mode, ENCRYPTED_MASTER_KEY_LEN};
+use core::convergent::{ConvergentSecrets, CONVERGENT_SALT};
+use core::digest::{self, DigestReader, ENCRYPTED_DIGEST_LEN};
+use core::file_info::{self, FileInfo};
+use core::header::{
+    Field, HashingAlgorithm, Header, HeaderType, HeaderVersion, Keyslot, Metadata,
+    BACKUP_HEADER_FIELD, FILE_INFO_FIELD, MAX_KEYSLOTS, PLACEHOLDER_FIELD,
+};
+use core::key::vec_to_arr;
+use core::mac::MacWriter;
+use core::manifest::{self, Entry as ManifestEntry};
+use core::padding::PaddedReader;
+use core::primitives::{
+    Algorithm, Compression, Mode, Padding, StreamCounter, BLOCK_SIZE, ENCRYPTED_MASTER_KEY_LEN,
+    MASTER_KEY_LEN,
+};
 use core::protected::Protected;
+use core::recipient::RecipientPublicKey;
+use core::seekable::ChunkTableWriter;
 use core::stream::EncryptionStreams;
+use core::subkeys::Subkeys;
+use core::token::TokenKey;
+
+use rand::RngCore;
 
 use crate::utils::{gen_master_key, gen_nonce, gen_salt};
 
@@ -21,6 +40,16 @@ pub enum Error {
     InitializeStreams,
     InitializeChiphers,
     CreateAad,
+    HashPlaintext,
+    Encapsulate,
+    WriteMac,
+    EncryptDigest,
+    ReuseDigestKey,
+    WriteChunkTable,
+    TooManyKeyslots,
+    EncryptManifest,
+    EncryptFileInfo,
+    LegacyKeyslots,
 }
 
 impl std::fmt::Display for Error {
@@ -34,12 +63,33 @@ impl std::fmt::Display for Error {
             Error::InitializeStreams => f.write_str("Cannot initialize streams"),
             Error::InitializeChiphers => f.write_str("Cannot initialize chiphers"),
             Error::CreateAad => f.write_str("Cannot create AAD"),
+            Error::HashPlaintext => f.write_str("Cannot hash plaintext"),
+            Error::Encapsulate => f.write_str("Cannot wrap the master key to the recipient"),
+            Error::WriteMac => f.write_str("Cannot write the MAC"),
+            Error::EncryptDigest => f.write_str("Cannot encrypt the plaintext digest"),
+            Error::ReuseDigestKey => f.write_str(
+                "Cannot encrypt a new plaintext digest with an existing master key, as its key and nonce would be reused",
+            ),
+            Error::WriteChunkTable => f.write_str("Cannot write the chunk table"),
+            Error::EncryptManifest => f.write_str("Cannot encrypt the manifest"),
+            Error::EncryptFileInfo => f.write_str("Cannot encrypt the file info"),
+            Error::LegacyKeyslots => f.write_str(
+                "Headers older than V5 only have room for one key (so there can't be any recipients, tokens or extra keys)",
+            ),
+            Error::TooManyKeyslots => write!(
+                f,
+                "There can't be more than {MAX_KEYSLOTS} keyslots (including recipients)"
+            ),
         }
     }
 }
 
 impl std::error::Error for Error {}
 
+/// At most this much of the input is read into memory while the keys are being hashed
+const PREFETCH_LEN: usize = 16 * BLOCK_SIZE;
+
+#[allow(clippy::struct_excessive_bools)]
 pub struct Request<'a, R, W>
 where
     R: Read + Seek,
@@ -52,111 +102,632 @@ where
     // TODO: don't use external types in logic
     pub header_type: HeaderType,
     pub hashing_algorithm: HashingAlgorithm,
+    pub compression: Compression,
+    /// This must be `BLOCK_SIZE`, unless the header is V6 and it's being encrypted in stream mode
+    pub block_size: usize,
+    pub padding: Padding,
+    /// If this is set, encrypting the same data with the same key will always produce the same output (see `core::convergent`)
+    pub convergent: bool,
+    /// The master key is also wrapped to each of these public keys, in additional keyslots (see `core::recipient`)
+    pub recipients: Vec<RecipientPublicKey>,
+    /// The master key is also wrapped with each of these hardware token keys, in additional keyslots (see `core::token`)
+    pub tokens: Vec<TokenKey>,
+    /// The master key is also wrapped with each of these keys (such as other passwords, or a normalized recovery code), in additional keyslots
+    pub extra_keys: Vec<Protected<Vec<u8>>>,
+    /// This records when (and by what) the data was encrypted, and it shouldn't be set for convergent encryption as it'd make the output unique
+    pub metadata: Option<Metadata>,
+    /// If this is set, a MAC of the entire ciphertext is appended, so that modifications are detected before anything is decrypted (see `core::mac`)
+    pub mac: bool,
+    /// If this is set, an encrypted digest of the plaintext is stored in the header, so the output can be verified after it's decrypted (see `core::digest`)
+    pub digest: bool,
+    /// If this is set, a chunk table is appended to the ciphertext, so that any part of it can be decrypted on its own (see `core::seekable`)
+    pub seekable: bool,
+    /// This must be set if any of the keys were derived from keyfiles with `core::key::hash_keyfile()`, so that they're hashed again when decrypting
+    pub keyfile_hash: bool,
+    /// This selects the counter that's used for each block's nonce, and `StreamCounter::Be64` is only supported in `Mode::StreamMode` (see `core::counter`)
+    pub counter: StreamCounter,
+    /// This must be set if `raw_key` was made with `core::key::combine_factors()`, so that both factors are asked for when decrypting
+    pub two_factor: bool,
+    /// If this is set, the entries are encrypted with the header key and stored in the header, so that metadata-only keyslots can list them (see `core::manifest`)
+    pub manifest: Option<Vec<ManifestEntry>>,
+    /// If this is set, the original file's name, modification time and permissions are encrypted with the header key and stored in a V6 header's field section (see `core::file_info`)
+    pub file_info: Option<FileInfo>,
+    /// These are stored in the field section of a V6 header (see `core::header::Field`), alongside the metadata
+    pub fields: Vec<Field>,
 }
 
-pub fn execute<R, W>(req: Request<'_, R, W>) -> Result<(), Error>
-where
-    R: Read + Seek,
-    W: Write + Seek,
-{
-    // 1. generate salt
-    let salt = gen_salt();
+/// These are derived from the master key (see `core::subkeys`), for the optional extensions that need one
+pub(crate) struct ExtensionKeys {
+    pub mac: Option<Protected<[u8; 32]>>,
+    pub digest: Option<Protected<[u8; 32]>>,
+    pub manifest: Option<Protected<[u8; 32]>>,
+}
 
-    // 2. hash key
-    let key = req
-        .hashing_algorithm
-        .hash(req.raw_key, &salt)
-        .map_err(|_| Error::HashKey)?;
+/// This creates a header with a single keyslot for `raw_key`, along with the streams that the data should be encrypted with.
+///
+/// A fresh master key is generated every time, unless `convergent_secrets` are provided.
+///
+/// A keyslot is added for each of the `recipients` and `tokens`, followed by one for each of the `extra_keys` (each with its own salt). There may only be `MAX_KEYSLOTS` in total.
+///
+/// V6 headers in stream mode separate the payload, header and MAC keys (see `core::subkeys`). If `mac`, `digest` or `manifest` are set, their keys are returned too. The digest in the header is a placeholder until it's been calculated.
+///
+/// Headers older than V5 only have room for `raw_key`, and V2/V3 headers have no keyslots at all (the hashed key encrypts the data).
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn init_header(
+    raw_key: Protected<Vec<u8>>,
+    header_type: HeaderType,
+    hashing_algorithm: HashingAlgorithm,
+    compression: Compression,
+    block_size: usize,
+    padding: Padding,
+    convergent_secrets: Option<ConvergentSecrets>,
+    recipients: &[RecipientPublicKey],
+    tokens: Vec<TokenKey>,
+    extra_keys: Vec<Protected<Vec<u8>>>,
+    mac: bool,
+    digest: bool,
+    manifest: bool,
+) -> Result<(Header, EncryptionStreams, ExtensionKeys), Error> {
+    let (header, master_key, keys) = init_keyslots(
+        raw_key,
+        header_type,
+        hashing_algorithm,
+        compression,
+        block_size,
+        padding,
+        convergent_secrets,
+        recipients,
+        tokens,
+        extra_keys,
+        mac,
+        digest,
+        manifest,
+    )?;
+    let streams = init_streams(master_key, &header)?;
 
-    // 3. initialize cipher
-    let cipher = Ciphers::initialize(key, &req.header_type.algorithm)
-        .map_err(|_| Error::InitializeChiphers)?;
+    Ok((header, streams, keys))
+}
 
-    // 4. generate master key
-    let master_key = gen_master_key();
+/// This is the part of `init_header()` that hashes the keys, and it returns the key that the streams should be initialized with
+///
+/// None of it is tied to the current thread, so it may run while the input is being read.
+#[allow(clippy::too_many_arguments, clippy::too_many_lines)]
+fn init_keyslots(
+    raw_key: Protected<Vec<u8>>,
+    header_type: HeaderType,
+    hashing_algorithm: HashingAlgorithm,
+    compression: Compression,
+    block_size: usize,
+    padding: Padding,
+    convergent_secrets: Option<ConvergentSecrets>,
+    recipients: &[RecipientPublicKey],
+    tokens: Vec<TokenKey>,
+    extra_keys: Vec<Protected<Vec<u8>>>,
+    mac: bool,
+    digest: bool,
+    manifest: bool,
+) -> Result<(Header, Protected<[u8; MASTER_KEY_LEN]>, ExtensionKeys), Error> {
+    if 1 + recipients.len() + tokens.len() + extra_keys.len() > MAX_KEYSLOTS {
+        return Err(Error::TooManyKeyslots);
+    }
 
-    let master_key_nonce = gen_nonce(&req.header_type.algorithm, &Mode::MemoryMode);
+    let legacy = header_type.version < HeaderVersion::V5;
+    if legacy && (!recipients.is_empty() || !tokens.is_empty() || !extra_keys.is_empty()) {
+        return Err(Error::LegacyKeyslots);
+    }
 
-    // 5. encrypt master key
-    let master_key_encrypted = {
-        let encrypted_key = cipher
-            .encrypt(master_key_nonce.as_slice(), master_key.as_slice())
-            .map_err(|_| Error::EncryptMasterKey)?;
+    // 1. generate salt, master key and nonces
+    let convergent = convergent_secrets.is_some();
+    let (salt, master_key, master_key_nonce, header_nonce) = match convergent_secrets {
+        Some(secrets) => (
+            secrets.salt,
+            secrets.master_key,
+            secrets.master_key_nonce,
+            secrets.nonce,
+        ),
+        None => (
+            gen_salt(),
+            gen_master_key(),
+            gen_nonce(&header_type.algorithm, &Mode::MemoryMode),
+            gen_nonce(&header_type.algorithm, &header_type.mode),
+        ),
+    };
 
-        let mut encrypted_key_arr = [0u8; ENCRYPTED_MASTER_KEY_LEN];
-        let len = ENCRYPTED_MASTER_KEY_LEN.min(encrypted_key.len());
-        encrypted_key_arr[..len].copy_from_slice(&encrypted_key[..len]);
+    // 2. hash key
+    let key = hashing_algorithm
+        .hash(raw_key, &salt)
+        .map_err(|_| Error::HashKey)?;
 
-        encrypted_key_arr
-    };
+    // V2 and V3 headers only store the salt, as the hashed key is what encrypts the data
+    if header_type.version < HeaderVersion::V4 {
+        let header = Header {
+            header_type,
+            nonce: header_nonce,
+            salt: Some(salt),
+            keyslots: None,
+            compression,
+            block_size,
+            padding,
+            convergent,
+            metadata: None,
+            mac,
+            digest: digest.then_some([0u8; ENCRYPTED_DIGEST_LEN]),
+            seekable: false,
+            keyfile_hash: false,
+            counter: StreamCounter::Le31,
+            subkeys: false,
+            two_factor: false,
+            manifest: None,
+            fields: Vec::new(),
+        };
+        let keys = ExtensionKeys {
+            mac: None,
+            digest: None,
+            manifest: None,
+        };
 
+        return Ok((header, key, keys));
+    }
+
+    // 3. encrypt master key
     let keyslot = Keyslot {
-        encrypted_key: master_key_encrypted,
+        encrypted_key: wrap_master_key(key, &master_key_nonce, &master_key, header_type.algorithm)?,
         nonce: master_key_nonce,
-        hash_algorithm: req.hashing_algorithm,
+        hash_algorithm: hashing_algorithm,
         salt,
+        encapsulated_key: None,
+        token: false,
+        metadata_only: false,
     };
 
-    let keyslots = vec![keyslot];
+    let mut keyslots = vec![keyslot];
+
+    // 4. wrap the master key to the recipients
+    for recipient in recipients {
+        let (key, encapsulated_key) = recipient.encapsulate().map_err(|_| Error::Encapsulate)?;
+        let nonce = gen_nonce(&header_type.algorithm, &Mode::MemoryMode);
 
-    let header_nonce = gen_nonce(&req.header_type.algorithm, &req.header_type.mode);
-    let streams =
-        EncryptionStreams::initialize(master_key, &header_nonce, &req.header_type.algorithm)
-            .map_err(|_| Error::InitializeStreams)?;
+        keyslots.push(Keyslot {
+            encrypted_key: wrap_master_key(key, &nonce, &master_key, header_type.algorithm)?,
+            nonce,
+            hash_algorithm: hashing_algorithm,
+            salt: recipient.fingerprint(),
+            encapsulated_key: Some(encapsulated_key),
+            token: false,
+            metadata_only: false,
+        });
+    }
+
+    // 5. wrap the master key with the token keys
+    for token in tokens {
+        let nonce = gen_nonce(&header_type.algorithm, &Mode::MemoryMode);
+
+        keyslots.push(Keyslot {
+            encrypted_key: wrap_master_key(token.key, &nonce, &master_key, header_type.algorithm)?,
+            nonce,
+            hash_algorithm: hashing_algorithm,
+            salt: token.salt,
+            encapsulated_key: None,
+            token: true,
+            metadata_only: false,
+        });
+    }
+
+    // 6. wrap the master key with the extra keys
+    for extra_key in extra_keys {
+        let salt = gen_salt();
+        let key = hashing_algorithm
+            .hash(extra_key, &salt)
+            .map_err(|_| Error::HashKey)?;
+        let nonce = gen_nonce(&header_type.algorithm, &Mode::MemoryMode);
+
+        keyslots.push(Keyslot {
+            encrypted_key: wrap_master_key(key, &nonce, &master_key, header_type.algorithm)?,
+            nonce,
+            hash_algorithm: hashing_algorithm,
+            salt,
+            encapsulated_key: None,
+            token: false,
+            metadata_only: false,
+        });
+    }
+
+    // the master key is only used directly by older headers
+    let subkeys = header_type.version >= HeaderVersion::V6 && header_type.mode != Mode::MemoryMode;
 
     let header = Header {
-        header_type: req.header_type,
+        header_type,
         nonce: header_nonce,
         salt: None,
+        fields: Vec::new(),
         keyslots: Some(keyslots),
+        compression,
+        block_size,
+        padding,
+        convergent,
+        metadata: None,
+        mac,
+        digest: digest.then_some([0u8; ENCRYPTED_DIGEST_LEN]),
+        seekable: false,
+        keyfile_hash: false,
+        counter: StreamCounter::Le31,
+        subkeys,
+        two_factor: false,
+        manifest: None,
+    };
+
+    let Subkeys {
+        payload,
+        header: header_key,
+        mac: mac_key,
+    } = Subkeys::derive(master_key, &header);
+    let keys = ExtensionKeys {
+        mac: mac.then_some(mac_key),
+        digest: digest.then(|| header_key.clone()),
+        manifest: manifest.then_some(header_key),
     };
 
-    req.writer
-        .borrow_mut()
-        .rewind()
-        .map_err(|_| Error::ResetCursorPosition)?;
-
-    match req.header_writer {
-        None => {
-            req.writer
-                .borrow_mut()
-                .write(&header.serialize().map_err(|_| Error::WriteHeader)?)
-                .map_err(|_| Error::WriteHeader)?;
+    Ok((header, payload, keys))
+}
+
+fn init_streams(
+    master_key: Protected<[u8; MASTER_KEY_LEN]>,
+    header: &Header,
+) -> Result<EncryptionStreams, Error> {
+    let algorithm = &header.header_type.algorithm;
+
+    match header.header_type.mode {
+        Mode::DerivedStreamMode => {
+            EncryptionStreams::initialize_derived(master_key, &header.nonce, algorithm)
         }
-        Some(header_writer) => {
-            header_writer
-                .borrow_mut()
-                .rewind()
-                .map_err(|_| Error::ResetCursorPosition)?;
-
-            header_writer
-                .borrow_mut()
-                .write(&header.serialize().map_err(|_| Error::WriteHeader)?)
-                .map_err(|_| Error::WriteHeader)?;
+        _ if header.counter == StreamCounter::Be64 => {
+            EncryptionStreams::initialize_be64(master_key, &header.nonce, algorithm)
         }
+        _ => EncryptionStreams::initialize(master_key, &header.nonce, algorithm),
     }
+    .map_err(|_| Error::InitializeStreams)
+}
 
-    let aad = header.create_aad().map_err(|_| Error::CreateAad)?;
+// this encrypts the master key with a hashed key (or a recipient's wrapping key), for storing in a keyslot
+fn wrap_master_key(
+    key: Protected<[u8; 32]>,
+    nonce: &[u8],
+    master_key: &Protected<[u8; MASTER_KEY_LEN]>,
+    algorithm: Algorithm,
+) -> Result<[u8; ENCRYPTED_MASTER_KEY_LEN], Error> {
+    let cipher = Ciphers::initialize(key, &algorithm).map_err(|_| Error::InitializeChiphers)?;
+    let encrypted_key = cipher
+        .encrypt(nonce, master_key.as_slice())
+        .map_err(|_| Error::EncryptMasterKey)?;
+
+    Ok(vec_to_arr(encrypted_key))
+}
 
-    let mut reader = req.reader.borrow_mut();
+// the padding depends on the length of the plaintext, so we need that first
+// the reader is rewound either way, so that encryption starts from the beginning
+fn plaintext_len(reader: &mut (impl Read + Seek), padding: Padding) -> Result<u64, Error> {
+    let len = if padding == Padding::Padme {
+        reader
+            .seek(SeekFrom::End(0))
+            .map_err(|_| Error::ResetCursorPosition)?
+    } else {
+        0
+    };
     reader.rewind().map_err(|_| Error::ResetCursorPosition)?;
 
-    let mut writer = req.writer.borrow_mut();
-    streams
-        .encrypt_file(&mut *reader, &mut *writer, &aad)
-        .map_err(|_| Error::EncryptFile)?;
+    Ok(len)
+}
+
+pub fn execute<R, W>(req: Request<'_, R, W>) -> Result<(), Error>
+where
+    R: Read + Seek,
+    W: Write + Seek,
+{
+    let convergent_secrets = if req.convergent {
+        let key = req
+            .hashing_algorithm
+            .hash(req.raw_key.clone(), &CONVERGENT_SALT)
+            .map_err(|_| Error::HashKey)?;
+
+        let mut reader = req.reader.borrow_mut();
+        reader.rewind().map_err(|_| Error::ResetCursorPosition)?;
+
+        let secrets = ConvergentSecrets::derive(
+            &key,
+            &mut *reader,
+            &req.header_type.algorithm,
+            &req.header_type.mode,
+        )
+        .map_err(|_| Error::HashPlaintext)?;
+        Some(secrets)
+    } else {
+        None
+    };
+
+    let len = plaintext_len(&mut *req.reader.borrow_mut(), req.padding)?;
+    // the file info is encrypted with the header key too
+    let manifest = req.manifest.is_some() || req.file_info.is_some();
+
+    // the keys are hashed on another thread, while the start of the input is read
+    // so for short jobs, we only wait for whichever takes longer (rather than both of them)
+    let (init, prefetched) = std::thread::scope(|scope| {
+        let recipients = &req.recipients;
+        let init = scope.spawn(move || {
+            init_keyslots(
+                req.raw_key,
+                req.header_type,
+                req.hashing_algorithm,
+                req.compression,
+                req.block_size,
+                req.padding,
+                convergent_secrets,
+                recipients,
+                req.tokens,
+                req.extra_keys,
+                req.mac,
+                req.digest,
+                manifest,
+            )
+        });
+
+        let prefetched = prefetch(&mut *req.reader.borrow_mut(), || init.is_finished());
+        let init = init
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+
+        (init, prefetched)
+    });
+    let (mut header, master_key, keys) = init?;
+    let prefetched = prefetched?;
+
+    header.metadata = req.metadata;
+    header.seekable = req.seekable;
+    header.keyfile_hash = req.keyfile_hash;
+    header.counter = req.counter;
+    header.two_factor = req.two_factor;
+    header.fields = req.fields;
+    if let (Some(entries), Some(key)) = (&req.manifest, &keys.manifest) {
+        let encrypted_manifest = manifest::encrypt(key, &header.header_type.algorithm, entries)
+            .map_err(|_| Error::EncryptManifest)?;
+        header.manifest = Some(encrypted_manifest);
+    }
+    if let (Some(info), Some(key)) = (&req.file_info, &keys.manifest) {
+        let encrypted_info = file_info::encrypt(key, &header.header_type.algorithm, info)
+            .map_err(|_| Error::EncryptFileInfo)?;
+        header.fields.push(Field {
+            tag: FILE_INFO_FIELD,
+            value: encrypted_info,
+        });
+    }
+
+    let mut input = req.reader.borrow_mut();
+    let mut reader = prefetched.expose().as_slice().chain(&mut *input);
+    encrypt_with_header(
+        header,
+        master_key,
+        keys,
+        &mut reader,
+        len,
+        req.writer,
+        req.header_writer,
+    )
+}
+
+/// This encrypts the reader with an existing header and the master key that it was made with, so that every keyslot still unlocks it
+///
+/// Only the nonce is generated again, as the data is different. The MAC is recalculated if the header has one, and everything else (including the manifest and fields) is kept as it is.
+///
+/// Headers with a plaintext digest are refused. The digest is always encrypted with the same nonce, under a key that only depends on the master key (see `core::digest`), so a new digest can't be encrypted without reusing them.
+pub(crate) fn execute_with_master_key<R, W>(
+    mut header: Header,
+    master_key: Protected<[u8; MASTER_KEY_LEN]>,
+    reader: &mut R,
+    writer: &RefCell<W>,
+) -> Result<(), Error>
+where
+    R: Read + Seek,
+    W: Write + Seek,
+{
+    if header.digest.is_some() {
+        return Err(Error::ReuseDigestKey);
+    }
+
+    let len = plaintext_len(reader, header.padding)?;
+    header.nonce = gen_nonce(&header.header_type.algorithm, &header.header_type.mode);
+
+    let Subkeys {
+        payload,
+        mac: mac_key,
+        ..
+    } = Subkeys::derive(master_key, &header);
+    let keys = ExtensionKeys {
+        mac: header.mac.then_some(mac_key),
+        digest: None,
+        manifest: None,
+    };
+
+    encrypt_with_header(header, payload, keys, reader, len, writer, None)
+}
+
+// this writes the header, then encrypts the reader after it (and rewrites the header once the digest is known)
+fn encrypt_with_header<W>(
+    mut header: Header,
+    payload_key: Protected<[u8; MASTER_KEY_LEN]>,
+    keys: ExtensionKeys,
+    mut reader: &mut impl Read,
+    len: u64,
+    writer: &RefCell<W>,
+    header_writer: Option<&RefCell<W>>,
+) -> Result<(), Error>
+where
+    W: Write + Seek,
+{
+    let streams = init_streams(payload_key, &header)?;
+
+    write_header(&header, writer, header_writer)?;
+    write_placeholder(&header, writer, header_writer)?;
+
+    let aad = header.create_aad().map_err(|_| Error::CreateAad)?;
+
+    let plaintext_digest = {
+        let mut writer = writer.borrow_mut();
+
+        let encrypt = |writer: &mut dyn Write| {
+            let encrypt = |writer: &mut dyn Write| {
+                if keys.digest.is_some() {
+                    let mut reader = DigestReader::new(&mut reader);
+                    encrypt_reader(streams, &header, &mut reader, len, writer, &aad)?;
+                    Ok(Some(reader.finalize()))
+                } else {
+                    encrypt_reader(streams, &header, &mut reader, len, writer, &aad)?;
+                    Ok(None)
+                }
+            };
+
+            // the chunk table is written before the MAC, so it's covered by it
+            if header.seekable {
+                let mut writer = ChunkTableWriter::new(writer, &header);
+                let plaintext_digest = encrypt(&mut writer)?;
+                writer.finish().map_err(|_| Error::WriteChunkTable)?;
+                Ok(plaintext_digest)
+            } else {
+                encrypt(writer)
+            }
+        };
+
+        match &keys.mac {
+            Some(key) => {
+                let mut writer = MacWriter::new(&mut *writer, key, &aad);
+                let plaintext_digest = encrypt(&mut writer)?;
+                writer.finish().map_err(|_| Error::WriteMac)?;
+                plaintext_digest
+            }
+            None => encrypt(&mut *writer)?,
+        }
+    };
+
+    // the digest is only known now, so the header is written again with it
+    if let (Some(plaintext_digest), Some(key)) = (plaintext_digest, keys.digest) {
+        let encrypted_digest =
+            digest::encrypt(key, &header.header_type.algorithm, &plaintext_digest)
+                .map_err(|_| Error::EncryptDigest)?;
+        header.digest = Some(encrypted_digest);
+
+        write_header(&header, writer, header_writer)?;
+    }
+
+    // the copy is written last, so that it's identical to the final header
+    if header_writer.is_none() && header.has_field(BACKUP_HEADER_FIELD) {
+        let header_bytes = header.serialize().map_err(|_| Error::WriteHeader)?;
+        crate::header::backup::write(&mut *writer.borrow_mut(), &header_bytes)
+            .map_err(|_| Error::WriteHeader)?;
+    }
 
     Ok(())
 }
 
+// this reads the start of the input into memory, until `done()` returns true or `PREFETCH_LEN` has been read
+fn prefetch(reader: &mut impl Read, done: impl Fn() -> bool) -> Result<Protected<Vec<u8>>, Error> {
+    let mut buffer = Vec::new();
+
+    while !done() && buffer.len() < PREFETCH_LEN {
+        let read = reader
+            .by_ref()
+            .take(BLOCK_SIZE as u64)
+            .read_to_end(&mut buffer)
+            .map_err(|_| Error::EncryptFile)?;
+        if read == 0 {
+            break;
+        }
+    }
+
+    Ok(Protected::new(buffer))
+}
+
+// the header is written to the start of the header writer if there is one, otherwise it's written to the start of the output
+fn write_header<W>(
+    header: &Header,
+    writer: &RefCell<W>,
+    header_writer: Option<&RefCell<W>>,
+) -> Result<(), Error>
+where
+    W: Write + Seek,
+{
+    let mut writer = header_writer.unwrap_or(writer).borrow_mut();
+    writer.rewind().map_err(|_| Error::ResetCursorPosition)?;
+    writer
+        .write_all(&header.serialize().map_err(|_| Error::WriteHeader)?)
+        .map_err(|_| Error::WriteHeader)
+}
+
+// a detached header may leave random bytes in its place, if it has a placeholder field
+fn write_placeholder<W>(
+    header: &Header,
+    writer: &RefCell<W>,
+    header_writer: Option<&RefCell<W>>,
+) -> Result<(), Error>
+where
+    W: Write + Seek,
+{
+    if header_writer.is_none() || !header.has_field(PLACEHOLDER_FIELD) {
+        return Ok(());
+    }
+
+    let mut placeholder =
+        vec![0u8; usize::try_from(header.get_size()).map_err(|_| Error::WriteHeader)?];
+    rand::thread_rng().fill_bytes(&mut placeholder);
+
+    let mut writer = writer.borrow_mut();
+    writer.rewind().map_err(|_| Error::ResetCursorPosition)?;
+    writer
+        .write_all(&placeholder)
+        .map_err(|_| Error::WriteHeader)
+}
+
+// the reader is padded first if the header asks for it (`len` is the length of the plaintext, which is only needed for padding)
+fn encrypt_reader(
+    streams: EncryptionStreams,
+    header: &Header,
+    reader: &mut impl Read,
+    len: u64,
+    mut writer: &mut dyn Write,
+    aad: &[u8],
+) -> Result<(), Error> {
+    if header.padding == Padding::Padme {
+        let mut reader = PaddedReader::new(reader, len);
+        encrypt_stream(streams, header, &mut reader, &mut writer, aad)
+    } else {
+        encrypt_stream(streams, header, reader, &mut writer, aad)
+    }
+}
+
+// compressed streams frame each block, so they need encrypting differently
+fn encrypt_stream(
+    streams: EncryptionStreams,
+    header: &Header,
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    aad: &[u8],
+) -> Result<(), Error> {
+    match header.compression {
+        Compression::None => streams.encrypt_file(reader, writer, aad, header.block_size),
+        Compression::Zstd(level) => {
+            streams.encrypt_file_compressed(reader, writer, aad, header.block_size, level, false)
+        }
+        Compression::ZstdPadded(level) => {
+            streams.encrypt_file_compressed(reader, writer, aad, header.block_size, level, true)
+        }
+    }
+    .map_err(|_| Error::EncryptFile)
+}
+
 // WARNING! Very expensive tests!
 // TODO(pleshevskiy): think about optimizations
 #[cfg(test)]
 pub mod tests {
     use std::io::Cursor;
 
-    use core::header::HeaderVersion;
-    use core::primitives::Algorithm;
+    use core::primitives::{Algorithm, BLOCK_SIZE};
 
     use super::*;
 
@@ -235,6 +806,45 @@ pub mod tests {
         0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
     ];
 
+    /// This encrypts `reader` with `PASSWORD`, in a V6 header with XChaCha20-Poly1305 in stream mode, and with every other option off
+    ///
+    /// Tests override what they need with `Request { mac: true, ..request(&reader, &writer) }`
+    pub fn request<'a, R, W>(reader: &'a RefCell<R>, writer: &'a RefCell<W>) -> Request<'a, R, W>
+    where
+        R: Read + Seek,
+        W: Write + Seek,
+    {
+        Request {
+            reader,
+            writer,
+            header_writer: None,
+            raw_key: Protected::new(PASSWORD.to_vec()),
+            header_type: HeaderType {
+                version: HeaderVersion::V6,
+                algorithm: Algorithm::XChaCha20Poly1305,
+                mode: Mode::StreamMode,
+            },
+            hashing_algorithm: HashingAlgorithm::Blake3Balloon(5),
+            compression: Compression::None,
+            block_size: BLOCK_SIZE,
+            padding: Padding::None,
+            convergent: false,
+            recipients: Vec::new(),
+            tokens: Vec::new(),
+            extra_keys: Vec::new(),
+            metadata: None,
+            mac: false,
+            digest: false,
+            seekable: false,
+            keyfile_hash: false,
+            counter: StreamCounter::Le31,
+            two_factor: false,
+            manifest: None,
+            file_info: None,
+            fields: Vec::new(),
+        }
+    }
+
     #[test]
     fn should_encrypt_content_with_v4_version() {
         let mut input_content = b"Hello world";
@@ -244,20 +854,17 @@ pub mod tests {
         let output_cur = RefCell::new(Cursor::new(&mut output_content));
 
         let req = Request {
-            reader: &input_cur,
-            writer: &output_cur,
-            header_writer: None,
-            raw_key: Protected::new(PASSWORD.to_vec()),
             header_type: HeaderType {
                 version: HeaderVersion::V4,
                 algorithm: Algorithm::XChaCha20Poly1305,
                 mode: Mode::StreamMode,
             },
             hashing_algorithm: HashingAlgorithm::Blake3Balloon(4),
+            ..request(&input_cur, &output_cur)
         };
 
         match execute(req) {
-            Ok(_) => {
+            Ok(()) => {
                 assert_eq!(output_content, V4_ENCRYPTED_CONTENT.to_vec());
             }
             Err(e) => {
@@ -276,20 +883,16 @@ pub mod tests {
         let output_cur = RefCell::new(Cursor::new(&mut output_content));
 
         let req = Request {
-            reader: &input_cur,
-            writer: &output_cur,
-            header_writer: None,
-            raw_key: Protected::new(PASSWORD.to_vec()),
             header_type: HeaderType {
                 version: HeaderVersion::V5,
                 algorithm: Algorithm::XChaCha20Poly1305,
                 mode: Mode::StreamMode,
             },
-            hashing_algorithm: HashingAlgorithm::Blake3Balloon(5),
+            ..request(&input_cur, &output_cur)
         };
 
         match execute(req) {
-            Ok(_) => {
+            Ok(()) => {
                 assert_eq!(output_content, V5_ENCRYPTED_CONTENT.to_vec());
             }
             Err(e) => {
@@ -311,20 +914,17 @@ pub mod tests {
         let output_header_cur = RefCell::new(Cursor::new(&mut output_header));
 
         let req = Request {
-            reader: &input_cur,
-            writer: &output_cur,
             header_writer: Some(&output_header_cur),
-            raw_key: Protected::new(PASSWORD.to_vec()),
             header_type: HeaderType {
                 version: HeaderVersion::V5,
                 algorithm: Algorithm::XChaCha20Poly1305,
                 mode: Mode::StreamMode,
             },
-            hashing_algorithm: HashingAlgorithm::Blake3Balloon(5),
+            ..request(&input_cur, &output_cur)
         };
 
         match execute(req) {
-            Ok(_) => {
+            Ok(()) => {
                 assert_eq!(output_content, V5_ENCRYPTED_FULL_DETACHED_CONTENT.to_vec());
                 assert_eq!(output_header, V5_ENCRYPTED_DETACHED_HEADER.to_vec());
             }
@@ -334,4 +934,210 @@ pub mod tests {
             }
         }
     }
+
+    #[test]
+    fn should_flag_hashed_keyfile_in_header() {
+        let mut input_content = b"Hello world";
+        let input_cur = RefCell::new(Cursor::new(&mut input_content));
+
+        let mut output_content = vec![];
+        let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+        let keyfile = vec![7u8; 4 * BLOCK_SIZE + 1];
+        let raw_key = core::key::hash_keyfile(&mut keyfile.as_slice()).unwrap();
+
+        let req = Request {
+            raw_key,
+            keyfile_hash: true,
+            ..request(&input_cur, &output_cur)
+        };
+
+        execute(req).unwrap();
+
+        let (header, _) = Header::deserialize(&mut Cursor::new(&output_content)).unwrap();
+        assert!(header.keyfile_hash);
+    }
+
+    fn encrypt_legacy(
+        version: HeaderVersion,
+        extra_keys: Vec<Protected<Vec<u8>>>,
+    ) -> Result<Vec<u8>, Error> {
+        let mut input_content = b"Hello world";
+        let input_cur = RefCell::new(Cursor::new(&mut input_content));
+
+        let mut output_content = vec![];
+        let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+        execute(Request {
+            header_type: HeaderType {
+                version,
+                algorithm: Algorithm::XChaCha20Poly1305,
+                mode: Mode::StreamMode,
+            },
+            // the KDF is up to the caller, and the parameters that these versions use would make this slow
+            hashing_algorithm: HashingAlgorithm::Argon2id(1),
+            extra_keys,
+            ..request(&input_cur, &output_cur)
+        })?;
+
+        Ok(output_content)
+    }
+
+    #[test]
+    fn should_encrypt_content_with_v2_and_v3_versions() {
+        for version in [HeaderVersion::V2, HeaderVersion::V3] {
+            let output_content = encrypt_legacy(version, Vec::new()).unwrap();
+
+            let mut reader = Cursor::new(&output_content);
+            let (header, aad) = Header::deserialize(&mut reader).unwrap();
+            assert!(header.header_type.version == version);
+            assert!(header.keyslots.is_none());
+            assert_eq!(header.get_size(), 64);
+
+            // V2 headers aren't authenticated, while V3 headers are authenticated in full
+            match version {
+                HeaderVersion::V2 => assert!(aad.is_empty()),
+                _ => assert_eq!(aad, output_content[..64].to_vec()),
+            }
+
+            // the hashed key encrypts the data directly
+            let key = HashingAlgorithm::Argon2id(1)
+                .hash(Protected::new(PASSWORD.to_vec()), &header.salt.unwrap())
+                .unwrap();
+            let streams = core::stream::DecryptionStreams::initialize(
+                key,
+                &header.nonce,
+                &header.header_type.algorithm,
+            )
+            .unwrap();
+
+            let mut plaintext = Vec::new();
+            streams
+                .decrypt_file(&mut reader, &mut plaintext, &aad, header.block_size)
+                .unwrap();
+            assert_eq!(plaintext, b"Hello world".to_vec());
+        }
+    }
+
+    #[test]
+    fn should_not_add_keyslots_to_legacy_headers() {
+        for version in [HeaderVersion::V2, HeaderVersion::V3, HeaderVersion::V4] {
+            assert!(matches!(
+                encrypt_legacy(version, vec![Protected::new(b"another key".to_vec())]),
+                Err(Error::LegacyKeyslots)
+            ));
+        }
+    }
+
+    #[test]
+    fn should_refuse_64_bit_counters_with_short_nonces() {
+        // AES-256-GCM would leave just 3 random bytes in each nonce
+        let mut input_content = b"Hello world";
+        let input_cur = RefCell::new(Cursor::new(&mut input_content));
+
+        let mut output_content = vec![];
+        let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+        let req = Request {
+            header_type: HeaderType {
+                version: HeaderVersion::V6,
+                algorithm: Algorithm::Aes256Gcm,
+                mode: Mode::StreamMode,
+            },
+            counter: StreamCounter::Be64,
+            ..request(&input_cur, &output_cur)
+        };
+
+        assert!(execute(req).is_err());
+
+        assert!(!core::counter::is_supported(&Algorithm::ChaCha20Poly1305));
+        assert!(!core::counter::is_supported(&Algorithm::DeoxysII256));
+        assert!(!core::counter::is_supported(&Algorithm::Ascon128a));
+        assert!(core::counter::is_supported(&Algorithm::XChaCha20Poly1305));
+        assert!(core::counter::is_supported(&Algorithm::Aegis256));
+    }
+
+    fn encrypt_convergent(password: &[u8]) -> Header {
+        let mut input_content = b"Hello world";
+        let input_cur = RefCell::new(Cursor::new(&mut input_content));
+
+        let mut output_content = vec![];
+        let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+        let req = Request {
+            raw_key: Protected::new(password.to_vec()),
+            convergent: true,
+            ..request(&input_cur, &output_cur)
+        };
+
+        execute(req).unwrap();
+        Header::deserialize(&mut Cursor::new(output_content))
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn should_derive_unrelated_convergent_secrets_for_different_keys() {
+        let header = encrypt_convergent(PASSWORD);
+        let same_key = encrypt_convergent(PASSWORD);
+        let other_key = encrypt_convergent(b"another key");
+
+        let salt = |header: &Header| header.keyslots.as_ref().unwrap()[0].salt;
+
+        assert_eq!(header.nonce, same_key.nonce);
+        assert_eq!(salt(&header), salt(&same_key));
+
+        assert_ne!(header.nonce, other_key.nonce);
+        assert_ne!(salt(&header), salt(&other_key));
+    }
+
+    #[test]
+    fn should_not_encrypt_a_new_digest_with_an_existing_master_key() {
+        let mut input_content = b"Hello world";
+        let input_cur = RefCell::new(Cursor::new(&mut input_content));
+
+        let mut output_content = vec![];
+        let output_cur = RefCell::new(Cursor::new(&mut output_content));
+
+        execute(Request {
+            digest: true,
+            ..request(&input_cur, &output_cur)
+        })
+        .unwrap();
+
+        let (header, _) = Header::deserialize(&mut Cursor::new(&output_content)).unwrap();
+        let master_key =
+            core::key::decrypt_master_key(Protected::new(PASSWORD.to_vec()), &header).unwrap();
+
+        let output_cur = RefCell::new(Cursor::new(Vec::new()));
+        assert!(matches!(
+            execute_with_master_key(
+                header,
+                master_key,
+                &mut Cursor::new(b"Hello again"),
+                &output_cur
+            ),
+            Err(Error::ReuseDigestKey)
+        ));
+        assert!(output_cur.into_inner().into_inner().is_empty());
+    }
+
+    #[test]
+    fn should_prefetch_until_done() {
+        let input = vec![1u8; PREFETCH_LEN + BLOCK_SIZE + 1];
+
+        let mut reader = input.as_slice();
+        let prefetched = prefetch(&mut reader, || false).unwrap();
+        assert_eq!(prefetched.expose().len(), PREFETCH_LEN);
+        assert_eq!(reader.len(), BLOCK_SIZE + 1);
+
+        let mut reader = input.as_slice();
+        let prefetched = prefetch(&mut reader, || true).unwrap();
+        assert!(prefetched.expose().is_empty());
+        assert_eq!(reader.len(), input.len());
+
+        let mut reader = &input[..10];
+        let prefetched = prefetch(&mut reader, || false).unwrap();
+        assert_eq!(prefetched.expose().len(), 10);
+    }
 }
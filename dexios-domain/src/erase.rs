@@ -1,8 +1,11 @@
 //! This provides functionality for "shredding" a file.
 //!
+//! Once the contents have been overwritten, the file is truncated, renamed to a random name and has its timestamps reset before it's removed.
+//!
 //! This will not be effective on flash storage, and if you are planning to release a program that uses this function, I'd recommend putting the default number of passes to 1.
 
 use std::io::{Read, Seek, Write};
+use std::num::NonZeroU8;
 use std::path::Path;
 use std::sync::Arc;
 
@@ -29,7 +32,7 @@ impl std::error::Error for Error {}
 
 pub struct Request<P: AsRef<Path>> {
     pub path: P,
-    pub passes: i32,
+    pub passes: NonZeroU8,
 }
 
 pub fn execute<RW, P>(stor: Arc<impl Storage<RW> + 'static>, req: Request<P>) -> Result<(), Error>
@@ -49,7 +52,8 @@ where
     })
     .map_err(Error::Overwrite)?;
 
-    stor.remove_file(file).map_err(|_| Error::RemoveFile)?;
+    stor.remove_file_securely(file)
+        .map_err(|_| Error::RemoveFile)?;
 
     Ok(())
 }
@@ -69,10 +73,10 @@ mod tests {
 
         let req = Request {
             path: "hello.txt",
-            passes: 2,
+            passes: NonZeroU8::new(2).unwrap(),
         };
         match execute(stor.clone(), req) {
-            Ok(_) => assert_eq!(stor.files().get(&PathBuf::from("hello.txt")), None),
+            Ok(()) => assert_eq!(stor.files().get(&PathBuf::from("hello.txt")), None),
             _ => unreachable!(),
         }
     }
@@ -83,7 +87,7 @@ mod tests {
 
         let req = Request {
             path: "hello.txt",
-            passes: 2,
+            passes: NonZeroU8::new(2).unwrap(),
         };
         match execute(stor, req) {
             Err(Error::OpenFile) => {}
@@ -3,6 +3,7 @@
 //! This will not be effective on flash storage, and if you are planning to release a program that uses this function, I'd recommend putting the default number of passes to 1.
 
 use std::io::{Read, Seek, Write};
+use std::num::NonZeroU8;
 use std::sync::Arc;
 
 use crate::storage::Storage;
@@ -33,7 +34,7 @@ where
     RW: Read + Write + Seek,
 {
     pub entry: crate::storage::Entry<RW>,
-    pub passes: i32,
+    pub passes: NonZeroU8,
 }
 
 pub fn execute<RW>(stor: Arc<impl Storage<RW> + 'static>, req: Request<RW>) -> Result<(), Error>
@@ -92,7 +93,7 @@ mod tests {
 
         let req = Request {
             entry: file,
-            passes: 2,
+            passes: NonZeroU8::new(2).unwrap(),
         };
 
         match execute(stor.clone(), req) {
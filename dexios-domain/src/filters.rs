@@ -0,0 +1,194 @@
+//! This contains filters, which transform the contents of matching files while they're being packed or unpacked.
+//!
+//! A filter is written as `<pattern>:<command>`, e.g. `*.sql:gzip -d`. The command is run with the shell, it's given the file's contents on stdin, and whatever it writes to stdout is used in place of them.
+//!
+//! Patterns support `*` (any number of characters) and `?` (any single character). A pattern without a `/` is matched against the file's name, otherwise it's matched against the file's whole path within the archive.
+//!
+//! If more than one filter matches a file, only the first is used. Filters aren't stored within the archive, so unpacking needs to be given the reverse filter if the transformation should be undone.
+
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidFilter(String),
+    RunCommand(String),
+    CommandFailed(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidFilter(filter) => write!(
+                f,
+                "Invalid filter `{filter}` (it should look like `<pattern>:<command>`)"
+            ),
+            Error::RunCommand(command) => write!(f, "Unable to run the filter `{command}`"),
+            Error::CommandFailed(command) => write!(f, "The filter `{command}` failed"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Filter {
+    pub pattern: String,
+    pub command: String,
+}
+
+impl std::str::FromStr for Filter {
+    type Err = Error;
+
+    fn from_str(filter: &str) -> Result<Self, Self::Err> {
+        let (pattern, command) = filter
+            .split_once(':')
+            .map(|(pattern, command)| (pattern.trim(), command.trim()))
+            .filter(|(pattern, command)| !pattern.is_empty() && !command.is_empty())
+            .ok_or_else(|| Error::InvalidFilter(filter.to_string()))?;
+
+        Ok(Self {
+            pattern: pattern.to_string(),
+            command: command.to_string(),
+        })
+    }
+}
+
+impl Filter {
+    /// This checks whether the filter applies to a file, given its path within the archive
+    #[must_use]
+    pub fn matches(&self, path: &str) -> bool {
+        let path = path.replace('\\', "/");
+        let target = if self.pattern.contains('/') {
+            path.as_str()
+        } else {
+            path.rsplit('/').next().unwrap_or_default()
+        };
+
+        wildcard_match(self.pattern.as_bytes(), target.as_bytes())
+    }
+
+    /// This runs the filter's command, and returns what it wrote to stdout
+    pub fn apply(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut child = shell(&self.command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|_| Error::RunCommand(self.command.clone()))?;
+
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| Error::RunCommand(self.command.clone()))?;
+        let mut stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| Error::RunCommand(self.command.clone()))?;
+
+        // stdin is written on another thread, so a command that writes before it's finished reading can't deadlock
+        let output = std::thread::scope(|scope| {
+            let writer = scope.spawn(move || {
+                // the command may exit without reading everything (which is up to it), so this error is ignored
+                stdin.write_all(data).ok();
+            });
+
+            let mut output = Vec::new();
+            let read = stdout.read_to_end(&mut output);
+            writer.join().ok();
+            read.map(|_| output)
+        })
+        .map_err(|_| Error::RunCommand(self.command.clone()))?;
+
+        let status = child
+            .wait()
+            .map_err(|_| Error::RunCommand(self.command.clone()))?;
+        if !status.success() {
+            return Err(Error::CommandFailed(self.command.clone()));
+        }
+
+        Ok(output)
+    }
+}
+
+/// This returns the first filter that applies to a file, given its path within the archive
+#[must_use]
+pub fn find<'a>(filters: &'a [Filter], path: &str) -> Option<&'a Filter> {
+    filters.iter().find(|filter| filter.matches(path))
+}
+
+fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.args(["/C", command]);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.args(["-c", command]);
+        shell
+    }
+}
+
+// this matches `*` and `?`, backtracking to the most recent `*` when a character doesn't match
+fn wildcard_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut star = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_filter() {
+        let filter = "*.sql: gzip -d".parse::<Filter>().unwrap();
+        assert_eq!(filter.pattern, "*.sql");
+        assert_eq!(filter.command, "gzip -d");
+
+        assert!("*.sql".parse::<Filter>().is_err());
+        assert!(":gzip".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn should_match_file_names_and_paths() {
+        let by_name = "*.sql:cat".parse::<Filter>().unwrap();
+        assert!(by_name.matches("dumps/2022/db.sql"));
+        assert!(!by_name.matches("dumps/db.sql.gz"));
+
+        let by_path = "dumps/*/db?.sql:cat".parse::<Filter>().unwrap();
+        assert!(by_path.matches("dumps/2022/db1.sql"));
+        assert!(!by_path.matches("other/2022/db1.sql"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_apply_filter_command() {
+        let filter = "*:tr a-z A-Z".parse::<Filter>().unwrap();
+        assert_eq!(filter.apply(b"hello").unwrap(), b"HELLO");
+
+        let failing = "*:exit 1".parse::<Filter>().unwrap();
+        assert!(failing.apply(b"hello").is_err());
+    }
+}
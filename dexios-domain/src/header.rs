@@ -1,8 +1,18 @@
 //! This module contains all Dexios header-related functions, such as dumping the header, restoring a dumped header, or stripping it entirely.
 
+pub mod armor;
+pub mod backup;
 pub mod dump;
+pub mod qr;
+pub mod recover;
 pub mod restore;
+pub mod scan;
 pub mod strip;
+pub mod verify;
+
+use std::io::{Read, Seek, SeekFrom};
+
+use core::header::Header;
 
 #[derive(Debug)]
 pub enum Error {
@@ -12,11 +22,21 @@ pub enum Error {
     Read,
     HeaderSizeParse,
     Rewind,
+    NoBackup,
+    WrongKey,
+    Mismatch,
+    InvalidArmor,
+    TooLargeForQr(usize),
+    InvalidQrImage,
+    NoQrCode,
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        use Error::{HeaderSizeParse, InvalidFile, Read, Rewind, UnsupportedRestore, Write};
+        use Error::{
+            HeaderSizeParse, InvalidArmor, InvalidFile, InvalidQrImage, Mismatch, NoBackup,
+            NoQrCode, Read, Rewind, TooLargeForQr, UnsupportedRestore, Write, WrongKey,
+        };
         match self {
             UnsupportedRestore => f.write_str("The provided request is unsupported with this file. It maybe isn't an encrypted file, or it was encrypted in detached mode."),
             InvalidFile => f.write_str("The file does not contain a valid Dexios header."),
@@ -24,8 +44,215 @@ impl std::fmt::Display for Error {
             Read => f.write_str("Unable to read the data."),
             Rewind => f.write_str("Unable to rewind the stream."),
             HeaderSizeParse => f.write_str("Unable to parse the size of the header."),
+            NoBackup => f.write_str("The file doesn't end with a copy of its header, so there's nothing to recover it from."),
+            WrongKey => f.write_str("The key doesn't unlock any of the header's keyslots."),
+            Mismatch => f.write_str("The header doesn't match the encrypted data (or the data has been modified)."),
+            InvalidArmor => f.write_str("The armored header is incomplete, or has been altered (it should be between BEGIN and END lines, and only contain base64)."),
+            TooLargeForQr(len) => write!(f, "The header is {len} bytes, which is too large for a QR code (it can hold up to {} bytes).", qr::MAX_QR_LEN),
+            InvalidQrImage => f.write_str("Unable to read the image (it should be a PNG or JPEG)."),
+            NoQrCode => f.write_str("Unable to find a readable QR code in the image."),
         }
     }
 }
 
 impl std::error::Error for Error {}
+
+// this reads the header's bytes exactly as they're stored, after checking that they're valid
+// they're copied rather than serialized again, so every version is handled (including those that can no longer be written)
+fn read_header_bytes<R>(reader: &mut R) -> Result<Vec<u8>, Error>
+where
+    R: Read + Seek,
+{
+    let start = reader.stream_position().map_err(|_| Error::Read)?;
+    let (header, _) = Header::deserialize(reader).map_err(|_| Error::InvalidFile)?;
+
+    let mut header_bytes = vec![
+        0u8;
+        header
+            .get_size()
+            .try_into()
+            .map_err(|_| Error::HeaderSizeParse)?
+    ];
+    reader
+        .seek(SeekFrom::Start(start))
+        .map_err(|_| Error::Rewind)?;
+    reader
+        .read_exact(&mut header_bytes)
+        .map_err(|_| Error::Read)?;
+
+    Ok(header_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::io::Cursor;
+
+    use core::header::{Field, HeaderType, HeaderVersion, PADDING_FIELD};
+    use core::primitives::{Algorithm, Mode};
+    use core::protected::Protected;
+
+    use crate::encrypt::tests::{V4_ENCRYPTED_CONTENT, V5_ENCRYPTED_CONTENT};
+
+    // V1-V3 headers are 64 bytes, and only their version, algorithm and mode are checked
+    fn legacy_content(version: u8) -> Vec<u8> {
+        let mut content = vec![0xDE, version, 0x0E, 0x01, 0x0C, 0x01];
+        content.extend_from_slice(&[0x55; 58]);
+        content.extend_from_slice(b"encrypted data");
+        content
+    }
+
+    // V6 headers vary in size, so this has a field section to cover
+    fn v6_content() -> Vec<u8> {
+        let encrypted = RefCell::new(Cursor::new(Vec::new()));
+        crate::encrypt::execute(crate::encrypt::Request {
+            raw_key: Protected::new(b"12345678".to_vec()),
+            header_type: HeaderType {
+                version: HeaderVersion::V6,
+                mode: Mode::StreamMode,
+                algorithm: Algorithm::XChaCha20Poly1305,
+            },
+            digest: true,
+            fields: vec![Field {
+                tag: PADDING_FIELD,
+                value: vec![0xAA; 37],
+            }],
+            ..crate::encrypt::tests::request(
+                &RefCell::new(Cursor::new(b"Hello world".to_vec())),
+                &encrypted,
+            )
+        })
+        .unwrap();
+        encrypted.into_inner().into_inner()
+    }
+
+    fn header_size(content: &[u8]) -> usize {
+        let (header, _) = Header::deserialize(&mut Cursor::new(content)).unwrap();
+        header.get_size().try_into().unwrap()
+    }
+
+    #[test]
+    fn should_dump_strip_and_restore_every_version() {
+        let contents = vec![
+            (HeaderVersion::V1, legacy_content(0x01)),
+            (HeaderVersion::V2, legacy_content(0x02)),
+            (HeaderVersion::V3, legacy_content(0x03)),
+            (HeaderVersion::V4, V4_ENCRYPTED_CONTENT.to_vec()),
+            (HeaderVersion::V5, V5_ENCRYPTED_CONTENT.to_vec()),
+            (HeaderVersion::V6, v6_content()),
+        ];
+
+        for (version, content) in contents {
+            let (header, _) = Header::deserialize(&mut Cursor::new(&content)).unwrap();
+            assert!(header.header_type.version == version);
+            let size = header_size(&content);
+
+            let dumped = RefCell::new(Vec::new());
+            dump::execute(dump::Request {
+                reader: &RefCell::new(Cursor::new(content.clone())),
+                writer: &dumped,
+                format: dump::Format::Raw,
+            })
+            .unwrap();
+            let dumped = dumped.into_inner();
+            assert_eq!(dumped, content[..size].to_vec(), "{version}");
+
+            let stripped = RefCell::new(Cursor::new(content.clone()));
+            strip::execute(strip::Request { handle: &stripped }).unwrap();
+            {
+                let stripped = stripped.borrow();
+                let stripped = stripped.get_ref();
+                assert!(stripped[..size].iter().all(|b| *b == 0), "{version}");
+                assert_eq!(stripped[size..], content[size..], "{version}");
+            }
+
+            stripped.borrow_mut().rewind().unwrap();
+            restore::execute(restore::Request {
+                reader: &RefCell::new(Cursor::new(dumped)),
+                writer: &stripped,
+                qr_image: false,
+            })
+            .unwrap();
+            assert_eq!(stripped.into_inner().into_inner(), content, "{version}");
+        }
+    }
+
+    #[test]
+    fn should_restore_an_armored_header() {
+        let content = v6_content();
+
+        let dumped = RefCell::new(Vec::new());
+        dump::execute(dump::Request {
+            reader: &RefCell::new(Cursor::new(content.clone())),
+            writer: &dumped,
+            format: dump::Format::Armor,
+        })
+        .unwrap();
+        let dumped = String::from_utf8(dumped.into_inner()).unwrap();
+        assert!(dumped.starts_with(armor::BEGIN));
+
+        let stripped = RefCell::new(Cursor::new(content.clone()));
+        strip::execute(strip::Request { handle: &stripped }).unwrap();
+        stripped.borrow_mut().rewind().unwrap();
+
+        // it may have been pasted somewhere that uses different line endings
+        restore::execute(restore::Request {
+            reader: &RefCell::new(Cursor::new(dumped.replace('\n', "\r\n").into_bytes())),
+            writer: &stripped,
+            qr_image: false,
+        })
+        .unwrap();
+        assert_eq!(stripped.into_inner().into_inner(), content);
+    }
+
+    #[test]
+    fn should_restore_a_header_from_a_qr_code() {
+        let content = v6_content();
+
+        let dumped = RefCell::new(Vec::new());
+        dump::execute(dump::Request {
+            reader: &RefCell::new(Cursor::new(content.clone())),
+            writer: &dumped,
+            format: dump::Format::QrPng,
+        })
+        .unwrap();
+
+        let stripped = RefCell::new(Cursor::new(content.clone()));
+        strip::execute(strip::Request { handle: &stripped }).unwrap();
+        stripped.borrow_mut().rewind().unwrap();
+
+        restore::execute(restore::Request {
+            reader: &RefCell::new(Cursor::new(dumped.into_inner())),
+            writer: &stripped,
+            qr_image: true,
+        })
+        .unwrap();
+        assert_eq!(stripped.into_inner().into_inner(), content);
+    }
+
+    #[test]
+    fn should_only_restore_to_stripped_files() {
+        let content = v6_content();
+        let header = content[..header_size(&content)].to_vec();
+
+        // the file still has its header
+        let restored = RefCell::new(Cursor::new(content.clone()));
+        assert!(restore::execute(restore::Request {
+            reader: &RefCell::new(Cursor::new(header.clone())),
+            writer: &restored,
+            qr_image: false,
+        })
+        .is_err());
+        assert_eq!(restored.into_inner().into_inner(), content);
+
+        // the file is shorter than the header
+        let restored = RefCell::new(Cursor::new(vec![0u8; header.len() - 1]));
+        assert!(restore::execute(restore::Request {
+            reader: &RefCell::new(Cursor::new(header)),
+            writer: &restored,
+            qr_image: false,
+        })
+        .is_err());
+    }
+}
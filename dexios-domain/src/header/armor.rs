@@ -0,0 +1,174 @@
+//! This provides an ASCII-armored form of a dumped header, so that it can be stored in text-only places (e.g. a password manager's notes) or printed.
+//!
+//! The header is encoded with standard base64 (with padding), in lines of `LINE_LEN` characters, between `BEGIN` and `END` lines, much like PEM:
+//!
+//! ```text
+//! -----BEGIN DEXIOS HEADER-----
+//! 3gYODAEB...
+//! -----END DEXIOS HEADER-----
+//! ```
+//!
+//! Whitespace (including `\r`) is ignored when it's decoded, as are any lines before `BEGIN`.
+
+use super::Error;
+use std::io::{Read, Seek};
+
+pub const BEGIN: &str = "-----BEGIN DEXIOS HEADER-----";
+pub const END: &str = "-----END DEXIOS HEADER-----";
+
+/// This is the number of base64 characters on each line
+const LINE_LEN: usize = 64;
+
+/// Armored headers that are larger than this aren't read into memory
+const MAX_ARMORED_LEN: u64 = 2 * 1024 * 1024;
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[must_use]
+pub fn encode(header_bytes: &[u8]) -> String {
+    let mut encoded = Vec::with_capacity(header_bytes.len().div_ceil(3) * 4);
+    for chunk in header_bytes.chunks(3) {
+        let b = [0, 1, 2].map(|i| chunk.get(i).copied().unwrap_or_default());
+        let sextets = [
+            b[0] >> 2,
+            (b[0] & 0x03) << 4 | b[1] >> 4,
+            (b[1] & 0x0F) << 2 | b[2] >> 6,
+            b[2] & 0x3F,
+        ];
+
+        // a chunk of n bytes needs n + 1 characters, and the rest are padding
+        for (i, sextet) in sextets.into_iter().enumerate() {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[usize::from(sextet)]);
+            } else {
+                encoded.push(b'=');
+            }
+        }
+    }
+
+    let mut armored = String::from(BEGIN);
+    armored.push('\n');
+    for line in encoded.chunks(LINE_LEN) {
+        // the alphabet is ASCII, so this can't fail
+        armored.push_str(std::str::from_utf8(line).unwrap_or_default());
+        armored.push('\n');
+    }
+    armored.push_str(END);
+    armored.push('\n');
+    armored
+}
+
+fn decode_char(c: u8) -> Option<u8> {
+    ALPHABET
+        .iter()
+        .position(|a| *a == c)
+        .and_then(|i| u8::try_from(i).ok())
+}
+
+/// This returns the bytes between the `BEGIN` and `END` lines
+///
+/// The bytes aren't checked to be a valid header, as `super::read_header_bytes()` does that.
+pub fn decode(armored: &str) -> Result<Vec<u8>, Error> {
+    let start = armored.find(BEGIN).ok_or(Error::InvalidArmor)? + BEGIN.len();
+    let len = armored[start..].find(END).ok_or(Error::InvalidArmor)?;
+
+    let encoded: Vec<u8> = armored[start..start + len]
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    if encoded.is_empty() || !encoded.len().is_multiple_of(4) {
+        return Err(Error::InvalidArmor);
+    }
+
+    let mut decoded = Vec::with_capacity(encoded.len() / 4 * 3);
+    let last = encoded.len() / 4 - 1;
+    for (i, quad) in encoded.chunks(4).enumerate() {
+        // padding is only allowed at the end of the last group
+        let padding = quad.iter().rev().take_while(|c| **c == b'=').count();
+        if padding > 2 || (padding > 0 && i != last) {
+            return Err(Error::InvalidArmor);
+        }
+
+        let mut s = [0u8; 4];
+        for (sextet, c) in s.iter_mut().zip(&quad[..4 - padding]) {
+            *sextet = decode_char(*c).ok_or(Error::InvalidArmor)?;
+        }
+
+        let bytes = [
+            s[0] << 2 | s[1] >> 4,
+            s[1] << 4 | s[2] >> 2,
+            s[2] << 6 | s[3],
+        ];
+        decoded.extend_from_slice(&bytes[..3 - padding]);
+    }
+
+    Ok(decoded)
+}
+
+/// This decodes the reader's contents if it's an armored header, and otherwise rewinds it and returns `None`
+pub fn read<R>(reader: &mut R) -> Result<Option<Vec<u8>>, Error>
+where
+    R: Read + Seek,
+{
+    let mut armored = Vec::new();
+    reader
+        .take(MAX_ARMORED_LEN)
+        .read_to_end(&mut armored)
+        .map_err(|_| Error::Read)?;
+
+    // binary headers aren't valid UTF-8, so anything else is left for `super::read_header_bytes()`
+    match std::str::from_utf8(&armored) {
+        Ok(armored) if armored.contains(BEGIN) => decode(armored).map(Some),
+        _ => {
+            reader.rewind().map_err(|_| Error::Rewind)?;
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_encode_and_decode() {
+        for len in 0..=70u8 {
+            let bytes: Vec<u8> = (0..len).map(|i| i.wrapping_mul(37)).collect();
+            let armored = encode(&bytes);
+
+            assert!(armored.starts_with(BEGIN));
+            assert!(armored.lines().all(|line| line.len() <= LINE_LEN));
+            if len > 0 {
+                assert_eq!(decode(&armored).unwrap(), bytes, "{len}");
+            }
+        }
+
+        assert_eq!(
+            encode(b"Hello world"),
+            format!("{BEGIN}\nSGVsbG8gd29ybGQ=\n{END}\n")
+        );
+    }
+
+    #[test]
+    fn should_decode_pasted_armor() {
+        let pasted =
+            format!("Notes for my backup:\r\n  {BEGIN}\r\n  SGVsbG8g\r\n  d29ybGQ=\r\n  {END}\r\n");
+        assert_eq!(decode(&pasted).unwrap(), b"Hello world".to_vec());
+    }
+
+    #[test]
+    fn should_not_decode_invalid_armor() {
+        for invalid in [
+            format!("{BEGIN}\nSGVsbG8gd29ybGQ=\n"),
+            format!("{BEGIN}\nSGVsbG8gd29ybGQ\n{END}"),
+            format!("{BEGIN}\nSGVs=G8gd29ybGQ=\n{END}"),
+            format!("{BEGIN}\nSGVsbG8gd29yb*Q=\n{END}"),
+            format!("{BEGIN}\n{END}"),
+        ] {
+            assert!(
+                matches!(decode(&invalid), Err(Error::InvalidArmor)),
+                "{invalid}"
+            );
+        }
+    }
+}
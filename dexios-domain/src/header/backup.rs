@@ -0,0 +1,296 @@
+//! This provides functionality for the copy of a header that may be appended to the end of a file (see `core::header::BACKUP_HEADER_FIELD`), much like LUKS' secondary header.
+//!
+//! The copy is followed by its length (a little-endian `u64`) and `BACKUP_MAGIC`, so that it can be found from the end of the file without reading the header at the start.
+
+use super::Error;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+
+use core::header::{Header, BACKUP_HEADER_FIELD};
+
+/// This marks the end of a file that has a copy of its header before it
+pub const BACKUP_MAGIC: [u8; 8] = *b"DXBKHDR1";
+
+/// This is the length of the copy's length and the magic, which follow the copy itself
+const TRAILER_LEN: usize = 8 + BACKUP_MAGIC.len();
+
+/// Copies that claim to be larger than this are rejected, rather than being read into memory
+const MAX_BACKUP_LEN: u64 = 1024 * 1024;
+
+pub struct Backup {
+    pub header: Header,
+    pub aad: Vec<u8>,
+    /// These are the copy's bytes, exactly as they're stored
+    pub bytes: Vec<u8>,
+    /// This is where the copy starts, which is also where the data before it ends
+    pub offset: u64,
+}
+
+/// This appends a copy of the header to the end of the writer
+pub fn write(writer: &mut (impl Write + Seek), header_bytes: &[u8]) -> Result<(), Error> {
+    writer.seek(SeekFrom::End(0)).map_err(|_| Error::Write)?;
+    writer.write_all(header_bytes).map_err(|_| Error::Write)?;
+    writer
+        .write_all(&(header_bytes.len() as u64).to_le_bytes())
+        .map_err(|_| Error::Write)?;
+    writer.write_all(&BACKUP_MAGIC).map_err(|_| Error::Write)
+}
+
+/// This reads the copy of the header from the end of the reader, if it has one
+///
+/// The reader's position is left wherever reading stopped.
+pub fn read<R>(reader: &mut R) -> Result<Option<Backup>, Error>
+where
+    R: Read + Seek,
+{
+    let end = reader.seek(SeekFrom::End(0)).map_err(|_| Error::Read)?;
+    let Some(trailer_start) = end.checked_sub(TRAILER_LEN as u64) else {
+        return Ok(None);
+    };
+
+    reader
+        .seek(SeekFrom::Start(trailer_start))
+        .map_err(|_| Error::Read)?;
+    let mut trailer = [0u8; TRAILER_LEN];
+    reader.read_exact(&mut trailer).map_err(|_| Error::Read)?;
+    if trailer[8..] != BACKUP_MAGIC {
+        return Ok(None);
+    }
+
+    let mut len = [0u8; 8];
+    len.copy_from_slice(&trailer[..8]);
+    let len = u64::from_le_bytes(len);
+    let offset = trailer_start
+        .checked_sub(len)
+        .filter(|_| len <= MAX_BACKUP_LEN)
+        .ok_or(Error::InvalidFile)?;
+
+    reader
+        .seek(SeekFrom::Start(offset))
+        .map_err(|_| Error::Read)?;
+    let mut bytes = vec![0u8; len.try_into().map_err(|_| Error::HeaderSizeParse)?];
+    reader.read_exact(&mut bytes).map_err(|_| Error::Read)?;
+
+    let (header, aad) =
+        Header::deserialize(&mut Cursor::new(&bytes)).map_err(|_| Error::InvalidFile)?;
+    if header.get_size() != len || !header.has_field(BACKUP_HEADER_FIELD) {
+        return Err(Error::InvalidFile);
+    }
+
+    Ok(Some(Backup {
+        header,
+        aad,
+        bytes,
+        offset,
+    }))
+}
+
+/// This returns the copy of the header, but only if the header at the start of the reader can't be read
+///
+/// A header that was stripped (see `super::strip`) isn't damaged, so its copy isn't returned.
+pub fn fallback<R>(reader: &mut R) -> Result<Option<Backup>, Error>
+where
+    R: Read + Seek,
+{
+    reader.rewind().map_err(|_| Error::Rewind)?;
+    if Header::deserialize(&mut *reader).is_ok() {
+        return Ok(None);
+    }
+
+    let mut tag = [0u8; 6];
+    reader.rewind().map_err(|_| Error::Rewind)?;
+    if reader.read_exact(&mut tag).is_ok() && tag.iter().all(|b| *b == 0) {
+        return Ok(None);
+    }
+
+    read(reader)
+}
+
+/// This writes the header over its copy, so that the copy stays up to date when the header is changed in place (e.g. when a key is added)
+///
+/// Nothing is written if the header doesn't have a copy, or if the handle doesn't end with one (e.g. it's a dumped header).
+pub fn refresh<RW>(handle: &mut RW, header: &Header) -> Result<(), Error>
+where
+    RW: Read + Write + Seek,
+{
+    if !header.has_field(BACKUP_HEADER_FIELD) {
+        return Ok(());
+    }
+
+    let Some(backup) = read(handle)? else {
+        return Ok(());
+    };
+    let header_bytes = header.serialize().map_err(|_| Error::Write)?;
+    if header_bytes.len() != backup.bytes.len() {
+        return Err(Error::HeaderSizeParse);
+    }
+
+    handle
+        .seek(SeekFrom::Start(backup.offset))
+        .map_err(|_| Error::Write)?;
+    handle.write_all(&header_bytes).map_err(|_| Error::Write)
+}
+
+/// This presents everything before the copy of a header, so that the copy isn't mistaken for part of the data
+///
+/// Seeking relative to the end is relative to where the copy starts.
+pub struct TrimmedReader<R>
+where
+    R: Read + Seek,
+{
+    inner: R,
+    end: u64,
+    position: u64,
+}
+
+impl<R> TrimmedReader<R>
+where
+    R: Read + Seek,
+{
+    /// This trims the copy from the end of `inner` if the header has one, and otherwise presents all of it
+    pub fn new(mut inner: R, header: &Header) -> Result<Self, Error> {
+        let position = inner.stream_position().map_err(|_| Error::Read)?;
+        let end = if header.has_field(BACKUP_HEADER_FIELD) {
+            read(&mut inner)?.ok_or(Error::InvalidFile)?.offset
+        } else {
+            inner.seek(SeekFrom::End(0)).map_err(|_| Error::Read)?
+        };
+
+        inner
+            .seek(SeekFrom::Start(position))
+            .map_err(|_| Error::Rewind)?;
+
+        Ok(Self {
+            inner,
+            end,
+            position,
+        })
+    }
+}
+
+impl<R> Read for TrimmedReader<R>
+where
+    R: Read + Seek,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = self.end.saturating_sub(self.position);
+        let len = usize::try_from(remaining).map_or(buf.len(), |r| r.min(buf.len()));
+
+        let read = self.inner.read(&mut buf[..len])?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R> Seek for TrimmedReader<R>
+where
+    R: Read + Seek,
+{
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) => self.position.checked_add_signed(n),
+            SeekFrom::End(n) => self.end.checked_add_signed(n),
+        }
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Unable to seek before the start of the data",
+            )
+        })?;
+
+        self.position = self.inner.seek(SeekFrom::Start(position))?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use core::header::{Field, HeaderType, HeaderVersion};
+    use core::primitives::{Algorithm, Mode};
+    use core::protected::Protected;
+    use std::cell::RefCell;
+
+    use crate::encrypt::tests::PASSWORD;
+
+    pub fn encrypt_with_backup(mac: bool) -> Vec<u8> {
+        let encrypted = RefCell::new(Cursor::new(Vec::new()));
+        crate::encrypt::execute(crate::encrypt::Request {
+            header_type: HeaderType {
+                version: HeaderVersion::V6,
+                mode: Mode::StreamMode,
+                algorithm: Algorithm::XChaCha20Poly1305,
+            },
+            mac,
+            digest: true,
+            fields: vec![Field {
+                tag: BACKUP_HEADER_FIELD,
+                value: Vec::new(),
+            }],
+            ..crate::encrypt::tests::request(
+                &RefCell::new(Cursor::new(b"Hello world".to_vec())),
+                &encrypted,
+            )
+        })
+        .unwrap();
+        encrypted.into_inner().into_inner()
+    }
+
+    fn decrypt(content: Vec<u8>) -> Result<Vec<u8>, crate::decrypt::Error> {
+        let output = RefCell::new(Cursor::new(Vec::new()));
+        crate::decrypt::execute(crate::decrypt::Request {
+            header_reader: None,
+            reader: &RefCell::new(Cursor::new(content)),
+            writer: &output,
+            raw_key: Protected::new(PASSWORD.to_vec()),
+            master_key: None,
+            identity: None,
+            on_decrypted_header: None,
+            on_file_info: None,
+        })?;
+        Ok(output.into_inner().into_inner())
+    }
+
+    #[test]
+    fn should_append_a_copy_of_the_header() {
+        let content = encrypt_with_backup(false);
+        let (header, _) = Header::deserialize(&mut Cursor::new(&content)).unwrap();
+        let size = usize::try_from(header.get_size()).unwrap();
+
+        let backup = read(&mut Cursor::new(&content)).unwrap().unwrap();
+        assert_eq!(backup.bytes, content[..size].to_vec());
+        assert_eq!(backup.offset, (content.len() - size - 16) as u64);
+
+        // the header at the start is readable, so there's nothing to fall back to
+        assert!(fallback(&mut Cursor::new(&content)).unwrap().is_none());
+    }
+
+    #[test]
+    fn should_decrypt_with_the_copy_if_the_header_is_damaged() {
+        for mac in [false, true] {
+            let mut content = encrypt_with_backup(mac);
+            assert_eq!(decrypt(content.clone()).unwrap(), b"Hello world".to_vec());
+
+            content[0] = 0;
+            content[1] = 0xFF;
+            assert!(fallback(&mut Cursor::new(&content)).unwrap().is_some());
+            assert_eq!(decrypt(content).unwrap(), b"Hello world".to_vec());
+        }
+    }
+
+    #[test]
+    fn should_only_read_up_to_the_copy() {
+        let content = encrypt_with_backup(false);
+        let backup = read(&mut Cursor::new(&content)).unwrap().unwrap();
+
+        let mut reader = TrimmedReader::new(Cursor::new(&content), &backup.header).unwrap();
+        let mut trimmed = Vec::new();
+        reader.read_to_end(&mut trimmed).unwrap();
+        assert_eq!(
+            trimmed,
+            content[..usize::try_from(backup.offset).unwrap()].to_vec()
+        );
+        assert_eq!(reader.seek(SeekFrom::End(0)).unwrap(), backup.offset);
+    }
+}
@@ -1,30 +1,49 @@
 //! This provides functionality for dumping a header that adheres to the Dexios format.
+//!
+//! The header can be written as it's stored, or in a form that's easier to keep somewhere else (see `super::armor` and `super::qr`).
 
 use super::Error;
 use std::cell::RefCell;
 use std::io::{Read, Seek, Write};
 
-use core::header::Header;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// The header's bytes, exactly as they're stored
+    Raw,
+    /// A base64 text block (see `super::armor`)
+    Armor,
+    /// A PNG of a QR code (see `super::qr`)
+    QrPng,
+    /// A QR code drawn with Unicode block characters, for showing in a terminal
+    QrText,
+}
 
 pub struct Request<'a, R, W>
 where
     R: Read + Seek,
-    W: Write + Seek,
+    W: Write,
 {
     pub reader: &'a RefCell<R>,
     pub writer: &'a RefCell<W>,
+    pub format: Format,
 }
 
 pub fn execute<R, W>(req: Request<'_, R, W>) -> Result<(), Error>
 where
     R: Read + Seek,
-    W: Write + Seek,
+    W: Write,
 {
-    let (header, _) =
-        Header::deserialize(&mut *req.reader.borrow_mut()).map_err(|_| Error::InvalidFile)?;
+    let header_bytes = super::read_header_bytes(&mut *req.reader.borrow_mut())?;
+    let header_bytes = match req.format {
+        Format::Raw => header_bytes,
+        Format::Armor => super::armor::encode(&header_bytes).into_bytes(),
+        Format::QrPng => super::qr::encode_png(&header_bytes)?,
+        Format::QrText => super::qr::encode_text(&header_bytes)?.into_bytes(),
+    };
 
-    header
-        .write(&mut *req.writer.borrow_mut())
+    req.writer
+        .borrow_mut()
+        .write_all(&header_bytes)
         .map_err(|_| Error::Write)?;
 
     Ok(())
@@ -0,0 +1,144 @@
+//! This provides a QR code form of a dumped header, so that it can be kept on paper and scanned back in (e.g. with a phone's camera, or a scanner).
+//!
+//! The header's bytes are stored as they are, with medium error correction so that a creased or smudged print can still be read. A QR code can hold up to `MAX_QR_LEN` bytes at that level, which is plenty for a header with a handful of keyslots.
+//!
+//! When decoding, an armored header (see `super::armor`) is accepted too, in case the QR code was made from that text by another tool.
+
+use super::Error;
+use std::io::{Cursor, Read};
+
+use qrcode::render::unicode::Dense1x2;
+use qrcode::{EcLevel, QrCode};
+
+/// This is the most that a QR code can hold at medium error correction (version 40, in byte mode)
+pub const MAX_QR_LEN: usize = 2331;
+
+/// Images that are larger than this aren't read into memory
+const MAX_IMAGE_LEN: u64 = 64 * 1024 * 1024;
+
+/// This is the smallest that the PNG will be (in pixels), so that each module is large enough to print
+const MIN_PNG_DIMENSIONS: u32 = 600;
+
+fn qr_code(header_bytes: &[u8]) -> Result<QrCode, Error> {
+    if header_bytes.len() > MAX_QR_LEN {
+        return Err(Error::TooLargeForQr(header_bytes.len()));
+    }
+
+    QrCode::with_error_correction_level(header_bytes, EcLevel::M)
+        .map_err(|_| Error::TooLargeForQr(header_bytes.len()))
+}
+
+/// This renders the header as a black and white PNG
+pub fn encode_png(header_bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    let image = qr_code(header_bytes)?
+        .render::<image::Luma<u8>>()
+        .min_dimensions(MIN_PNG_DIMENSIONS, MIN_PNG_DIMENSIONS)
+        .build();
+
+    let mut png = Cursor::new(Vec::new());
+    image
+        .write_to(&mut png, image::ImageFormat::Png)
+        .map_err(|_| Error::Write)?;
+
+    Ok(png.into_inner())
+}
+
+/// This renders the header with Unicode block characters, so that it can be scanned straight from a terminal
+///
+/// The colours are inverted, as most terminals draw light text on a dark background.
+pub fn encode_text(header_bytes: &[u8]) -> Result<String, Error> {
+    let text = qr_code(header_bytes)?
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build();
+
+    Ok(text + "\n")
+}
+
+/// This finds a QR code within the image (a PNG or JPEG), and returns the header bytes that it holds
+///
+/// The bytes aren't checked to be a valid header, as `super::read_header_bytes()` does that.
+pub fn decode(image_bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    let image = image::load_from_memory(image_bytes)
+        .map_err(|_| Error::InvalidQrImage)?
+        .to_luma8();
+
+    let (width, height) = image.dimensions();
+    let mut prepared = rqrr::PreparedImage::prepare_from_greyscale(
+        width.try_into().map_err(|_| Error::InvalidQrImage)?,
+        height.try_into().map_err(|_| Error::InvalidQrImage)?,
+        |x, y| {
+            // the dimensions came from the image, so these always fit
+            let x = u32::try_from(x).unwrap_or_default();
+            let y = u32::try_from(y).unwrap_or_default();
+            image.get_pixel(x, y).0[0]
+        },
+    );
+
+    // a photo may have caught other codes too, so the first one that decodes is used
+    let decoded = prepared
+        .detect_grids()
+        .into_iter()
+        .find_map(|grid| {
+            let mut decoded = Vec::new();
+            grid.decode_to(&mut decoded).ok().map(|_| decoded)
+        })
+        .ok_or(Error::NoQrCode)?;
+
+    match std::str::from_utf8(&decoded) {
+        Ok(armored) if armored.contains(super::armor::BEGIN) => super::armor::decode(armored),
+        _ => Ok(decoded),
+    }
+}
+
+/// This reads an image of a QR code from the reader, and decodes it
+pub fn read<R>(reader: &mut R) -> Result<Vec<u8>, Error>
+where
+    R: Read,
+{
+    let mut image_bytes = Vec::new();
+    reader
+        .take(MAX_IMAGE_LEN)
+        .read_to_end(&mut image_bytes)
+        .map_err(|_| Error::Read)?;
+
+    decode(&image_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_encode_and_decode_a_png() {
+        let bytes: Vec<u8> = (0..=255u8).chain(0..=255u8).collect();
+        let png = encode_png(&bytes).unwrap();
+
+        assert!(png.starts_with(b"\x89PNG"));
+        assert_eq!(decode(&png).unwrap(), bytes);
+    }
+
+    #[test]
+    fn should_not_encode_a_header_that_is_too_large() {
+        assert!(encode_text(&[0xAA; MAX_QR_LEN]).is_ok());
+        assert!(matches!(
+            encode_text(&[0xAA; MAX_QR_LEN + 1]),
+            Err(Error::TooLargeForQr(len)) if len == MAX_QR_LEN + 1
+        ));
+    }
+
+    #[test]
+    fn should_not_decode_an_image_without_a_qr_code() {
+        let mut png = Cursor::new(Vec::new());
+        image::GrayImage::from_pixel(64, 64, image::Luma([0xFF]))
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+
+        assert!(matches!(decode(png.get_ref()), Err(Error::NoQrCode)));
+        assert!(matches!(
+            decode(b"not an image"),
+            Err(Error::InvalidQrImage)
+        ));
+    }
+}
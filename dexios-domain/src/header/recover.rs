@@ -0,0 +1,55 @@
+//! This provides functionality for recovering a damaged header from the copy at the end of the file (see `super::backup`).
+
+use super::Error;
+use std::cell::RefCell;
+use std::io::{Read, Seek, Write};
+
+pub struct Request<'a, RW>
+where
+    RW: Read + Write + Seek,
+{
+    pub handle: &'a RefCell<RW>,
+}
+
+/// This writes the copy of the header over the header at the start of the file
+///
+/// The header is overwritten even if it still parses, so this also undoes `super::strip`.
+pub fn execute<RW>(req: Request<'_, RW>) -> Result<(), Error>
+where
+    RW: Read + Write + Seek,
+{
+    let mut handle = req.handle.borrow_mut();
+    let backup = super::backup::read(&mut *handle)?.ok_or(Error::NoBackup)?;
+
+    handle.rewind().map_err(|_| Error::Rewind)?;
+    handle.write_all(&backup.bytes).map_err(|_| Error::Write)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    use crate::encrypt::tests::V5_ENCRYPTED_CONTENT;
+    use crate::header::backup::tests::encrypt_with_backup;
+
+    #[test]
+    fn should_recover_a_damaged_header() {
+        let content = encrypt_with_backup(true);
+        let mut damaged = content.clone();
+        damaged[2..40].fill(0xAA);
+
+        let handle = RefCell::new(Cursor::new(damaged));
+        execute(Request { handle: &handle }).unwrap();
+        assert_eq!(handle.into_inner().into_inner(), content);
+
+        // there's no copy to recover from
+        let handle = RefCell::new(Cursor::new(V5_ENCRYPTED_CONTENT.to_vec()));
+        assert!(matches!(
+            execute(Request { handle: &handle }),
+            Err(Error::NoBackup)
+        ));
+    }
+}
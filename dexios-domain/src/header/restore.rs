@@ -1,10 +1,10 @@
 //! This provides functionality for restoring a dumped header that adheres to the Dexios format, provided the target file contains enough empty bytes at the start to do so.
+//!
+//! The dumped header may also be armored (see `super::armor`), or be an image of a QR code (see `super::qr`).
 
 use super::Error;
 use std::cell::RefCell;
-use std::io::{Read, Seek, Write};
-
-use core::header::Header;
+use std::io::{Cursor, Read, Seek, Write};
 
 pub struct Request<'a, R, RW>
 where
@@ -13,6 +13,8 @@ where
 {
     pub reader: &'a RefCell<R>,
     pub writer: &'a RefCell<RW>,
+    /// If this is set, the reader holds an image of a QR code, rather than the header itself
+    pub qr_image: bool,
 }
 
 pub fn execute<R, RW>(req: Request<'_, R, RW>) -> Result<(), Error>
@@ -20,22 +22,25 @@ where
     R: Read + Seek,
     RW: Read + Write + Seek,
 {
-    let (header, _) =
-        Header::deserialize(&mut *req.reader.borrow_mut()).map_err(|_| Error::InvalidFile)?;
-
-    let mut header_bytes = vec![
-        0u8;
-        header
-            .get_size()
-            .try_into()
-            .map_err(|_| Error::HeaderSizeParse)?
-    ];
+    let mut reader = req.reader.borrow_mut();
+    let header_bytes = if req.qr_image {
+        super::read_header_bytes(&mut Cursor::new(super::qr::read(&mut *reader)?))?
+    } else {
+        match super::armor::read(&mut *reader)? {
+            Some(decoded) => super::read_header_bytes(&mut Cursor::new(decoded))?,
+            None => super::read_header_bytes(&mut *reader)?,
+        }
+    };
+    drop(reader);
+
+    // the space that the header was stripped from must be empty, and a file that's too short can't have had it
+    let mut existing = vec![0u8; header_bytes.len()];
     req.writer
         .borrow_mut()
-        .read(&mut header_bytes)
-        .map_err(|_| Error::Read)?;
+        .read_exact(&mut existing)
+        .map_err(|_| Error::UnsupportedRestore)?;
 
-    if !header_bytes.into_iter().all(|b| b == 0) {
+    if !existing.into_iter().all(|b| b == 0) {
         return Err(Error::UnsupportedRestore);
     }
 
@@ -44,8 +49,9 @@ where
         .rewind()
         .map_err(|_| Error::Rewind)?;
 
-    header
-        .write(&mut *req.writer.borrow_mut())
+    req.writer
+        .borrow_mut()
+        .write_all(&header_bytes)
         .map_err(|_| Error::Write)?;
 
     Ok(())
@@ -0,0 +1,183 @@
+//! This provides functionality for finding a header that isn't at the start of a file (e.g. because another program prepended something to it).
+
+use super::Error;
+use std::cell::RefCell;
+use std::io::{Read, Seek, SeekFrom};
+
+use core::header::Header;
+
+pub struct Request<'a, R>
+where
+    R: Read + Seek,
+{
+    pub reader: &'a RefCell<R>,
+    /// Only headers that start within this many bytes are found
+    pub limit: u64,
+}
+
+pub struct Response {
+    /// This is where the header starts, and anything before it should be ignored
+    pub offset: u64,
+    pub header: Header,
+}
+
+/// This looks for the first offset that a valid header can be parsed from
+///
+/// Every header starts with `0xDE` followed by its version, so only those offsets are tried.
+pub fn execute<R>(req: Request<'_, R>) -> Result<Response, Error>
+where
+    R: Read + Seek,
+{
+    let mut reader = req.reader.borrow_mut();
+    reader.rewind().map_err(|_| Error::Rewind)?;
+
+    let mut bytes = Vec::new();
+    (&mut *reader)
+        .take(req.limit.saturating_add(1))
+        .read_to_end(&mut bytes)
+        .map_err(|_| Error::Read)?;
+
+    bytes
+        .windows(2)
+        .enumerate()
+        .filter(|(_, magic)| magic[0] == 0xDE && (0x01..=0x06).contains(&magic[1]))
+        .find_map(|(offset, _)| {
+            let offset = offset as u64;
+            reader.seek(SeekFrom::Start(offset)).ok()?;
+            Header::deserialize(&mut *reader)
+                .ok()
+                .map(|(header, _)| Response { offset, header })
+        })
+        .ok_or(Error::InvalidFile)
+}
+
+/// This presents a file whose header starts at `offset` as though the header was at the very start, so it can be decrypted as usual
+///
+/// If a `tag` is provided, it replaces the first 6 bytes of the header (see `HeaderType::serialize()`). This allows for decrypting files whose header type was damaged, as long as it's known.
+pub struct RecoveredReader<R>
+where
+    R: Read + Seek,
+{
+    inner: R,
+    offset: u64,
+    tag: Option<[u8; 6]>,
+    position: u64,
+}
+
+impl<R> RecoveredReader<R>
+where
+    R: Read + Seek,
+{
+    pub fn new(mut inner: R, offset: u64, tag: Option<[u8; 6]>) -> std::io::Result<Self> {
+        inner.seek(SeekFrom::Start(offset))?;
+        Ok(Self {
+            inner,
+            offset,
+            tag,
+            position: 0,
+        })
+    }
+}
+
+impl<R> Read for RecoveredReader<R>
+where
+    R: Read + Seek,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+
+        if let Some(tag) = &self.tag {
+            for (i, byte) in buf[..read].iter_mut().enumerate() {
+                match usize::try_from(self.position).map(|p| p + i) {
+                    Ok(p) if p < tag.len() => *byte = tag[p],
+                    _ => break,
+                }
+            }
+        }
+
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R> Seek for RecoveredReader<R>
+where
+    R: Read + Seek,
+{
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => SeekFrom::Start(n + self.offset),
+            pos => pos,
+        };
+
+        let position = self.inner.seek(pos)?;
+        if position < self.offset {
+            // don't leave the inner reader before the header
+            self.inner
+                .seek(SeekFrom::Start(self.offset + self.position))?;
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Unable to seek before the start of the header",
+            ));
+        }
+
+        self.position = position - self.offset;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    use core::header::HeaderVersion;
+
+    use crate::encrypt::tests::V5_ENCRYPTED_CONTENT;
+
+    #[test]
+    fn should_find_header_after_junk() {
+        let mut content = b"prepended by another program \xDE\x05".to_vec();
+        let offset = content.len() as u64;
+        content.extend_from_slice(&V5_ENCRYPTED_CONTENT);
+
+        let reader = RefCell::new(Cursor::new(content.clone()));
+        match execute(Request {
+            reader: &reader,
+            limit: 1024,
+        }) {
+            Ok(res) => {
+                assert_eq!(res.offset, offset);
+                assert!(res.header.header_type.version == HeaderVersion::V5);
+            }
+            _ => unreachable!(),
+        }
+
+        // the header must start within the limit
+        let reader = RefCell::new(Cursor::new(content));
+        assert!(execute(Request {
+            reader: &reader,
+            limit: 16
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn should_replace_header_tag() {
+        let mut content = b"junk".to_vec();
+        content.extend_from_slice(&V5_ENCRYPTED_CONTENT);
+        content[4..10].copy_from_slice(&[0u8; 6]);
+
+        let mut tag = [0u8; 6];
+        tag.copy_from_slice(&V5_ENCRYPTED_CONTENT[..6]);
+
+        let mut reader = RecoveredReader::new(Cursor::new(content), 4, Some(tag)).unwrap();
+        let mut recovered = Vec::new();
+        reader.read_to_end(&mut recovered).unwrap();
+        assert_eq!(recovered, V5_ENCRYPTED_CONTENT.to_vec());
+
+        reader.rewind().unwrap();
+        assert!(Header::deserialize(&mut reader).is_ok());
+        assert!(reader.seek(SeekFrom::Current(-1000)).is_err());
+    }
+}
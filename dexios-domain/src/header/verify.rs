@@ -0,0 +1,110 @@
+//! This provides functionality for verifying that a header (embedded or detached) is valid, can be unlocked with a key, and belongs to the encrypted data.
+//!
+//! Everything is decrypted to check this, but nothing is written anywhere.
+
+use super::Error;
+use std::cell::RefCell;
+use std::io::{Read, Seek};
+
+use core::header::Header;
+use core::protected::Protected;
+
+use crate::decrypt::Error as DecryptError;
+
+pub struct Request<'a, R>
+where
+    R: Read + Seek,
+{
+    /// If this is set, the header is read from here instead of the start of `reader`
+    pub header_reader: Option<&'a RefCell<R>>,
+    pub reader: &'a RefCell<R>,
+    pub raw_key: Protected<Vec<u8>>,
+}
+
+pub fn execute<R>(req: Request<'_, R>) -> Result<(), Error>
+where
+    R: Read + Seek,
+{
+    // decryption would fall back to a copy of a damaged header (see `super::backup`), but that shouldn't pass
+    let mut header_reader = req.header_reader.unwrap_or(req.reader).borrow_mut();
+    Header::deserialize(&mut *header_reader).map_err(|_| Error::InvalidFile)?;
+    header_reader.rewind().map_err(|_| Error::Rewind)?;
+    drop(header_reader);
+
+    let sink = RefCell::new(std::io::sink());
+
+    crate::decrypt::execute(crate::decrypt::Request {
+        header_reader: req.header_reader,
+        reader: req.reader,
+        writer: &sink,
+        raw_key: req.raw_key,
+        master_key: None,
+        identity: None,
+        on_decrypted_header: None,
+        on_file_info: None,
+    })
+    .map_err(|e| match e {
+        DecryptError::DeserializeHeader | DecryptError::StrippedHeader => Error::InvalidFile,
+        DecryptError::DecryptMasterKey => Error::WrongKey,
+        DecryptError::ReadEncryptedData | DecryptError::RewindDataReader => Error::Read,
+        _ => Error::Mismatch,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    use crate::encrypt::tests::{
+        PASSWORD, V5_ENCRYPTED_CONTENT, V5_ENCRYPTED_DETACHED_CONTENT, V5_ENCRYPTED_DETACHED_HEADER,
+    };
+
+    fn verify(header: Option<&[u8]>, content: &[u8], password: &[u8]) -> Result<(), Error> {
+        let header_reader = header.map(|header| RefCell::new(Cursor::new(header.to_vec())));
+        execute(Request {
+            header_reader: header_reader.as_ref(),
+            reader: &RefCell::new(Cursor::new(content.to_vec())),
+            raw_key: Protected::new(password.to_vec()),
+        })
+    }
+
+    #[test]
+    fn should_verify_a_header_that_matches() {
+        assert!(verify(None, &V5_ENCRYPTED_CONTENT, PASSWORD).is_ok());
+        assert!(verify(
+            Some(&V5_ENCRYPTED_DETACHED_HEADER[..]),
+            &V5_ENCRYPTED_DETACHED_CONTENT,
+            PASSWORD
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn should_not_verify_with_the_wrong_key() {
+        assert!(matches!(
+            verify(None, &V5_ENCRYPTED_CONTENT, b"wrong password"),
+            Err(Error::WrongKey)
+        ));
+    }
+
+    #[test]
+    fn should_not_verify_a_header_that_belongs_to_other_data() {
+        let mut content = V5_ENCRYPTED_CONTENT.to_vec();
+        let last = content.len() - 1;
+        content[last] ^= 1;
+
+        assert!(matches!(
+            verify(None, &content, PASSWORD),
+            Err(Error::Mismatch)
+        ));
+        assert!(matches!(
+            verify(
+                Some(&V5_ENCRYPTED_DETACHED_HEADER[..]),
+                &V5_ENCRYPTED_CONTENT,
+                PASSWORD
+            ),
+            Err(Error::Mismatch)
+        ));
+    }
+}
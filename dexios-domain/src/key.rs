@@ -1,5 +1,10 @@
+use std::cell::RefCell;
+use std::io::{Read, Seek, SeekFrom};
+use std::time::SystemTime;
+
 use core::key::vec_to_arr;
 use core::primitives::Algorithm;
+use core::primitives::BLOCK_SIZE;
 use core::primitives::ENCRYPTED_MASTER_KEY_LEN;
 use core::primitives::MASTER_KEY_LEN;
 use core::protected::Protected;
@@ -9,6 +14,9 @@ use core::{cipher::Ciphers, header::Keyslot};
 pub mod add;
 pub mod change;
 pub mod delete;
+pub mod list;
+pub mod mnemonic;
+pub mod rotate;
 pub mod verify;
 
 #[derive(Debug)]
@@ -23,6 +31,16 @@ pub enum Error {
     HeaderDeserialize,
     HeaderWrite,
     Seek,
+    KeyslotNotFound,
+    LastKeyslot,
+    Read,
+    FileChanged,
+    Convergent,
+    DetachedHeader,
+    DecryptManifest,
+    DecryptFileInfo,
+    Decrypt(crate::decrypt::Error),
+    Encrypt(crate::encrypt::Error),
 }
 
 impl std::fmt::Display for Error {
@@ -42,6 +60,92 @@ impl std::fmt::Display for Error {
                 f.write_str("The provided request is unsupported with this header version")
             }
             Error::IncorrectKey => f.write_str("The provided key is incorrect"),
+            Error::KeyslotNotFound => f.write_str("There is no keyslot with that index"),
+            Error::LastKeyslot => f.write_str(
+                "This is the only keyslot, so deleting it would make the file impossible to decrypt",
+            ),
+            Error::Read => f.write_str("Unable to read the file"),
+            Error::FileChanged => f.write_str(
+                "The file was modified while its header was being updated, so the new header wasn't written",
+            ),
+            Error::Convergent => f.write_str(
+                "This file was encrypted convergently, so its keys come from its contents and can't be rotated",
+            ),
+            Error::DetachedHeader => f.write_str(
+                "This is a header on its own, and the data that it belongs to is needed to rotate its keys",
+            ),
+            Error::DecryptManifest => f.write_str("Unable to decrypt the manifest"),
+            Error::DecryptFileInfo => f.write_str("Unable to decrypt the file info"),
+            Error::Decrypt(inner) => write!(f, "Unable to decrypt the file: {inner}"),
+            Error::Encrypt(inner) => write!(f, "Unable to encrypt the file again: {inner}"),
+        }
+    }
+}
+
+/// This returns the file's current modification time, if it's known
+///
+/// It's used alongside the file's length and contents to detect other writers during an in-place header update.
+pub type ModifiedFn<'a> = &'a dyn Fn() -> Option<SystemTime>;
+
+/// A cheap fingerprint of a file, taken when its header is read
+///
+/// Hashing the key can take a while, and if something else writes to the file in the meantime, the new header may no longer match the ciphertext.
+/// The fingerprint is taken again right before the header is written, and the write is aborted if they differ.
+#[derive(PartialEq, Eq)]
+pub(crate) struct Snapshot {
+    header_size: u64,
+    len: u64,
+    modified: Option<SystemTime>,
+    // this covers the header, and the first block of ciphertext
+    hash: blake3::Hash,
+}
+
+impl Snapshot {
+    pub(crate) fn take<R>(
+        handle: &RefCell<R>,
+        header_size: u64,
+        modified: Option<ModifiedFn<'_>>,
+    ) -> Result<Self, Error>
+    where
+        R: Read + Seek,
+    {
+        let mut handle = handle.borrow_mut();
+        let position = handle.stream_position().map_err(|_| Error::Seek)?;
+        let len = handle.seek(SeekFrom::End(0)).map_err(|_| Error::Seek)?;
+        handle.rewind().map_err(|_| Error::Seek)?;
+
+        let mut hasher = blake3::Hasher::new();
+        std::io::copy(
+            &mut (&mut *handle).take(header_size + BLOCK_SIZE as u64),
+            &mut hasher,
+        )
+        .map_err(|_| Error::Read)?;
+
+        handle
+            .seek(SeekFrom::Start(position))
+            .map_err(|_| Error::Seek)?;
+
+        Ok(Self {
+            header_size,
+            len,
+            modified: modified.and_then(|modified| modified()),
+            hash: hasher.finalize(),
+        })
+    }
+
+    /// This fails with `Error::FileChanged` if the file no longer matches the snapshot
+    pub(crate) fn check<R>(
+        &self,
+        handle: &RefCell<R>,
+        modified: Option<ModifiedFn<'_>>,
+    ) -> Result<(), Error>
+    where
+        R: Read + Seek,
+    {
+        if *self == Self::take(handle, self.header_size, modified)? {
+            Ok(())
+        } else {
+            Err(Error::FileChanged)
         }
     }
 }
@@ -56,6 +160,10 @@ pub fn decrypt_v5_master_key_with_index(
 
     // we need the index, so we can't use `decrypt_master_key()`
     for (i, keyslot) in keyslots.iter().enumerate() {
+        if keyslot.is_recipient() || keyslot.is_token() || keyslot.is_metadata_only() {
+            continue;
+        }
+
         let key_old = keyslot
             .hash_algorithm
             .hash(raw_key_old.clone(), &keyslot.salt)
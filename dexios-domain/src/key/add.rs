@@ -1,15 +1,19 @@
 //! This provides functionality for adding a key to a header that both adheres to the Dexios format, and is using a version >= V5.
+//!
+//! The new key may be metadata-only, in which case it unlocks the header key rather than the master key (see `core::subkeys`). It can list the manifest, but it can't decrypt the file.
 
 use std::io::Seek;
 
-use super::Error;
+use super::{Error, ModifiedFn, Snapshot};
 use core::header::HashingAlgorithm;
-use core::header::Keyslot;
+use core::header::BLAKE3BALLOON_LATEST;
 use core::header::{Header, HeaderVersion};
+use core::header::{Keyslot, MAX_KEYSLOTS};
 use core::primitives::gen_nonce;
 use core::primitives::gen_salt;
 use core::primitives::Mode;
 use core::protected::Protected;
+use core::subkeys::Subkeys;
 use std::cell::RefCell;
 use std::io::{Read, Write};
 
@@ -17,18 +21,20 @@ pub struct Request<'a, RW>
 where
     RW: Read + Write + Seek,
 {
-    pub handle: &'a RefCell<RW>, // header read+write+seek
+    pub handle: &'a RefCell<RW>,          // header read+write+seek
+    pub modified: Option<ModifiedFn<'a>>, // used to detect other writers, alongside the file's contents
     pub raw_key_old: Protected<Vec<u8>>,
     pub raw_key_new: Protected<Vec<u8>>,
-    pub hash_algorithm: HashingAlgorithm,
+    pub hash_algorithm: HashingAlgorithm, // this is ignored for metadata-only keys, which always use the latest BLAKE3-Balloon parameters
+    pub metadata_only: bool,
 }
 
 pub fn execute<RW>(req: Request<'_, RW>) -> Result<(), Error>
 where
     RW: Read + Write + Seek,
 {
-    let (header, _) = core::header::Header::deserialize(&mut *req.handle.borrow_mut())
-        .map_err(|_| Error::HeaderDeserialize)?;
+    let (header, _) =
+        Header::deserialize(&mut *req.handle.borrow_mut()).map_err(|_| Error::HeaderDeserialize)?;
 
     if header.header_type.version < HeaderVersion::V5 {
         return Err(Error::Unsupported);
@@ -44,6 +50,8 @@ where
         .seek(std::io::SeekFrom::Current(-header_size))
         .map_err(|_| Error::Seek)?;
 
+    let snapshot = Snapshot::take(req.handle, header.get_size(), req.modified)?;
+
     // this gets modified, then any changes from below are written at the end
     let mut keyslots = header.keyslots.clone().unwrap();
 
@@ -54,20 +62,33 @@ where
         &header.header_type.algorithm,
     )?;
 
-    if keyslots.len() == 4 {
+    if keyslots.len() == MAX_KEYSLOTS {
         return Err(Error::TooManyKeyslots);
     }
 
+    // the header key can only be handed out on its own if it's independent of the payload key
+    if req.metadata_only && !header.subkeys {
+        return Err(Error::Unsupported);
+    }
+
+    let (wrapped_key, hash_algorithm) = if req.metadata_only {
+        (
+            Subkeys::derive(master_key, &header).header,
+            HashingAlgorithm::Blake3Balloon(BLAKE3BALLOON_LATEST),
+        )
+    } else {
+        (master_key, req.hash_algorithm)
+    };
+
     let salt = gen_salt();
     let master_key_nonce = gen_nonce(&header.header_type.algorithm, &Mode::MemoryMode);
 
-    let key_new = req
-        .hash_algorithm
+    let key_new = hash_algorithm
         .hash(req.raw_key_new, &salt)
         .map_err(|_| Error::KeyHash)?;
 
     let encrypted_master_key = super::encrypt_master_key(
-        master_key,
+        wrapped_key,
         key_new,
         &master_key_nonce,
         &header.header_type.algorithm,
@@ -77,7 +98,10 @@ where
         encrypted_key: encrypted_master_key,
         nonce: master_key_nonce,
         salt,
-        hash_algorithm: req.hash_algorithm,
+        hash_algorithm,
+        encapsulated_key: None,
+        token: false,
+        metadata_only: req.metadata_only,
     };
 
     keyslots.push(keyslot);
@@ -86,14 +110,33 @@ where
     let header_new = Header {
         nonce: header.nonce,
         salt: header.salt,
+        fields: header.fields.clone(),
         keyslots: Some(keyslots),
         header_type: header.header_type,
+        compression: header.compression,
+        block_size: header.block_size,
+        padding: header.padding,
+        convergent: header.convergent,
+        metadata: header.metadata.clone(),
+        mac: header.mac,
+        digest: header.digest,
+        seekable: header.seekable,
+        keyfile_hash: header.keyfile_hash,
+        counter: header.counter,
+        subkeys: header.subkeys,
+        two_factor: header.two_factor,
+        manifest: header.manifest.clone(),
     };
 
-    // write the header to the handle
+    // the file may have been written to while the key was being hashed
+    snapshot.check(req.handle, req.modified)?;
+
+    // write the header to the handle (and over its copy at the end, if it has one)
     header_new
         .write(&mut *req.handle.borrow_mut())
         .map_err(|_| Error::HeaderWrite)?;
+    crate::header::backup::refresh(&mut *req.handle.borrow_mut(), &header_new)
+        .map_err(|_| Error::HeaderWrite)?;
 
     Ok(())
 }
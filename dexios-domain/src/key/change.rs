@@ -2,7 +2,7 @@
 
 use std::io::Seek;
 
-use super::Error;
+use super::{Error, ModifiedFn, Snapshot};
 use core::header::HashingAlgorithm;
 use core::header::Keyslot;
 use core::header::{Header, HeaderVersion};
@@ -17,7 +17,8 @@ pub struct Request<'a, RW>
 where
     RW: Read + Write + Seek,
 {
-    pub handle: &'a RefCell<RW>, // header read+write+seek
+    pub handle: &'a RefCell<RW>,          // header read+write+seek
+    pub modified: Option<ModifiedFn<'a>>, // used to detect other writers, alongside the file's contents
     pub raw_key_old: Protected<Vec<u8>>,
     pub raw_key_new: Protected<Vec<u8>>,
     pub hash_algorithm: HashingAlgorithm,
@@ -27,8 +28,8 @@ pub fn execute<RW>(req: Request<'_, RW>) -> Result<(), Error>
 where
     RW: Read + Write + Seek,
 {
-    let (header, _) = core::header::Header::deserialize(&mut *req.handle.borrow_mut())
-        .map_err(|_| Error::HeaderDeserialize)?;
+    let (header, _) =
+        Header::deserialize(&mut *req.handle.borrow_mut()).map_err(|_| Error::HeaderDeserialize)?;
 
     if header.header_type.version < HeaderVersion::V5 {
         return Err(Error::Unsupported);
@@ -44,6 +45,8 @@ where
         .seek(std::io::SeekFrom::Current(-header_size))
         .map_err(|_| Error::Seek)?;
 
+    let snapshot = Snapshot::take(req.handle, header.get_size(), req.modified)?;
+
     // this gets modified, then any changes from below are written at the end
     let mut keyslots = header.keyslots.clone().unwrap();
 
@@ -74,20 +77,89 @@ where
         nonce: master_key_nonce,
         salt,
         hash_algorithm: req.hash_algorithm,
+        encapsulated_key: None,
+        token: false,
+        metadata_only: false,
     };
 
     // recreate header and inherit everything (except keyslots)
     let header_new = Header {
         nonce: header.nonce,
         salt: header.salt,
+        fields: header.fields.clone(),
         keyslots: Some(keyslots),
         header_type: header.header_type,
+        compression: header.compression,
+        block_size: header.block_size,
+        padding: header.padding,
+        convergent: header.convergent,
+        metadata: header.metadata.clone(),
+        mac: header.mac,
+        digest: header.digest,
+        seekable: header.seekable,
+        keyfile_hash: header.keyfile_hash,
+        counter: header.counter,
+        subkeys: header.subkeys,
+        two_factor: header.two_factor,
+        manifest: header.manifest.clone(),
     };
 
-    // write the header to the handle
+    // the file may have been written to while the key was being hashed
+    snapshot.check(req.handle, req.modified)?;
+
+    // write the header to the handle (and over its copy at the end, if it has one)
     header_new
         .write(&mut *req.handle.borrow_mut())
         .map_err(|_| Error::HeaderWrite)?;
+    crate::header::backup::refresh(&mut *req.handle.borrow_mut(), &header_new)
+        .map_err(|_| Error::HeaderWrite)?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::io::Cursor;
+    use std::time::{Duration, SystemTime};
+
+    use crate::encrypt::tests::{PASSWORD, V5_ENCRYPTED_CONTENT};
+
+    #[test]
+    fn should_not_write_header_if_file_changed() {
+        let handle = RefCell::new(Cursor::new(V5_ENCRYPTED_CONTENT.to_vec()));
+
+        // every call reports a later modification time, as if something else was writing to the file
+        let calls = Cell::new(0);
+        let modified = || {
+            calls.set(calls.get() + 1);
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(calls.get()))
+        };
+
+        match execute(Request {
+            handle: &handle,
+            modified: Some(&modified),
+            raw_key_old: Protected::new(PASSWORD.to_vec()),
+            raw_key_new: Protected::new(b"12345678".to_vec()),
+            hash_algorithm: HashingAlgorithm::Argon2id(1),
+        }) {
+            Err(Error::FileChanged) => {}
+            _ => unreachable!(),
+        }
+
+        assert_eq!(calls.get(), 2);
+        assert_eq!(handle.borrow().get_ref(), &V5_ENCRYPTED_CONTENT.to_vec());
+
+        execute(Request {
+            handle: &handle,
+            modified: Some(&|| Some(SystemTime::UNIX_EPOCH)),
+            raw_key_old: Protected::new(PASSWORD.to_vec()),
+            raw_key_new: Protected::new(b"12345678".to_vec()),
+            hash_algorithm: HashingAlgorithm::Argon2id(1),
+        })
+        .unwrap();
+
+        assert_ne!(handle.borrow().get_ref(), &V5_ENCRYPTED_CONTENT.to_vec());
+    }
+}
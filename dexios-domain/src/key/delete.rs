@@ -1,6 +1,8 @@
-//! This provides functionality for adding a key to a header that both adheres to the Dexios format, and is using a version >= V5.
+//! This provides functionality for deleting a keyslot from a header that both adheres to the Dexios format, and is using a version >= V5.
+//!
+//! The keyslot that the key unlocks is deleted, unless another keyslot is chosen by its index (the key must still unlock one of them).
 
-use super::Error;
+use super::{Error, ModifiedFn, Snapshot};
 use core::header::{Header, HeaderVersion};
 use core::protected::Protected;
 use std::cell::RefCell;
@@ -11,16 +13,18 @@ pub struct Request<'a, RW>
 where
     RW: Read + Write + Seek,
 {
-    pub handle: &'a RefCell<RW>, // header read+write+seek
+    pub handle: &'a RefCell<RW>,          // header read+write+seek
+    pub modified: Option<ModifiedFn<'a>>, // used to detect other writers, alongside the file's contents
     pub raw_key_old: Protected<Vec<u8>>,
+    pub slot: Option<usize>, // the keyslot to delete, if it isn't the one that the key unlocks
 }
 
 pub fn execute<RW>(req: Request<'_, RW>) -> Result<(), Error>
 where
     RW: Read + Write + Seek,
 {
-    let (header, _) = core::header::Header::deserialize(&mut *req.handle.borrow_mut())
-        .map_err(|_| Error::HeaderDeserialize)?;
+    let (header, _) =
+        Header::deserialize(&mut *req.handle.borrow_mut()).map_err(|_| Error::HeaderDeserialize)?;
 
     if header.header_type.version < HeaderVersion::V5 {
         return Err(Error::Unsupported);
@@ -36,6 +40,8 @@ where
         .seek(std::io::SeekFrom::Current(-header_size))
         .map_err(|_| Error::Seek)?;
 
+    let snapshot = Snapshot::take(req.handle, header.get_size(), req.modified)?;
+
     // this gets modified, then any changes from below are written at the end
     let mut keyslots = header.keyslots.clone().unwrap();
 
@@ -46,20 +52,128 @@ where
         &header.header_type.algorithm,
     )?;
 
+    let index = match req.slot {
+        Some(slot) if slot >= keyslots.len() => return Err(Error::KeyslotNotFound),
+        Some(slot) => slot,
+        None => index,
+    };
+
+    // metadata-only keyslots can't decrypt the file, so at least one other keyslot has to remain
+    let remaining = keyslots
+        .iter()
+        .enumerate()
+        .filter(|(i, k)| *i != index && !k.is_metadata_only())
+        .count();
+    if remaining == 0 {
+        return Err(Error::LastKeyslot);
+    }
+
     keyslots.remove(index);
 
     // recreate header and inherit everything (except keyslots)
     let header_new = Header {
         nonce: header.nonce,
         salt: header.salt,
+        fields: header.fields.clone(),
         keyslots: Some(keyslots),
         header_type: header.header_type,
+        compression: header.compression,
+        block_size: header.block_size,
+        padding: header.padding,
+        convergent: header.convergent,
+        metadata: header.metadata.clone(),
+        mac: header.mac,
+        digest: header.digest,
+        seekable: header.seekable,
+        keyfile_hash: header.keyfile_hash,
+        counter: header.counter,
+        subkeys: header.subkeys,
+        two_factor: header.two_factor,
+        manifest: header.manifest.clone(),
     };
 
-    // write the header to the handle
+    // the file may have been written to while the key was being hashed
+    snapshot.check(req.handle, req.modified)?;
+
+    // write the header to the handle (and over its copy at the end, if it has one)
     header_new
         .write(&mut *req.handle.borrow_mut())
         .map_err(|_| Error::HeaderWrite)?;
+    crate::header::backup::refresh(&mut *req.handle.borrow_mut(), &header_new)
+        .map_err(|_| Error::HeaderWrite)?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::header::HashingAlgorithm;
+    use std::io::Cursor;
+
+    use crate::encrypt::tests::{PASSWORD, V5_ENCRYPTED_CONTENT};
+    use crate::key::list::tests::with_keyslots;
+
+    fn keyslots(content: &[u8]) -> Vec<core::header::Keyslot> {
+        let (header, _) = Header::deserialize(&mut Cursor::new(content.to_vec())).unwrap();
+        header.keyslots.unwrap()
+    }
+
+    fn delete(content: &RefCell<Cursor<Vec<u8>>>, slot: Option<usize>) -> Result<(), Error> {
+        execute(Request {
+            handle: content,
+            modified: None,
+            raw_key_old: Protected::new(PASSWORD.to_vec()),
+            slot,
+        })
+    }
+
+    #[test]
+    fn should_delete_the_chosen_keyslot() {
+        let content = with_keyslots(&[
+            HashingAlgorithm::Argon2id(3),
+            HashingAlgorithm::Blake3Balloon(4),
+        ]);
+        let before = keyslots(&content);
+
+        let handle = RefCell::new(Cursor::new(content));
+        delete(&handle, Some(1)).unwrap();
+
+        let after = keyslots(handle.borrow().get_ref());
+        assert_eq!(after.len(), 2);
+
+        // the keyslot that unlocked the header is untouched, and the other copy moves up
+        assert_eq!(after[0].salt, before[0].salt);
+        assert_eq!(after[0].encrypted_key, before[0].encrypted_key);
+        assert!(after[0].hash_algorithm == before[0].hash_algorithm);
+        assert_eq!(after[1].salt, before[2].salt);
+        assert!(after[1].hash_algorithm == before[2].hash_algorithm);
+    }
+
+    #[test]
+    fn should_refuse_a_keyslot_that_does_not_exist() {
+        let content = with_keyslots(&[HashingAlgorithm::Argon2id(3)]);
+        let handle = RefCell::new(Cursor::new(content.clone()));
+
+        match delete(&handle, Some(2)) {
+            Err(Error::KeyslotNotFound) => {}
+            _ => unreachable!(),
+        }
+
+        assert_eq!(handle.borrow().get_ref(), &content);
+    }
+
+    #[test]
+    fn should_refuse_to_delete_the_last_keyslot() {
+        for slot in [None, Some(0)] {
+            let handle = RefCell::new(Cursor::new(V5_ENCRYPTED_CONTENT.to_vec()));
+
+            match delete(&handle, slot) {
+                Err(Error::LastKeyslot) => {}
+                _ => unreachable!(),
+            }
+
+            assert_eq!(handle.borrow().get_ref(), &V5_ENCRYPTED_CONTENT.to_vec());
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! This provides functionality for listing the keyslots within a header (header version >= V5)
+//!
+//! No key is required, as only the public information within each keyslot is returned.
+
+use std::io::Seek;
+
+use super::Error;
+use core::header::{HashingAlgorithm, Header, HeaderVersion, MAX_KEYSLOTS};
+use core::primitives::SALT_LEN;
+use std::cell::RefCell;
+use std::io::Read;
+
+pub struct Request<'a, R>
+where
+    R: Read + Seek,
+{
+    pub handle: &'a RefCell<R>, // header read+seek
+}
+
+/// This describes what unlocks a keyslot
+pub enum Kind {
+    /// A password or keyfile, which is hashed with this algorithm
+    Key(HashingAlgorithm),
+    /// A recipient's secret key, with this fingerprint (see `core::recipient`)
+    Recipient([u8; SALT_LEN]),
+    /// A hardware token (see `core::token`)
+    Token,
+    /// A password or keyfile that only unlocks the header key, so it can list the manifest but not decrypt (see `core::manifest`)
+    MetadataOnly,
+}
+
+pub struct Slot {
+    pub index: usize,
+    pub kind: Kind,
+}
+
+pub struct Response {
+    pub slots: Vec<Slot>,
+    /// The number of keyslots that the header has room for
+    pub capacity: usize,
+}
+
+pub fn execute<R>(req: Request<'_, R>) -> Result<Response, Error>
+where
+    R: Read + Seek,
+{
+    let (header, _) =
+        Header::deserialize(&mut *req.handle.borrow_mut()).map_err(|_| Error::HeaderDeserialize)?;
+
+    if header.header_type.version < HeaderVersion::V5 {
+        return Err(Error::Unsupported);
+    }
+
+    let slots = header
+        .keyslots
+        .unwrap_or_default()
+        .into_iter()
+        .enumerate()
+        .map(|(index, keyslot)| Slot {
+            index,
+            kind: if keyslot.is_recipient() {
+                Kind::Recipient(keyslot.salt)
+            } else if keyslot.is_token() {
+                Kind::Token
+            } else if keyslot.is_metadata_only() {
+                Kind::MetadataOnly
+            } else {
+                Kind::Key(keyslot.hash_algorithm)
+            },
+        })
+        .collect();
+
+    Ok(Response {
+        slots,
+        capacity: MAX_KEYSLOTS,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use core::header::Keyslot;
+    use std::io::Cursor;
+
+    use crate::encrypt::tests::V5_ENCRYPTED_CONTENT;
+
+    // this copies the encrypted content's only keyslot into the remaining slots, without hashing anything
+    // the copies can't be unlocked, but the original (which stays at index 0) can
+    pub fn with_keyslots(hash_algorithms: &[HashingAlgorithm]) -> Vec<u8> {
+        let (mut header, _) =
+            Header::deserialize(&mut Cursor::new(V5_ENCRYPTED_CONTENT.to_vec())).unwrap();
+
+        let keyslots = header.keyslots.as_mut().unwrap();
+        let original = keyslots[0].clone();
+        for (i, hash_algorithm) in (1u8..).zip(hash_algorithms) {
+            keyslots.push(Keyslot {
+                hash_algorithm: *hash_algorithm,
+                salt: [i; SALT_LEN],
+                ..original.clone()
+            });
+        }
+
+        let mut content = Vec::new();
+        header.write(&mut content).unwrap();
+        let header_size = usize::try_from(header.get_size()).unwrap();
+        content.extend_from_slice(&V5_ENCRYPTED_CONTENT[header_size..]);
+        content
+    }
+
+    #[test]
+    fn should_list_every_keyslot() {
+        let extra = [
+            HashingAlgorithm::Argon2id(3),
+            HashingAlgorithm::Blake3Balloon(4),
+            HashingAlgorithm::Argon2id(2),
+        ];
+        let content = with_keyslots(&extra);
+        assert_eq!(content.len(), V5_ENCRYPTED_CONTENT.len());
+
+        let response = execute(Request {
+            handle: &RefCell::new(Cursor::new(content)),
+        })
+        .unwrap();
+
+        assert_eq!(response.capacity, MAX_KEYSLOTS);
+        assert_eq!(response.slots.len(), MAX_KEYSLOTS);
+
+        let expected = [HashingAlgorithm::Blake3Balloon(5)]
+            .into_iter()
+            .chain(extra)
+            .collect::<Vec<_>>();
+        for (i, (slot, hash_algorithm)) in response.slots.iter().zip(expected).enumerate() {
+            assert_eq!(slot.index, i);
+            match slot.kind {
+                Kind::Key(listed) => assert!(listed == hash_algorithm),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn should_list_a_single_keyslot() {
+        let response = execute(Request {
+            handle: &RefCell::new(Cursor::new(V5_ENCRYPTED_CONTENT.to_vec())),
+        })
+        .unwrap();
+
+        assert_eq!(response.slots.len(), 1);
+        assert_eq!(response.slots[0].index, 0);
+        assert!(matches!(
+            response.slots[0].kind,
+            Kind::Key(HashingAlgorithm::Blake3Balloon(5))
+        ));
+    }
+}
@@ -0,0 +1,102 @@
+//! This provides functionality for exporting the master key as a mnemonic phrase (header version >= V5)
+//!
+//! The phrase may be given to `decrypt` (see `decrypt::Request::master_key`) instead of a key, regardless of the file's keyslots.
+
+use std::io::Seek;
+
+use super::Error;
+use core::header::HeaderVersion;
+use core::key::master_key_to_mnemonic;
+use core::protected::Protected;
+use std::cell::RefCell;
+use std::io::Read;
+
+pub struct Request<'a, R>
+where
+    R: Read + Seek,
+{
+    pub handle: &'a RefCell<R>, // header read+seek
+    pub raw_key: Protected<Vec<u8>>,
+}
+
+pub fn execute<R>(req: Request<'_, R>) -> Result<Protected<String>, Error>
+where
+    R: Read + Seek,
+{
+    let (header, _) = core::header::Header::deserialize(&mut *req.handle.borrow_mut())
+        .map_err(|_| Error::HeaderDeserialize)?;
+
+    if header.header_type.version < HeaderVersion::V5 {
+        return Err(Error::Unsupported);
+    }
+
+    let keyslots = header.keyslots.clone().unwrap();
+
+    let (master_key, _) = super::decrypt_v5_master_key_with_index(
+        &keyslots,
+        req.raw_key,
+        &header.header_type.algorithm,
+    )?;
+
+    Ok(master_key_to_mnemonic(&master_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::header::{HashingAlgorithm, HeaderType};
+    use core::key::mnemonic_to_master_key;
+    use core::primitives::{Algorithm, Mode};
+    use std::io::Cursor;
+
+    const PASSWORD: &[u8; 8] = b"12345678";
+
+    #[test]
+    fn should_decrypt_with_exported_mnemonic() {
+        let input_cur = RefCell::new(Cursor::new(b"hello world".to_vec()));
+        let encrypted_cur = RefCell::new(Cursor::new(Vec::new()));
+
+        crate::encrypt::execute(crate::encrypt::Request {
+            header_type: HeaderType {
+                version: HeaderVersion::V5,
+                algorithm: Algorithm::XChaCha20Poly1305,
+                mode: Mode::StreamMode,
+            },
+            hashing_algorithm: HashingAlgorithm::Argon2id(1),
+            ..crate::encrypt::tests::request(&input_cur, &encrypted_cur)
+        })
+        .unwrap();
+
+        encrypted_cur.borrow_mut().set_position(0);
+        let phrase = execute(Request {
+            handle: &encrypted_cur,
+            raw_key: Protected::new(PASSWORD.to_vec()),
+        })
+        .unwrap();
+        assert_eq!(phrase.split(' ').count(), core::key::MNEMONIC_WORDS);
+
+        // the phrase is accepted regardless of case and spacing
+        let typed = format!("  {}\n", phrase.to_uppercase().replace(' ', "   "));
+
+        encrypted_cur.borrow_mut().set_position(0);
+        let output_cur = RefCell::new(Cursor::new(Vec::new()));
+        crate::decrypt::execute(crate::decrypt::Request {
+            header_reader: None,
+            reader: &encrypted_cur,
+            writer: &output_cur,
+            raw_key: Protected::new(Vec::new()),
+            master_key: Some(mnemonic_to_master_key(&typed).unwrap()),
+            identity: None,
+            on_decrypted_header: None,
+            on_file_info: None,
+        })
+        .unwrap();
+        assert_eq!(output_cur.into_inner().into_inner(), b"hello world");
+
+        // words that aren't within the wordlist are rejected
+        let mut words: Vec<&str> = phrase.split(' ').collect();
+        words[0] = "dexios";
+        assert!(mnemonic_to_master_key(&words.join(" ")).is_err());
+        assert!(mnemonic_to_master_key("abandon abandon abandon").is_err());
+    }
+}
@@ -0,0 +1,275 @@
+//! This provides functionality for rotating every key of a file that adheres to the Dexios format, and is using a version >= V5.
+//!
+//! Unlike `key::change`, which only wraps the existing master key with a new key, this generates a new master key (and new nonces), and re-encrypts the data with them. The data is decrypted on one thread and encrypted on another, and the plaintext is only ever handed between them in memory.
+//!
+//! The new file has a single keyslot, so any other keys (including recipients and tokens) must be added again.
+//!
+//! The new file is written with the latest header version (see `Header::migrate`), so this also upgrades V5 files.
+
+use std::cell::RefCell;
+use std::io::{Read, Seek, Write};
+
+use super::Error;
+use core::file_info;
+use core::header::{HashingAlgorithm, Header, HeaderVersion, FILE_INFO_FIELD};
+use core::manifest;
+use core::primitives::{Mode, Padding, MASTER_KEY_LEN};
+use core::protected::Protected;
+use core::subkeys::Subkeys;
+
+use crate::pipe::{pipe, Counter};
+
+pub struct Request<'a, R, W>
+where
+    R: Read + Seek + Send,
+    W: Write + Seek,
+{
+    pub reader: &'a RefCell<R>, // the encrypted file
+    pub writer: &'a RefCell<W>, // where the re-encrypted file is written
+    pub raw_key_old: Protected<Vec<u8>>,
+    pub raw_key_new: Protected<Vec<u8>>,
+    pub hash_algorithm: HashingAlgorithm,
+}
+
+pub struct Response {
+    /// This is the number of keyslots that weren't carried over, as they don't unlock the new master key
+    pub dropped_keyslots: usize,
+}
+
+fn decrypt<R, W>(
+    reader: &RefCell<R>,
+    writer: &RefCell<W>,
+    master_key: Protected<[u8; MASTER_KEY_LEN]>,
+) -> Result<(), Error>
+where
+    R: Read + Seek,
+    W: Write,
+{
+    reader.borrow_mut().rewind().map_err(|_| Error::Seek)?;
+
+    crate::decrypt::execute(crate::decrypt::Request {
+        header_reader: None,
+        reader,
+        writer,
+        raw_key: Protected::new(Vec::new()),
+        master_key: Some(master_key),
+        identity: None,
+        on_decrypted_header: None,
+        on_file_info: None,
+    })
+    .map_err(Error::Decrypt)
+}
+
+pub fn execute<R, W>(req: Request<'_, R, W>) -> Result<Response, Error>
+where
+    R: Read + Seek + Send,
+    W: Write + Seek,
+{
+    req.reader.borrow_mut().rewind().map_err(|_| Error::Seek)?;
+    let (header, _) =
+        Header::deserialize(&mut *req.reader.borrow_mut()).map_err(|_| Error::HeaderDeserialize)?;
+
+    if header.header_type.version < HeaderVersion::V5 || header.header_type.mode == Mode::MemoryMode
+    {
+        return Err(Error::Unsupported);
+    }
+
+    // a convergent file's keys are derived from its plaintext, so they can't be replaced with random ones
+    if header.convergent {
+        return Err(Error::Convergent);
+    }
+
+    // a header that was dumped can't be rotated on its own, as the data that it belongs to needs to be encrypted again
+    let read = req
+        .reader
+        .borrow_mut()
+        .read(&mut [0u8; 1])
+        .map_err(|_| Error::Read)?;
+    if read == 0 {
+        return Err(Error::DetachedHeader);
+    }
+
+    let master_key =
+        core::key::decrypt_master_key(req.raw_key_old, &header).map_err(|_| Error::IncorrectKey)?;
+
+    // the manifest is encrypted with a key that's derived from the master key, so it's encrypted again too
+    let manifest = header
+        .manifest
+        .as_ref()
+        .map(|encrypted| {
+            let header_key = Subkeys::derive(master_key.clone(), &header).header;
+            manifest::decrypt(&header_key, &header.header_type.algorithm, encrypted)
+        })
+        .transpose()
+        .map_err(|_| Error::DecryptManifest)?;
+
+    // and so is the file info, which is taken out of the fields so it isn't stored twice
+    let (info_fields, fields): (Vec<_>, Vec<_>) = header
+        .fields
+        .iter()
+        .cloned()
+        .partition(|f| f.tag == FILE_INFO_FIELD);
+    let file_info = info_fields
+        .first()
+        .map(|field| {
+            let header_key = Subkeys::derive(master_key.clone(), &header).header;
+            file_info::decrypt(&header_key, &header.header_type.algorithm, &field.value)
+        })
+        .transpose()
+        .map_err(|_| Error::DecryptFileInfo)?;
+
+    // the new padding depends on the plaintext's length, which is only known once it's been decrypted
+    let len = if header.padding == Padding::Padme {
+        let counter = RefCell::new(Counter(0));
+        decrypt(req.reader, &counter, master_key.clone())?;
+        counter.into_inner().0
+    } else {
+        0
+    };
+
+    // the data is being encrypted again anyway, so it's written in the latest layout
+    let header = header.migrate().map_err(|_| Error::Unsupported)?;
+
+    let (pipe_writer, pipe_reader) = pipe(len);
+    let mut input = req.reader.borrow_mut();
+    let input = &mut *input;
+
+    let (decrypted, encrypted) = std::thread::scope(|scope| {
+        let decrypting = scope
+            .spawn(move || decrypt(&RefCell::new(input), &RefCell::new(pipe_writer), master_key));
+
+        // every option is carried over, besides the keys and nonces (which are all generated again)
+        let encrypted = crate::encrypt::execute(crate::encrypt::Request {
+            reader: &RefCell::new(pipe_reader),
+            writer: req.writer,
+            header_writer: None,
+            raw_key: req.raw_key_new,
+            header_type: header.header_type,
+            hashing_algorithm: req.hash_algorithm,
+            compression: header.compression,
+            block_size: header.block_size,
+            padding: header.padding,
+            convergent: false,
+            recipients: Vec::new(),
+            tokens: Vec::new(),
+            extra_keys: Vec::new(),
+            metadata: header.metadata.clone(),
+            mac: header.mac,
+            digest: header.digest.is_some(),
+            seekable: header.seekable,
+            keyfile_hash: header.keyfile_hash,
+            counter: header.counter,
+            two_factor: header.two_factor,
+            manifest,
+            file_info,
+            fields,
+        });
+
+        let decrypted = decrypting
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+
+        (decrypted, encrypted)
+    });
+
+    // if decryption failed, the encrypted data would be truncated (so that's checked first)
+    decrypted?;
+    encrypted.map_err(Error::Encrypt)?;
+
+    Ok(Response {
+        dropped_keyslots: header
+            .keyslots
+            .as_ref()
+            .map_or(0, |keyslots| keyslots.len().saturating_sub(1)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    use core::header::HeaderType;
+    use core::primitives::Algorithm;
+
+    const PASSWORD: &[u8; 8] = b"12345678";
+    const NEW_PASSWORD: &[u8; 8] = b"87654321";
+
+    fn encrypt(version: HeaderVersion, padding: Padding, mac: bool) -> Vec<u8> {
+        let encrypted = RefCell::new(Cursor::new(Vec::new()));
+        crate::encrypt::execute(crate::encrypt::Request {
+            header_type: HeaderType {
+                version,
+                mode: Mode::StreamMode,
+                algorithm: Algorithm::XChaCha20Poly1305,
+            },
+            padding,
+            mac,
+            digest: mac,
+            ..crate::encrypt::tests::request(
+                &RefCell::new(Cursor::new(b"Hello world".to_vec())),
+                &encrypted,
+            )
+        })
+        .unwrap();
+        encrypted.into_inner().into_inner()
+    }
+
+    fn decrypt_with(content: &[u8], password: &[u8]) -> Result<Vec<u8>, crate::decrypt::Error> {
+        let output = RefCell::new(Vec::new());
+        crate::decrypt::execute(crate::decrypt::Request {
+            header_reader: None,
+            reader: &RefCell::new(Cursor::new(content.to_vec())),
+            writer: &output,
+            raw_key: Protected::new(password.to_vec()),
+            master_key: None,
+            identity: None,
+            on_decrypted_header: None,
+            on_file_info: None,
+        })?;
+        Ok(output.into_inner())
+    }
+
+    fn rotate(content: &[u8]) -> Vec<u8> {
+        let output = RefCell::new(Cursor::new(Vec::new()));
+        execute(Request {
+            reader: &RefCell::new(Cursor::new(content.to_vec())),
+            writer: &output,
+            raw_key_old: Protected::new(PASSWORD.to_vec()),
+            raw_key_new: Protected::new(NEW_PASSWORD.to_vec()),
+            hash_algorithm: HashingAlgorithm::Blake3Balloon(5),
+        })
+        .unwrap();
+        output.into_inner().into_inner()
+    }
+
+    #[test]
+    fn should_rotate_keys() {
+        let rotated = rotate(&encrypt(HeaderVersion::V5, Padding::None, false));
+
+        // V5 files are migrated as they're encrypted again
+        let (header, _) = Header::deserialize(&mut Cursor::new(&rotated)).unwrap();
+        assert!(header.header_type.version == core::header::HEADER_VERSION);
+
+        assert_eq!(
+            decrypt_with(&rotated, NEW_PASSWORD).unwrap(),
+            b"Hello world".to_vec()
+        );
+        assert!(decrypt_with(&rotated, PASSWORD).is_err());
+    }
+
+    #[test]
+    fn should_rotate_padded_keys() {
+        let rotated = rotate(&encrypt(HeaderVersion::V6, Padding::Padme, true));
+
+        let (header, _) = Header::deserialize(&mut Cursor::new(&rotated)).unwrap();
+        assert!(header.padding == Padding::Padme);
+        assert!(header.mac);
+        assert!(header.digest.is_some());
+
+        assert_eq!(
+            decrypt_with(&rotated, NEW_PASSWORD).unwrap(),
+            b"Hello world".to_vec()
+        );
+    }
+}
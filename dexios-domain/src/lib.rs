@@ -8,7 +8,7 @@
 //!
 //! You may find the audits for both AES-256-GCM and XChaCha20-Poly1305 on [the NCC Group's website](https://research.nccgroup.com/2020/02/26/public-report-rustcrypto-aes-gcm-and-chacha20poly1305-implementation-review/).
 //!
-//! <sup>1</sup> Deoxys-II-256 does not have an official audit, so use it at your own risk
+//! <sup>1</sup> Deoxys-II-256, AEGIS-256 and Ascon-128a do not have an official audit, so use them at your own risk
 //!
 //! ## Who uses Dexios-Domain?
 //!
@@ -51,17 +51,30 @@
     clippy::missing_errors_doc
 )]
 
+pub mod compare;
 pub mod decrypt;
 pub mod encrypt;
 pub mod erase;
 pub mod erase_dir;
+pub mod filters;
 pub mod hash;
 pub mod hasher;
 pub mod header;
 pub mod key;
+pub mod manifest;
 pub mod overwrite;
 pub mod pack;
+pub(crate) mod pipe;
+pub mod recovery_bundle;
+pub mod relocate;
+pub mod sign;
+pub mod split;
 pub mod storage;
+pub mod streams;
+pub mod template;
+pub mod transfer;
 pub mod unpack;
+pub mod upgrade;
+pub mod verify_signature;
 
 pub mod utils;
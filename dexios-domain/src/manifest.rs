@@ -0,0 +1,135 @@
+//! This provides functionality for listing the files within an encrypted pack, without decrypting it.
+//!
+//! The manifest is read from the header (see `core::manifest`), so any key will do - including a metadata-only key, which can't decrypt the archive itself.
+
+use core::header::Header;
+use core::manifest::{self, Entry};
+use core::protected::Protected;
+use std::cell::RefCell;
+use std::io::{Read, Seek};
+
+#[derive(Debug)]
+pub enum Error {
+    HeaderDeserialize,
+    NoManifest,
+    IncorrectKey,
+    DecryptManifest,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::HeaderDeserialize => f.write_str("Unable to deserialize the header"),
+            Error::NoManifest => f.write_str("The header doesn't contain a manifest"),
+            Error::IncorrectKey => f.write_str("The provided key is incorrect"),
+            Error::DecryptManifest => f.write_str("Unable to decrypt the manifest"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub struct Request<'a, R>
+where
+    R: Read + Seek,
+{
+    pub handle: &'a RefCell<R>, // header read+seek
+    pub raw_key: Protected<Vec<u8>>,
+}
+
+pub fn execute<R>(req: Request<'_, R>) -> Result<Vec<Entry>, Error>
+where
+    R: Read + Seek,
+{
+    let (header, _) =
+        Header::deserialize(&mut *req.handle.borrow_mut()).map_err(|_| Error::HeaderDeserialize)?;
+
+    let encrypted_manifest = header.manifest.as_ref().ok_or(Error::NoManifest)?;
+
+    let header_key =
+        core::key::decrypt_header_key(req.raw_key, &header).map_err(|_| Error::IncorrectKey)?;
+
+    manifest::decrypt(
+        &header_key,
+        &header.header_type.algorithm,
+        encrypted_manifest,
+    )
+    .map_err(|_| Error::DecryptManifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::header::HashingAlgorithm;
+    use std::io::Cursor;
+
+    const PASSWORD: &[u8; 8] = b"12345678";
+    const METADATA_PASSWORD: &[u8; 8] = b"87654321";
+
+    #[test]
+    fn should_list_manifest_with_metadata_only_key() {
+        let entries = vec![Entry {
+            path: "hello.txt".to_string(),
+            size: 5,
+            hash: blake3::hash(b"hello"),
+        }];
+
+        let input_cur = RefCell::new(Cursor::new(b"hello".to_vec()));
+        let encrypted_cur = RefCell::new(Cursor::new(Vec::new()));
+
+        crate::encrypt::execute(crate::encrypt::Request {
+            hashing_algorithm: HashingAlgorithm::Argon2id(1),
+            manifest: Some(entries.clone()),
+            ..crate::encrypt::tests::request(&input_cur, &encrypted_cur)
+        })
+        .unwrap();
+
+        encrypted_cur.borrow_mut().set_position(0);
+        crate::key::add::execute(crate::key::add::Request {
+            handle: &encrypted_cur,
+            modified: None,
+            raw_key_old: Protected::new(PASSWORD.to_vec()),
+            raw_key_new: Protected::new(METADATA_PASSWORD.to_vec()),
+            hash_algorithm: HashingAlgorithm::Argon2id(1),
+            metadata_only: true,
+        })
+        .unwrap();
+
+        for password in [PASSWORD, METADATA_PASSWORD] {
+            encrypted_cur.borrow_mut().set_position(0);
+            let listed = execute(Request {
+                handle: &encrypted_cur,
+                raw_key: Protected::new(password.to_vec()),
+            })
+            .unwrap();
+            assert_eq!(listed, entries);
+        }
+
+        // the metadata-only key mustn't be able to decrypt the payload
+        encrypted_cur.borrow_mut().set_position(0);
+        let output_cur = RefCell::new(Cursor::new(Vec::new()));
+        let res = crate::decrypt::execute(crate::decrypt::Request {
+            header_reader: None,
+            reader: &encrypted_cur,
+            writer: &output_cur,
+            raw_key: Protected::new(METADATA_PASSWORD.to_vec()),
+            master_key: None,
+            identity: None,
+            on_decrypted_header: None,
+            on_file_info: None,
+        });
+        assert!(res.is_err());
+
+        // and it can't be used to add a key that could
+        encrypted_cur.borrow_mut().set_position(0);
+        let res = crate::key::add::execute(crate::key::add::Request {
+            handle: &encrypted_cur,
+            modified: None,
+            raw_key_old: Protected::new(METADATA_PASSWORD.to_vec()),
+            raw_key_new: Protected::new(b"attacker".to_vec()),
+            hash_algorithm: HashingAlgorithm::Argon2id(1),
+            metadata_only: false,
+        });
+        assert!(matches!(res, Err(crate::key::Error::IncorrectKey)));
+    }
+}
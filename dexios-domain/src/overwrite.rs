@@ -6,6 +6,7 @@ use rand::RngCore;
 use std::cell::RefCell;
 use std::fmt;
 use std::io::{Seek, Write};
+use std::num::NonZeroU8;
 
 const BLOCK_SIZE: usize = 512;
 
@@ -33,15 +34,15 @@ impl std::error::Error for Error {}
 pub struct Request<'a, W: Write + Seek> {
     pub writer: &'a RefCell<W>,
     pub buf_capacity: usize,
-    pub passes: i32,
+    pub passes: NonZeroU8,
 }
 
 pub fn execute<W: Write + Seek>(req: Request<'_, W>) -> Result<(), Error> {
     let mut writer = req.writer.borrow_mut();
-    for _ in 0..req.passes {
+    for _ in 0..req.passes.get() {
         writer.rewind().map_err(|_| Error::ResetCursorPosition)?;
 
-        let mut blocks = vec![BLOCK_SIZE].repeat(req.buf_capacity / BLOCK_SIZE);
+        let mut blocks = [BLOCK_SIZE].repeat(req.buf_capacity / BLOCK_SIZE);
         blocks.push(req.buf_capacity % BLOCK_SIZE);
 
         for block_size in blocks.into_iter().take_while(|bs| *bs > 0) {
@@ -67,7 +68,7 @@ mod tests {
     use super::*;
     use std::io::Cursor;
 
-    fn make_test(capacity: usize, passes: i32) {
+    fn make_test(capacity: usize, passes: u8) {
         let mut buf = Vec::with_capacity(capacity);
         rand::thread_rng().fill_bytes(&mut buf);
 
@@ -76,13 +77,13 @@ mod tests {
         let req = Request {
             writer: &RefCell::new(writer),
             buf_capacity: capacity,
-            passes,
+            passes: NonZeroU8::new(passes).unwrap(),
         };
 
         match execute(req) {
-            Ok(_) => {
+            Ok(()) => {
                 assert_eq!(buf.len(), capacity);
-                assert_eq!(buf, vec![0].repeat(capacity));
+                assert_eq!(buf, [0].repeat(capacity));
             }
             _ => unreachable!(),
         }
@@ -117,9 +118,4 @@ mod tests {
     fn should_erase_fill_random_bytes_one_hundred_times() {
         make_test(515, 100);
     }
-
-    #[test]
-    fn should_erase_fill_random_bytes_zero_times() {
-        make_test(515, 0);
-    }
 }
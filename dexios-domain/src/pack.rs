@@ -2,18 +2,53 @@
 //!
 //! This is known as "packing" within Dexios.
 //!
+//! The archive is prefixed with a BLAKE3 hash of its contents (see `ARCHIVE_HASH_MAGIC`), so that unpacking can verify it before extracting anything. Zip readers skip over the prefix, just as they would for a self-extracting archive.
+//!
 //! DISCLAIMER: Encryption with compression is generally not recommended, however here it is fine. As the data is at-rest, and it's assumed you have complete control over the data you're encrypting (e.g. not attacker-controlled), there should be no problems. Feel free to use no compression if you feel otherwise.
 
+pub mod estimate;
+pub mod recompress;
+
 use std::cell::RefCell;
-use std::io::{BufWriter, Read, Seek, Write};
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::io::{BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::num::{NonZeroU8, NonZeroUsize};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use core::header::{HashingAlgorithm, HeaderType};
-use core::primitives::BLOCK_SIZE;
+use core::header::{HashingAlgorithm, HeaderType, Metadata};
+use core::manifest::Entry as ManifestEntry;
+use core::primitives::{Compression, Padding, StreamCounter, BLOCK_SIZE};
 use core::protected::Protected;
 use zip::write::FileOptions;
 
-use crate::storage::Storage;
+use crate::filters::{self, Filter};
+use crate::storage::{Entry, Storage};
+use crate::streams;
+
+/// This marks the start of an archive that's prefixed with its hash
+pub(crate) const ARCHIVE_HASH_MAGIC: [u8; 8] = *b"DXPKHSH1";
+
+/// This is the length of the magic and the BLAKE3 hash, which come before the archive itself
+pub(crate) const ARCHIVE_HASH_PREFIX_LEN: usize = ARCHIVE_HASH_MAGIC.len() + blake3::OUT_LEN;
+
+/// This hashes everything from the reader's current position to the end
+pub(crate) fn hash_archive(reader: &mut impl Read) -> std::io::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0u8; BLOCK_SIZE].into_boxed_slice();
+    loop {
+        let read_count = reader.read(&mut buffer)?;
+        if read_count == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read_count]);
+    }
+    Ok(hasher.finalize())
+}
+
+/// Files larger than this are compressed on the current thread, rather than being read into memory and sent to a worker
+pub(crate) const PARALLEL_FILE_LIMIT: usize = 8 * BLOCK_SIZE;
 
 #[derive(Debug)]
 pub enum Error {
@@ -22,7 +57,11 @@ pub enum Error {
     AddFileToArchive,
     FinishArchive,
     ReadData,
+    ReadStreams,
     WriteData,
+    DuplicatePath(String),
+    ListArchive,
+    Filter(filters::Error),
     Encrypt(crate::encrypt::Error),
 }
 
@@ -34,7 +73,15 @@ impl std::fmt::Display for Error {
             Error::AddFileToArchive => f.write_str("Unable to add file to archive"),
             Error::FinishArchive => f.write_str("Unable to finish archive"),
             Error::ReadData => f.write_str("Unable to read data"),
+            Error::ReadStreams => f.write_str("Unable to read the data attached to a file"),
             Error::WriteData => f.write_str("Unable to write data"),
+            Error::DuplicatePath(path) => {
+                write!(f, "More than one file would be archived as {path}")
+            }
+            Error::ListArchive => {
+                f.write_str("Unable to list the archive's files for the manifest")
+            }
+            Error::Filter(inner) => write!(f, "Unable to filter a file: {inner}"),
             Error::Encrypt(inner) => write!(f, "Unable to encrypt archive: {inner}"),
         }
     }
@@ -42,31 +89,588 @@ impl std::fmt::Display for Error {
 
 impl std::error::Error for Error {}
 
+/// This contains the time spent in (and the data that passed through) each stage of packing
+///
+/// Compression time is summed across all workers, so it may be longer than the time that packing took. With a single job, files are compressed as they're archived, so it's included in the archive time instead.
+#[derive(Debug, Default, Clone)]
+pub struct Stats {
+    pub jobs: usize,
+    pub files: usize,
+    /// The number of files that were left out, as they match `Request::base`
+    pub unchanged: usize,
+    pub read_bytes: u64,
+    pub read_time: Duration,
+    pub compress_time: Duration,
+    pub archive_bytes: u64,
+    pub archive_time: Duration,
+    pub encrypt_time: Duration,
+}
+
+pub type OnStatsFn = Box<dyn FnOnce(&Stats)>;
+
+/// This is reported each time a file has been written into the archive
+pub struct Progress<'a> {
+    /// The file's path within the archive
+    pub name: &'a str,
+    /// The number of files that have been archived so far, including this one
+    pub files: usize,
+    /// The total number of files that will be archived
+    pub total: usize,
+    /// The number of bytes that have been read so far
+    pub read_bytes: u64,
+    pub elapsed: Duration,
+}
+
+pub type OnProgressFn = Box<dyn FnMut(&Progress<'_>)>;
+
+/// This keeps track of how many files have been archived, for `on_progress`
+struct ProgressReporter {
+    on_progress: Option<OnProgressFn>,
+    files: usize,
+    start: Instant,
+}
+
+impl ProgressReporter {
+    fn file_written(&mut self, name: &str, stats: &Stats) {
+        self.files += 1;
+
+        if let Some(cb) = self.on_progress.as_mut() {
+            cb(&Progress {
+                name,
+                files: self.files,
+                total: stats.files,
+                read_bytes: stats.read_bytes,
+                elapsed: self.start.elapsed(),
+            });
+        }
+    }
+}
+
+/// Entries beneath `path` are archived beneath `prefix`, rather than with their own path
+///
+/// This allows several inputs to be merged into one archive. If the prefix is empty, the root's contents are placed at the top level of the archive.
+#[derive(Debug, Clone)]
+pub struct Root {
+    pub path: PathBuf,
+    pub prefix: String,
+}
+
+/// This returns the path that an entry is stored as within the archive, or `None` if it shouldn't be stored at all
+///
+/// Entries that aren't beneath any of the roots keep their own path.
+fn archive_path(roots: &[Root], path: &Path) -> Result<Option<String>, Error> {
+    let Some((root, relative)) = roots
+        .iter()
+        .find_map(|root| Some((root, path.strip_prefix(&root.path).ok()?)))
+    else {
+        return path
+            .to_str()
+            .map(|p| Some(p.to_string()))
+            .ok_or(Error::ReadData);
+    };
+
+    // the root itself only needs an entry if it has a prefix to create
+    if relative.as_os_str().is_empty() {
+        return Ok((!root.prefix.is_empty()).then(|| root.prefix.clone()));
+    }
+
+    Path::new(&root.prefix)
+        .join(relative)
+        .to_str()
+        .map(|p| Some(p.to_string()))
+        .ok_or(Error::ReadData)
+}
+
+/// Each entry, paired with its path within the archive
+type NamedEntries<'a, RW> = Vec<(String, &'a Entry<RW>)>;
+
+/// This pairs each entry with its path within the archive
+///
+/// Directories may appear more than once (if several roots share a prefix), but files can't.
+fn name_entries<'a, RW>(
+    roots: &[Root],
+    entries: &'a [Entry<RW>],
+) -> Result<NamedEntries<'a, RW>, Error>
+where
+    RW: Read + Write + Seek,
+{
+    let mut names = std::collections::HashSet::new();
+    let mut named = Vec::with_capacity(entries.len());
+
+    for entry in entries {
+        let Some(name) = archive_path(roots, entry.path())? else {
+            continue;
+        };
+
+        let key = name.trim_end_matches(['/', '\\']).to_string();
+        if !names.insert(key) {
+            if entry.is_dir() {
+                continue;
+            }
+            return Err(Error::DuplicatePath(name));
+        }
+
+        named.push((name, entry));
+    }
+
+    Ok(named)
+}
+
 pub struct Request<'a, RW>
 where
     RW: Read + Write + Seek,
 {
     pub writer: &'a RefCell<RW>,
-    pub compress_files: Vec<crate::storage::Entry<RW>>,
+    pub compress_files: Vec<Entry<RW>>,
+    /// These set where each input is placed within the archive (see `Root`)
+    pub roots: Vec<Root>,
     pub compression_method: zip::CompressionMethod,
     pub header_writer: Option<&'a RefCell<RW>>,
     pub raw_key: Protected<Vec<u8>>,
     // TODO: don't use external types in logic
     pub header_type: HeaderType,
     pub hashing_algorithm: HashingAlgorithm,
+    /// This is the number of threads that compress files - with one, everything is done on the current thread
+    pub jobs: NonZeroUsize,
+    pub on_stats: Option<OnStatsFn>,
+    /// This is called after each file has been archived (directories aren't reported)
+    pub on_progress: Option<OnProgressFn>,
+    pub metadata: Option<Metadata>,
+    /// This selects which data attached to each file (alternate data streams, extended attributes) is archived alongside it (see `crate::streams`)
+    pub streams: streams::Options,
+    /// These transform the contents of matching files before they're archived (see `crate::filters`)
+    pub filters: Vec<Filter>,
+    /// This must be set if the raw key was derived from a keyfile with `core::key::hash_keyfile()`
+    pub keyfile_hash: bool,
+    /// This selects the counter that's used for each block's nonce (see `core::counter`)
+    pub counter: StreamCounter,
+    /// This must be set if the raw key was made with `core::key::combine_factors()`
+    pub two_factor: bool,
+    /// If this is set, the path, size and hash of each archived file is stored in the header, so it can be listed without decrypting the archive (see `core::manifest`)
+    pub manifest: bool,
+    /// Files that match an entry here (by their path within the archive, size and hash) are left out of the archive, for incremental backups
+    ///
+    /// They're still listed within the manifest, so it always describes the whole input and the next backup only needs this one's manifest. Filtered files are never left out, as the manifest holds the filter's output.
+    pub base: Vec<ManifestEntry>,
+}
+
+/// A file that has been read, and is waiting to be compressed by a worker
+struct CompressJob {
+    index: usize,
+    path: String,
+    data: Vec<u8>,
+}
+
+/// A file that a worker has compressed into its own single-file archive, so it can be copied into the real one
+struct CompressedFile {
+    index: usize,
+    path: String,
+    archive: Vec<u8>,
+    elapsed: Duration,
+}
+
+fn compress_file(job: &CompressJob, options: FileOptions) -> Result<CompressedFile, Error> {
+    let start = Instant::now();
+
+    let mut zip_writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    zip_writer
+        .start_file(job.path.as_str(), options)
+        .map_err(|_| Error::AddFileToArchive)?;
+    zip_writer
+        .write_all(&job.data)
+        .map_err(|_| Error::WriteData)?;
+    let archive = zip_writer
+        .finish()
+        .map_err(|_| Error::FinishArchive)?
+        .into_inner();
+
+    Ok(CompressedFile {
+        index: job.index,
+        path: job.path.clone(),
+        archive,
+        elapsed: start.elapsed(),
+    })
+}
+
+/// This writes a directory or file entry straight into the archive, on the current thread
+///
+/// Files that are filtered are read into memory, as the filter's output is needed before it can be archived.
+fn add_entry<RW, W>(
+    zip_writer: &mut zip::ZipWriter<W>,
+    file_path: &str,
+    entry: &Entry<RW>,
+    options: FileOptions,
+    filters: &[Filter],
+    stats: &mut Stats,
+) -> Result<(), Error>
+where
+    RW: Read + Write + Seek,
+    W: Write + Seek,
+{
+    if entry.is_dir() {
+        return zip_writer
+            .add_directory(file_path, options)
+            .map_err(|_| Error::AddDirToArchive);
+    }
+
+    zip_writer
+        .start_file(file_path, options)
+        .map_err(|_| Error::AddFileToArchive)?;
+
+    let mut reader = entry
+        .try_reader()
+        .map_err(|_| Error::ReadData)?
+        .borrow_mut();
+
+    if let Some(filter) = filters::find(filters, file_path) {
+        let start = Instant::now();
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).map_err(|_| Error::ReadData)?;
+        stats.read_time += start.elapsed();
+        stats.read_bytes += data.len() as u64;
+
+        let data = filter.apply(&data).map_err(Error::Filter)?;
+
+        let start = Instant::now();
+        zip_writer.write_all(&data).map_err(|_| Error::WriteData)?;
+        stats.archive_time += start.elapsed();
+        return Ok(());
+    }
+
+    let mut buffer = vec![0u8; BLOCK_SIZE].into_boxed_slice();
+    loop {
+        let start = Instant::now();
+        let read_count = reader.read(&mut buffer).map_err(|_| Error::ReadData)?;
+        stats.read_time += start.elapsed();
+        stats.read_bytes += read_count as u64;
+
+        let start = Instant::now();
+        zip_writer
+            .write_all(&buffer[..read_count])
+            .map_err(|_| Error::WriteData)?;
+        stats.archive_time += start.elapsed();
+
+        if read_count != BLOCK_SIZE {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// This archives the data that's attached to each file (see `crate::streams`)
+///
+/// This reads from the filesystem directly, as there's no way to represent attached data with `Storage`.
+fn add_sidecars<RW, W>(
+    zip_writer: &mut zip::ZipWriter<W>,
+    entries: &[(String, &Entry<RW>)],
+    options: FileOptions,
+    streams: streams::Options,
+) -> Result<(), Error>
+where
+    RW: Read + Write + Seek,
+    W: Write + Seek,
+{
+    for (file_path, entry) in entries.iter().filter(|(_, e)| !e.is_dir()) {
+        let sidecars =
+            streams::capture(entry.path(), file_path, streams).map_err(|_| Error::ReadStreams)?;
+
+        for (name, data) in sidecars {
+            zip_writer
+                .start_file(name, options)
+                .map_err(|_| Error::AddFileToArchive)?;
+            zip_writer.write_all(&data).map_err(|_| Error::WriteData)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// This copies every compressed file that's next in line into the archive
+fn write_ready<W>(
+    zip_writer: &mut zip::ZipWriter<W>,
+    ready: &mut BTreeMap<usize, CompressedFile>,
+    next_index: &mut usize,
+    stats: &mut Stats,
+    progress: &mut ProgressReporter,
+) -> Result<(), Error>
+where
+    W: Write + Seek,
+{
+    while let Some(compressed) = ready.remove(next_index) {
+        let start = Instant::now();
+        let mut archive = zip::ZipArchive::new(Cursor::new(compressed.archive))
+            .map_err(|_| Error::AddFileToArchive)?;
+        let file = archive
+            .by_index_raw(0)
+            .map_err(|_| Error::AddFileToArchive)?;
+        zip_writer
+            .raw_copy_file(file)
+            .map_err(|_| Error::AddFileToArchive)?;
+        stats.archive_time += start.elapsed();
+        stats.compress_time += compressed.elapsed;
+        progress.file_written(&compressed.path, stats);
+
+        *next_index += 1;
+    }
+
+    Ok(())
+}
+
+/// This is the parallel version of the archive stage
+///
+/// Files are read on the current thread (storage handles can't be shared between threads), and sent to the workers through a bounded channel. Their results are copied into the archive in the original order, so the archive is the same regardless of which worker finishes first.
+///
+/// Directories, large files and filtered files are written directly, once everything before them has been.
+#[allow(clippy::too_many_arguments)]
+fn add_entries_parallel<RW, W>(
+    zip_writer: &mut zip::ZipWriter<W>,
+    stor: &Arc<impl Storage<RW>>,
+    entries: &[(String, &Entry<RW>)],
+    options: FileOptions,
+    filters: &[Filter],
+    jobs: usize,
+    stats: &mut Stats,
+    progress: &mut ProgressReporter,
+) -> Result<(), Error>
+where
+    RW: Read + Write + Seek,
+    W: Write + Seek,
+{
+    let (job_sender, job_receiver) = mpsc::sync_channel::<CompressJob>(jobs);
+    let job_receiver = Mutex::new(job_receiver);
+    let (result_sender, result_receiver) = mpsc::channel::<Result<CompressedFile, Error>>();
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs {
+            let job_receiver = &job_receiver;
+            let result_sender = result_sender.clone();
+            scope.spawn(move || loop {
+                let job = match job_receiver.lock() {
+                    Ok(receiver) => receiver.recv(),
+                    Err(_) => break,
+                };
+                let Ok(job) = job else { break };
+
+                if result_sender.send(compress_file(&job, options)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(result_sender);
+
+        // the job sender is moved in here, so the workers stop once this returns (even if it errors)
+        let job_sender = job_sender;
+        let mut ready = BTreeMap::new();
+        let mut next_index = 0;
+        let mut in_flight = 0;
+
+        let receive_one = |ready: &mut BTreeMap<usize, CompressedFile>,
+                           in_flight: &mut usize|
+         -> Result<(), Error> {
+            let compressed = result_receiver
+                .recv()
+                .map_err(|_| Error::AddFileToArchive)??;
+            ready.insert(compressed.index, compressed);
+            *in_flight -= 1;
+            Ok(())
+        };
+
+        for (index, (path, entry)) in entries.iter().enumerate() {
+            let filtered = filters::find(filters, path).is_some();
+            let len = if entry.is_dir() || filtered {
+                None
+            } else {
+                Some(stor.file_len(entry).map_err(|_| Error::ReadData)?)
+            };
+
+            match len {
+                Some(len) if len <= PARALLEL_FILE_LIMIT => {
+                    // this bounds the memory that's used by files waiting to be compressed/archived
+                    while in_flight >= jobs * 2 {
+                        receive_one(&mut ready, &mut in_flight)?;
+                        write_ready(zip_writer, &mut ready, &mut next_index, stats, progress)?;
+                    }
+
+                    let start = Instant::now();
+                    let mut data = Vec::with_capacity(len);
+                    entry
+                        .try_reader()
+                        .map_err(|_| Error::ReadData)?
+                        .borrow_mut()
+                        .read_to_end(&mut data)
+                        .map_err(|_| Error::ReadData)?;
+                    stats.read_time += start.elapsed();
+                    stats.read_bytes += data.len() as u64;
+
+                    job_sender
+                        .send(CompressJob {
+                            index,
+                            path: path.clone(),
+                            data,
+                        })
+                        .map_err(|_| Error::AddFileToArchive)?;
+                    in_flight += 1;
+                }
+                _ => {
+                    while next_index < index {
+                        receive_one(&mut ready, &mut in_flight)?;
+                        write_ready(zip_writer, &mut ready, &mut next_index, stats, progress)?;
+                    }
+
+                    add_entry(zip_writer, path, entry, options, filters, stats)?;
+                    if !entry.is_dir() {
+                        progress.file_written(path, stats);
+                    }
+                    next_index += 1;
+                }
+            }
+
+            write_ready(zip_writer, &mut ready, &mut next_index, stats, progress)?;
+        }
+
+        drop(job_sender);
+        while next_index < entries.len() {
+            receive_one(&mut ready, &mut in_flight)?;
+            write_ready(zip_writer, &mut ready, &mut next_index, stats, progress)?;
+        }
+
+        Ok(())
+    })
 }
 
+// this fills in the space that was reserved at the start of the archive
+fn prefix_archive_hash(tmp_writer: &mut (impl Read + Write + Seek)) -> Result<(), Error> {
+    tmp_writer
+        .seek(SeekFrom::Start(ARCHIVE_HASH_PREFIX_LEN as u64))
+        .map_err(|_| Error::FinishArchive)?;
+    let hash = hash_archive(tmp_writer).map_err(|_| Error::ReadData)?;
+
+    tmp_writer.rewind().map_err(|_| Error::FinishArchive)?;
+    tmp_writer
+        .write_all(&ARCHIVE_HASH_MAGIC)
+        .and_then(|()| tmp_writer.write_all(hash.as_bytes()))
+        .map_err(|_| Error::WriteData)
+}
+
+/// This splits out the files that match `base`, and returns their manifest entries
+///
+/// Files are only hashed if their path and size match, so a full backup (with an empty base) doesn't read anything.
+fn remove_unchanged<'a, RW>(
+    entries: NamedEntries<'a, RW>,
+    base: &[ManifestEntry],
+    filters: &[Filter],
+) -> Result<(NamedEntries<'a, RW>, Vec<ManifestEntry>), Error>
+where
+    RW: Read + Write + Seek,
+{
+    if base.is_empty() {
+        return Ok((entries, Vec::new()));
+    }
+
+    let base: BTreeMap<&str, &ManifestEntry> = base.iter().map(|e| (e.path.as_str(), e)).collect();
+    let mut changed = Vec::with_capacity(entries.len());
+    let mut unchanged = Vec::new();
+
+    for (name, entry) in entries {
+        let previous = base
+            .get(name.as_str())
+            .filter(|_| !entry.is_dir() && filters::find(filters, &name).is_none());
+        let Some(previous) = previous else {
+            changed.push((name, entry));
+            continue;
+        };
+
+        let mut reader = entry
+            .try_reader()
+            .map_err(|_| Error::ReadData)?
+            .borrow_mut();
+        let size = reader.seek(SeekFrom::End(0)).map_err(|_| Error::ReadData)?;
+        let hash = if size == previous.size {
+            reader.rewind().map_err(|_| Error::ReadData)?;
+            let mut hasher = blake3::Hasher::new();
+            std::io::copy(&mut *reader, &mut hasher).map_err(|_| Error::ReadData)?;
+            Some(hasher.finalize())
+        } else {
+            None
+        };
+        reader.rewind().map_err(|_| Error::ReadData)?;
+        drop(reader);
+
+        if hash == Some(previous.hash) {
+            unchanged.push((*previous).clone());
+        } else {
+            changed.push((name, entry));
+        }
+    }
+
+    Ok((changed, unchanged))
+}
+
+/// This lists every file within the finished archive, for the manifest, followed by the `unchanged` files that were left out of it
+///
+/// The files are read back from the archive itself, so their sizes and hashes match what unpacking produces (after any filters).
+fn list_archive(
+    tmp_reader: &mut (impl Read + Seek),
+    unchanged: Vec<ManifestEntry>,
+) -> Result<Vec<ManifestEntry>, Error> {
+    tmp_reader.rewind().map_err(|_| Error::ListArchive)?;
+    let mut archive = zip::ZipArchive::new(tmp_reader).map_err(|_| Error::ListArchive)?;
+
+    let mut entries = Vec::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(|_| Error::ListArchive)?;
+        if file.is_dir() {
+            continue;
+        }
+
+        let mut hasher = blake3::Hasher::new();
+        std::io::copy(&mut file, &mut hasher).map_err(|_| Error::ListArchive)?;
+
+        entries.push(ManifestEntry {
+            path: file.name().to_string(),
+            size: file.size(),
+            hash: hasher.finalize(),
+        });
+    }
+
+    entries.extend(unchanged);
+    Ok(entries)
+}
+
+#[allow(clippy::too_many_lines)]
 pub fn execute<RW>(stor: Arc<impl Storage<RW>>, req: Request<'_, RW>) -> Result<(), Error>
 where
     RW: Read + Write + Seek,
 {
+    let entries = name_entries(&req.roots, &req.compress_files)?;
+    let (entries, unchanged) = remove_unchanged(entries, &req.base, &req.filters)?;
+
+    let mut stats = Stats {
+        jobs: req.jobs.get(),
+        files: entries.iter().filter(|(_, f)| !f.is_dir()).count(),
+        unchanged: unchanged.len(),
+        ..Stats::default()
+    };
+    let mut progress = ProgressReporter {
+        on_progress: req.on_progress,
+        files: 0,
+        start: Instant::now(),
+    };
+
     // 1. Create zip archive.
     let tmp_file = stor.create_temp_file().map_err(|_| Error::CreateArchive)?;
-    {
+    let manifest = {
         let mut tmp_writer = tmp_file
             .try_writer()
             .map_err(|_| Error::CreateArchive)?
             .borrow_mut();
+
+        // reserve space for the hash, which is written once the archive is complete
+        tmp_writer
+            .write_all(&[0u8; ARCHIVE_HASH_PREFIX_LEN])
+            .map_err(|_| Error::WriteData)?;
+
         let mut zip_writer = zip::ZipWriter::new(BufWriter::new(&mut *tmp_writer));
 
         let options = FileOptions::default()
@@ -75,40 +679,52 @@ where
             .unix_permissions(0o755);
 
         // 2. Add files to the archive.
-        req.compress_files.into_iter().try_for_each(|f| {
-            let file_path = f.path().to_str().ok_or(Error::ReadData)?;
-            if f.is_dir() {
-                zip_writer
-                    .add_directory(file_path, options)
-                    .map_err(|_| Error::AddDirToArchive)?;
-            } else {
-                zip_writer
-                    .start_file(file_path, options)
-                    .map_err(|_| Error::AddFileToArchive)?;
-
-                let mut reader = f.try_reader().map_err(|_| Error::ReadData)?.borrow_mut();
-                let mut buffer = vec![0u8; BLOCK_SIZE].into_boxed_slice();
-                loop {
-                    let read_count = reader.read(&mut buffer).map_err(|_| Error::ReadData)?;
-                    zip_writer
-                        .write_all(&buffer[..read_count])
-                        .map_err(|_| Error::WriteData)?;
-                    if read_count != BLOCK_SIZE {
-                        break;
-                    }
+        if req.jobs.get() == 1 {
+            for (name, f) in &entries {
+                add_entry(&mut zip_writer, name, f, options, &req.filters, &mut stats)?;
+                if !f.is_dir() {
+                    progress.file_written(name, &stats);
                 }
             }
+        } else {
+            add_entries_parallel(
+                &mut zip_writer,
+                &stor,
+                &entries,
+                options,
+                &req.filters,
+                req.jobs.get(),
+                &mut stats,
+                &mut progress,
+            )?;
+        }
 
-            Ok(())
-        })?;
+        // 2a. Add the data that's attached to each file, once the files themselves are in place.
+        if req.streams.any() {
+            add_sidecars(&mut zip_writer, &entries, options, req.streams)?;
+        }
 
-        // 3. Close archive and switch writer to reader.
-        zip_writer.finish().map_err(|_| Error::FinishArchive)?;
-    }
+        // 3. Close archive, and prefix it with its hash.
+        zip_writer
+            .finish()
+            .map_err(|_| Error::FinishArchive)?
+            .flush()
+            .map_err(|_| Error::FinishArchive)?;
+        drop(zip_writer);
+
+        prefix_archive_hash(&mut *tmp_writer)?;
+
+        // 3a. List the archive's files, if they should be stored in the header.
+        req.manifest
+            .then(|| list_archive(&mut *tmp_writer, unchanged))
+            .transpose()?
+    };
 
     let buf_capacity = stor.file_len(&tmp_file).map_err(|_| Error::FinishArchive)?;
+    stats.archive_bytes = buf_capacity as u64;
 
     // 4. Encrypt zip archive
+    let start = Instant::now();
     let encrypt_res = crate::encrypt::execute(crate::encrypt::Request {
         reader: tmp_file.try_reader().map_err(|_| Error::FinishArchive)?,
         writer: req.writer,
@@ -116,19 +732,43 @@ where
         raw_key: req.raw_key,
         header_type: req.header_type,
         hashing_algorithm: req.hashing_algorithm,
+        compression: Compression::None,
+        block_size: BLOCK_SIZE,
+        padding: Padding::None,
+        convergent: false,
+        recipients: Vec::new(),
+        tokens: Vec::new(),
+        extra_keys: Vec::new(),
+        metadata: req.metadata,
+        mac: false,
+        digest: false,
+        seekable: false,
+        keyfile_hash: req.keyfile_hash,
+        counter: req.counter,
+        two_factor: req.two_factor,
+        manifest,
+        file_info: None,
+        fields: Vec::new(),
     })
     .map_err(Error::Encrypt);
+    stats.encrypt_time = start.elapsed();
 
     // 5. Finally eraze zip archive with zeros.
     crate::overwrite::execute(crate::overwrite::Request {
         buf_capacity,
         writer: tmp_file.try_writer().map_err(|_| Error::FinishArchive)?,
-        passes: 2,
+        passes: NonZeroU8::new(2).unwrap(),
     })
     .ok();
 
     stor.remove_file(tmp_file).ok();
 
+    if encrypt_res.is_ok() {
+        if let Some(cb) = req.on_stats {
+            cb(&stats);
+        }
+    }
+
     encrypt_res
 }
 
@@ -136,6 +776,7 @@ where
 mod tests {
     use super::*;
     use std::io::Read;
+    use std::rc::Rc;
 
     use core::header::{HeaderType, HeaderVersion};
     use core::primitives::{Algorithm, Mode};
@@ -143,7 +784,7 @@ mod tests {
     use crate::encrypt::tests::PASSWORD;
     use crate::storage::{InMemoryStorage, Storage};
 
-    const ENCRYPTED_PACKED_BAR_DIR: [u8; 1202] = [
+    const ENCRYPTED_PACKED_BAR_DIR: [u8; 1242] = [
         222, 5, 14, 1, 12, 1, 173, 240, 60, 45, 230, 243, 58, 160, 69, 50, 217, 192, 66, 223, 124,
         190, 148, 91, 92, 129, 0, 0, 0, 0, 0, 0, 223, 181, 71, 240, 140, 106, 41, 36, 82, 150, 105,
         215, 159, 108, 234, 246, 25, 19, 65, 206, 177, 146, 15, 174, 209, 129, 82, 2, 62, 76, 129,
@@ -159,49 +800,108 @@ mod tests {
         0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
         0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
         0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 22, 64, 6, 177, 49,
-        139, 218, 8, 121, 228, 19, 5, 8, 117, 33, 131, 131, 70, 76, 147, 108, 49, 191, 191, 127,
-        223, 64, 127, 248, 65, 201, 130, 166, 129, 195, 245, 241, 188, 143, 148, 191, 86, 7, 102,
-        124, 253, 12, 44, 172, 79, 236, 207, 68, 229, 117, 49, 250, 55, 6, 48, 86, 48, 244, 189,
-        137, 27, 142, 241, 44, 118, 35, 5, 138, 237, 47, 248, 108, 30, 224, 42, 91, 16, 216, 14,
-        235, 132, 33, 123, 83, 188, 196, 205, 18, 71, 152, 231, 231, 127, 182, 29, 156, 157, 203,
-        178, 178, 3, 216, 51, 84, 28, 67, 91, 255, 14, 124, 180, 131, 80, 48, 27, 111, 195, 39,
-        127, 37, 231, 111, 82, 132, 168, 253, 149, 230, 199, 161, 78, 6, 175, 98, 210, 9, 25, 145,
-        199, 151, 38, 142, 199, 217, 35, 247, 168, 73, 138, 94, 175, 45, 0, 184, 252, 55, 250, 19,
-        8, 79, 247, 38, 230, 133, 143, 66, 27, 69, 96, 183, 201, 238, 81, 114, 131, 123, 229, 78,
-        39, 140, 151, 4, 196, 49, 37, 58, 12, 48, 243, 83, 111, 84, 6, 82, 249, 200, 120, 238, 190,
-        136, 135, 189, 34, 237, 52, 18, 23, 43, 164, 113, 31, 111, 221, 119, 216, 110, 0, 74, 53,
-        81, 86, 83, 234, 70, 69, 194, 224, 96, 26, 47, 133, 49, 147, 204, 96, 125, 165, 105, 182,
-        161, 2, 143, 225, 195, 95, 64, 24, 49, 236, 210, 124, 32, 214, 69, 201, 5, 73, 5, 7, 160,
-        233, 35, 202, 226, 40, 104, 45, 214, 0, 39, 55, 167, 203, 184, 145, 150, 233, 119, 115,
-        246, 55, 162, 5, 154, 147, 144, 69, 217, 185, 39, 82, 223, 87, 132, 164, 148, 85, 234, 15,
-        160, 2, 214, 133, 27, 73, 53, 27, 86, 53, 215, 96, 142, 85, 25, 127, 11, 111, 19, 1, 72,
-        74, 92, 16, 14, 98, 20, 203, 163, 227, 160, 192, 158, 223, 99, 116, 212, 137, 101, 150,
-        182, 125, 244, 59, 20, 157, 129, 149, 34, 21, 136, 185, 41, 242, 168, 45, 135, 100, 219,
-        239, 132, 211, 238, 37, 242, 139, 218, 120, 112, 158, 75, 53, 172, 162, 136, 202, 94, 117,
-        152, 175, 205, 34, 198, 99, 49, 174, 187, 80, 151, 225, 169, 120, 192, 77, 61, 38, 2, 158,
-        45, 216, 78, 215, 134, 255, 7, 46, 144, 119, 60, 168, 202, 24, 239, 147, 122, 58, 48, 50,
-        178, 58, 153, 243, 242, 169, 238, 42, 78, 123, 37, 181, 17, 109, 175, 84, 6, 212, 122, 89,
-        60, 111, 248, 41, 156, 214, 222, 151, 212, 52, 10, 221, 69, 1, 215, 170, 76, 149, 134, 241,
-        212, 217, 131, 179, 34, 240, 124, 224, 192, 105, 34, 254, 165, 211, 100, 169, 240, 171,
-        131, 50, 80, 54, 254, 128, 179, 233, 223, 22, 39, 56, 205, 221, 76, 177, 197, 164, 140,
-        181, 42, 154, 82, 239, 240, 127, 211, 45, 146, 57, 154, 151, 153, 112, 215, 222, 199, 37,
-        44, 98, 118, 182, 189, 15, 139, 88, 227, 37, 149, 107, 13, 123, 201, 51, 61, 67, 220, 161,
-        13, 72, 176, 39, 157, 128, 105, 144, 10, 46, 29, 113, 1, 76, 162, 157, 200, 213, 175, 107,
-        128, 13, 47, 170, 216, 107, 48, 241, 149, 219, 20, 186, 74, 210, 5, 210, 18, 201, 78, 159,
-        121, 180, 195, 154, 176, 154, 255, 21, 5, 86, 212, 181, 237, 131, 116, 59, 241, 57, 24,
-        102, 126, 132, 135, 154, 99, 217, 2, 201, 139, 202, 125, 64, 165, 195, 210, 255, 165, 197,
-        172, 166, 27, 200, 226, 158, 225, 224, 10, 150, 97, 2, 77, 73, 51, 112, 201, 146, 74, 245,
-        95, 191, 244, 128, 170, 109, 227, 44, 24, 11, 216, 35, 137, 61, 120, 207, 212, 57, 229, 70,
-        152, 118, 92, 235, 187, 55, 189, 231, 126, 15, 86, 66, 78, 251, 39, 181, 191, 193, 226,
-        199, 131, 61, 145, 177, 76, 168, 0, 235, 172, 21, 213, 87, 81, 176, 135, 139, 61, 3, 91,
-        67, 84, 199, 40, 113, 140, 68, 174, 34, 199, 50, 33, 187, 208, 209, 155, 237, 140, 16, 204,
-        135, 151, 241, 28, 95, 87, 91, 169, 160, 1, 206, 18, 220, 65, 236, 52, 63, 184, 226, 237,
-        129, 19, 170, 194, 11, 154, 168, 110, 242, 19, 167, 195, 205, 68, 4, 151, 99, 196, 164, 13,
-        137, 140, 175, 134, 102, 47, 63, 0, 229, 73, 218, 226, 121, 230, 98, 31, 102, 161, 40, 233,
-        229, 39, 224, 19, 92, 220, 151, 154, 193, 191, 30,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 83, 85, 254, 109,
+        216, 146, 57, 87, 40, 162, 136, 117, 246, 255, 99, 154, 185, 12, 96, 238, 207, 103, 197,
+        99, 112, 205, 253, 241, 117, 54, 122, 156, 123, 94, 63, 13, 212, 85, 5, 239, 29, 4, 98,
+        104, 253, 12, 44, 172, 79, 236, 207, 101, 229, 37, 122, 249, 51, 237, 207, 169, 207, 11,
+        66, 118, 228, 171, 241, 56, 118, 65, 100, 7, 61, 209, 7, 131, 225, 31, 213, 82, 16, 204,
+        14, 137, 229, 83, 84, 125, 218, 171, 162, 61, 70, 200, 188, 228, 123, 162, 29, 156, 157,
+        203, 178, 178, 3, 249, 51, 84, 28, 67, 91, 0, 161, 200, 72, 120, 187, 207, 228, 102, 195,
+        51, 127, 71, 167, 29, 251, 12, 222, 164, 5, 54, 57, 94, 161, 249, 80, 157, 192, 9, 13, 145,
+        165, 246, 84, 161, 233, 191, 76, 152, 135, 33, 191, 121, 192, 70, 58, 204, 132, 67, 251,
+        19, 24, 79, 211, 38, 96, 35, 159, 116, 228, 186, 154, 72, 54, 17, 174, 141, 145, 123, 153,
+        43, 41, 129, 138, 123, 161, 84, 78, 65, 35, 88, 150, 63, 3, 59, 40, 7, 129, 255, 104, 153,
+        148, 119, 125, 66, 221, 18, 203, 237, 232, 57, 161, 101, 31, 13, 188, 5, 247, 64, 14, 64,
+        54, 18, 78, 108, 211, 41, 37, 248, 148, 24, 110, 46, 133, 33, 147, 232, 96, 62, 180, 30,
+        140, 94, 253, 117, 30, 60, 160, 191, 231, 35, 236, 177, 19, 48, 219, 83, 182, 96, 44, 110,
+        124, 143, 158, 76, 184, 142, 76, 70, 120, 174, 242, 128, 39, 129, 52, 66, 110, 105, 22,
+        136, 140, 9, 57, 167, 17, 154, 241, 241, 55, 246, 151, 56, 88, 193, 87, 143, 218, 171, 46,
+        154, 26, 160, 18, 214, 128, 27, 73, 53, 58, 86, 179, 113, 117, 184, 170, 230, 128, 244,
+        144, 236, 150, 210, 40, 48, 107, 94, 75, 118, 189, 152, 205, 200, 165, 242, 179, 12, 90,
+        129, 241, 82, 134, 193, 87, 11, 193, 235, 98, 126, 106, 221, 234, 133, 188, 61, 242, 202,
+        76, 245, 75, 172, 232, 147, 211, 230, 100, 214, 184, 173, 125, 100, 142, 75, 48, 172, 162,
+        136, 202, 127, 117, 219, 187, 186, 24, 57, 156, 206, 81, 68, 216, 7, 108, 200, 28, 132, 6,
+        94, 69, 94, 178, 78, 183, 60, 187, 226, 209, 115, 86, 197, 118, 60, 184, 202, 29, 239, 147,
+        122, 58, 48, 50, 178, 63, 157, 243, 242, 169, 238, 42, 78, 12, 74, 199, 125, 9, 18, 94, 7,
+        214, 84, 90, 74, 14, 138, 6, 204, 157, 223, 149, 219, 55, 30, 221, 69, 1, 215, 170, 76,
+        149, 167, 241, 212, 217, 135, 179, 34, 240, 124, 224, 192, 105, 34, 254, 172, 211, 137,
+        232, 216, 171, 131, 50, 50, 87, 140, 175, 14, 227, 232, 20, 9, 59, 187, 188, 62, 158, 235,
+        194, 227, 218, 36, 202, 25, 238, 242, 81, 208, 57, 146, 57, 154, 151, 153, 112, 222, 255,
+        199, 163, 138, 114, 64, 179, 189, 15, 139, 93, 14, 100, 203, 121, 13, 123, 171, 82, 79,
+        108, 242, 199, 98, 39, 159, 154, 87, 240, 107, 190, 9, 88, 124, 3, 46, 98, 196, 242, 167,
+        219, 199, 136, 74, 113, 118, 129, 172, 19, 68, 164, 222, 218, 22, 134, 73, 198, 5, 210, 18,
+        201, 78, 159, 121, 149, 195, 52, 32, 116, 197, 16, 5, 52, 181, 194, 194, 173, 18, 70, 158,
+        22, 112, 3, 18, 232, 232, 180, 23, 161, 118, 116, 65, 113, 127, 110, 166, 181, 179, 141,
+        138, 235, 202, 201, 116, 198, 149, 178, 130, 251, 84, 189, 21, 122, 57, 28, 120, 113, 203,
+        174, 73, 225, 95, 191, 244, 128, 170, 109, 227, 13, 24, 96, 255, 209, 191, 56, 120, 173,
+        181, 78, 202, 104, 254, 23, 51, 196, 204, 88, 207, 139, 26, 33, 34, 58, 58, 70, 237, 183,
+        188, 239, 225, 177, 226, 79, 190, 159, 36, 205, 108, 166, 195, 189, 7, 63, 19, 229, 204,
+        138, 63, 40, 88, 87, 84, 201, 40, 113, 140, 68, 174, 3, 199, 113, 48, 204, 234, 57, 26,
+        198, 141, 21, 204, 229, 246, 142, 51, 113, 63, 62, 197, 204, 110, 224, 102, 164, 53, 81,
+        254, 118, 187, 204, 238, 247, 114, 216, 237, 124, 245, 218, 2, 183, 61, 144, 170, 206, 46,
+        74, 146, 101, 196, 161, 13, 137, 138, 162, 128, 102, 79, 62, 0, 229, 197, 219, 226, 121,
+        230, 143, 217, 4, 130, 63, 23, 223, 29, 220, 60, 105, 239, 115, 208, 146, 98, 163, 85, 246,
+        71, 112, 199, 141, 170, 67, 47, 175, 146, 11, 43, 21, 255, 242, 104, 103, 232, 232, 177,
+        207, 218, 57, 245, 244, 90, 158, 86, 80, 100, 148, 90, 105, 21, 136, 179, 71, 249, 97,
     ];
 
+    #[test]
+    fn should_place_roots_beneath_their_prefixes() {
+        let stor = Arc::new(InMemoryStorage::default());
+        stor.add_hello_txt();
+        stor.add_bar_foo_folder();
+
+        let dir = stor.read_file("bar/").unwrap();
+        let mut entries = stor.read_dir(&dir).unwrap();
+        entries.sort_by(|a, b| a.path().cmp(b.path()));
+        entries.push(stor.read_file("hello.txt").unwrap());
+
+        let roots = vec![
+            Root {
+                path: PathBuf::from("bar/"),
+                prefix: "docs".to_string(),
+            },
+            Root {
+                path: PathBuf::from("hello.txt"),
+                prefix: "greeting.txt".to_string(),
+            },
+        ];
+
+        let names = name_entries(&roots, &entries)
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name.replace('\\', "/"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "docs",
+                "docs/foo",
+                "docs/foo/hello.txt",
+                "docs/foo/world.txt",
+                "docs/hello.txt",
+                "docs/world.txt",
+                "greeting.txt",
+            ]
+        );
+
+        // the directory's contents would clash with the file at the top level
+        let roots = vec![
+            Root {
+                path: PathBuf::from("bar/"),
+                prefix: String::new(),
+            },
+            Root {
+                path: PathBuf::from("hello.txt"),
+                prefix: "hello.txt".to_string(),
+            },
+        ];
+        assert!(matches!(
+            name_entries(&roots, &entries),
+            Err(Error::DuplicatePath(_))
+        ));
+    }
+
     #[test]
     fn should_pack_bar_directory() {
         let stor = Arc::new(InMemoryStorage::default());
@@ -216,6 +916,7 @@ mod tests {
 
         let req = Request {
             compress_files,
+            roots: Vec::new(),
             compression_method: zip::CompressionMethod::Stored,
             writer: output_file.try_writer().unwrap(),
             header_writer: None,
@@ -226,6 +927,17 @@ mod tests {
                 mode: Mode::StreamMode,
             },
             hashing_algorithm: HashingAlgorithm::Blake3Balloon(5),
+            jobs: NonZeroUsize::new(1).unwrap(),
+            on_stats: None,
+            on_progress: None,
+            metadata: None,
+            streams: streams::Options::default(),
+            filters: Vec::new(),
+            keyfile_hash: false,
+            counter: StreamCounter::Le31,
+            two_factor: false,
+            manifest: false,
+            base: Vec::new(),
         };
 
         match execute(stor, req) {
@@ -241,4 +953,195 @@ mod tests {
             _ => unreachable!(),
         }
     }
+
+    #[test]
+    fn should_pack_bar_directory_in_parallel() {
+        let stor = Arc::new(InMemoryStorage::default());
+        stor.add_hello_txt();
+        stor.add_bar_foo_folder_with_hidden();
+
+        let file = stor.read_file("bar/").unwrap();
+        let mut compress_files = stor.read_dir(&file).unwrap();
+        compress_files.sort_by(|a, b| a.path().cmp(b.path()));
+        let paths: Vec<_> = compress_files
+            .iter()
+            .map(|f| f.path().to_str().unwrap().to_string())
+            .collect();
+
+        let output_file = stor.create_file("bar.zip.enc").unwrap();
+        let stats = Rc::new(RefCell::new(None));
+        let on_stats = {
+            let stats = stats.clone();
+            Box::new(move |s: &Stats| *stats.borrow_mut() = Some(s.clone()))
+        };
+        let progress = Rc::new(RefCell::new(Vec::new()));
+        let on_progress = {
+            let progress = progress.clone();
+            Box::new(move |p: &Progress<'_>| {
+                progress.borrow_mut().push((p.files, p.total));
+            })
+        };
+
+        let req = Request {
+            compress_files,
+            roots: Vec::new(),
+            compression_method: zip::CompressionMethod::Zstd,
+            writer: output_file.try_writer().unwrap(),
+            header_writer: None,
+            raw_key: Protected::new(PASSWORD.to_vec()),
+            header_type: HeaderType {
+                version: HeaderVersion::V5,
+                algorithm: Algorithm::XChaCha20Poly1305,
+                mode: Mode::StreamMode,
+            },
+            hashing_algorithm: HashingAlgorithm::Blake3Balloon(5),
+            jobs: NonZeroUsize::new(3).unwrap(),
+            on_stats: Some(on_stats),
+            on_progress: Some(on_progress),
+            metadata: None,
+            streams: streams::Options::default(),
+            filters: Vec::new(),
+            keyfile_hash: false,
+            counter: StreamCounter::Le31,
+            two_factor: false,
+            manifest: false,
+            base: Vec::new(),
+        };
+
+        match execute(stor.clone(), req) {
+            Ok(()) => {
+                let stats = stats.borrow_mut().take().unwrap();
+                assert_eq!(stats.jobs, 3);
+                assert_eq!(stats.files, 4);
+                assert_eq!(stats.read_bytes, 20);
+
+                // files are reported in archive order, even though they're compressed in parallel
+                let progress = progress.borrow();
+                assert_eq!(progress.len(), 4);
+                for (i, reported) in progress.iter().enumerate() {
+                    assert_eq!(*reported, (i + 1, 4));
+                }
+
+                let reader = output_file.try_writer().unwrap();
+                reader.borrow_mut().rewind().unwrap();
+                let archive_file = stor.create_file("bar.zip").unwrap();
+
+                crate::decrypt::execute(crate::decrypt::Request {
+                    header_reader: None,
+                    reader,
+                    writer: archive_file.try_writer().unwrap(),
+                    raw_key: Protected::new(PASSWORD.to_vec()),
+                    master_key: None,
+                    identity: None,
+                    on_decrypted_header: None,
+                    on_file_info: None,
+                })
+                .unwrap();
+
+                let mut archive = archive_file.try_writer().unwrap().borrow_mut();
+                archive.rewind().unwrap();
+                let mut content = vec![];
+                archive.read_to_end(&mut content).unwrap();
+
+                // the hash prefix is skipped over by the zip reader
+                let mut zip = zip::ZipArchive::new(Cursor::new(content)).unwrap();
+                assert_eq!(zip.file_names().count(), paths.len());
+
+                for (index, path) in paths.iter().enumerate() {
+                    let mut file = zip.by_index(index).unwrap();
+                    assert_eq!(file.name(), path);
+
+                    if !file.is_dir() {
+                        let mut text = String::new();
+                        file.read_to_string(&mut text).unwrap();
+                        assert!(text == "hello" || text == "world");
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // this packs the bar directory with a manifest, and returns its stats and manifest
+    fn pack_bar_with_base(
+        stor: &Arc<InMemoryStorage>,
+        base: Vec<ManifestEntry>,
+    ) -> (Stats, Vec<ManifestEntry>) {
+        let file = stor.read_file("bar/").unwrap();
+        let mut compress_files = stor.read_dir(&file).unwrap();
+        compress_files.sort_by(|a, b| a.path().cmp(b.path()));
+
+        let output_file = stor.create_temp_file().unwrap();
+        let stats = Rc::new(RefCell::new(None));
+        let on_stats = {
+            let stats = stats.clone();
+            Box::new(move |s: &Stats| *stats.borrow_mut() = Some(s.clone()))
+        };
+
+        execute(
+            stor.clone(),
+            Request {
+                compress_files,
+                roots: Vec::new(),
+                compression_method: zip::CompressionMethod::Stored,
+                writer: output_file.try_writer().unwrap(),
+                header_writer: None,
+                raw_key: Protected::new(PASSWORD.to_vec()),
+                header_type: HeaderType {
+                    version: HeaderVersion::V6,
+                    algorithm: Algorithm::XChaCha20Poly1305,
+                    mode: Mode::StreamMode,
+                },
+                hashing_algorithm: HashingAlgorithm::Blake3Balloon(5),
+                jobs: NonZeroUsize::new(1).unwrap(),
+                on_stats: Some(on_stats),
+                on_progress: None,
+                metadata: None,
+                streams: streams::Options::default(),
+                filters: Vec::new(),
+                keyfile_hash: false,
+                counter: StreamCounter::Le31,
+                two_factor: false,
+                manifest: true,
+                base,
+            },
+        )
+        .unwrap();
+
+        let handle = output_file.try_writer().unwrap();
+        handle.borrow_mut().rewind().unwrap();
+        let manifest = crate::manifest::execute(crate::manifest::Request {
+            handle,
+            raw_key: Protected::new(PASSWORD.to_vec()),
+        })
+        .unwrap();
+
+        let stats = stats.borrow_mut().take().unwrap();
+        (stats, manifest)
+    }
+
+    #[test]
+    fn should_leave_unchanged_files_out() {
+        let stor = Arc::new(InMemoryStorage::default());
+        stor.add_hello_txt();
+        stor.add_bar_foo_folder_with_hidden();
+
+        let (stats, mut base) = pack_bar_with_base(&stor, Vec::new());
+        assert_eq!(stats.files, 4);
+        assert_eq!(stats.unchanged, 0);
+
+        // one file has changed since the base was packed
+        base[0].hash = blake3::hash(b"changed");
+        let mut expected: Vec<String> = base.iter().map(|e| e.path.clone()).collect();
+
+        let (stats, manifest) = pack_bar_with_base(&stor, base);
+        assert_eq!(stats.files, 1);
+        assert_eq!(stats.unchanged, 3);
+
+        // the manifest still lists every file
+        let mut listed: Vec<String> = manifest.iter().map(|e| e.path.clone()).collect();
+        listed.sort();
+        expected.sort();
+        assert_eq!(listed, expected);
+    }
 }
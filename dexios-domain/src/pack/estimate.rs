@@ -0,0 +1,304 @@
+//! This estimates how large an archive will be (and how long packing it will take) without creating it.
+//!
+//! Files are grouped by their extension, and a percentage of each group is compressed in memory. The group's compression ratio and throughput are then extrapolated to the rest of its files, which are only measured (not read). The sampled data is also encrypted, to time the encryption.
+//!
+//! Neither the header nor the key derivation are included, as they don't depend on the input.
+
+use std::collections::BTreeMap;
+use std::io::{Read, Seek, Write};
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use core::primitives::{get_nonce_len, Algorithm, Mode, BLOCK_SIZE};
+use core::protected::Protected;
+use core::stream::EncryptionStreams;
+use zip::write::FileOptions;
+
+use super::{compress_file, name_entries, CompressJob, Error, Root, ARCHIVE_HASH_PREFIX_LEN};
+use crate::storage::{Entry, Storage};
+
+/// This is the length of the AEAD tag that's appended to each block of the stream
+const TAG_LEN: u64 = 16;
+
+/// This is the length of the zip's end of central directory record, which every archive ends with
+const END_OF_ARCHIVE_LEN: u64 = 22;
+
+pub struct Request<'a, RW>
+where
+    RW: Read + Write + Seek,
+{
+    pub compress_files: &'a [Entry<RW>],
+    pub roots: &'a [Root],
+    pub compression_method: zip::CompressionMethod,
+    pub algorithm: Algorithm,
+    /// This is the percentage (1-100) of each type's files that are compressed, at least one of each type is always sampled
+    pub percent: u8,
+    pub jobs: NonZeroUsize,
+}
+
+/// This is what was measured for the files of one type, and what's extrapolated from it
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeEstimate {
+    /// The lowercase extension, which is empty for files without one
+    pub extension: String,
+    pub files: usize,
+    pub sampled_files: usize,
+    pub bytes: u64,
+    pub sampled_bytes: u64,
+    /// The compressed size divided by the original size, for the sampled files
+    pub ratio: f64,
+    pub archive_bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Estimate {
+    pub types: Vec<TypeEstimate>,
+    pub files: usize,
+    pub sampled_files: usize,
+    pub bytes: u64,
+    pub archive_bytes: u64,
+    /// This is the archive's size once it's encrypted, excluding the header
+    pub encrypted_bytes: u64,
+    pub duration: Duration,
+}
+
+struct Sample {
+    len: u64,
+    compressed: u64,
+    overhead: u64,
+    elapsed: Duration,
+}
+
+fn extension(name: &str) -> String {
+    std::path::Path::new(name)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+// the files are sampled evenly across the (sorted) group, rather than just taking the first few
+fn pick(files: usize, percent: u8) -> impl Iterator<Item = usize> {
+    let count = (files * usize::from(percent.clamp(1, 100)))
+        .div_ceil(100)
+        .max(1);
+    (0..count.min(files)).map(move |i| i * files / count)
+}
+
+// this compresses the file just as `super::compress_file()` would, and compares it with an empty file of the same name (which is the zip's per-file overhead)
+fn sample_file<RW>(
+    name: &str,
+    entry: &Entry<RW>,
+    options: FileOptions,
+) -> Result<(Sample, Vec<u8>), Error>
+where
+    RW: Read + Write + Seek,
+{
+    let start = Instant::now();
+    let mut data = Vec::new();
+    {
+        let mut reader = entry
+            .try_reader()
+            .map_err(|_| Error::ReadData)?
+            .borrow_mut();
+        reader.rewind().map_err(|_| Error::ReadData)?;
+        reader.read_to_end(&mut data).map_err(|_| Error::ReadData)?;
+        reader.rewind().map_err(|_| Error::ReadData)?;
+    }
+
+    let job = CompressJob {
+        index: 0,
+        path: name.to_string(),
+        data,
+    };
+    let compressed = compress_file(&job, options)?;
+    let elapsed = start.elapsed();
+
+    let empty = compress_file(
+        &CompressJob {
+            index: 0,
+            path: name.to_string(),
+            data: Vec::new(),
+        },
+        options,
+    )?;
+    let overhead = (empty.archive.len() as u64).saturating_sub(END_OF_ARCHIVE_LEN);
+
+    Ok((
+        Sample {
+            len: job.data.len() as u64,
+            compressed: (compressed.archive.len() as u64).saturating_sub(overhead),
+            overhead,
+            elapsed,
+        },
+        compressed.archive,
+    ))
+}
+
+// this encrypts the data with a throwaway key, and returns how long it took per byte
+fn encryption_time_per_byte(algorithm: Algorithm, data: &[u8]) -> Result<f64, Error> {
+    let nonce = vec![0u8; get_nonce_len(&algorithm, &Mode::StreamMode)];
+    let streams = EncryptionStreams::initialize(Protected::new([0u8; 32]), &nonce, &algorithm)
+        .map_err(|_| Error::Encrypt(crate::encrypt::Error::InitializeStreams))?;
+
+    let start = Instant::now();
+    streams
+        .encrypt_file(&mut &data[..], &mut std::io::sink(), &[], BLOCK_SIZE)
+        .map_err(|_| Error::Encrypt(crate::encrypt::Error::EncryptFile))?;
+
+    #[allow(clippy::cast_precision_loss)]
+    Ok(start.elapsed().as_secs_f64() / data.len().max(1) as f64)
+}
+
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+pub fn execute<RW>(stor: Arc<impl Storage<RW>>, req: Request<'_, RW>) -> Result<Estimate, Error>
+where
+    RW: Read + Write + Seek,
+{
+    let entries = name_entries(req.roots, req.compress_files)?;
+    let options = FileOptions::default()
+        .compression_method(req.compression_method)
+        .large_file(true)
+        .unix_permissions(0o755);
+
+    let mut groups: BTreeMap<String, Vec<(&str, &Entry<RW>)>> = BTreeMap::new();
+    let mut dirs = 0u64;
+    for (name, entry) in &entries {
+        if entry.is_dir() {
+            dirs += 1;
+        } else {
+            groups
+                .entry(extension(name))
+                .or_default()
+                .push((name.as_str(), *entry));
+        }
+    }
+
+    let mut types = Vec::with_capacity(groups.len());
+    let mut compress_time = 0.0;
+    let mut sampled_archives = Vec::new();
+    for (extension, mut files) in groups {
+        files.sort_by(|a, b| a.0.cmp(b.0));
+
+        let bytes = files
+            .iter()
+            .map(|(_, entry)| stor.file_len(entry).map(|len| len as u64))
+            .sum::<Result<u64, _>>()
+            .map_err(|_| Error::ReadData)?;
+
+        let mut samples = Vec::new();
+        for i in pick(files.len(), req.percent) {
+            let (name, entry) = files[i];
+            let (sample, archive) = sample_file(name, entry, options)?;
+            samples.push(sample);
+            sampled_archives.extend(archive);
+        }
+
+        let sampled_bytes: u64 = samples.iter().map(|s| s.len).sum();
+        let compressed: u64 = samples.iter().map(|s| s.compressed).sum();
+        let overhead: u64 = samples.iter().map(|s| s.overhead).sum();
+        let elapsed: f64 = samples.iter().map(|s| s.elapsed.as_secs_f64()).sum();
+
+        let ratio = if sampled_bytes == 0 {
+            1.0
+        } else {
+            compressed as f64 / sampled_bytes as f64
+        };
+        let per_file_overhead = overhead as f64 / samples.len() as f64;
+        let archive_bytes = (bytes as f64 * ratio + per_file_overhead * files.len() as f64) as u64;
+
+        // empty files still take time to open, so their time is spread across the files rather than the bytes
+        compress_time += if sampled_bytes == 0 {
+            elapsed / samples.len() as f64 * files.len() as f64
+        } else {
+            elapsed / sampled_bytes as f64 * bytes as f64
+        };
+
+        types.push(TypeEstimate {
+            extension,
+            files: files.len(),
+            sampled_files: samples.len(),
+            bytes,
+            sampled_bytes,
+            ratio,
+            archive_bytes,
+        });
+    }
+
+    // directories are stored as empty entries, which are roughly the same size as an empty file's
+    let dir_overhead = dirs * 128;
+    let archive_bytes = types.iter().map(|t| t.archive_bytes).sum::<u64>()
+        + dir_overhead
+        + ARCHIVE_HASH_PREFIX_LEN as u64
+        + END_OF_ARCHIVE_LEN;
+    let blocks = archive_bytes.div_ceil(BLOCK_SIZE as u64);
+    let encrypted_bytes = archive_bytes + blocks * TAG_LEN;
+
+    let encrypt_time =
+        encryption_time_per_byte(req.algorithm, &sampled_archives)? * archive_bytes as f64;
+    let jobs = req
+        .jobs
+        .get()
+        .min(types.iter().map(|t| t.files).sum::<usize>().max(1));
+
+    Ok(Estimate {
+        files: types.iter().map(|t| t.files).sum(),
+        sampled_files: types.iter().map(|t| t.sampled_files).sum(),
+        bytes: types.iter().map(|t| t.bytes).sum(),
+        types,
+        archive_bytes,
+        encrypted_bytes,
+        duration: Duration::from_secs_f64(compress_time / jobs as f64 + encrypt_time),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::InMemoryStorage;
+
+    #[test]
+    fn should_sample_evenly() {
+        assert_eq!(pick(10, 10).collect::<Vec<_>>(), vec![0]);
+        assert_eq!(pick(10, 20).collect::<Vec<_>>(), vec![0, 5]);
+        assert_eq!(pick(3, 1).collect::<Vec<_>>(), vec![0]);
+        assert_eq!(pick(3, 100).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(pick(0, 50).count(), 0);
+    }
+
+    #[test]
+    fn should_estimate_by_type() {
+        let stor = Arc::new(InMemoryStorage::default());
+        stor.add_bar_foo_folder();
+
+        let file = stor.read_file("bar/").unwrap();
+        let compress_files = stor.read_dir(&file).unwrap();
+
+        let estimate = execute(
+            stor,
+            Request {
+                compress_files: &compress_files,
+                roots: &[],
+                compression_method: zip::CompressionMethod::Stored,
+                algorithm: Algorithm::XChaCha20Poly1305,
+                percent: 50,
+                jobs: NonZeroUsize::new(1).unwrap(),
+            },
+        )
+        .unwrap();
+
+        assert_eq!(estimate.files, 4);
+        assert_eq!(estimate.sampled_files, 2);
+        assert_eq!(estimate.bytes, 20);
+        assert_eq!(estimate.types.len(), 1);
+        assert_eq!(estimate.types[0].extension, "txt");
+
+        // stored files aren't compressed, so the zip's own overhead makes the archive larger
+        assert!(estimate.archive_bytes > estimate.bytes);
+        assert!(estimate.encrypted_bytes > estimate.archive_bytes);
+    }
+}
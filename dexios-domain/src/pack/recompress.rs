@@ -0,0 +1,549 @@
+//! This compresses the files within an encrypted pack again, with a different codec or level (e.g. to move old backups onto cheaper storage).
+//!
+//! The archive is decrypted on one thread, compressed again on another, and encrypted on the current one, so none of it is ever written anywhere unencrypted. Each file is compressed in memory on its own, and copied into the new archive in the same order.
+//!
+//! The new archive is prefixed with its hash (see `super::ARCHIVE_HASH_MAGIC`), which is only known once it's complete. So this happens twice: the first pass only hashes the new archive (and checks the old one's hash), and the second encrypts it. Compression is deterministic, and the hashes of both passes are compared to make sure.
+//!
+//! The master key is kept, so every keyslot still unlocks the new pack. Only the nonce is generated again, and the rest of the header (including the manifest, which lists each file's size and hash rather than how it's compressed) is kept as it is.
+//!
+//! Packs with a plaintext digest (see `core::digest`) can't be recompressed. The digest is always encrypted with the same nonce, under a key that only depends on the master key, so encrypting the new archive's digest would reuse that key and nonce.
+
+use std::cell::RefCell;
+use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+
+use core::header::Header;
+use core::primitives::{Mode, MASTER_KEY_LEN};
+use core::protected::Protected;
+use zip::write::FileOptions;
+
+use super::{ARCHIVE_HASH_MAGIC, ARCHIVE_HASH_PREFIX_LEN};
+use crate::pipe::pipe;
+
+#[derive(Debug)]
+pub enum Error {
+    Read,
+    Seek,
+    HeaderDeserialize,
+    Unsupported,
+    Digest,
+    DetachedHeader,
+    IncorrectKey,
+    ReadArchive,
+    WriteArchive,
+    ArchiveHashMismatch,
+    Nondeterministic,
+    Decrypt(crate::decrypt::Error),
+    Encrypt(crate::encrypt::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Read => f.write_str("Unable to read the encrypted pack"),
+            Error::Seek => f.write_str("Unable to seek the encrypted pack"),
+            Error::HeaderDeserialize => f.write_str("Unable to deserialize the header"),
+            Error::Unsupported => {
+                f.write_str("Only packs that were encrypted in stream mode can be recompressed")
+            }
+            Error::Digest => f.write_str(
+                "Packs with a plaintext digest can't be recompressed, as the new digest would be encrypted with the same key and nonce",
+            ),
+            Error::DetachedHeader => f.write_str(
+                "The input only contains a header, so the pack it belongs to can't be recompressed",
+            ),
+            Error::IncorrectKey => {
+                f.write_str("Unable to decrypt the master key (maybe you supplied the wrong key?)")
+            }
+            Error::ReadArchive => f.write_str("Unable to read a file from the archive"),
+            Error::WriteArchive => f.write_str("Unable to write a file to the new archive"),
+            Error::ArchiveHashMismatch => {
+                f.write_str("The archive doesn't match its hash, so it may be corrupted")
+            }
+            Error::Nondeterministic => f.write_str(
+                "The archive was compressed differently each time, so its hash can't be trusted",
+            ),
+            Error::Decrypt(inner) => write!(f, "Unable to decrypt the pack: {inner}"),
+            Error::Encrypt(inner) => write!(f, "Unable to encrypt the new pack: {inner}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub struct Request<'a, R, W>
+where
+    R: Read + Seek + Send,
+    W: Write + Seek,
+{
+    pub reader: &'a RefCell<R>, // the encrypted pack
+    pub writer: &'a RefCell<W>, // where the new pack is written
+    pub raw_key: Protected<Vec<u8>>,
+    pub compression_method: zip::CompressionMethod,
+    /// This is the level that `compression_method` uses (the codec's default is used if it's not set)
+    pub compression_level: Option<i32>,
+}
+
+pub struct Response {
+    /// This is the number of entries (files and directories) that were compressed again
+    pub entries: usize,
+    /// The size of the old archive, before it was encrypted
+    pub old_archive_bytes: u64,
+    /// The size of the new archive, before it was encrypted
+    pub new_archive_bytes: u64,
+}
+
+/// This is what one pass found, once the whole archive has been read
+struct Recompressed {
+    entries: usize,
+    read_bytes: u64,
+    written_bytes: u64,
+    hash: blake3::Hash,
+}
+
+// this hashes the old archive as it's read, so it can be checked against its prefix
+struct HashingReader<R> {
+    inner: R,
+    hasher: blake3::Hasher,
+    len: u64,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.len += read as u64;
+        Ok(read)
+    }
+}
+
+// every entry is copied into the new archive as it is, so the zip writer never needs to seek back into it
+// it only asks where it is (for the central directory), which this keeps track of
+struct ArchiveWriter<W> {
+    inner: W,
+    hasher: blake3::Hasher,
+    position: u64,
+}
+
+impl<W: Write> Write for ArchiveWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W> Seek for ArchiveWriter<W> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match pos {
+            SeekFrom::Current(0) => Ok(self.position),
+            SeekFrom::Start(position) if position == self.position => Ok(self.position),
+            _ => Err(std::io::Error::new(
+                ErrorKind::Unsupported,
+                "The new archive can only be written in order",
+            )),
+        }
+    }
+}
+
+// this compresses the entry into its own single-entry archive, so it can be copied into the new one
+fn compress_entry(
+    file: &mut zip::read::ZipFile<'_>,
+    options: FileOptions,
+) -> Result<Vec<u8>, Error> {
+    let options = options.last_modified_time(file.last_modified());
+    let mut zip_writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+
+    if file.is_dir() {
+        zip_writer
+            .add_directory(file.name(), options)
+            .map_err(|_| Error::WriteArchive)?;
+    } else {
+        zip_writer
+            .start_file(file.name(), options)
+            .map_err(|_| Error::WriteArchive)?;
+        std::io::copy(file, &mut zip_writer).map_err(|_| Error::ReadArchive)?;
+    }
+
+    Ok(zip_writer
+        .finish()
+        .map_err(|_| Error::WriteArchive)?
+        .into_inner())
+}
+
+/// This reads the decrypted archive from start to finish, and writes each of its entries into a new archive (prefixed with `new_hash`, or zeros if it isn't known yet)
+///
+/// Archives that were packed before they were prefixed with a hash are read too.
+fn recompress_archive(
+    mut reader: impl Read,
+    writer: impl Write,
+    options: FileOptions,
+    new_hash: Option<blake3::Hash>,
+) -> Result<Recompressed, Error> {
+    let mut prefix = Vec::with_capacity(ARCHIVE_HASH_PREFIX_LEN);
+    reader
+        .by_ref()
+        .take(ARCHIVE_HASH_PREFIX_LEN as u64)
+        .read_to_end(&mut prefix)
+        .map_err(|_| Error::ReadArchive)?;
+
+    // without a prefix, what was read is the start of the archive itself
+    let prefixed =
+        prefix.len() == ARCHIVE_HASH_PREFIX_LEN && prefix.starts_with(&ARCHIVE_HASH_MAGIC);
+    let (old_hash, start) = if prefixed {
+        (&prefix[ARCHIVE_HASH_MAGIC.len()..], &[][..])
+    } else {
+        (&[][..], &prefix[..])
+    };
+
+    let mut reader = HashingReader {
+        inner: start.chain(reader),
+        hasher: blake3::Hasher::new(),
+        len: 0,
+    };
+
+    let new_hash = new_hash.map_or([0u8; blake3::OUT_LEN], |hash| *hash.as_bytes());
+    let mut writer = writer;
+    writer
+        .write_all(&ARCHIVE_HASH_MAGIC)
+        .and_then(|()| writer.write_all(&new_hash))
+        .map_err(|_| Error::WriteArchive)?;
+
+    let mut zip_writer = zip::ZipWriter::new(ArchiveWriter {
+        inner: writer,
+        hasher: blake3::Hasher::new(),
+        position: ARCHIVE_HASH_PREFIX_LEN as u64,
+    });
+
+    let mut entries = 0;
+    while let Some(mut file) =
+        zip::read::read_zipfile_from_stream(&mut reader).map_err(|_| Error::ReadArchive)?
+    {
+        let compressed = compress_entry(&mut file, options)?;
+
+        let mut archive =
+            zip::ZipArchive::new(Cursor::new(compressed)).map_err(|_| Error::WriteArchive)?;
+        let file = archive.by_index_raw(0).map_err(|_| Error::WriteArchive)?;
+        zip_writer
+            .raw_copy_file(file)
+            .map_err(|_| Error::WriteArchive)?;
+        entries += 1;
+    }
+
+    // the rest is the old central directory, which is only read so that it's hashed
+    std::io::copy(&mut reader, &mut std::io::sink()).map_err(|_| Error::ReadArchive)?;
+
+    let writer = zip_writer.finish().map_err(|_| Error::WriteArchive)?;
+
+    if prefixed && reader.hasher.finalize().as_bytes()[..] != old_hash[..] {
+        return Err(Error::ArchiveHashMismatch);
+    }
+
+    Ok(Recompressed {
+        entries,
+        read_bytes: (prefix.len() - start.len()) as u64 + reader.len,
+        written_bytes: writer.position,
+        hash: writer.hasher.finalize(),
+    })
+}
+
+// decryption stops part of the way through if recompression fails, and the archive is truncated if decryption fails
+// so this reports whichever of them failed first
+fn first_error<T>(
+    decrypted: Result<(), Error>,
+    recompressed: Result<T, Error>,
+) -> Result<T, Error> {
+    match (decrypted, recompressed) {
+        (Err(e), Ok(_) | Err(Error::ReadArchive)) => Err(e),
+        (_, recompressed) => recompressed,
+    }
+}
+
+fn decrypt<R, W>(
+    reader: &RefCell<R>,
+    writer: &RefCell<W>,
+    master_key: Protected<[u8; MASTER_KEY_LEN]>,
+) -> Result<(), Error>
+where
+    R: Read + Seek,
+    W: Write,
+{
+    reader.borrow_mut().rewind().map_err(|_| Error::Seek)?;
+
+    crate::decrypt::execute(crate::decrypt::Request {
+        header_reader: None,
+        reader,
+        writer,
+        raw_key: Protected::new(Vec::new()),
+        master_key: Some(master_key),
+        identity: None,
+        on_decrypted_header: None,
+        on_file_info: None,
+    })
+    .map_err(Error::Decrypt)
+}
+
+pub fn execute<R, W>(req: Request<'_, R, W>) -> Result<Response, Error>
+where
+    R: Read + Seek + Send,
+    W: Write + Seek,
+{
+    req.reader.borrow_mut().rewind().map_err(|_| Error::Seek)?;
+    let (header, _) =
+        Header::deserialize(&mut *req.reader.borrow_mut()).map_err(|_| Error::HeaderDeserialize)?;
+
+    // packs are always encrypted in stream mode, and a convergent file's nonce can't be replaced
+    if header.header_type.mode == Mode::MemoryMode || header.convergent {
+        return Err(Error::Unsupported);
+    }
+
+    if header.digest.is_some() {
+        return Err(Error::Digest);
+    }
+
+    // a header that was dumped can't be recompressed on its own, as the archive that it belongs to is needed
+    let read = req
+        .reader
+        .borrow_mut()
+        .read(&mut [0u8; 1])
+        .map_err(|_| Error::Read)?;
+    if read == 0 {
+        return Err(Error::DetachedHeader);
+    }
+
+    let master_key =
+        core::key::decrypt_master_key(req.raw_key, &header).map_err(|_| Error::IncorrectKey)?;
+
+    let options = FileOptions::default()
+        .compression_method(req.compression_method)
+        .compression_level(req.compression_level)
+        .large_file(true)
+        .unix_permissions(0o755);
+
+    let mut input = req.reader.borrow_mut();
+    let input = &mut *input;
+
+    // 1. Hash the new archive, without keeping any of it.
+    let (decrypted, first) = std::thread::scope(|scope| {
+        let (pipe_writer, pipe_reader) = pipe(0);
+        let master_key = master_key.clone();
+        let input = &mut *input;
+        let decrypting = scope
+            .spawn(move || decrypt(&RefCell::new(input), &RefCell::new(pipe_writer), master_key));
+
+        let first = recompress_archive(pipe_reader, std::io::sink(), options, None);
+
+        let decrypted = decrypting
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+
+        (decrypted, first)
+    });
+
+    let first = first_error(decrypted, first)?;
+
+    // 2. Write the new archive again, prefixed with its hash, and encrypt it as it's written.
+    let (decrypted, second, encrypted) = std::thread::scope(|scope| {
+        let (plaintext_writer, plaintext_reader) = pipe(0);
+        let (archive_writer, mut archive_reader) = pipe(first.written_bytes);
+
+        let decrypt_key = master_key.clone();
+        let decrypting = scope.spawn(move || {
+            decrypt(
+                &RefCell::new(input),
+                &RefCell::new(plaintext_writer),
+                decrypt_key,
+            )
+        });
+        let recompressing = scope.spawn(move || {
+            recompress_archive(plaintext_reader, archive_writer, options, Some(first.hash))
+        });
+
+        let encrypted = crate::encrypt::execute_with_master_key(
+            header,
+            master_key,
+            &mut archive_reader,
+            req.writer,
+        );
+        // this stops the other threads, if encryption failed part of the way through
+        drop(archive_reader);
+
+        let second = recompressing
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+        let decrypted = decrypting
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+
+        (decrypted, second, encrypted)
+    });
+
+    // recompression stops part of the way through if encryption fails, so that's reported instead
+    let second = match (first_error(decrypted, second), encrypted) {
+        (Ok(_) | Err(Error::WriteArchive), Err(e)) => return Err(Error::Encrypt(e)),
+        (second, _) => second?,
+    };
+
+    if second.hash != first.hash {
+        return Err(Error::Nondeterministic);
+    }
+
+    Ok(Response {
+        entries: second.entries,
+        old_archive_bytes: second.read_bytes,
+        new_archive_bytes: second.written_bytes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZeroUsize;
+    use std::sync::Arc;
+
+    use core::header::{HashingAlgorithm, HeaderType, HeaderVersion};
+    use core::primitives::{Algorithm, StreamCounter};
+
+    use crate::encrypt::tests::PASSWORD;
+    use crate::storage::{InMemoryStorage, Storage};
+
+    // this packs the bar directory without compression, and with a manifest
+    fn pack_bar() -> Vec<u8> {
+        let stor = Arc::new(InMemoryStorage::default());
+        stor.add_bar_foo_folder();
+
+        let file = stor.read_file("bar/").unwrap();
+        let mut compress_files = stor.read_dir(&file).unwrap();
+        compress_files.sort_by(|a, b| a.path().cmp(b.path()));
+
+        let output_file = stor.create_file("bar.zip.enc").unwrap();
+        super::super::execute(
+            stor,
+            super::super::Request {
+                compress_files,
+                roots: Vec::new(),
+                compression_method: zip::CompressionMethod::Stored,
+                writer: output_file.try_writer().unwrap(),
+                header_writer: None,
+                raw_key: Protected::new(PASSWORD.to_vec()),
+                header_type: HeaderType {
+                    version: HeaderVersion::V6,
+                    algorithm: Algorithm::XChaCha20Poly1305,
+                    mode: Mode::StreamMode,
+                },
+                hashing_algorithm: HashingAlgorithm::Blake3Balloon(5),
+                jobs: NonZeroUsize::new(1).unwrap(),
+                on_stats: None,
+                on_progress: None,
+                metadata: None,
+                streams: crate::streams::Options::default(),
+                filters: Vec::new(),
+                keyfile_hash: false,
+                counter: StreamCounter::Le31,
+                two_factor: false,
+                manifest: true,
+                base: Vec::new(),
+            },
+        )
+        .unwrap();
+
+        let mut content = Vec::new();
+        let mut reader = output_file.try_writer().unwrap().borrow_mut();
+        reader.rewind().unwrap();
+        reader.read_to_end(&mut content).unwrap();
+        content
+    }
+
+    fn recompress(content: &[u8], password: &[u8]) -> Result<(Response, Vec<u8>), Error> {
+        let output = RefCell::new(Cursor::new(Vec::new()));
+        let response = execute(Request {
+            reader: &RefCell::new(Cursor::new(content.to_vec())),
+            writer: &output,
+            raw_key: Protected::new(password.to_vec()),
+            compression_method: zip::CompressionMethod::Zstd,
+            compression_level: Some(19),
+        })?;
+        Ok((response, output.into_inner().into_inner()))
+    }
+
+    fn decrypt_archive(content: &[u8]) -> Vec<u8> {
+        let output = RefCell::new(Vec::new());
+        crate::decrypt::execute(crate::decrypt::Request {
+            header_reader: None,
+            reader: &RefCell::new(Cursor::new(content.to_vec())),
+            writer: &output,
+            raw_key: Protected::new(PASSWORD.to_vec()),
+            master_key: None,
+            identity: None,
+            on_decrypted_header: None,
+            on_file_info: None,
+        })
+        .unwrap();
+        output.into_inner()
+    }
+
+    #[test]
+    fn should_recompress_a_pack() {
+        let packed = pack_bar();
+        let (response, recompressed) = recompress(&packed, PASSWORD).unwrap();
+
+        // the keyslot and manifest are carried over as they are
+        let (old_header, _) = Header::deserialize(&mut Cursor::new(&packed)).unwrap();
+        let (new_header, _) = Header::deserialize(&mut Cursor::new(&recompressed)).unwrap();
+        assert_eq!(
+            old_header.keyslots.unwrap()[0].encrypted_key,
+            new_header.keyslots.unwrap()[0].encrypted_key
+        );
+        assert_eq!(old_header.manifest, new_header.manifest);
+
+        let archive = decrypt_archive(&recompressed);
+        assert_eq!(archive.len() as u64, response.new_archive_bytes);
+        assert_eq!(&archive[..ARCHIVE_HASH_MAGIC.len()], ARCHIVE_HASH_MAGIC);
+        assert_eq!(
+            &archive[ARCHIVE_HASH_MAGIC.len()..ARCHIVE_HASH_PREFIX_LEN],
+            blake3::hash(&archive[ARCHIVE_HASH_PREFIX_LEN..]).as_bytes()
+        );
+
+        let mut zip = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+        assert_eq!(zip.len(), response.entries);
+        for i in 0..zip.len() {
+            let mut file = zip.by_index(i).unwrap();
+            if !file.is_dir() {
+                assert_eq!(file.compression(), zip::CompressionMethod::Zstd);
+
+                let mut text = String::new();
+                file.read_to_string(&mut text).unwrap();
+                assert!(text == "hello" || text == "world");
+            }
+        }
+    }
+
+    #[test]
+    fn should_not_recompress_a_pack_with_a_digest() {
+        let archive = decrypt_archive(&pack_bar());
+
+        let output = RefCell::new(Cursor::new(Vec::new()));
+        crate::encrypt::execute(crate::encrypt::Request {
+            digest: true,
+            ..crate::encrypt::tests::request(&RefCell::new(Cursor::new(archive)), &output)
+        })
+        .unwrap();
+
+        assert!(matches!(
+            recompress(&output.into_inner().into_inner(), PASSWORD),
+            Err(Error::Digest)
+        ));
+    }
+
+    #[test]
+    fn should_not_recompress_with_the_wrong_key() {
+        assert!(matches!(
+            recompress(&pack_bar(), b"wrong password"),
+            Err(Error::IncorrectKey)
+        ));
+    }
+}
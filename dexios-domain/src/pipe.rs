@@ -0,0 +1,103 @@
+//! This hands data from one thread to another in memory, for pipelines that decrypt on one thread and encrypt on another (e.g. `key::rotate`).
+//!
+//! The channel is bounded, so the writer waits once `PIPE_BLOCKS` blocks are waiting to be read.
+
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+
+use core::protected::Protected;
+
+/// This is the number of written blocks that may be waiting to be read
+const PIPE_BLOCKS: usize = 4;
+
+/// This creates both ends of a pipe, and `len` is what the reader reports as its length
+pub(crate) fn pipe(len: u64) -> (PipeWriter, PipeReader) {
+    let (sender, receiver) = sync_channel(PIPE_BLOCKS);
+
+    (
+        PipeWriter(sender),
+        PipeReader {
+            receiver,
+            block: Protected::new(Vec::new()),
+            offset: 0,
+            started: false,
+            len,
+        },
+    )
+}
+
+// this counts the plaintext, without keeping any of it (e.g. to find the length for padding before the real pass)
+pub(crate) struct Counter(pub(crate) u64);
+
+impl Write for Counter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// this hands each block that's written to the reader on the other end
+pub(crate) struct PipeWriter(SyncSender<Protected<Vec<u8>>>);
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .send(Protected::new(buf.to_vec()))
+            .map_err(|_| std::io::Error::new(ErrorKind::BrokenPipe, "The reader has stopped"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// encryption only seeks to find the length (for padding) and to rewind, before anything has been read
+// so this supports exactly that, and the length is provided up front
+pub(crate) struct PipeReader {
+    receiver: Receiver<Protected<Vec<u8>>>,
+    block: Protected<Vec<u8>>,
+    offset: usize,
+    started: bool,
+    len: u64,
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.offset == self.block.expose().len() {
+            match self.receiver.recv() {
+                Ok(block) => {
+                    self.block = block;
+                    self.offset = 0;
+                }
+                // the writer has finished
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let block = &self.block.expose()[self.offset..];
+        let read = block.len().min(buf.len());
+        buf[..read].copy_from_slice(&block[..read]);
+        self.offset += read;
+        self.started = true;
+
+        Ok(read)
+    }
+}
+
+impl Seek for PipeReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match pos {
+            SeekFrom::Start(0) | SeekFrom::Current(0) if !self.started => Ok(0),
+            SeekFrom::End(0) if !self.started => Ok(self.len),
+            _ => Err(std::io::Error::new(
+                ErrorKind::Unsupported,
+                "The plaintext can only be read once",
+            )),
+        }
+    }
+}
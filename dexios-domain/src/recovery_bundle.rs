@@ -0,0 +1,166 @@
+//! This contains the logic for exporting and importing recovery bundles.
+//!
+//! A recovery bundle holds a copy of the header of every Dexios file within a directory, along with the length and a BLAKE3 checksum of the data that follows each header. It's encrypted in the same way as any other file.
+//!
+//! If a file's header is later damaged or stripped, the checksum is used to confirm that the data still belongs to that header before it's written back.
+
+use std::io::{Cursor, Read, Write};
+
+use core::header::Header;
+
+pub mod export;
+pub mod import;
+
+/// The magic bytes found at the very start of every (decrypted) recovery bundle
+pub const BUNDLE_MAGIC: [u8; 5] = *b"DXREC";
+pub const BUNDLE_VERSION: u8 = 1;
+
+#[derive(Debug)]
+pub enum Error {
+    ReadData,
+    WriteData,
+    InvalidPath,
+    InvalidBundle,
+    UnsupportedVersion,
+    DataMismatch,
+    Encrypt(crate::encrypt::Error),
+    Decrypt(crate::decrypt::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::ReadData => f.write_str("Unable to read data"),
+            Error::WriteData => f.write_str("Unable to write data"),
+            Error::InvalidPath => f.write_str("Unable to store a path within the bundle"),
+            Error::InvalidBundle => {
+                f.write_str("This does not seem like a valid Dexios recovery bundle")
+            }
+            Error::UnsupportedVersion => f.write_str("This recovery bundle version is not supported"),
+            Error::DataMismatch => f.write_str(
+                "The file's data doesn't match the bundle's checksum, so its header can't be restored",
+            ),
+            Error::Encrypt(inner) => write!(f, "Unable to encrypt recovery bundle: {inner}"),
+            Error::Decrypt(inner) => write!(f, "Unable to decrypt recovery bundle: {inner}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A single file's record within a recovery bundle
+///
+/// `path` is relative to the directory that the bundle was exported from
+pub struct BundleEntry {
+    pub path: String,
+    /// The header exactly as it was found at the start of the file
+    pub header: Vec<u8>,
+    /// The length of everything that follows the header
+    pub data_len: u64,
+    /// A BLAKE3 checksum of everything that follows the header
+    pub data_hash: [u8; 32],
+}
+
+impl BundleEntry {
+    /// This parses the stored header, which contains the format version and keyslots
+    pub fn parse_header(&self) -> Result<Header, Error> {
+        Header::deserialize(&mut Cursor::new(&self.header))
+            .map(|(header, _)| header)
+            .map_err(|_| Error::InvalidBundle)
+    }
+
+    fn write(&self, writer: &mut impl Write) -> Result<(), Error> {
+        let path_len = u16::try_from(self.path.len()).map_err(|_| Error::InvalidPath)?;
+        let header_len = u32::try_from(self.header.len()).map_err(|_| Error::WriteData)?;
+
+        writer
+            .write_all(&path_len.to_le_bytes())
+            .map_err(|_| Error::WriteData)?;
+        writer
+            .write_all(self.path.as_bytes())
+            .map_err(|_| Error::WriteData)?;
+        writer
+            .write_all(&header_len.to_le_bytes())
+            .map_err(|_| Error::WriteData)?;
+        writer
+            .write_all(&self.header)
+            .map_err(|_| Error::WriteData)?;
+        writer
+            .write_all(&self.data_len.to_le_bytes())
+            .map_err(|_| Error::WriteData)?;
+        writer
+            .write_all(&self.data_hash)
+            .map_err(|_| Error::WriteData)
+    }
+
+    fn read(reader: &mut impl Read) -> Result<Self, Error> {
+        let path_len = u16::from_le_bytes(read_array(reader)?);
+        let mut path = vec![0u8; path_len.into()];
+        reader.read_exact(&mut path).map_err(|_| Error::ReadData)?;
+        let path = String::from_utf8(path).map_err(|_| Error::InvalidPath)?;
+
+        let header_len = u32::from_le_bytes(read_array(reader)?);
+        let mut header = vec![0u8; header_len.try_into().map_err(|_| Error::InvalidBundle)?];
+        reader
+            .read_exact(&mut header)
+            .map_err(|_| Error::ReadData)?;
+
+        let data_len = u64::from_le_bytes(read_array(reader)?);
+        let data_hash = read_array(reader)?;
+
+        let entry = BundleEntry {
+            path,
+            header,
+            data_len,
+            data_hash,
+        };
+
+        // a bundle is useless if the headers within it can't be parsed
+        entry.parse_header()?;
+
+        Ok(entry)
+    }
+}
+
+/// This is the entire contents of a decrypted recovery bundle
+#[derive(Default)]
+pub struct Bundle {
+    pub entries: Vec<BundleEntry>,
+}
+
+impl Bundle {
+    pub fn write(&self, writer: &mut impl Write) -> Result<(), Error> {
+        writer
+            .write_all(&BUNDLE_MAGIC)
+            .map_err(|_| Error::WriteData)?;
+        writer
+            .write_all(&[BUNDLE_VERSION])
+            .map_err(|_| Error::WriteData)?;
+        writer
+            .write_all(&(self.entries.len() as u64).to_le_bytes())
+            .map_err(|_| Error::WriteData)?;
+        self.entries.iter().try_for_each(|e| e.write(writer))
+    }
+
+    pub fn read(reader: &mut impl Read) -> Result<Self, Error> {
+        if read_array::<5>(reader)? != BUNDLE_MAGIC {
+            return Err(Error::InvalidBundle);
+        }
+        if read_array::<1>(reader)? != [BUNDLE_VERSION] {
+            return Err(Error::UnsupportedVersion);
+        }
+
+        let entry_count = u64::from_le_bytes(read_array(reader)?);
+        let entries = (0..entry_count)
+            .map(|_| BundleEntry::read(reader))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Bundle { entries })
+    }
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> Result<[u8; N], Error> {
+    let mut buf = [0u8; N];
+    reader.read_exact(&mut buf).map_err(|_| Error::ReadData)?;
+    Ok(buf)
+}
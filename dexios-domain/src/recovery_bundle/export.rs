@@ -0,0 +1,120 @@
+//! This provides functionality for exporting a recovery bundle from a list of entries.
+
+use std::cell::RefCell;
+use std::io::{Cursor, Read, Seek, Write};
+use std::path::Path;
+
+use core::header::{HashingAlgorithm, Header, HeaderType};
+use core::primitives::{Compression, Padding, StreamCounter, BLOCK_SIZE};
+use core::protected::Protected;
+
+use super::{Bundle, BundleEntry, Error};
+
+pub struct Request<'a, RW>
+where
+    RW: Read + Write + Seek,
+{
+    pub writer: &'a RefCell<RW>,
+    /// Paths within the bundle are stored relative to this
+    pub root: &'a Path,
+    pub entries: Vec<crate::storage::Entry<RW>>,
+    pub raw_key: Protected<Vec<u8>>,
+    // TODO: don't use external types in logic
+    pub header_type: HeaderType,
+    pub hashing_algorithm: HashingAlgorithm,
+    /// This must be set if the raw key was derived from a keyfile with `core::key::hash_keyfile()`
+    pub keyfile_hash: bool,
+}
+
+// entries that don't start with a valid header are skipped, as there's nothing to recover
+// this includes files encrypted in detached mode, and files that already had their header stripped
+fn bundle_entry<RW>(
+    root: &Path,
+    entry: &crate::storage::Entry<RW>,
+) -> Result<Option<BundleEntry>, Error>
+where
+    RW: Read + Write + Seek,
+{
+    let mut reader = entry
+        .try_reader()
+        .map_err(|_| Error::ReadData)?
+        .borrow_mut();
+    reader.rewind().map_err(|_| Error::ReadData)?;
+
+    let Ok((header, _)) = Header::deserialize(&mut *reader) else {
+        return Ok(None);
+    };
+
+    let mut header_bytes = vec![0u8; header.get_size().try_into().map_err(|_| Error::ReadData)?];
+    reader.rewind().map_err(|_| Error::ReadData)?;
+    reader
+        .read_exact(&mut header_bytes)
+        .map_err(|_| Error::ReadData)?;
+
+    let mut hasher = blake3::Hasher::new();
+    let data_len = std::io::copy(&mut *reader, &mut hasher).map_err(|_| Error::ReadData)?;
+
+    let path = entry
+        .path()
+        .strip_prefix(root)
+        .unwrap_or_else(|_| entry.path())
+        .to_str()
+        .ok_or(Error::InvalidPath)?
+        .to_string();
+
+    Ok(Some(BundleEntry {
+        path,
+        header: header_bytes,
+        data_len,
+        data_hash: *hasher.finalize().as_bytes(),
+    }))
+}
+
+/// This returns the bundle that was encrypted, so that its contents can be reported
+pub fn execute<RW>(req: Request<'_, RW>) -> Result<Bundle, Error>
+where
+    RW: Read + Write + Seek,
+{
+    // 1. Collect the header and checksum of every Dexios file.
+    let entries = req
+        .entries
+        .iter()
+        .filter(|e| !e.is_dir())
+        .filter_map(|e| bundle_entry(req.root, e).transpose())
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let bundle = Bundle { entries };
+
+    // 2. Encrypt the bundle. It only contains headers, so it's kept in memory.
+    let mut plaintext = Vec::new();
+    bundle.write(&mut plaintext)?;
+
+    crate::encrypt::execute(crate::encrypt::Request {
+        reader: &RefCell::new(Cursor::new(plaintext)),
+        writer: req.writer,
+        header_writer: None,
+        raw_key: req.raw_key,
+        header_type: req.header_type,
+        hashing_algorithm: req.hashing_algorithm,
+        compression: Compression::None,
+        block_size: BLOCK_SIZE,
+        padding: Padding::None,
+        convergent: false,
+        recipients: Vec::new(),
+        tokens: Vec::new(),
+        extra_keys: Vec::new(),
+        metadata: None,
+        mac: false,
+        digest: false,
+        seekable: false,
+        keyfile_hash: req.keyfile_hash,
+        counter: StreamCounter::Le31,
+        two_factor: false,
+        manifest: None,
+        file_info: None,
+        fields: Vec::new(),
+    })
+    .map_err(Error::Encrypt)?;
+
+    Ok(bundle)
+}
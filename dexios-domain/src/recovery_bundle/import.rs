@@ -0,0 +1,172 @@
+//! This provides functionality for decrypting a recovery bundle, and restoring the headers within it.
+
+use std::cell::RefCell;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+
+use core::header::Header;
+use core::protected::Protected;
+
+use super::{Bundle, BundleEntry, Error};
+
+/// This decrypts and parses a recovery bundle
+pub fn read_bundle<R>(reader: &RefCell<R>, raw_key: Protected<Vec<u8>>) -> Result<Bundle, Error>
+where
+    R: Read + Seek,
+{
+    let mut plaintext = Vec::new();
+    crate::decrypt::execute(crate::decrypt::Request {
+        header_reader: None,
+        reader,
+        writer: &RefCell::new(&mut plaintext),
+        raw_key,
+        master_key: None,
+        identity: None,
+        on_decrypted_header: None,
+        on_file_info: None,
+    })
+    .map_err(Error::Decrypt)?;
+
+    Bundle::read(&mut Cursor::new(plaintext))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The file's header already matches the bundle
+    Intact,
+    /// The file's header was missing or damaged, and it has been restored from the bundle
+    Restored,
+    /// The file's header is valid, but it differs from the bundle (e.g. a key was changed after the bundle was exported), so it was left as-is
+    Diverged,
+}
+
+pub struct Request<'a, RW>
+where
+    RW: Read + Write + Seek,
+{
+    pub handle: &'a RefCell<RW>,
+    pub entry: &'a BundleEntry,
+    /// If this is set, headers that are valid but differ from the bundle are replaced too
+    pub replace_valid: bool,
+}
+
+pub fn execute<RW>(req: Request<'_, RW>) -> Result<Outcome, Error>
+where
+    RW: Read + Write + Seek,
+{
+    let mut handle = req.handle.borrow_mut();
+    let header_len = req.entry.header.len() as u64;
+
+    // 1. Ensure the data that follows the header is the same as when the bundle was exported.
+    let file_len = handle.seek(SeekFrom::End(0)).map_err(|_| Error::ReadData)?;
+    if file_len != header_len + req.entry.data_len {
+        return Err(Error::DataMismatch);
+    }
+
+    handle
+        .seek(SeekFrom::Start(header_len))
+        .map_err(|_| Error::ReadData)?;
+    let mut hasher = blake3::Hasher::new();
+    std::io::copy(&mut *handle, &mut hasher).map_err(|_| Error::ReadData)?;
+    if *hasher.finalize().as_bytes() != req.entry.data_hash {
+        return Err(Error::DataMismatch);
+    }
+
+    // 2. Compare the current header with the bundle's copy.
+    let mut current = vec![0u8; req.entry.header.len()];
+    handle.rewind().map_err(|_| Error::ReadData)?;
+    handle
+        .read_exact(&mut current)
+        .map_err(|_| Error::ReadData)?;
+
+    if current == req.entry.header {
+        return Ok(Outcome::Intact);
+    }
+
+    if !req.replace_valid && Header::deserialize(&mut Cursor::new(&current)).is_ok() {
+        return Ok(Outcome::Diverged);
+    }
+
+    // 3. Restore the header.
+    handle.rewind().map_err(|_| Error::WriteData)?;
+    handle
+        .write_all(&req.entry.header)
+        .map_err(|_| Error::WriteData)?;
+    handle.flush().map_err(|_| Error::WriteData)?;
+
+    Ok(Outcome::Restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    use core::header::{HashingAlgorithm, HeaderType, HeaderVersion};
+    use core::primitives::{Algorithm, Mode};
+
+    use crate::encrypt::tests::{PASSWORD, V5_ENCRYPTED_CONTENT};
+    use crate::storage::{IMFile, InMemoryFile, InMemoryStorage, Storage};
+
+    #[test]
+    fn should_restore_stripped_header_from_bundle() {
+        let stor = InMemoryStorage::default();
+        stor.add_bar_foo_folder();
+        stor.mut_files().insert(
+            "bar/foo/hello.enc".into(),
+            IMFile::File(InMemoryFile {
+                buf: V5_ENCRYPTED_CONTENT.to_vec(),
+                len: V5_ENCRYPTED_CONTENT.len(),
+            }),
+        );
+
+        let dir = stor.read_file("bar/").unwrap();
+        let entries = stor.read_dir(&dir).unwrap();
+        let bundle_file = RefCell::new(Cursor::new(Vec::new()));
+
+        let exported =
+            crate::recovery_bundle::export::execute(crate::recovery_bundle::export::Request {
+                writer: &bundle_file,
+                root: Path::new("bar/"),
+                entries,
+                raw_key: Protected::new(PASSWORD.to_vec()),
+                header_type: HeaderType {
+                    version: HeaderVersion::V5,
+                    algorithm: Algorithm::XChaCha20Poly1305,
+                    mode: Mode::StreamMode,
+                },
+                hashing_algorithm: HashingAlgorithm::Argon2id(1),
+                keyfile_hash: false,
+            })
+            .unwrap();
+        assert_eq!(exported.entries.len(), 1);
+
+        bundle_file.borrow_mut().rewind().unwrap();
+        let bundle = read_bundle(&bundle_file, Protected::new(PASSWORD.to_vec())).unwrap();
+        assert_eq!(bundle.entries.len(), 1);
+
+        let entry = &bundle.entries[0];
+        assert_eq!(entry.path, "foo/hello.enc");
+        assert!(entry.parse_header().unwrap().header_type.version == HeaderVersion::V5);
+
+        let mut stripped = V5_ENCRYPTED_CONTENT.to_vec();
+        stripped[..entry.header.len()].fill(0);
+        let handle = RefCell::new(Cursor::new(stripped));
+
+        let outcome = execute(Request {
+            handle: &handle,
+            entry,
+            replace_valid: false,
+        })
+        .unwrap();
+        assert_eq!(outcome, Outcome::Restored);
+        assert_eq!(handle.borrow().get_ref(), &V5_ENCRYPTED_CONTENT.to_vec());
+
+        let outcome = execute(Request {
+            handle: &handle,
+            entry,
+            replace_valid: false,
+        })
+        .unwrap();
+        assert_eq!(outcome, Outcome::Intact);
+    }
+}
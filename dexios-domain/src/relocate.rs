@@ -0,0 +1,172 @@
+//! This provides functionality for moving a file to its final destination, which may be on another (slower) device.
+//!
+//! The file is copied beside the destination, and the copy is read back and compared with the source's `BLAKE3` hash. The destination is only replaced once the copy has been verified, and the source is only removed after that.
+
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use core::primitives::BLOCK_SIZE;
+
+use crate::hasher::{Blake3Hasher, Hasher};
+use crate::storage::Storage;
+
+#[derive(Debug)]
+pub enum Error {
+    OpenSource,
+    CreateDestination,
+    CopyData,
+    VerifyCopy,
+    PersistDestination,
+    RemoveSource,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::OpenSource => f.write_str("Unable to open the source file"),
+            Error::CreateDestination => f.write_str("Unable to create the destination file"),
+            Error::CopyData => f.write_str("Unable to copy the file to its destination"),
+            Error::VerifyCopy => f.write_str(
+                "The copy doesn't match the source - the source has been kept, and the destination is unchanged",
+            ),
+            Error::PersistDestination => {
+                f.write_str("Unable to move the copy into place at the destination")
+            }
+            Error::RemoveSource => {
+                f.write_str("The file was copied and verified, but the source couldn't be removed")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub struct Request<S: AsRef<Path>, D: AsRef<Path>> {
+    pub source: S,
+    pub destination: D,
+}
+
+// this hashes everything that's left within the reader, and writes it to `writer` (if there is one)
+fn copy_and_hash<R, W>(reader: &mut R, mut writer: Option<&mut W>) -> std::io::Result<String>
+where
+    R: Read,
+    W: Write,
+{
+    let mut hasher = Blake3Hasher::default();
+    let mut buffer = vec![0u8; BLOCK_SIZE].into_boxed_slice();
+
+    loop {
+        let read_count = reader.read(&mut buffer)?;
+        if read_count == 0 {
+            break;
+        }
+        hasher.write(&buffer[..read_count]);
+        if let Some(writer) = writer.as_mut() {
+            writer.write_all(&buffer[..read_count])?;
+        }
+    }
+
+    Ok(hasher.finish())
+}
+
+/// This returns the `BLAKE3` hash of the file that was moved
+pub fn execute<RW, S, D>(
+    stor: Arc<impl Storage<RW> + 'static>,
+    req: Request<S, D>,
+) -> Result<String, Error>
+where
+    RW: Read + Write + Seek,
+    S: AsRef<Path>,
+    D: AsRef<Path>,
+{
+    let source = stor.read_file(&req.source).map_err(|_| Error::OpenSource)?;
+    let copy = stor
+        .create_temp_file_beside(&req.destination)
+        .map_err(|_| Error::CreateDestination)?;
+
+    // 1. copy the source beside the destination, hashing it along the way
+    let copied = (|| {
+        let mut reader = source.try_reader().ok()?.borrow_mut();
+        reader.rewind().ok()?;
+        let mut writer = copy.try_writer().ok()?.borrow_mut();
+        let hash = copy_and_hash(&mut *reader, Some(&mut *writer)).ok()?;
+        drop(writer);
+        stor.flush_file(&copy).ok()?;
+        Some(hash)
+    })();
+    let Some(hash) = copied else {
+        stor.remove_file(copy).ok();
+        return Err(Error::CopyData);
+    };
+
+    // 2. read the copy back (with a new handle), and make sure that it matches
+    let copy_hash = stor.read_file(copy.path()).ok().and_then(|entry| {
+        let mut reader = entry.try_reader().ok()?.borrow_mut();
+        copy_and_hash::<_, std::io::Sink>(&mut *reader, None).ok()
+    });
+    if copy_hash.as_ref() != Some(&hash) {
+        stor.remove_file(copy).ok();
+        return Err(Error::VerifyCopy);
+    }
+
+    // 3. move the copy into place, and remove the source
+    stor.persist_file(copy, &req.destination)
+        .map_err(|_| Error::PersistDestination)?;
+    // the source was only opened for reading, so it's reopened to be removed
+    drop(source);
+    stor.write_file(&req.source)
+        .and_then(|source| stor.remove_file(source))
+        .map_err(|_| Error::RemoveSource)?;
+
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::storage::{IMFile, InMemoryFile, InMemoryStorage};
+
+    use super::*;
+
+    #[test]
+    fn should_move_file() {
+        let stor = Arc::new(InMemoryStorage::default());
+        stor.add_hello_txt();
+        stor.add_bar_foo_folder();
+
+        let hash = execute(
+            stor.clone(),
+            Request {
+                source: "hello.txt",
+                destination: "bar/foo/hello.txt",
+            },
+        )
+        .unwrap();
+
+        assert_eq!(hash, blake3::hash(b"hello world").to_hex().to_string());
+        assert_eq!(stor.files().get(&PathBuf::from("hello.txt")), None);
+        assert_eq!(
+            stor.files().get(&PathBuf::from("bar/foo/hello.txt")),
+            Some(&IMFile::File(InMemoryFile {
+                buf: b"hello world".to_vec(),
+                len: 11,
+            }))
+        );
+    }
+
+    #[test]
+    fn should_not_open_source() {
+        let stor = Arc::new(InMemoryStorage::default());
+
+        let req = Request {
+            source: "hello.txt",
+            destination: "world.txt",
+        };
+        match execute(stor, req) {
+            Err(Error::OpenSource) => {}
+            _ => unreachable!(),
+        }
+    }
+}
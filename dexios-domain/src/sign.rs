@@ -0,0 +1,98 @@
+//! This provides functionality for signing an encrypted file with an Ed25519 key.
+//!
+//! The signature is detached, so the encrypted file is left untouched and can still be decrypted by anyone with the key.
+
+use core::primitives::BLOCK_SIZE;
+use core::signature::{signed_digest, Signature, SigningSecretKey};
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{Read, Seek};
+
+#[derive(Debug)]
+pub enum Error {
+    ResetCursorPosition,
+    ReadData,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ResetCursorPosition => f.write_str("Unable to reset cursor position"),
+            Error::ReadData => f.write_str("Unable to read data"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub struct Request<'a, R>
+where
+    R: Read + Seek,
+{
+    pub reader: &'a RefCell<R>,
+    pub header_reader: Option<&'a RefCell<R>>,
+    pub signing_key: &'a SigningSecretKey,
+}
+
+/// This hashes the detached header (if there is one), followed by the encrypted file
+pub(crate) fn digest<R>(
+    reader: &RefCell<R>,
+    header_reader: Option<&RefCell<R>>,
+) -> Result<blake3::Hash, Error>
+where
+    R: Read + Seek,
+{
+    let mut hasher = signed_digest();
+    let mut buffer = vec![0u8; BLOCK_SIZE].into_boxed_slice();
+
+    for reader in header_reader.into_iter().chain(Some(reader)) {
+        let mut reader = reader.borrow_mut();
+        reader.rewind().map_err(|_| Error::ResetCursorPosition)?;
+
+        loop {
+            let read_count = reader.read(&mut buffer).map_err(|_| Error::ReadData)?;
+            hasher.update(&buffer[..read_count]);
+            if read_count == 0 {
+                break;
+            }
+        }
+
+        reader.rewind().map_err(|_| Error::ResetCursorPosition)?;
+    }
+
+    Ok(hasher.finalize())
+}
+
+pub fn execute<R>(req: Request<'_, R>) -> Result<Signature, Error>
+where
+    R: Read + Seek,
+{
+    let digest = digest(req.reader, req.header_reader)?;
+    Ok(req.signing_key.sign(&digest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn should_sign_content() {
+        let signing_key = SigningSecretKey::generate();
+        let reader = RefCell::new(Cursor::new(b"encrypted content".to_vec()));
+
+        let req = Request {
+            reader: &reader,
+            header_reader: None,
+            signing_key: &signing_key,
+        };
+
+        match execute(req) {
+            Ok(signature) => {
+                let digest = digest(&reader, None).unwrap();
+                assert!(signing_key.public_key().verify(&digest, &signature).is_ok());
+            }
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//! This provides functionality for splitting a file into numbered parts (`<file>.001`, `<file>.002`, ...), so that it fits within a file size limit (e.g. of a cloud drive, or a FAT32 disk).
+//!
+//! The parts are consecutive chunks of the file, so they may be joined with `cat file.001 file.002 > file` (or `copy /b` on Windows). The first part starts with the header, so it can be inspected without joining them.
+//!
+//! The parts after the first may instead be named with keyed hashes (see `names_key`), so that they can't be linked to the file (or to each other) without a key that unlocks it.
+
+use std::io::{Read, Seek, Write};
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use core::primitives::{BLOCK_SIZE, MASTER_KEY_LEN};
+use core::protected::Protected;
+
+use crate::storage::Storage;
+
+#[derive(Debug)]
+pub enum Error {
+    OpenSource,
+    CreatePart(PathBuf),
+    CopyData,
+    RemoveSource,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::OpenSource => f.write_str("Unable to open the file that's being split"),
+            Error::CreatePart(path) => {
+                write!(
+                    f,
+                    "Unable to create {} (it may already exist)",
+                    path.display()
+                )
+            }
+            Error::CopyData => f.write_str("Unable to copy the file into its parts"),
+            Error::RemoveSource => {
+                f.write_str("The file was split, but the original couldn't be removed")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub struct Request<P: AsRef<Path>> {
+    pub path: P,
+    pub part_size: NonZeroU64,
+    /// If this is set, every part after the first is named with a keyed hash (see `obfuscated_part_path`)
+    pub names_key: Option<Protected<[u8; 32]>>,
+}
+
+/// This derives the key that obfuscated part names are hashed with from the file's master key
+///
+/// Nothing else needs to be stored, as anyone that's able to decrypt the file can find its parts again.
+#[must_use]
+pub fn names_key(master_key: &Protected<[u8; MASTER_KEY_LEN]>) -> Protected<[u8; 32]> {
+    Protected::new(blake3::derive_key(
+        "dexios 2026-10-17 split part names",
+        master_key.expose(),
+    ))
+}
+
+/// This returns the path of a part, which are numbered from 1
+#[must_use]
+pub fn part_path(path: &Path, number: usize) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(format!(".{number:03}"));
+    PathBuf::from(part)
+}
+
+/// This returns the path of a part that's named with a keyed hash, which are numbered from 1
+///
+/// The first part keeps its usual name, as it contains the header (and so is how the file is found). The others are named with 32 hex characters, in the same directory.
+#[must_use]
+pub fn obfuscated_part_path(
+    path: &Path,
+    number: usize,
+    names_key: &Protected<[u8; 32]>,
+) -> PathBuf {
+    if number == 1 {
+        return part_path(path, number);
+    }
+
+    let name = part_path(path, number)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let hash = blake3::keyed_hash(names_key.expose(), name.as_bytes());
+    path.with_file_name(crate::utils::hex_encode(&hash.as_bytes()[..16]))
+}
+
+/// This returns the paths of every part of a file that was split, in order, by checking which of them exist
+///
+/// If `names_key` is set, the parts after the first are expected to be named with keyed hashes.
+#[must_use]
+pub fn find_parts(path: &Path, names_key: Option<&Protected<[u8; 32]>>) -> Vec<PathBuf> {
+    (1..usize::MAX)
+        .map(|number| match names_key {
+            Some(names_key) => obfuscated_part_path(path, number, names_key),
+            None => part_path(path, number),
+        })
+        .take_while(|part| part.is_file())
+        .collect()
+}
+
+/// This returns the path that a part was split from, along with its number, if `path` is named like a part (e.g. `file.dx.002`)
+#[must_use]
+pub fn parse_part_path(path: &Path) -> Option<(PathBuf, usize)> {
+    let name = path.file_name()?.to_str()?;
+    let (base, number) = name.rsplit_once('.')?;
+    if base.is_empty() || number.len() < 3 || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let number = number.parse().ok().filter(|number| *number > 0)?;
+    Some((path.with_file_name(base), number))
+}
+
+// this copies up to `limit` bytes, and returns the number that were copied
+fn copy_part<R, W>(reader: &mut R, writer: &mut W, limit: u64) -> std::io::Result<u64>
+where
+    R: Read,
+    W: Write,
+{
+    let mut buffer = vec![0u8; BLOCK_SIZE].into_boxed_slice();
+    let mut copied = 0;
+
+    while copied < limit {
+        let want = usize::try_from(limit - copied).map_or(BLOCK_SIZE, |left| left.min(BLOCK_SIZE));
+        let read_count = reader.read(&mut buffer[..want])?;
+        if read_count == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..read_count])?;
+        copied += read_count as u64;
+    }
+
+    Ok(copied)
+}
+
+/// This returns the paths of the parts, in order
+///
+/// A file that already fits within a single part isn't split, and its own path is returned. Otherwise, the original is removed once every part has been written.
+pub fn execute<RW, P>(
+    stor: Arc<impl Storage<RW> + 'static>,
+    req: Request<P>,
+) -> Result<Vec<PathBuf>, Error>
+where
+    RW: Read + Write + Seek,
+    P: AsRef<Path>,
+{
+    let path = req.path.as_ref();
+    let source = stor.read_file(path).map_err(|_| Error::OpenSource)?;
+    let len = stor.file_len(&source).map_err(|_| Error::OpenSource)? as u64;
+
+    if len <= req.part_size.get() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut parts = Vec::new();
+    let result = (|| {
+        let mut reader = source
+            .try_reader()
+            .map_err(|_| Error::OpenSource)?
+            .borrow_mut();
+        reader.rewind().map_err(|_| Error::OpenSource)?;
+
+        let mut remaining = len;
+        while remaining > 0 {
+            let part_path = match &req.names_key {
+                Some(names_key) => obfuscated_part_path(path, parts.len() + 1, names_key),
+                None => part_path(path, parts.len() + 1),
+            };
+            let part = stor
+                .create_file(&part_path)
+                .map_err(|_| Error::CreatePart(part_path.clone()))?;
+            parts.push(part_path);
+
+            let mut writer = part.try_writer().map_err(|_| Error::CopyData)?.borrow_mut();
+            let copied = copy_part(&mut *reader, &mut *writer, req.part_size.get())
+                .map_err(|_| Error::CopyData)?;
+            drop(writer);
+            stor.flush_file(&part).map_err(|_| Error::CopyData)?;
+
+            if copied == 0 {
+                return Err(Error::CopyData);
+            }
+            remaining = remaining.saturating_sub(copied);
+        }
+
+        Ok(())
+    })();
+
+    // incomplete parts are removed, so they can't be mistaken for a whole set
+    if let Err(e) = result {
+        for part in &parts {
+            if let Ok(part) = stor.write_file(part) {
+                stor.remove_file(part).ok();
+            }
+        }
+        return Err(e);
+    }
+
+    // the source was only opened for reading, so it's reopened to be removed
+    drop(source);
+    stor.write_file(path)
+        .and_then(|source| stor.remove_file(source))
+        .map_err(|_| Error::RemoveSource)?;
+
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::{IMFile, InMemoryFile, InMemoryStorage};
+
+    use super::*;
+
+    #[test]
+    fn should_split_file() {
+        let stor = Arc::new(InMemoryStorage::default());
+        stor.add_hello_txt();
+
+        let parts = execute(
+            stor.clone(),
+            Request {
+                path: "hello.txt",
+                part_size: NonZeroU64::new(4).unwrap(),
+                names_key: None,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            parts,
+            vec![
+                PathBuf::from("hello.txt.001"),
+                PathBuf::from("hello.txt.002"),
+                PathBuf::from("hello.txt.003"),
+            ]
+        );
+        assert_eq!(stor.files().get(&PathBuf::from("hello.txt")), None);
+
+        let joined: Vec<u8> = parts
+            .iter()
+            .flat_map(|part| match stor.files().get(part) {
+                Some(IMFile::File(InMemoryFile { buf, .. })) => buf.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(joined, b"hello world");
+    }
+
+    #[test]
+    fn should_split_file_with_obfuscated_names() {
+        let stor = Arc::new(InMemoryStorage::default());
+        stor.add_hello_txt();
+        let names_key = names_key(&Protected::new([7u8; MASTER_KEY_LEN]));
+
+        let parts = execute(
+            stor.clone(),
+            Request {
+                path: "hello.txt",
+                part_size: NonZeroU64::new(4).unwrap(),
+                names_key: Some(names_key.clone()),
+            },
+        )
+        .unwrap();
+
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0], PathBuf::from("hello.txt.001"));
+        for (i, part) in parts.iter().enumerate().skip(1) {
+            assert_eq!(
+                *part,
+                obfuscated_part_path(Path::new("hello.txt"), i + 1, &names_key)
+            );
+            let name = part.to_str().unwrap();
+            assert_eq!(name.len(), 32);
+            assert!(!name.contains("hello"));
+        }
+
+        // a different key gives different names
+        let other_key = super::names_key(&Protected::new([8u8; MASTER_KEY_LEN]));
+        assert_ne!(
+            obfuscated_part_path(Path::new("hello.txt"), 2, &other_key),
+            parts[1]
+        );
+    }
+
+    #[test]
+    fn should_parse_part_paths() {
+        for number in [1, 2, 999, 1000] {
+            let part = part_path(Path::new("backups/file.dx"), number);
+            assert_eq!(
+                parse_part_path(&part),
+                Some((PathBuf::from("backups/file.dx"), number))
+            );
+        }
+
+        assert_eq!(parse_part_path(Path::new("file.dx")), None);
+        assert_eq!(parse_part_path(Path::new("file.01")), None);
+        assert_eq!(parse_part_path(Path::new("file.000")), None);
+        assert_eq!(parse_part_path(Path::new(".001")), None);
+    }
+
+    #[test]
+    fn should_not_split_small_file() {
+        let stor = Arc::new(InMemoryStorage::default());
+        stor.add_hello_txt();
+
+        let parts = execute(
+            stor.clone(),
+            Request {
+                path: "hello.txt",
+                part_size: NonZeroU64::new(11).unwrap(),
+                names_key: None,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(parts, vec![PathBuf::from("hello.txt")]);
+        assert!(stor.files().get(&PathBuf::from("hello.txt")).is_some());
+    }
+}
@@ -1,8 +1,10 @@
 use rand::distributions::{Alphanumeric, DistString};
 use std::cell::RefCell;
 use std::fs;
-use std::io::{Read, Seek, Write};
+use std::io::{ErrorKind, Read, Seek, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
 
 #[cfg(test)]
 use std::collections::HashMap;
@@ -23,13 +25,17 @@ pub enum FileMode {
 pub enum Error {
     CreateDir,
     CreateFile,
+    AlreadyExists,
     OpenFile(FileMode),
     RemoveFile,
+    RenameFile,
+    ResetTimestamps,
     RemoveDir,
     DirEntries,
     FlushFile,
     FileAccess,
     FileLen,
+    SpecialFile(PathBuf, EntryKind),
 }
 
 impl std::fmt::Display for Error {
@@ -37,30 +43,224 @@ impl std::fmt::Display for Error {
         match self {
             Error::CreateDir => f.write_str("Unable to create a new directory"),
             Error::CreateFile => f.write_str("Unable to create a new file"),
+            Error::AlreadyExists => f.write_str("The file already exists"),
             Error::OpenFile(mode) => write!(f, "Unable to read the file in {mode:?} mode"),
             Error::FlushFile => f.write_str("Unable to flush the file"),
             Error::RemoveFile => f.write_str("Unable to remove the file"),
+            Error::RenameFile => f.write_str("Unable to rename the file"),
+            Error::ResetTimestamps => f.write_str("Unable to reset the file's timestamps"),
             Error::RemoveDir => f.write_str("Unable to remove dir"),
             Error::DirEntries => f.write_str("Unable to read directory"),
             Error::FileAccess => f.write_str("Permission denied"),
             Error::FileLen => f.write_str("Unable to get file length"),
+            Error::SpecialFile(path, kind) => {
+                write!(f, "Refusing to read {} as it's a {kind}", path.display())
+            }
         }
     }
 }
 
 impl std::error::Error for Error {}
 
+/// This is what an entry is on the filesystem, which can be found without opening it (see `entry_kind()`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EntryKind {
+    File,
+    Dir,
+    /// A symlink whose target doesn't exist (others are classified as whatever they point to)
+    Symlink,
+    Fifo,
+    Socket,
+    Device,
+}
+
+impl EntryKind {
+    /// Special files can't be archived, and opening some of them (such as a FIFO without a writer) blocks forever
+    #[must_use]
+    pub fn is_special(self) -> bool {
+        matches!(
+            self,
+            EntryKind::Fifo | EntryKind::Socket | EntryKind::Device
+        )
+    }
+}
+
+impl std::fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EntryKind::File => f.write_str("file"),
+            EntryKind::Dir => f.write_str("directory"),
+            EntryKind::Symlink => f.write_str("broken symlink"),
+            EntryKind::Fifo => f.write_str("FIFO"),
+            EntryKind::Socket => f.write_str("socket"),
+            EntryKind::Device => f.write_str("device"),
+        }
+    }
+}
+
+/// This decides what happens to special files (see `EntryKind::is_special()`) that are found while reading a directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SpecialFiles {
+    /// They're left out, and returned alongside the entries so that they can be reported
+    #[default]
+    Skip,
+    /// The first one that's found is returned as an error
+    Refuse,
+}
+
+/// A special file that was left out while reading a directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedEntry {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+}
+
+/// This classifies an entry on the filesystem, without opening it
+///
+/// Symlinks are followed, as reading one reads its target.
+pub fn entry_kind<P: AsRef<Path>>(path: P) -> Result<EntryKind, Error> {
+    let path = path.as_ref();
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) if fs::symlink_metadata(path).is_ok() => return Ok(EntryKind::Symlink),
+        Err(_) => return Err(Error::OpenFile(FileMode::Read)),
+    };
+
+    let file_type = metadata.file_type();
+    if file_type.is_dir() {
+        return Ok(EntryKind::Dir);
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+
+        if file_type.is_fifo() {
+            return Ok(EntryKind::Fifo);
+        }
+        if file_type.is_socket() {
+            return Ok(EntryKind::Socket);
+        }
+        if file_type.is_block_device() || file_type.is_char_device() {
+            return Ok(EntryKind::Device);
+        }
+    }
+
+    Ok(EntryKind::File)
+}
+
+/// This is how many random names are tried when creating a temporary file, before giving up
+const TEMP_FILE_ATTEMPTS: usize = 16;
+
+fn random_file_name() -> String {
+    Alphanumeric.sample_string(&mut rand::thread_rng(), 16)
+}
+
+// temporary files are hidden, and clearly belong to dexios if they're ever left behind
+fn temp_file_name() -> String {
+    format!(".dexios-{}.tmp", random_file_name())
+}
+
+/// This environment variable chooses where temporary files are created, and takes precedence over `TMPDIR`
+pub const TEMP_DIR_ENV: &str = "DEXIOS_TMPDIR";
+
+static TEMP_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// This chooses where temporary files are created (e.g. from `--tmpdir`), and takes precedence over the environment
+pub fn set_temp_dir(dir: Option<PathBuf>) {
+    *TEMP_DIR
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner) = dir;
+}
+
+/// This is the directory that was chosen for temporary files, if there is one
+///
+/// It's the one that was set with `set_temp_dir()`, otherwise `DEXIOS_TMPDIR` or `TMPDIR` (whichever is set first).
+pub fn configured_temp_dir() -> Option<PathBuf> {
+    let set = TEMP_DIR
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .clone();
+
+    set.or_else(|| {
+        [TEMP_DIR_ENV, "TMPDIR"]
+            .iter()
+            .filter_map(std::env::var_os)
+            .find(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+    })
+}
+
+/// This is where temporary files that aren't moved anywhere are created
+///
+/// It's the configured directory (see `configured_temp_dir()`), or the system's default.
+pub fn temp_dir() -> PathBuf {
+    configured_temp_dir().unwrap_or_else(std::env::temp_dir)
+}
+
+// a temporary file can only be renamed over its destination if they're on the same filesystem,
+// so the configured directory is only used if it is (otherwise the file is created beside its destination)
+fn temp_dir_for(configured: Option<PathBuf>, path: &Path) -> PathBuf {
+    let beside = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    match configured {
+        Some(dir) if same_filesystem(&dir, &beside) => dir,
+        _ => beside,
+    }
+}
+
+#[cfg(unix)]
+fn same_filesystem(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.is_dir() && a.dev() == b.dev(),
+        _ => false,
+    }
+}
+
+// there's no portable way to find a file's volume elsewhere, so it's assumed to be different
+#[cfg(not(unix))]
+fn same_filesystem(_a: &Path, _b: &Path) -> bool {
+    false
+}
+
 pub trait Storage<RW>: Send + Sync
 where
     RW: Read + Write + Seek,
 {
     // TODO(pleshevskiy): return a new struct that will be removed on drop.
     fn create_temp_file(&self) -> Result<Entry<RW>, Error> {
-        let mut path = std::env::temp_dir();
-        let file_name = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
-        path.push(file_name);
+        self.create_temp_file_in(temp_dir())
+    }
 
-        self.create_file(path)
+    // Creates a file with a random name within `dir`.
+    // `create_file` never opens an existing file, so another name is tried if one is taken.
+    fn create_temp_file_in<P: AsRef<Path>>(&self, dir: P) -> Result<Entry<RW>, Error> {
+        for _ in 0..TEMP_FILE_ATTEMPTS {
+            match self.create_file(dir.as_ref().join(temp_file_name())) {
+                Err(Error::AlreadyExists) => {}
+                result => return result,
+            }
+        }
+
+        Err(Error::CreateFile)
+    }
+
+    // Creates a temporary file in the same directory as `path`, so it can replace `path` once
+    // it's complete (see `persist_file`), without anything being lost if it's never completed.
+    fn create_temp_file_beside<P: AsRef<Path>>(&self, path: P) -> Result<Entry<RW>, Error> {
+        let dir = path.as_ref().parent().unwrap_or_else(|| Path::new(""));
+        self.create_temp_file_in(dir)
+    }
+
+    // Removes the file while leaving as little metadata behind as possible.
+    // Storages that don't keep any metadata may just remove the file.
+    fn remove_file_securely(&self, file: Entry<RW>) -> Result<(), Error> {
+        self.remove_file(file)
     }
 
     fn create_dir_all<P: AsRef<Path>>(&self, path: P) -> Result<(), Error>;
@@ -70,14 +270,73 @@ where
     fn flush_file(&self, file: &Entry<RW>) -> Result<(), Error>;
     fn file_len(&self, file: &Entry<RW>) -> Result<usize, Error>;
     fn remove_file(&self, file: Entry<RW>) -> Result<(), Error>;
+    // Moves a (temporary) file to `path`, replacing anything that's already there.
+    fn persist_file<P: AsRef<Path>>(&self, file: Entry<RW>, path: P) -> Result<(), Error>;
     fn remove_dir_all(&self, file: Entry<RW>) -> Result<(), Error>;
     // TODO(pleshevskiy): return iterator instead of Vector
     fn read_dir(&self, file: &Entry<RW>) -> Result<Vec<Entry<RW>>, Error>;
+
+    // Reads a directory like `read_dir()`, but special files are handled according to `specials` rather than being opened.
+    // Storages that can't contain special files may just call `read_dir()`.
+    fn read_dir_with(
+        &self,
+        file: &Entry<RW>,
+        _specials: SpecialFiles,
+    ) -> Result<(Vec<Entry<RW>>, Vec<SkippedEntry>), Error> {
+        self.read_dir(file).map(|entries| (entries, Vec::new()))
+    }
 }
 
 pub struct FileStorage;
 
+impl FileStorage {
+    /// Truncates the file, renames it to a random name and resets its timestamps.
+    ///
+    /// Returns the new path, which is left for the caller to unlink.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be truncated, renamed or have its timestamps reset.
+    pub fn scrub_file(&self, file: Entry<fs::File>) -> Result<PathBuf, Error> {
+        let (path, stream) = match file {
+            Entry::File(FileData { path, stream }) => (path, stream.into_inner()),
+            Entry::Dir(_) => return Err(Error::FileAccess),
+        };
+
+        stream.set_len(0).map_err(|_| Error::RemoveFile)?;
+        stream.sync_all().map_err(|_| Error::FlushFile)?;
+        drop(stream);
+
+        // the random name is reserved first, so the rename can't replace another file
+        let random_path = match self.create_temp_file_beside(&path)? {
+            Entry::File(FileData { path, .. }) => path,
+            Entry::Dir(_) => return Err(Error::FileAccess),
+        };
+        fs::rename(&path, &random_path).map_err(|_| Error::RenameFile)?;
+
+        let stream = fs::File::options()
+            .write(true)
+            .open(&random_path)
+            .map_err(|_| Error::OpenFile(FileMode::Write))?;
+        let times = fs::FileTimes::new()
+            .set_accessed(SystemTime::UNIX_EPOCH)
+            .set_modified(SystemTime::UNIX_EPOCH);
+        stream
+            .set_times(times)
+            .map_err(|_| Error::ResetTimestamps)?;
+        stream.sync_all().map_err(|_| Error::FlushFile)?;
+        drop(stream);
+
+        Ok(random_path)
+    }
+}
+
 impl Storage<fs::File> for FileStorage {
+    // the configured temporary directory is used instead, if the file can still be renamed from there to `path`
+    fn create_temp_file_beside<P: AsRef<Path>>(&self, path: P) -> Result<Entry<fs::File>, Error> {
+        self.create_temp_file_in(temp_dir_for(configured_temp_dir(), path.as_ref()))
+    }
+
     fn create_dir_all<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
         fs::create_dir_all(&path).map_err(|_| Error::CreateDir)
     }
@@ -89,7 +348,10 @@ impl Storage<fs::File> for FileStorage {
             .read(true)
             .write(true)
             .open(&path)
-            .map_err(|_| Error::CreateFile)?;
+            .map_err(|e| match e.kind() {
+                ErrorKind::AlreadyExists => Error::AlreadyExists,
+                _ => Error::CreateFile,
+            })?;
         Ok(Entry::File(FileData {
             path,
             stream: RefCell::new(file),
@@ -150,6 +412,41 @@ impl Storage<fs::File> for FileStorage {
         fs::remove_file(file.path()).map_err(|_| Error::RemoveFile)
     }
 
+    fn persist_file<P: AsRef<Path>>(&self, file: Entry<fs::File>, path: P) -> Result<(), Error> {
+        let (temp_path, stream) = match file {
+            Entry::File(FileData { path, stream }) => (path, stream.into_inner()),
+            Entry::Dir(_) => return Err(Error::FileAccess),
+        };
+
+        // the file is closed first, as open files can't be renamed on some platforms
+        stream.sync_all().map_err(|_| Error::FlushFile)?;
+        drop(stream);
+
+        fs::rename(temp_path, &path).map_err(|_| Error::RenameFile)?;
+
+        // the directory is synced too, so that the rename itself isn't lost if the system crashes
+        // (directories can't be opened as files on Windows, where the rename is journaled instead)
+        #[cfg(unix)]
+        {
+            let dir = match path.as_ref().parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            fs::File::open(dir)
+                .and_then(|dir| dir.sync_all())
+                .map_err(|_| Error::FlushFile)?;
+        }
+
+        Ok(())
+    }
+
+    // the file is scrubbed first, so that the original name/size/times aren't the last thing
+    // that the filesystem (or its journal) saw
+    fn remove_file_securely(&self, file: Entry<fs::File>) -> Result<(), Error> {
+        let random_path = self.scrub_file(file)?;
+        fs::remove_file(random_path).map_err(|_| Error::RemoveFile)
+    }
+
     fn remove_dir_all(&self, file: Entry<fs::File>) -> Result<(), Error> {
         if !file.is_dir() {
             return Err(Error::RemoveDir);
@@ -158,19 +455,40 @@ impl Storage<fs::File> for FileStorage {
         fs::remove_dir_all(file.path()).map_err(|_| Error::RemoveDir)
     }
 
+    // special files are always skipped here, as opening a FIFO could block forever
     fn read_dir(&self, file: &Entry<fs::File>) -> Result<Vec<Entry<fs::File>>, Error> {
+        self.read_dir_with(file, SpecialFiles::Skip)
+            .map(|(entries, _)| entries)
+    }
+
+    fn read_dir_with(
+        &self,
+        file: &Entry<fs::File>,
+        specials: SpecialFiles,
+    ) -> Result<(Vec<Entry<fs::File>>, Vec<SkippedEntry>), Error> {
         if !file.is_dir() {
             return Err(Error::FileAccess);
         }
 
-        walkdir::WalkDir::new(file.path())
-            .into_iter()
-            .map(|res| {
-                res.map(|e| e.path().to_owned())
-                    .map_err(|_| Error::DirEntries)
-            })
-            .map(|path| path.and_then(|path| self.read_file(path)))
-            .collect()
+        let mut entries = Vec::new();
+        let mut skipped = Vec::new();
+
+        for res in walkdir::WalkDir::new(file.path()) {
+            let path = res.map_err(|_| Error::DirEntries)?.into_path();
+
+            let kind = entry_kind(&path)?;
+            if kind.is_special() {
+                match specials {
+                    SpecialFiles::Skip => skipped.push(SkippedEntry { path, kind }),
+                    SpecialFiles::Refuse => return Err(Error::SpecialFile(path, kind)),
+                }
+                continue;
+            }
+
+            entries.push(self.read_file(path)?);
+        }
+
+        Ok((entries, skipped))
     }
 }
 
@@ -253,7 +571,7 @@ impl Storage<io::Cursor<Vec<u8>>> for InMemoryStorage {
 
         #[allow(clippy::significant_drop_in_scrutinee)]
         let im_file = match self.files().get(&file_path) {
-            Some(_) => Err(Error::CreateFile),
+            Some(_) => Err(Error::AlreadyExists),
             None => Ok(IMFile::File(InMemoryFile::default())),
         }?;
 
@@ -342,6 +660,19 @@ impl Storage<io::Cursor<Vec<u8>>> for InMemoryStorage {
         Ok(())
     }
 
+    fn persist_file<P: AsRef<Path>>(
+        &self,
+        file: Entry<io::Cursor<Vec<u8>>>,
+        path: P,
+    ) -> Result<(), Error> {
+        let im_file = self
+            .mut_files()
+            .remove(file.path())
+            .ok_or(Error::RenameFile)?;
+        self.save_file(path, im_file);
+        Ok(())
+    }
+
     fn remove_dir_all(&self, file: Entry<io::Cursor<Vec<u8>>>) -> Result<(), Error> {
         if !file.is_dir() {
             return Err(Error::FileAccess);
@@ -485,7 +816,71 @@ mod tests {
         stor.add_hello_txt();
 
         match stor.create_file("hello.txt") {
-            Err(Error::CreateFile) => {}
+            Err(Error::AlreadyExists) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn should_create_temp_file_beside_path() {
+        let stor = InMemoryStorage::default();
+
+        let file = stor.create_temp_file_beside("bar/hello.txt").unwrap();
+        assert_eq!(file.path().parent(), Some(Path::new("bar")));
+        assert!(file.path().to_string_lossy().starts_with("bar/.dexios-"));
+    }
+
+    #[test]
+    fn should_only_use_temp_dir_on_the_same_filesystem() {
+        let tmp = std::env::temp_dir();
+        let dest = tmp.join("hello.txt");
+
+        assert_eq!(temp_dir_for(None, &dest), tmp);
+        assert_eq!(
+            temp_dir_for(None, Path::new("hello.txt")),
+            PathBuf::from(".")
+        );
+        assert_eq!(
+            temp_dir_for(Some(tmp.join("missing-dexios-dir")), &dest),
+            tmp
+        );
+
+        // a directory that was just created within the configured one is on the same filesystem
+        #[cfg(unix)]
+        {
+            let dir = tmp.join(format!("dexios-{}", random_file_name()));
+            fs::create_dir(&dir).unwrap();
+            assert_eq!(temp_dir_for(Some(tmp.clone()), &dir.join("hello.txt")), tmp);
+            fs::remove_dir(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn should_persist_temp_file_over_existing_file() {
+        let stor = InMemoryStorage::default();
+        stor.add_hello_txt();
+
+        let file = stor.create_temp_file_beside("hello.txt").unwrap();
+        let temp_path = file.path().to_path_buf();
+        file.try_writer()
+            .unwrap()
+            .borrow_mut()
+            .write_all(b"goodbye")
+            .unwrap();
+        stor.flush_file(&file).unwrap();
+
+        match stor.persist_file(file, "hello.txt") {
+            Ok(()) => {
+                let files = stor.files();
+                assert!(files.get(&temp_path).is_none());
+                assert_eq!(
+                    files.get(Path::new("hello.txt")).cloned(),
+                    Some(IMFile::File(InMemoryFile {
+                        buf: b"goodbye".to_vec(),
+                        len: 7
+                    }))
+                );
+            }
             _ => unreachable!(),
         }
     }
@@ -563,7 +958,7 @@ mod tests {
             .unwrap();
 
         match stor.flush_file(&file) {
-            Ok(_) => {
+            Ok(()) => {
                 let im_file = stor.files().get(file.path()).cloned();
                 assert_eq!(
                     im_file,
@@ -586,7 +981,7 @@ mod tests {
         let file_path = file.path().to_path_buf();
 
         match stor.remove_file(file) {
-            Ok(_) => {
+            Ok(()) => {
                 let im_file = stor.files().get(&file_path).cloned();
                 assert_eq!(im_file, None);
             }
@@ -603,7 +998,7 @@ mod tests {
         let file_path = file.path().to_path_buf();
 
         match stor.remove_file(file) {
-            Ok(_) => {
+            Ok(()) => {
                 let im_file = stor.files().get(&file_path).cloned();
                 assert_eq!(im_file, None);
             }
@@ -0,0 +1,519 @@
+//! This contains the logic for capturing (and restoring) the data that's attached to a file outside of its contents, so that packing doesn't silently lose it.
+//!
+//! * NTFS alternate data streams (Windows only) are stored as `__DEXIOS_META__/ads/<path>/<stream name>`
+//! * Extended attributes (Unix only, including macOS resource forks and Finder info) are stored in the `AppleDouble` format, as `__DEXIOS_META__/appledouble/<path>`
+//!
+//! These "sidecar" entries are added to the archive after everything else, and they're never extracted as regular files. When unpacking, they're applied to the file that they belong to if the same option is enabled and the platform supports it, otherwise they're skipped.
+//!
+//! Only `user.*` and `com.apple.*` extended attributes are restored (as with tar), as the others (e.g. `security.capability` or `trusted.*`) could grant an extracted file privileges when unpacking as root. Any others within the archive are skipped.
+
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+/// This is the directory within the archive that sidecar entries are stored in
+pub const SIDECAR_PREFIX: &str = "__DEXIOS_META__/";
+
+const ADS_PREFIX: &str = "__DEXIOS_META__/ads/";
+const APPLE_DOUBLE_PREFIX: &str = "__DEXIOS_META__/appledouble/";
+
+/// This selects which kinds of attached data are captured/restored
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Options {
+    /// NTFS alternate data streams (Windows)
+    pub alternate_data_streams: bool,
+    /// Extended attributes, resource forks and Finder info, stored as `AppleDouble` (macOS and other Unix platforms)
+    pub apple_meta: bool,
+}
+
+impl Options {
+    #[must_use]
+    pub fn any(&self) -> bool {
+        self.alternate_data_streams || self.apple_meta
+    }
+
+    /// This returns the options that were requested, but can't be used on this platform
+    #[must_use]
+    pub fn unsupported(&self) -> Self {
+        Self {
+            alternate_data_streams: self.alternate_data_streams && !cfg!(windows),
+            apple_meta: self.apple_meta && !cfg!(unix),
+        }
+    }
+}
+
+/// This returns true if the archive entry is a sidecar, rather than a file that was packed
+#[must_use]
+pub fn is_sidecar(name: &str) -> bool {
+    name.starts_with(SIDECAR_PREFIX)
+}
+
+/// This returns the archive path of the file that a sidecar entry belongs to
+#[must_use]
+pub fn owner(name: &str) -> Option<&str> {
+    if let Some(rest) = name.strip_prefix(ADS_PREFIX) {
+        rest.rsplit_once('/').map(|(file, _)| file)
+    } else {
+        name.strip_prefix(APPLE_DOUBLE_PREFIX)
+    }
+}
+
+/// This reads everything that's attached to the file at `path`, and returns the archive entries that store it
+///
+/// `name` is the file's own name within the archive.
+pub fn capture(path: &Path, name: &str, options: Options) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut sidecars = Vec::new();
+
+    if options.alternate_data_streams {
+        for (stream, data) in read_alternate_data_streams(path)? {
+            sidecars.push((format!("{ADS_PREFIX}{name}/{stream}"), data));
+        }
+    }
+
+    if options.apple_meta {
+        let attributes = read_extended_attributes(path)?;
+        if !attributes.is_empty() {
+            sidecars.push((
+                format!("{APPLE_DOUBLE_PREFIX}{name}"),
+                encode_apple_double(&attributes),
+            ));
+        }
+    }
+
+    Ok(sidecars)
+}
+
+/// This applies a sidecar entry to the file that it belongs to, within `output_dir`
+///
+/// Sidecars that aren't enabled by `options`, or whose file wasn't extracted, are skipped.
+pub fn restore(output_dir: &Path, name: &str, data: &[u8], options: Options) -> Result<()> {
+    if let Some(rest) = name.strip_prefix(ADS_PREFIX) {
+        let (file, stream) = rest
+            .rsplit_once('/')
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Invalid stream entry"))?;
+        let path = enclosed_path(output_dir, file)?;
+        if options.alternate_data_streams && path.is_file() {
+            write_alternate_data_stream(&path, stream, data)?;
+        }
+    } else if let Some(file) = name.strip_prefix(APPLE_DOUBLE_PREFIX) {
+        let path = enclosed_path(output_dir, file)?;
+        if options.apple_meta && path.exists() {
+            write_extended_attributes(&path, &decode_apple_double(data)?)?;
+        }
+    }
+
+    Ok(())
+}
+
+// sidecar names come from the archive, so they're checked in the same way as the files themselves (see `crate::unpack`)
+fn enclosed_path(output_dir: &Path, name: &str) -> Result<PathBuf> {
+    let path = Path::new(name);
+    if path
+        .components()
+        .any(|c| !matches!(c, std::path::Component::Normal(_)))
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "Sidecar entry points outside of the output directory",
+        ));
+    }
+    Ok(output_dir.join(path))
+}
+
+#[cfg(windows)]
+fn read_alternate_data_streams(path: &Path) -> Result<Vec<(String, Vec<u8>)>> {
+    // enumerating streams requires `FindFirstStreamW`, which can't be called without unsafe code, so powershell does it for us
+    // the path is passed through the environment, so it doesn't need to be quoted
+    let output = std::process::Command::new("powershell")
+        .args([
+            "-NoProfile",
+            "-NonInteractive",
+            "-Command",
+            "Get-Item -LiteralPath $env:DEXIOS_ADS_PATH -Stream * | ForEach-Object { $_.Stream }",
+        ])
+        .env("DEXIOS_ADS_PATH", path)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            "Unable to list the file's alternate data streams",
+        ));
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        // this is the file's contents
+        .filter(|stream| !stream.is_empty() && *stream != ":$DATA")
+        .map(|stream| -> Result<(String, Vec<u8>)> {
+            let mut stream_path = path.as_os_str().to_owned();
+            stream_path.push(format!(":{stream}"));
+            Ok((stream.to_string(), std::fs::read(stream_path)?))
+        })
+        .collect()
+}
+
+#[cfg(not(windows))]
+#[allow(clippy::unnecessary_wraps)]
+fn read_alternate_data_streams(_path: &Path) -> Result<Vec<(String, Vec<u8>)>> {
+    Ok(Vec::new())
+}
+
+#[cfg(windows)]
+fn write_alternate_data_stream(path: &Path, stream: &str, data: &[u8]) -> Result<()> {
+    let mut stream_path = path.as_os_str().to_owned();
+    stream_path.push(format!(":{stream}"));
+    std::fs::write(stream_path, data)
+}
+
+#[cfg(not(windows))]
+#[allow(clippy::unnecessary_wraps)]
+fn write_alternate_data_stream(_path: &Path, _stream: &str, _data: &[u8]) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn read_extended_attributes(path: &Path) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut attributes = Vec::new();
+    for name in xattr::list(path)? {
+        // AppleDouble can only store UTF-8 names
+        let Some(name_str) = name.to_str() else {
+            continue;
+        };
+        if let Some(value) = xattr::get(path, &name)? {
+            attributes.push((name_str.to_string(), value));
+        }
+    }
+    attributes.sort();
+    Ok(attributes)
+}
+
+#[cfg(not(unix))]
+#[allow(clippy::unnecessary_wraps)]
+fn read_extended_attributes(_path: &Path) -> Result<Vec<(String, Vec<u8>)>> {
+    Ok(Vec::new())
+}
+
+// these are the only extended attributes that are restored, as the archive isn't trusted
+#[cfg(unix)]
+const RESTORED_ATTRIBUTE_PREFIXES: [&str; 2] = ["user.", "com.apple."];
+
+#[cfg(unix)]
+fn is_restorable(name: &str) -> bool {
+    RESTORED_ATTRIBUTE_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+}
+
+#[cfg(unix)]
+fn write_extended_attributes(path: &Path, attributes: &[(String, Vec<u8>)]) -> Result<()> {
+    attributes
+        .iter()
+        .filter(|(name, _)| is_restorable(name))
+        .try_for_each(|(name, value)| xattr::set(path, name, value))
+}
+
+#[cfg(not(unix))]
+#[allow(clippy::unnecessary_wraps)]
+fn write_extended_attributes(_path: &Path, _attributes: &[(String, Vec<u8>)]) -> Result<()> {
+    Ok(())
+}
+
+// these are the AppleDouble (version 2) constants, along with the extended attribute header that macOS appends to the Finder info
+const APPLE_DOUBLE_MAGIC: u32 = 0x0005_1607;
+const APPLE_DOUBLE_VERSION: u32 = 0x0002_0000;
+const RESOURCE_FORK_ID: u32 = 2;
+const FINDER_INFO_ID: u32 = 9;
+const FINDER_INFO_LEN: usize = 32;
+const ATTR_MAGIC: u32 = 0x4154_5452; // "ATTR"
+const ATTR_HEADER_LEN: usize = 36;
+
+const RESOURCE_FORK_NAME: &str = "com.apple.ResourceFork";
+const FINDER_INFO_NAME: &str = "com.apple.FinderInfo";
+
+// the header (26 bytes) and two entry descriptors (12 bytes each)
+const APPLE_DOUBLE_HEADER_LEN: usize = 50;
+
+fn be_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "AppleDouble file is truncated"))
+}
+
+fn be_u16(bytes: &[u8], offset: usize) -> Result<u16> {
+    bytes
+        .get(offset..offset + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "AppleDouble file is truncated"))
+}
+
+fn to_u32(len: usize) -> u32 {
+    // sidecars are held in memory, and are far smaller than 4 GiB
+    u32::try_from(len).unwrap_or(u32::MAX)
+}
+
+/// This encodes extended attributes as an `AppleDouble` file, in the same layout that macOS' own archivers use
+///
+/// The resource fork and Finder info get their own entries, and everything else is stored in an "ATTR" block that follows the Finder info.
+#[must_use]
+pub fn encode_apple_double(attributes: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut finder_info = [0u8; FINDER_INFO_LEN];
+    let mut resource_fork: &[u8] = &[];
+    let mut others = Vec::new();
+
+    for (name, value) in attributes {
+        match name.as_str() {
+            RESOURCE_FORK_NAME => resource_fork = value,
+            FINDER_INFO_NAME if value.len() == FINDER_INFO_LEN => {
+                finder_info.copy_from_slice(value);
+            }
+            _ => others.push((name, value)),
+        }
+    }
+
+    // each attribute entry is its offset, length, flags, name length and NUL-terminated name, aligned to 4 bytes
+    let entry_lens: Vec<usize> = others
+        .iter()
+        .map(|(name, _)| (11 + name.len() + 1 + 3) & !3)
+        .collect();
+
+    let attr_start = APPLE_DOUBLE_HEADER_LEN + FINDER_INFO_LEN + 2;
+    let data_start = attr_start + ATTR_HEADER_LEN + entry_lens.iter().sum::<usize>();
+    let data_len: usize = others.iter().map(|(_, value)| value.len()).sum();
+    let finder_info_end = if others.is_empty() {
+        APPLE_DOUBLE_HEADER_LEN + FINDER_INFO_LEN
+    } else {
+        data_start + data_len
+    };
+    let total_len = finder_info_end + resource_fork.len();
+
+    let mut bytes = Vec::with_capacity(total_len);
+    bytes.extend_from_slice(&APPLE_DOUBLE_MAGIC.to_be_bytes());
+    bytes.extend_from_slice(&APPLE_DOUBLE_VERSION.to_be_bytes());
+    bytes.extend_from_slice(b"Mac OS X        ");
+    bytes.extend_from_slice(&2u16.to_be_bytes());
+
+    bytes.extend_from_slice(&FINDER_INFO_ID.to_be_bytes());
+    bytes.extend_from_slice(&to_u32(APPLE_DOUBLE_HEADER_LEN).to_be_bytes());
+    bytes.extend_from_slice(&to_u32(finder_info_end - APPLE_DOUBLE_HEADER_LEN).to_be_bytes());
+
+    bytes.extend_from_slice(&RESOURCE_FORK_ID.to_be_bytes());
+    bytes.extend_from_slice(&to_u32(finder_info_end).to_be_bytes());
+    bytes.extend_from_slice(&to_u32(resource_fork.len()).to_be_bytes());
+
+    bytes.extend_from_slice(&finder_info);
+
+    if !others.is_empty() {
+        bytes.extend_from_slice(&[0u8; 2]);
+        bytes.extend_from_slice(&ATTR_MAGIC.to_be_bytes());
+        bytes.extend_from_slice(&[0u8; 4]); // debug tag
+        bytes.extend_from_slice(&to_u32(total_len).to_be_bytes());
+        bytes.extend_from_slice(&to_u32(data_start).to_be_bytes());
+        bytes.extend_from_slice(&to_u32(data_len).to_be_bytes());
+        bytes.extend_from_slice(&[0u8; 12]); // reserved
+        bytes.extend_from_slice(&0u16.to_be_bytes()); // flags
+        bytes.extend_from_slice(
+            &u16::try_from(others.len())
+                .unwrap_or(u16::MAX)
+                .to_be_bytes(),
+        );
+
+        let mut offset = data_start;
+        for ((name, value), entry_len) in others.iter().zip(&entry_lens) {
+            let entry_start = bytes.len();
+            bytes.extend_from_slice(&to_u32(offset).to_be_bytes());
+            bytes.extend_from_slice(&to_u32(value.len()).to_be_bytes());
+            bytes.extend_from_slice(&0u16.to_be_bytes());
+            bytes.push(u8::try_from(name.len() + 1).unwrap_or(u8::MAX));
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.push(0);
+            bytes.resize(entry_start + entry_len, 0);
+            offset += value.len();
+        }
+
+        for (_, value) in &others {
+            bytes.extend_from_slice(value);
+        }
+    }
+
+    bytes.extend_from_slice(resource_fork);
+    bytes
+}
+
+/// This decodes an `AppleDouble` file into the extended attributes that it stores
+pub fn decode_apple_double(bytes: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+    let invalid = |msg: &str| Error::new(ErrorKind::InvalidData, msg.to_string());
+    let slice = |offset: u32, len: u32| {
+        let start = offset as usize;
+        bytes
+            .get(start..start + len as usize)
+            .ok_or_else(|| invalid("AppleDouble entry is out of bounds"))
+    };
+
+    if be_u32(bytes, 0)? != APPLE_DOUBLE_MAGIC || be_u32(bytes, 4)? != APPLE_DOUBLE_VERSION {
+        return Err(invalid("This isn't an AppleDouble file"));
+    }
+
+    let mut attributes = Vec::new();
+    for i in 0..usize::from(be_u16(bytes, 24)?) {
+        let descriptor = 26 + i * 12;
+        let (id, offset, len) = (
+            be_u32(bytes, descriptor)?,
+            be_u32(bytes, descriptor + 4)?,
+            be_u32(bytes, descriptor + 8)?,
+        );
+
+        match id {
+            RESOURCE_FORK_ID if len > 0 => {
+                attributes.push((RESOURCE_FORK_NAME.to_string(), slice(offset, len)?.to_vec()));
+            }
+            FINDER_INFO_ID => {
+                let entry = slice(offset, len)?;
+                let finder_info = entry
+                    .get(..FINDER_INFO_LEN)
+                    .ok_or_else(|| invalid("Finder info is truncated"))?;
+                if finder_info.iter().any(|b| *b != 0) {
+                    attributes.push((FINDER_INFO_NAME.to_string(), finder_info.to_vec()));
+                }
+
+                // the attribute header's offsets are relative to the start of the file
+                let attr_start = offset as usize + FINDER_INFO_LEN + 2;
+                if entry.len() < FINDER_INFO_LEN + 2 + ATTR_HEADER_LEN
+                    || be_u32(bytes, attr_start)? != ATTR_MAGIC
+                {
+                    continue;
+                }
+
+                let mut entry_offset = attr_start + ATTR_HEADER_LEN;
+                for _ in 0..be_u16(bytes, attr_start + 34)? {
+                    let value_offset = be_u32(bytes, entry_offset)?;
+                    let value_len = be_u32(bytes, entry_offset + 4)?;
+                    let name_len = usize::from(
+                        *bytes
+                            .get(entry_offset + 10)
+                            .ok_or_else(|| invalid("Attribute entry is truncated"))?,
+                    );
+                    let name = bytes
+                        .get(entry_offset + 11..entry_offset + 11 + name_len)
+                        .ok_or_else(|| invalid("Attribute name is truncated"))?;
+                    let name = std::str::from_utf8(name.strip_suffix(&[0]).unwrap_or(name))
+                        .map_err(|_| invalid("Attribute name isn't valid UTF-8"))?;
+
+                    attributes.push((name.to_string(), slice(value_offset, value_len)?.to_vec()));
+                    entry_offset = (entry_offset + 11 + name_len + 3) & !3;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    attributes.sort();
+    Ok(attributes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_encode_and_decode_apple_double() {
+        let mut finder_info = vec![0u8; FINDER_INFO_LEN];
+        finder_info[..8].copy_from_slice(b"TEXTttxt");
+
+        let mut attributes = vec![
+            (RESOURCE_FORK_NAME.to_string(), b"resource fork".to_vec()),
+            (FINDER_INFO_NAME.to_string(), finder_info),
+            (
+                "com.apple.quarantine".to_string(),
+                b"0081;5f;Safari;".to_vec(),
+            ),
+            ("user.a".to_string(), vec![1, 2, 3]),
+        ];
+        attributes.sort();
+
+        let encoded = encode_apple_double(&attributes);
+        assert_eq!(decode_apple_double(&encoded).unwrap(), attributes);
+    }
+
+    #[test]
+    fn should_encode_and_decode_empty_apple_double() {
+        let encoded = encode_apple_double(&[]);
+        assert!(decode_apple_double(&encoded).unwrap().is_empty());
+    }
+
+    #[test]
+    fn should_find_sidecar_owner() {
+        assert_eq!(
+            owner("__DEXIOS_META__/ads/dir/a.txt/Zone.Identifier"),
+            Some("dir/a.txt")
+        );
+        assert_eq!(
+            owner("__DEXIOS_META__/appledouble/dir/a.txt"),
+            Some("dir/a.txt")
+        );
+        assert_eq!(owner("dir/a.txt"), None);
+    }
+
+    #[test]
+    fn should_reject_sidecars_outside_of_the_output_dir() {
+        let options = Options {
+            alternate_data_streams: true,
+            apple_meta: true,
+        };
+
+        assert!(restore(
+            Path::new("out"),
+            "__DEXIOS_META__/appledouble/../escape",
+            &encode_apple_double(&[]),
+            options
+        )
+        .is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_only_restore_user_and_apple_attributes() {
+        assert!(is_restorable("user.a"));
+        assert!(is_restorable("com.apple.quarantine"));
+        assert!(is_restorable(FINDER_INFO_NAME));
+        assert!(!is_restorable("security.capability"));
+        assert!(!is_restorable("security.selinux"));
+        assert!(!is_restorable("trusted.a"));
+        assert!(!is_restorable("system.posix_acl_access"));
+        assert!(!is_restorable("users.a"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_not_restore_security_attributes() {
+        let output_dir =
+            std::env::temp_dir().join(format!("dexios-streams-{}", std::process::id()));
+        std::fs::create_dir_all(&output_dir).unwrap();
+        std::fs::write(output_dir.join("a.txt"), b"Hello world").unwrap();
+
+        let options = Options {
+            alternate_data_streams: false,
+            apple_meta: true,
+        };
+
+        // this capability would let the file bind to privileged ports, if it were restored by root
+        let attributes = vec![(
+            "security.capability".to_string(),
+            vec![0, 0, 0, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        )];
+        restore(
+            &output_dir,
+            "__DEXIOS_META__/appledouble/a.txt",
+            &encode_apple_double(&attributes),
+            options,
+        )
+        .unwrap();
+
+        assert!(xattr::get(output_dir.join("a.txt"), "security.capability")
+            .unwrap()
+            .is_none());
+
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+}
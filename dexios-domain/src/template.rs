@@ -0,0 +1,262 @@
+//! This contains output naming templates, which build an output path from the input and the encrypted file, e.g. `{name}-{date}-{hash8}.enc`.
+//!
+//! The following placeholders are supported:
+//!
+//! - `{name}` - the input's file name, e.g. `report.pdf`
+//! - `{stem}` - the input's file name without its extension, e.g. `report`
+//! - `{ext}` - the input's extension, e.g. `pdf` (this is empty if there isn't one)
+//! - `{date}` - the UTC date, e.g. `2022-09-30`
+//! - `{time}` - the UTC time, e.g. `140500`
+//! - `{timestamp}` - the UNIX timestamp, in seconds
+//! - `{hash}` - the BLAKE3 hash of the encrypted file, or `{hashN}` for its first N hex characters (e.g. `{hash8}`)
+//! - `{n}` - the lowest number (starting from 1) that doesn't collide with an existing file
+//!
+//! A literal brace may be written as `{{` or `}}`.
+
+use std::ffi::OsStr;
+use std::path::Path;
+
+use crate::utils::civil_date;
+
+#[derive(Debug)]
+pub enum Error {
+    UnknownPlaceholder(String),
+    Unbalanced(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnknownPlaceholder(placeholder) => write!(
+                f,
+                "Unknown placeholder `{{{placeholder}}}` (supported placeholders are name, stem, ext, date, time, timestamp, hash, hashN and n)"
+            ),
+            Error::Unbalanced(template) => write!(
+                f,
+                "Unbalanced braces in template `{template}` (use `{{{{` or `}}}}` for a literal brace)"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Placeholder {
+    Name,
+    Stem,
+    Ext,
+    Date,
+    Time,
+    Timestamp,
+    Hash(usize),
+    Counter,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Part {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+/// This is everything that a template may be filled in with
+pub struct Fields<'a> {
+    pub input: &'a str,
+    /// A UNIX timestamp, in seconds
+    pub timestamp: u64,
+    /// The hex-encoded BLAKE3 hash of the encrypted file
+    pub hash: &'a str,
+}
+
+impl std::str::FromStr for Template {
+    type Err = Error;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let (name, rest) = chars
+                        .as_str()
+                        .split_once('}')
+                        .ok_or_else(|| Error::Unbalanced(template.to_string()))?;
+                    chars = rest.chars();
+
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Placeholder(placeholder(name)?));
+                }
+                '}' => return Err(Error::Unbalanced(template.to_string())),
+                _ => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(Self { parts })
+    }
+}
+
+fn placeholder(name: &str) -> Result<Placeholder, Error> {
+    let placeholder = match name {
+        "name" => Placeholder::Name,
+        "stem" => Placeholder::Stem,
+        "ext" => Placeholder::Ext,
+        "date" => Placeholder::Date,
+        "time" => Placeholder::Time,
+        "timestamp" => Placeholder::Timestamp,
+        "hash" => Placeholder::Hash(64),
+        "n" => Placeholder::Counter,
+        _ => match name.strip_prefix("hash").and_then(|len| len.parse().ok()) {
+            Some(len @ 1..=64) => Placeholder::Hash(len),
+            _ => return Err(Error::UnknownPlaceholder(name.to_string())),
+        },
+    };
+
+    Ok(placeholder)
+}
+
+impl Template {
+    /// This returns true if the template needs the hash of the encrypted file, so it can't be rendered until it's written
+    #[must_use]
+    pub fn needs_hash(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Placeholder(Placeholder::Hash(_))))
+    }
+
+    /// This fills in the template
+    ///
+    /// If it contains `{n}`, the lowest number for which `exists` returns false is used. Otherwise, `exists` isn't called.
+    #[must_use]
+    pub fn render(&self, fields: &Fields<'_>, exists: impl Fn(&str) -> bool) -> String {
+        let has_counter = self
+            .parts
+            .contains(&Part::Placeholder(Placeholder::Counter));
+
+        if !has_counter {
+            return self.render_with(fields, 0);
+        }
+
+        (1..=u64::MAX)
+            .map(|n| self.render_with(fields, n))
+            .find(|path| !exists(path))
+            .unwrap_or_default()
+    }
+
+    fn render_with(&self, fields: &Fields<'_>, n: u64) -> String {
+        let input = Path::new(fields.input);
+
+        let days = fields.timestamp / 86_400;
+        let secs = fields.timestamp % 86_400;
+
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(literal) => literal.clone(),
+                Part::Placeholder(placeholder) => match placeholder {
+                    Placeholder::Name => lossy(input.file_name()),
+                    Placeholder::Stem => lossy(input.file_stem()),
+                    Placeholder::Ext => lossy(input.extension()),
+                    Placeholder::Date => {
+                        let (year, month, day) = civil_date(days);
+                        format!("{year:04}-{month:02}-{day:02}")
+                    }
+                    Placeholder::Time => {
+                        format!("{:02}{:02}{:02}", secs / 3600, secs % 3600 / 60, secs % 60)
+                    }
+                    Placeholder::Timestamp => fields.timestamp.to_string(),
+                    Placeholder::Hash(len) => fields.hash.chars().take(*len).collect(),
+                    Placeholder::Counter => n.to_string(),
+                },
+            })
+            .collect()
+    }
+}
+
+fn lossy(s: Option<&OsStr>) -> String {
+    s.map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIELDS: Fields<'static> = Fields {
+        input: "docs/report.pdf",
+        timestamp: 1_664_546_700,
+        hash: "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+    };
+
+    #[test]
+    fn should_render_placeholders() {
+        let template: Template = "out/{stem}-{date}-{time}-{hash8}.{ext}.enc"
+            .parse()
+            .unwrap();
+
+        assert!(template.needs_hash());
+        assert_eq!(
+            template.render(&FIELDS, |_| unreachable!()),
+            "out/report-2022-09-30-140500-01234567.pdf.enc"
+        );
+    }
+
+    #[test]
+    fn should_pick_lowest_free_counter() {
+        let template: Template = "{name}.{n}.enc".parse().unwrap();
+
+        assert!(!template.needs_hash());
+        assert_eq!(
+            template.render(&FIELDS, |path| path == "report.pdf.1.enc"
+                || path == "report.pdf.2.enc"),
+            "report.pdf.3.enc"
+        );
+    }
+
+    #[test]
+    fn should_escape_braces() {
+        let template: Template = "{{{timestamp}}}".parse().unwrap();
+
+        assert_eq!(template.render(&FIELDS, |_| false), "{1664546700}");
+    }
+
+    #[test]
+    fn should_reject_invalid_templates() {
+        assert!(matches!(
+            "{name".parse::<Template>(),
+            Err(Error::Unbalanced(_))
+        ));
+        assert!(matches!(
+            "name}".parse::<Template>(),
+            Err(Error::Unbalanced(_))
+        ));
+        assert!(matches!(
+            "{hash65}".parse::<Template>(),
+            Err(Error::UnknownPlaceholder(_))
+        ));
+        assert!(matches!(
+            "{size}".parse::<Template>(),
+            Err(Error::UnknownPlaceholder(_))
+        ));
+    }
+}
@@ -0,0 +1,106 @@
+//! This provides functionality for sending encrypted data directly to a peer.
+//!
+//! Nothing here is tied to a transport - anything that implements `Read`/`Write` (e.g. a `TcpStream`) may be used.
+//!
+//! The sender writes a short preamble, followed by a standard Dexios header, and then the stream-encrypted data. The preamble is:
+//!
+//! ```text
+//! magic (5 bytes) | version (1 byte) | header length (u32 LE)
+//! ```
+//!
+//! As the header length is sent up front, the receiver never needs to seek.
+
+use std::io::{Read, Write};
+
+use crate::encrypt;
+
+pub mod pake;
+pub mod receive;
+pub mod send;
+
+pub const TRANSFER_MAGIC: [u8; 5] = *b"DXTFR";
+pub const TRANSFER_VERSION: u8 = 1;
+
+/// Headers are never this large, so anything above it is rejected rather than allocated
+const MAX_HEADER_LEN: u32 = 4096;
+
+#[derive(Debug)]
+pub enum Error {
+    ReadData,
+    WriteData,
+    InvalidPreamble,
+    UnsupportedVersion,
+    DeserializeHeader,
+    SerializeHeader,
+    UnsupportedMode,
+    CreateAad,
+    DecryptMasterKey,
+    InitializeStreams,
+    EncryptData,
+    DecryptData,
+    KeyExchange,
+    IncorrectCode,
+    Encrypt(encrypt::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::ReadData => f.write_str("Unable to read data"),
+            Error::WriteData => f.write_str("Unable to write data"),
+            Error::InvalidPreamble => f.write_str("The peer didn't send a valid transfer preamble"),
+            Error::UnsupportedVersion => f.write_str("The transfer version is not supported"),
+            Error::DeserializeHeader => f.write_str("Cannot deserialize header"),
+            Error::SerializeHeader => f.write_str("Cannot serialize header"),
+            Error::UnsupportedMode => f.write_str("Only stream mode may be used for transfers"),
+            Error::CreateAad => f.write_str("Cannot create AAD"),
+            Error::DecryptMasterKey => f.write_str("Cannot decrypt master key"),
+            Error::InitializeStreams => f.write_str("Cannot initialize streams"),
+            Error::EncryptData => f.write_str("Unable to encrypt data"),
+            Error::DecryptData => f.write_str("Unable to decrypt data"),
+            Error::KeyExchange => f.write_str("Unable to complete the key exchange"),
+            Error::IncorrectCode => {
+                f.write_str("The peer used a different code (or the connection was tampered with)")
+            }
+            Error::Encrypt(inner) => write!(f, "Unable to encrypt: {inner}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn write_preamble(writer: &mut impl Write, header_len: u32) -> Result<(), Error> {
+    writer
+        .write_all(&TRANSFER_MAGIC)
+        .and_then(|()| writer.write_all(&[TRANSFER_VERSION]))
+        .and_then(|()| writer.write_all(&header_len.to_le_bytes()))
+        .map_err(|_| Error::WriteData)
+}
+
+/// This reads the preamble, and returns the length of the header that follows it
+fn read_preamble(reader: &mut impl Read) -> Result<u32, Error> {
+    let mut magic = [0u8; TRANSFER_MAGIC.len()];
+    reader.read_exact(&mut magic).map_err(|_| Error::ReadData)?;
+    if magic != TRANSFER_MAGIC {
+        return Err(Error::InvalidPreamble);
+    }
+
+    let mut version = [0u8; 1];
+    reader
+        .read_exact(&mut version)
+        .map_err(|_| Error::ReadData)?;
+    if version[0] != TRANSFER_VERSION {
+        return Err(Error::UnsupportedVersion);
+    }
+
+    let mut header_len = [0u8; 4];
+    reader
+        .read_exact(&mut header_len)
+        .map_err(|_| Error::ReadData)?;
+    let header_len = u32::from_le_bytes(header_len);
+    if header_len > MAX_HEADER_LEN {
+        return Err(Error::InvalidPreamble);
+    }
+
+    Ok(header_len)
+}
@@ -0,0 +1,150 @@
+//! This provides a password-authenticated key exchange (SPAKE2), so that a transfer can be bootstrapped from a short, human-readable code.
+//!
+//! Both peers only end up with the same session key if they used the same code, and an attacker gets a single guess per connection. Each peer then proves that it holds the session key before any data is sent, so a wrong code is reported up front.
+//!
+//! The session key is used as the raw key for `send`/`receive`, so the data is still wrapped in a standard Dexios header.
+
+use std::cell::RefCell;
+use std::io::{Read, Write};
+
+use core::protected::Protected;
+use spake2::{Ed25519Group, Identity, Password, Spake2};
+
+use super::Error;
+
+const SENDER_ID: &[u8] = b"dexios-transfer-sender";
+const RECEIVER_ID: &[u8] = b"dexios-transfer-receiver";
+
+/// The length of a SPAKE2 message over Ed25519 (a one byte side identifier, and a compressed point)
+const MESSAGE_LEN: usize = 33;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Role {
+    Sender,
+    Receiver,
+}
+
+impl Role {
+    fn confirmation_context(self) -> &'static str {
+        match self {
+            Role::Sender => "dexios transfer 2022-10 sender confirmation",
+            Role::Receiver => "dexios transfer 2022-10 receiver confirmation",
+        }
+    }
+
+    fn peer(self) -> Self {
+        match self {
+            Role::Sender => Role::Receiver,
+            Role::Receiver => Role::Sender,
+        }
+    }
+}
+
+/// This runs the key exchange over `stream`, and returns the session key once the peer has proven that it used the same code
+pub fn handshake<S>(
+    stream: &RefCell<S>,
+    code: &[u8],
+    role: Role,
+) -> Result<Protected<Vec<u8>>, Error>
+where
+    S: Read + Write,
+{
+    let password = Password::new(code);
+    let (sender_id, receiver_id) = (Identity::new(SENDER_ID), Identity::new(RECEIVER_ID));
+
+    let (state, message) = match role {
+        Role::Sender => Spake2::<Ed25519Group>::start_a(&password, &sender_id, &receiver_id),
+        Role::Receiver => Spake2::<Ed25519Group>::start_b(&password, &sender_id, &receiver_id),
+    };
+
+    let mut stream = stream.borrow_mut();
+
+    stream
+        .write_all(&message)
+        .and_then(|()| stream.flush())
+        .map_err(|_| Error::WriteData)?;
+
+    let mut peer_message = [0u8; MESSAGE_LEN];
+    stream
+        .read_exact(&mut peer_message)
+        .map_err(|_| Error::ReadData)?;
+
+    let shared_key = Protected::new(
+        state
+            .finish(&peer_message)
+            .map_err(|_| Error::KeyExchange)?,
+    );
+    let shared_key: &[u8; 32] = shared_key
+        .expose()
+        .as_slice()
+        .try_into()
+        .map_err(|_| Error::KeyExchange)?;
+
+    // 1. prove that we hold the shared key
+    let confirmation = blake3::derive_key(role.confirmation_context(), shared_key);
+    stream
+        .write_all(&confirmation)
+        .and_then(|()| stream.flush())
+        .map_err(|_| Error::WriteData)?;
+
+    // 2. and check that the peer does too
+    let mut peer_confirmation = [0u8; blake3::OUT_LEN];
+    stream
+        .read_exact(&mut peer_confirmation)
+        .map_err(|_| Error::ReadData)?;
+
+    let expected = blake3::Hash::from(blake3::derive_key(
+        role.peer().confirmation_context(),
+        shared_key,
+    ));
+
+    // `blake3::Hash` comparisons are constant-time
+    if expected != blake3::Hash::from(peer_confirmation) {
+        return Err(Error::IncorrectCode);
+    }
+
+    let session_key = blake3::derive_key("dexios transfer 2022-10 session key", shared_key);
+    Ok(Protected::new(session_key.to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, TcpStream};
+
+    type HandshakeResult = Result<Protected<Vec<u8>>, Error>;
+
+    fn exchange(
+        sender_code: &'static [u8],
+        receiver_code: &'static [u8],
+    ) -> (HandshakeResult, HandshakeResult) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let receiver = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handshake(&RefCell::new(stream), receiver_code, Role::Receiver)
+        });
+
+        let stream = TcpStream::connect(address).unwrap();
+        let sender = handshake(&RefCell::new(stream), sender_code, Role::Sender);
+
+        (sender, receiver.join().unwrap())
+    }
+
+    #[test]
+    fn should_agree_on_session_key_with_same_code() {
+        match exchange(b"hello-world-foo", b"hello-world-foo") {
+            (Ok(sender), Ok(receiver)) => assert_eq!(sender.expose(), receiver.expose()),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn should_reject_different_code() {
+        match exchange(b"hello-world-foo", b"hello-world-bar") {
+            (Err(Error::IncorrectCode), Err(Error::IncorrectCode)) => (),
+            _ => unreachable!(),
+        }
+    }
+}
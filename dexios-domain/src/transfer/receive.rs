@@ -0,0 +1,199 @@
+//! This provides functionality for receiving data from a peer, and decrypting it.
+
+use std::cell::RefCell;
+use std::io::{Cursor, Read, Write};
+
+use core::header::Header;
+use core::key::decrypt_master_key;
+use core::primitives::{Mode, StreamCounter};
+use core::protected::Protected;
+use core::stream::DecryptionStreams;
+use core::subkeys::Subkeys;
+
+use super::{read_preamble, Error};
+use crate::decrypt::{decrypt_stream, OnDecryptedHeaderFn};
+
+pub struct Request<'a, R, W>
+where
+    R: Read,
+    W: Write,
+{
+    pub reader: &'a RefCell<R>,
+    pub writer: &'a RefCell<W>,
+    pub raw_key: Protected<Vec<u8>>,
+    /// If set, the header and encrypted data are written verbatim instead of the plaintext
+    ///
+    /// The data is still decrypted (and discarded) so that it's authenticated
+    pub keep_encrypted: bool,
+    pub on_decrypted_header: Option<OnDecryptedHeaderFn>,
+}
+
+/// This copies everything read from `reader` into `writer`
+struct TeeReader<'a, R, W> {
+    reader: &'a mut R,
+    writer: &'a mut W,
+}
+
+impl<R: Read, W: Write> Read for TeeReader<'_, R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read_count = self.reader.read(buf)?;
+        self.writer.write_all(&buf[..read_count])?;
+        Ok(read_count)
+    }
+}
+
+pub fn execute<R, W>(req: Request<'_, R, W>) -> Result<(), Error>
+where
+    R: Read,
+    W: Write,
+{
+    let mut reader = req.reader.borrow_mut();
+
+    let header_len = read_preamble(&mut *reader)?;
+    let mut header_bytes = vec![0u8; header_len as usize];
+    reader
+        .read_exact(&mut header_bytes)
+        .map_err(|_| Error::ReadData)?;
+
+    let (header, aad) = Header::deserialize(&mut Cursor::new(&header_bytes))
+        .map_err(|_| Error::DeserializeHeader)?;
+
+    if header.header_type.mode != Mode::StreamMode {
+        return Err(Error::UnsupportedMode);
+    }
+
+    if let Some(cb) = req.on_decrypted_header {
+        cb(&header.header_type);
+    }
+
+    let master_key =
+        decrypt_master_key(req.raw_key, &header).map_err(|_| Error::DecryptMasterKey)?;
+
+    let payload_key = Subkeys::derive(master_key, &header).payload;
+
+    let streams = if header.counter == StreamCounter::Be64 {
+        DecryptionStreams::initialize_be64(
+            payload_key,
+            &header.nonce,
+            &header.header_type.algorithm,
+        )
+    } else {
+        DecryptionStreams::initialize(payload_key, &header.nonce, &header.header_type.algorithm)
+    }
+    .map_err(|_| Error::InitializeStreams)?;
+
+    let mut writer = req.writer.borrow_mut();
+    if req.keep_encrypted {
+        writer
+            .write_all(&header_bytes)
+            .map_err(|_| Error::WriteData)?;
+
+        let mut tee = TeeReader {
+            reader: &mut *reader,
+            writer: &mut *writer,
+        };
+        decrypt_stream(streams, &header, &mut tee, &mut std::io::sink(), &aad)
+    } else {
+        decrypt_stream(streams, &header, &mut *reader, &mut *writer, &aad)
+    }
+    .map_err(|_| Error::DecryptData)?;
+
+    writer.flush().map_err(|_| Error::WriteData)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::header::HashingAlgorithm;
+    use core::primitives::Algorithm;
+
+    use crate::encrypt::tests::PASSWORD;
+
+    fn send(content: &[u8]) -> Vec<u8> {
+        let mut sent = vec![];
+        crate::transfer::send::execute(crate::transfer::send::Request {
+            reader: &RefCell::new(content),
+            writer: &RefCell::new(&mut sent),
+            raw_key: Protected::new(PASSWORD.to_vec()),
+            algorithm: Algorithm::XChaCha20Poly1305,
+            hashing_algorithm: HashingAlgorithm::Argon2id(1),
+        })
+        .unwrap();
+        sent
+    }
+
+    #[test]
+    fn should_receive_sent_content() {
+        let sent = send(b"Hello world");
+
+        let mut output_content = vec![];
+        let req = Request {
+            reader: &RefCell::new(sent.as_slice()),
+            writer: &RefCell::new(&mut output_content),
+            raw_key: Protected::new(PASSWORD.to_vec()),
+            keep_encrypted: false,
+            on_decrypted_header: None,
+        };
+
+        match execute(req) {
+            Ok(()) => assert_eq!(output_content, b"Hello world".to_vec()),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn should_not_receive_with_wrong_key() {
+        let sent = send(b"Hello world");
+
+        let mut output_content = vec![];
+        let req = Request {
+            reader: &RefCell::new(sent.as_slice()),
+            writer: &RefCell::new(&mut output_content),
+            raw_key: Protected::new(b"87654321".to_vec()),
+            keep_encrypted: false,
+            on_decrypted_header: None,
+        };
+
+        match execute(req) {
+            Err(Error::DecryptMasterKey) => assert!(output_content.is_empty()),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn should_reject_invalid_preamble() {
+        let req = Request {
+            reader: &RefCell::new(&b"not a transfer"[..]),
+            writer: &RefCell::new(vec![]),
+            raw_key: Protected::new(PASSWORD.to_vec()),
+            keep_encrypted: false,
+            on_decrypted_header: None,
+        };
+
+        match execute(req) {
+            Err(Error::InvalidPreamble) => (),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn should_keep_received_content_encrypted() {
+        let sent = send(b"Hello world");
+
+        let mut output_content = vec![];
+        let req = Request {
+            reader: &RefCell::new(sent.as_slice()),
+            writer: &RefCell::new(&mut output_content),
+            raw_key: Protected::new(PASSWORD.to_vec()),
+            keep_encrypted: true,
+            on_decrypted_header: None,
+        };
+
+        match execute(req) {
+            // everything after the preamble is a standard encrypted file
+            Ok(()) => assert_eq!(output_content, sent[10..].to_vec()),
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! This provides functionality for encrypting data and sending it to a peer.
+
+use std::cell::RefCell;
+use std::io::{Read, Write};
+
+use core::header::{HashingAlgorithm, HeaderType, HEADER_VERSION};
+use core::primitives::{Algorithm, Compression, Mode, Padding, BLOCK_SIZE};
+use core::protected::Protected;
+
+use super::{write_preamble, Error};
+
+pub struct Request<'a, R, W>
+where
+    R: Read,
+    W: Write,
+{
+    pub reader: &'a RefCell<R>,
+    pub writer: &'a RefCell<W>,
+    pub raw_key: Protected<Vec<u8>>,
+    // TODO: don't use external types in logic
+    pub algorithm: Algorithm,
+    pub hashing_algorithm: HashingAlgorithm,
+}
+
+pub fn execute<R, W>(req: Request<'_, R, W>) -> Result<(), Error>
+where
+    R: Read,
+    W: Write,
+{
+    let header_type = HeaderType {
+        version: HEADER_VERSION,
+        algorithm: req.algorithm,
+        mode: Mode::StreamMode,
+    };
+
+    let (header, streams, _) = crate::encrypt::init_header(
+        req.raw_key,
+        header_type,
+        req.hashing_algorithm,
+        Compression::None,
+        BLOCK_SIZE,
+        Padding::None,
+        None,
+        &[],
+        Vec::new(),
+        Vec::new(),
+        false,
+        false,
+        false,
+    )
+    .map_err(Error::Encrypt)?;
+
+    let header_bytes = header.serialize().map_err(|_| Error::SerializeHeader)?;
+    let aad = header.create_aad().map_err(|_| Error::CreateAad)?;
+
+    let mut writer = req.writer.borrow_mut();
+    write_preamble(
+        &mut *writer,
+        u32::try_from(header_bytes.len()).map_err(|_| Error::SerializeHeader)?,
+    )?;
+    writer
+        .write_all(&header_bytes)
+        .map_err(|_| Error::WriteData)?;
+
+    streams
+        .encrypt_file(
+            &mut *req.reader.borrow_mut(),
+            &mut *writer,
+            &aad,
+            header.block_size,
+        )
+        .map_err(|_| Error::EncryptData)?;
+
+    writer.flush().map_err(|_| Error::WriteData)
+}
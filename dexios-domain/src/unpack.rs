@@ -1,13 +1,21 @@
 //! This contains the logic for decrypting a zip file, and extracting each file to the target directory. The temporary zip file is then erased with one pass.
 //!
 //! This is known as "unpacking" within Dexios.
+//!
+//! If the archive is prefixed with its hash (see `crate::pack`), the hash is verified before any files are extracted.
+//!
+//! The archive's declared sizes are checked against `Limits` before anything is extracted too, so that an archive from someone else can't be used to fill the disk (a "zip bomb"). Each entry is also stopped at its declared size while it's being extracted, in case the declaration was a lie.
 
 use std::cell::RefCell;
 use std::io::{Read, Seek, Write};
-use std::path::PathBuf;
+use std::num::NonZeroU8;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+use crate::filters::{self, Filter};
+use crate::pack::{hash_archive, ARCHIVE_HASH_MAGIC, ARCHIVE_HASH_PREFIX_LEN};
 use crate::storage::{self, Storage};
+use crate::streams;
 use crate::{decrypt, overwrite};
 use core::protected::Protected;
 
@@ -17,6 +25,14 @@ pub enum Error {
     OpenArchive,
     OpenArchivedFile,
     ResetCursorPosition,
+    ReadData,
+    ArchiveHashMismatch,
+    RestoreStreams,
+    TooManyEntries(usize, usize),
+    TooLarge(u64, u64),
+    SuspiciousRatio(String, u64, u64),
+    SizeMismatch(String),
+    Filter(filters::Error),
     Storage(storage::Error),
     Decrypt(decrypt::Error),
 }
@@ -28,6 +44,27 @@ impl std::fmt::Display for Error {
             Error::OpenArchive => f.write_str("Unable to open archive"),
             Error::OpenArchivedFile => f.write_str("Unable to open archived file"),
             Error::ResetCursorPosition => f.write_str("Unable to reset cursor position"),
+            Error::ReadData => f.write_str("Unable to read data"),
+            Error::ArchiveHashMismatch => {
+                f.write_str("The archive's hash doesn't match, it may be corrupted")
+            }
+            Error::RestoreStreams => f.write_str("Unable to restore the data attached to a file"),
+            Error::TooManyEntries(entries, max) => write!(
+                f,
+                "The archive has {entries} entries, which is more than the limit of {max}"
+            ),
+            Error::TooLarge(size, max) => write!(
+                f,
+                "The archive would extract to {size} bytes, which is more than the limit of {max} bytes"
+            ),
+            Error::SuspiciousRatio(name, ratio, max) => write!(
+                f,
+                "`{name}` is compressed by {ratio}:1, which is more than the limit of {max}:1 (it may be a zip bomb)"
+            ),
+            Error::SizeMismatch(name) => {
+                write!(f, "`{name}` is larger than the archive says it is")
+            }
+            Error::Filter(inner) => write!(f, "Unable to filter a file: {inner}"),
             Error::Storage(inner) => write!(f, "Storage error: {inner}"),
             Error::Decrypt(inner) => write!(f, "Decrypt error: {inner}"),
         }
@@ -36,6 +73,69 @@ impl std::fmt::Display for Error {
 
 impl std::error::Error for Error {}
 
+/// Entries smaller than this aren't checked for their compression ratio, as tiny or empty files can have extreme ratios
+pub const RATIO_MIN_SIZE: u64 = 1024 * 1024;
+
+/// This is the compression ratio that's allowed by default, which only the most repetitive data (such as a file full of zeros) exceeds
+pub const DEFAULT_MAX_RATIO: u64 = 1000;
+
+/// These limit what an archive may extract to, and `None` means that there's no limit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// The total uncompressed size of every entry, in bytes
+    pub max_size: Option<u64>,
+    pub max_entries: Option<usize>,
+    /// The uncompressed size of each entry divided by its compressed size
+    pub max_ratio: Option<u64>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_size: None,
+            max_entries: None,
+            max_ratio: Some(DEFAULT_MAX_RATIO),
+        }
+    }
+}
+
+/// This checks the sizes that the archive declares for its entries, before anything is extracted
+fn check_limits<R: Read + Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    limits: Limits,
+) -> Result<(), Error> {
+    if let Some(max) = limits.max_entries {
+        if archive.len() > max {
+            return Err(Error::TooManyEntries(archive.len(), max));
+        }
+    }
+
+    let mut total: u64 = 0;
+    for i in 0..archive.len() {
+        let zip_file = archive
+            .by_index_raw(i)
+            .map_err(|_| Error::OpenArchivedFile)?;
+        let size = zip_file.size();
+        total = total.saturating_add(size);
+
+        if let Some(max) = limits.max_ratio {
+            let ratio = size / zip_file.compressed_size().max(1);
+            if size >= RATIO_MIN_SIZE && ratio > max {
+                return Err(Error::SuspiciousRatio(
+                    zip_file.name().to_string(),
+                    ratio,
+                    max,
+                ));
+            }
+        }
+    }
+
+    match limits.max_size {
+        Some(max) if total > max => Err(Error::TooLarge(total, max)),
+        _ => Ok(()),
+    }
+}
+
 type OnArchiveInfo = Box<dyn FnOnce(usize)>;
 type OnZipFileFn = Box<dyn Fn(PathBuf) -> bool>;
 
@@ -50,6 +150,144 @@ where
     pub on_decrypted_header: Option<decrypt::OnDecryptedHeaderFn>,
     pub on_archive_info: Option<OnArchiveInfo>,
     pub on_zip_file: Option<OnZipFileFn>,
+    /// This selects which data attached to each file is restored, if it was archived (see `crate::streams`)
+    pub streams: streams::Options,
+    /// These transform the contents of matching files before they're written (see `crate::filters`)
+    pub filters: Vec<Filter>,
+    pub limits: Limits,
+}
+
+/// This verifies the hash that prefixes the archive, if there is one
+///
+/// Archives created before the hash was added are accepted as-is.
+fn verify_archive_hash(reader: &mut (impl Read + Seek)) -> Result<(), Error> {
+    reader.rewind().map_err(|_| Error::ResetCursorPosition)?;
+
+    let mut prefix = [0u8; ARCHIVE_HASH_PREFIX_LEN];
+    if reader.read_exact(&mut prefix).is_err()
+        || prefix[..ARCHIVE_HASH_MAGIC.len()] != ARCHIVE_HASH_MAGIC
+    {
+        return Ok(());
+    }
+
+    let mut expected_hash = [0u8; blake3::OUT_LEN];
+    expected_hash.copy_from_slice(&prefix[ARCHIVE_HASH_MAGIC.len()..]);
+
+    let hash = hash_archive(reader).map_err(|_| Error::ReadData)?;
+
+    // `blake3::Hash` compares in constant time
+    if hash != blake3::Hash::from(expected_hash) {
+        return Err(Error::ArchiveHashMismatch);
+    }
+
+    Ok(())
+}
+
+/// This is an entry that's going to be extracted: its path within the output directory, its index within the archive, and whether it's a directory
+type Entity = (PathBuf, usize, bool);
+
+/// This finds every entry to extract, and sets the sidecars (see `crate::streams`) aside, as they're never extracted as files
+///
+/// Entries that would be written outside of the output directory are skipped, as are any that `on_zip_file` rejects.
+fn prepare_entities<R: Read + Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    output_dir: &Path,
+    on_zip_file: Option<&OnZipFileFn>,
+) -> (Vec<Entity>, Vec<(String, usize)>) {
+    let mut sidecars = Vec::new();
+    let entities = (0..archive.len())
+        .filter_map(|i| {
+            let zip_file = archive.by_index(i).ok()?;
+            if streams::is_sidecar(zip_file.name()) {
+                sidecars.push((zip_file.name().to_string(), i));
+                return None;
+            }
+
+            let mut full_path = output_dir.to_path_buf();
+
+            // Prevent zip slip attack
+            //
+            // Source: https://snyk.io/research/zip-slip-vulnerability
+            zip_file.enclosed_name().map(|path| {
+                full_path.push(path);
+
+                (full_path, i, zip_file.is_dir())
+            })
+        })
+        .filter(|(full_path, ..)| {
+            if let Some(on_zip_file) = on_zip_file {
+                on_zip_file(full_path.clone())
+            } else {
+                true
+            }
+        })
+        .collect();
+
+    (entities, sidecars)
+}
+
+/// This applies every sidecar entry to the file that it belongs to (see `crate::streams`)
+///
+/// Files that were skipped keep their own data, as the sidecar describes what was in the archive.
+fn restore_sidecars<R: Read + Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    output_dir: &Path,
+    entities: &[Entity],
+    sidecars: &[(String, usize)],
+    options: streams::Options,
+) -> Result<(), Error> {
+    let extracted = |name: &str| {
+        streams::owner(name).is_some_and(|owner| {
+            let path = output_dir.join(owner);
+            entities.iter().any(|(full_path, ..)| *full_path == path)
+        })
+    };
+
+    for (name, i) in sidecars.iter().filter(|(name, _)| extracted(name)) {
+        let mut data = Vec::new();
+        archive
+            .by_index(*i)
+            .map_err(|_| Error::OpenArchivedFile)?
+            .read_to_end(&mut data)
+            .map_err(|_| Error::ReadData)?;
+        streams::restore(output_dir, name, &data, options).map_err(|_| Error::RestoreStreams)?;
+    }
+
+    Ok(())
+}
+
+/// This writes an archived file's contents, after running them through the first filter that matches it
+///
+/// The contents are stopped at the size that the archive declared, so that the limits can't be dodged.
+fn extract_file(
+    zip_file: zip::read::ZipFile<'_>,
+    filters: &[Filter],
+    writer: &mut impl Write,
+) -> Result<(), Error> {
+    let name = zip_file.name().to_string();
+    let filter = filters::find(filters, &name);
+
+    // one extra byte is allowed through, so that we can tell if there was more than declared
+    let size = zip_file.size();
+    let mut zip_file = zip_file.take(size.saturating_add(1));
+
+    if let Some(filter) = filter {
+        let mut data = Vec::new();
+        zip_file
+            .read_to_end(&mut data)
+            .map_err(|_| Error::ReadData)?;
+        if data.len() as u64 > size {
+            return Err(Error::SizeMismatch(name));
+        }
+        let data = filter.apply(&data).map_err(Error::Filter)?;
+        writer.write_all(&data).map_err(|_| Error::WriteData)
+    } else {
+        let written = std::io::copy(&mut zip_file, writer).map_err(|_| Error::WriteData)?;
+        if written > size {
+            return Err(Error::SizeMismatch(name));
+        }
+        Ok(())
+    }
 }
 
 pub fn execute<RW: Read + Write + Seek>(
@@ -67,7 +305,10 @@ pub fn execute<RW: Read + Write + Seek>(
             .try_writer()
             .expect("We sure that file in write mode"),
         raw_key: req.raw_key,
+        master_key: None,
+        identity: None,
         on_decrypted_header: req.on_decrypted_header,
+        on_file_info: None,
     })
     .map_err(Error::Decrypt)?;
 
@@ -80,35 +321,18 @@ pub fn execute<RW: Read + Write + Seek>(
             .expect("We sure that file in read mode")
             .borrow_mut();
 
+        // 3a. Verify the archive before anything is extracted.
+        verify_archive_hash(&mut *reader)?;
         reader.rewind().map_err(|_| Error::ResetCursorPosition)?;
 
         let mut archive = zip::ZipArchive::new(&mut *reader).map_err(|_| Error::OpenArchive)?;
+        check_limits(&mut archive, req.limits)?;
 
         let output_dir = req.output_dir_path.clone();
 
         // 4. prepare phase
-        let entities = (0..archive.len())
-            .filter_map(|i| {
-                let zip_file = archive.by_index(i).ok()?;
-                let mut full_path = output_dir.clone();
-
-                // Prevent zip slip attack
-                //
-                // Source: https://snyk.io/research/zip-slip-vulnerability
-                zip_file.enclosed_name().map(|path| {
-                    full_path.push(path);
-
-                    (full_path, i, zip_file.is_dir())
-                })
-            })
-            .filter(|(full_path, ..)| {
-                if let Some(on_zip_file) = req.on_zip_file.as_ref() {
-                    on_zip_file(full_path.clone())
-                } else {
-                    true
-                }
-            })
-            .collect::<Vec<_>>();
+        let (entities, sidecars) =
+            prepare_entities(&mut archive, &output_dir, req.on_zip_file.as_ref());
 
         let files_count = entities.len();
         if let Some(on_archive_info) = req.on_archive_info {
@@ -138,18 +362,20 @@ pub fn execute<RW: Read + Write + Seek>(
             .iter()
             .filter(|(_, _, is_dir)| !*is_dir)
             .try_for_each(|(full_path, i, _)| {
-                let mut zip_file = archive.by_index(*i).map_err(|_| Error::OpenArchivedFile)?;
+                let zip_file = archive.by_index(*i).map_err(|_| Error::OpenArchivedFile)?;
                 let file = stor
                     .create_file(full_path)
                     .or_else(|_| stor.write_file(full_path))
                     .map_err(Error::Storage)?;
-                std::io::copy(
-                    &mut zip_file,
-                    &mut *file.try_writer().map_err(Error::Storage)?.borrow_mut(),
-                )
-                .map_err(|_| Error::WriteData)?;
-                Ok(())
+                let mut writer = file.try_writer().map_err(Error::Storage)?.borrow_mut();
+
+                extract_file(zip_file, &req.filters, &mut *writer)
             })?;
+
+        // 6a. restore the data attached to each file
+        if req.streams.any() {
+            restore_sidecars(&mut archive, &output_dir, &entities, &sidecars, req.streams)?;
+        }
     }
 
     // 7. Finally eraze temp zip archive with zeros.
@@ -158,7 +384,7 @@ pub fn execute<RW: Read + Write + Seek>(
         writer: tmp_file
             .try_writer()
             .expect("We sure that file in write mode"),
-        passes: 1,
+        passes: NonZeroU8::new(1).unwrap(),
     })
     .ok();
 
@@ -169,6 +395,117 @@ pub fn execute<RW: Read + Write + Seek>(
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn archive_with_hash(archive: &[u8], hash: blake3::Hash) -> Vec<u8> {
+        let mut content = ARCHIVE_HASH_MAGIC.to_vec();
+        content.extend_from_slice(hash.as_bytes());
+        content.extend_from_slice(archive);
+        content
+    }
+
+    #[test]
+    fn should_verify_archive_hash() {
+        let content = archive_with_hash(b"archive", blake3::hash(b"archive"));
+
+        match verify_archive_hash(&mut Cursor::new(content)) {
+            Ok(()) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn should_reject_archive_with_wrong_hash() {
+        let content = archive_with_hash(b"archivf", blake3::hash(b"archive"));
+
+        match verify_archive_hash(&mut Cursor::new(content)) {
+            Err(Error::ArchiveHashMismatch) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn should_accept_archive_without_hash() {
+        match verify_archive_hash(&mut Cursor::new(b"PK\x03\x04".to_vec())) {
+            Ok(()) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    fn archive_with(files: &[(&str, Vec<u8>)]) -> zip::ZipArchive<Cursor<Vec<u8>>> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Zstd);
+
+        for (name, data) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+
+        let mut cursor = writer.finish().unwrap();
+        cursor.rewind().unwrap();
+        zip::ZipArchive::new(cursor).unwrap()
+    }
+
+    #[test]
+    fn should_accept_archive_within_limits() {
+        let mut archive = archive_with(&[("a", vec![1u8; 10]), ("b", vec![2u8; 10])]);
+        let limits = Limits {
+            max_size: Some(20),
+            max_entries: Some(2),
+            max_ratio: Some(DEFAULT_MAX_RATIO),
+        };
+
+        match check_limits(&mut archive, limits) {
+            Ok(()) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn should_reject_archive_over_limits() {
+        let mut archive = archive_with(&[("a", vec![1u8; 10]), ("b", vec![2u8; 10])]);
+
+        let limits = Limits {
+            max_entries: Some(1),
+            ..Limits::default()
+        };
+        match check_limits(&mut archive, limits) {
+            Err(Error::TooManyEntries(2, 1)) => {}
+            _ => unreachable!(),
+        }
+
+        let limits = Limits {
+            max_size: Some(19),
+            ..Limits::default()
+        };
+        match check_limits(&mut archive, limits) {
+            Err(Error::TooLarge(20, 19)) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn should_reject_pathological_compression_ratio() {
+        #[allow(clippy::cast_possible_truncation)]
+        let mut archive = archive_with(&[("zeros", vec![0u8; 16 * RATIO_MIN_SIZE as usize])]);
+
+        match check_limits(&mut archive, Limits::default()) {
+            Err(Error::SuspiciousRatio(name, _, DEFAULT_MAX_RATIO)) => assert_eq!(name, "zeros"),
+            _ => unreachable!(),
+        }
+
+        let limits = Limits {
+            max_ratio: None,
+            ..Limits::default()
+        };
+        match check_limits(&mut archive, limits) {
+            Ok(()) => {}
+            _ => unreachable!(),
+        }
+    }
+
     #[test]
     #[ignore = "not yet implemented"]
     fn should_unpack_encrypted_archive() {
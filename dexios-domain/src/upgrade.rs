@@ -0,0 +1,347 @@
+//! This provides functionality for upgrading a file that adheres to the Dexios format to the latest header version (`HEADER_VERSION`).
+//!
+//! The file is decrypted with the parameters of its own version, and encrypted again on another thread as it's decrypted (so the plaintext is only ever handed between them in memory).
+//!
+//! V5 files are rewrapped: their master key and keyslots are kept (see `Header::migrate`), so every key that unlocked the file still does. Older headers store their keys differently, so V1-V4 files are encrypted with a new master key, which is wrapped by the key that was provided (hashed with `hash_algorithm`).
+//!
+//! Options that the old header had are carried over, and memory mode files are written in stream mode.
+
+use std::cell::RefCell;
+use std::io::{Read, Seek, Write};
+
+use core::header::{HashingAlgorithm, Header, HeaderType, HeaderVersion, HEADER_VERSION};
+use core::primitives::{Mode, Padding, MASTER_KEY_LEN};
+use core::protected::Protected;
+
+use crate::pipe::{pipe, Counter};
+
+#[derive(Debug)]
+pub enum Error {
+    Read,
+    Seek,
+    HeaderDeserialize,
+    AlreadyLatest,
+    DetachedHeader,
+    IncorrectKey,
+    Unsupported,
+    Decrypt(crate::decrypt::Error),
+    Encrypt(crate::encrypt::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Read => f.write_str("Unable to read the file"),
+            Error::Seek => f.write_str("Unable to seek the data's cursor"),
+            Error::HeaderDeserialize => f.write_str("Unable to deserialize the header"),
+            Error::AlreadyLatest => {
+                write!(f, "This file already uses the latest header version ({HEADER_VERSION})")
+            }
+            Error::DetachedHeader => f.write_str(
+                "This is a header on its own, and the data that it belongs to is needed to upgrade it",
+            ),
+            Error::IncorrectKey => f.write_str("The provided key is incorrect"),
+            Error::Unsupported => {
+                f.write_str("This file uses options that the latest header version doesn't support")
+            }
+            Error::Decrypt(inner) => write!(f, "Unable to decrypt the file: {inner}"),
+            Error::Encrypt(inner) => write!(f, "Unable to encrypt the file again: {inner}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub struct Request<'a, R, W>
+where
+    R: Read + Seek + Send,
+    W: Write + Seek,
+{
+    pub reader: &'a RefCell<R>, // the encrypted file
+    pub writer: &'a RefCell<W>, // where the upgraded file is written
+    pub raw_key: Protected<Vec<u8>>,
+    /// This hashes the key for the new keyslot, which V5 files don't need (as their keyslots are kept)
+    pub hash_algorithm: HashingAlgorithm,
+}
+
+pub struct Response {
+    /// This is the version that the file was upgraded from
+    pub version: HeaderVersion,
+    /// This is true if the keyslots were kept, rather than replaced with a single new one
+    pub rewrapped: bool,
+}
+
+fn decrypt<R, W>(
+    reader: &RefCell<R>,
+    writer: &RefCell<W>,
+    master_key: Protected<[u8; MASTER_KEY_LEN]>,
+) -> Result<(), Error>
+where
+    R: Read + Seek,
+    W: Write,
+{
+    reader.borrow_mut().rewind().map_err(|_| Error::Seek)?;
+
+    crate::decrypt::execute(crate::decrypt::Request {
+        header_reader: None,
+        reader,
+        writer,
+        raw_key: Protected::new(Vec::new()),
+        master_key: Some(master_key),
+        identity: None,
+        on_decrypted_header: None,
+        on_file_info: None,
+    })
+    .map_err(Error::Decrypt)
+}
+
+// this decrypts the reader on another thread, while `encrypt` encrypts what's been decrypted so far
+fn pipeline<R>(
+    reader: &RefCell<R>,
+    master_key: Protected<[u8; MASTER_KEY_LEN]>,
+    len: u64,
+    encrypt: impl FnOnce(&mut crate::pipe::PipeReader) -> Result<(), crate::encrypt::Error>,
+) -> Result<(), Error>
+where
+    R: Read + Seek + Send,
+{
+    let (pipe_writer, mut pipe_reader) = pipe(len);
+    let mut input = reader.borrow_mut();
+    let input = &mut *input;
+
+    let (decrypted, encrypted) = std::thread::scope(|scope| {
+        let decrypting = scope
+            .spawn(move || decrypt(&RefCell::new(input), &RefCell::new(pipe_writer), master_key));
+
+        let encrypted = encrypt(&mut pipe_reader);
+        // the decrypting thread stops once nothing is reading from the pipe
+        drop(pipe_reader);
+
+        let decrypted = decrypting
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+
+        (decrypted, encrypted)
+    });
+
+    // if decryption failed, the encrypted data would be truncated (so that's checked first)
+    decrypted?;
+    encrypted.map_err(Error::Encrypt)
+}
+
+pub fn execute<R, W>(req: Request<'_, R, W>) -> Result<Response, Error>
+where
+    R: Read + Seek + Send,
+    W: Write + Seek,
+{
+    req.reader.borrow_mut().rewind().map_err(|_| Error::Seek)?;
+    let (header, _) =
+        Header::deserialize(&mut *req.reader.borrow_mut()).map_err(|_| Error::HeaderDeserialize)?;
+
+    let version = header.header_type.version;
+    if version >= HEADER_VERSION {
+        return Err(Error::AlreadyLatest);
+    }
+
+    // a header that was dumped can't be upgraded on its own, as the data that it belongs to needs to be encrypted again
+    let read = req
+        .reader
+        .borrow_mut()
+        .read(&mut [0u8; 1])
+        .map_err(|_| Error::Read)?;
+    if read == 0 {
+        return Err(Error::DetachedHeader);
+    }
+
+    // V1-V3 files don't have a master key, so this is the hashed key that the data was encrypted with
+    // it's only hashed once, and used for both passes
+    let master_key = core::key::decrypt_master_key(req.raw_key.clone(), &header)
+        .map_err(|_| Error::IncorrectKey)?;
+
+    // the new padding depends on the plaintext's length, which is only known once it's been decrypted
+    let len = if header.padding == Padding::Padme {
+        let counter = RefCell::new(Counter(0));
+        decrypt(req.reader, &counter, master_key.clone())?;
+        counter.into_inner().0
+    } else {
+        0
+    };
+
+    // memory mode is only readable by older versions, and the whole file had to fit in memory
+    let mode = if header.header_type.mode == Mode::DerivedStreamMode {
+        Mode::DerivedStreamMode
+    } else {
+        Mode::StreamMode
+    };
+
+    if version == HeaderVersion::V5 {
+        let mut new_header = header.migrate().map_err(|_| Error::Unsupported)?;
+        new_header.header_type.mode = mode;
+        // the new nonce is random, so the file's keys no longer come from its contents alone
+        new_header.convergent = false;
+        // every new stream mode header separates the payload, header and MAC keys (see `core::subkeys`)
+        new_header.subkeys = true;
+
+        pipeline(req.reader, master_key.clone(), len, |pipe_reader| {
+            crate::encrypt::execute_with_master_key(new_header, master_key, pipe_reader, req.writer)
+        })?;
+
+        return Ok(Response {
+            version,
+            rewrapped: true,
+        });
+    }
+
+    // older files never have recipients, tokens, a manifest or file info, so there's nothing else to carry over
+    pipeline(req.reader, master_key, len, |pipe_reader| {
+        crate::encrypt::execute(crate::encrypt::Request {
+            reader: &RefCell::new(pipe_reader),
+            writer: req.writer,
+            header_writer: None,
+            raw_key: req.raw_key,
+            header_type: HeaderType {
+                version: HEADER_VERSION,
+                algorithm: header.header_type.algorithm,
+                mode,
+            },
+            hashing_algorithm: req.hash_algorithm,
+            compression: header.compression,
+            block_size: header.block_size,
+            padding: header.padding,
+            convergent: false,
+            recipients: Vec::new(),
+            tokens: Vec::new(),
+            extra_keys: Vec::new(),
+            metadata: header.metadata.clone(),
+            mac: header.mac,
+            digest: header.digest.is_some(),
+            seekable: header.seekable,
+            keyfile_hash: header.keyfile_hash,
+            counter: header.counter,
+            two_factor: header.two_factor,
+            manifest: None,
+            file_info: None,
+            fields: header.fields.clone(),
+        })
+    })?;
+
+    Ok(Response {
+        version,
+        rewrapped: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    use core::primitives::Algorithm;
+
+    const PASSWORD: &[u8; 8] = b"12345678";
+    const EXTRA_PASSWORD: &[u8; 8] = b"87654321";
+
+    fn encrypt(version: HeaderVersion, extra_keys: Vec<Protected<Vec<u8>>>) -> Vec<u8> {
+        let encrypted = RefCell::new(Cursor::new(Vec::new()));
+        crate::encrypt::execute(crate::encrypt::Request {
+            header_type: HeaderType {
+                version,
+                mode: Mode::StreamMode,
+                algorithm: Algorithm::XChaCha20Poly1305,
+            },
+            // V4 keyslots don't store their KDF, as it's always this version's
+            hashing_algorithm: if version == HeaderVersion::V4 {
+                HashingAlgorithm::Blake3Balloon(4)
+            } else {
+                HashingAlgorithm::Blake3Balloon(5)
+            },
+            extra_keys,
+            ..crate::encrypt::tests::request(
+                &RefCell::new(Cursor::new(b"Hello world".to_vec())),
+                &encrypted,
+            )
+        })
+        .unwrap();
+        encrypted.into_inner().into_inner()
+    }
+
+    fn decrypt_with(content: &[u8], password: &[u8]) -> Result<Vec<u8>, crate::decrypt::Error> {
+        let output = RefCell::new(Vec::new());
+        crate::decrypt::execute(crate::decrypt::Request {
+            header_reader: None,
+            reader: &RefCell::new(Cursor::new(content.to_vec())),
+            writer: &output,
+            raw_key: Protected::new(password.to_vec()),
+            master_key: None,
+            identity: None,
+            on_decrypted_header: None,
+            on_file_info: None,
+        })?;
+        Ok(output.into_inner())
+    }
+
+    fn upgrade(content: &[u8], password: &[u8]) -> Result<(Response, Vec<u8>), Error> {
+        let output = RefCell::new(Cursor::new(Vec::new()));
+        let response = execute(Request {
+            reader: &RefCell::new(Cursor::new(content.to_vec())),
+            writer: &output,
+            raw_key: Protected::new(password.to_vec()),
+            hash_algorithm: HashingAlgorithm::Blake3Balloon(5),
+        })?;
+        Ok((response, output.into_inner().into_inner()))
+    }
+
+    fn version(content: &[u8]) -> HeaderVersion {
+        Header::deserialize(&mut Cursor::new(content))
+            .unwrap()
+            .0
+            .header_type
+            .version
+    }
+
+    #[test]
+    fn should_upgrade_a_v4_file() {
+        let (response, upgraded) =
+            upgrade(&encrypt(HeaderVersion::V4, Vec::new()), PASSWORD).unwrap();
+
+        assert!(response.version == HeaderVersion::V4);
+        assert!(!response.rewrapped);
+        assert!(version(&upgraded) == HEADER_VERSION);
+        assert_eq!(
+            decrypt_with(&upgraded, PASSWORD).unwrap(),
+            b"Hello world".to_vec()
+        );
+    }
+
+    #[test]
+    fn should_keep_every_keyslot_of_a_v5_file() {
+        let encrypted = encrypt(
+            HeaderVersion::V5,
+            vec![Protected::new(EXTRA_PASSWORD.to_vec())],
+        );
+        let (response, upgraded) = upgrade(&encrypted, EXTRA_PASSWORD).unwrap();
+
+        assert!(response.rewrapped);
+        let (header, _) = Header::deserialize(&mut Cursor::new(&upgraded)).unwrap();
+        assert!(header.header_type.version == HEADER_VERSION);
+        assert!(header.subkeys);
+        for password in [&PASSWORD[..], &EXTRA_PASSWORD[..]] {
+            assert_eq!(
+                decrypt_with(&upgraded, password).unwrap(),
+                b"Hello world".to_vec()
+            );
+        }
+    }
+
+    #[test]
+    fn should_not_upgrade_the_latest_version_or_with_the_wrong_key() {
+        assert!(matches!(
+            upgrade(&encrypt(HEADER_VERSION, Vec::new()), PASSWORD),
+            Err(Error::AlreadyLatest)
+        ));
+        assert!(matches!(
+            upgrade(&encrypt(HeaderVersion::V4, Vec::new()), b"wrong password"),
+            Err(Error::IncorrectKey)
+        ));
+    }
+}
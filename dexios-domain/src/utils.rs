@@ -1,5 +1,62 @@
 // TODO(pleshevskiy): dedup these utils
 
+#[must_use]
+pub fn hex_encode(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+
+    bytes.iter().fold(String::new(), |mut acc, b| {
+        let _ = write!(acc, "{b:02x}");
+        acc
+    })
+}
+
+/// This formats a UNIX timestamp (in seconds) as a UTC date and time, e.g. "2022-09-30 14:05:00 UTC"
+#[must_use]
+pub fn format_timestamp(secs: u64) -> String {
+    let (year, month, day) = civil_date(secs / 86_400);
+    let time = secs % 86_400;
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// This converts days since the UNIX epoch to a (year, month, day) civil date
+#[must_use]
+pub(crate) fn civil_date(days: u64) -> (i64, i64, i64) {
+    let days = i64::try_from(days).unwrap_or(i64::MAX / 2);
+
+    // see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+pub use test::gen_master_key;
+#[cfg(test)]
+pub use test::gen_nonce;
+#[cfg(test)]
+pub use test::gen_salt;
+
+#[cfg(not(test))]
+pub use core::primitives::gen_master_key;
+#[cfg(not(test))]
+pub use core::primitives::gen_nonce;
+#[cfg(not(test))]
+pub use core::primitives::gen_salt;
+
 #[cfg(test)]
 mod test {
     use core::primitives::{get_nonce_len, Algorithm, Mode, MASTER_KEY_LEN, SALT_LEN};
@@ -31,26 +88,13 @@ mod test {
         StdRng::seed_from_u64(MASTER_KEY_SEED).fill_bytes(&mut master_key);
         Protected::new(master_key)
     }
-}
 
-#[must_use]
-pub fn hex_encode(bytes: &[u8]) -> String {
-    bytes
-        .iter()
-        .map(|b| format!("{b:02x}"))
-        .collect::<String>()
-}
+    #[test]
+    fn should_format_timestamps() {
+        use super::format_timestamp;
 
-#[cfg(test)]
-pub use test::gen_master_key;
-#[cfg(test)]
-pub use test::gen_nonce;
-#[cfg(test)]
-pub use test::gen_salt;
-
-#[cfg(not(test))]
-pub use core::primitives::gen_master_key;
-#[cfg(not(test))]
-pub use core::primitives::gen_nonce;
-#[cfg(not(test))]
-pub use core::primitives::gen_salt;
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(format_timestamp(1_664_546_700), "2022-09-30 14:05:00 UTC");
+    }
+}
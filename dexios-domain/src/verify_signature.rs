@@ -0,0 +1,124 @@
+//! This provides functionality for verifying an encrypted file's detached Ed25519 signature.
+
+use core::signature::{Signature, SigningPublicKey};
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{Read, Seek};
+
+use crate::sign;
+
+#[derive(Debug)]
+pub enum Error {
+    Digest(sign::Error),
+    WrongSigner,
+    InvalidSignature,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Digest(inner) => write!(f, "Unable to hash the file: {inner}"),
+            Error::WrongSigner => f.write_str("The file was signed by a different key"),
+            Error::InvalidSignature => {
+                f.write_str("The signature is invalid - the file may have been modified")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub struct Request<'a, R>
+where
+    R: Read + Seek,
+{
+    pub reader: &'a RefCell<R>,
+    pub header_reader: Option<&'a RefCell<R>>,
+    pub signature: &'a Signature,
+    /// This is the signer's public key, which must be obtained separately (not from the signature)
+    pub public_key: &'a SigningPublicKey,
+}
+
+pub fn execute<R>(req: Request<'_, R>) -> Result<(), Error>
+where
+    R: Read + Seek,
+{
+    if req.signature.signer() != req.public_key {
+        return Err(Error::WrongSigner);
+    }
+
+    let digest = sign::digest(req.reader, req.header_reader).map_err(Error::Digest)?;
+
+    req.public_key
+        .verify(&digest, req.signature)
+        .map_err(|_| Error::InvalidSignature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::signature::SigningSecretKey;
+    use std::io::Cursor;
+
+    fn sign(signing_key: &SigningSecretKey, content: &[u8]) -> Signature {
+        sign::execute(sign::Request {
+            reader: &RefCell::new(Cursor::new(content.to_vec())),
+            header_reader: None,
+            signing_key,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn should_verify_signature() {
+        let signing_key = SigningSecretKey::generate();
+        let signature = sign(&signing_key, b"encrypted content");
+
+        let req = Request {
+            reader: &RefCell::new(Cursor::new(b"encrypted content".to_vec())),
+            header_reader: None,
+            signature: &signature,
+            public_key: &signing_key.public_key(),
+        };
+
+        match execute(req) {
+            Ok(()) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn should_reject_modified_content() {
+        let signing_key = SigningSecretKey::generate();
+        let signature = sign(&signing_key, b"encrypted content");
+
+        let req = Request {
+            reader: &RefCell::new(Cursor::new(b"encrypted contenu".to_vec())),
+            header_reader: None,
+            signature: &signature,
+            public_key: &signing_key.public_key(),
+        };
+
+        match execute(req) {
+            Err(Error::InvalidSignature) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn should_reject_signature_from_another_key() {
+        let signature = sign(&SigningSecretKey::generate(), b"encrypted content");
+
+        let req = Request {
+            reader: &RefCell::new(Cursor::new(b"encrypted content".to_vec())),
+            header_reader: None,
+            signature: &signature,
+            public_key: &SigningSecretKey::generate().public_key(),
+        };
+
+        match execute(req) {
+            Err(Error::WrongSigner) => {}
+            _ => unreachable!(),
+        }
+    }
+}
@@ -4,6 +4,7 @@ use dexios_domain::storage::*;
 use std::fs;
 use std::io::{Read, Write};
 use std::path::PathBuf;
+use std::time::SystemTime;
 
 #[test]
 fn should_create_a_new_file() {
@@ -24,7 +25,7 @@ fn should_throw_an_error_if_file_already_exist() {
     add_hello_txt(&stor).unwrap();
 
     match stor.create_file("hello_2.txt") {
-        Err(Error::CreateFile) => {}
+        Err(Error::AlreadyExists) => {}
         _ => unreachable!(),
     }
 }
@@ -275,3 +276,72 @@ fn should_include_hidden_files_names() {
         _ => unreachable!(),
     }
 }
+
+#[test]
+fn should_securely_remove_a_file() {
+    let stor = TestFileStorage::new(16);
+    add_hello_txt(&stor).unwrap();
+
+    let file = stor.write_file("hello_16.txt").unwrap();
+
+    match stor.remove_file_securely(file) {
+        Ok(()) => match fs::File::open("hello_16.txt") {
+            Err(_) => {}
+            _ => unreachable!(),
+        },
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn should_clear_name_size_and_times_before_removing_a_file() {
+    let stor = TestFileStorage::new(18);
+    add_hello_txt(&stor).unwrap();
+
+    let file = stor.write_file("hello_18.txt").unwrap();
+
+    match stor.scrub_file(file) {
+        Ok(path) => {
+            assert!(fs::metadata("hello_18.txt").is_err());
+            assert_ne!(path.file_name().unwrap(), "hello_18.txt");
+
+            let metadata = fs::metadata(&path).unwrap();
+            assert_eq!(metadata.len(), 0);
+            assert_eq!(metadata.modified().unwrap(), SystemTime::UNIX_EPOCH);
+
+            fs::remove_file(path).unwrap();
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn should_skip_special_files_in_dir() {
+    let stor = TestFileStorage::new(17);
+    add_bar_foo_folder(&stor).unwrap();
+    let _listener = std::os::unix::net::UnixListener::bind("bar_17/foo/socket").unwrap();
+
+    let file = stor.read_file("bar_17/").unwrap();
+
+    match stor.read_dir_with(&file, SpecialFiles::Skip) {
+        Ok((files, skipped)) => {
+            assert_eq!(files.len(), 6);
+            assert_eq!(
+                skipped,
+                vec![SkippedEntry {
+                    path: PathBuf::from("bar_17/foo/socket"),
+                    kind: EntryKind::Socket,
+                }]
+            );
+        }
+        _ => unreachable!(),
+    }
+
+    match stor.read_dir_with(&file, SpecialFiles::Refuse) {
+        Err(Error::SpecialFile(path, EntryKind::Socket)) => {
+            assert_eq!(path, PathBuf::from("bar_17/foo/socket"));
+        }
+        _ => unreachable!(),
+    }
+}
@@ -0,0 +1,416 @@
+//! Python bindings for `dexios-core`, built with PyO3.
+//!
+//! This exposes header parsing, along with stream encryption/decryption that works with any Python file-like object (anything with `read`, `write` and `seek` methods).
+//!
+//! # Examples
+//!
+//! ```python,ignore
+//! import dexios
+//!
+//! with open("secret.txt", "rb") as src, open("secret.txt.dx", "wb") as dst:
+//!     dexios.encrypt(src, dst, b"secure key")
+//!
+//! with open("secret.txt.dx", "rb") as src:
+//!     header = dexios.parse_header(src)
+//!     print(header.version, header.algorithm)
+//! ```
+
+#![forbid(unsafe_code)]
+
+use std::cell::RefCell;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use dexios_core::header::{HashingAlgorithm, HeaderType, HEADER_VERSION};
+use dexios_core::primitives::{
+    Algorithm, Compression, Mode, Padding, StreamCounter, ALGORITHMS, BLOCK_SIZE,
+};
+use dexios_core::protected::Protected;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+create_exception!(dexios, DexiosError, PyException);
+
+/// This wraps a Python file-like object, so that it may be used anywhere `Read`, `Write` and `Seek` are expected
+struct PyFile(PyObject);
+
+impl Read for PyFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        Python::with_gil(|py| {
+            let data = self
+                .0
+                .call_method1(py, "read", (buf.len(),))
+                .map_err(std::io::Error::other)?;
+            let bytes = data.downcast_bound::<PyBytes>(py).map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "read() must return bytes (is the file opened in binary mode?)",
+                )
+            })?;
+            let bytes = bytes.as_bytes();
+            if bytes.len() > buf.len() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "read() returned more bytes than were requested",
+                ));
+            }
+            buf[..bytes.len()].copy_from_slice(bytes);
+            Ok(bytes.len())
+        })
+    }
+}
+
+impl Write for PyFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Python::with_gil(|py| {
+            let written = self
+                .0
+                .call_method1(py, "write", (PyBytes::new(py, buf),))
+                .map_err(std::io::Error::other)?;
+            // raw (unbuffered) files may return `None` or a short write
+            Ok(written
+                .extract::<Option<usize>>(py)
+                .ok()
+                .flatten()
+                .unwrap_or(buf.len()))
+        })
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Python::with_gil(|py| {
+            if self
+                .0
+                .bind(py)
+                .hasattr("flush")
+                .map_err(std::io::Error::other)?
+            {
+                self.0
+                    .call_method0(py, "flush")
+                    .map_err(std::io::Error::other)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Seek for PyFile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let (offset, whence) = match pos {
+            SeekFrom::Start(n) => (i64::try_from(n).map_err(std::io::Error::other)?, 0),
+            SeekFrom::Current(n) => (n, 1),
+            SeekFrom::End(n) => (n, 2),
+        };
+
+        Python::with_gil(|py| {
+            self.0
+                .call_method1(py, "seek", (offset, whence))
+                .and_then(|pos| pos.extract::<u64>(py))
+                .map_err(std::io::Error::other)
+        })
+    }
+}
+
+/// A deserialized Dexios header
+#[pyclass(frozen, module = "dexios")]
+struct Header {
+    inner: dexios_core::header::Header,
+}
+
+#[pymethods]
+impl Header {
+    #[getter]
+    fn version(&self) -> String {
+        self.inner.header_type.version.to_string()
+    }
+
+    #[getter]
+    fn algorithm(&self) -> String {
+        self.inner.header_type.algorithm.to_string()
+    }
+
+    #[getter]
+    fn mode(&self) -> String {
+        self.inner.header_type.mode.to_string()
+    }
+
+    #[getter]
+    fn compression(&self) -> String {
+        self.inner.compression.to_string()
+    }
+
+    #[getter]
+    fn block_size(&self) -> usize {
+        self.inner.block_size
+    }
+
+    #[getter]
+    fn padding(&self) -> String {
+        self.inner.padding.to_string()
+    }
+
+    #[getter]
+    fn convergent(&self) -> bool {
+        self.inner.convergent
+    }
+
+    /// Whether the ciphertext is followed by a MAC footer
+    #[getter]
+    fn mac(&self) -> bool {
+        self.inner.mac
+    }
+
+    /// Whether an encrypted digest of the plaintext is stored, so the output can be verified after decryption
+    #[getter]
+    fn has_digest(&self) -> bool {
+        self.inner.digest.is_some()
+    }
+
+    /// Whether a chunk table follows the ciphertext, so any part of it can be decrypted on its own
+    #[getter]
+    fn seekable(&self) -> bool {
+        self.inner.seekable
+    }
+
+    /// When the data was encrypted (seconds since the UNIX epoch), if it was recorded
+    #[getter]
+    fn created(&self) -> Option<u64> {
+        self.inner.metadata.as_ref().map(|m| m.created)
+    }
+
+    /// The program that encrypted the data, if it was recorded
+    #[getter]
+    fn created_with(&self) -> Option<String> {
+        self.inner.metadata.as_ref().map(|m| m.version.clone())
+    }
+
+    #[getter]
+    fn nonce<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.inner.nonce)
+    }
+
+    #[getter]
+    fn size(&self) -> u64 {
+        self.inner.get_size()
+    }
+
+    #[getter]
+    fn keyslots(&self) -> Vec<String> {
+        self.inner
+            .keyslots
+            .as_ref()
+            .map(|keyslots| {
+                keyslots
+                    .iter()
+                    .map(|k| {
+                        if k.is_recipient() {
+                            "Recipient (X25519 + ML-KEM-768)".to_string()
+                        } else {
+                            k.hash_algorithm.to_string()
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "<dexios.Header version={} algorithm={} mode={}>",
+            self.inner.header_type.version,
+            self.inner.header_type.algorithm,
+            self.inner.header_type.mode
+        )
+    }
+}
+
+fn algorithm_from_str(name: &str) -> PyResult<Algorithm> {
+    ALGORITHMS
+        .into_iter()
+        .find(|a| {
+            a.to_string()
+                .replace(' ', "-")
+                .eq_ignore_ascii_case(&name.replace(' ', "-"))
+        })
+        .ok_or_else(|| PyValueError::new_err(format!("Unknown algorithm: {name}")))
+}
+
+/// Deserialize the header from the start of a file-like object
+///
+/// The file's cursor is left directly after the header.
+#[pyfunction]
+fn parse_header(file: PyObject) -> PyResult<Header> {
+    let mut file = PyFile(file);
+    let (inner, _) = dexios_core::header::Header::deserialize(&mut file)
+        .map_err(|e| DexiosError::new_err(e.to_string()))?;
+    Ok(Header { inner })
+}
+
+/// Encrypt everything from `reader` into `writer`, in stream mode
+///
+/// If `header_writer` is provided, the header is written there instead of to the start of `writer`.
+///
+/// If `mac` is set, a MAC of the whole ciphertext is appended, so any modifications are detected before decryption starts.
+///
+/// If `digest` is set, an encrypted digest of the plaintext is stored in the header, and the output is checked against it when it's decrypted.
+///
+/// If `seekable` is set, a chunk table is appended to the ciphertext, so that any part of it can be decrypted on its own.
+#[pyfunction]
+#[pyo3(signature = (reader, writer, key, algorithm = "XChaCha20-Poly1305", header_writer = None, mac = false, digest = false, seekable = false))]
+#[allow(clippy::too_many_arguments)]
+fn encrypt(
+    reader: PyObject,
+    writer: PyObject,
+    key: &[u8],
+    algorithm: &str,
+    header_writer: Option<PyObject>,
+    mac: bool,
+    digest: bool,
+    seekable: bool,
+) -> PyResult<()> {
+    let algorithm = algorithm_from_str(algorithm)?;
+    let raw_key = Protected::new(key.to_vec());
+
+    let reader = RefCell::new(PyFile(reader));
+    let writer = RefCell::new(PyFile(writer));
+    let header_writer = header_writer.map(|w| RefCell::new(PyFile(w)));
+
+    dexios_domain::encrypt::execute(dexios_domain::encrypt::Request {
+        reader: &reader,
+        writer: &writer,
+        header_writer: header_writer.as_ref(),
+        raw_key,
+        header_type: HeaderType {
+            version: HEADER_VERSION,
+            mode: Mode::StreamMode,
+            algorithm,
+        },
+        hashing_algorithm: HashingAlgorithm::Blake3Balloon(5),
+        compression: Compression::None,
+        block_size: BLOCK_SIZE,
+        padding: Padding::None,
+        convergent: false,
+        recipients: Vec::new(),
+        tokens: Vec::new(),
+        extra_keys: Vec::new(),
+        metadata: Some(dexios_core::header::Metadata::new(concat!(
+            "dexios-py ",
+            env!("CARGO_PKG_VERSION")
+        ))),
+        mac,
+        digest,
+        seekable,
+        keyfile_hash: false,
+        counter: StreamCounter::Le31,
+        two_factor: false,
+        manifest: None,
+        file_info: None,
+        fields: Vec::new(),
+    })
+    .map_err(|e| DexiosError::new_err(e.to_string()))?;
+
+    writer.borrow_mut().flush()?;
+    Ok(())
+}
+
+/// Decrypt everything from `reader` into `writer`
+///
+/// If `header_reader` is provided, the header is read from there instead of from the start of `reader`.
+#[pyfunction]
+#[pyo3(signature = (reader, writer, key, header_reader = None))]
+fn decrypt(
+    reader: PyObject,
+    writer: PyObject,
+    key: &[u8],
+    header_reader: Option<PyObject>,
+) -> PyResult<()> {
+    let raw_key = Protected::new(key.to_vec());
+
+    let reader = RefCell::new(PyFile(reader));
+    let writer = RefCell::new(PyFile(writer));
+    let header_reader = header_reader.map(|r| RefCell::new(PyFile(r)));
+
+    dexios_domain::decrypt::execute(dexios_domain::decrypt::Request {
+        header_reader: header_reader.as_ref(),
+        reader: &reader,
+        writer: &writer,
+        raw_key,
+        master_key: None,
+        identity: None,
+        on_decrypted_header: None,
+        on_file_info: None,
+    })
+    .map_err(|e| DexiosError::new_err(e.to_string()))?;
+
+    writer.borrow_mut().flush()?;
+    Ok(())
+}
+
+#[pymodule]
+fn dexios(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("DexiosError", m.py().get_type::<DexiosError>())?;
+    m.add_class::<Header>()?;
+    m.add_function(wrap_pyfunction!(parse_header, m)?)?;
+    m.add_function(wrap_pyfunction!(encrypt, m)?)?;
+    m.add_function(wrap_pyfunction!(decrypt, m)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::ffi::c_str;
+    use pyo3::types::PyModule;
+
+    fn bytes_io(py: Python<'_>, content: &[u8]) -> PyObject {
+        py.import("io")
+            .unwrap()
+            .call_method1("BytesIO", (PyBytes::new(py, content),))
+            .unwrap()
+            .unbind()
+    }
+
+    #[test]
+    fn should_encrypt_and_decrypt_file_like_objects() {
+        Python::with_gil(|py| {
+            let plaintext = bytes_io(py, b"Hello world");
+            let ciphertext = bytes_io(py, b"");
+            encrypt(
+                plaintext,
+                ciphertext.clone_ref(py),
+                b"12345678",
+                "XChaCha20-Poly1305",
+                None,
+                false,
+                false,
+                false,
+            )
+            .unwrap();
+
+            ciphertext.call_method1(py, "seek", (0,)).unwrap();
+            let output = bytes_io(py, b"");
+            decrypt(ciphertext, output.clone_ref(py), b"12345678", None).unwrap();
+
+            let output = output.call_method0(py, "getvalue").unwrap();
+            assert_eq!(output.extract::<Vec<u8>>(py).unwrap(), b"Hello world");
+        });
+    }
+
+    #[test]
+    fn should_refuse_reads_longer_than_requested() {
+        Python::with_gil(|py| {
+            let module = PyModule::from_code(
+                py,
+                c_str!("class Greedy:\n    def read(self, n):\n        return b'x' * (n + 1)\n"),
+                c_str!("greedy.py"),
+                c_str!("greedy"),
+            )
+            .unwrap();
+            let mut file = PyFile(module.getattr("Greedy").unwrap().call0().unwrap().unbind());
+
+            let mut buf = [0u8; 4];
+            let err = file.read(&mut buf).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        });
+    }
+}
@@ -1,12 +1,18 @@
 use clap::{Arg, Command};
 
+pub mod examples;
 pub mod prompt;
 
+// it returns the ArgMatches so that a match statement can send everything to the correct place
+pub fn get_matches() -> clap::ArgMatches {
+    let examples = examples::help_texts();
+    examples::add_help(build(), &examples).get_matches()
+}
+
 // this defines all of the clap subcommands and arguments
 // it's long, and clunky, but i feel that's just the nature of the clap builder api
-// it returns the ArgMatches so that a match statement can send everything to the correct place
 #[allow(clippy::too_many_lines)]
-pub fn get_matches() -> clap::ArgMatches {
+pub fn build<'a>() -> Command<'a> {
     let encrypt = Command::new("encrypt")
         .short_flag('e')
         .about("Encrypt a file")
@@ -21,16 +27,99 @@ pub fn get_matches() -> clap::ArgMatches {
             Arg::new("output")
                 .value_name("output")
                 .takes_value(true)
-                .required(true)
+                .required_unless_present("output-template")
                 .help("The output file"),
         )
+        .arg(
+            Arg::new("output-template")
+                .long("output-template")
+                .value_name("template")
+                .takes_value(true)
+                .conflicts_with("output")
+                .help("Name the output file with a template, e.g. '{name}-{date}-{hash8}.enc' (placeholders: name, stem, ext, date, time, timestamp, hash/hashN, n)"),
+        )
         .arg(
             Arg::new("keyfile")
                 .short('k')
                 .long("keyfile")
+                .multiple_occurrences(true)
+                .value_name("file")
+                .takes_value(true)
+                .help("Use a keyfile instead of a password (repeat to require several keyfiles)"),
+        )
+        .arg(
+            Arg::new("keyfile-fd")
+                .long("keyfile-fd")
+                .value_name("fd")
+                .takes_value(true)
+                .value_parser(clap::value_parser!(u32))
+                .conflicts_with_all(&["keyfile", "password-command"])
+                .help("Read the keyfile from an inherited file descriptor (a keyfile may also be set with DEXIOS_KEYFILE)"),
+        )
+        .arg(
+            Arg::new("password-command")
+                .long("password-command")
+                .value_name("command")
+                .takes_value(true)
+                .conflicts_with("keyfile")
+                .help("Use the output of a command as the key, run without a shell (e.g. 'pass show dexios')"),
+        )
+        .arg(
+            Arg::new("key-command")
+                .long("key-command")
+                .value_name("command")
+                .takes_value(true)
+                .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file", "password-fd"])
+                .help("Use the output of a program as the key, without a shell (e.g. 'pass show backups/dexios')"),
+        )
+        .arg(
+            Arg::new("password-file")
+                .long("password-file")
                 .value_name("file")
                 .takes_value(true)
-                .help("Use a keyfile instead of a password"),
+                .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command"])
+                .help("Read the password from a file (or STDIN with '-'), without its trailing newline"),
+        )
+        .arg(
+            Arg::new("password-fd")
+                .long("password-fd")
+                .value_name("fd")
+                .takes_value(true)
+                .value_parser(clap::value_parser!(u32))
+                .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file"])
+                .help("Read the password from an inherited file descriptor, without its trailing newline"),
+        )
+        .arg(
+            Arg::new("two-factor")
+                .long("two-factor")
+                .takes_value(false)
+                .conflicts_with_all(&["password-command", "password-file", "password-fd", "autogenerate", "yubikey"])
+                .help("Require a password as well as the keyfile (the password is read from DEXIOS_KEY, or asked for)"),
+        )
+        .arg(
+            Arg::new("strict")
+                .long("strict")
+                .takes_value(false)
+                .help("Refuse weak passwords, rather than asking whether to use them"),
+        )
+        .arg(
+            Arg::new("move-to")
+                .long("move-to")
+                .value_name("path")
+                .takes_value(true)
+                .help("Move the output here once it's complete (e.g. from local scratch space to a NAS), verifying the copy before the original is removed"),
+        )
+        .arg(
+            Arg::new("yubikey")
+                .long("yubikey")
+                .value_name("slot")
+                .min_values(0)
+                .value_parser(["1", "2"])
+                .default_missing_value("2")
+                .takes_value(true)
+                .require_equals(true)
+                .conflicts_with("keyfile")
+                .help("Use a YubiKey's HMAC-SHA1 challenge-response slot as the key (default is slot 2)"),
         )
         .arg(
             Arg::new("erase")
@@ -55,6 +144,39 @@ pub fn get_matches() -> clap::ArgMatches {
                 .takes_value(false)
                 .help("Use argon2id for password hashing"),
         )
+        .arg(
+            Arg::new("kdf")
+                .long("kdf")
+                .value_name("kdf")
+                .takes_value(true)
+                .value_parser(["argon2id", "blake3-balloon", "scrypt"])
+                .conflicts_with("argon")
+                .help("The KDF to use for password hashing (default is blake3-balloon)"),
+        )
+        .arg(
+            Arg::new("kdf-memory")
+                .long("kdf-memory")
+                .value_name("MiB")
+                .takes_value(true)
+                .value_parser(clap::value_parser!(u32).range(1..=4096))
+                .help("The amount of memory the password hashing uses, in MiB, up to 4096 (stored in the header)"),
+        )
+        .arg(
+            Arg::new("kdf-iterations")
+                .long("kdf-iterations")
+                .value_name("#")
+                .takes_value(true)
+                .value_parser(clap::value_parser!(u32).range(1..=255))
+                .help("The number of iterations the password hashing uses (stored in the header)"),
+        )
+        .arg(
+            Arg::new("kdf-parallelism")
+                .long("kdf-parallelism")
+                .value_name("#")
+                .takes_value(true)
+                .value_parser(clap::value_parser!(u32).range(1..=255))
+                .help("The number of lanes the password hashing uses (stored in the header)"),
+        )
         .arg(
             Arg::new("autogenerate")
                 .long("auto")
@@ -69,9 +191,31 @@ pub fn get_matches() -> clap::ArgMatches {
         .arg(
             Arg::new("header")
                 .long("header")
+                .visible_alias("detached-header")
                 .value_name("file")
                 .takes_value(true)
-                .help("Store the header separately from the file"),
+                .help("Write the header to this file instead, so it's never stored alongside the data"),
+        )
+        .arg(
+            Arg::new("header-placeholder")
+                .long("header-placeholder")
+                .takes_value(false)
+                .requires("header")
+                .help("Start the encrypted file with random bytes in place of the detached header (they're skipped when it's decrypted with --header)"),
+        )
+        .arg(
+            Arg::new("backup-header")
+                .long("backup-header")
+                .takes_value(false)
+                .conflicts_with("header")
+                .help("Also store a copy of the header at the end of the file, which is used if the header at the start is damaged (see `header recover`)"),
+        )
+        .arg(
+            Arg::new("store-metadata")
+                .long("store-metadata")
+                .takes_value(false)
+                .conflicts_with("convergent")
+                .help("Store the file's name, modification time and permissions in the header, encrypted, so `decrypt --restore-metadata` can restore them"),
         )
         .arg(
             Arg::new("force")
@@ -80,11 +224,195 @@ pub fn get_matches() -> clap::ArgMatches {
                 .takes_value(false)
                 .help("Force all actions"),
         )
+        .arg(
+            Arg::new("algorithm")
+                .long("algorithm")
+                .value_name("algorithm")
+                .takes_value(true)
+                .help("The AEAD to encrypt with: xchacha20-poly1305 (default), aes-256-gcm, deoxys-ii-256, aegis-256, chacha20-poly1305 or ascon-128a"),
+        )
         .arg(
             Arg::new("aes")
                 .long("aes")
                 .takes_value(false)
-                .help("Use AES-256-GCM for encryption"),
+                .hide(true)
+                .conflicts_with("algorithm")
+                .help("Use AES-256-GCM for encryption (the same as --algorithm aes-256-gcm)"),
+        )
+        .arg(
+            Arg::new("aegis")
+                .long("aegis")
+                .takes_value(false)
+                .hide(true)
+                .conflicts_with_all(&["aes", "algorithm"])
+                .help("Use AEGIS-256 for encryption (the same as --algorithm aegis-256)"),
+        )
+        .arg(
+            Arg::new("chacha20")
+                .long("chacha20")
+                .takes_value(false)
+                .hide(true)
+                .conflicts_with_all(&["aes", "aegis", "algorithm"])
+                .help("Use ChaCha20-Poly1305 for encryption (the same as --algorithm chacha20-poly1305)"),
+        )
+        .arg(
+            Arg::new("ascon")
+                .long("ascon")
+                .takes_value(false)
+                .hide(true)
+                .conflicts_with_all(&["aes", "aegis", "chacha20", "algorithm"])
+                .help("Use Ascon-128a for encryption (the same as --algorithm ascon-128a)"),
+        )
+        .arg(
+            Arg::new("compress")
+                .long("compress")
+                .value_name("zstd:level")
+                .takes_value(true)
+                .require_equals(true)
+                .min_values(0)
+                .default_missing_value("zstd:3")
+                .help("Compress each block with zstd before it's encrypted (default level is 3)"),
+        )
+        .arg(
+            Arg::new("pad-blocks")
+                .long("pad-blocks")
+                .takes_value(false)
+                .requires("compress")
+                .help("Pad each compressed block, so block sizes don't reveal how compressible the data is"),
+        )
+        .arg(
+            Arg::new("misuse-resistant")
+                .long("misuse-resistant")
+                .takes_value(false)
+                .help("Encrypt each block with its own derived subkey (safe even if the header is reused)"),
+        )
+        .arg(
+            Arg::new("mode")
+                .long("mode")
+                .value_name("mode")
+                .takes_value(true)
+                .conflicts_with("misuse-resistant")
+                .help("The mode to encrypt with (default is stream, derived-stream is the same as --misuse-resistant)"),
+        )
+        .arg(
+            Arg::new("block-size")
+                .long("block-size")
+                .value_name("size")
+                .takes_value(true)
+                .help("The size of each encrypted block, as a power of two between 64K and 64M (default is 1M)"),
+        )
+        .arg(
+            Arg::new("pad")
+                .long("pad")
+                .takes_value(false)
+                .conflicts_with("compress")
+                .help("Pad the file before it's encrypted, so the output doesn't reveal its exact size"),
+        )
+        .arg(
+            Arg::new("convergent")
+                .long("convergent")
+                .takes_value(false)
+                .conflicts_with("misuse-resistant")
+                .help("Derive the nonces and keys from the file, so identical files encrypt identically (WARNING: this reveals which files are identical)"),
+        )
+        .arg(
+            Arg::new("recipient")
+                .long("recipient")
+                .value_name("public key")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .conflicts_with("convergent")
+                .help("Also allow the file to be decrypted with a recipient's secret key (see `key keypair`), and may be repeated"),
+        )
+        .arg(
+            Arg::new("pkcs11-uri")
+                .long("pkcs11-uri")
+                .value_name("uri")
+                .takes_value(true)
+                .conflicts_with("convergent")
+                .help("Also allow the file to be decrypted with a secret key on a PKCS#11 token (e.g. `pkcs11:token=dexios;object=key?module-path=/usr/lib/softhsm/libsofthsm2.so`)"),
+        )
+        .arg(
+            Arg::new("extra-keyfile")
+                .long("extra-keyfile")
+                .value_name("file")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .conflicts_with("convergent")
+                .help("Also allow the file to be decrypted with another keyfile, and may be repeated"),
+        )
+        .arg(
+            Arg::new("recovery-key")
+                .long("recovery-key")
+                .takes_value(false)
+                .conflicts_with("convergent")
+                .help("Also allow the file to be decrypted with a generated recovery code, which is shown once"),
+        )
+        .arg(
+            Arg::new("sign-key")
+                .long("sign-key")
+                .value_name("secret key")
+                .takes_value(true)
+                .help("Sign the encrypted file, and write the signature to <output>.sig (see `key keypair --signing`)"),
+        )
+        .arg(
+            Arg::new("mac")
+                .long("mac")
+                .takes_value(false)
+                .help("Append a MAC of the whole file, so truncation or tampering is detected before anything is decrypted"),
+        )
+        .arg(
+            Arg::new("digest")
+                .long("digest")
+                .takes_value(false)
+                .help("Store an encrypted hash of the file in the header, so the output can be verified after it's decrypted"),
+        )
+        .arg(
+            Arg::new("seekable")
+                .long("seekable")
+                .takes_value(false)
+                .help("Append a table of where each block starts, so applications can decrypt any part of the file on its own"),
+        )
+        .arg(
+            Arg::new("stream-counter")
+                .long("stream-counter")
+                .value_name("counter")
+                .takes_value(true)
+                .value_parser(["le31", "be64"])
+                .conflicts_with("misuse-resistant")
+                .help("The counter used for each block's nonce (default is le31, be64 allows far more blocks per file but needs an AEAD with a long nonce, e.g. XChaCha20-Poly1305)"),
+        )
+        .arg(
+            Arg::new("paranoid")
+                .long("paranoid")
+                .takes_value(false)
+                .conflicts_with_all(&["algorithm", "aes", "aegis", "chacha20", "ascon", "kdf-memory", "compress", "convergent"])
+                .help("Use the most conservative options: XChaCha20-Poly1305, argon2id (unless --kdf is provided) with as much memory as --max-memory allows, random header padding and a plaintext digest, and check that the output decrypts to the input before it's erased"),
+        )
+        .arg(
+            Arg::new("max-memory")
+                .long("max-memory")
+                .value_name("MiB")
+                .takes_value(true)
+                .value_parser(clap::value_parser!(u32).range(8..))
+                .requires("paranoid")
+                .help("The most memory that deriving a key may use with --paranoid, in MiB (default is 1024)"),
+        )
+        .arg(
+            Arg::new("compat")
+                .long("compat")
+                .value_name("version")
+                .takes_value(true)
+                .value_parser(["v5", "v4", "v3", "v2"])
+                .conflicts_with_all(&["paranoid", "store-metadata", "backup-header", "header-placeholder"])
+                .help("Write an older header layout, so the file can be read by older versions of dexios (most options aren't supported by older headers)"),
+        )
+        .arg(
+            Arg::new("allow-unauthenticated-header")
+                .long("allow-unauthenticated-header")
+                .takes_value(false)
+                .requires("compat")
+                .help("Allow --compat v2, whose header isn't authenticated (so changes to it can't be detected)"),
         );
 
     let decrypt = Command::new("decrypt")
@@ -101,23 +429,161 @@ pub fn get_matches() -> clap::ArgMatches {
             Arg::new("output")
                 .value_name("output")
                 .takes_value(true)
-                .required(true)
-                .help("The output file"),
+                .required_unless_present_any(["range", "stdout", "restore-metadata"])
+                .help("The output file (with --range, stdout is used if this isn't provided)"),
+        )
+        .arg(
+            Arg::new("stdout")
+                .long("stdout")
+                .takes_value(false)
+                .conflicts_with_all(&["output", "erase", "hash"])
+                .help("Write the decrypted data to stdout, instead of an output file (implies --quiet)"),
         )
         .arg(
             Arg::new("keyfile")
                 .short('k')
                 .long("keyfile")
+                .multiple_occurrences(true)
+                .value_name("file")
+                .takes_value(true)
+                .help("Use a keyfile instead of a password (repeat to require several keyfiles)"),
+        )
+        .arg(
+            Arg::new("keyfile-fd")
+                .long("keyfile-fd")
+                .value_name("fd")
+                .takes_value(true)
+                .value_parser(clap::value_parser!(u32))
+                .conflicts_with_all(&["keyfile", "password-command"])
+                .help("Read the keyfile from an inherited file descriptor (a keyfile may also be set with DEXIOS_KEYFILE)"),
+        )
+        .arg(
+            Arg::new("password-command")
+                .long("password-command")
+                .value_name("command")
+                .takes_value(true)
+                .conflicts_with("keyfile")
+                .help("Use the output of a command as the key, run without a shell (e.g. 'pass show dexios')"),
+        )
+        .arg(
+            Arg::new("key-command")
+                .long("key-command")
+                .value_name("command")
+                .takes_value(true)
+                .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file", "password-fd"])
+                .help("Use the output of a program as the key, without a shell (e.g. 'pass show backups/dexios')"),
+        )
+        .arg(
+            Arg::new("password-file")
+                .long("password-file")
+                .value_name("file")
+                .takes_value(true)
+                .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command"])
+                .help("Read the password from a file (or STDIN with '-'), without its trailing newline"),
+        )
+        .arg(
+            Arg::new("password-fd")
+                .long("password-fd")
+                .value_name("fd")
+                .takes_value(true)
+                .value_parser(clap::value_parser!(u32))
+                .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file"])
+                .help("Read the password from an inherited file descriptor, without its trailing newline"),
+        )
+        .arg(
+            Arg::new("yubikey")
+                .long("yubikey")
+                .value_name("slot")
+                .min_values(0)
+                .value_parser(["1", "2"])
+                .default_missing_value("2")
+                .takes_value(true)
+                .require_equals(true)
+                .conflicts_with("keyfile")
+                .help("Use a YubiKey's HMAC-SHA1 challenge-response slot as the key (default is slot 2)"),
+        )
+        .arg(
+            Arg::new("identity")
+                .long("identity")
+                .value_name("secret key")
+                .takes_value(true)
+                .conflicts_with("keyfile")
+                .help("Use a recipient's secret key instead of a password"),
+        )
+        .arg(
+            Arg::new("pkcs11-uri")
+                .long("pkcs11-uri")
+                .value_name("uri")
+                .takes_value(true)
+                .conflicts_with_all(&["keyfile", "identity", "recovery"])
+                .help("Use a secret key on a PKCS#11 token instead of a password (see `encrypt --pkcs11-uri`)"),
+        )
+        .arg(
+            Arg::new("recovery")
+                .long("recovery")
+                .takes_value(false)
+                .conflicts_with("identity")
+                .help("Use the recovery code from `encrypt --recovery-key` instead of a password (it may be given like any other key)"),
+        )
+        .arg(
+            Arg::new("mnemonic")
+                .long("mnemonic")
+                .takes_value(false)
+                .conflicts_with_all(&["identity", "pkcs11-uri", "recovery"])
+                .help("Use the mnemonic from `key export-mnemonic` instead of a password (it may be given like any other key)"),
+        )
+        .arg(
+            Arg::new("verify-key")
+                .long("verify-key")
+                .value_name("public key")
+                .takes_value(true)
+                .help("Verify the file's signature with the signer's public key before decrypting"),
+        )
+        .arg(
+            Arg::new("signature")
+                .long("signature")
                 .value_name("file")
                 .takes_value(true)
-                .help("Use a keyfile instead of a password"),
+                .help("The file's signature (default is <input>.sig)"),
         )
         .arg(
             Arg::new("header")
                 .long("header")
                 .value_name("file")
                 .takes_value(true)
-                .help("Use a header file that was dumped"),
+                .help("Use a header file that was dumped (the input may have had its header stripped, or been encrypted with --header)"),
+        )
+        .arg(
+            Arg::new("scan-for-header")
+                .long("scan-for-header")
+                .value_name("MiB")
+                .takes_value(true)
+                .require_equals(true)
+                .min_values(0)
+                .default_missing_value("16")
+                .help("If the header can't be read, search the start of the file for it (default is the first 16 MiB)"),
+        )
+        .arg(
+            Arg::new("assume")
+                .long("assume")
+                .value_name("version,algorithm,mode")
+                .takes_value(true)
+                .help("Assume the header's type (e.g. `v6,xchacha,stream`), if its first bytes have been damaged"),
+        )
+        .arg(
+            Arg::new("range")
+                .long("range")
+                .value_name("start:len")
+                .takes_value(true)
+                .conflicts_with_all(&["scan-for-header", "assume", "erase", "hash"])
+                .help("Only decrypt `len` bytes of the plaintext, starting at `start` (the file must have been encrypted with --seekable)"),
+        )
+        .arg(
+            Arg::new("restore-metadata")
+                .long("restore-metadata")
+                .takes_value(false)
+                .conflicts_with_all(&["range", "stdout"])
+                .help("Restore the original file's name (if the output is a directory, or isn't provided), modification time and permissions, if they were stored with `encrypt --store-metadata`"),
         )
         .arg(
             Arg::new("erase")
@@ -150,6 +616,57 @@ pub fn get_matches() -> clap::ArgMatches {
         .about("Secure, fast and modern command-line encryption of files.")
         .subcommand_required(true)
         .arg_required_else_help(true)
+        .arg(
+            Arg::new("lenient")
+                .long("lenient")
+                .global(true)
+                .takes_value(false)
+                .help("Fall back to the default for invalid numeric values, instead of failing"),
+        )
+        .arg(
+            Arg::new("policy")
+                .long("policy")
+                .value_name("file")
+                .takes_value(true)
+                .global(true)
+                .help("Restrict the permitted algorithms, KDFs and header versions (may also be set with DEXIOS_POLICY)"),
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .global(true)
+                .takes_value(false)
+                .help("Only write the requested output to stdout (warnings and errors go to stderr)"),
+        )
+        .arg(
+            Arg::new("pinentry")
+                .long("pinentry")
+                .value_name("program")
+                .min_values(0)
+                .takes_value(true)
+                .require_equals(true)
+                .default_missing_value("pinentry")
+                .global(true)
+                .help("Enter passwords with pinentry (default is `pinentry`), or with `agent` to go through gpg-agent and its cache"),
+        )
+        .arg(
+            Arg::new("tmpdir")
+                .long("tmpdir")
+                .value_name("dir")
+                .takes_value(true)
+                .global(true)
+                .help("Where temporary files are created (may also be set with DEXIOS_TMPDIR or TMPDIR), outputs on other filesystems still use their own directory"),
+        )
+        .arg(
+            Arg::new("crypto-backend")
+                .long("crypto-backend")
+                .value_name("backend")
+                .takes_value(true)
+                .global(true)
+                .value_parser(["auto", "software", "kernel"])
+                .help("Where AES-256-GCM is computed (default is auto, which benchmarks the kernel crypto API if the CPU lacks AES instructions)"),
+        )
         .subcommand(encrypt.clone())
         .subcommand(decrypt.clone())
         .subcommand(
@@ -177,31 +694,116 @@ pub fn get_matches() -> clap::ArgMatches {
                         .require_equals(true)
                         .help("Specify the number of passes (default is 1)")
                         .min_values(0)
+                        .default_value("1")
                         .default_missing_value("1"),
                 ),
         )
         .subcommand(
-            Command::new("hash").about("Hash files with BLAKE3").arg(
-                Arg::new("input")
-                    .value_name("input")
-                    .takes_value(true)
-                    .required(true)
-                    .help("The file(s) to hash")
-                    .min_values(1)
-                    .multiple_occurrences(true),
-            ),
+            Command::new("sign")
+                .about("Sign an encrypted file with an Ed25519 key")
+                .arg(
+                    Arg::new("input")
+                        .value_name("input")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The file to sign"),
+                )
+                .arg(
+                    Arg::new("sign-key")
+                        .long("sign-key")
+                        .value_name("secret key")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The secret key to sign with (see `key keypair --signing`)"),
+                )
+                .arg(
+                    Arg::new("header")
+                        .long("header")
+                        .value_name("file")
+                        .takes_value(true)
+                        .help("Also sign a header file that was dumped"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("file")
+                        .takes_value(true)
+                        .help("The file to write the signature to (default is <input>.sig)"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .short('f')
+                        .long("force")
+                        .takes_value(false)
+                        .help("Force all actions"),
+                ),
         )
         .subcommand(
-            Command::new("pack")
-            .about("Pack and encrypt an entire directory")
-            .short_flag('p')
-            .arg(
+            Command::new("verify-sig")
+                .about("Verify an encrypted file's signature")
+                .arg(
+                    Arg::new("input")
+                        .value_name("input")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The file to verify"),
+                )
+                .arg(
+                    Arg::new("verify-key")
+                        .long("verify-key")
+                        .value_name("public key")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The signer's public key"),
+                )
+                .arg(
+                    Arg::new("header")
+                        .long("header")
+                        .value_name("file")
+                        .takes_value(true)
+                        .help("The header file that was signed alongside the input"),
+                )
+                .arg(
+                    Arg::new("signature")
+                        .long("signature")
+                        .value_name("file")
+                        .takes_value(true)
+                        .help("The signature (default is <input>.sig)"),
+                ),
+        )
+        .subcommand(
+            Command::new("hash").about("Hash files with BLAKE3").arg(
+                Arg::new("input")
+                    .value_name("input")
+                    .takes_value(true)
+                    .required(true)
+                    .help("The file(s) to hash")
+                    .min_values(1)
+                    .multiple_occurrences(true),
+            ),
+        )
+        .subcommand(
+            Command::new("pack")
+            .about("Pack and encrypt entire directories (and files) into one archive")
+            .short_flag('p')
+            .args_conflicts_with_subcommands(true)
+            .subcommand_negates_reqs(true)
+            .arg(
                 Arg::new("input")
                     .value_name("input")
                     .takes_value(true)
                     .multiple_values(true)
                     .required(true)
-                    .help("The directory to encrypt"),
+                    .help("The directories and files to encrypt"),
+            )
+            .arg(
+                Arg::new("prefix")
+                    .long("prefix")
+                    .value_name("input=prefix")
+                    .takes_value(true)
+                    .multiple_occurrences(true)
+                    .help("Place an input beneath this prefix within the archive (default is the input's name, and an empty prefix places its contents at the top level)"),
             )
             .arg(
                 Arg::new("output")
@@ -210,6 +812,17 @@ pub fn get_matches() -> clap::ArgMatches {
                     .required(true)
                     .help("The output file"),
             )
+            .arg(
+                Arg::new("estimate")
+                    .long("estimate")
+                    .value_name("percent")
+                    .min_values(0)
+                    .value_parser(clap::value_parser!(u8).range(1..=100))
+                    .default_missing_value("10")
+                    .takes_value(true)
+                    .require_equals(true)
+                    .help("Estimate the archive's size and how long packing will take, by compressing this percentage of each type of file (default is 10), without creating it"),
+            )
             .arg(
                 Arg::new("erase")
                     .long("erase")
@@ -222,6 +835,39 @@ pub fn get_matches() -> clap::ArgMatches {
                     .takes_value(false)
                     .help("Use argon2id for password hashing"),
             )
+            .arg(
+                Arg::new("kdf")
+                    .long("kdf")
+                    .value_name("kdf")
+                    .takes_value(true)
+                    .value_parser(["argon2id", "blake3-balloon", "scrypt"])
+                    .conflicts_with("argon")
+                    .help("The KDF to use for password hashing (default is blake3-balloon)"),
+            )
+            .arg(
+                Arg::new("kdf-memory")
+                    .long("kdf-memory")
+                    .value_name("MiB")
+                    .takes_value(true)
+                    .value_parser(clap::value_parser!(u32).range(1..=4096))
+                    .help("The amount of memory the password hashing uses, in MiB, up to 4096 (stored in the header)"),
+            )
+            .arg(
+                Arg::new("kdf-iterations")
+                    .long("kdf-iterations")
+                    .value_name("#")
+                    .takes_value(true)
+                    .value_parser(clap::value_parser!(u32).range(1..=255))
+                    .help("The number of iterations the password hashing uses (stored in the header)"),
+            )
+            .arg(
+                Arg::new("kdf-parallelism")
+                    .long("kdf-parallelism")
+                    .value_name("#")
+                    .takes_value(true)
+                    .value_parser(clap::value_parser!(u32).range(1..=255))
+                    .help("The number of lanes the password hashing uses (stored in the header)"),
+            )
             .arg(
                 Arg::new("verbose")
                     .short('v')
@@ -229,6 +875,14 @@ pub fn get_matches() -> clap::ArgMatches {
                     .takes_value(false)
                     .help("Show a detailed output"),
             )
+            .arg(
+                Arg::new("progress")
+                    .long("progress")
+                    .value_name("N or N%")
+                    .takes_value(true)
+                    .conflicts_with("verbose")
+                    .help("Log progress (with the current throughput) every N files, or every N percent of them"),
+            )
             .arg(
                 Arg::new("autogenerate")
                     .long("auto")
@@ -265,9 +919,98 @@ pub fn get_matches() -> clap::ArgMatches {
                 Arg::new("keyfile")
                     .short('k')
                     .long("keyfile")
+                    .multiple_occurrences(true)
+                    .value_name("file")
+                    .takes_value(true)
+                    .help("Use a keyfile instead of a password (repeat to require several keyfiles)"),
+            )
+            .arg(
+                Arg::new("keyfile-fd")
+                    .long("keyfile-fd")
+                    .value_name("fd")
+                    .takes_value(true)
+                    .value_parser(clap::value_parser!(u32))
+                    .conflicts_with_all(&["keyfile", "password-command"])
+                    .help("Read the keyfile from an inherited file descriptor (a keyfile may also be set with DEXIOS_KEYFILE)"),
+            )
+            .arg(
+                Arg::new("password-command")
+                    .long("password-command")
+                    .value_name("command")
+                    .takes_value(true)
+                    .conflicts_with("keyfile")
+                    .help("Use the output of a command as the key, run without a shell (e.g. 'pass show dexios')"),
+            )
+            .arg(
+                Arg::new("key-command")
+                    .long("key-command")
+                    .value_name("command")
+                    .takes_value(true)
+                    .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file", "password-fd"])
+                    .help("Use the output of a program as the key, without a shell (e.g. 'pass show backups/dexios')"),
+            )
+            .arg(
+                Arg::new("password-file")
+                    .long("password-file")
                     .value_name("file")
                     .takes_value(true)
-                    .help("Use a keyfile instead of a password"),
+                    .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command"])
+                    .help("Read the password from a file (or STDIN with '-'), without its trailing newline"),
+            )
+            .arg(
+                Arg::new("password-fd")
+                    .long("password-fd")
+                    .value_name("fd")
+                    .takes_value(true)
+                    .value_parser(clap::value_parser!(u32))
+                    .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file"])
+                    .help("Read the password from an inherited file descriptor, without its trailing newline"),
+            )
+            .arg(
+                Arg::new("two-factor")
+                    .long("two-factor")
+                    .takes_value(false)
+                    .conflicts_with_all(&["password-command", "password-file", "password-fd", "autogenerate", "yubikey"])
+                    .help("Require a password as well as the keyfile (the password is read from DEXIOS_KEY, or asked for)"),
+            )
+            .arg(
+                Arg::new("strict")
+                    .long("strict")
+                    .takes_value(false)
+                    .help("Refuse weak passwords, rather than asking whether to use them"),
+            )
+            .arg(
+                Arg::new("move-to")
+                    .long("move-to")
+                    .value_name("path")
+                    .takes_value(true)
+                    .help("Move the output here once it's complete (e.g. from local scratch space to a NAS), verifying the copy before the original is removed"),
+            )
+            .arg(
+                Arg::new("manifest")
+                    .long("manifest")
+                    .takes_value(false)
+                    .help("Store an encrypted list of the packed files in the header, which metadata-only keys can read (see `key add --metadata-only`)"),
+            )
+            .arg(
+                Arg::new("special-files")
+                    .long("special-files")
+                    .value_name("policy")
+                    .takes_value(true)
+                    .value_parser(["skip", "refuse"])
+                    .help("Whether to skip FIFOs, sockets and devices in the input (default), or refuse to pack them"),
+            )
+            .arg(
+                Arg::new("yubikey")
+                    .long("yubikey")
+                    .value_name("slot")
+                    .min_values(0)
+                    .value_parser(["1", "2"])
+                    .default_missing_value("2")
+                    .takes_value(true)
+                    .require_equals(true)
+                    .conflicts_with("keyfile")
+                    .help("Use a YubiKey's HMAC-SHA1 challenge-response slot as the key (default is slot 2)"),
             )
             .arg(
                 Arg::new("hash")
@@ -283,124 +1026,1593 @@ pub fn get_matches() -> clap::ArgMatches {
                     .takes_value(false)
                     .help("Force all actions"),
             )
+            .arg(
+                Arg::new("algorithm")
+                    .long("algorithm")
+                    .value_name("algorithm")
+                    .takes_value(true)
+                    .help("The AEAD to encrypt with: xchacha20-poly1305 (default), aes-256-gcm, deoxys-ii-256, aegis-256, chacha20-poly1305 or ascon-128a"),
+            )
             .arg(
                 Arg::new("aes")
                     .long("aes")
                     .takes_value(false)
-                    .help("Use AES-256-GCM for encryption"),
+                    .hide(true)
+                    .conflicts_with("algorithm")
+                    .help("Use AES-256-GCM for encryption (the same as --algorithm aes-256-gcm)"),
+            )
+            .arg(
+                Arg::new("aegis")
+                    .long("aegis")
+                    .takes_value(false)
+                    .hide(true)
+                    .conflicts_with_all(&["aes", "algorithm"])
+                    .help("Use AEGIS-256 for encryption (the same as --algorithm aegis-256)"),
+            )
+            .arg(
+                Arg::new("chacha20")
+                    .long("chacha20")
+                    .takes_value(false)
+                    .hide(true)
+                    .conflicts_with_all(&["aes", "aegis", "algorithm"])
+                    .help("Use ChaCha20-Poly1305 for encryption (the same as --algorithm chacha20-poly1305)"),
+            )
+            .arg(
+                Arg::new("ascon")
+                    .long("ascon")
+                    .takes_value(false)
+                    .hide(true)
+                    .conflicts_with_all(&["aes", "aegis", "chacha20", "algorithm"])
+                    .help("Use Ascon-128a for encryption (the same as --algorithm ascon-128a)"),
+            )
+            .arg(
+                Arg::new("jobs")
+                    .short('j')
+                    .long("jobs")
+                    .value_name("# of threads")
+                    .takes_value(true)
+                    .help("The number of threads to compress files with (default is the number of CPUs)"),
+            )
+            .arg(
+                Arg::new("stats")
+                    .long("stats")
+                    .takes_value(false)
+                    .help("Show the time spent in each stage of packing"),
+            )
+            .arg(
+                Arg::new("filter")
+                    .long("filter")
+                    .value_name("pattern:command")
+                    .takes_value(true)
+                    .multiple_occurrences(true)
+                    .help("Pipe matching files through a command before they're archived (e.g. '*.sql:gzip')"),
+            )
+            .arg(
+                Arg::new("ads")
+                    .long("ads")
+                    .takes_value(false)
+                    .help("Store each file's NTFS alternate data streams (Windows only)"),
+            )
+            .arg(
+                Arg::new("apple-meta")
+                    .long("apple-meta")
+                    .takes_value(false)
+                    .help("Store each file's extended attributes and resource fork as AppleDouble (macOS/Unix only)"),
+            )
+            .subcommand(
+                Command::new("recompress")
+                    .about("Compress the files within an encrypted pack again, with a different codec or level")
+                    .long_about("Compress the files within an encrypted pack again, with a different codec or level (e.g. to move old backups onto cheaper storage). The pack is decrypted, compressed and encrypted again at the same time, so none of it is written to disk unencrypted. The master key is kept, so every keyslot (and the manifest) is carried over.")
+                    .arg_required_else_help(true)
+                    .arg(
+                        Arg::new("input")
+                            .value_name("input")
+                            .takes_value(true)
+                            .required(true)
+                            .help("The encrypted pack (its header must be embedded)"),
+                    )
+                    .arg(
+                        Arg::new("output")
+                            .value_name("output")
+                            .takes_value(true)
+                            .required(true)
+                            .help("The new pack"),
+                    )
+                    .arg(
+                        Arg::new("compression")
+                            .long("compression")
+                            .value_name("codec[:level]")
+                            .takes_value(true)
+                            .required(true)
+                            .help("The compression to use: 'none', or 'zstd' with an optional level from 1 to 22 (e.g. 'zstd:19', default is 3)"),
+                    )
+                    .arg(
+                        Arg::new("keyfile")
+                            .short('k')
+                            .long("keyfile")
+                            .multiple_occurrences(true)
+                            .value_name("file")
+                            .takes_value(true)
+                            .help("Use a keyfile instead of a password"),
+                    )
+                    .arg(
+                        Arg::new("keyfile-fd")
+                            .long("keyfile-fd")
+                            .value_name("fd")
+                            .takes_value(true)
+                            .value_parser(clap::value_parser!(u32))
+                            .conflicts_with_all(&["keyfile", "password-command"])
+                            .help("Read the keyfile from an inherited file descriptor (a keyfile may also be set with DEXIOS_KEYFILE)"),
+                    )
+                    .arg(
+                        Arg::new("password-command")
+                            .long("password-command")
+                            .value_name("command")
+                            .takes_value(true)
+                            .conflicts_with("keyfile")
+                            .help("Use the output of a command as the key, run without a shell (e.g. 'pass show dexios')"),
+                    )
+                    .arg(
+                        Arg::new("key-command")
+                            .long("key-command")
+                            .value_name("command")
+                            .takes_value(true)
+                            .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file", "password-fd"])
+                            .help("Use the output of a program as the key, without a shell (e.g. 'pass show backups/dexios')"),
+                    )
+                    .arg(
+                        Arg::new("password-file")
+                            .long("password-file")
+                            .value_name("file")
+                            .takes_value(true)
+                            .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command"])
+                            .help("Read the password from a file (or STDIN with '-'), without its trailing newline"),
+                    )
+                    .arg(
+                        Arg::new("password-fd")
+                            .long("password-fd")
+                            .value_name("fd")
+                            .takes_value(true)
+                            .value_parser(clap::value_parser!(u32))
+                            .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file"])
+                            .help("Read the password from an inherited file descriptor, without its trailing newline"),
+                    )
+                    .arg(
+                        Arg::new("force")
+                            .short('f')
+                            .long("force")
+                            .takes_value(false)
+                            .help("Force all actions"),
+                    ),
             )
         )
         .subcommand(
-            Command::new("unpack")
-                .short_flag('u')
-                .about("Unpack a previously-packed file")
-                .arg(
-                    Arg::new("input")
-                        .value_name("input")
-                        .takes_value(true)
-                        .required(true)
-                        .help("The file to decrypt"),
-                )
-                .arg(
-                    Arg::new("output")
-                        .value_name("output")
-                        .takes_value(true)
-                        .required(true)
-                        .help("The output file"),
-                )
-                .arg(
-                    Arg::new("keyfile")
-                        .short('k')
-                        .long("keyfile")
-                        .value_name("file")
-                        .takes_value(true)
-                        .help("Use a keyfile instead of a password"),
-                )
-                .arg(
-                    Arg::new("header")
-                        .long("header")
-                        .value_name("file")
-                        .takes_value(true)
-                        .help("Use a header file that was dumped"),
-                )
-                .arg(
-                    Arg::new("erase")
-                        .long("erase")
-                        .value_name("# of passes")
-                        .takes_value(true)
-                        .require_equals(true)
-                        .help("Securely erase the input file once complete (default is 1 pass)")
-                        .min_values(0)
-                        .default_missing_value("1"),
-                )
-                .arg(
-                    Arg::new("verbose")
-                        .short('v')
-                        .long("verbose")
-                        .takes_value(false)
-                        .help("Show a detailed output"),
-                )
-                .arg(
-                    Arg::new("hash")
-                        .short('H')
-                        .long("hash")
-                        .takes_value(false)
-                        .help("Return a BLAKE3 hash of the encrypted file"),
-                )
-                .arg(
-                    Arg::new("force")
-                        .short('f')
-                        .long("force")
-                        .takes_value(false)
-                        .help("Force all actions"),
-                )
-        )
-        .subcommand(Command::new("key")
-                .about("Manipulate keys within the header (for advanced users")
-                .subcommand_required(true)
-                .subcommand(
-                    Command::new("change")
-                        .about("Change an encrypted file's key")
-                        .arg_required_else_help(true)
-                        .arg(
-                            Arg::new("input")
-                                .value_name("input")
-                                .takes_value(true)
-                                .required(true)
-                                .help("The encrypted file/header file"),
-                        )
-                        .arg(
-                            Arg::new("autogenerate")
-                                .long("auto")
-                                .value_name("# of words")
-                                .min_values(0)
-                                .default_missing_value("7")
-                                .takes_value(true)
-                                .require_equals(true)
-                                .help("Autogenerate a passphrase (default is 7 words)")
-                                .conflicts_with("keyfile"),
+            Command::new("backup")
+            .about("Back up a directory, storing only the files that changed since the previous backup")
+            .long_about("Back up a directory into `destination`, as a compressed and encrypted pack with a manifest. Only the files that have changed since the previous backup are stored, and the backup is verified once it's written. Restore it with `dexios restore`, which combines it with the backups before it.")
+            .arg(
+                Arg::new("input")
+                    .value_name("input")
+                    .takes_value(true)
+                    .required(true)
+                    .help("The directory to back up"),
+            )
+            .arg(
+                Arg::new("destination")
+                    .value_name("destination")
+                    .takes_value(true)
+                    .required(true)
+                    .help("The directory to store the backup in (it's named after the directory, and the time)"),
+            )
+            .arg(
+                Arg::new("full")
+                    .long("full")
+                    .takes_value(false)
+                    .help("Store every file, rather than only the ones that changed since the previous backup"),
+            )
+            .arg(
+                Arg::new("split")
+                    .long("split")
+                    .value_name("size")
+                    .takes_value(true)
+                    .help("Split the backup into numbered parts of this size (e.g. `4G`), which can be joined with `cat`"),
+            )
+            .arg(
+                Arg::new("obfuscate-sidecars")
+                    .long("obfuscate-sidecars")
+                    .takes_value(false)
+                    .requires("split")
+                    .help("Name the parts after the first with keyed hashes, so they can't be linked to the backup without its key"),
+            )
+            .arg(
+                Arg::new("upload")
+                    .long("upload")
+                    .value_name("directory")
+                    .takes_value(true)
+                    .help("Move the backup here once it's verified (e.g. to a NAS), verifying the copy before the original is removed"),
+            )
+            .arg(
+                Arg::new("keyfile")
+                    .short('k')
+                    .long("keyfile")
+                    .multiple_occurrences(true)
+                    .value_name("file")
+                    .takes_value(true)
+                    .help("Use a keyfile instead of a password (repeat to require several keyfiles)"),
+            )
+            .arg(
+                Arg::new("keyfile-fd")
+                    .long("keyfile-fd")
+                    .value_name("fd")
+                    .takes_value(true)
+                    .value_parser(clap::value_parser!(u32))
+                    .conflicts_with_all(&["keyfile", "password-command"])
+                    .help("Read the keyfile from an inherited file descriptor (a keyfile may also be set with DEXIOS_KEYFILE)"),
+            )
+            .arg(
+                Arg::new("password-command")
+                    .long("password-command")
+                    .value_name("command")
+                    .takes_value(true)
+                    .conflicts_with("keyfile")
+                    .help("Use the output of a command as the key, run without a shell (e.g. 'pass show dexios')"),
+            )
+            .arg(
+                Arg::new("key-command")
+                    .long("key-command")
+                    .value_name("command")
+                    .takes_value(true)
+                    .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file", "password-fd"])
+                    .help("Use the output of a program as the key, without a shell (e.g. 'pass show backups/dexios')"),
+            )
+            .arg(
+                Arg::new("password-file")
+                    .long("password-file")
+                    .value_name("file")
+                    .takes_value(true)
+                    .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command"])
+                    .help("Read the password from a file (or STDIN with '-'), without its trailing newline"),
+            )
+            .arg(
+                Arg::new("password-fd")
+                    .long("password-fd")
+                    .value_name("fd")
+                    .takes_value(true)
+                    .value_parser(clap::value_parser!(u32))
+                    .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file"])
+                    .help("Read the password from an inherited file descriptor, without its trailing newline"),
+            )
+            .arg(
+                Arg::new("yubikey")
+                    .long("yubikey")
+                    .value_name("slot")
+                    .min_values(0)
+                    .value_parser(["1", "2"])
+                    .default_missing_value("2")
+                    .takes_value(true)
+                    .require_equals(true)
+                    .conflicts_with("keyfile")
+                    .help("Use a YubiKey's HMAC-SHA1 challenge-response slot as the key (default is slot 2)"),
+            )
+            .arg(
+                Arg::new("strict")
+                    .long("strict")
+                    .takes_value(false)
+                    .help("Refuse weak passwords, rather than asking whether to use them"),
+            )
+            .arg(
+                Arg::new("kdf")
+                    .long("kdf")
+                    .value_name("kdf")
+                    .takes_value(true)
+                    .value_parser(["argon2id", "blake3-balloon", "scrypt"])
+                    .help("The KDF to use for password hashing (default is blake3-balloon)"),
+            )
+            .arg(
+                Arg::new("algorithm")
+                    .long("algorithm")
+                    .value_name("algorithm")
+                    .takes_value(true)
+                    .help("The AEAD to encrypt with: xchacha20-poly1305 (default), aes-256-gcm, deoxys-ii-256, aegis-256, chacha20-poly1305 or ascon-128a"),
+            )
+            .arg(
+                Arg::new("ads")
+                    .long("ads")
+                    .takes_value(false)
+                    .help("Store each file's NTFS alternate data streams (Windows only)"),
+            )
+            .arg(
+                Arg::new("apple-meta")
+                    .long("apple-meta")
+                    .takes_value(false)
+                    .help("Store each file's extended attributes and resource fork as AppleDouble (macOS/Unix only)"),
+            )
+            .arg(
+                Arg::new("jobs")
+                    .short('j')
+                    .long("jobs")
+                    .value_name("# of threads")
+                    .takes_value(true)
+                    .help("The number of threads to compress files with (default is the number of CPUs)"),
+            )
+            .arg(
+                Arg::new("verbose")
+                    .short('v')
+                    .long("verbose")
+                    .takes_value(false)
+                    .help("Show a detailed output"),
+            )
+            .arg(
+                Arg::new("force")
+                    .short('f')
+                    .long("force")
+                    .takes_value(false)
+                    .help("Force all actions"),
+            )
+        )
+        .subcommand(
+            Command::new("restore")
+            .about("Restore a directory from its backups")
+            .long_about("Restore a directory from a backup made with `dexios backup`, along with the backups before it that hold the files it didn't store. Pass a backup to restore the directory as it was then, or the directory that the backups are in to restore the newest. Every backup is verified as it's decrypted, and the restored files are checked against the backup's manifest.")
+            .arg(
+                Arg::new("input")
+                    .value_name("backup")
+                    .takes_value(true)
+                    .required(true)
+                    .help("The backup to restore (the first part, if it was split), or the directory that contains the backups"),
+            )
+            .arg(
+                Arg::new("output")
+                    .value_name("output")
+                    .takes_value(true)
+                    .required(true)
+                    .help("The directory to restore into (the files are placed in a directory named after the one that was backed up)"),
+            )
+            .arg(
+                Arg::new("keyfile")
+                    .short('k')
+                    .long("keyfile")
+                    .multiple_occurrences(true)
+                    .value_name("file")
+                    .takes_value(true)
+                    .help("Use a keyfile instead of a password (repeat to require several keyfiles)"),
+            )
+            .arg(
+                Arg::new("keyfile-fd")
+                    .long("keyfile-fd")
+                    .value_name("fd")
+                    .takes_value(true)
+                    .value_parser(clap::value_parser!(u32))
+                    .conflicts_with_all(&["keyfile", "password-command"])
+                    .help("Read the keyfile from an inherited file descriptor (a keyfile may also be set with DEXIOS_KEYFILE)"),
+            )
+            .arg(
+                Arg::new("password-command")
+                    .long("password-command")
+                    .value_name("command")
+                    .takes_value(true)
+                    .conflicts_with("keyfile")
+                    .help("Use the output of a command as the key, run without a shell (e.g. 'pass show dexios')"),
+            )
+            .arg(
+                Arg::new("key-command")
+                    .long("key-command")
+                    .value_name("command")
+                    .takes_value(true)
+                    .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file", "password-fd"])
+                    .help("Use the output of a program as the key, without a shell (e.g. 'pass show backups/dexios')"),
+            )
+            .arg(
+                Arg::new("password-file")
+                    .long("password-file")
+                    .value_name("file")
+                    .takes_value(true)
+                    .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command"])
+                    .help("Read the password from a file (or STDIN with '-'), without its trailing newline"),
+            )
+            .arg(
+                Arg::new("password-fd")
+                    .long("password-fd")
+                    .value_name("fd")
+                    .takes_value(true)
+                    .value_parser(clap::value_parser!(u32))
+                    .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file"])
+                    .help("Read the password from an inherited file descriptor, without its trailing newline"),
+            )
+            .arg(
+                Arg::new("yubikey")
+                    .long("yubikey")
+                    .value_name("slot")
+                    .min_values(0)
+                    .value_parser(["1", "2"])
+                    .default_missing_value("2")
+                    .takes_value(true)
+                    .require_equals(true)
+                    .conflicts_with("keyfile")
+                    .help("Use a YubiKey's HMAC-SHA1 challenge-response slot as the key (default is slot 2)"),
+            )
+            .arg(
+                Arg::new("ads")
+                    .long("ads")
+                    .takes_value(false)
+                    .help("Restore NTFS alternate data streams, if they were stored (Windows only)"),
+            )
+            .arg(
+                Arg::new("apple-meta")
+                    .long("apple-meta")
+                    .takes_value(false)
+                    .help("Restore user.* and com.apple.* extended attributes and resource forks, if they were stored (macOS/Unix only)"),
+            )
+            .arg(
+                Arg::new("verbose")
+                    .short('v')
+                    .long("verbose")
+                    .takes_value(false)
+                    .help("Show a detailed output"),
+            )
+            .arg(
+                Arg::new("force")
+                    .short('f')
+                    .long("force")
+                    .takes_value(false)
+                    .help("Force all actions"),
+            )
+        )
+        .subcommand(
+            Command::new("unpack")
+                .short_flag('u')
+                .about("Unpack a previously-packed file")
+                .arg(
+                    Arg::new("input")
+                        .value_name("input")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The file to decrypt"),
+                )
+                .arg(
+                    Arg::new("stream-counter")
+                        .long("stream-counter")
+                        .value_name("counter")
+                        .takes_value(true)
+                        .value_parser(["le31", "be64"])
+                        .help("The counter used for each block's nonce (default is le31, be64 allows far more blocks per file but needs an AEAD with a long nonce, e.g. XChaCha20-Poly1305)"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .value_name("output")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The output file"),
+                )
+                .arg(
+                    Arg::new("keyfile")
+                        .short('k')
+                        .long("keyfile")
+                        .multiple_occurrences(true)
+                        .value_name("file")
+                        .takes_value(true)
+                        .help("Use a keyfile instead of a password (repeat to require several keyfiles)"),
+                )
+                .arg(
+                    Arg::new("keyfile-fd")
+                        .long("keyfile-fd")
+                        .value_name("fd")
+                        .takes_value(true)
+                        .value_parser(clap::value_parser!(u32))
+                        .conflicts_with_all(&["keyfile", "password-command"])
+                        .help("Read the keyfile from an inherited file descriptor (a keyfile may also be set with DEXIOS_KEYFILE)"),
+                )
+                .arg(
+                    Arg::new("password-command")
+                        .long("password-command")
+                        .value_name("command")
+                        .takes_value(true)
+                        .conflicts_with("keyfile")
+                        .help("Use the output of a command as the key, run without a shell (e.g. 'pass show dexios')"),
+                )
+                .arg(
+                    Arg::new("key-command")
+                        .long("key-command")
+                        .value_name("command")
+                        .takes_value(true)
+                        .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file", "password-fd"])
+                        .help("Use the output of a program as the key, without a shell (e.g. 'pass show backups/dexios')"),
+                )
+                .arg(
+                    Arg::new("password-file")
+                        .long("password-file")
+                        .value_name("file")
+                        .takes_value(true)
+                        .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command"])
+                        .help("Read the password from a file (or STDIN with '-'), without its trailing newline"),
+                )
+                .arg(
+                    Arg::new("password-fd")
+                        .long("password-fd")
+                        .value_name("fd")
+                        .takes_value(true)
+                        .value_parser(clap::value_parser!(u32))
+                        .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file"])
+                        .help("Read the password from an inherited file descriptor, without its trailing newline"),
+                )
+                .arg(
+                    Arg::new("yubikey")
+                        .long("yubikey")
+                        .value_name("slot")
+                        .min_values(0)
+                        .value_parser(["1", "2"])
+                        .default_missing_value("2")
+                        .takes_value(true)
+                        .require_equals(true)
+                        .conflicts_with("keyfile")
+                        .help("Use a YubiKey's HMAC-SHA1 challenge-response slot as the key (default is slot 2)"),
+                )
+                .arg(
+                    Arg::new("filter")
+                        .long("filter")
+                        .value_name("pattern:command")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .help("Pipe matching files through a command before they're written (e.g. '*.sql:gzip -d')"),
+                )
+                .arg(
+                    Arg::new("ads")
+                        .long("ads")
+                        .takes_value(false)
+                        .help("Restore NTFS alternate data streams, if they were stored (Windows only)"),
+                )
+                .arg(
+                    Arg::new("apple-meta")
+                        .long("apple-meta")
+                        .takes_value(false)
+                        .help("Restore user.* and com.apple.* extended attributes and resource forks, if they were stored (macOS/Unix only)"),
+                )
+                .arg(
+                    Arg::new("header")
+                        .long("header")
+                        .value_name("file")
+                        .takes_value(true)
+                        .help("Use a header file that was dumped (the input may have had its header stripped, or been encrypted with --header)"),
+                )
+                .arg(
+                    Arg::new("erase")
+                        .long("erase")
+                        .value_name("# of passes")
+                        .takes_value(true)
+                        .require_equals(true)
+                        .help("Securely erase the input file once complete (default is 1 pass)")
+                        .min_values(0)
+                        .default_missing_value("1"),
+                )
+                .arg(
+                    Arg::new("verbose")
+                        .short('v')
+                        .long("verbose")
+                        .takes_value(false)
+                        .help("Show a detailed output"),
+                )
+                .arg(
+                    Arg::new("hash")
+                        .short('H')
+                        .long("hash")
+                        .takes_value(false)
+                        .help("Return a BLAKE3 hash of the encrypted file"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .short('f')
+                        .long("force")
+                        .takes_value(false)
+                        .help("Force all actions"),
+                )
+                .arg(
+                    Arg::new("verify-against")
+                        .long("verify-against")
+                        .value_name("directory")
+                        .takes_value(true)
+                        .help("Compare the unpacked files with the original directory, and report any differences"),
+                )
+                .arg(
+                    Arg::new("max-extract-size")
+                        .long("max-extract-size")
+                        .value_name("size")
+                        .takes_value(true)
+                        .help("Refuse to unpack archives that would extract to more than this (e.g. `500M`, `2G`)"),
+                )
+                .arg(
+                    Arg::new("max-entries")
+                        .long("max-entries")
+                        .value_name("count")
+                        .takes_value(true)
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Refuse to unpack archives with more entries than this"),
+                )
+                .arg(
+                    Arg::new("max-ratio")
+                        .long("max-ratio")
+                        .value_name("ratio")
+                        .takes_value(true)
+                        .value_parser(clap::value_parser!(u64))
+                        .help("Refuse to unpack files that compressed better than this ratio (default is 1000, 0 disables the check)"),
+                )
+        )
+        .subcommand(
+            Command::new("manifest")
+                .about("List the files within an encrypted pack, without decrypting it")
+                .arg_required_else_help(true)
+                .arg(
+                    Arg::new("input")
+                        .value_name("input")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The encrypted pack/header file"),
+                )
+                .arg(
+                    Arg::new("keyfile")
+                        .short('k')
+                        .long("keyfile")
+                        .multiple_occurrences(true)
+                        .value_name("file")
+                        .takes_value(true)
+                        .help("Use a keyfile (which may be metadata-only)"),
+                )
+                .arg(
+                    Arg::new("keyfile-fd")
+                        .long("keyfile-fd")
+                        .value_name("fd")
+                        .takes_value(true)
+                        .value_parser(clap::value_parser!(u32))
+                        .conflicts_with_all(&["keyfile", "password-command"])
+                        .help("Read the keyfile from an inherited file descriptor (a keyfile may also be set with DEXIOS_KEYFILE)"),
+                )
+                .arg(
+                    Arg::new("password-command")
+                        .long("password-command")
+                        .value_name("command")
+                        .takes_value(true)
+                        .conflicts_with("keyfile")
+                        .help("Use the output of a command as the key, run without a shell (e.g. 'pass show dexios')"),
+                )
+                .arg(
+                    Arg::new("key-command")
+                        .long("key-command")
+                        .value_name("command")
+                        .takes_value(true)
+                        .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file", "password-fd"])
+                        .help("Use the output of a program as the key, without a shell (e.g. 'pass show backups/dexios')"),
+                )
+                .arg(
+                    Arg::new("password-file")
+                        .long("password-file")
+                        .value_name("file")
+                        .takes_value(true)
+                        .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command"])
+                        .help("Read the password from a file (or STDIN with '-'), without its trailing newline"),
+                )
+                .arg(
+                    Arg::new("password-fd")
+                        .long("password-fd")
+                        .value_name("fd")
+                        .takes_value(true)
+                        .value_parser(clap::value_parser!(u32))
+                        .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file"])
+                        .help("Read the password from an inherited file descriptor, without its trailing newline"),
+                ),
+        )
+        .subcommand(
+            Command::new("agent")
+                .about("Start an agent that caches your password, so it's only asked for once (e.g. `eval \"$(dexios agent)\"`)")
+                .arg(
+                    Arg::new("ttl")
+                        .long("ttl")
+                        .value_name("duration")
+                        .takes_value(true)
+                        .help("How long the password is cached for, e.g. `90s`, `15m` or `2h` (default is 15m)"),
+                )
+                .arg(
+                    Arg::new("socket")
+                        .long("socket")
+                        .value_name("path")
+                        .takes_value(true)
+                        .help("The socket to listen on (default is a new one within a private directory)"),
+                )
+                .arg(
+                    Arg::new("foreground")
+                        .long("foreground")
+                        .takes_value(false)
+                        .requires("socket")
+                        .help("Run the agent in the foreground, rather than in the background"),
+                )
+                .arg(
+                    Arg::new("clear")
+                        .long("clear")
+                        .takes_value(false)
+                        .conflicts_with_all(&["ttl", "socket", "foreground", "stop"])
+                        .help("Make the running agent (from DEXIOS_AGENT_SOCK) forget its password"),
+                )
+                .arg(
+                    Arg::new("stop")
+                        .long("stop")
+                        .takes_value(false)
+                        .conflicts_with_all(&["ttl", "socket", "foreground"])
+                        .help("Stop the running agent (from DEXIOS_AGENT_SOCK)"),
+                ),
+        )
+        .subcommand(
+            Command::new("audit")
+                .about("Inventory the header of every encrypted file within a directory, and check each of them against the policy")
+                .arg_required_else_help(true)
+                .arg(
+                    Arg::new("input")
+                        .value_name("dir")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The directory to search for encrypted files"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("format")
+                        .takes_value(true)
+                        .value_parser(["csv", "json"])
+                        .help("The format of the report (default is csv)"),
+                ),
+        )
+        .subcommand(
+            Command::new("upgrade")
+                .about("Upgrade encrypted files to the latest header version, in place")
+                .long_about("Upgrade encrypted files to the latest header version, in place. Each file is decrypted with its own version's parameters and encrypted again at the same time, so its plaintext is never written to disk, and it's only replaced once the upgraded copy is complete. V5 files keep their master key and every keyslot, while older files are given a new master key that's wrapped with the key that was provided. Directories are searched for encrypted files, and files that already use the latest version are skipped.")
+                .arg_required_else_help(true)
+                .arg(
+                    Arg::new("input")
+                        .value_name("input")
+                        .takes_value(true)
+                        .required(true)
+                        .multiple_values(true)
+                        .help("The encrypted files (or directories of them) to upgrade, their headers must be embedded"),
+                )
+                .arg(
+                    Arg::new("keyfile")
+                        .short('k')
+                        .long("keyfile")
+                        .multiple_occurrences(true)
+                        .value_name("file")
+                        .takes_value(true)
+                        .help("Use a keyfile instead of a password"),
+                )
+                .arg(
+                    Arg::new("keyfile-fd")
+                        .long("keyfile-fd")
+                        .value_name("fd")
+                        .takes_value(true)
+                        .value_parser(clap::value_parser!(u32))
+                        .conflicts_with_all(&["keyfile", "password-command"])
+                        .help("Read the keyfile from an inherited file descriptor (a keyfile may also be set with DEXIOS_KEYFILE)"),
+                )
+                .arg(
+                    Arg::new("password-command")
+                        .long("password-command")
+                        .value_name("command")
+                        .takes_value(true)
+                        .conflicts_with("keyfile")
+                        .help("Use the output of a command as the key, run without a shell (e.g. 'pass show dexios')"),
+                )
+                .arg(
+                    Arg::new("key-command")
+                        .long("key-command")
+                        .value_name("command")
+                        .takes_value(true)
+                        .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file", "password-fd"])
+                        .help("Use the output of a program as the key, without a shell (e.g. 'pass show backups/dexios')"),
+                )
+                .arg(
+                    Arg::new("password-file")
+                        .long("password-file")
+                        .value_name("file")
+                        .takes_value(true)
+                        .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command"])
+                        .help("Read the password from a file (or STDIN with '-'), without its trailing newline"),
+                )
+                .arg(
+                    Arg::new("password-fd")
+                        .long("password-fd")
+                        .value_name("fd")
+                        .takes_value(true)
+                        .value_parser(clap::value_parser!(u32))
+                        .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file"])
+                        .help("Read the password from an inherited file descriptor, without its trailing newline"),
+                )
+                .arg(
+                    Arg::new("argon")
+                        .long("argon")
+                        .takes_value(false)
+                        .help("Use argon2id for password hashing"),
+                )
+                .arg(
+                    Arg::new("kdf")
+                        .long("kdf")
+                        .value_name("kdf")
+                        .takes_value(true)
+                        .value_parser(["argon2id", "blake3-balloon", "scrypt"])
+                        .conflicts_with("argon")
+                        .help("The KDF that hashes the key for the new keyslot of V1-V4 files (default is blake3-balloon)"),
+                )
+                .arg(
+                    Arg::new("kdf-memory")
+                        .long("kdf-memory")
+                        .value_name("MiB")
+                        .takes_value(true)
+                        .value_parser(clap::value_parser!(u32).range(1..=4096))
+                        .help("The amount of memory the password hashing uses, in MiB, up to 4096 (stored in the header)"),
+                )
+                .arg(
+                    Arg::new("kdf-iterations")
+                        .long("kdf-iterations")
+                        .value_name("#")
+                        .takes_value(true)
+                        .value_parser(clap::value_parser!(u32).range(1..=255))
+                        .help("The number of iterations the password hashing uses (stored in the header)"),
+                )
+                .arg(
+                    Arg::new("kdf-parallelism")
+                        .long("kdf-parallelism")
+                        .value_name("#")
+                        .takes_value(true)
+                        .value_parser(clap::value_parser!(u32).range(1..=255))
+                        .help("The number of lanes the password hashing uses (stored in the header)"),
+                ),
+        )
+        .subcommand(
+            Command::new("export-recovery")
+                .about("Export the headers of every encrypted file within a directory to an encrypted recovery bundle")
+                .arg_required_else_help(true)
+                .arg(
+                    Arg::new("input")
+                        .value_name("dir")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The directory to search for encrypted files"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("file")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The output recovery bundle"),
+                )
+                .arg(
+                    Arg::new("keyfile")
+                        .short('k')
+                        .long("keyfile")
+                        .multiple_occurrences(true)
+                        .value_name("file")
+                        .takes_value(true)
+                        .help("Use a keyfile instead of a password (repeat to require several keyfiles)"),
+                )
+                .arg(
+                    Arg::new("keyfile-fd")
+                        .long("keyfile-fd")
+                        .value_name("fd")
+                        .takes_value(true)
+                        .value_parser(clap::value_parser!(u32))
+                        .conflicts_with_all(&["keyfile", "password-command"])
+                        .help("Read the keyfile from an inherited file descriptor (a keyfile may also be set with DEXIOS_KEYFILE)"),
+                )
+                .arg(
+                    Arg::new("password-command")
+                        .long("password-command")
+                        .value_name("command")
+                        .takes_value(true)
+                        .conflicts_with("keyfile")
+                        .help("Use the output of a command as the key, run without a shell (e.g. 'pass show dexios')"),
+                )
+                .arg(
+                    Arg::new("key-command")
+                        .long("key-command")
+                        .value_name("command")
+                        .takes_value(true)
+                        .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file", "password-fd"])
+                        .help("Use the output of a program as the key, without a shell (e.g. 'pass show backups/dexios')"),
+                )
+                .arg(
+                    Arg::new("password-file")
+                        .long("password-file")
+                        .value_name("file")
+                        .takes_value(true)
+                        .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command"])
+                        .help("Read the password from a file (or STDIN with '-'), without its trailing newline"),
+                )
+                .arg(
+                    Arg::new("password-fd")
+                        .long("password-fd")
+                        .value_name("fd")
+                        .takes_value(true)
+                        .value_parser(clap::value_parser!(u32))
+                        .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file"])
+                        .help("Read the password from an inherited file descriptor, without its trailing newline"),
+                )
+                .arg(
+                    Arg::new("autogenerate")
+                        .long("auto")
+                        .value_name("# of words")
+                        .min_values(0)
+                        .default_missing_value("7")
+                        .takes_value(true)
+                        .require_equals(true)
+                        .help("Autogenerate a passphrase (default is 7 words)")
+                        .conflicts_with("keyfile"),
+                )
+                .arg(
+                    Arg::new("argon")
+                        .long("argon")
+                        .takes_value(false)
+                        .help("Use argon2id for password hashing"),
+                )
+                .arg(
+                    Arg::new("kdf")
+                        .long("kdf")
+                        .value_name("kdf")
+                        .takes_value(true)
+                        .value_parser(["argon2id", "blake3-balloon", "scrypt"])
+                        .conflicts_with("argon")
+                        .help("The KDF to use for password hashing (default is blake3-balloon)"),
+                )
+                .arg(
+                    Arg::new("algorithm")
+                        .long("algorithm")
+                        .value_name("algorithm")
+                        .takes_value(true)
+                        .help("The AEAD to encrypt with: xchacha20-poly1305 (default), aes-256-gcm, deoxys-ii-256, aegis-256, chacha20-poly1305 or ascon-128a"),
+                )
+                .arg(
+                    Arg::new("aes")
+                        .long("aes")
+                        .takes_value(false)
+                        .hide(true)
+                        .conflicts_with("algorithm")
+                        .help("Use AES-256-GCM for encryption (the same as --algorithm aes-256-gcm)"),
+                )
+                .arg(
+                    Arg::new("aegis")
+                        .long("aegis")
+                        .takes_value(false)
+                        .hide(true)
+                        .conflicts_with_all(&["aes", "algorithm"])
+                        .help("Use AEGIS-256 for encryption (the same as --algorithm aegis-256)"),
+                )
+                .arg(
+                    Arg::new("chacha20")
+                        .long("chacha20")
+                        .takes_value(false)
+                        .hide(true)
+                        .conflicts_with_all(&["aes", "aegis", "algorithm"])
+                        .help("Use ChaCha20-Poly1305 for encryption (the same as --algorithm chacha20-poly1305)"),
+                )
+                .arg(
+                    Arg::new("ascon")
+                        .long("ascon")
+                        .takes_value(false)
+                        .hide(true)
+                        .conflicts_with_all(&["aes", "aegis", "chacha20", "algorithm"])
+                        .help("Use Ascon-128a for encryption (the same as --algorithm ascon-128a)"),
+                )
+                .arg(
+                    Arg::new("hash")
+                        .short('H')
+                        .long("hash")
+                        .takes_value(false)
+                        .help("Return a BLAKE3 hash of the recovery bundle"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .short('f')
+                        .long("force")
+                        .takes_value(false)
+                        .help("Force all actions"),
+                ),
+        )
+        .subcommand(
+            Command::new("import-recovery")
+                .about("Restore missing or damaged headers within a directory from a recovery bundle")
+                .arg_required_else_help(true)
+                .arg(
+                    Arg::new("input")
+                        .value_name("bundle")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The recovery bundle"),
+                )
+                .arg(
+                    Arg::new("dir")
+                        .value_name("dir")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The directory that the bundle was exported from"),
+                )
+                .arg(
+                    Arg::new("keyfile")
+                        .short('k')
+                        .long("keyfile")
+                        .multiple_occurrences(true)
+                        .value_name("file")
+                        .takes_value(true)
+                        .help("Use a keyfile instead of a password (repeat to require several keyfiles)"),
+                )
+                .arg(
+                    Arg::new("keyfile-fd")
+                        .long("keyfile-fd")
+                        .value_name("fd")
+                        .takes_value(true)
+                        .value_parser(clap::value_parser!(u32))
+                        .conflicts_with_all(&["keyfile", "password-command"])
+                        .help("Read the keyfile from an inherited file descriptor (a keyfile may also be set with DEXIOS_KEYFILE)"),
+                )
+                .arg(
+                    Arg::new("password-command")
+                        .long("password-command")
+                        .value_name("command")
+                        .takes_value(true)
+                        .conflicts_with("keyfile")
+                        .help("Use the output of a command as the key, run without a shell (e.g. 'pass show dexios')"),
+                )
+                .arg(
+                    Arg::new("key-command")
+                        .long("key-command")
+                        .value_name("command")
+                        .takes_value(true)
+                        .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file", "password-fd"])
+                        .help("Use the output of a program as the key, without a shell (e.g. 'pass show backups/dexios')"),
+                )
+                .arg(
+                    Arg::new("password-file")
+                        .long("password-file")
+                        .value_name("file")
+                        .takes_value(true)
+                        .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command"])
+                        .help("Read the password from a file (or STDIN with '-'), without its trailing newline"),
+                )
+                .arg(
+                    Arg::new("password-fd")
+                        .long("password-fd")
+                        .value_name("fd")
+                        .takes_value(true)
+                        .value_parser(clap::value_parser!(u32))
+                        .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file"])
+                        .help("Read the password from an inherited file descriptor, without its trailing newline"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .short('f')
+                        .long("force")
+                        .takes_value(false)
+                        .help("Also replace headers that are valid, but differ from the bundle (e.g. after a key was changed)"),
+                ),
+        )
+        .subcommand(
+            Command::new("kdf")
+                .about("Inspect the supported key derivation functions")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("bench")
+                        .about("Benchmark every supported KDF on this machine"),
+                ),
+        )
+        .subcommand(
+            Command::new("info")
+                .about("Report hardware acceleration and supported features, and recommend an algorithm"),
+        )
+        .subcommand(
+            Command::new("version")
+                .about("Show the version, along with the formats, algorithms, KDFs and backends that this build supports")
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .takes_value(false)
+                        .help("Print it as JSON, so deployment tooling can check that two hosts are compatible"),
+                ),
+        )
+        .subcommand(
+            Command::new("examples")
+                .about("Show examples of common tasks, such as encrypting with a keyfile or backing up a header")
+                .arg(
+                    Arg::new("command")
+                        .value_name("command")
+                        .takes_value(true)
+                        .multiple_values(true)
+                        .help("Only show the examples of this subcommand (e.g. `header` or `key rotate`)"),
+                ),
+        )
+        .subcommand(
+            Command::new("send")
+                .about("Encrypt a file and send it directly to a listening peer")
+                .arg(
+                    Arg::new("input")
+                        .value_name("input")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The file to send"),
+                )
+                .arg(
+                    Arg::new("address")
+                        .value_name("address")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The peer's address (e.g. 192.168.1.10:7700)"),
+                )
+                .arg(
+                    Arg::new("code")
+                        .long("code")
+                        .value_name("code")
+                        .takes_value(true)
+                        .help("Use the code shown by `dexios receive --code` instead of a key")
+                        .conflicts_with_all(&["keyfile", "autogenerate"]),
+                )
+                .arg(
+                    Arg::new("keyfile")
+                        .short('k')
+                        .long("keyfile")
+                        .multiple_occurrences(true)
+                        .value_name("file")
+                        .takes_value(true)
+                        .help("Use a keyfile instead of a password (repeat to require several keyfiles)"),
+                )
+                .arg(
+                    Arg::new("keyfile-fd")
+                        .long("keyfile-fd")
+                        .value_name("fd")
+                        .takes_value(true)
+                        .value_parser(clap::value_parser!(u32))
+                        .conflicts_with_all(&["keyfile", "password-command"])
+                        .help("Read the keyfile from an inherited file descriptor (a keyfile may also be set with DEXIOS_KEYFILE)"),
+                )
+                .arg(
+                    Arg::new("password-command")
+                        .long("password-command")
+                        .value_name("command")
+                        .takes_value(true)
+                        .conflicts_with("keyfile")
+                        .help("Use the output of a command as the key, run without a shell (e.g. 'pass show dexios')"),
+                )
+                .arg(
+                    Arg::new("key-command")
+                        .long("key-command")
+                        .value_name("command")
+                        .takes_value(true)
+                        .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file", "password-fd"])
+                        .help("Use the output of a program as the key, without a shell (e.g. 'pass show backups/dexios')"),
+                )
+                .arg(
+                    Arg::new("password-file")
+                        .long("password-file")
+                        .value_name("file")
+                        .takes_value(true)
+                        .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command"])
+                        .help("Read the password from a file (or STDIN with '-'), without its trailing newline"),
+                )
+                .arg(
+                    Arg::new("password-fd")
+                        .long("password-fd")
+                        .value_name("fd")
+                        .takes_value(true)
+                        .value_parser(clap::value_parser!(u32))
+                        .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file"])
+                        .help("Read the password from an inherited file descriptor, without its trailing newline"),
+                )
+                .arg(
+                    Arg::new("autogenerate")
+                        .long("auto")
+                        .value_name("# of words")
+                        .min_values(0)
+                        .default_missing_value("7")
+                        .takes_value(true)
+                        .require_equals(true)
+                        .help("Autogenerate a passphrase (default is 7 words)")
+                        .conflicts_with("keyfile"),
+                )
+                .arg(
+                    Arg::new("argon")
+                        .long("argon")
+                        .takes_value(false)
+                        .help("Use argon2id for password hashing"),
+                )
+                .arg(
+                    Arg::new("kdf")
+                        .long("kdf")
+                        .value_name("kdf")
+                        .takes_value(true)
+                        .value_parser(["argon2id", "blake3-balloon", "scrypt"])
+                        .conflicts_with("argon")
+                        .help("The KDF to use for password hashing (default is blake3-balloon)"),
+                )
+                .arg(
+                    Arg::new("algorithm")
+                        .long("algorithm")
+                        .value_name("algorithm")
+                        .takes_value(true)
+                        .help("The AEAD to encrypt with: xchacha20-poly1305 (default), aes-256-gcm, deoxys-ii-256, aegis-256, chacha20-poly1305 or ascon-128a"),
+                )
+                .arg(
+                    Arg::new("aes")
+                        .long("aes")
+                        .takes_value(false)
+                        .hide(true)
+                        .conflicts_with("algorithm")
+                        .help("Use AES-256-GCM for encryption (the same as --algorithm aes-256-gcm)"),
+                )
+                .arg(
+                    Arg::new("aegis")
+                        .long("aegis")
+                        .takes_value(false)
+                        .hide(true)
+                        .conflicts_with_all(&["aes", "algorithm"])
+                        .help("Use AEGIS-256 for encryption (the same as --algorithm aegis-256)"),
+                )
+                .arg(
+                    Arg::new("chacha20")
+                        .long("chacha20")
+                        .takes_value(false)
+                        .hide(true)
+                        .conflicts_with_all(&["aes", "aegis", "algorithm"])
+                        .help("Use ChaCha20-Poly1305 for encryption (the same as --algorithm chacha20-poly1305)"),
+                )
+                .arg(
+                    Arg::new("ascon")
+                        .long("ascon")
+                        .takes_value(false)
+                        .hide(true)
+                        .conflicts_with_all(&["aes", "aegis", "chacha20", "algorithm"])
+                        .help("Use Ascon-128a for encryption (the same as --algorithm ascon-128a)"),
+                ),
+        )
+        .subcommand(
+            Command::new("receive")
+                .about("Receive a file from a peer, and decrypt it")
+                .arg(
+                    Arg::new("output")
+                        .value_name("output")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The output file"),
+                )
+                .arg(
+                    Arg::new("listen")
+                        .long("listen")
+                        .value_name("address")
+                        .takes_value(true)
+                        .default_value("0.0.0.0:7700")
+                        .help("The address to listen on"),
+                )
+                .arg(
+                    Arg::new("code")
+                        .long("code")
+                        .takes_value(false)
+                        .help("Generate a short code for the sender to use, instead of sharing a key"),
+                )
+                .arg(
+                    Arg::new("keep-encrypted")
+                        .long("keep-encrypted")
+                        .takes_value(false)
+                        .help("Store the received file encrypted (with your key, if a code was used)"),
+                )
+                .arg(
+                    Arg::new("keyfile")
+                        .short('k')
+                        .long("keyfile")
+                        .multiple_occurrences(true)
+                        .value_name("file")
+                        .takes_value(true)
+                        .help("Use a keyfile instead of a password (repeat to require several keyfiles)"),
+                )
+                .arg(
+                    Arg::new("keyfile-fd")
+                        .long("keyfile-fd")
+                        .value_name("fd")
+                        .takes_value(true)
+                        .value_parser(clap::value_parser!(u32))
+                        .conflicts_with_all(&["keyfile", "password-command"])
+                        .help("Read the keyfile from an inherited file descriptor (a keyfile may also be set with DEXIOS_KEYFILE)"),
+                )
+                .arg(
+                    Arg::new("password-command")
+                        .long("password-command")
+                        .value_name("command")
+                        .takes_value(true)
+                        .conflicts_with("keyfile")
+                        .help("Use the output of a command as the key, run without a shell (e.g. 'pass show dexios')"),
+                )
+                .arg(
+                    Arg::new("key-command")
+                        .long("key-command")
+                        .value_name("command")
+                        .takes_value(true)
+                        .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file", "password-fd"])
+                        .help("Use the output of a program as the key, without a shell (e.g. 'pass show backups/dexios')"),
+                )
+                .arg(
+                    Arg::new("password-file")
+                        .long("password-file")
+                        .value_name("file")
+                        .takes_value(true)
+                        .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command"])
+                        .help("Read the password from a file (or STDIN with '-'), without its trailing newline"),
+                )
+                .arg(
+                    Arg::new("password-fd")
+                        .long("password-fd")
+                        .value_name("fd")
+                        .takes_value(true)
+                        .value_parser(clap::value_parser!(u32))
+                        .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file"])
+                        .help("Read the password from an inherited file descriptor, without its trailing newline"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .short('f')
+                        .long("force")
+                        .takes_value(false)
+                        .help("Force all actions"),
+                ),
+        )
+        .subcommand(Command::new("key")
+                .about("Manipulate keys within the header (for advanced users")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("change")
+                        .about("Change an encrypted file's key")
+                        .arg_required_else_help(true)
+                        .arg(
+                            Arg::new("input")
+                                .value_name("input")
+                                .takes_value(true)
+                                .required(true)
+                                .help("The encrypted file/header file"),
+                        )
+                        .arg(
+                            Arg::new("autogenerate")
+                                .long("auto")
+                                .value_name("# of words")
+                                .min_values(0)
+                                .default_missing_value("7")
+                                .takes_value(true)
+                                .require_equals(true)
+                                .help("Autogenerate a passphrase (default is 7 words)")
+                                .conflicts_with("keyfile-new"),
+                        )
+                        .arg(
+                            Arg::new("argon")
+                                .long("argon")
+                                .takes_value(false)
+                                .help("Use argon2id for password hashing"),
+                        )
+                        .arg(
+                            Arg::new("kdf")
+                                .long("kdf")
+                                .value_name("kdf")
+                                .takes_value(true)
+                                .value_parser(["argon2id", "blake3-balloon", "scrypt"])
+                                .conflicts_with("argon")
+                                .help("The KDF to use for password hashing (default is blake3-balloon)"),
+                        )
+                        .arg(
+                            Arg::new("kdf-memory")
+                                .long("kdf-memory")
+                                .value_name("MiB")
+                                .takes_value(true)
+                                .value_parser(clap::value_parser!(u32).range(1..=4096))
+                                .help("The amount of memory the password hashing uses, in MiB, up to 4096 (stored in the header)"),
+                        )
+                        .arg(
+                            Arg::new("kdf-iterations")
+                                .long("kdf-iterations")
+                                .value_name("#")
+                                .takes_value(true)
+                                .value_parser(clap::value_parser!(u32).range(1..=255))
+                                .help("The number of iterations the password hashing uses (stored in the header)"),
+                        )
+                        .arg(
+                            Arg::new("kdf-parallelism")
+                                .long("kdf-parallelism")
+                                .value_name("#")
+                                .takes_value(true)
+                                .value_parser(clap::value_parser!(u32).range(1..=255))
+                                .help("The number of lanes the password hashing uses (stored in the header)"),
+                        )
+                        .arg(
+                            Arg::new("keyfile-old")
+                                .short('k')
+                                .long("keyfile-old")
+                                .multiple_occurrences(true)
+                                .value_name("file")
+                                .takes_value(true)
+                                .help("Use an old keyfile to decrypt the master key"),
+                        )
+                        .arg(
+                            Arg::new("password-command-old")
+                                .long("password-command-old")
+                                .value_name("command")
+                                .takes_value(true)
+                                .conflicts_with("keyfile-old")
+                                .help("Use the output of a command as the old key, run without a shell (e.g. 'pass show dexios')"),
+                        )
+                        .arg(
+                            Arg::new("key-command-old")
+                                .long("key-command-old")
+                                .value_name("command")
+                                .takes_value(true)
+                                .conflicts_with_all(&["keyfile-old", "password-command-old", "password-file-old"])
+                                .help("Use the output of a program as the old key, without a shell (e.g. 'pass show backups/dexios')"),
+                        )
+                        .arg(
+                            Arg::new("password-file-old")
+                                .long("password-file-old")
+                                .value_name("file")
+                                .takes_value(true)
+                                .conflicts_with_all(&["keyfile-old", "password-command-old"])
+                                .help("Read the old password from a file (or STDIN with '-'), without its trailing newline"),
+                        )
+                        .arg(
+                            Arg::new("keyfile-new")
+                                .short('n')
+                                .long("keyfile-new")
+                                .multiple_occurrences(true)
+                                .value_name("file")
+                                .takes_value(true)
+                                .help("Use a keyfile as the new key"),
+                        )
+                        .arg(
+                            Arg::new("password-command-new")
+                                .long("password-command-new")
+                                .value_name("command")
+                                .takes_value(true)
+                                .conflicts_with("keyfile-new")
+                                .help("Use the output of a command as the new key, run without a shell (e.g. 'pass show dexios')"),
+                        )
+                        .arg(
+                            Arg::new("key-command-new")
+                                .long("key-command-new")
+                                .value_name("command")
+                                .takes_value(true)
+                                .conflicts_with_all(&["keyfile-new", "password-command-new", "password-file-new"])
+                                .help("Use the output of a program as the new key, without a shell (e.g. 'pass show backups/dexios')"),
+                        )
+                        .arg(
+                            Arg::new("password-file-new")
+                                .long("password-file-new")
+                                .value_name("file")
+                                .takes_value(true)
+                                .conflicts_with_all(&["keyfile-new", "password-command-new"])
+                                .help("Read the new password from a file (or STDIN with '-'), without its trailing newline"),
+                        ),
+                )
+                .subcommand(
+                                        Command::new("rotate")
+                        .about("Re-encrypt a file with a new master key, as well as a new key")
+                        .long_about("Re-encrypt a file with a new master key and new nonces, and wrap it with a new key. Unlike `key change`, this replaces every key, so it's suitable if the master key may have been exposed. The file is decrypted and encrypted again at the same time, so its plaintext is never written to disk, and the file is only replaced once it's complete. The new file only has a single keyslot.")
+                        .arg_required_else_help(true)
+                        .arg(
+                            Arg::new("input")
+                                .value_name("input")
+                                .takes_value(true)
+                                .required(true)
+                                .help("The encrypted file (its header must be embedded)"),
+                        )
+                        .arg(
+                            Arg::new("autogenerate")
+                                .long("auto")
+                                .value_name("# of words")
+                                .min_values(0)
+                                .default_missing_value("7")
+                                .takes_value(true)
+                                .require_equals(true)
+                                .help("Autogenerate a passphrase (default is 7 words)")
+                                .conflicts_with("keyfile-new"),
+                        )
+                        .arg(
+                            Arg::new("argon")
+                                .long("argon")
+                                .takes_value(false)
+                                .help("Use argon2id for password hashing"),
+                        )
+                        .arg(
+                            Arg::new("kdf")
+                                .long("kdf")
+                                .value_name("kdf")
+                                .takes_value(true)
+                                .value_parser(["argon2id", "blake3-balloon", "scrypt"])
+                                .conflicts_with("argon")
+                                .help("The KDF to use for password hashing (default is blake3-balloon)"),
+                        )
+                        .arg(
+                            Arg::new("kdf-memory")
+                                .long("kdf-memory")
+                                .value_name("MiB")
+                                .takes_value(true)
+                                .value_parser(clap::value_parser!(u32).range(1..=4096))
+                                .help("The amount of memory the password hashing uses, in MiB, up to 4096 (stored in the header)"),
+                        )
+                        .arg(
+                            Arg::new("kdf-iterations")
+                                .long("kdf-iterations")
+                                .value_name("#")
+                                .takes_value(true)
+                                .value_parser(clap::value_parser!(u32).range(1..=255))
+                                .help("The number of iterations the password hashing uses (stored in the header)"),
+                        )
+                        .arg(
+                            Arg::new("kdf-parallelism")
+                                .long("kdf-parallelism")
+                                .value_name("#")
+                                .takes_value(true)
+                                .value_parser(clap::value_parser!(u32).range(1..=255))
+                                .help("The number of lanes the password hashing uses (stored in the header)"),
+                        )
+                        .arg(
+                            Arg::new("keyfile-old")
+                                .short('k')
+                                .long("keyfile-old")
+                                .multiple_occurrences(true)
+                                .value_name("file")
+                                .takes_value(true)
+                                .help("Use an old keyfile to decrypt the master key"),
+                        )
+                        .arg(
+                            Arg::new("password-command-old")
+                                .long("password-command-old")
+                                .value_name("command")
+                                .takes_value(true)
+                                .conflicts_with("keyfile-old")
+                                .help("Use the output of a command as the old key, run without a shell (e.g. 'pass show dexios')"),
                         )
                         .arg(
-                            Arg::new("argon")
-                                .long("argon")
-                                .takes_value(false)
-                                .help("Use argon2id for password hashing"),
+                            Arg::new("key-command-old")
+                                .long("key-command-old")
+                                .value_name("command")
+                                .takes_value(true)
+                                .conflicts_with_all(&["keyfile-old", "password-command-old", "password-file-old"])
+                                .help("Use the output of a program as the old key, without a shell (e.g. 'pass show backups/dexios')"),
                         )
                         .arg(
-                            Arg::new("keyfile-old")
-                                .short('k')
-                                .long("keyfile-old")
+                            Arg::new("password-file-old")
+                                .long("password-file-old")
                                 .value_name("file")
                                 .takes_value(true)
-                                .help("Use an old keyfile to decrypt the master key"),
+                                .conflicts_with_all(&["keyfile-old", "password-command-old"])
+                                .help("Read the old password from a file (or STDIN with '-'), without its trailing newline"),
                         )
                         .arg(
                             Arg::new("keyfile-new")
                                 .short('n')
                                 .long("keyfile-new")
+                                .multiple_occurrences(true)
                                 .value_name("file")
                                 .takes_value(true)
                                 .help("Use a keyfile as the new key"),
+                        )
+                        .arg(
+                            Arg::new("password-command-new")
+                                .long("password-command-new")
+                                .value_name("command")
+                                .takes_value(true)
+                                .conflicts_with("keyfile-new")
+                                .help("Use the output of a command as the new key, run without a shell (e.g. 'pass show dexios')"),
+                        )
+                        .arg(
+                            Arg::new("key-command-new")
+                                .long("key-command-new")
+                                .value_name("command")
+                                .takes_value(true)
+                                .conflicts_with_all(&["keyfile-new", "password-command-new", "password-file-new"])
+                                .help("Use the output of a program as the new key, without a shell (e.g. 'pass show backups/dexios')"),
+                        )
+                        .arg(
+                            Arg::new("password-file-new")
+                                .long("password-file-new")
+                                .value_name("file")
+                                .takes_value(true)
+                                .conflicts_with_all(&["keyfile-new", "password-command-new"])
+                                .help("Read the new password from a file (or STDIN with '-'), without its trailing newline"),
                         ),
                 )
                 .subcommand(
@@ -420,6 +2632,39 @@ pub fn get_matches() -> clap::ArgMatches {
                                 .takes_value(false)
                                 .help("Use argon2id for password hashing"),
                         )
+                        .arg(
+                            Arg::new("kdf")
+                                .long("kdf")
+                                .value_name("kdf")
+                                .takes_value(true)
+                                .value_parser(["argon2id", "blake3-balloon", "scrypt"])
+                                .conflicts_with("argon")
+                                .help("The KDF to use for password hashing (default is blake3-balloon)"),
+                        )
+                        .arg(
+                            Arg::new("kdf-memory")
+                                .long("kdf-memory")
+                                .value_name("MiB")
+                                .takes_value(true)
+                                .value_parser(clap::value_parser!(u32).range(1..=4096))
+                                .help("The amount of memory the password hashing uses, in MiB, up to 4096 (stored in the header)"),
+                        )
+                        .arg(
+                            Arg::new("kdf-iterations")
+                                .long("kdf-iterations")
+                                .value_name("#")
+                                .takes_value(true)
+                                .value_parser(clap::value_parser!(u32).range(1..=255))
+                                .help("The number of iterations the password hashing uses (stored in the header)"),
+                        )
+                        .arg(
+                            Arg::new("kdf-parallelism")
+                                .long("kdf-parallelism")
+                                .value_name("#")
+                                .takes_value(true)
+                                .value_parser(clap::value_parser!(u32).range(1..=255))
+                                .help("The number of lanes the password hashing uses (stored in the header)"),
+                        )
                         .arg(
                             Arg::new("autogenerate")
                                 .long("auto")
@@ -429,23 +2674,79 @@ pub fn get_matches() -> clap::ArgMatches {
                                 .takes_value(true)
                                 .require_equals(true)
                                 .help("Autogenerate a passphrase (default is 7 words)")
-                                .conflicts_with("keyfile"),
+                                .conflicts_with("keyfile-new"),
                         )
                         .arg(
                             Arg::new("keyfile-old")
                                 .short('k')
                                 .long("keyfile-old")
+                                .multiple_occurrences(true)
                                 .value_name("file")
                                 .takes_value(true)
                                 .help("Use an old keyfile to decrypt the master key"),
                         )
+                        .arg(
+                            Arg::new("password-command-old")
+                                .long("password-command-old")
+                                .value_name("command")
+                                .takes_value(true)
+                                .conflicts_with("keyfile-old")
+                                .help("Use the output of a command as the old key, run without a shell (e.g. 'pass show dexios')"),
+                        )
+                        .arg(
+                            Arg::new("key-command-old")
+                                .long("key-command-old")
+                                .value_name("command")
+                                .takes_value(true)
+                                .conflicts_with_all(&["keyfile-old", "password-command-old", "password-file-old"])
+                                .help("Use the output of a program as the old key, without a shell (e.g. 'pass show backups/dexios')"),
+                        )
+                        .arg(
+                            Arg::new("password-file-old")
+                                .long("password-file-old")
+                                .value_name("file")
+                                .takes_value(true)
+                                .conflicts_with_all(&["keyfile-old", "password-command-old"])
+                                .help("Read the old password from a file (or STDIN with '-'), without its trailing newline"),
+                        )
                         .arg(
                             Arg::new("keyfile-new")
                                 .short('n')
                                 .long("keyfile-new")
+                                .multiple_occurrences(true)
                                 .value_name("file")
                                 .takes_value(true)
                                 .help("Use a keyfile as the new key"),
+                        )
+                        .arg(
+                            Arg::new("password-command-new")
+                                .long("password-command-new")
+                                .value_name("command")
+                                .takes_value(true)
+                                .conflicts_with("keyfile-new")
+                                .help("Use the output of a command as the new key, run without a shell (e.g. 'pass show dexios')"),
+                        )
+                        .arg(
+                            Arg::new("key-command-new")
+                                .long("key-command-new")
+                                .value_name("command")
+                                .takes_value(true)
+                                .conflicts_with_all(&["keyfile-new", "password-command-new", "password-file-new"])
+                                .help("Use the output of a program as the new key, without a shell (e.g. 'pass show backups/dexios')"),
+                        )
+                        .arg(
+                            Arg::new("password-file-new")
+                                .long("password-file-new")
+                                .value_name("file")
+                                .takes_value(true)
+                                .conflicts_with_all(&["keyfile-new", "password-command-new"])
+                                .help("Read the new password from a file (or STDIN with '-'), without its trailing newline"),
+                        )
+                        .arg(
+                            Arg::new("metadata-only")
+                                .long("metadata-only")
+                                .takes_value(false)
+                                .help("Only allow the new key to list the file's manifest, rather than decrypt it"),
                         ),
                 )
                 .subcommand(
@@ -463,9 +2764,138 @@ pub fn get_matches() -> clap::ArgMatches {
                             Arg::new("keyfile")
                                 .short('k')
                                 .long("keyfile")
+                                .multiple_occurrences(true)
                                 .value_name("file")
                                 .takes_value(true)
                                 .help("Use a keyfile to identify the key you want to delete"),
+                        )
+                        .arg(
+                            Arg::new("keyfile-fd")
+                                .long("keyfile-fd")
+                                .value_name("fd")
+                                .takes_value(true)
+                                .value_parser(clap::value_parser!(u32))
+                                .conflicts_with_all(&["keyfile", "password-command"])
+                                .help("Read the keyfile from an inherited file descriptor (a keyfile may also be set with DEXIOS_KEYFILE)"),
+                        )
+                        .arg(
+                            Arg::new("password-command")
+                                .long("password-command")
+                                .value_name("command")
+                                .takes_value(true)
+                                .conflicts_with("keyfile")
+                                .help("Use the output of a command as the key, run without a shell (e.g. 'pass show dexios')"),
+                        )
+                        .arg(
+                            Arg::new("key-command")
+                                .long("key-command")
+                                .value_name("command")
+                                .takes_value(true)
+                                .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file", "password-fd"])
+                                .help("Use the output of a program as the key, without a shell (e.g. 'pass show backups/dexios')"),
+                        )
+                        .arg(
+                            Arg::new("password-file")
+                                .long("password-file")
+                                .value_name("file")
+                                .takes_value(true)
+                                .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command"])
+                                .help("Read the password from a file (or STDIN with '-'), without its trailing newline"),
+                        )
+                        .arg(
+                            Arg::new("password-fd")
+                                .long("password-fd")
+                                .value_name("fd")
+                                .takes_value(true)
+                                .value_parser(clap::value_parser!(u32))
+                                .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file"])
+                                .help("Read the password from an inherited file descriptor, without its trailing newline"),
+                        )
+                        .arg(
+                            Arg::new("slot")
+                                .long("slot")
+                                .value_name("index")
+                                .takes_value(true)
+                                .value_parser(clap::value_parser!(usize))
+                                .help("Delete this keyslot instead (see `key list`) - the key may unlock any keyslot"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("List the keyslots within an encrypted file")
+                        .arg_required_else_help(true)
+                        .arg(
+                            Arg::new("input")
+                                .value_name("input")
+                                .takes_value(true)
+                                .required(true)
+                                .help("The encrypted file/header file"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("keypair")
+                        .about("Generate a hybrid (X25519 + ML-KEM-768) recipient keypair")
+                        .arg_required_else_help(true)
+                        .arg(
+                            Arg::new("output")
+                                .value_name("output")
+                                .takes_value(true)
+                                .required(true)
+                                .help("The file to write the secret key to (the public key is written to <output>.pub)"),
+                        )
+                        .arg(
+                            Arg::new("signing")
+                                .long("signing")
+                                .takes_value(false)
+                                .help("Generate an Ed25519 signing keypair, instead of a recipient keypair"),
+                        )
+                        .arg(
+                            Arg::new("x25519")
+                                .long("x25519")
+                                .takes_value(false)
+                                .conflicts_with("signing")
+                                .help("Generate a plain X25519 recipient keypair, which is far smaller but offers no protection against quantum computers"),
+                        )
+                        .arg(
+                            Arg::new("force")
+                                .short('f')
+                                .long("force")
+                                .takes_value(false)
+                                .help("Force all actions"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("generate")
+                        .about("Generate a random keyfile")
+                        .arg_required_else_help(true)
+                        .arg(
+                            Arg::new("output")
+                                .value_name("output")
+                                .takes_value(true)
+                                .required(true)
+                                .help("The file to write the keyfile to"),
+                        )
+                        .arg(
+                            Arg::new("size")
+                                .long("size")
+                                .value_name("bytes")
+                                .takes_value(true)
+                                .value_parser(clap::value_parser!(u64).range(16..=1_048_576))
+                                .default_value("64")
+                                .help("The size of the keyfile, in bytes"),
+                        )
+                        .arg(
+                            Arg::new("fingerprint")
+                                .long("fingerprint")
+                                .takes_value(false)
+                                .help("Print the keyfile's BLAKE3 hash, so copies of it can be checked later"),
+                        )
+                        .arg(
+                            Arg::new("force")
+                                .short('f')
+                                .long("force")
+                                .takes_value(false)
+                                .help("Force all actions"),
                         ),
                 )
                 .subcommand(
@@ -483,9 +2913,158 @@ pub fn get_matches() -> clap::ArgMatches {
                             Arg::new("keyfile")
                                 .short('k')
                                 .long("keyfile")
+                                .multiple_occurrences(true)
                                 .value_name("file")
                                 .takes_value(true)
                                 .help("Verify a keyfile"),
+                        )
+                        .arg(
+                            Arg::new("keyfile-fd")
+                                .long("keyfile-fd")
+                                .value_name("fd")
+                                .takes_value(true)
+                                .value_parser(clap::value_parser!(u32))
+                                .conflicts_with_all(&["keyfile", "password-command"])
+                                .help("Read the keyfile from an inherited file descriptor (a keyfile may also be set with DEXIOS_KEYFILE)"),
+                        )
+                        .arg(
+                            Arg::new("password-command")
+                                .long("password-command")
+                                .value_name("command")
+                                .takes_value(true)
+                                .conflicts_with("keyfile")
+                                .help("Use the output of a command as the key, run without a shell (e.g. 'pass show dexios')"),
+                        )
+                        .arg(
+                            Arg::new("key-command")
+                                .long("key-command")
+                                .value_name("command")
+                                .takes_value(true)
+                                .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file", "password-fd"])
+                                .help("Use the output of a program as the key, without a shell (e.g. 'pass show backups/dexios')"),
+                        )
+                        .arg(
+                            Arg::new("password-file")
+                                .long("password-file")
+                                .value_name("file")
+                                .takes_value(true)
+                                .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command"])
+                                .help("Read the password from a file (or STDIN with '-'), without its trailing newline"),
+                        )
+                        .arg(
+                            Arg::new("password-fd")
+                                .long("password-fd")
+                                .value_name("fd")
+                                .takes_value(true)
+                                .value_parser(clap::value_parser!(u32))
+                                .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file"])
+                                .help("Read the password from an inherited file descriptor, without its trailing newline"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("export-mnemonic")
+                        .about("Print the master key as a 24-word BIP39 mnemonic, for a paper backup (see `decrypt --mnemonic`)")
+                        .arg_required_else_help(true)
+                        .arg(
+                            Arg::new("input")
+                                .value_name("input")
+                                .takes_value(true)
+                                .required(true)
+                                .help("The encrypted file/header file"),
+                        )
+                        .arg(
+                            Arg::new("keyfile")
+                                .short('k')
+                                .long("keyfile")
+                                .multiple_occurrences(true)
+                                .value_name("file")
+                                .takes_value(true)
+                                .help("Use a keyfile to unlock the master key"),
+                        )
+                        .arg(
+                            Arg::new("keyfile-fd")
+                                .long("keyfile-fd")
+                                .value_name("fd")
+                                .takes_value(true)
+                                .value_parser(clap::value_parser!(u32))
+                                .conflicts_with_all(&["keyfile", "password-command"])
+                                .help("Read the keyfile from an inherited file descriptor (a keyfile may also be set with DEXIOS_KEYFILE)"),
+                        )
+                        .arg(
+                            Arg::new("password-command")
+                                .long("password-command")
+                                .value_name("command")
+                                .takes_value(true)
+                                .conflicts_with("keyfile")
+                                .help("Use the output of a command as the key, run without a shell (e.g. 'pass show dexios')"),
+                        )
+                        .arg(
+                            Arg::new("key-command")
+                                .long("key-command")
+                                .value_name("command")
+                                .takes_value(true)
+                                .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file", "password-fd"])
+                                .help("Use the output of a program as the key, without a shell (e.g. 'pass show backups/dexios')"),
+                        )
+                        .arg(
+                            Arg::new("password-file")
+                                .long("password-file")
+                                .value_name("file")
+                                .takes_value(true)
+                                .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command"])
+                                .help("Read the password from a file (or STDIN with '-'), without its trailing newline"),
+                        )
+                        .arg(
+                            Arg::new("password-fd")
+                                .long("password-fd")
+                                .value_name("fd")
+                                .takes_value(true)
+                                .value_parser(clap::value_parser!(u32))
+                                .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file"])
+                                .help("Read the password from an inherited file descriptor, without its trailing newline"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("calibrate")
+                        .about("Find KDF parameters that take a given amount of time on this machine")
+                        .arg(
+                            Arg::new("argon")
+                                .long("argon")
+                                .takes_value(false)
+                                .help("Calibrate argon2id instead of BLAKE3-Balloon"),
+                        )
+                        .arg(
+                            Arg::new("kdf")
+                                .long("kdf")
+                                .value_name("kdf")
+                                .takes_value(true)
+                                .value_parser(["argon2id", "blake3-balloon", "scrypt"])
+                                .conflicts_with("argon")
+                                .help("The KDF to use for password hashing (default is blake3-balloon)"),
+                        )
+                        .arg(
+                            Arg::new("target")
+                                .long("target")
+                                .value_name("ms")
+                                .takes_value(true)
+                                .value_parser(clap::value_parser!(u64).range(100..=60_000))
+                                .default_value("1000")
+                                .help("How long deriving a key should take, in milliseconds"),
+                        )
+                        .arg(
+                            Arg::new("max-memory")
+                                .long("max-memory")
+                                .value_name("MiB")
+                                .takes_value(true)
+                                .value_parser(clap::value_parser!(u32).range(8..))
+                                .default_value("1024")
+                                .help("The most memory that deriving a key may use, in MiB"),
+                        )
+                        .arg(
+                            Arg::new("write")
+                                .long("write")
+                                .takes_value(false)
+                                .help("Save the parameters to the config file, so they're used by default"),
                         ),
                 )
          )
@@ -502,14 +3081,27 @@ pub fn get_matches() -> clap::ArgMatches {
                                 .value_name("input")
                                 .takes_value(true)
                                 .required(true)
-                                .help("The encrypted file"),
+                                .help("The encrypted file (if it was split, its first volume is used)"),
                         )
                         .arg(
                             Arg::new("output")
                                 .value_name("output")
                                 .takes_value(true)
                                 .required(true)
-                                .help("The output file"),
+                                .help("The output file (or - for stdout, which implies --quiet)"),
+                        )
+                        .arg(
+                            Arg::new("armor")
+                                .long("armor")
+                                .takes_value(false)
+                                .help("Write the header as a base64 text block, which can be pasted into a password manager or printed (`header restore` accepts either form)"),
+                        )
+                        .arg(
+                            Arg::new("qr")
+                                .long("qr")
+                                .takes_value(false)
+                                .conflicts_with("armor")
+                                .help("Write the header as a QR code PNG for a paper backup, or draw it in the terminal if the output is - (`header restore --qr-image` reads it back)"),
                         )
                         .arg(
                             Arg::new("force")
@@ -528,26 +3120,114 @@ pub fn get_matches() -> clap::ArgMatches {
                                 .value_name("input")
                                 .takes_value(true)
                                 .required(true)
-                                .help("The dumped header file"),
+                                .help("The dumped header file (which may be armored)"),
                         )
                         .arg(
                             Arg::new("output")
                                 .value_name("output")
                                 .takes_value(true)
                                 .required(true)
-                                .help("The encrypted file"),
+                                .help("The encrypted file (if it was split, its first volume is used)"),
+                        )
+                        .arg(
+                            Arg::new("qr-image")
+                                .long("qr-image")
+                                .takes_value(false)
+                                .help("The input is a photo, scan or screenshot (PNG or JPEG) of a QR code from `header dump --qr`"),
                         ),
                 )
                 .subcommand(
                     Command::new("strip")
                         .about("Strip a header")
                         .arg_required_else_help(true)
+                        .arg(
+                            Arg::new("input")
+                                .value_name("input")
+                                .takes_value(true)
+                                .required(true)
+                                .help("The encrypted file (if it was split, its first volume is used)"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("recover")
+                        .about("Repair a damaged header with the copy at the end of the file (see `encrypt --backup-header`)")
+                        .arg_required_else_help(true)
+                        .arg(
+                            Arg::new("input")
+                                .value_name("input")
+                                .takes_value(true)
+                                .required(true)
+                                .help("The encrypted file"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("verify")
+                        .about("Check that a header is valid, that a key unlocks it, and that it matches the encrypted data (nothing is written)")
+                        .arg_required_else_help(true)
                         .arg(
                             Arg::new("input")
                                 .value_name("input")
                                 .takes_value(true)
                                 .required(true)
                                 .help("The encrypted file"),
+                        )
+                        .arg(
+                            Arg::new("header")
+                                .long("header")
+                                .value_name("file")
+                                .takes_value(true)
+                                .help("Check this detached header against the encrypted file, instead of the header within it"),
+                        )
+                        .arg(
+                            Arg::new("keyfile")
+                                .short('k')
+                                .long("keyfile")
+                                .multiple_occurrences(true)
+                                .value_name("file")
+                                .takes_value(true)
+                                .help("Use a keyfile instead of a password"),
+                        )
+                        .arg(
+                            Arg::new("keyfile-fd")
+                                .long("keyfile-fd")
+                                .value_name("fd")
+                                .takes_value(true)
+                                .value_parser(clap::value_parser!(u32))
+                                .conflicts_with_all(&["keyfile", "password-command"])
+                                .help("Read the keyfile from an inherited file descriptor (a keyfile may also be set with DEXIOS_KEYFILE)"),
+                        )
+                        .arg(
+                            Arg::new("password-command")
+                                .long("password-command")
+                                .value_name("command")
+                                .takes_value(true)
+                                .conflicts_with("keyfile")
+                                .help("Use the output of a command as the key, run without a shell (e.g. 'pass show dexios')"),
+                        )
+                        .arg(
+                            Arg::new("key-command")
+                                .long("key-command")
+                                .value_name("command")
+                                .takes_value(true)
+                                .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file", "password-fd"])
+                                .help("Use the output of a program as the key, without a shell (e.g. 'pass show backups/dexios')"),
+                        )
+                        .arg(
+                            Arg::new("password-file")
+                                .long("password-file")
+                                .value_name("file")
+                                .takes_value(true)
+                                .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command"])
+                                .help("Read the password from a file (or STDIN with '-'), without its trailing newline"),
+                        )
+                        .arg(
+                            Arg::new("password-fd")
+                                .long("password-fd")
+                                .value_name("fd")
+                                .takes_value(true)
+                                .value_parser(clap::value_parser!(u32))
+                                .conflicts_with_all(&["keyfile", "keyfile-fd", "password-command", "password-file"])
+                                .help("Read the password from an inherited file descriptor, without its trailing newline"),
                         ),
                 )
                 .subcommand(
@@ -559,9 +3239,14 @@ pub fn get_matches() -> clap::ArgMatches {
                                 .value_name("input")
                                 .takes_value(true)
                                 .required(true)
-                                .help("The encrypted/header file"),
+                                .help("The encrypted/header file (if it was split, its first volume is used)"),
+                        )
+                        .arg(
+                            Arg::new("json")
+                                .long("json")
+                                .takes_value(false)
+                                .help("Print the details as JSON, for scripting"),
                         ),
                 ),
         )
-        .get_matches()
 }
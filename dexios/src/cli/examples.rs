@@ -0,0 +1,311 @@
+use clap::Command;
+
+// each example is a real invocation, which is parsed by the tests below so that none of them go stale
+// they're shown by `dexios examples`, and after the `--help` of the subcommand that they run
+pub struct Example {
+    pub command: &'static str, // the subcommand, including its parents (e.g. `header dump`)
+    pub description: &'static str,
+    pub args: &'static [&'static str], // everything after `dexios`
+}
+
+pub const EXAMPLES: &[Example] = &[
+    Example {
+        command: "encrypt",
+        description: "Encrypt a file with a keyfile",
+        args: &["encrypt", "-k", "keyfile", "secret.txt", "secret.dx"],
+    },
+    Example {
+        command: "encrypt",
+        description:
+            "Encrypt a file with the most conservative options, and check that the output decrypts",
+        args: &["encrypt", "--paranoid", "secret.txt", "secret.dx"],
+    },
+    Example {
+        command: "encrypt",
+        description:
+            "Encrypt a file, and write its header to another file (it's needed to decrypt the file)",
+        args: &[
+            "encrypt",
+            "-k",
+            "keyfile",
+            "--detached-header",
+            "secret.hdr",
+            "secret.txt",
+            "secret.dx",
+        ],
+    },
+    Example {
+        command: "encrypt",
+        description: "Encrypt a file with a V4 header, so that it can be read by older versions of dexios",
+        args: &["encrypt", "--compat", "v4", "secret.txt", "secret.dx"],
+    },
+    Example {
+        command: "decrypt",
+        description: "Decrypt a file with a keyfile",
+        args: &["decrypt", "-k", "keyfile", "secret.dx", "secret.txt"],
+    },
+    Example {
+        command: "decrypt",
+        description: "Decrypt a file that was encrypted with --store-metadata, restoring its original name and modification time",
+        args: &["decrypt", "-k", "keyfile", "--restore-metadata", "secret.dx"],
+    },
+    Example {
+        command: "decrypt",
+        description: "Decrypt a file with a key that's kept in a password manager (the program is run without a shell)",
+        args: &[
+            "decrypt",
+            "--key-command",
+            "pass show backups/dexios",
+            "secret.dx",
+            "secret.txt",
+        ],
+    },
+    Example {
+        command: "pack",
+        description: "Pack a directory (and everything within it) into one compressed archive",
+        args: &[
+            "pack",
+            "-r",
+            "-z",
+            "-k",
+            "keyfile",
+            "documents",
+            "documents.dx",
+        ],
+    },
+    Example {
+        command: "pack",
+        description: "Pack a directory, piping any SQL dumps through gzip before they're archived",
+        args: &[
+            "pack",
+            "-r",
+            "--filter",
+            "*.sql:gzip",
+            "-k",
+            "keyfile",
+            "backups",
+            "backups.dx",
+        ],
+    },
+    Example {
+        command: "pack",
+        description: "Estimate how large a directory's archive will be, by compressing 5% of each type of file",
+        args: &[
+            "pack",
+            "-r",
+            "--zstd",
+            "--estimate=5",
+            "photos",
+            "photos.dx",
+        ],
+    },
+    Example {
+        command: "pack recompress",
+        description: "Compress an old backup's files again at a higher zstd level, keeping its keys and manifest",
+        args: &[
+            "pack",
+            "recompress",
+            "--compression",
+            "zstd:19",
+            "backup-2021.dx",
+            "backup-2021-archived.dx",
+        ],
+    },
+    Example {
+        command: "unpack",
+        description: "Unpack an archive into a directory",
+        args: &["unpack", "-k", "keyfile", "documents.dx", "documents"],
+    },
+    Example {
+        command: "header dump",
+        description: "Back up a file's header (the file can't be decrypted without it)",
+        args: &["header", "dump", "secret.dx", "secret.hdr"],
+    },
+    Example {
+        command: "header dump",
+        description: "Back up a file's header as text, e.g. to keep it in a password manager",
+        args: &["header", "dump", "--armor", "secret.dx", "secret.hdr.txt"],
+    },
+    Example {
+        command: "header dump",
+        description: "Back up a file's header as a QR code, which can be printed and kept on paper",
+        args: &["header", "dump", "--qr", "secret.dx", "secret.hdr.png"],
+    },
+    Example {
+        command: "header strip",
+        description: "Remove the header from a file, once it's been backed up",
+        args: &["header", "strip", "secret.dx"],
+    },
+    Example {
+        command: "header recover",
+        description: "Repair a damaged header, if the file was encrypted with --backup-header",
+        args: &["header", "recover", "secret.dx"],
+    },
+    Example {
+        command: "header verify",
+        description: "Check that a detached header unlocks and matches its file, without decrypting it to disk",
+        args: &["header", "verify", "secret.dx", "--header", "secret.hdr"],
+    },
+    Example {
+        command: "header restore",
+        description: "Restore a header that was backed up to the file that it was stripped from",
+        args: &["header", "restore", "secret.hdr", "secret.dx"],
+    },
+    Example {
+        command: "header restore",
+        description: "Restore a header from a photo of its QR code",
+        args: &["header", "restore", "--qr-image", "photo.jpg", "secret.dx"],
+    },
+    Example {
+        command: "header details",
+        description: "Show what a file's header contains, as JSON",
+        args: &["header", "details", "--json", "secret.dx"],
+    },
+    Example {
+        command: "key add",
+        description: "Allow a file to also be decrypted with another keyfile",
+        args: &[
+            "key",
+            "add",
+            "-k",
+            "keyfile",
+            "-n",
+            "spare-keyfile",
+            "secret.dx",
+        ],
+    },
+    Example {
+        command: "key rotate",
+        description: "Re-encrypt a file with a new master key, and replace its keyfile",
+        args: &[
+            "key",
+            "rotate",
+            "-k",
+            "keyfile",
+            "-n",
+            "new-keyfile",
+            "secret.dx",
+        ],
+    },
+    Example {
+        command: "upgrade",
+        description: "Upgrade every encrypted file within a directory to the latest header version",
+        args: &["upgrade", "-k", "keyfile", "archives/"],
+    },
+];
+
+// arguments are quoted if a shell would otherwise interpret them
+fn quote(arg: &str) -> String {
+    if arg
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "-_./=:".contains(c))
+    {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+pub fn invocation(example: &Example) -> String {
+    std::iter::once("dexios".to_string())
+        .chain(example.args.iter().map(|arg| quote(arg)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn format<'a>(examples: impl Iterator<Item = &'a Example>, indent: &str) -> String {
+    examples
+        .map(|example| {
+            format!(
+                "{indent}# {}\n{indent}{}",
+                example.description,
+                invocation(example),
+                indent = indent
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+// this is the text that's shown after the `--help` of each subcommand that has examples
+pub fn help_texts() -> Vec<(&'static str, String)> {
+    let mut commands = EXAMPLES
+        .iter()
+        .map(|example| example.command)
+        .collect::<Vec<_>>();
+    commands.dedup();
+
+    commands
+        .into_iter()
+        .map(|command| {
+            let examples = EXAMPLES.iter().filter(|example| example.command == command);
+            (command, format!("EXAMPLES:\n{}", format(examples, "    ")))
+        })
+        .collect()
+}
+
+fn add_help_to<'a>(command: Command<'a>, path: &[&str], text: &'a str) -> Command<'a> {
+    match path {
+        [] => command.after_help(text),
+        [name, rest @ ..] => {
+            let mut command = command;
+            // `mut_subcommand` would move the subcommand to the end of the list, so it's replaced in place
+            if let Some(subcommand) = command
+                .get_subcommands_mut()
+                .find(|subcommand| subcommand.get_name() == *name)
+            {
+                let taken = std::mem::replace(subcommand, Command::new(*name));
+                *subcommand = add_help_to(taken, rest, text);
+            }
+            command
+        }
+    }
+}
+
+pub fn add_help<'a>(command: Command<'a>, texts: &'a [(&'static str, String)]) -> Command<'a> {
+    texts.iter().fold(command, |command, (path, text)| {
+        add_help_to(command, &path.split(' ').collect::<Vec<_>>(), text)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // clap only checks the definitions of the subcommands that are used (and only in debug builds)
+    #[test]
+    fn cli_should_be_valid() {
+        crate::cli::build().debug_assert();
+    }
+
+    #[test]
+    fn examples_should_parse() {
+        for example in EXAMPLES {
+            let matches = crate::cli::build()
+                .try_get_matches_from(std::iter::once("dexios").chain(example.args.iter().copied()))
+                .unwrap_or_else(|e| panic!("{}: {}", invocation(example), e));
+
+            let mut path = Vec::new();
+            let mut matches = &matches;
+            while let Some((name, sub_matches)) = matches.subcommand() {
+                path.push(name);
+                matches = sub_matches;
+            }
+            assert_eq!(path.join(" "), example.command, "{}", invocation(example));
+        }
+    }
+
+    #[test]
+    fn examples_should_be_in_help() {
+        let texts = help_texts();
+        let command = add_help(crate::cli::build(), &texts);
+
+        for example in EXAMPLES {
+            let subcommand = example.command.split(' ').fold(&command, |command, name| {
+                command.find_subcommand(name).unwrap()
+            });
+            let help = subcommand.get_after_help().unwrap_or_default();
+            assert!(help.contains(&invocation(example)), "{}", example.command);
+        }
+    }
+}
@@ -21,7 +21,11 @@ pub fn get_answer(prompt: &str, default: bool, force: ForceMode) -> Result<bool>
 
     let answer_bool = loop {
         question!("{prompt} {switch}: ");
-        io::stdout().flush().context("Unable to flush stdout")?;
+        if crate::global::quiet() {
+            io::stderr().flush().context("Unable to flush stderr")?;
+        } else {
+            io::stdout().flush().context("Unable to flush stdout")?;
+        }
 
         let mut answer = String::new();
         stdin()
@@ -61,6 +65,10 @@ pub fn overwrite_check(name: &str, force: ForceMode) -> Result<bool> {
 }
 
 pub fn get_password(pass_state: &PasswordState) -> Result<Protected<Vec<u8>>> {
+    if let Some(program) = crate::global::pinentry::selected() {
+        return crate::global::pinentry::get_password(&program, pass_state);
+    }
+
     Ok(loop {
         let input = rpassword::prompt_password("Password: ").context("Unable to read password")?;
         if pass_state == &PasswordState::Direct {
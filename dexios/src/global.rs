@@ -1,39 +1,76 @@
+pub mod agent;
+pub mod command;
+pub mod config;
 pub mod parameters;
+pub mod pinentry;
+pub mod pkcs11;
+pub mod policy;
 pub mod states;
+pub mod strength;
 pub mod structs;
+pub mod yubikey;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// in quiet mode, stdout is reserved for whatever was requested (decrypted data, a header, JSON or a digest)
+// informational messages are dropped, and warnings, errors and questions are written to stderr instead
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
 
 #[macro_export]
 macro_rules! info {
     ($($arg:tt)*) => {
-        println!("[i] {}", format!($($arg)*))
+        if !$crate::global::quiet() {
+            println!("[i] {}", format!($($arg)*))
+        }
     }
 }
 
 #[macro_export]
 macro_rules! error {
     ($($arg:tt)*) => {
-        println!("[!] {}", format!($($arg)*))
+        if $crate::global::quiet() {
+            eprintln!("[!] {}", format!($($arg)*))
+        } else {
+            println!("[!] {}", format!($($arg)*))
+        }
     }
 }
 
 #[macro_export]
 macro_rules! success {
     ($($arg:tt)*) => {
-        println!("[+] {}", format!($($arg)*))
+        if !$crate::global::quiet() {
+            println!("[+] {}", format!($($arg)*))
+        }
     }
 }
 
 #[macro_export]
 macro_rules! warn {
     ($($arg:tt)*) => {
-        println!("[-] {}", format!($($arg)*))
+        if $crate::global::quiet() {
+            eprintln!("[-] {}", format!($($arg)*))
+        } else {
+            println!("[-] {}", format!($($arg)*))
+        }
     }
 }
 
 #[macro_export]
 macro_rules! question {
     ($($arg:tt)*) => {
-        print!("[?] {}", format!($($arg)*));
-
+        if $crate::global::quiet() {
+            eprint!("[?] {}", format!($($arg)*));
+        } else {
+            print!("[?] {}", format!($($arg)*));
+        }
     }
 }
@@ -0,0 +1,135 @@
+// this is the client for `dexios agent`, which caches a password so that batch operations don't ask for it every time
+// the agent is found through DEXIOS_AGENT_SOCK (which `dexios agent` prints, like `ssh-agent`), and it's only used if that's set
+//
+// a password that's entered is only given to the agent once the command has succeeded, so mistyped passwords aren't cached
+// if a command fails with the agent's password (e.g. as another file uses a different one), the agent forgets it
+//
+// the protocol is a line per request over a unix socket: `GET`, `PUT <hex>`, `CLEAR` and `STOP`
+// the agent replies with `OK` (followed by the hex-encoded password, for `GET`), or `NONE` if it has nothing cached
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use core::protected::Protected;
+use core::Zeroize;
+
+use crate::warn;
+
+pub const SOCKET_ENV: &str = "DEXIOS_AGENT_SOCK";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// this is set if the agent's password was used
+static USED: AtomicBool = AtomicBool::new(false);
+
+// this is the password that was entered, which is given to the agent if the command succeeds
+static ENTERED: Mutex<Option<Protected<Vec<u8>>>> = Mutex::new(None);
+
+// the agent isn't used for commands that take an old and a new key (e.g. `key change`), as it can't tell them apart
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(
+        enabled && std::env::var_os(SOCKET_ENV).is_some(),
+        Ordering::Relaxed,
+    );
+}
+
+#[cfg(unix)]
+pub fn request(socket: &str, command: &str) -> Option<Protected<String>> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    let mut stream = UnixStream::connect(socket).ok()?;
+    stream.set_read_timeout(Some(Duration::from_secs(5))).ok()?;
+    stream.write_all(format!("{}\n", command).as_bytes()).ok()?;
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response).ok()?;
+    Some(Protected::new(response))
+}
+
+#[cfg(not(unix))]
+pub fn request(_socket: &str, _command: &str) -> Option<Protected<String>> {
+    None
+}
+
+fn request_enabled(command: &str) -> Option<Protected<String>> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    let socket = std::env::var(SOCKET_ENV).ok()?;
+    let response = request(&socket, command);
+    if response.is_none() {
+        warn!("Unable to reach the agent at {} (is it running?)", socket);
+        ENABLED.store(false, Ordering::Relaxed);
+    }
+    response
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// this returns the agent's password, if it has one
+pub fn cached() -> Option<Protected<Vec<u8>>> {
+    let response = request_enabled("GET")?;
+    let hex = response.expose().trim_end().strip_prefix("OK ")?;
+    let password = Protected::new(hex_decode(hex)?);
+
+    USED.store(true, Ordering::Relaxed);
+    Some(password)
+}
+
+pub fn entered(password: &Protected<Vec<u8>>) {
+    if ENABLED.load(Ordering::Relaxed) {
+        *ENTERED
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = Some(password.clone());
+    }
+}
+
+// this is held for the whole command, and `succeeded()` is called once it has
+// if it's dropped without that (as the command failed), the agent's password is forgotten if it was used
+pub struct Session {
+    succeeded: bool,
+}
+
+impl Session {
+    pub fn start() -> Self {
+        Self { succeeded: false }
+    }
+
+    pub fn succeeded(mut self) {
+        self.succeeded = true;
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let entered = ENTERED
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .take();
+
+        if !self.succeeded {
+            if USED.load(Ordering::Relaxed) {
+                request_enabled("CLEAR");
+            }
+            return;
+        }
+
+        if let Some(password) = entered {
+            let mut command = format!("PUT {}", domain::utils::hex_encode(password.expose()));
+            request_enabled(&command);
+            command.zeroize();
+        }
+    }
+}
@@ -0,0 +1,240 @@
+// this runs an external program for the key, with `--password-command` or `--key-command`, so that any password manager can provide it (e.g. `pass show backups/dexios` or `op read ...`)
+// the program is run directly rather than through a shell, so nothing within the command is expanded
+// the command is split into words like a shell would, but only quotes and backslashes are understood (there are no variables, globs, pipes or `~`)
+//
+// stdin and stderr are left alone, so that the program can prompt for its own passwords (e.g. a GPG PIN)
+// its output is limited to `MAX_OUTPUT_LEN`, and it's read into a buffer that's zeroed when it's dropped
+
+use anyhow::{Context, Result};
+use core::protected::Protected;
+use core::Zeroizing;
+use std::io::Read;
+use std::process::{Command, Stdio};
+
+use super::states::strip_newline;
+
+// no password manager prints anywhere near this much for a single secret
+pub const MAX_OUTPUT_LEN: usize = 64 * 1024;
+
+// this splits the command into the program and its arguments
+// single quotes keep everything within them, while double quotes only treat `\"` and `\\` as escapes
+pub fn split(command: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(anyhow::anyhow!("The command has an unclosed quote")),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => {
+                                return Err(anyhow::anyhow!("The command has an unclosed quote"))
+                            }
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(anyhow::anyhow!("The command has an unclosed quote")),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => return Err(anyhow::anyhow!("The command ends with a backslash")),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+
+    if words.is_empty() {
+        return Err(anyhow::anyhow!("The command is empty"));
+    }
+
+    Ok(words)
+}
+
+// a single trailing newline is removed (see `strip_newline()`)
+pub fn run(argv: &[String]) -> Result<Protected<Vec<u8>>> {
+    let (program, args) = argv
+        .split_first()
+        .ok_or_else(|| anyhow::anyhow!("The command is empty"))?;
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .with_context(|| format!("Unable to run the command `{}`", program))?;
+
+    // the buffer is allocated up front (with room to notice anything too long), so the key is never copied while it grows
+    let mut buffer = Zeroizing::new(vec![0u8; MAX_OUTPUT_LEN + 1]);
+    let mut len = 0;
+    let mut stdout = child
+        .stdout
+        .take()
+        .context("Unable to read the command's output")?;
+    let read = loop {
+        match stdout.read(&mut buffer[len..]) {
+            Ok(0) => break Ok(()),
+            Ok(n) if len + n > MAX_OUTPUT_LEN => {
+                break Err(anyhow::anyhow!(
+                    "The command `{}` printed more than {} bytes",
+                    program,
+                    MAX_OUTPUT_LEN
+                ))
+            }
+            Ok(n) => len += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => break Err(e).context("Unable to read the command's output"),
+        }
+    };
+    drop(stdout);
+
+    let secret = Protected::new(buffer[..len].to_vec());
+    drop(buffer);
+
+    if let Err(e) = read {
+        // it may still be waiting to write the rest of its output
+        child.kill().ok();
+        child.wait().ok();
+        return Err(e);
+    }
+
+    let status = child
+        .wait()
+        .with_context(|| format!("Unable to wait for the command `{}`", program))?;
+    if !status.success() {
+        return Err(anyhow::anyhow!(
+            "The command `{}` failed ({})",
+            program,
+            status
+        ));
+    }
+
+    let secret = strip_newline(secret);
+    if secret.expose().is_empty() {
+        return Err(anyhow::anyhow!(
+            "The command `{}` didn't output anything",
+            program
+        ));
+    }
+
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| (*word).to_string()).collect()
+    }
+
+    #[test]
+    fn should_split_on_whitespace() {
+        assert_eq!(
+            split("  pass  show\tbackups/dexios ").unwrap(),
+            argv(&["pass", "show", "backups/dexios"])
+        );
+    }
+
+    #[test]
+    fn should_keep_quoted_words_together() {
+        assert_eq!(
+            split(r#"op read 'op://Private/My Vault/password' "a b""#).unwrap(),
+            argv(&["op", "read", "op://Private/My Vault/password", "a b"])
+        );
+        assert_eq!(split("a'b c'd").unwrap(), argv(&["ab cd"]));
+        assert_eq!(split("'' \"\"").unwrap(), argv(&["", ""]));
+    }
+
+    #[test]
+    fn should_only_expand_escapes() {
+        assert_eq!(
+            split(r#"echo $HOME ~ * \$x a\ b"#).unwrap(),
+            argv(&["echo", "$HOME", "~", "*", "$x", "a b"])
+        );
+        // single quotes keep everything, while double quotes only treat `\"` and `\\` as escapes
+        assert_eq!(split(r#"'a\"b'"#).unwrap(), argv(&[r#"a\"b"#]));
+        assert_eq!(split(r#""a\"b\\c\d""#).unwrap(), argv(&[r#"a"b\c\d"#]));
+    }
+
+    #[test]
+    fn should_refuse_unterminated_quotes_and_escapes() {
+        assert!(split("pass show 'backups").is_err());
+        assert!(split("pass show \"backups").is_err());
+        assert!(split("pass show \"backups\\\"").is_err());
+        assert!(split("pass show backups\\").is_err());
+    }
+
+    #[test]
+    fn should_refuse_an_empty_command() {
+        assert!(split("").is_err());
+        assert!(split(" \t\n").is_err());
+        assert!(run(&[]).is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn should_use_the_output_without_its_newline() {
+        let secret = run(&argv(&["printf", "hunter2\\n"])).unwrap();
+        assert_eq!(secret.expose(), b"hunter2");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn should_not_expand_anything() {
+        let secret = run(&argv(&["echo", "$HOME", "*", "$(id)"])).unwrap();
+        assert_eq!(secret.expose(), b"$HOME * $(id)");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn should_refuse_a_failed_command() {
+        assert!(run(&argv(&["sh", "-c", "echo hunter2; exit 1"])).is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn should_refuse_empty_output() {
+        assert!(run(&argv(&["true"])).is_err());
+        assert!(run(&argv(&["echo"])).is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn should_limit_the_output() {
+        let max = MAX_OUTPUT_LEN.to_string();
+        let secret = run(&argv(&["head", "-c", &max, "/dev/zero"])).unwrap();
+        assert_eq!(secret.expose().len(), MAX_OUTPUT_LEN);
+
+        let over = (MAX_OUTPUT_LEN + 1).to_string();
+        assert!(run(&argv(&["head", "-c", &over, "/dev/zero"])).is_err());
+        assert!(run(&argv(&["cat", "/dev/zero"])).is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn should_refuse_a_missing_program() {
+        assert!(run(&argv(&["dexios-no-such-program"])).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+// this handles the config file, which stores defaults for settings that weren't provided on the command line
+// it uses the same `key = value` format as the policy file, and `#` starts a comment:
+//
+//   kdf = argon2id            # or blake3-balloon, scrypt
+//   kdf-memory = 1024         # in MiB
+//   kdf-iterations = 4
+//   kdf-parallelism = 4
+//   pack-filter = *.sql:sed 's/ AUTO_INCREMENT=[0-9]*//'
+//   unpack-filter = *.log:gzip -d
+//
+// the KDF parameters only apply to the KDF that they were chosen for
+// `dexios key calibrate --write` fills these in for the current machine
+// filters may be repeated, and they're used after any that are given with `--filter` (see `domain::filters`)
+
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use core::kdf::Kdf;
+
+// the config file may be moved elsewhere through the environment
+const CONFIG_ENV: &str = "DEXIOS_CONFIG";
+
+#[derive(Default, Debug)]
+pub struct Config {
+    pub kdf: Option<Kdf>,
+    pub kdf_memory: Option<u32>, // in MiB
+    pub kdf_iterations: Option<u32>,
+    pub kdf_parallelism: Option<u32>,
+    pub pack_filters: Vec<String>,
+    pub unpack_filters: Vec<String>,
+}
+
+impl Config {
+    // this is `DEXIOS_CONFIG`, or `dexios/config` within the user's config directory
+    pub fn path() -> Option<PathBuf> {
+        if let Ok(path) = std::env::var(CONFIG_ENV) {
+            if !path.is_empty() {
+                return Some(PathBuf::from(path));
+            }
+        }
+
+        let config_dir = if cfg!(windows) {
+            std::env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+            std::env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| {
+                    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config"))
+                })
+        };
+
+        config_dir.map(|dir| dir.join("dexios").join("config"))
+    }
+
+    // a missing config file is the same as an empty one
+    pub fn load() -> Result<Self> {
+        let path = match Self::path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Self::default()),
+        };
+
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("Unable to read the config file: {}", path.display()))?;
+
+        Self::parse(&text)
+            .with_context(|| format!("Unable to parse the config file: {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut config = Self::default();
+
+        for (i, line) in text.lines().enumerate() {
+            // filters are commands, so they may contain `#` themselves
+            let line = match line.trim_start() {
+                line if line.starts_with("pack-filter") || line.starts_with("unpack-filter") => {
+                    line.trim()
+                }
+                line => line.split('#').next().unwrap_or_default().trim(),
+            };
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .with_context(|| format!("Line {} is not a `key = value` setting", i + 1))?;
+            let value = value.trim();
+
+            let number = |name: &str| {
+                value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .with_context(|| format!("{name} must be a positive number"))
+            };
+
+            match key.trim() {
+                "kdf" => config.kdf = Some(value.parse::<Kdf>()?),
+                "kdf-memory" => config.kdf_memory = Some(number("kdf-memory")?),
+                "kdf-iterations" => config.kdf_iterations = Some(number("kdf-iterations")?),
+                "kdf-parallelism" => config.kdf_parallelism = Some(number("kdf-parallelism")?),
+                "pack-filter" => config.pack_filters.push(value.to_string()),
+                "unpack-filter" => config.unpack_filters.push(value.to_string()),
+                key => return Err(anyhow::anyhow!("Unknown config setting: {}", key)),
+            }
+        }
+
+        if config.kdf.is_none()
+            && (config.kdf_memory.is_some()
+                || config.kdf_iterations.is_some()
+                || config.kdf_parallelism.is_some())
+        {
+            return Err(anyhow::anyhow!(
+                "The KDF parameters require `kdf` to be set, so it's clear which KDF they're for"
+            ));
+        }
+
+        Ok(config)
+    }
+
+    // this writes every setting that's set (any comments in an existing file are lost)
+    pub fn save(&self, path: &PathBuf) -> Result<()> {
+        let mut text = String::from("# written by dexios\n");
+        if let Some(kdf) = self.kdf {
+            text.push_str(&format!("kdf = {kdf}\n"));
+        }
+        if let Some(memory) = self.kdf_memory {
+            text.push_str(&format!("kdf-memory = {memory}    # in MiB\n"));
+        }
+        if let Some(iterations) = self.kdf_iterations {
+            text.push_str(&format!("kdf-iterations = {iterations}\n"));
+        }
+        if let Some(parallelism) = self.kdf_parallelism {
+            text.push_str(&format!("kdf-parallelism = {parallelism}\n"));
+        }
+        for filter in &self.pack_filters {
+            text.push_str(&format!("pack-filter = {filter}\n"));
+        }
+        for filter in &self.unpack_filters {
+            text.push_str(&format!("unpack-filter = {filter}\n"));
+        }
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Unable to create {}", dir.display()))?;
+        }
+
+        std::fs::write(path, text)
+            .with_context(|| format!("Unable to write the config file: {}", path.display()))
+    }
+}
@@ -4,13 +4,31 @@
 use crate::global::states::{EraseMode, EraseSourceDir, ForceMode, HashMode, HeaderLocation};
 use crate::global::structs::CryptoParams;
 use crate::global::structs::PackParams;
+
+use super::config::Config;
+use super::policy::Policy;
 use crate::warn;
 use anyhow::{Context, Result};
 use clap::ArgMatches;
-use core::header::{HashingAlgorithm, ARGON2ID_LATEST, BLAKE3BALLOON_LATEST};
-use core::primitives::Algorithm;
-
-use super::states::{Compression, DirectoryMode, Key, KeyParams, PrintMode};
+use core::header::{
+    HashingAlgorithm, HeaderType, HeaderVersion, ARGON2ID_LATEST, BLAKE3BALLOON_LATEST,
+    SCRYPT_LATEST,
+};
+use core::kdf::{
+    Argon2id, Argon2idParams, BalloonParams, Blake3Balloon, Kdf, KdfParams, Scrypt, ScryptParams,
+};
+use core::primitives::{
+    Algorithm, Mode, StreamCounter, BLOCK_SIZE, MAX_BLOCK_SIZE, MIN_BLOCK_SIZE,
+};
+use domain::filters::Filter;
+use std::num::{NonZeroU64, NonZeroU8, NonZeroUsize};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::states::{
+    Compression, DirectoryMode, Key, KeyParams, PrintMode, ProgressInterval, StatsMode,
+};
 use super::structs::KeyManipulationParams;
 
 pub fn get_params(name: &str, sub_matches: &ArgMatches) -> Result<Vec<String>> {
@@ -33,6 +51,7 @@ pub fn get_param(name: &str, sub_matches: &ArgMatches) -> Result<String> {
 // the main parameter handler for encrypt/decrypt
 pub fn parameter_handler(sub_matches: &ArgMatches) -> Result<CryptoParams> {
     let key = Key::init(sub_matches, &KeyParams::default(), "keyfile")?;
+    let key = two_factor(key, sub_matches)?;
 
     let hash_mode = if sub_matches.is_present("hash") {
         //specify to emit hash after operation
@@ -44,18 +63,12 @@ pub fn parameter_handler(sub_matches: &ArgMatches) -> Result<CryptoParams> {
 
     let force = forcemode(sub_matches);
 
-    let erase = if sub_matches.is_present("erase") {
-        let result = sub_matches
-            .value_of("erase")
-            .context("No amount of passes specified")?
-            .parse();
+    // not every subcommand that uses these params can erase its input
+    let erase = if let Ok(true) = sub_matches.try_contains_id("erase") {
+        let passes =
+            ranged(sub_matches, "erase", &PASSES)?.context("No amount of passes specified")?;
 
-        if let Ok(value) = result {
-            EraseMode::EraseFile(value)
-        } else {
-            warn!("No amount of passes provided - using the default.");
-            EraseMode::EraseFile(1)
-        }
+        EraseMode::EraseFile(passes)
     } else {
         EraseMode::IgnoreFile
     };
@@ -71,7 +84,7 @@ pub fn parameter_handler(sub_matches: &ArgMatches) -> Result<CryptoParams> {
         HeaderLocation::Embedded
     };
 
-    let hashing_algorithm = hashing_algorithm(sub_matches);
+    let hashing_algorithm = hashing_algorithm(sub_matches)?;
 
     Ok(CryptoParams {
         hash_mode,
@@ -80,43 +93,516 @@ pub fn parameter_handler(sub_matches: &ArgMatches) -> Result<CryptoParams> {
         key,
         header_location,
         hashing_algorithm,
+        policy: Policy::from_matches(sub_matches)?,
+        move_to: move_to(sub_matches),
     })
 }
 
-pub fn hashing_algorithm(sub_matches: &ArgMatches) -> HashingAlgorithm {
-    if sub_matches.is_present("argon") {
-        HashingAlgorithm::Argon2id(ARGON2ID_LATEST)
+// `--move-to` is where the output ends up once it's complete (only encrypt and pack have it)
+fn move_to(sub_matches: &ArgMatches) -> Option<String> {
+    match sub_matches.try_get_one::<String>("move-to") {
+        Ok(path) => path.cloned(),
+        Err(_) => None,
+    }
+}
+
+// `--two-factor` pairs the keyfile with a password (only encrypt and pack have it)
+fn two_factor(key: Key, sub_matches: &ArgMatches) -> Result<Key> {
+    if let Ok(true) = sub_matches.try_contains_id("two-factor") {
+        key.with_password()
+    } else {
+        Ok(key)
+    }
+}
+
+// this returns the KDF from `--kdf`, or argon2id if `--argon` was provided
+pub fn kdf(sub_matches: &ArgMatches) -> Result<Option<Kdf>> {
+    if let Ok(Some(kdf)) = sub_matches.try_get_one::<String>("kdf") {
+        return kdf.parse::<Kdf>().map(Some);
+    }
+
+    Ok(matches!(sub_matches.try_contains_id("argon"), Ok(true)).then_some(Kdf::Argon2id))
+}
+
+// this is how much memory (in MiB) `--paranoid` lets the KDF use, if `--max-memory` isn't provided
+const PARANOID_MAX_MEMORY: u32 = 1024;
+
+pub fn hashing_algorithm(sub_matches: &ArgMatches) -> Result<HashingAlgorithm> {
+    // decrypt shares these params, but the hashing algorithm comes from the header
+    let config = Config::load()?;
+    let paranoid = matches!(sub_matches.try_contains_id("paranoid"), Ok(true));
+    // argon2id (RFC 9106's recommendation) is far quicker per MiB, so `--paranoid` can use much more memory with it
+    let kdf = kdf(sub_matches)?
+        .or(paranoid.then_some(Kdf::Argon2id))
+        .or(config.kdf)
+        .unwrap_or(Kdf::Blake3Balloon);
+
+    // the config file's parameters only apply to the KDF that they were chosen for
+    let config = if config.kdf == Some(kdf) {
+        config
     } else {
-        HashingAlgorithm::Blake3Balloon(BLAKE3BALLOON_LATEST)
+        Config::default()
+    };
+
+    // any parameters that aren't provided default to the config file's, and then to the latest version's
+    let get = |name: &str| match sub_matches.try_get_one::<u32>(name) {
+        Ok(value) => value.copied(),
+        Err(_) => None,
+    };
+    // `--paranoid` uses as much memory as `--max-memory` allows, in place of the config file's
+    let paranoid_memory = paranoid.then(|| get("max-memory").unwrap_or(PARANOID_MAX_MEMORY));
+    let (memory, iterations, parallelism) = (
+        get("kdf-memory").or(paranoid_memory).or(config.kdf_memory),
+        get("kdf-iterations").or(config.kdf_iterations),
+        get("kdf-parallelism").or(config.kdf_parallelism),
+    );
+
+    if memory.is_none() && iterations.is_none() && parallelism.is_none() {
+        return HashingAlgorithm::from_kdf(kdf, None);
     }
+
+    custom_hashing_algorithm(kdf, memory, iterations, parallelism)
+}
+
+// this builds a KDF with custom parameters (memory is in MiB), and validates them
+// any parameters that aren't provided default to the latest version's
+pub fn custom_hashing_algorithm(
+    kdf: Kdf,
+    memory: Option<u32>,
+    iterations: Option<u32>,
+    parallelism: Option<u32>,
+) -> Result<HashingAlgorithm> {
+    let params = match kdf {
+        Kdf::Argon2id => {
+            let defaults = Argon2id::from_version(ARGON2ID_LATEST)?.params;
+            let m_cost = match memory {
+                Some(mib) => mib.checked_mul(1024).context("--kdf-memory is too large")?,
+                None => defaults.m_cost,
+            };
+
+            KdfParams::Argon2id(Argon2idParams {
+                m_cost,
+                t_cost: iterations.unwrap_or(defaults.t_cost),
+                p_cost: parallelism.unwrap_or(defaults.p_cost),
+            })
+        }
+        Kdf::Blake3Balloon => {
+            let defaults = Blake3Balloon::from_version(BLAKE3BALLOON_LATEST)?.params;
+            // each balloon block is the size of a BLAKE3 hash (32 bytes)
+            let s_cost = match memory {
+                Some(mib) => mib
+                    .checked_mul(1024 * 1024 / 32)
+                    .context("--kdf-memory is too large")?,
+                None => defaults.s_cost,
+            };
+
+            KdfParams::Blake3Balloon(BalloonParams {
+                s_cost,
+                t_cost: iterations.unwrap_or(defaults.t_cost),
+                p_cost: parallelism.unwrap_or(defaults.p_cost),
+            })
+        }
+        Kdf::Scrypt => {
+            if iterations.is_some() {
+                return Err(anyhow::anyhow!(
+                    "scrypt doesn't have an iteration count, use --kdf-memory instead"
+                ));
+            }
+
+            let defaults = Scrypt::from_version(SCRYPT_LATEST)?.params;
+            // scrypt uses 128 * r * N bytes, and N must be a power of two (so this rounds down)
+            let log_n = match memory {
+                Some(mib) => {
+                    let n = u64::from(mib) * 1024 * 1024 / (128 * u64::from(defaults.r));
+                    u8::try_from(63 - n.leading_zeros()).unwrap_or(u8::MAX)
+                }
+                None => defaults.log_n,
+            };
+
+            KdfParams::Scrypt(ScryptParams {
+                log_n,
+                r: defaults.r,
+                p: parallelism.unwrap_or(defaults.p),
+            })
+        }
+    };
+
+    HashingAlgorithm::from_kdf(kdf, Some(params))
 }
 
 // gets the algorithm, primarily for encrypt functions
-pub fn algorithm(sub_matches: &ArgMatches) -> Algorithm {
-    if sub_matches.is_present("aes") {
+// `--aes`, `--aegis`, `--chacha20` and `--ascon` are hidden aliases, from before `--algorithm` existed
+pub fn algorithm(sub_matches: &ArgMatches) -> Result<Algorithm> {
+    if let Ok(Some(algorithm)) = sub_matches.try_get_one::<String>("algorithm") {
+        return algorithm.parse();
+    }
+
+    Ok(if sub_matches.is_present("aes") {
         Algorithm::Aes256Gcm
+    } else if sub_matches.is_present("aegis") {
+        Algorithm::Aegis256
+    } else if sub_matches.is_present("chacha20") {
+        Algorithm::ChaCha20Poly1305
+    } else if sub_matches.is_present("ascon") {
+        Algorithm::Ascon128a
     } else {
         Algorithm::XChaCha20Poly1305
+    })
+}
+
+// memory mode can only be used to decrypt, so it's refused here
+pub fn mode(sub_matches: &ArgMatches) -> Result<Mode> {
+    let mode = match sub_matches.try_get_one::<String>("mode") {
+        Ok(Some(mode)) => mode.parse()?,
+        _ if sub_matches.is_present("misuse-resistant") => Mode::DerivedStreamMode,
+        _ => Mode::StreamMode,
+    };
+
+    if mode == Mode::MemoryMode {
+        return Err(anyhow::anyhow!(
+            "Memory mode can't be used for encryption (use stream or derived-stream)"
+        ));
+    }
+
+    // clap only knows that these conflict with `--misuse-resistant`
+    if mode == Mode::DerivedStreamMode
+        && (sub_matches.is_present("convergent") || sub_matches.is_present("stream-counter"))
+    {
+        return Err(anyhow::anyhow!(
+            "--convergent and --stream-counter can't be used with derived-stream mode"
+        ));
     }
+
+    Ok(mode)
 }
 
-pub fn erase_params(sub_matches: &ArgMatches) -> Result<(i32, ForceMode)> {
-    let passes = if sub_matches.is_present("passes") {
-        let result = sub_matches
-            .value_of("passes")
-            .context("No amount of passes specified")?
-            .parse::<i32>();
-        if let Ok(value) = result {
-            value
-        } else {
-            warn!("Unable to read number of passes provided - using the default.");
-            1
-        }
+// `le31` is the default, as it's what every version of dexios supports
+pub fn stream_counter(sub_matches: &ArgMatches) -> StreamCounter {
+    match sub_matches.try_get_one::<String>("stream-counter") {
+        Ok(Some(counter)) if counter == "be64" => StreamCounter::Be64,
+        _ => StreamCounter::Le31,
+    }
+}
+
+// this parses `--compress=zstd:<level>` (or just `zstd`, which uses level 3), along with `--pad-blocks`
+pub fn compression(sub_matches: &ArgMatches) -> Result<core::primitives::Compression> {
+    let value = match sub_matches.try_get_one::<String>("compress") {
+        Ok(Some(value)) => value,
+        _ => return Ok(core::primitives::Compression::None),
+    };
+
+    let (name, level) = value.split_once(':').unwrap_or((value, "3"));
+    if !name.eq_ignore_ascii_case("zstd") {
+        return Err(anyhow::anyhow!(
+            "Unsupported compression algorithm: {name} (only zstd is supported)"
+        ));
+    }
+
+    let level = level
+        .parse::<i32>()
+        .ok()
+        .filter(|level| (1..=22).contains(level))
+        .context("The zstd compression level must be between 1 and 22")?;
+
+    if sub_matches.is_present("pad-blocks") {
+        Ok(core::primitives::Compression::ZstdPadded(level))
+    } else {
+        Ok(core::primitives::Compression::Zstd(level))
+    }
+}
+
+// this parses `pack recompress --compression=<codec[:level]>`, where the level is only accepted for zstd
+pub fn recompression(sub_matches: &ArgMatches) -> Result<(Compression, Option<i32>)> {
+    let value = get_param("compression", sub_matches)?;
+    let (name, level) = match value.split_once(':') {
+        Some((name, level)) => (name, Some(level)),
+        None => (value.as_str(), None),
+    };
+
+    if name.eq_ignore_ascii_case("none") && level.is_none() {
+        return Ok((Compression::None, None));
+    }
+    if !name.eq_ignore_ascii_case("zstd") {
+        return Err(anyhow::anyhow!(
+            "Unsupported compression: {value} (only 'none' and 'zstd' are supported)"
+        ));
+    }
+
+    let level = level
+        .map(|level| {
+            level
+                .parse::<i32>()
+                .ok()
+                .filter(|level| (1..=22).contains(level))
+                .context("The zstd compression level must be between 1 and 22")
+        })
+        .transpose()?;
+
+    Ok((Compression::Zstd, level))
+}
+
+// this parses `--block-size=<size>`, which may be in bytes or have a K/M suffix (e.g. `256K`, `8M`)
+// the header only stores powers of two, so anything else is rejected here
+pub fn block_size(sub_matches: &ArgMatches) -> Result<usize> {
+    let value = match sub_matches.try_get_one::<String>("block-size") {
+        Ok(Some(value)) => value.to_ascii_uppercase(),
+        _ => return Ok(BLOCK_SIZE),
+    };
+
+    let value = value.trim_end_matches("IB").trim_end_matches('B');
+    let (number, multiplier) = if let Some(number) = value.strip_suffix('K') {
+        (number, 1024)
+    } else if let Some(number) = value.strip_suffix('M') {
+        (number, 1024 * 1024)
     } else {
-        warn!("Number of passes not provided - using the default.");
-        1
+        (value, 1)
     };
 
+    number
+        .parse::<usize>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .filter(|size| size.is_power_of_two() && (MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(size))
+        .context("The block size must be a power of two between 64K and 64M")
+}
+
+// this is `start:len` (in bytes), and the length may be left out to decrypt everything after `start`
+pub fn range(sub_matches: &ArgMatches) -> Result<Option<(u64, Option<u64>)>> {
+    let value = match sub_matches.try_get_one::<String>("range") {
+        Ok(Some(value)) => value,
+        _ => return Ok(None),
+    };
+
+    let (start, len) = value
+        .split_once(':')
+        .context("The range should look like `start:len`")?;
+    let start = start
+        .trim()
+        .parse::<u64>()
+        .context("The start of the range must be a number of bytes")?;
+    let len = match len.trim() {
+        "" => None,
+        len => Some(
+            len.parse::<u64>()
+                .context("The length of the range must be a number of bytes")?,
+        ),
+    };
+
+    Ok(Some((start, len)))
+}
+
+// these guard against zip bombs when unpacking
+// `--max-extract-size` may be in bytes or have a K/M/G/T suffix, and `--max-ratio=0` disables the ratio check
+// this parses a number of bytes, optionally with a K/M/G/T suffix (e.g. `500M` or `2GiB`)
+fn byte_size(value: &str) -> Option<u64> {
+    let value = value.to_ascii_uppercase();
+    let value = value.trim_end_matches("IB").trim_end_matches('B');
+    let (number, shift) = match value.char_indices().last() {
+        Some((i, 'K')) => (&value[..i], 10),
+        Some((i, 'M')) => (&value[..i], 20),
+        Some((i, 'G')) => (&value[..i], 30),
+        Some((i, 'T')) => (&value[..i], 40),
+        _ => (value, 0),
+    };
+
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(1 << shift))
+}
+
+// `--ttl` is how long the agent keeps a password for, e.g. `90s`, `15m` or `2h` (plain numbers are seconds)
+pub fn agent_ttl(sub_matches: &ArgMatches) -> Result<Duration> {
+    let Ok(Some(value)) = sub_matches.try_get_one::<String>("ttl") else {
+        return Ok(Duration::from_secs(15 * 60));
+    };
+
+    let (number, unit) = match value.char_indices().last() {
+        Some((i, 's')) => (&value[..i], 1),
+        Some((i, 'm')) => (&value[..i], 60),
+        Some((i, 'h')) => (&value[..i], 3600),
+        _ => (value.as_str(), 1),
+    };
+
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|number| *number > 0)
+        .and_then(|number| number.checked_mul(unit))
+        .map(Duration::from_secs)
+        .context("The TTL must be a number of seconds (above 0), optionally with an s/m/h suffix")
+}
+
+// `--split` is the largest that each part of a backup may be
+pub fn split_size(sub_matches: &ArgMatches) -> Result<Option<NonZeroU64>> {
+    match sub_matches.try_get_one::<String>("split") {
+        Ok(Some(value)) => byte_size(value)
+            .and_then(NonZeroU64::new)
+            .map(Some)
+            .context(
+            "The split size must be a number of bytes (above 0), optionally with a K/M/G/T suffix",
+        ),
+        _ => Ok(None),
+    }
+}
+
+pub fn extract_limits(sub_matches: &ArgMatches) -> Result<domain::unpack::Limits> {
+    let mut limits = domain::unpack::Limits::default();
+
+    if let Ok(Some(value)) = sub_matches.try_get_one::<String>("max-extract-size") {
+        limits.max_size = Some(byte_size(value).context(
+            "The maximum extract size must be a number of bytes, optionally with a K/M/G/T suffix",
+        )?);
+    }
+
+    if let Ok(Some(max)) = sub_matches.try_get_one::<usize>("max-entries") {
+        limits.max_entries = Some(*max);
+    }
+
+    match sub_matches.try_get_one::<u64>("max-ratio") {
+        Ok(Some(0)) => limits.max_ratio = None,
+        Ok(Some(max)) => limits.max_ratio = Some(*max),
+        _ => (),
+    }
+
+    Ok(limits)
+}
+
+// these numeric arguments are parsed once clap has matched everything, so that `--lenient` can be taken into account
+// invalid values are refused, unless `--lenient` was provided, in which case they fall back to the default (which is how older versions behaved)
+pub struct Ranged {
+    name: &'static str,
+    range: RangeInclusive<u8>,
+    default: u8,
+}
+
+pub const PASSES: Ranged = Ranged {
+    name: "number of passes",
+    range: 1..=32,
+    default: 1,
+};
+
+pub const WORDS: Ranged = Ranged {
+    name: "number of words",
+    range: 1..=32,
+    default: 7,
+};
+
+pub const JOBS: Ranged = Ranged {
+    name: "number of jobs",
+    range: 1..=128,
+    default: 1,
+};
+
+// this is in MiB
+pub const SCAN_LIMIT: Ranged = Ranged {
+    name: "scan limit",
+    range: 1..=255,
+    default: 16,
+};
+
+impl Ranged {
+    fn parse(&self, value: &str) -> Result<NonZeroU8> {
+        value
+            .parse::<u8>()
+            .ok()
+            .filter(|value| self.range.contains(value))
+            .and_then(NonZeroU8::new)
+            .with_context(|| {
+                format!(
+                    "The {} must be between {} and {} (not '{}')",
+                    self.name,
+                    self.range.start(),
+                    self.range.end(),
+                    value
+                )
+            })
+    }
+}
+
+// this returns `None` if the argument wasn't provided
+pub fn ranged(sub_matches: &ArgMatches, name: &str, ranged: &Ranged) -> Result<Option<NonZeroU8>> {
+    let Ok(Some(value)) = sub_matches.try_get_one::<String>(name) else {
+        return Ok(None);
+    };
+
+    match ranged.parse(value) {
+        Ok(value) => Ok(Some(value)),
+        Err(_) if sub_matches.is_present("lenient") => {
+            warn!(
+                "Invalid {} '{}' - using the default ({}).",
+                ranged.name, value, ranged.default
+            );
+            Ok(NonZeroU8::new(ranged.default))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// this parses `--assume=<version>,<algorithm>,<mode>` (e.g. `v6,xchacha,stream`)
+// algorithms may be shortened, as long as it's clear which one is meant (see `Algorithm::from_str`)
+pub fn assumed_header_type(sub_matches: &ArgMatches) -> Result<Option<HeaderType>> {
+    let value = match sub_matches.try_get_one::<String>("assume") {
+        Ok(Some(value)) => value.to_ascii_lowercase(),
+        _ => return Ok(None),
+    };
+
+    let parts: Vec<&str> = value.split(',').map(str::trim).collect();
+    let [version, algorithm, mode] = parts[..] else {
+        return Err(anyhow::anyhow!(
+            "--assume must be in the format `version,algorithm,mode` (e.g. `v6,xchacha,stream`)"
+        ));
+    };
+
+    let version = match version.trim_start_matches('v') {
+        "1" => HeaderVersion::V1,
+        "2" => HeaderVersion::V2,
+        "3" => HeaderVersion::V3,
+        "4" => HeaderVersion::V4,
+        "5" => HeaderVersion::V5,
+        "6" => HeaderVersion::V6,
+        _ => return Err(anyhow::anyhow!("Unknown header version: {version}")),
+    };
+
+    let algorithm = algorithm.parse()?;
+    let mode = mode.parse()?;
+
+    Ok(Some(HeaderType {
+        version,
+        algorithm,
+        mode,
+    }))
+}
+
+// `--compat` writes an older header, for files that need to be read by older versions of dexios
+// V2 headers aren't authenticated, so they also need `--allow-unauthenticated-header`
+pub fn compat_version(sub_matches: &ArgMatches) -> Result<Option<HeaderVersion>> {
+    let version = match sub_matches.try_get_one::<String>("compat") {
+        Ok(Some(version)) => version.as_str(),
+        _ => return Ok(None),
+    };
+
+    let version = match version {
+        "v2" if !sub_matches.is_present("allow-unauthenticated-header") => {
+            return Err(anyhow::anyhow!(
+                "V2 headers aren't authenticated, so --compat v2 also needs --allow-unauthenticated-header"
+            ))
+        }
+        "v2" => HeaderVersion::V2,
+        "v3" => HeaderVersion::V3,
+        "v4" => HeaderVersion::V4,
+        _ => HeaderVersion::V5,
+    };
+
+    Ok(Some(version))
+}
+
+pub fn erase_params(sub_matches: &ArgMatches) -> Result<(NonZeroU8, ForceMode)> {
+    let passes =
+        ranged(sub_matches, "passes", &PASSES)?.context("No amount of passes specified")?;
+
     let force = forcemode(sub_matches);
 
     Ok((passes, force))
@@ -124,6 +610,7 @@ pub fn erase_params(sub_matches: &ArgMatches) -> Result<(i32, ForceMode)> {
 
 pub fn pack_params(sub_matches: &ArgMatches) -> Result<(CryptoParams, PackParams)> {
     let key = Key::init(sub_matches, &KeyParams::default(), "keyfile")?;
+    let key = two_factor(key, sub_matches)?;
 
     let hash_mode = if sub_matches.is_present("hash") {
         //specify to emit hash after operation
@@ -148,7 +635,7 @@ pub fn pack_params(sub_matches: &ArgMatches) -> Result<(CryptoParams, PackParams
         HeaderLocation::Embedded
     };
 
-    let hashing_algorithm = hashing_algorithm(sub_matches);
+    let hashing_algorithm = hashing_algorithm(sub_matches)?;
 
     let crypto_params = CryptoParams {
         hash_mode,
@@ -157,11 +644,14 @@ pub fn pack_params(sub_matches: &ArgMatches) -> Result<(CryptoParams, PackParams
         key,
         header_location,
         hashing_algorithm,
+        policy: Policy::from_matches(sub_matches)?,
+        move_to: move_to(sub_matches),
     };
 
     let print_mode = if sub_matches.is_present("verbose") {
-        //specify to emit hash after operation
         PrintMode::Verbose
+    } else if let Some(interval) = sub_matches.value_of("progress") {
+        PrintMode::Summary(progress_interval(interval)?)
     } else {
         // default
         PrintMode::Quiet
@@ -187,16 +677,131 @@ pub fn pack_params(sub_matches: &ArgMatches) -> Result<(CryptoParams, PackParams
         Compression::None
     };
 
+    let jobs = match ranged(sub_matches, "jobs", &JOBS)? {
+        Some(jobs) => NonZeroUsize::from(jobs),
+        None => std::thread::available_parallelism().unwrap_or(NonZeroUsize::new(1).unwrap()),
+    };
+
+    let stats_mode = if sub_matches.is_present("stats") {
+        StatsMode::ShowStats
+    } else {
+        StatsMode::NoStats
+    };
+
     let pack_params = PackParams {
         dir_mode,
         print_mode,
         erase_source,
         compression,
+        jobs,
+        stats_mode,
+        streams: stream_options(sub_matches),
+        filters: filters(sub_matches, Config::load()?.pack_filters)?,
+        roots: pack_roots(sub_matches)?,
+        counter: stream_counter(sub_matches),
+        manifest: sub_matches.is_present("manifest"),
+        special_files: if sub_matches.value_of("special-files") == Some("refuse") {
+            domain::storage::SpecialFiles::Refuse
+        } else {
+            domain::storage::SpecialFiles::Skip
+        },
+        estimate: sub_matches.get_one::<u8>("estimate").copied(),
     };
 
     Ok((crypto_params, pack_params))
 }
 
+// this parses `--progress`, which is either a number of files or a percentage of them
+fn progress_interval(interval: &str) -> Result<ProgressInterval> {
+    let invalid =
+        || anyhow::anyhow!("Progress should be logged every N files, or every N% (1-100)");
+
+    match interval.strip_suffix('%') {
+        Some(percent) => percent
+            .parse::<NonZeroU8>()
+            .ok()
+            .filter(|percent| percent.get() <= 100)
+            .map(ProgressInterval::Percent)
+            .ok_or_else(invalid),
+        None => interval
+            .parse::<NonZeroUsize>()
+            .map(ProgressInterval::Files)
+            .map_err(|_| invalid()),
+    }
+}
+
+// this places each of pack's inputs beneath a prefix within the archive
+// the prefix defaults to the input's own name, but it can be set with `--prefix input=prefix` (an empty prefix places it at the top level)
+pub fn pack_roots(sub_matches: &ArgMatches) -> Result<Vec<domain::pack::Root>> {
+    let mut prefixes = sub_matches
+        .get_many::<String>("prefix")
+        .into_iter()
+        .flatten()
+        .map(|prefix| {
+            prefix
+                .rsplit_once('=')
+                .map(|(input, prefix)| (PathBuf::from(input), prefix.trim_matches('/').to_string()))
+                .with_context(|| format!("Invalid prefix (expected input=prefix): {}", prefix))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let roots = get_params("input", sub_matches)?
+        .into_iter()
+        .map(|input| {
+            let path = PathBuf::from(input);
+            let prefix = match prefixes.iter().position(|(input, _)| *input == path) {
+                Some(index) => prefixes.remove(index).1,
+                None => path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+            };
+
+            domain::pack::Root { path, prefix }
+        })
+        .collect();
+
+    if let Some((input, _)) = prefixes.first() {
+        return Err(anyhow::anyhow!(
+            "A prefix was provided for {}, but it isn't one of the inputs",
+            input.display()
+        ));
+    }
+
+    Ok(roots)
+}
+
+// this gets `--ads` and `--apple-meta` for pack/unpack
+// they're accepted everywhere, so the same command works on every platform, but unsupported ones are ignored
+pub fn stream_options(sub_matches: &ArgMatches) -> domain::streams::Options {
+    let options = domain::streams::Options {
+        alternate_data_streams: sub_matches.is_present("ads"),
+        apple_meta: sub_matches.is_present("apple-meta"),
+    };
+
+    let unsupported = options.unsupported();
+    if unsupported.alternate_data_streams {
+        warn!("Alternate data streams are only supported on Windows - ignoring --ads.");
+    }
+    if unsupported.apple_meta {
+        warn!("Extended attributes are only supported on macOS/Unix - ignoring --apple-meta.");
+    }
+
+    options
+}
+
+// this gets every `--filter`, followed by the ones from the config file (the first that matches a file is used)
+pub fn filters(sub_matches: &ArgMatches, configured: Vec<String>) -> Result<Vec<Filter>> {
+    sub_matches
+        .get_many::<String>("filter")
+        .into_iter()
+        .flatten()
+        .cloned()
+        .chain(configured)
+        .map(|filter| filter.parse::<Filter>().map_err(anyhow::Error::from))
+        .collect()
+}
+
 pub fn forcemode(sub_matches: &ArgMatches) -> ForceMode {
     if sub_matches.is_present("force") {
         ForceMode::Force
@@ -213,6 +818,8 @@ pub fn key_manipulation_params(sub_matches: &ArgMatches) -> Result<KeyManipulati
             env: false,
             autogenerate: false,
             keyfile: true,
+            command: true,
+            yubikey: false,
         },
         "keyfile-old",
     )?;
@@ -224,15 +831,67 @@ pub fn key_manipulation_params(sub_matches: &ArgMatches) -> Result<KeyManipulati
             env: false,
             autogenerate: true,
             keyfile: true,
+            command: true,
+            yubikey: false,
         },
         "keyfile-new",
     )?;
 
-    let hashing_algorithm = hashing_algorithm(sub_matches);
+    let hashing_algorithm = hashing_algorithm(sub_matches)?;
 
     Ok(KeyManipulationParams {
         key_old,
         key_new,
         hashing_algorithm,
+        policy: Policy::from_matches(sub_matches)?,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn erase_passes(args: &[&str]) -> Result<NonZeroU8> {
+        let mut argv = vec!["dexios"];
+        argv.extend_from_slice(args);
+        let matches = crate::cli::build().try_get_matches_from(argv).unwrap();
+        erase_params(matches.subcommand_matches("erase").unwrap()).map(|(passes, _)| passes)
+    }
+
+    #[test]
+    fn should_parse_numeric_arguments() {
+        assert_eq!(erase_passes(&["erase", "file"]).unwrap().get(), 1);
+        assert_eq!(
+            erase_passes(&["erase", "--passes=20", "file"])
+                .unwrap()
+                .get(),
+            20
+        );
+    }
+
+    #[test]
+    fn should_refuse_invalid_numeric_arguments() {
+        assert!(erase_passes(&["erase", "--passes=2O", "file"]).is_err());
+        assert!(erase_passes(&["erase", "--passes=0", "file"]).is_err());
+        assert!(erase_passes(&["erase", "--passes=33", "file"]).is_err());
+    }
+
+    #[test]
+    fn should_fall_back_to_the_default_with_lenient() {
+        assert_eq!(
+            erase_passes(&["--lenient", "erase", "--passes=2O", "file"])
+                .unwrap()
+                .get(),
+            1
+        );
+        assert_eq!(
+            erase_passes(&["erase", "--passes=2O", "--lenient", "file"])
+                .unwrap()
+                .get(),
+            1
+        );
+
+        // a file that happens to be called `--lenient` doesn't count
+        assert!(erase_passes(&["erase", "--passes=2O", "--", "--lenient"]).is_err());
+    }
+}
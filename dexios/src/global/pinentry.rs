@@ -0,0 +1,352 @@
+// this allows passwords to be entered with pinentry, rather than on the terminal (e.g. in GUI contexts)
+// `--pinentry` runs the `pinentry` program (or whichever program is given), and speaks the Assuan protocol with it
+// `--pinentry=agent` asks gpg-agent instead, which runs its own pinentry and caches the password under `dexios`
+// the agent's caching policy (e.g. `default-cache-ttl`) applies, and a cached password may be cleared with
+// `gpg-connect-agent 'CLEAR_PASSPHRASE dexios' /bye`
+
+use anyhow::{Context, Result};
+use core::protected::Protected;
+use core::Zeroize;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+use crate::global::states::PasswordState;
+
+// this is used in place of a program, to select gpg-agent
+pub const AGENT: &str = "agent";
+
+// the agent caches the password under this ID
+const CACHE_ID: &str = "dexios";
+
+static PINENTRY: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn set(program: Option<String>) {
+    *PINENTRY
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner) = program;
+}
+
+pub fn selected() -> Option<String> {
+    PINENTRY
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .clone()
+}
+
+pub fn get_password(program: &str, pass_state: &PasswordState) -> Result<Protected<Vec<u8>>> {
+    if program == AGENT {
+        agent_password(pass_state)
+    } else {
+        pinentry_password(program, pass_state)
+    }
+}
+
+// a minimal Assuan client, which is all that pinentry and gpg-agent need
+struct Assuan<R: BufRead, W: Write> {
+    reader: R,
+    writer: W,
+}
+
+impl<R: BufRead, W: Write> Assuan<R, W> {
+    // the server greets us with an `OK` once it's ready
+    fn connect(reader: R, writer: W) -> Result<Self> {
+        let mut assuan = Self { reader, writer };
+        assuan.response()?;
+        Ok(assuan)
+    }
+
+    fn command(&mut self, command: &str) -> Result<Protected<Vec<u8>>> {
+        self.writer
+            .write_all(command.as_bytes())
+            .and_then(|()| self.writer.write_all(b"\n"))
+            .and_then(|()| self.writer.flush())
+            .context("Unable to write to pinentry")?;
+
+        self.response()
+    }
+
+    // this collects any data lines, until the server replies with `OK` or `ERR`
+    fn response(&mut self) -> Result<Protected<Vec<u8>>> {
+        let mut data = Vec::new();
+
+        loop {
+            let mut line = String::new();
+            let read = self
+                .reader
+                .read_line(&mut line)
+                .context("Unable to read from pinentry")?;
+            if read == 0 {
+                return Err(anyhow::anyhow!("pinentry exited unexpectedly"));
+            }
+
+            let trimmed = line.trim_end_matches(['\r', '\n']);
+            if let Some(encoded) = trimmed.strip_prefix("D ") {
+                percent_decode(encoded, &mut data);
+            } else if trimmed == "OK" || trimmed.starts_with("OK ") {
+                line.zeroize();
+                return Ok(Protected::new(data));
+            } else if let Some(error) = trimmed.strip_prefix("ERR ") {
+                let error = error.to_string();
+                line.zeroize();
+                data.zeroize();
+                return Err(anyhow::anyhow!("pinentry returned an error: {}", error));
+            }
+            // anything else is a status line (`S`), a comment (`#`) or an inquiry, none of which we need
+
+            line.zeroize();
+        }
+    }
+}
+
+fn percent_decode(encoded: &str, data: &mut Vec<u8>) {
+    let bytes = encoded.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[i], escaped) {
+            (b'%', Some(value)) => {
+                data.push(value);
+                i += 3;
+            }
+            (byte, _) => {
+                data.push(byte);
+                i += 1;
+            }
+        }
+    }
+}
+
+// arguments may not contain raw newlines, and `%` has to be escaped as it's the escape character
+// the agent also separates its arguments with spaces, which it expects to be written as `+`
+fn escape(text: &str, plus_spaces: bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '%' => escaped.push_str("%25"),
+            '\n' => escaped.push_str("%0A"),
+            '\r' => escaped.push_str("%0D"),
+            '+' if plus_spaces => escaped.push_str("%2B"),
+            ' ' if plus_spaces => escaped.push('+'),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+// curses-based pinentries need to know which terminal to use
+fn tty_options() -> Vec<String> {
+    let mut options = Vec::new();
+
+    if let Ok(tty) = std::env::var("GPG_TTY") {
+        options.push(format!("OPTION ttyname={}", tty));
+    }
+    if let Ok(term) = std::env::var("TERM") {
+        options.push(format!("OPTION ttytype={}", term));
+    }
+
+    options
+}
+
+fn pinentry_password(program: &str, pass_state: &PasswordState) -> Result<Protected<Vec<u8>>> {
+    let mut child = Command::new(program)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Unable to run pinentry program: {}", program))?;
+
+    let stdin = child.stdin.take().context("Unable to write to pinentry")?;
+    let stdout = child
+        .stdout
+        .take()
+        .context("Unable to read from pinentry")?;
+
+    let password = (|| -> Result<Protected<Vec<u8>>> {
+        let mut assuan = Assuan::connect(BufReader::new(stdout), stdin)?;
+
+        for option in tty_options() {
+            // older pinentries may not support every option, and they're only hints
+            assuan.command(&option).ok();
+        }
+        assuan.command("SETTITLE dexios")?;
+        assuan.command("SETDESC Enter the password for dexios")?;
+
+        let password = loop {
+            assuan.command("SETPROMPT Password:")?;
+            let password = assuan.command("GETPIN")?;
+            if pass_state == &PasswordState::Direct {
+                break password;
+            }
+
+            assuan.command("SETPROMPT Confirm password:")?;
+            let confirmation = assuan.command("GETPIN")?;
+
+            if password.expose().is_empty() {
+                assuan.command(&format!(
+                    "SETERROR {}",
+                    escape("Password cannot be empty, please try again.", false)
+                ))?;
+            } else if password.expose() != confirmation.expose() {
+                assuan.command(&format!(
+                    "SETERROR {}",
+                    escape("The passwords aren't the same, please try again.", false)
+                ))?;
+            } else {
+                break password;
+            }
+        };
+
+        assuan.command("BYE").ok();
+        Ok(password)
+    })();
+
+    // pinentry exits once its input is closed, and anything it reports afterwards isn't useful
+    child.wait().ok();
+
+    password
+}
+
+#[cfg(unix)]
+fn agent_password(pass_state: &PasswordState) -> Result<Protected<Vec<u8>>> {
+    use std::os::unix::net::UnixStream;
+
+    // this starts the agent if it isn't already running
+    Command::new("gpgconf")
+        .args(["--launch", "gpg-agent"])
+        .status()
+        .context("Unable to run gpgconf (is GnuPG installed?)")?;
+
+    let output = Command::new("gpgconf")
+        .args(["--list-dirs", "agent-socket"])
+        .output()
+        .context("Unable to run gpgconf (is GnuPG installed?)")?;
+    let socket = String::from_utf8(output.stdout)
+        .context("Unable to find gpg-agent's socket")?
+        .trim()
+        .to_string();
+
+    let stream = UnixStream::connect(&socket)
+        .with_context(|| format!("Unable to connect to gpg-agent: {}", socket))?;
+    let reader = BufReader::new(
+        stream
+            .try_clone()
+            .context("Unable to connect to gpg-agent")?,
+    );
+    let mut assuan = Assuan::connect(reader, stream)?;
+
+    for option in tty_options() {
+        assuan.command(&option).ok();
+    }
+
+    // the agent asks for confirmation itself (`--repeat`), and it only caches the password once it's been entered
+    let repeat = if pass_state == &PasswordState::Direct {
+        ""
+    } else {
+        " --repeat=1"
+    };
+    let password = assuan.command(&format!(
+        "GET_PASSPHRASE --data{} {} X {} {}",
+        repeat,
+        CACHE_ID,
+        escape("Password:", true),
+        escape("Enter the password for dexios", true)
+    ))?;
+
+    assuan.command("BYE").ok();
+
+    if password.expose().is_empty() {
+        return Err(anyhow::anyhow!("Password cannot be empty"));
+    }
+
+    Ok(password)
+}
+
+#[cfg(not(unix))]
+fn agent_password(_pass_state: &PasswordState) -> Result<Protected<Vec<u8>>> {
+    Err(anyhow::anyhow!(
+        "gpg-agent is only supported on Unix-like systems, use `--pinentry` on its own instead"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn connect(server: &str) -> Assuan<Cursor<Vec<u8>>, Vec<u8>> {
+        Assuan::connect(Cursor::new(server.as_bytes().to_vec()), Vec::new()).unwrap()
+    }
+
+    #[test]
+    fn should_decode_data_lines() {
+        let mut assuan =
+            connect("OK Pleased to meet you\nS PROGRESS\nD hunter%252\r\nD %0Aend\nOK\n");
+
+        let data = assuan.command("GETPIN").unwrap();
+        assert_eq!(data.expose(), b"hunter%2\nend");
+        assert_eq!(assuan.writer, b"GETPIN\n");
+    }
+
+    #[test]
+    fn should_keep_invalid_escapes() {
+        let mut data = Vec::new();
+        percent_decode("100% %zz %4", &mut data);
+        assert_eq!(data, b"100% %zz %4");
+    }
+
+    #[test]
+    fn should_return_errors() {
+        let mut assuan = connect("OK\nD hunter2\nERR 83886179 Operation cancelled\n");
+        assert!(assuan.command("GETPIN").is_err());
+    }
+
+    #[test]
+    fn should_refuse_a_closed_connection() {
+        assert!(Assuan::connect(Cursor::new(Vec::new()), Vec::new()).is_err());
+
+        let mut assuan = connect("OK\nD hunter2\n");
+        assert!(assuan.command("GETPIN").is_err());
+    }
+
+    #[test]
+    fn should_escape_arguments() {
+        assert_eq!(escape("100% sure\r\n", false), "100%25 sure%0D%0A");
+        assert_eq!(escape("a b+c", false), "a b+c");
+        assert_eq!(escape("a b+c", true), "a+b%2Bc");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn should_get_the_password_from_a_pinentry_program() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // this answers every command with `OK`, and every `GETPIN` with the password
+        let program = std::env::temp_dir().join(format!("dexios-pinentry-{}", std::process::id()));
+        std::fs::write(
+            &program,
+            "#!/bin/sh\necho OK\nwhile read -r command; do\n  case \"$command\" in\n    GETPIN) echo 'D hunter%25'; echo OK ;;\n    BYE) echo OK; exit 0 ;;\n    *) echo OK ;;\n  esac\ndone\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o700)).unwrap();
+
+        let program_str = program.to_str().unwrap();
+        let direct = get_password(program_str, &PasswordState::Direct);
+        let validated = get_password(program_str, &PasswordState::Validate);
+        std::fs::remove_file(&program).unwrap();
+
+        assert_eq!(direct.unwrap().expose(), b"hunter%");
+        assert_eq!(validated.unwrap().expose(), b"hunter%");
+    }
+
+    #[test]
+    fn should_refuse_a_missing_program() {
+        assert!(get_password("dexios-no-such-program", &PasswordState::Direct).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+// this allows a secret key on a PKCS#11 token (a smartcard, an HSM, SoftHSM etc.) to unlock a token keyslot
+// each keyslot's salt is signed with CKM_SHA256_HMAC, and the master key is wrapped with a key derived from the signature
+// the key needs to be created on the token beforehand, e.g.
+// `pkcs11-tool --module <module> --login --keygen --key-type GENERIC:32 --label dexios --usage-sign`
+//
+// tokens are selected with a PKCS#11 URI (RFC 7512), of which the following attributes are supported:
+// `pkcs11:token=<label>;object=<label>;id=<id>?module-path=<path>&pin-value=<pin>`
+// the module path is required, and the PIN is prompted for if it isn't within the URI
+
+use anyhow::{Context, Result};
+use core::challenge::Responder;
+use core::protected::Protected;
+use cryptoki::context::{CInitializeArgs, Pkcs11};
+use cryptoki::error::{Error, RvError};
+use cryptoki::mechanism::Mechanism;
+use cryptoki::object::{Attribute, ObjectClass, ObjectHandle};
+use cryptoki::session::{Session, UserType};
+use cryptoki::types::AuthPin;
+
+pub struct Pkcs11Token {
+    session: Session,
+    key: ObjectHandle,
+}
+
+#[derive(Default)]
+struct Uri {
+    token: Option<String>,
+    object: Option<String>,
+    id: Option<Vec<u8>>,
+    module_path: Option<String>,
+    pin: Option<String>,
+}
+
+impl Uri {
+    fn parse(uri: &str) -> Result<Self> {
+        let uri = uri
+            .strip_prefix("pkcs11:")
+            .ok_or_else(|| anyhow::anyhow!("PKCS#11 URIs should start with `pkcs11:`"))?;
+        let (path, query) = uri.split_once('?').unwrap_or((uri, ""));
+
+        let mut parsed = Uri::default();
+
+        for (name, value) in attributes(path, ';')? {
+            match name {
+                "token" => parsed.token = Some(utf8(value)?),
+                "object" => parsed.object = Some(utf8(value)?),
+                "id" => parsed.id = Some(value),
+                // anything else (e.g. `type` or `manufacturer`) is only used to narrow the search, so it's safe to ignore
+                _ => (),
+            }
+        }
+
+        for (name, value) in attributes(query, '&')? {
+            match name {
+                "module-path" => parsed.module_path = Some(utf8(value)?),
+                "pin-value" => parsed.pin = Some(utf8(value)?),
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "Unsupported PKCS#11 URI query attribute: {}",
+                        name
+                    ))
+                }
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+fn attributes(part: &str, separator: char) -> Result<Vec<(&str, Vec<u8>)>> {
+    part.split(separator)
+        .filter(|attribute| !attribute.is_empty())
+        .map(|attribute| {
+            let (name, value) = attribute
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Invalid PKCS#11 URI attribute: {}", attribute))?;
+            Ok((name, percent_decode(value)?))
+        })
+        .collect()
+}
+
+fn percent_decode(value: &str) -> Result<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(value.len());
+    let mut bytes = value.bytes();
+
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let hex = [bytes.next(), bytes.next()];
+            let hex = match hex {
+                [Some(high), Some(low)] => [high, low],
+                _ => return Err(anyhow::anyhow!("Invalid percent-encoding in PKCS#11 URI")),
+            };
+            let hex =
+                std::str::from_utf8(&hex).context("Invalid percent-encoding in PKCS#11 URI")?;
+            decoded.push(
+                u8::from_str_radix(hex, 16).context("Invalid percent-encoding in PKCS#11 URI")?,
+            );
+        } else {
+            decoded.push(byte);
+        }
+    }
+
+    Ok(decoded)
+}
+
+fn utf8(value: Vec<u8>) -> Result<String> {
+    String::from_utf8(value).context("PKCS#11 URI attributes should be valid UTF-8")
+}
+
+impl Pkcs11Token {
+    pub fn open(uri: &str) -> Result<Self> {
+        let uri = Uri::parse(uri)?;
+
+        let module_path = uri.module_path.ok_or_else(|| {
+            anyhow::anyhow!("The PKCS#11 URI needs a `module-path` query attribute")
+        })?;
+        let context = Pkcs11::new(&module_path)
+            .with_context(|| format!("Unable to load PKCS#11 module: {}", module_path))?;
+        context
+            .initialize(CInitializeArgs::OsThreads)
+            .context("Unable to initialize the PKCS#11 module")?;
+
+        let mut slot = None;
+        for candidate in context
+            .get_slots_with_token()
+            .context("Unable to list PKCS#11 tokens")?
+        {
+            let info = context
+                .get_token_info(candidate)
+                .context("Unable to read PKCS#11 token information")?;
+            if uri
+                .token
+                .as_deref()
+                .is_none_or(|label| info.label() == label)
+            {
+                slot = Some((candidate, info.label().to_string()));
+                break;
+            }
+        }
+        let (slot, label) =
+            slot.ok_or_else(|| anyhow::anyhow!("Unable to find the PKCS#11 token"))?;
+
+        let session = context
+            .open_ro_session(slot)
+            .context("Unable to open a PKCS#11 session")?;
+
+        let pin = match uri.pin {
+            Some(pin) => pin,
+            None => rpassword::prompt_password(format!("PIN for {}: ", label))
+                .context("Unable to read PIN")?,
+        };
+
+        match session.login(UserType::User, Some(&AuthPin::new(pin))) {
+            Ok(()) | Err(Error::Pkcs11(RvError::UserAlreadyLoggedIn, _)) => (),
+            Err(e) => {
+                return Err(anyhow::anyhow!(
+                    "Unable to log in to the PKCS#11 token: {}",
+                    e
+                ))
+            }
+        }
+
+        let mut template = vec![Attribute::Class(ObjectClass::SECRET_KEY)];
+        if let Some(object) = uri.object {
+            template.push(Attribute::Label(object.into_bytes()));
+        }
+        if let Some(id) = uri.id {
+            template.push(Attribute::Id(id));
+        }
+
+        let keys = session
+            .find_objects(&template)
+            .context("Unable to search the PKCS#11 token")?;
+        let key = match keys.as_slice() {
+            [key] => *key,
+            [] => return Err(anyhow::anyhow!("Unable to find a secret key on the PKCS#11 token")),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "There are several secret keys on the PKCS#11 token, so `object` or `id` should be set within the URI"
+                ))
+            }
+        };
+
+        Ok(Self { session, key })
+    }
+}
+
+impl Responder for Pkcs11Token {
+    fn respond(&mut self, challenge: &[u8]) -> Result<Protected<Vec<u8>>> {
+        let response = self
+            .session
+            .sign(&Mechanism::Sha256Hmac, self.key, challenge)
+            .map_err(|e| {
+                anyhow::anyhow!(
+                    "PKCS#11 token was unable to sign the challenge (does the key allow CKM_SHA256_HMAC?): {}",
+                    e
+                )
+            })?;
+
+        Ok(Protected::new(response))
+    }
+}
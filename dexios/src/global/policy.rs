@@ -0,0 +1,374 @@
+// this handles `--policy`, which lets an organisation restrict the algorithms, KDFs and header versions that may be used
+// a policy file contains one `key = value` setting per line, and `#` starts a comment:
+//
+//   algorithms = xchacha20-poly1305
+//   kdfs = argon2id           # or blake3-balloon, scrypt
+//   min-kdf-memory = 262144   # in KiB
+//   min-header-version = 5
+//   on-violation = refuse     # or warn
+//
+// every setting is optional, and anything that isn't set isn't restricted
+
+use std::fs::File;
+
+use anyhow::{Context, Result};
+use clap::ArgMatches;
+use core::header::{HashingAlgorithm, Header, HeaderVersion};
+use core::kdf::{Argon2id, Blake3Balloon, Kdf, Scrypt, ScryptParams};
+use core::primitives::{Algorithm, ALGORITHMS};
+
+use crate::warn;
+
+// the policy file may also be provided through the environment, so it can be set fleet-wide
+const POLICY_ENV: &str = "DEXIOS_POLICY";
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum ViolationAction {
+    Refuse,
+    Warn,
+}
+
+pub struct Policy {
+    algorithms: Option<Vec<Algorithm>>,
+    kdfs: Option<Vec<Kdf>>,
+    min_kdf_memory: Option<u64>,
+    min_header_version: Option<HeaderVersion>,
+    on_violation: ViolationAction,
+}
+
+// this is used for matching names from the policy file, so "XChaCha20-Poly1305" and "xchacha20 poly1305" are equal
+fn normalise(name: &str) -> String {
+    name.trim().replace(' ', "-").to_ascii_lowercase()
+}
+
+// this returns the amount of memory that a KDF uses, in KiB
+fn kdf_memory(hashing_algorithm: &HashingAlgorithm) -> Result<u64> {
+    Ok(match hashing_algorithm {
+        HashingAlgorithm::Argon2id(i) => u64::from(Argon2id::from_version(*i)?.params.m_cost),
+        // each balloon block is the size of a BLAKE3 hash (32 bytes)
+        HashingAlgorithm::Blake3Balloon(i) => {
+            u64::from(Blake3Balloon::from_version(*i)?.params.s_cost) * 32 / 1024
+        }
+        HashingAlgorithm::Argon2idCustom(params) => u64::from(params.m_cost),
+        HashingAlgorithm::Blake3BalloonCustom(params) => u64::from(params.s_cost) * 32 / 1024,
+        // scrypt uses 128 * r * N bytes
+        HashingAlgorithm::Scrypt(i) => scrypt_memory(&Scrypt::from_version(*i)?.params),
+        HashingAlgorithm::ScryptCustom(params) => scrypt_memory(params),
+    })
+}
+
+fn scrypt_memory(params: &ScryptParams) -> u64 {
+    (128 * u64::from(params.r))
+        .saturating_mul(1u64.checked_shl(params.log_n.into()).unwrap_or(u64::MAX))
+        / 1024
+}
+
+// this returns the KDF of every password/keyfile keyslot within the header
+// V1-V3 headers don't have keyslots, but their KDF is tied to the version
+pub fn header_kdfs(header: &Header) -> Vec<HashingAlgorithm> {
+    match header.header_type.version {
+        HeaderVersion::V1 => vec![HashingAlgorithm::Argon2id(1)],
+        HeaderVersion::V2 => vec![HashingAlgorithm::Argon2id(2)],
+        HeaderVersion::V3 => vec![HashingAlgorithm::Argon2id(3)],
+        HeaderVersion::V4 | HeaderVersion::V5 | HeaderVersion::V6 => header
+            .keyslots
+            .iter()
+            .flatten()
+            .filter(|k| !k.is_recipient() && !k.is_token())
+            .map(|k| k.hash_algorithm)
+            .collect(),
+    }
+}
+
+impl Policy {
+    // this loads the policy from `--policy`, or `DEXIOS_POLICY` if that isn't set
+    pub fn from_matches(sub_matches: &ArgMatches) -> Result<Option<Self>> {
+        let path = match sub_matches.value_of("policy") {
+            Some(path) => path.to_string(),
+            None => match std::env::var(POLICY_ENV) {
+                Ok(path) if !path.is_empty() => path,
+                _ => return Ok(None),
+            },
+        };
+
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("Unable to read the policy file: {}", path))?;
+
+        Self::parse(&text)
+            .with_context(|| format!("Unable to parse the policy file: {}", path))
+            .map(Some)
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut policy = Self {
+            algorithms: None,
+            kdfs: None,
+            min_kdf_memory: None,
+            min_header_version: None,
+            on_violation: ViolationAction::Refuse,
+        };
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .with_context(|| format!("Line {} is not a `key = value` setting", i + 1))?;
+            let value = value.trim();
+
+            match key.trim() {
+                "algorithms" => {
+                    let algorithms = value
+                        .split(',')
+                        .map(|name| {
+                            ALGORITHMS
+                                .into_iter()
+                                .find(|a| normalise(&a.to_string()) == normalise(name))
+                                .with_context(|| format!("Unknown algorithm: {}", name.trim()))
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    policy.algorithms = Some(algorithms);
+                }
+                "kdfs" => {
+                    let kdfs = value
+                        .split(',')
+                        .map(|name| normalise(name).parse::<Kdf>())
+                        .collect::<Result<Vec<_>>>()?;
+                    policy.kdfs = Some(kdfs);
+                }
+                "min-kdf-memory" => {
+                    let memory = value
+                        .parse::<u64>()
+                        .context("min-kdf-memory must be a number of KiB")?;
+                    policy.min_kdf_memory = Some(memory);
+                }
+                "min-header-version" => {
+                    let version = match value.trim_start_matches(['v', 'V']) {
+                        "1" => HeaderVersion::V1,
+                        "2" => HeaderVersion::V2,
+                        "3" => HeaderVersion::V3,
+                        "4" => HeaderVersion::V4,
+                        "5" => HeaderVersion::V5,
+                        "6" => HeaderVersion::V6,
+                        _ => return Err(anyhow::anyhow!("Unknown header version: {}", value)),
+                    };
+                    policy.min_header_version = Some(version);
+                }
+                "on-violation" => {
+                    policy.on_violation = match value {
+                        "refuse" => ViolationAction::Refuse,
+                        "warn" => ViolationAction::Warn,
+                        _ => {
+                            return Err(anyhow::anyhow!(
+                                "on-violation must be either `refuse` or `warn`"
+                            ))
+                        }
+                    };
+                }
+                key => return Err(anyhow::anyhow!("Unknown policy setting: {}", key)),
+            }
+        }
+
+        Ok(policy)
+    }
+
+    fn algorithm_violation(&self, algorithm: &Algorithm) -> Option<String> {
+        match &self.algorithms {
+            Some(algorithms) if !algorithms.contains(algorithm) => {
+                Some(format!("{} is not permitted by the policy", algorithm))
+            }
+            _ => None,
+        }
+    }
+
+    fn kdf_violations(&self, hashing_algorithm: &HashingAlgorithm) -> Result<Vec<String>> {
+        let mut violations = Vec::new();
+
+        if let Some(kdfs) = &self.kdfs {
+            if !kdfs.contains(&hashing_algorithm.family()) {
+                violations.push(format!(
+                    "{} is not permitted by the policy",
+                    hashing_algorithm
+                ));
+            }
+        }
+
+        if let Some(min_memory) = self.min_kdf_memory {
+            let memory = kdf_memory(hashing_algorithm)?;
+            if memory < min_memory {
+                violations.push(format!(
+                    "{} uses {} KiB of memory, but the policy requires at least {} KiB",
+                    hashing_algorithm, memory, min_memory
+                ));
+            }
+        }
+
+        Ok(violations)
+    }
+
+    fn enforce(&self, violations: &[String]) -> Result<()> {
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        match self.on_violation {
+            ViolationAction::Refuse => Err(anyhow::anyhow!(
+                "Refusing to continue, as this violates the policy:\n  {}",
+                violations.join("\n  ")
+            )),
+            ViolationAction::Warn => {
+                for violation in violations {
+                    warn!("Policy violation: {}", violation);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // this is used before encrypting (the latest header version is always used for that)
+    pub fn check_encrypt(
+        &self,
+        algorithm: &Algorithm,
+        hashing_algorithm: &HashingAlgorithm,
+    ) -> Result<()> {
+        let mut violations = Vec::new();
+        violations.extend(self.algorithm_violation(algorithm));
+        violations.extend(self.kdf_violations(hashing_algorithm)?);
+        self.enforce(&violations)
+    }
+
+    // this is used before adding/changing a keyslot
+    pub fn check_keyslot(&self, hashing_algorithm: &HashingAlgorithm) -> Result<()> {
+        self.enforce(&self.kdf_violations(hashing_algorithm)?)
+    }
+
+    // this returns every violation within the header, as any keyslot may be used to decrypt the file
+    pub fn header_violations(&self, header: &Header) -> Result<Vec<String>> {
+        let mut violations = Vec::new();
+
+        if let Some(min_version) = self.min_header_version {
+            if header.header_type.version < min_version {
+                violations.push(format!(
+                    "Header {} is older than the policy allows ({} or newer)",
+                    header.header_type.version, min_version
+                ));
+            }
+        }
+
+        violations.extend(self.algorithm_violation(&header.header_type.algorithm));
+
+        for hashing_algorithm in &header_kdfs(header) {
+            for violation in self.kdf_violations(hashing_algorithm)? {
+                if !violations.contains(&violation) {
+                    violations.push(violation);
+                }
+            }
+        }
+
+        Ok(violations)
+    }
+
+    // this is used before decrypting
+    pub fn check_header(&self, header: &Header) -> Result<()> {
+        self.enforce(&self.header_violations(header)?)
+    }
+
+    // this reads the header from the input file (or the detached header), and checks it
+    pub fn check_file(&self, input: &str, header: Option<&str>) -> Result<()> {
+        let path = header.unwrap_or(input);
+        let mut file =
+            File::open(path).with_context(|| format!("Unable to open the header: {}", path))?;
+        let (header, _) = Header::deserialize(&mut file)?;
+
+        self.check_header(&header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_every_setting() {
+        let policy = Policy::parse(
+            "# fleet-wide policy\n\
+             \n\
+             algorithms = XChaCha20-Poly1305, aes 256 gcm\n\
+             kdfs = argon2id, Scrypt   # balloon isn't allowed\n\
+             min-kdf-memory = 262144\n\
+             min-header-version = v6\n\
+             on-violation = warn\n",
+        )
+        .unwrap();
+
+        assert!(
+            policy.algorithms == Some(vec![Algorithm::XChaCha20Poly1305, Algorithm::Aes256Gcm])
+        );
+        assert_eq!(policy.kdfs, Some(vec![Kdf::Argon2id, Kdf::Scrypt]));
+        assert_eq!(policy.min_kdf_memory, Some(262_144));
+        assert!(policy.min_header_version == Some(HeaderVersion::V6));
+        assert!(policy.on_violation == ViolationAction::Warn);
+    }
+
+    #[test]
+    fn should_leave_unset_settings_unrestricted() {
+        let policy = Policy::parse("# nothing here\n\n").unwrap();
+
+        assert!(policy.algorithms.is_none());
+        assert!(policy.kdfs.is_none());
+        assert!(policy.min_kdf_memory.is_none());
+        assert!(policy.min_header_version.is_none());
+        assert!(policy.on_violation == ViolationAction::Refuse);
+    }
+
+    #[test]
+    fn should_refuse_invalid_values() {
+        for text in [
+            "algorithms",
+            "algorithms = rot13",
+            "kdfs = pbkdf2",
+            "min-kdf-memory = lots",
+            "min-header-version = 7",
+            "on-violation = ignore",
+        ] {
+            assert!(Policy::parse(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn should_refuse_unknown_settings() {
+        match Policy::parse("algorithms = aes-256-gcm\nmax-file-size = 10") {
+            Err(e) => assert_eq!(e.to_string(), "Unknown policy setting: max-file-size"),
+            Ok(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn should_enforce_the_policy_before_encrypting() {
+        let policy = Policy::parse("algorithms = aes-256-gcm\nmin-kdf-memory = 262144").unwrap();
+        assert!(policy
+            .check_encrypt(&Algorithm::Aes256Gcm, &HashingAlgorithm::Argon2id(3))
+            .is_ok());
+        assert!(policy
+            .check_encrypt(
+                &Algorithm::XChaCha20Poly1305,
+                &HashingAlgorithm::Argon2id(3)
+            )
+            .is_err());
+        // argon2id v1 only uses 8MiB of memory
+        assert!(policy
+            .check_encrypt(&Algorithm::Aes256Gcm, &HashingAlgorithm::Argon2id(1))
+            .is_err());
+
+        let policy = Policy::parse("algorithms = aes-256-gcm\non-violation = warn").unwrap();
+        assert!(policy
+            .check_encrypt(
+                &Algorithm::XChaCha20Poly1305,
+                &HashingAlgorithm::Argon2id(3)
+            )
+            .is_ok());
+    }
+}
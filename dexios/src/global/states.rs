@@ -5,10 +5,14 @@
 use anyhow::{Context, Result};
 use clap::ArgMatches;
 use core::protected::Protected;
+use std::num::{NonZeroU8, NonZeroUsize};
 
 use crate::cli::prompt::get_password;
+use crate::global::parameters::{ranged, WORDS};
+use crate::global::yubikey::Yubikey;
 use crate::warn;
-use core::key::generate_passphrase;
+use core::header::Header;
+use core::key::{combine_factors, combine_keyfiles, generate_passphrase, hash_keyfile};
 
 #[derive(PartialEq, Eq, Clone, Copy)]
 pub enum DirectoryMode {
@@ -30,9 +34,23 @@ pub enum EraseSourceDir {
 #[derive(PartialEq, Eq)]
 pub enum PrintMode {
     Verbose,
+    // this only logs every so often, for trees that are too large to list file-by-file
+    Summary(ProgressInterval),
     Quiet,
 }
 
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum ProgressInterval {
+    Files(NonZeroUsize),
+    Percent(NonZeroU8),
+}
+
+#[derive(PartialEq, Eq)]
+pub enum StatsMode {
+    ShowStats,
+    NoStats,
+}
+
 pub enum HeaderLocation {
     Embedded,
     Detached(String),
@@ -40,7 +58,7 @@ pub enum HeaderLocation {
 
 #[derive(PartialEq, Eq, Clone, Copy)]
 pub enum EraseMode {
-    EraseFile(i32),
+    EraseFile(NonZeroU8),
     IgnoreFile,
 }
 
@@ -59,8 +77,16 @@ pub enum ForceMode {
 #[derive(PartialEq, Eq)]
 pub enum Key {
     Keyfile(String),
+    // every one of these keyfiles is needed (see `core::key::combine_keyfiles()`)
+    Keyfiles(Vec<String>),
+    // both the keyfile and the password are needed (see `core::key::combine_factors()`)
+    TwoFactor(String, Box<Key>),
+    // the program and its arguments, which are run without a shell (see `command`)
+    Command(Vec<String>),
+    PasswordFile(String),
     Env,
-    Generate(i32),
+    Generate(NonZeroU8),
+    Yubikey(u8),
     User,
 }
 
@@ -78,6 +104,52 @@ fn get_bytes<R: std::io::Read>(reader: &mut R) -> Result<Protected<Vec<u8>>> {
     Ok(Protected::new(data))
 }
 
+// only a single trailing newline is removed (e.g. from `echo` or a text editor), as anything else may be part of the password
+pub fn strip_newline(secret: Protected<Vec<u8>>) -> Protected<Vec<u8>> {
+    let mut stripped = secret.expose().as_slice();
+    stripped = stripped.strip_suffix(b"\n").unwrap_or(stripped);
+    stripped = stripped.strip_suffix(b"\r").unwrap_or(stripped);
+
+    if stripped.len() == secret.expose().len() {
+        secret
+    } else {
+        Protected::new(stripped.to_vec())
+    }
+}
+
+// unlike a keyfile, this is the password itself (so it's never hashed as a keyfile)
+fn read_password_file(path: &str) -> Result<Protected<Vec<u8>>> {
+    let secret = if path == "-" {
+        get_bytes(&mut std::io::stdin())?
+    } else {
+        let mut reader = std::fs::File::open(path)
+            .with_context(|| format!("Unable to read password file: {}", path))?;
+        get_bytes(&mut reader)?
+    };
+
+    let secret = strip_newline(secret);
+    if secret.expose().is_empty() {
+        return Err(anyhow::anyhow!("Password file '{}' is empty", path));
+    }
+
+    Ok(secret)
+}
+
+// this points at a keyfile, so that it doesn't need to be given on the command line
+const KEYFILE_ENV: &str = "DEXIOS_KEYFILE";
+
+// inherited file descriptors are read through `/dev/fd`, as that doesn't need any unsafe code
+// the orchestrator keeps the descriptor open for us, e.g. `dexios decrypt --keyfile-fd 3 in out 3<secret`
+fn fd_path(fd: u32) -> Result<String> {
+    if cfg!(unix) {
+        Ok(format!("/dev/fd/{}", fd))
+    } else {
+        Err(anyhow::anyhow!(
+            "Reading from a file descriptor is only supported on Unix-like systems"
+        ))
+    }
+}
+
 impl Key {
     // this handles getting the secret, and returning it
     // it relies on `parameters.rs`' handling and logic to determine which route to get the key
@@ -102,14 +174,42 @@ impl Key {
                 }
                 secret
             }
+            Key::Keyfiles(paths) => combine_keyfiles(
+                &paths
+                    .iter()
+                    .map(|path| Key::Keyfile(path.clone()).get_secret(pass_state))
+                    .collect::<Result<Vec<_>>>()?,
+            ),
+            Key::TwoFactor(path, password) => combine_factors(
+                &Key::Keyfile(path.clone()).get_secret(pass_state)?,
+                &password.get_secret(pass_state)?,
+            ),
+            Key::Command(argv) => super::command::run(argv)?,
+            Key::PasswordFile(path) => read_password_file(path)?,
             Key::Env => Protected::new(
                 std::env::var("DEXIOS_KEY")
                     .context("Unable to read DEXIOS_KEY from environment variable")?
                     .into_bytes(),
             ),
-            Key::User => get_password(pass_state)?,
+            Key::User => match super::agent::cached() {
+                Some(password) => password,
+                None => {
+                    let password = get_password(pass_state)?;
+                    if pass_state == &PasswordState::Validate {
+                        super::strength::check(&password)?;
+                    }
+                    super::agent::entered(&password);
+                    password
+                }
+            },
+            Key::Yubikey(slot) => {
+                // the token's secret is what protects the file, so this only needs to be recognisable
+                let raw_key = Protected::new(format!("dexios-yubikey-slot-{}", slot).into_bytes());
+                core::challenge::register(&raw_key, Box::new(Yubikey::new(*slot)?));
+                raw_key
+            }
             Key::Generate(i) => {
-                let passphrase = generate_passphrase(i);
+                let passphrase = generate_passphrase(&i32::from(i.get()));
                 warn!("Your generated passphrase is: {}", passphrase.expose());
                 let key = Protected::new(passphrase.expose().clone().into_bytes());
                 drop(passphrase);
@@ -124,34 +224,191 @@ impl Key {
         }
     }
 
+    // this is `get_secret()`, but keyfiles are hashed as they're read, rather than used as-is (see `core::key::hash_keyfile()`)
+    // new files always use hashed keyfiles, and existing files flag it within their header
+    pub fn get_secret_with(
+        &self,
+        pass_state: &PasswordState,
+        keyfile_hash: bool,
+    ) -> Result<Protected<Vec<u8>>> {
+        match self {
+            Key::Keyfile(path) if keyfile_hash && path == "-" => {
+                hash_keyfile(&mut std::io::stdin().lock()).context("Unable to hash STDIN")
+            }
+            Key::Keyfile(path) if keyfile_hash => {
+                let mut reader = std::fs::File::open(path)
+                    .with_context(|| format!("Unable to read file: {}", path))?;
+                hash_keyfile(&mut reader)
+                    .with_context(|| format!("Unable to hash keyfile '{}'", path))
+            }
+            Key::Keyfiles(paths) => Ok(combine_keyfiles(
+                &paths
+                    .iter()
+                    .map(|path| {
+                        Key::Keyfile(path.clone()).get_secret_with(pass_state, keyfile_hash)
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+            )),
+            Key::TwoFactor(path, password) => Ok(combine_factors(
+                &Key::Keyfile(path.clone()).get_secret_with(pass_state, keyfile_hash)?,
+                &password.get_secret(pass_state)?,
+            )),
+            _ => self.get_secret(pass_state),
+        }
+    }
+
+    // this is `get_secret_with()`, but it also asks for a password if the header needs one alongside the keyfile
+    pub fn get_secret_for_header(
+        &self,
+        pass_state: &PasswordState,
+        header: &Header,
+    ) -> Result<Protected<Vec<u8>>> {
+        match self {
+            Key::Keyfile(path) if header.two_factor => Key::Keyfile(path.clone())
+                .with_password()?
+                .get_secret_with(pass_state, header.keyfile_hash),
+            _ if header.two_factor => Err(anyhow::anyhow!(
+                "This file needs both a keyfile and a password"
+            )),
+            _ => self.get_secret_with(pass_state, header.keyfile_hash),
+        }
+    }
+
+    // this is true if the user will be asked for the password (unless the agent has it), rather than it being provided some other way
+    pub fn prompts(&self) -> bool {
+        match self {
+            Key::User => true,
+            Key::TwoFactor(_, password) => password.prompts(),
+            _ => false,
+        }
+    }
+
+    // this reads the header at `path`, to find out whether keyfiles should be hashed (or need a password too)
+    // if it can't be read, keyfiles are used as-is (decryption will report the actual problem)
+    pub fn get_secret_for(
+        &self,
+        pass_state: &PasswordState,
+        path: &str,
+    ) -> Result<Protected<Vec<u8>>> {
+        match std::fs::File::open(path)
+            .ok()
+            .and_then(|mut file| Header::deserialize(&mut file).ok())
+        {
+            Some((header, _)) => self.get_secret_for_header(pass_state, &header),
+            None => self.get_secret(pass_state),
+        }
+    }
+
+    // this pairs a keyfile with a password, for `--two-factor`
+    // the password is read from DEXIOS_KEY if it's set, otherwise the user is asked for it
+    pub fn with_password(self) -> Result<Self> {
+        let password = if std::env::var("DEXIOS_KEY").is_ok() {
+            Key::Env
+        } else {
+            Key::User
+        };
+
+        match self {
+            Key::Keyfile(path) => Ok(Key::TwoFactor(path, Box::new(password))),
+            Key::Keyfiles(_) => Err(anyhow::anyhow!(
+                "Two-factor keys can only be made from a single keyfile"
+            )),
+            _ => Err(anyhow::anyhow!(
+                "Two-factor keys need a keyfile (with -k, --keyfile-fd or {})",
+                KEYFILE_ENV
+            )),
+        }
+    }
+
     pub fn init(
         sub_matches: &ArgMatches,
         params: &KeyParams,
         keyfile_descriptor: &str,
     ) -> Result<Self> {
         let key = if sub_matches.is_present(keyfile_descriptor) && params.keyfile {
-            Key::Keyfile(
+            let mut paths: Vec<String> = sub_matches
+                .values_of(keyfile_descriptor)
+                .context("No keyfile/invalid text provided")?
+                .map(str::to_string)
+                .collect();
+
+            if paths.iter().filter(|path| *path == "-").count() > 1 {
+                return Err(anyhow::anyhow!("Only one keyfile may be read from STDIN"));
+            }
+
+            // otherwise the same file would silently count as two of the keyfiles that are needed
+            let mut seen = std::collections::HashSet::new();
+            if let Some(path) = paths.iter().find(|path| {
+                !seen.insert(std::fs::canonicalize(path).unwrap_or_else(|_| path.into()))
+            }) {
+                return Err(anyhow::anyhow!(
+                    "The keyfile '{}' was provided more than once",
+                    path
+                ));
+            }
+
+            if paths.len() == 1 {
+                Key::Keyfile(paths.remove(0))
+            } else {
+                Key::Keyfiles(paths)
+            }
+        } else if let (Ok(Some(fd)), true) = (
+            sub_matches.try_get_one::<u32>(&format!("{}-fd", keyfile_descriptor)),
+            params.keyfile,
+        ) {
+            Key::Keyfile(fd_path(*fd)?)
+        } else if let (Some(command), true) = (
+            // `--key-command` is the same as `--password-command`
+            // either pairs with the keyfile, e.g. `password-command-old` goes with `keyfile-old`
+            ["password-command", "key-command"].iter().find_map(|name| {
                 sub_matches
-                    .value_of(keyfile_descriptor)
-                    .context("No keyfile/invalid text provided")?
-                    .to_string(),
-            )
+                    .try_get_one::<String>(&keyfile_descriptor.replacen("keyfile", name, 1))
+                    .ok()
+                    .flatten()
+            }),
+            params.command,
+        ) {
+            Key::Command(super::command::split(command)?)
+        } else if let (Ok(Some(path)), true) = (
+            sub_matches.try_get_one::<String>(&keyfile_descriptor.replacen(
+                "keyfile",
+                "password-file",
+                1,
+            )),
+            params.user,
+        ) {
+            Key::PasswordFile(path.clone())
+        } else if let (Ok(Some(fd)), true) = (
+            sub_matches.try_get_one::<u32>(&keyfile_descriptor.replacen(
+                "keyfile",
+                "password-fd",
+                1,
+            )),
+            params.user,
+        ) {
+            Key::PasswordFile(fd_path(*fd)?)
+        } else if let (Ok(Some(slot)), true) =
+            (sub_matches.try_get_one::<String>("yubikey"), params.yubikey)
+        {
+            Key::Yubikey(slot.parse().context("Invalid YubiKey slot")?)
         } else if std::env::var("DEXIOS_KEY").is_ok() && params.env {
             Key::Env
+        } else if let (Some(path), true) = (
+            std::env::var_os(KEYFILE_ENV).filter(|path| !path.is_empty()),
+            params.env && params.keyfile,
+        ) {
+            Key::Keyfile(
+                path.into_string()
+                    .map_err(|_| anyhow::anyhow!("{} isn't valid UTF-8", KEYFILE_ENV))?,
+            )
         } else if let (Ok(true), true) = (
             sub_matches.try_contains_id("autogenerate"),
             params.autogenerate,
         ) {
-            let result = sub_matches
-                .value_of("autogenerate")
-                .context("No amount of words specified")?
-                .parse::<i32>();
-            if let Ok(value) = result {
-                Key::Generate(value)
-            } else {
-                warn!("No amount of words specified - using the default.");
-                Key::Generate(7)
-            }
+            let words = ranged(sub_matches, "autogenerate", &WORDS)?
+                .context("No amount of words specified")?;
+
+            Key::Generate(words)
         } else if params.user {
             Key::User
         } else {
@@ -170,6 +427,8 @@ pub struct KeyParams {
     pub env: bool,
     pub autogenerate: bool,
     pub keyfile: bool,
+    pub command: bool,
+    pub yubikey: bool,
 }
 
 impl KeyParams {
@@ -179,6 +438,245 @@ impl KeyParams {
             env: true,
             autogenerate: true,
             keyfile: true,
+            command: true,
+            yubikey: true,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // the environment is shared between tests, so any test that reads it has to hold this
+    static ENV: Mutex<()> = Mutex::new(());
+
+    fn encrypt_matches(args: &[&str]) -> ArgMatches {
+        let mut argv = vec!["dexios", "encrypt"];
+        argv.extend_from_slice(args);
+        argv.extend_from_slice(&["input", "output"]);
+
+        crate::cli::build()
+            .get_matches_from(argv)
+            .subcommand_matches("encrypt")
+            .unwrap()
+            .clone()
+    }
+
+    fn temp_file(name: &str, contents: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("dexios-{}-{}", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn should_use_the_keyfile_from_the_environment() {
+        let _env = ENV
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        std::env::remove_var("DEXIOS_KEY");
+        std::env::set_var(KEYFILE_ENV, "env.key");
+
+        let key = Key::init(&encrypt_matches(&[]), &KeyParams::default(), "keyfile").unwrap();
+        assert!(key == Key::Keyfile("env.key".to_string()));
+
+        // `-k` takes precedence over the environment
+        let key = Key::init(
+            &encrypt_matches(&["-k", "arg.key"]),
+            &KeyParams::default(),
+            "keyfile",
+        )
+        .unwrap();
+        assert!(key == Key::Keyfile("arg.key".to_string()));
+
+        // and the environment isn't read where keyfiles aren't allowed
+        let params = KeyParams {
+            keyfile: false,
+            ..KeyParams::default()
+        };
+        let key = Key::init(&encrypt_matches(&[]), &params, "keyfile").unwrap();
+        assert!(key == Key::User);
+
+        std::env::remove_var(KEYFILE_ENV);
+    }
+
+    #[test]
+    fn should_refuse_a_keyfile_that_is_provided_twice() {
+        let path = temp_file("repeated-keyfile", b"keyfile contents");
+        let path = path.to_str().unwrap();
+        let other = temp_file("other-keyfile", b"other keyfile contents");
+        let other = other.to_str().unwrap();
+
+        let key = Key::init(
+            &encrypt_matches(&["-k", path, "-k", other]),
+            &KeyParams::default(),
+            "keyfile",
+        )
+        .unwrap();
+        assert!(key == Key::Keyfiles(vec![path.to_string(), other.to_string()]));
+
+        assert!(Key::init(
+            &encrypt_matches(&["-k", path, "-k", other, "-k", path]),
+            &KeyParams::default(),
+            "keyfile",
+        )
+        .is_err());
+
+        // the same file is refused under another name too
+        let dir = std::path::Path::new(path).parent().unwrap();
+        let renamed = dir
+            .join(".")
+            .join(std::path::Path::new(path).file_name().unwrap());
+        assert!(Key::init(
+            &encrypt_matches(&["-k", path, "-k", renamed.to_str().unwrap()]),
+            &KeyParams::default(),
+            "keyfile",
+        )
+        .is_err());
+
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(other).unwrap();
+    }
+
+    #[test]
+    fn should_ignore_an_empty_keyfile_variable() {
+        let _env = ENV
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        std::env::remove_var("DEXIOS_KEY");
+        std::env::set_var(KEYFILE_ENV, "");
+
+        let key = Key::init(&encrypt_matches(&[]), &KeyParams::default(), "keyfile").unwrap();
+        assert!(key == Key::User);
+
+        std::env::remove_var(KEYFILE_ENV);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn should_use_the_keyfile_from_a_descriptor() {
+        use std::os::unix::io::AsRawFd;
+
+        let _env = ENV
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let path = temp_file("keyfile-fd", b"keyfile contents\n");
+        let file = std::fs::File::open(&path).unwrap();
+        let fd = file.as_raw_fd().to_string();
+
+        let key = Key::init(
+            &encrypt_matches(&["--keyfile-fd", &fd]),
+            &KeyParams::default(),
+            "keyfile",
+        )
+        .unwrap();
+        assert!(key == Key::Keyfile(format!("/dev/fd/{}", fd)));
+
+        // keyfiles are used exactly as they are, newline included
+        let secret = key.get_secret(&PasswordState::Direct).unwrap();
+        assert_eq!(secret.expose(), b"keyfile contents\n");
+
+        drop(file);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn should_strip_a_single_trailing_newline() {
+        let strip = |secret: &[u8]| {
+            strip_newline(Protected::new(secret.to_vec()))
+                .expose()
+                .clone()
+        };
+
+        assert_eq!(strip(b"hunter2\n"), b"hunter2");
+        assert_eq!(strip(b"hunter2\r\n"), b"hunter2");
+        assert_eq!(strip(b"hunter2\n\n"), b"hunter2\n");
+        assert_eq!(strip(b"hunter2"), b"hunter2");
+        assert_eq!(strip(b" hunter2 \t"), b" hunter2 \t");
+        assert_eq!(strip(b"\n"), b"");
+    }
+
+    #[test]
+    fn should_read_the_password_from_a_file() {
+        let path = temp_file("password-file", b"hunter2\n");
+        let path_str = path.to_str().unwrap().to_string();
+
+        let key = Key::init(
+            &encrypt_matches(&["--password-file", &path_str]),
+            &KeyParams::default(),
+            "keyfile",
+        )
+        .unwrap();
+        assert!(key == Key::PasswordFile(path_str));
+
+        // it's the password itself, so it's never hashed like a keyfile would be
+        let secret = key.get_secret_with(&PasswordState::Direct, true).unwrap();
+        assert_eq!(secret.expose(), b"hunter2");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn should_refuse_an_empty_or_missing_password_file() {
+        let empty = temp_file("password-file-empty", b"");
+        let newline = temp_file("password-file-newline", b"\r\n");
+
+        assert!(read_password_file(empty.to_str().unwrap()).is_err());
+        assert!(read_password_file(newline.to_str().unwrap()).is_err());
+        assert!(read_password_file("dexios-no-such-password-file").is_err());
+
+        std::fs::remove_file(empty).unwrap();
+        std::fs::remove_file(newline).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn should_read_the_password_from_a_descriptor() {
+        use std::os::unix::io::AsRawFd;
+
+        let path = temp_file("password-fd", b"hunter2\r\n");
+        let file = std::fs::File::open(&path).unwrap();
+        let fd = file.as_raw_fd().to_string();
+
+        let key = Key::init(
+            &encrypt_matches(&["--password-fd", &fd]),
+            &KeyParams::default(),
+            "keyfile",
+        )
+        .unwrap();
+        assert!(key == Key::PasswordFile(format!("/dev/fd/{}", fd)));
+
+        let secret = key.get_secret(&PasswordState::Validate).unwrap();
+        assert_eq!(secret.expose(), b"hunter2");
+
+        drop(file);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn should_refuse_a_password_file_alongside_a_keyfile() {
+        assert!(crate::cli::build()
+            .try_get_matches_from([
+                "dexios",
+                "encrypt",
+                "-k",
+                "key",
+                "--password-file",
+                "password",
+                "in",
+                "out"
+            ])
+            .is_err());
+    }
+
+    #[test]
+    fn should_refuse_an_invalid_descriptor() {
+        assert!(crate::cli::build()
+            .try_get_matches_from(["dexios", "encrypt", "--keyfile-fd", "-1", "in", "out"])
+            .is_err());
+        assert!(crate::cli::build()
+            .try_get_matches_from(["dexios", "encrypt", "--keyfile-fd", "three", "in", "out"])
+            .is_err());
+    }
+}
@@ -0,0 +1,341 @@
+// this estimates how many guesses it would take to find a password, in the style of zxcvbn
+// the password is split into the cheapest run of patterns (common passwords, repeats, sequences, keyboard rows and years)
+// anything that isn't part of a pattern is brute-forced, from the character classes that the password uses
+//
+// new passwords that are entered interactively are checked, and a weak one has to be confirmed
+// with `--strict`, weak passwords are refused instead
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::Result;
+use core::protected::Protected;
+use core::Zeroize;
+
+use crate::cli::prompt::get_answer;
+use crate::global::states::ForceMode;
+use crate::warn;
+
+// passwords that score below this are weak (zxcvbn's 3 is "safely unguessable" against offline attacks on a slow hash)
+const MIN_SCORE: u8 = 3;
+
+// these are the most common passwords (and password fragments), in order
+// a match costs as many guesses as its rank, so only the first few are almost free
+const COMMON: &[&str] = &[
+    "password", "123456", "qwerty", "letmein", "iloveyou", "admin", "welcome", "monkey", "dragon",
+    "login", "abc123", "master", "sunshine", "princess", "football", "baseball", "shadow",
+    "superman", "batman", "trustno1", "freedom", "whatever", "starwars", "hello", "secret",
+    "passw0rd", "charlie", "michael", "jordan", "hunter", "ranger", "buster", "soccer", "hockey",
+    "killer", "george", "andrew", "thomas", "jessica", "pepper", "ginger", "summer", "winter",
+    "spring", "autumn", "love", "pass", "test", "guest", "root", "user", "default", "changeme",
+    "dexios", "encrypt", "backup", "computer", "internet", "mustang", "access", "flower", "cookie",
+    "cheese", "banana", "orange", "purple", "silver", "golden", "diamond", "angel", "tigger",
+    "maggie", "daniel",
+];
+
+const KEYBOARD_ROWS: &[&str] = &["qwertyuiop", "asdfghjkl", "zxcvbnm", "1234567890"];
+
+// common substitutions, which are undone before looking for common passwords
+fn unleet(c: char) -> char {
+    match c {
+        '0' => 'o',
+        '1' | '!' => 'i',
+        '3' => 'e',
+        '4' | '@' => 'a',
+        '5' | '$' => 's',
+        '7' => 't',
+        c => c.to_ascii_lowercase(),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Pattern {
+    BruteForce,
+    Common,
+    Repeat,
+    Sequence,
+    Keyboard,
+    Year,
+}
+
+pub struct Estimate {
+    // log2 of the number of guesses
+    pub bits: f64,
+    // from 0 (trivially guessable) to 4 (very unguessable), like zxcvbn
+    pub score: u8,
+    pub warning: Option<&'static str>,
+}
+
+// this is the size of the alphabet that an attacker would have to brute-force
+fn charset_size(chars: &[char]) -> f64 {
+    let mut size = 0;
+    if chars.iter().any(char::is_ascii_lowercase) {
+        size += 26;
+    }
+    if chars.iter().any(char::is_ascii_uppercase) {
+        size += 26;
+    }
+    if chars.iter().any(char::is_ascii_digit) {
+        size += 10;
+    }
+    if chars.iter().any(char::is_ascii_punctuation) || chars.contains(&' ') {
+        size += 33;
+    }
+    if chars.iter().any(|c| !c.is_ascii()) {
+        size += 100;
+    }
+    f64::from(size.max(10))
+}
+
+// this returns the cost (in bits) of every pattern that covers `chars[start..end]`, if any do
+fn patterns(chars: &[char], start: usize, end: usize) -> Vec<(Pattern, f64)> {
+    let slice = &chars[start..end];
+    let len = slice.len();
+    let mut found = Vec::new();
+
+    let mut lower: String = slice.iter().map(char::to_ascii_lowercase).collect();
+    let mut unleeted: String = slice.iter().map(|c| unleet(*c)).collect();
+    if let Some(rank) = COMMON.iter().position(|w| *w == lower || *w == unleeted) {
+        // capitals and substitutions are the first things that are tried
+        let mut bits = (rank as f64 + 1.0).log2();
+        if slice.iter().any(char::is_ascii_uppercase) {
+            bits += 1.0;
+        }
+        if COMMON[rank] != lower {
+            bits += 1.0;
+        }
+        found.push((Pattern::Common, bits));
+    }
+
+    if len < 3 {
+        lower.zeroize();
+        unleeted.zeroize();
+        return found;
+    }
+
+    if slice.iter().all(|c| *c == slice[0]) {
+        found.push((
+            Pattern::Repeat,
+            charset_size(slice).log2() + (len as f64).log2(),
+        ));
+    }
+
+    let deltas: Vec<i64> = slice
+        .windows(2)
+        .map(|w| i64::from(u32::from(w[1])) - i64::from(u32::from(w[0])))
+        .collect();
+    if deltas.iter().all(|d| *d == deltas[0]) && deltas[0].abs() == 1 {
+        let alphabet: f64 = if slice[0].is_ascii_digit() {
+            10.0
+        } else {
+            26.0
+        };
+        found.push((
+            Pattern::Sequence,
+            alphabet.log2() + (len as f64).log2() + 1.0,
+        ));
+    }
+
+    let mut reversed: String = lower.chars().rev().collect();
+    if KEYBOARD_ROWS
+        .iter()
+        .any(|row| row.contains(&lower) || row.contains(&reversed))
+    {
+        // the row, where it starts and which way it goes
+        found.push((Pattern::Keyboard, 40f64.log2() + (len as f64).log2() + 1.0));
+    }
+
+    if len == 4 {
+        if let Ok(year) = lower.parse::<u32>() {
+            if (1900..2040).contains(&year) {
+                found.push((Pattern::Year, 140f64.log2()));
+            }
+        }
+    }
+
+    // these are fragments of the password
+    lower.zeroize();
+    unleeted.zeroize();
+    reversed.zeroize();
+
+    found
+}
+
+pub fn estimate(password: &str) -> Estimate {
+    let mut chars: Vec<char> = password.chars().collect();
+    let n = chars.len();
+    let brute_force = charset_size(&chars).log2();
+
+    // best[i] is the cheapest way to guess the first i characters, and how it ends
+    let mut best: Vec<(f64, usize, Pattern)> = vec![(f64::INFINITY, 0, Pattern::BruteForce); n + 1];
+    best[0].0 = 0.0;
+    for end in 1..=n {
+        best[end] = (best[end - 1].0 + brute_force, end - 1, Pattern::BruteForce);
+        for start in 0..end {
+            for (pattern, bits) in patterns(&chars, start, end) {
+                // every pattern costs a bit, so that splitting a password up isn't free
+                let total = best[start].0 + bits + 1.0;
+                if total < best[end].0 {
+                    best[end] = (total, start, pattern);
+                }
+            }
+        }
+    }
+
+    // the warning comes from the longest pattern that was found
+    let mut warning = None;
+    let mut longest = 0;
+    let mut end = n;
+    while end > 0 {
+        let (_, start, pattern) = best[end];
+        if pattern != Pattern::BruteForce && end - start > longest {
+            longest = end - start;
+            warning = Some(match pattern {
+                Pattern::Common => "This is similar to a commonly used password",
+                Pattern::Repeat => "Repeated characters like \"aaa\" are easy to guess",
+                Pattern::Sequence => "Sequences like \"abc\" or \"6543\" are easy to guess",
+                Pattern::Keyboard => "Straight rows of keys are easy to guess",
+                Pattern::Year => "Years are easy to guess",
+                Pattern::BruteForce => unreachable!(),
+            });
+        }
+        end = start;
+    }
+
+    let bits = best[n].0;
+    chars.zeroize();
+
+    let score = match bits {
+        b if b < 10.0 => 0,
+        b if b < 20.0 => 1,
+        b if b < 26.6 => 2,
+        b if b < 33.2 => 3,
+        _ => 4,
+    };
+
+    Estimate {
+        bits,
+        score,
+        warning: warning.or_else(|| (n < 12).then_some("Short passwords are easy to guess")),
+    }
+}
+
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed);
+}
+
+// this is used on new passwords, once they've been confirmed
+pub fn check(password: &Protected<Vec<u8>>) -> Result<()> {
+    let estimate = {
+        let password = Protected::new(String::from_utf8_lossy(password.expose()).into_owned());
+        estimate(password.expose())
+    };
+
+    if estimate.score >= MIN_SCORE {
+        return Ok(());
+    }
+
+    warn!(
+        "This password is weak (score {}/4), it could be guessed in around 2^{:.0} attempts",
+        estimate.score, estimate.bits
+    );
+    if let Some(warning) = estimate.warning {
+        warn!("{}", warning);
+    }
+
+    if STRICT.load(Ordering::Relaxed) {
+        return Err(anyhow::anyhow!(
+            "Refusing to use a weak password (use a longer passphrase, or --auto)"
+        ));
+    }
+
+    if get_answer("Would you like to use it anyway?", false, ForceMode::Prompt)? {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "Please choose a stronger password (a longer passphrase, or --auto)"
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_score_common_patterns_as_weak() {
+        for (password, warning) in [
+            ("password", "This is similar to a commonly used password"),
+            ("P@ssw0rd", "This is similar to a commonly used password"),
+            ("dexios1990", "This is similar to a commonly used password"),
+            (
+                "aaaaaaaaaaaa",
+                "Repeated characters like \"aaa\" are easy to guess",
+            ),
+            (
+                "abcdefgh",
+                "Sequences like \"abc\" or \"6543\" are easy to guess",
+            ),
+            (
+                "98765",
+                "Sequences like \"abc\" or \"6543\" are easy to guess",
+            ),
+            ("qwertyuiop", "Straight rows of keys are easy to guess"),
+            ("asdfghjkl", "Straight rows of keys are easy to guess"),
+            ("1990", "Years are easy to guess"),
+        ] {
+            let estimate = estimate(password);
+            assert!(estimate.score < MIN_SCORE, "{} should be weak", password);
+            assert_eq!(estimate.warning, Some(warning), "{}", password);
+        }
+    }
+
+    #[test]
+    fn should_score_long_or_random_passwords_as_strong() {
+        for password in [
+            "correct horse battery staple",
+            "xK9#mQ2$vL7!",
+            "Tr0ub4dor&3",
+        ] {
+            assert_eq!(estimate(password).score, 4, "{} should be strong", password);
+        }
+
+        assert_eq!(estimate("correct horse battery staple").warning, None);
+        assert_eq!(
+            estimate("Tr0ub4dor&3").warning,
+            Some("Short passwords are easy to guess")
+        );
+    }
+
+    #[test]
+    fn should_only_pass_passwords_at_the_threshold() {
+        // random lowercase letters are brute-forced, at log2(26) bits each
+        let below = estimate("zqxjv");
+        let at = estimate("zqxjvk");
+
+        assert!((below.bits - 26f64.log2() * 5.0).abs() < 1e-9);
+        assert_eq!(below.score, MIN_SCORE - 1);
+        assert_eq!(at.score, MIN_SCORE);
+    }
+
+    #[test]
+    fn should_score_an_empty_password_as_zero() {
+        let estimate = estimate("");
+        assert_eq!(estimate.score, 0);
+        assert_eq!(estimate.warning, Some("Short passwords are easy to guess"));
+    }
+
+    #[test]
+    fn should_refuse_weak_passwords_when_strict() {
+        set_strict(true);
+
+        assert!(check(&Protected::new(b"password".to_vec())).is_err());
+        assert!(check(&Protected::new(b"zqxjv".to_vec())).is_err());
+        // strong passwords never reach the prompt
+        assert!(check(&Protected::new(b"zqxjvk".to_vec())).is_ok());
+        assert!(check(&Protected::new(b"correct horse battery staple".to_vec())).is_ok());
+
+        set_strict(false);
+    }
+}
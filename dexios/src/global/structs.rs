@@ -1,9 +1,13 @@
 use core::header::HashingAlgorithm;
+use std::num::NonZeroUsize;
+
+use super::policy::Policy;
 
 use crate::global::states::{ForceMode, HashMode};
 
 use super::states::{
     Compression, DirectoryMode, EraseMode, EraseSourceDir, HeaderLocation, Key, PrintMode,
+    StatsMode,
 };
 
 pub struct CryptoParams {
@@ -13,17 +17,32 @@ pub struct CryptoParams {
     pub key: Key,
     pub header_location: HeaderLocation,
     pub hashing_algorithm: HashingAlgorithm,
+    pub policy: Option<Policy>,
+    // the output is moved here once it's complete, see `domain::relocate`
+    pub move_to: Option<String>,
 }
 
 pub struct PackParams {
+    #[allow(dead_code)]
     pub dir_mode: DirectoryMode,
     pub print_mode: PrintMode,
     pub erase_source: EraseSourceDir,
     pub compression: Compression,
+    pub jobs: NonZeroUsize,
+    pub stats_mode: StatsMode,
+    pub streams: domain::streams::Options,
+    pub filters: Vec<domain::filters::Filter>,
+    pub roots: Vec<domain::pack::Root>,
+    pub counter: core::primitives::StreamCounter,
+    pub manifest: bool,
+    pub special_files: domain::storage::SpecialFiles,
+    // the percentage of each type of file to sample, if only an estimate is wanted
+    pub estimate: Option<u8>,
 }
 
 pub struct KeyManipulationParams {
     pub key_old: Key,
     pub key_new: Key,
     pub hashing_algorithm: HashingAlgorithm,
+    pub policy: Option<Policy>,
 }
@@ -0,0 +1,61 @@
+// this allows a yubikey's HMAC-SHA1 challenge-response slot to be used as the key
+// the salt of each keyslot is sent as the challenge, and the response is mixed into the key before it's hashed
+// the slot needs to be programmed beforehand (e.g. `ykman otp chalresp --generate 2`)
+
+use anyhow::Result;
+use challenge_response::config::{Config, Mode, Slot};
+use challenge_response::ChallengeResponse;
+use core::challenge::Responder;
+use core::protected::Protected;
+
+use crate::info;
+
+pub struct Yubikey {
+    slot: u8,
+}
+
+impl Yubikey {
+    pub fn new(slot: u8) -> Result<Self> {
+        if Slot::from_int(slot.into()).is_none() {
+            return Err(anyhow::anyhow!("YubiKeys only have slots 1 and 2"));
+        }
+
+        // this is checked up-front, as errors from within key derivation don't reach the user
+        ChallengeResponse::new()
+            .and_then(|mut yubikey| yubikey.find_device())
+            .map_err(|_| anyhow::anyhow!("Unable to find a YubiKey (is it plugged in?)"))?;
+
+        Ok(Self { slot })
+    }
+}
+
+impl Responder for Yubikey {
+    fn respond(&mut self, challenge: &[u8]) -> Result<Protected<Vec<u8>>> {
+        let mut yubikey = ChallengeResponse::new()
+            .map_err(|e| anyhow::anyhow!("Unable to access USB devices: {}", e))?;
+        let device = yubikey
+            .find_device()
+            .map_err(|_| anyhow::anyhow!("Unable to find a YubiKey (is it plugged in?)"))?;
+
+        // this can't fail, as the slot was checked in `Yubikey::new()`
+        let slot = Slot::from_int(self.slot.into()).unwrap();
+        let config = Config::new_from(device)
+            .set_variable_size(true)
+            .set_mode(Mode::Sha1)
+            .set_slot(slot);
+
+        info!("Touch your YubiKey if it's flashing");
+
+        let response = yubikey
+            .challenge_response_hmac(challenge, config)
+            .map_err(|e| {
+                anyhow::anyhow!(
+                    "YubiKey challenge-response failed (is slot {} configured for HMAC-SHA1?): {}",
+                    self.slot,
+                    e
+                )
+            })?;
+
+        Ok(Protected::new(response.to_vec()))
+    }
+}
@@ -13,6 +13,16 @@ mod subcommands;
 // it handles the calling of other functions, and some (minimal) argument parsing
 fn main() -> Result<()> {
     let matches = cli::get_matches();
+    let agent = global::agent::Session::start();
+
+    if let Some((name, sub_matches)) = matches.subcommand() {
+        global::set_quiet(subcommands::quiet(name, sub_matches));
+        subcommands::crypto_backend(sub_matches)?;
+        subcommands::pinentry(sub_matches);
+        subcommands::tmpdir(sub_matches)?;
+        subcommands::password_strength(sub_matches);
+        subcommands::agent_client(name);
+    }
 
     match matches.subcommand() {
         Some(("encrypt", sub_matches)) => {
@@ -24,12 +34,41 @@ fn main() -> Result<()> {
         Some(("erase", sub_matches)) => {
             subcommands::erase(sub_matches)?;
         }
-        Some(("pack", sub_matches)) => {
-            subcommands::pack(sub_matches)?;
+        Some(("pack", sub_matches)) => match sub_matches.subcommand_name() {
+            Some("recompress") => {
+                subcommands::pack_recompress(sub_matches)?;
+            }
+            _ => {
+                subcommands::pack(sub_matches)?;
+            }
+        },
+        Some(("backup", sub_matches)) => {
+            subcommands::backup(sub_matches)?;
+        }
+        Some(("restore", sub_matches)) => {
+            subcommands::restore(sub_matches)?;
         }
         Some(("unpack", sub_matches)) => {
             subcommands::unpack(sub_matches)?;
         }
+        Some(("manifest", sub_matches)) => {
+            subcommands::manifest(sub_matches)?;
+        }
+        Some(("agent", sub_matches)) => {
+            subcommands::agent(sub_matches)?;
+        }
+        Some(("audit", sub_matches)) => {
+            subcommands::audit(sub_matches)?;
+        }
+        Some(("upgrade", sub_matches)) => {
+            subcommands::upgrade(sub_matches)?;
+        }
+        Some(("sign", sub_matches)) => {
+            subcommands::sign(sub_matches)?;
+        }
+        Some(("verify-sig", sub_matches)) => {
+            subcommands::verify_sig(sub_matches)?;
+        }
         Some(("hash", sub_matches)) => {
             subcommands::hash_stream(sub_matches)?;
         }
@@ -43,27 +82,77 @@ fn main() -> Result<()> {
             Some("strip") => {
                 subcommands::header_strip(sub_matches)?;
             }
+            Some("recover") => {
+                subcommands::header_recover(sub_matches)?;
+            }
+            Some("verify") => {
+                subcommands::header_verify(sub_matches)?;
+            }
             Some("details") => {
                 subcommands::header_details(sub_matches)?;
             }
             _ => (),
         },
+        Some(("export-recovery", sub_matches)) => {
+            subcommands::export_recovery(sub_matches)?;
+        }
+        Some(("import-recovery", sub_matches)) => {
+            subcommands::import_recovery(sub_matches)?;
+        }
+        Some(("kdf", sub_matches)) if sub_matches.subcommand_name() == Some("bench") => {
+            subcommands::kdf_bench()?;
+        }
+        Some(("info", _)) => {
+            subcommands::info()?;
+        }
+        Some(("version", sub_matches)) => {
+            subcommands::version(sub_matches)?;
+        }
+        Some(("examples", sub_matches)) => {
+            subcommands::examples(sub_matches)?;
+        }
+        Some(("send", sub_matches)) => {
+            subcommands::send(sub_matches)?;
+        }
+        Some(("receive", sub_matches)) => {
+            subcommands::receive(sub_matches)?;
+        }
         Some(("key", sub_matches)) => match sub_matches.subcommand_name() {
             Some("change") => {
                 subcommands::key_change(sub_matches)?;
             }
+            Some("rotate") => {
+                subcommands::key_rotate(sub_matches)?;
+            }
             Some("add") => {
                 subcommands::key_add(sub_matches)?;
             }
             Some("del") => {
                 subcommands::key_del(sub_matches)?;
             }
+            Some("list") => {
+                subcommands::key_list(sub_matches)?;
+            }
             Some("verify") => {
                 subcommands::key_verify(sub_matches)?;
             }
+            Some("export-mnemonic") => {
+                subcommands::key_export_mnemonic(sub_matches)?;
+            }
+            Some("generate") => {
+                subcommands::key_generate(sub_matches)?;
+            }
+            Some("keypair") => {
+                subcommands::key_keypair(sub_matches)?;
+            }
+            Some("calibrate") => {
+                subcommands::key_calibrate(sub_matches)?;
+            }
             _ => (),
         },
         _ => (),
     }
+
+    agent.succeeded();
     Ok(())
 }
@@ -1,47 +1,150 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::ArgMatches;
+use core::kdf::Kdf;
+use core::os_crypto::CryptoBackend;
+use core::primitives::Padding;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
 
 // this is called from main.rs
 // it gets params and sends them to the appropriate functions
 
 use crate::global::{
+    config::Config,
     parameters::{
-        algorithm, erase_params, forcemode, get_param, get_params, key_manipulation_params,
-        pack_params, parameter_handler,
+        agent_ttl, algorithm, assumed_header_type, block_size, compat_version, compression,
+        erase_params, extract_limits, filters, forcemode, get_param, get_params, hashing_algorithm,
+        key_manipulation_params, mode, pack_params, parameter_handler, range, ranged,
+        recompression, split_size, stream_counter, stream_options, JOBS, SCAN_LIMIT,
     },
+    policy::Policy,
     states::{Key, KeyParams},
 };
 
+pub mod agent;
+pub mod audit;
+pub mod backup;
 pub mod decrypt;
 pub mod encrypt;
 pub mod erase;
 pub mod hashing;
 pub mod header;
+pub mod info;
+pub mod kdf;
 pub mod key;
+pub mod manifest;
 pub mod pack;
+pub mod recovery_bundle;
+pub mod restore;
+pub mod sign;
+pub mod transfer;
 pub mod unpack;
+pub mod upgrade;
 
 pub fn encrypt(sub_matches: &ArgMatches) -> Result<()> {
     let params = parameter_handler(sub_matches)?;
-    let algorithm = algorithm(sub_matches);
+    // `--paranoid` conflicts with everything that it overrides, so the algorithm is XChaCha20-Poly1305 (and the KDF's memory is handled with the other KDF parameters)
+    let paranoid = sub_matches.is_present("paranoid");
+    let algorithm = algorithm(sub_matches)?;
+    let compression = compression(sub_matches)?;
+    let block_size = block_size(sub_matches)?;
+    let mode = mode(sub_matches)?;
+
+    let padding = if sub_matches.is_present("pad") {
+        Padding::Padme
+    } else {
+        Padding::None
+    };
+
+    let output = match sub_matches.value_of("output-template") {
+        Some(template) => encrypt::Output::Template(template.parse()?),
+        None => encrypt::Output::Path(get_param("output", sub_matches)?),
+    };
 
     // stream mode is the only mode to encrypt (v8.5.0+)
-    encrypt::stream_mode(
-        &get_param("input", sub_matches)?,
-        &get_param("output", sub_matches)?,
-        &params,
+    encrypt::stream_mode(encrypt::Request {
+        input: &get_param("input", sub_matches)?,
+        output,
+        params: &params,
         algorithm,
-    )
+        mode,
+        compression,
+        block_size,
+        padding,
+        convergent: sub_matches.is_present("convergent"),
+        recipients: sub_matches
+            .get_many::<String>("recipient")
+            .map(|paths| paths.map(String::as_str).collect())
+            .unwrap_or_default(),
+        pkcs11_uri: sub_matches.value_of("pkcs11-uri"),
+        extra_keyfiles: sub_matches
+            .get_many::<String>("extra-keyfile")
+            .map(|paths| paths.map(String::as_str).collect())
+            .unwrap_or_default(),
+        recovery_key: sub_matches.is_present("recovery-key"),
+        sign_key: sub_matches.value_of("sign-key"),
+        mac: sub_matches.is_present("mac"),
+        digest: paranoid || sub_matches.is_present("digest"),
+        seekable: sub_matches.is_present("seekable"),
+        counter: stream_counter(sub_matches),
+        paranoid,
+        header_placeholder: sub_matches.is_present("header-placeholder"),
+        backup_header: sub_matches.is_present("backup-header"),
+        store_metadata: sub_matches.is_present("store-metadata"),
+        compat: compat_version(sub_matches)?,
+    })
 }
 
 pub fn decrypt(sub_matches: &ArgMatches) -> Result<()> {
     let params = parameter_handler(sub_matches)?;
 
     // stream decrypt is the default as it will redirect to memory mode if the header says so (for backwards-compat)
-    decrypt::stream_mode(
-        &get_param("input", sub_matches)?,
-        &get_param("output", sub_matches)?,
-        &params,
+    decrypt::stream_mode(decrypt::Request {
+        input: &get_param("input", sub_matches)?,
+        output: sub_matches.value_of("output"),
+        params: &params,
+        identity: sub_matches.value_of("identity"),
+        pkcs11_uri: sub_matches.value_of("pkcs11-uri"),
+        verify_key: sub_matches.value_of("verify-key"),
+        signature: sub_matches.value_of("signature"),
+        recovery_code: sub_matches.is_present("recovery"),
+        mnemonic: sub_matches.is_present("mnemonic"),
+        range: range(sub_matches)?,
+        scan_limit: ranged(sub_matches, "scan-for-header", &SCAN_LIMIT)?
+            .map(|mib| u64::from(mib.get()) * 1024 * 1024),
+        assume: assumed_header_type(sub_matches)?,
+        restore_metadata: sub_matches.is_present("restore-metadata"),
+    })
+}
+
+pub fn sign(sub_matches: &ArgMatches) -> Result<()> {
+    let input = get_param("input", sub_matches)?;
+    let signing_key = sign::read_signing_key(&get_param("sign-key", sub_matches)?)?;
+    let output = sub_matches
+        .value_of("output")
+        .map_or_else(|| sign::default_signature_path(&input), String::from);
+
+    sign::sign(
+        &input,
+        sub_matches.value_of("header"),
+        &signing_key,
+        &output,
+        forcemode(sub_matches),
+    )
+}
+
+pub fn verify_sig(sub_matches: &ArgMatches) -> Result<()> {
+    let input = get_param("input", sub_matches)?;
+    let public_key = sign::read_verify_key(&get_param("verify-key", sub_matches)?)?;
+    let signature = sub_matches
+        .value_of("signature")
+        .map_or_else(|| sign::default_signature_path(&input), String::from);
+
+    sign::verify(
+        &input,
+        sub_matches.value_of("header"),
+        &public_key,
+        &signature,
     )
 }
 
@@ -53,7 +156,7 @@ pub fn erase(sub_matches: &ArgMatches) -> Result<()> {
 
 pub fn pack(sub_matches: &ArgMatches) -> Result<()> {
     let (crypto_params, pack_params) = pack_params(sub_matches)?;
-    let algorithm = algorithm(sub_matches);
+    let algorithm = algorithm(sub_matches)?;
 
     pack::execute(&pack::Request {
         input_file: &get_params("input", sub_matches)?,
@@ -64,6 +167,74 @@ pub fn pack(sub_matches: &ArgMatches) -> Result<()> {
     })
 }
 
+pub fn pack_recompress(sub_matches: &ArgMatches) -> Result<()> {
+    let sub_matches_recompress = sub_matches.subcommand_matches("recompress").unwrap();
+
+    let (compression, level) = recompression(sub_matches_recompress)?;
+    let key = Key::init(sub_matches_recompress, &KeyParams::default(), "keyfile")?;
+
+    pack::recompress(
+        &get_param("input", sub_matches_recompress)?,
+        &get_param("output", sub_matches_recompress)?,
+        &compression,
+        level,
+        &key,
+        forcemode(sub_matches_recompress),
+    )
+}
+
+pub fn backup(sub_matches: &ArgMatches) -> Result<()> {
+    use super::global::states::PrintMode;
+
+    let print_mode = if sub_matches.is_present("verbose") {
+        PrintMode::Verbose
+    } else {
+        PrintMode::Quiet
+    };
+
+    let jobs = match ranged(sub_matches, "jobs", &JOBS)? {
+        Some(jobs) => NonZeroUsize::from(jobs),
+        None => std::thread::available_parallelism().unwrap_or(NonZeroUsize::new(1).unwrap()),
+    };
+
+    backup::execute(backup::Request {
+        input: &get_param("input", sub_matches)?,
+        destination: &get_param("destination", sub_matches)?,
+        key: Key::init(sub_matches, &KeyParams::default(), "keyfile")?,
+        hashing_algorithm: hashing_algorithm(sub_matches)?,
+        algorithm: algorithm(sub_matches)?,
+        policy: Policy::from_matches(sub_matches)?,
+        full: sub_matches.is_present("full"),
+        split: split_size(sub_matches)?,
+        obfuscate_sidecars: sub_matches.is_present("obfuscate-sidecars"),
+        upload: sub_matches.value_of("upload"),
+        streams: stream_options(sub_matches),
+        jobs,
+        print_mode,
+        force: forcemode(sub_matches),
+    })
+}
+
+pub fn restore(sub_matches: &ArgMatches) -> Result<()> {
+    use super::global::states::PrintMode;
+
+    let print_mode = if sub_matches.is_present("verbose") {
+        PrintMode::Verbose
+    } else {
+        PrintMode::Quiet
+    };
+
+    restore::execute(restore::Request {
+        input: &get_param("input", sub_matches)?,
+        output: &get_param("output", sub_matches)?,
+        key: Key::init(sub_matches, &KeyParams::default(), "keyfile")?,
+        policy: Policy::from_matches(sub_matches)?,
+        streams: stream_options(sub_matches),
+        print_mode,
+        force: forcemode(sub_matches),
+    })
+}
+
 pub fn unpack(sub_matches: &ArgMatches) -> Result<()> {
     use super::global::states::PrintMode;
 
@@ -80,9 +251,45 @@ pub fn unpack(sub_matches: &ArgMatches) -> Result<()> {
         &get_param("output", sub_matches)?,
         print_mode,
         crypto_params,
+        sub_matches
+            .try_get_one::<String>("verify-against")
+            .ok()
+            .flatten()
+            .map(String::as_str),
+        stream_options(sub_matches),
+        filters(sub_matches, Config::load()?.unpack_filters)?,
+        extract_limits(sub_matches)?,
     )
 }
 
+pub fn manifest(sub_matches: &ArgMatches) -> Result<()> {
+    let key = Key::init(sub_matches, &KeyParams::default(), "keyfile")?;
+
+    manifest::list(&get_param("input", sub_matches)?, &key)
+}
+
+pub fn audit(sub_matches: &ArgMatches) -> Result<()> {
+    let policy = Policy::from_matches(sub_matches)?;
+    let format = match sub_matches.value_of("format") {
+        Some("json") => audit::Format::Json,
+        _ => audit::Format::Csv,
+    };
+
+    audit::audit(&get_param("input", sub_matches)?, policy.as_ref(), format)
+}
+
+pub fn upgrade(sub_matches: &ArgMatches) -> Result<()> {
+    let key = Key::init(sub_matches, &KeyParams::default(), "keyfile")?;
+    let policy = Policy::from_matches(sub_matches)?;
+
+    upgrade::upgrade(&upgrade::Request {
+        inputs: &get_params("input", sub_matches)?,
+        key: &key,
+        hashing_algorithm: hashing_algorithm(sub_matches)?,
+        policy: policy.as_ref(),
+    })
+}
+
 pub fn hash_stream(sub_matches: &ArgMatches) -> Result<()> {
     let files: Vec<String> = if sub_matches.is_present("input") {
         let list: Vec<&str> = sub_matches.values_of("input").unwrap().collect();
@@ -101,6 +308,8 @@ pub fn header_dump(sub_matches: &ArgMatches) -> Result<()> {
     header::dump(
         &get_param("input", sub_matches_dump)?,
         &get_param("output", sub_matches_dump)?,
+        sub_matches_dump.is_present("armor"),
+        sub_matches_dump.is_present("qr"),
         force,
     )
 }
@@ -111,6 +320,7 @@ pub fn header_restore(sub_matches: &ArgMatches) -> Result<()> {
     header::restore(
         &get_param("input", sub_matches_restore)?,
         &get_param("output", sub_matches_restore)?,
+        sub_matches_restore.is_present("qr-image"),
     )
 }
 
@@ -120,10 +330,226 @@ pub fn header_strip(sub_matches: &ArgMatches) -> Result<()> {
     header::strip(&get_param("input", sub_matches_strip)?)
 }
 
+pub fn header_recover(sub_matches: &ArgMatches) -> Result<()> {
+    let sub_matches_recover = sub_matches.subcommand_matches("recover").unwrap();
+
+    header::recover(&get_param("input", sub_matches_recover)?)
+}
+
+pub fn header_verify(sub_matches: &ArgMatches) -> Result<()> {
+    let sub_matches_verify = sub_matches.subcommand_matches("verify").unwrap();
+    let key = Key::init(sub_matches_verify, &KeyParams::default(), "keyfile")?;
+
+    header::verify(
+        &get_param("input", sub_matches_verify)?,
+        sub_matches_verify.value_of("header"),
+        &key,
+    )
+}
+
 pub fn header_details(sub_matches: &ArgMatches) -> Result<()> {
     let sub_matches_details = sub_matches.subcommand_matches("details").unwrap();
 
-    header::details(&get_param("input", sub_matches_details)?)
+    header::details(
+        &get_param("input", sub_matches_details)?,
+        sub_matches_details.is_present("json"),
+    )
+}
+
+pub fn export_recovery(sub_matches: &ArgMatches) -> Result<()> {
+    let params = parameter_handler(sub_matches)?;
+    let algorithm = algorithm(sub_matches)?;
+
+    recovery_bundle::export(
+        &get_param("input", sub_matches)?,
+        &get_param("output", sub_matches)?,
+        &params,
+        algorithm,
+    )
+}
+
+pub fn import_recovery(sub_matches: &ArgMatches) -> Result<()> {
+    let key = Key::init(sub_matches, &KeyParams::default(), "keyfile")?;
+
+    recovery_bundle::import(
+        &get_param("input", sub_matches)?,
+        &get_param("dir", sub_matches)?,
+        &key,
+        sub_matches.is_present("force"),
+    )
+}
+
+pub fn send(sub_matches: &ArgMatches) -> Result<()> {
+    let key = Key::init(sub_matches, &KeyParams::default(), "keyfile")?;
+
+    transfer::send(
+        &get_param("input", sub_matches)?,
+        &get_param("address", sub_matches)?,
+        sub_matches.value_of("code"),
+        &key,
+        hashing_algorithm(sub_matches)?,
+        algorithm(sub_matches)?,
+    )
+}
+
+pub fn receive(sub_matches: &ArgMatches) -> Result<()> {
+    let key = Key::init(sub_matches, &KeyParams::default(), "keyfile")?;
+
+    transfer::receive(&transfer::ReceiveRequest {
+        output: &get_param("output", sub_matches)?,
+        address: &get_param("listen", sub_matches)?,
+        use_code: sub_matches.is_present("code"),
+        keep_encrypted: sub_matches.is_present("keep-encrypted"),
+        key: &key,
+        force: forcemode(sub_matches),
+    })
+}
+
+// quiet mode is also implied when the output is written to stdout, so that nothing else is mixed in with it
+pub fn quiet(name: &str, sub_matches: &ArgMatches) -> bool {
+    let stdout_output = match name {
+        "decrypt" => !sub_matches.is_present("output"),
+        "header" => {
+            sub_matches
+                .subcommand_matches("dump")
+                .and_then(|dump| dump.value_of("output"))
+                .filter(|output| *output == "-")
+                .is_some()
+                || sub_matches
+                    .subcommand_matches("details")
+                    .filter(|details| details.is_present("json"))
+                    .is_some()
+        }
+        _ => false,
+    };
+
+    stdout_output || sub_matches.is_present("quiet")
+}
+
+// this is called before any subcommand, as the backend has to be chosen before anything is encrypted
+pub fn crypto_backend(sub_matches: &ArgMatches) -> Result<()> {
+    let preference = match sub_matches.try_get_one::<String>("crypto-backend") {
+        Ok(Some(backend)) if backend != "auto" => Some(backend.parse::<CryptoBackend>()?),
+        _ => None,
+    };
+
+    info::select_backend(preference)
+}
+
+// this is called before any subcommand, so that every password prompt goes through pinentry
+pub fn pinentry(sub_matches: &ArgMatches) {
+    let program = sub_matches
+        .try_get_one::<String>("pinentry")
+        .ok()
+        .flatten()
+        .cloned();
+
+    crate::global::pinentry::set(program);
+}
+
+// this is called before any subcommand, so that every temporary file is created in the same place
+pub fn tmpdir(sub_matches: &ArgMatches) -> Result<()> {
+    let dir = match sub_matches.try_get_one::<String>("tmpdir") {
+        Ok(Some(dir)) => PathBuf::from(dir),
+        _ => match domain::storage::configured_temp_dir() {
+            Some(dir) => dir,
+            None => return Ok(()),
+        },
+    };
+
+    if !dir.is_dir() {
+        return Err(anyhow::anyhow!(
+            "The temporary directory ({}) doesn't exist, or isn't a directory",
+            dir.display()
+        ));
+    }
+
+    domain::storage::set_temp_dir(Some(dir));
+    Ok(())
+}
+
+// this is called before any subcommand, as new passwords are checked wherever they're entered
+// the agent isn't used by `key`, as its subcommands take an old and a new key
+pub fn agent_client(name: &str) {
+    crate::global::agent::set_enabled(name != "key" && name != "agent");
+}
+
+pub fn agent(sub_matches: &ArgMatches) -> Result<()> {
+    if sub_matches.is_present("clear") {
+        return agent::clear();
+    }
+    if sub_matches.is_present("stop") {
+        return agent::stop();
+    }
+
+    agent::start(
+        sub_matches.value_of("socket"),
+        agent_ttl(sub_matches)?,
+        sub_matches.is_present("foreground"),
+    )
+}
+
+pub fn password_strength(sub_matches: &ArgMatches) {
+    crate::global::strength::set_strict(matches!(sub_matches.try_contains_id("strict"), Ok(true)));
+}
+
+pub fn kdf_bench() -> Result<()> {
+    kdf::bench()
+}
+
+pub fn key_calibrate(sub_matches: &ArgMatches) -> Result<()> {
+    let sub_matches_calibrate = sub_matches.subcommand_matches("calibrate").unwrap();
+
+    let target = sub_matches_calibrate
+        .get_one::<u64>("target")
+        .context("No target provided")?;
+    let max_memory = sub_matches_calibrate
+        .get_one::<u32>("max-memory")
+        .context("No memory limit provided")?;
+
+    kdf::calibrate(
+        crate::global::parameters::kdf(sub_matches_calibrate)?.unwrap_or(Kdf::Blake3Balloon),
+        std::time::Duration::from_millis(*target),
+        *max_memory,
+        sub_matches_calibrate.is_present("write"),
+    )
+}
+
+pub fn info() -> Result<()> {
+    info::report()
+}
+
+pub fn version(sub_matches: &ArgMatches) -> Result<()> {
+    info::version(sub_matches.is_present("json"));
+    Ok(())
+}
+
+pub fn examples(sub_matches: &ArgMatches) -> Result<()> {
+    use crate::cli::examples::{format, EXAMPLES};
+
+    let command = sub_matches
+        .values_of("command")
+        .map(|words| words.collect::<Vec<_>>().join(" "));
+
+    let examples = EXAMPLES
+        .iter()
+        .filter(|example| {
+            command.as_ref().is_none_or(|command| {
+                example.command == command || example.command.starts_with(&format!("{} ", command))
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if examples.is_empty() {
+        return Err(anyhow::anyhow!(
+            "There are no examples for `{}` (see `dexios examples` for all of them)",
+            command.unwrap_or_default()
+        ));
+    }
+
+    println!("{}", format(examples.into_iter(), ""));
+
+    Ok(())
 }
 
 pub fn key_change(sub_matches: &ArgMatches) -> Result<()> {
@@ -134,19 +560,70 @@ pub fn key_change(sub_matches: &ArgMatches) -> Result<()> {
     key::change(&get_param("input", sub_matches_change_key)?, &params)
 }
 
+pub fn key_rotate(sub_matches: &ArgMatches) -> Result<()> {
+    let sub_matches_rotate_key = sub_matches.subcommand_matches("rotate").unwrap();
+
+    let params = key_manipulation_params(sub_matches_rotate_key)?;
+
+    key::rotate(&get_param("input", sub_matches_rotate_key)?, &params)
+}
+
 pub fn key_add(sub_matches: &ArgMatches) -> Result<()> {
     let sub_matches_add_key = sub_matches.subcommand_matches("add").unwrap();
 
     let params = key_manipulation_params(sub_matches_add_key)?;
 
-    key::add(&get_param("input", sub_matches_add_key)?, &params)
+    key::add(
+        &get_param("input", sub_matches_add_key)?,
+        &params,
+        sub_matches_add_key.is_present("metadata-only"),
+    )
 }
 
 pub fn key_del(sub_matches: &ArgMatches) -> Result<()> {
     let sub_matches_del_key = sub_matches.subcommand_matches("del").unwrap();
     let key = Key::init(sub_matches_del_key, &KeyParams::default(), "keyfile")?;
 
-    key::delete(&get_param("input", sub_matches_del_key)?, &key)
+    key::delete(
+        &get_param("input", sub_matches_del_key)?,
+        &key,
+        sub_matches_del_key.get_one::<usize>("slot").copied(),
+    )
+}
+
+pub fn key_list(sub_matches: &ArgMatches) -> Result<()> {
+    let sub_matches_list_key = sub_matches.subcommand_matches("list").unwrap();
+
+    key::list(&get_param("input", sub_matches_list_key)?)
+}
+
+pub fn key_keypair(sub_matches: &ArgMatches) -> Result<()> {
+    let sub_matches_keypair = sub_matches.subcommand_matches("keypair").unwrap();
+
+    let output = get_param("output", sub_matches_keypair)?;
+    let force = forcemode(sub_matches_keypair);
+
+    if sub_matches_keypair.is_present("signing") {
+        key::signing_keypair(&output, force)
+    } else {
+        key::keypair(&output, sub_matches_keypair.is_present("x25519"), force)
+    }
+}
+
+pub fn key_generate(sub_matches: &ArgMatches) -> Result<()> {
+    let sub_matches_generate = sub_matches.subcommand_matches("generate").unwrap();
+
+    let output = get_param("output", sub_matches_generate)?;
+    let size = *sub_matches_generate
+        .get_one::<u64>("size")
+        .context("No keyfile size provided")?;
+
+    key::generate(
+        &output,
+        usize::try_from(size)?,
+        sub_matches_generate.is_present("fingerprint"),
+        forcemode(sub_matches_generate),
+    )
 }
 
 pub fn key_verify(sub_matches: &ArgMatches) -> Result<()> {
@@ -155,3 +632,10 @@ pub fn key_verify(sub_matches: &ArgMatches) -> Result<()> {
 
     key::verify(&get_param("input", sub_matches_verify_key)?, &key)
 }
+
+pub fn key_export_mnemonic(sub_matches: &ArgMatches) -> Result<()> {
+    let sub_matches_export = sub_matches.subcommand_matches("export-mnemonic").unwrap();
+    let key = Key::init(sub_matches_export, &KeyParams::default(), "keyfile")?;
+
+    key::export_mnemonic(&get_param("input", sub_matches_export)?, &key)
+}
@@ -0,0 +1,373 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::global::agent::{request, SOCKET_ENV};
+use crate::success;
+
+// this sends a command to the agent that DEXIOS_AGENT_SOCK points at
+fn send(command: &str) -> Result<()> {
+    let socket = std::env::var(SOCKET_ENV)
+        .with_context(|| format!("{} isn't set, so there's no agent to use", SOCKET_ENV))?;
+
+    match request(&socket, command) {
+        Some(response) if response.expose().starts_with("OK") => Ok(()),
+        Some(response) => Err(anyhow::anyhow!(
+            "The agent refused the request: {}",
+            response.expose().trim_end()
+        )),
+        None => Err(anyhow::anyhow!(
+            "Unable to reach the agent at {} (is it running?)",
+            socket
+        )),
+    }
+}
+
+pub fn clear() -> Result<()> {
+    send("CLEAR")?;
+    success!("The agent has forgotten its password");
+    Ok(())
+}
+
+pub fn stop() -> Result<()> {
+    send("STOP")?;
+    success!("The agent has been stopped");
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn start(_socket: Option<&str>, _ttl: Duration, _foreground: bool) -> Result<()> {
+    Err(anyhow::anyhow!(
+        "The agent is only supported on Unix-like systems"
+    ))
+}
+
+// this starts the agent in the background, and prints the commands that point dexios at it (like `ssh-agent`)
+// e.g. `eval "$(dexios agent)"`
+// with `foreground`, it runs the agent itself instead (the background agent is this, with a new process)
+#[cfg(unix)]
+pub fn start(socket: Option<&str>, ttl: Duration, foreground: bool) -> Result<()> {
+    use std::os::unix::net::UnixStream;
+    use std::process::{Command, Stdio};
+
+    if foreground {
+        let socket = socket.context("The agent needs a socket to listen on")?;
+        return unix::serve(socket, ttl);
+    }
+
+    let socket = match socket {
+        Some(socket) => socket.to_string(),
+        None => unix::default_socket()?,
+    };
+
+    if UnixStream::connect(&socket).is_ok() {
+        return Err(anyhow::anyhow!(
+            "An agent is already listening at {}",
+            socket
+        ));
+    }
+
+    let child = Command::new(std::env::current_exe().context("Unable to find dexios")?)
+        .args(["agent", "--foreground", "--socket", &socket, "--ttl"])
+        .arg(ttl.as_secs().to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("Unable to start the agent")?;
+
+    // the agent is ready once it's listening
+    let started = (0..50).any(|_| {
+        std::thread::sleep(Duration::from_millis(100));
+        UnixStream::connect(&socket).is_ok()
+    });
+    if !started {
+        return Err(anyhow::anyhow!(
+            "The agent didn't start listening at {}",
+            socket
+        ));
+    }
+
+    println!("{}={}; export {};", SOCKET_ENV, socket, SOCKET_ENV);
+    println!("echo Agent pid {};", child.id());
+
+    Ok(())
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::os::unix::fs::DirBuilderExt;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use anyhow::{Context, Result};
+    use core::protected::Protected;
+    use nix::sys::stat::Mode;
+    use nix::unistd::Uid;
+
+    // agents that dexios makes a directory for are named like this, so the directory is removed once they stop
+    const DIR_PREFIX: &str = "dexios-agent-";
+
+    // a request is a command and a hex-encoded password, so anything longer than this can't be valid
+    const MAX_REQUEST_LEN: u64 = 16 * 1024;
+
+    // requests are handled one at a time, so a client that connects and then stalls only holds up the others for this long
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+    // the hex-encoded password, and when it was stored
+    type Cache = Arc<Mutex<Option<(Protected<String>, Instant)>>>;
+
+    // the socket is placed within a directory that only we can access, so other users can't connect to it
+    pub fn default_socket() -> Result<String> {
+        let base =
+            std::env::var_os("XDG_RUNTIME_DIR").map_or_else(std::env::temp_dir, PathBuf::from);
+        let dir = base.join(format!("{}{:08x}", DIR_PREFIX, rand::random::<u32>()));
+
+        std::fs::DirBuilder::new()
+            .mode(0o700)
+            .create(&dir)
+            .with_context(|| format!("Unable to create {}", dir.display()))?;
+
+        dir.join("agent.sock")
+            .to_str()
+            .map(str::to_string)
+            .context("The socket's path isn't valid UTF-8")
+    }
+
+    // this returns the reply, and whether the agent should stop
+    fn handle(line: &str, cache: &Cache, ttl: Duration) -> (Protected<String>, bool) {
+        let mut cache = cache
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        let reply = match line.split_once(' ').unwrap_or((line, "")) {
+            ("GET", _) => match &*cache {
+                Some((password, stored)) if stored.elapsed() < ttl => {
+                    format!("OK {}\n", password.expose())
+                }
+                _ => "NONE\n".to_string(),
+            },
+            ("PUT", password) if !password.is_empty() => {
+                *cache = Some((Protected::new(password.to_string()), Instant::now()));
+                "OK\n".to_string()
+            }
+            ("CLEAR", _) => {
+                *cache = None;
+                "OK\n".to_string()
+            }
+            ("STOP", _) => return (Protected::new("OK\n".to_string()), true),
+            _ => "ERR unknown command\n".to_string(),
+        };
+
+        (Protected::new(reply), false)
+    }
+
+    // the socket is created without any permissions for other users, rather than being restricted once it's been bound
+    // otherwise, anyone could connect to it in between (e.g. if it's within a shared directory)
+    fn bind(socket: &str) -> Result<UnixListener> {
+        let umask = nix::sys::stat::umask(Mode::from_bits_truncate(0o177));
+        let listener = UnixListener::bind(socket);
+        nix::sys::stat::umask(umask);
+
+        listener.with_context(|| format!("Unable to listen at {}", socket))
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn peer_uid(stream: &UnixStream) -> nix::Result<Uid> {
+        use nix::sys::socket::{getsockopt, sockopt::PeerCredentials};
+
+        getsockopt(stream.as_raw_fd(), PeerCredentials).map(|cred| Uid::from_raw(cred.uid()))
+    }
+
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd",
+        target_os = "dragonfly",
+    ))]
+    fn peer_uid(stream: &UnixStream) -> nix::Result<Uid> {
+        nix::unistd::getpeereid(stream.as_raw_fd()).map(|(uid, _)| uid)
+    }
+
+    // nobody can use the agent if we can't tell who's connected
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd",
+        target_os = "dragonfly",
+    )))]
+    fn peer_uid(_stream: &UnixStream) -> nix::Result<Uid> {
+        Err(nix::errno::Errno::ENOTSUP)
+    }
+
+    pub fn serve(socket: &str, ttl: Duration) -> Result<()> {
+        let listener = bind(socket)?;
+
+        let cache: Cache = Arc::new(Mutex::new(None));
+
+        // the password is wiped once it expires, rather than whenever it's next asked for
+        {
+            let cache = cache.clone();
+            std::thread::spawn(move || loop {
+                std::thread::sleep(Duration::from_secs(1));
+                let mut cache = cache
+                    .lock()
+                    .unwrap_or_else(std::sync::PoisonError::into_inner);
+                if matches!(&*cache, Some((_, stored)) if stored.elapsed() >= ttl) {
+                    *cache = None;
+                }
+            });
+        }
+
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            stream.set_read_timeout(Some(REQUEST_TIMEOUT)).ok();
+            stream.set_write_timeout(Some(REQUEST_TIMEOUT)).ok();
+
+            // only the user that the agent runs as may use it, even if the socket's permissions were loosened
+            if peer_uid(&stream).ok() != Some(nix::unistd::geteuid()) {
+                stream.write_all(b"ERR permission denied\n").ok();
+                continue;
+            }
+
+            let mut line = String::new();
+            let read = BufReader::new((&stream).take(MAX_REQUEST_LEN)).read_line(&mut line);
+            let line = Protected::new(line);
+            if read.is_err() {
+                continue;
+            }
+
+            // a request that was cut short at the limit isn't handled, so part of a password is never stored
+            if !line.expose().ends_with('\n') {
+                stream.write_all(b"ERR request too long\n").ok();
+                continue;
+            }
+
+            let (reply, stop) = handle(line.expose().trim_end(), &cache, ttl);
+            stream.write_all(reply.expose().as_bytes()).ok();
+
+            if stop {
+                break;
+            }
+        }
+
+        std::fs::remove_file(socket).ok();
+        if let Some(dir) = Path::new(socket).parent().filter(|dir| {
+            dir.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(DIR_PREFIX))
+        }) {
+            std::fs::remove_dir(dir).ok();
+        }
+
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::global::agent::request;
+        use std::os::unix::fs::PermissionsExt;
+
+        fn send(line: &str, cache: &Cache, ttl: Duration) -> String {
+            handle(line, cache, ttl).0.expose().clone()
+        }
+
+        #[test]
+        fn should_return_the_password_until_it_expires() {
+            let cache: Cache = Arc::new(Mutex::new(None));
+            let ttl = Duration::from_secs(60);
+
+            assert_eq!(send("GET", &cache, ttl), "NONE\n");
+            assert_eq!(send("PUT 68756e74657232", &cache, ttl), "OK\n");
+            assert_eq!(send("GET", &cache, ttl), "OK 68756e74657232\n");
+
+            // this is as if it had been stored a minute ago
+            let stored = Instant::now().checked_sub(ttl).unwrap();
+            cache.lock().unwrap().as_mut().unwrap().1 = stored;
+            assert_eq!(send("GET", &cache, ttl), "NONE\n");
+        }
+
+        #[test]
+        fn should_never_return_the_password_with_no_ttl() {
+            let cache: Cache = Arc::new(Mutex::new(None));
+
+            assert_eq!(send("PUT 68756e74657232", &cache, Duration::ZERO), "OK\n");
+            assert_eq!(send("GET", &cache, Duration::ZERO), "NONE\n");
+        }
+
+        #[test]
+        fn should_clear_and_refuse_unknown_commands() {
+            let cache: Cache = Arc::new(Mutex::new(None));
+            let ttl = Duration::from_secs(60);
+
+            send("PUT 68756e74657232", &cache, ttl);
+            assert_eq!(send("CLEAR", &cache, ttl), "OK\n");
+            assert_eq!(send("GET", &cache, ttl), "NONE\n");
+
+            assert_eq!(send("PUT", &cache, ttl), "ERR unknown command\n");
+            assert_eq!(send("DUMP", &cache, ttl), "ERR unknown command\n");
+            assert!(cache.lock().unwrap().is_none());
+
+            let (reply, stop) = handle("STOP", &cache, ttl);
+            assert_eq!(reply.expose(), "OK\n");
+            assert!(stop);
+        }
+
+        #[test]
+        fn should_expire_the_password_through_the_socket() {
+            let dir =
+                std::env::temp_dir().join(format!("{}test-{}", DIR_PREFIX, std::process::id()));
+            std::fs::create_dir(&dir).unwrap();
+            let socket = dir.join("agent.sock").to_str().unwrap().to_string();
+
+            let agent = {
+                let socket = socket.clone();
+                std::thread::spawn(move || serve(&socket, Duration::from_secs(1)))
+            };
+            while !Path::new(&socket).exists() {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+
+            // the socket was never accessible to other users
+            let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+
+            let too_long = format!("PUT {}", "61".repeat(MAX_REQUEST_LEN as usize));
+            assert_eq!(
+                request(&socket, &too_long).unwrap().expose(),
+                "ERR request too long\n"
+            );
+            assert_eq!(request(&socket, "GET").unwrap().expose(), "NONE\n");
+
+            assert_eq!(
+                request(&socket, "PUT 68756e74657232").unwrap().expose(),
+                "OK\n"
+            );
+            assert_eq!(
+                request(&socket, "GET").unwrap().expose(),
+                "OK 68756e74657232\n"
+            );
+
+            std::thread::sleep(Duration::from_millis(1500));
+            assert_eq!(request(&socket, "GET").unwrap().expose(), "NONE\n");
+
+            // the agent removes its socket and directory once it stops
+            assert_eq!(request(&socket, "STOP").unwrap().expose(), "OK\n");
+            agent.join().unwrap().unwrap();
+            assert!(!dir.exists());
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use core::header::Header;
+use domain::storage::Storage;
+
+use crate::global::policy::{header_kdfs, Policy};
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+// this is everything that's reported for a single dexios file
+struct Record {
+    path: String,
+    version: String,
+    algorithm: String,
+    mode: String,
+    kdfs: Vec<String>,
+    keyslots: usize,
+    violations: Vec<String>,
+}
+
+impl Record {
+    fn new(path: String, header: &Header, policy: Option<&Policy>) -> Result<Self> {
+        let mut kdfs = Vec::new();
+        for kdf in header_kdfs(header).iter().map(ToString::to_string) {
+            if !kdfs.contains(&kdf) {
+                kdfs.push(kdf);
+            }
+        }
+
+        Ok(Self {
+            path,
+            version: header.header_type.version.to_string(),
+            algorithm: header.header_type.algorithm.to_string(),
+            mode: header.header_type.mode.to_string(),
+            kdfs,
+            keyslots: header.keyslots.as_ref().map_or(1, Vec::len),
+            violations: match policy {
+                Some(policy) => policy.header_violations(header)?,
+                None => Vec::new(),
+            },
+        })
+    }
+}
+
+// fields are only quoted if they need to be
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+pub fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if u32::from(c) < 0x20 => escaped.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+pub fn json_array(items: &[String]) -> String {
+    let items: Vec<String> = items.iter().map(|i| json_string(i)).collect();
+    format!("[{}]", items.join(", "))
+}
+
+fn print_csv(records: &[Record]) {
+    println!("path,version,algorithm,mode,kdfs,keyslots,compliant,violations");
+    for record in records {
+        println!(
+            "{},{},{},{},{},{},{},{}",
+            csv_field(&record.path),
+            csv_field(&record.version),
+            csv_field(&record.algorithm),
+            csv_field(&record.mode),
+            csv_field(&record.kdfs.join("; ")),
+            record.keyslots,
+            record.violations.is_empty(),
+            csv_field(&record.violations.join("; ")),
+        );
+    }
+}
+
+fn print_json(records: &[Record]) {
+    println!("[");
+    for (i, record) in records.iter().enumerate() {
+        println!(
+            "  {{\"path\": {}, \"version\": {}, \"algorithm\": {}, \"mode\": {}, \"kdfs\": {}, \"keyslots\": {}, \"compliant\": {}, \"violations\": {}}}{}",
+            json_string(&record.path),
+            json_string(&record.version),
+            json_string(&record.algorithm),
+            json_string(&record.mode),
+            json_array(&record.kdfs),
+            record.keyslots,
+            record.violations.is_empty(),
+            json_array(&record.violations),
+            if i + 1 < records.len() { "," } else { "" }
+        );
+    }
+    println!("]");
+}
+
+// this inventories the header of every dexios file within the directory, and checks each of them against the policy (if one is set)
+// nothing is decrypted or modified, so no key is needed
+// files that don't start with a valid header (including stripped files) are skipped
+fn records(input: &str, policy: Option<&Policy>) -> Result<Vec<Record>> {
+    let stor = Arc::new(domain::storage::FileStorage);
+
+    let input_dir = stor.read_file(input)?;
+    if !input_dir.is_dir() {
+        return Err(anyhow::anyhow!("Input path must be a directory."));
+    }
+
+    let mut entries = stor.read_dir(&input_dir)?;
+    entries.retain(|e| !e.is_dir());
+    entries.sort_by(|a, b| a.path().cmp(b.path()));
+
+    let mut records = Vec::new();
+    for entry in &entries {
+        let Ok((header, _)) = Header::deserialize(&mut *entry.try_reader()?.borrow_mut()) else {
+            continue;
+        };
+
+        records.push(Record::new(
+            entry.path().display().to_string(),
+            &header,
+            policy,
+        )?);
+    }
+
+    Ok(records)
+}
+
+pub fn audit(input: &str, policy: Option<&Policy>, format: Format) -> Result<()> {
+    let records = records(input, policy)?;
+
+    match format {
+        Format::Csv => print_csv(&records),
+        Format::Json => print_json(&records),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::header::{HashingAlgorithm, HeaderType, HeaderVersion, Keyslot};
+    use core::primitives::{
+        get_nonce_len, Algorithm, Compression, Mode, Padding, StreamCounter, BLOCK_SIZE,
+        ENCRYPTED_MASTER_KEY_LEN, SALT_LEN,
+    };
+
+    // only the header is read, so the rest of the file doesn't need to be valid
+    fn dexios_file(algorithm: Algorithm) -> Vec<u8> {
+        let header = Header {
+            header_type: HeaderType {
+                version: HeaderVersion::V5,
+                algorithm,
+                mode: Mode::StreamMode,
+            },
+            nonce: vec![1u8; get_nonce_len(&algorithm, &Mode::StreamMode)],
+            salt: None,
+            keyslots: Some(vec![Keyslot {
+                hash_algorithm: HashingAlgorithm::Blake3Balloon(5),
+                encrypted_key: [2u8; ENCRYPTED_MASTER_KEY_LEN],
+                nonce: vec![3u8; get_nonce_len(&algorithm, &Mode::MemoryMode)],
+                salt: [4u8; SALT_LEN],
+                encapsulated_key: None,
+                token: false,
+                metadata_only: false,
+            }]),
+            fields: Vec::new(),
+            compression: Compression::None,
+            block_size: BLOCK_SIZE,
+            padding: Padding::None,
+            convergent: false,
+            metadata: None,
+            mac: false,
+            digest: None,
+            seekable: false,
+            keyfile_hash: false,
+            counter: StreamCounter::Le31,
+            subkeys: false,
+            two_factor: false,
+            manifest: None,
+        };
+
+        let mut content = header.serialize().unwrap();
+        content.extend_from_slice(&[5u8; 64]);
+        content
+    }
+
+    #[test]
+    fn should_classify_files_without_modifying_them() {
+        let dir = std::env::temp_dir().join(format!("dexios-audit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let files = [
+            ("compliant.dx", dexios_file(Algorithm::XChaCha20Poly1305)),
+            ("non-compliant.dx", dexios_file(Algorithm::Aes256Gcm)),
+            ("notes.txt", b"this isn't a dexios file".to_vec()),
+        ];
+        for (name, content) in &files {
+            std::fs::write(dir.join(name), content).unwrap();
+        }
+        let modified = |name: &str| {
+            std::fs::metadata(dir.join(name))
+                .unwrap()
+                .modified()
+                .unwrap()
+        };
+        let before = files
+            .iter()
+            .map(|(name, _)| modified(name))
+            .collect::<Vec<_>>();
+
+        let policy = Policy::parse("algorithms = XChaCha20-Poly1305").unwrap();
+        let records = records(dir.to_str().unwrap(), Some(&policy)).unwrap();
+
+        // the non-dexios file is skipped, and the rest are sorted by their path
+        assert_eq!(records.len(), 2);
+        assert!(records[0].path.ends_with("compliant.dx"));
+        assert!(records[0].violations.is_empty());
+        assert!(records[1].path.ends_with("non-compliant.dx"));
+        assert_eq!(
+            records[1].violations,
+            ["AES-256-GCM is not permitted by the policy"]
+        );
+        for record in &records {
+            assert_eq!(record.version, "V5");
+            assert_eq!(record.keyslots, 1);
+        }
+
+        // without a policy, every file is reported as compliant
+        let records = super::records(dir.to_str().unwrap(), None).unwrap();
+        assert!(records.iter().all(|r| r.violations.is_empty()));
+
+        for ((name, content), modified_before) in files.iter().zip(before) {
+            assert_eq!(&std::fs::read(dir.join(name)).unwrap(), content);
+            assert_eq!(modified(name), modified_before);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,301 @@
+use std::cell::RefCell;
+use std::io::Seek;
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use core::header::{HashingAlgorithm, Header, HeaderType, HEADER_VERSION};
+use core::manifest::Entry as ManifestEntry;
+use core::primitives::{Algorithm, Mode, StreamCounter, MASTER_KEY_LEN};
+use core::protected::Protected;
+use domain::storage::{SpecialFiles, Storage};
+use domain::template::{Fields, Template};
+
+use crate::cli::prompt::overwrite_check;
+use crate::global::policy::Policy;
+use crate::global::states::{ForceMode, Key, PasswordState, PrintMode};
+use crate::{info, success, warn};
+
+// backups are named after the directory and when they were made, so they sort by age
+const NAME_TEMPLATE: &str = "{name}-{date}-{time}.dx";
+
+pub struct Request<'a> {
+    pub input: &'a str,
+    pub destination: &'a str,
+    pub key: Key,
+    pub hashing_algorithm: HashingAlgorithm,
+    pub algorithm: Algorithm,
+    pub policy: Option<Policy>,
+    // this ignores the previous backup, so every file is stored
+    pub full: bool,
+    pub split: Option<NonZeroU64>,
+    // this names the parts after the first with keyed hashes (see `domain::split::names_key`)
+    pub obfuscate_sidecars: bool,
+    pub upload: Option<&'a str>,
+    pub streams: domain::streams::Options,
+    pub jobs: NonZeroUsize,
+    pub print_mode: PrintMode,
+    pub force: ForceMode,
+}
+
+// this returns the name of the directory that a backup is of, if the file is named like `NAME_TEMPLATE` (or is the first part of one)
+pub fn backup_name(file_name: &str) -> Option<&str> {
+    let rest = file_name
+        .strip_suffix(".001")
+        .unwrap_or(file_name)
+        .strip_suffix(".dx")?;
+
+    // e.g. `-2022-09-30-140500`
+    let split = rest.len().checked_sub(18)?;
+    let (name, stamp) = (rest.get(..split)?, rest.get(split..)?);
+
+    let is_stamp = stamp.char_indices().all(|(i, c)| match i {
+        0 | 5 | 8 | 11 => c == '-',
+        _ => c.is_ascii_digit(),
+    });
+
+    (is_stamp && !name.is_empty()).then_some(name)
+}
+
+// this lists the backups of `name` within any of the directories, oldest first
+// split backups are listed by their first part, as that's where the header is
+pub fn find_backups(dirs: &[&str], name: &str) -> Vec<PathBuf> {
+    let mut backups = dirs
+        .iter()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name().to_str().and_then(backup_name) == Some(name))
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+
+    backups.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    backups
+}
+
+// the manifest lists every file that a backup covers, including the ones that it carried over from the previous backup
+pub fn read_manifest(path: &Path, raw_key: Protected<Vec<u8>>) -> Result<Vec<ManifestEntry>> {
+    let stor = Arc::new(domain::storage::FileStorage);
+    let file = stor.read_file(path)?;
+
+    Ok(domain::manifest::execute(domain::manifest::Request {
+        handle: file.try_reader()?,
+        raw_key,
+    })?)
+}
+
+// this decrypts the whole backup (without writing it anywhere), so every block's tag is checked
+// the master key is returned, as the names of obfuscated parts are derived from it
+fn verify(path: &str, raw_key: Protected<Vec<u8>>) -> Result<Protected<[u8; MASTER_KEY_LEN]>> {
+    let stor = Arc::new(domain::storage::FileStorage);
+    let file = stor.read_file(path)?;
+
+    let (header, _) = Header::deserialize(&mut *file.try_reader()?.borrow_mut())
+        .context("The backup couldn't be verified")?;
+    let master_key = core::key::decrypt_master_key(raw_key, &header)
+        .context("The backup couldn't be verified")?;
+    file.try_reader()?.borrow_mut().rewind()?;
+
+    domain::decrypt::execute(domain::decrypt::Request {
+        header_reader: None,
+        reader: file.try_reader()?,
+        writer: &RefCell::new(std::io::sink()),
+        raw_key: Protected::new(Vec::new()),
+        master_key: Some(master_key.clone()),
+        identity: None,
+        on_decrypted_header: None,
+        on_file_info: None,
+    })
+    .context("The backup couldn't be verified")?;
+
+    Ok(master_key)
+}
+
+// this packs a directory into `destination`, with everything that a backup should have:
+// the files are compressed, a manifest is stored in the header, and the backup is decrypted again once it's written
+// unless `full` is set, only files that have changed since the previous backup (within `destination` or `upload`) are stored
+// the backup may then be split into parts, and moved to `upload` (the copies are verified before the originals are removed)
+//
+// a backup only contains the files that changed, so `dexios restore` combines it with the backups before it
+pub fn execute(req: Request) -> Result<()> {
+    let stor = Arc::new(domain::storage::FileStorage);
+
+    // 1. validate and prepare options
+    let input = req.input.trim_end_matches(['/', '\\']);
+    let input = if input.is_empty() { req.input } else { input };
+    if !stor.read_file(input)?.is_dir() {
+        return Err(anyhow::anyhow!("Input path must be a directory."));
+    }
+
+    let name = Path::new(input)
+        .canonicalize()?
+        .file_name()
+        .and_then(|name| name.to_str())
+        .context("The input directory has no name")?
+        .to_string();
+
+    if let Some(policy) = &req.policy {
+        policy.check_encrypt(&req.algorithm, &req.hashing_algorithm)?;
+    }
+
+    stor.create_dir_all(req.destination)?;
+    if let Some(upload) = req.upload {
+        stor.create_dir_all(upload)?;
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let file_name = NAME_TEMPLATE.parse::<Template>()?.render(
+        &Fields {
+            input: &name,
+            timestamp,
+            hash: "",
+        },
+        |_| false,
+    );
+    let output = Path::new(req.destination)
+        .join(file_name)
+        .to_str()
+        .context("The destination isn't valid UTF-8")?
+        .to_string();
+
+    if !overwrite_check(&output, req.force)? {
+        exit(0);
+    }
+
+    // this is the only time that the key is asked for
+    let raw_key = req.key.get_secret_with(&PasswordState::Validate, true)?;
+
+    // 2. find out which files have changed since the previous backup
+    let mut search = vec![req.destination];
+    search.extend(req.upload);
+    let base = match find_backups(&search, &name).pop() {
+        Some(previous) if !req.full => match read_manifest(&previous, raw_key.clone()) {
+            Ok(manifest) => {
+                info!(
+                    "Only storing files that have changed since {}",
+                    previous.display()
+                );
+                manifest
+            }
+            Err(e) => {
+                warn!(
+                    "Unable to read the manifest of {} ({}), so every file will be stored",
+                    previous.display(),
+                    e
+                );
+                Vec::new()
+            }
+        },
+        _ => Vec::new(),
+    };
+
+    // 3. pack and encrypt the directory
+    let input_dir = stor.read_file(input)?;
+    let (compress_files, skipped) = stor.read_dir_with(&input_dir, SpecialFiles::Skip)?;
+    super::pack::report_skipped(&skipped, &req.print_mode);
+    let output_file = stor.create_temp_file_beside(&output)?;
+
+    let stats = Rc::new(RefCell::new(None));
+    let on_stats = {
+        let stats = stats.clone();
+        Box::new(move |s: &domain::pack::Stats| *stats.borrow_mut() = Some(s.clone()))
+    };
+
+    let result = domain::pack::execute(
+        stor.clone(),
+        domain::pack::Request {
+            compress_files,
+            // the files are stored beneath the directory's name, so they match the previous backup however the path was written
+            roots: vec![domain::pack::Root {
+                path: PathBuf::from(input),
+                prefix: name.clone(),
+            }],
+            compression_method: zip::CompressionMethod::Zstd,
+            writer: output_file.try_writer()?,
+            header_writer: None,
+            raw_key: raw_key.clone(),
+            header_type: HeaderType {
+                version: HEADER_VERSION,
+                mode: Mode::StreamMode,
+                algorithm: req.algorithm,
+            },
+            hashing_algorithm: req.hashing_algorithm,
+            jobs: req.jobs,
+            on_stats: Some(on_stats),
+            on_progress: super::pack::on_progress(&req.print_mode),
+            metadata: Some(super::encrypt::metadata()),
+            streams: req.streams,
+            filters: Vec::new(),
+            keyfile_hash: matches!(req.key, Key::Keyfile(_) | Key::Keyfiles(_)),
+            counter: StreamCounter::Le31,
+            two_factor: false,
+            manifest: true,
+            base,
+        },
+    );
+    if let Err(e) = result {
+        stor.remove_file(output_file).ok();
+        return Err(e.into());
+    }
+
+    stor.flush_file(&output_file)?;
+    stor.persist_file(output_file, &output)?;
+
+    let stats = stats.borrow_mut().take().unwrap_or_default();
+    info!(
+        "Stored {} changed {} ({} unchanged)",
+        stats.files,
+        if stats.files == 1 { "file" } else { "files" },
+        stats.unchanged
+    );
+
+    // 4. make sure that the backup can be decrypted
+    let master_key = verify(&output, raw_key)?;
+    success!("Verified {}", output);
+
+    // 5. split the backup, and move it to its final destination
+    let parts = match req.split {
+        Some(part_size) => domain::split::execute(
+            stor,
+            domain::split::Request {
+                path: &output,
+                part_size,
+                names_key: req
+                    .obfuscate_sidecars
+                    .then(|| domain::split::names_key(&master_key)),
+            },
+        )?
+        .into_iter()
+        .map(|part| part.to_str().map(str::to_string))
+        .collect::<Option<Vec<_>>>()
+        .context("The destination isn't valid UTF-8")?,
+        None => vec![output],
+    };
+
+    let parts = match req.upload {
+        Some(upload) => parts
+            .iter()
+            .map(|part| super::encrypt::move_output(part, upload, req.force))
+            .collect::<Result<Vec<_>>>()?,
+        None => parts,
+    };
+
+    match &parts[..] {
+        [backup] => success!("Backed up {} to {}", input, backup),
+        _ => success!(
+            "Backed up {} to {} parts ({} to {}), which can be joined with `cat`",
+            input,
+            parts.len(),
+            parts[0],
+            parts[parts.len() - 1]
+        ),
+    }
+
+    Ok(())
+}
@@ -1,56 +1,547 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::rc::Rc;
 use std::sync::Arc;
+use std::time::UNIX_EPOCH;
 
 use crate::cli::prompt::overwrite_check;
+use crate::global::pkcs11::Pkcs11Token;
 use crate::global::states::{EraseMode, HashMode, HeaderLocation, PasswordState};
 use crate::global::structs::CryptoParams;
 
-use anyhow::Result;
+use crate::{info, warn};
+use anyhow::{Context, Result};
+use core::file_info::FileInfo;
+use core::header::{Header, HeaderType, FILE_INFO_FIELD};
+use core::key::{mnemonic_to_master_key, normalize_recovery_code};
+use core::primitives::MASTER_KEY_LEN;
+use core::protected::Protected;
+use core::recipient::RecipientSecretKey;
 
+use domain::decrypt::OnFileInfoFn;
+use domain::header::scan::RecoveredReader;
 use domain::storage::Storage;
+use domain::utils::format_timestamp;
 
 // this function is for decrypting a file in stream mode
 // it handles any user-facing interactiveness, opening files, or redirecting to memory mode if
 // the header says so (backwards-compat)
 // it also manages using a detached header file if selected
 // it creates the stream object and uses the convenience function provided by dexios-core
-pub fn stream_mode(input: &str, output: &str, params: &CryptoParams) -> Result<()> {
+pub struct Request<'a> {
+    pub input: &'a str,
+    // the plaintext is written to stdout if there's no output
+    pub output: Option<&'a str>,
+    pub params: &'a CryptoParams,
+    pub identity: Option<&'a str>,
+    pub pkcs11_uri: Option<&'a str>,
+    pub verify_key: Option<&'a str>,
+    pub signature: Option<&'a str>,
+    // if this is set, the key is a recovery code (see `encrypt --recovery-key`)
+    pub recovery_code: bool,
+    // if this is set, the key is the master key's mnemonic phrase (see `key export-mnemonic`)
+    pub mnemonic: bool,
+    // this is the start and length of the plaintext to decrypt, if only part of it is needed
+    pub range: Option<(u64, Option<u64>)>,
+    // these are for recovering headers that another program has moved or damaged
+    pub scan_limit: Option<u64>,
+    pub assume: Option<HeaderType>,
+    // the output is named, timestamped and given permissions from the file info (see `encrypt --store-metadata`)
+    pub restore_metadata: bool,
+}
+
+// this is where the header was found, and what's needed to read it
+struct Recovery {
+    offset: u64,
+    tag: Option<[u8; 6]>,
+    header: Header,
+}
+
+// the header is read from the detached header file if there is one, otherwise it's read from the input
+// if it can't be parsed where it should be, the first `scan_limit` bytes of the file are searched for it
+fn recover_header(
+    path: &str,
+    scan_limit: Option<u64>,
+    assume: Option<&HeaderType>,
+) -> Result<Recovery> {
+    let tag = assume.map(HeaderType::serialize);
+    let open = || File::open(path).with_context(|| format!("Unable to open the header: {}", path));
+
+    let mut reader = RecoveredReader::new(open()?, 0, tag)?;
+    if let Ok((header, _)) = Header::deserialize(&mut reader) {
+        return Ok(Recovery {
+            offset: 0,
+            tag,
+            header,
+        });
+    }
+
+    let limit = scan_limit
+        .context("Unable to read the header, even with the assumed type (try --scan-for-header)")?;
+
+    let found = domain::header::scan::execute(domain::header::scan::Request {
+        reader: &RefCell::new(open()?),
+        limit,
+    })
+    .context("Unable to find a header")?;
+
+    info!(
+        "Found a {} header at byte {} of {}",
+        found.header.header_type.version, found.offset, path
+    );
+
+    // the assumed type still takes priority, as the scan only checks that the header parses
+    let header = match tag {
+        Some(_) => {
+            let mut reader = RecoveredReader::new(open()?, found.offset, tag)?;
+            Header::deserialize(&mut reader)?.0
+        }
+        None => found.header,
+    };
+
+    Ok(Recovery {
+        offset: found.offset,
+        tag,
+        header,
+    })
+}
+
+// this reads the header from the detached header or the input, before anything is decrypted
+fn peek_header(input: &str, header_path: Option<&str>) -> Option<Header> {
+    File::open(header_path.unwrap_or(input))
+        .ok()
+        .and_then(|mut file| Header::deserialize(&mut file).ok())
+        .map(|(header, _)| header)
+}
+
+// this is shown before the password prompt, so the user can check that it's the file they meant to decrypt
+// if the header isn't already known, it's read from the detached header or the input (and nothing is shown if it can't be)
+fn print_summary(input: &str, header_path: Option<&str>, header: Option<&Header>) {
+    let read;
+    let header = match header {
+        Some(header) => header,
+        None => match peek_header(input, header_path) {
+            Some(header) => {
+                read = header;
+                &read
+            }
+            None => return,
+        },
+    };
+
+    info!(
+        "Decrypting {}: {} header, {}, {}",
+        input, header.header_type.version, header.header_type.algorithm, header.header_type.mode
+    );
+    if let Some(metadata) = &header.metadata {
+        info!(
+            "It was encrypted at {} with {}",
+            format_timestamp(metadata.created),
+            metadata.version
+        );
+    }
+}
+
+// the modification time and permissions are restored once the output is in place, as moving it could change them
+// the setuid, setgid and sticky bits are never restored, as they'd be taken from a header that anyone could have written
+fn apply_file_info(path: &str, info: &FileInfo) -> Result<()> {
+    if let Some(modified) = info
+        .modified
+        .and_then(|modified| UNIX_EPOCH.checked_add(modified))
+    {
+        File::options()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_modified(modified))
+            .with_context(|| format!("Unable to restore the modification time of {path}"))?;
+    }
+
+    #[cfg(unix)]
+    if let Some(mode) = info.mode {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o777))
+            .with_context(|| format!("Unable to restore the permissions of {path}"))?;
+    }
+
+    Ok(())
+}
+
+// this is everything that the ciphertext (and its header) may be read from
+struct Sources<'a> {
+    input: &'a str,
+    header_path: Option<&'a str>,
+    reader: &'a RefCell<File>,
+    header_reader: Option<&'a RefCell<File>>,
+    recovery: Option<Recovery>,
+}
+
+// this decrypts everything into `writer`, from wherever the header was found
+fn decrypt_into<W: Write>(
+    sources: Sources,
+    writer: &RefCell<W>,
+    raw_key: Protected<Vec<u8>>,
+    master_key: Option<Protected<[u8; MASTER_KEY_LEN]>>,
+    identity: Option<RecipientSecretKey>,
+    on_file_info: Option<OnFileInfoFn>,
+) -> Result<()> {
+    let Sources {
+        input,
+        header_path,
+        reader,
+        header_reader,
+        recovery,
+    } = sources;
+
+    match recovery {
+        None => domain::decrypt::execute(domain::decrypt::Request {
+            header_reader,
+            reader,
+            writer,
+            raw_key,
+            master_key,
+            identity,
+            on_decrypted_header: None,
+            on_file_info,
+        })
+        .map_err(|e| {
+            match e {
+                domain::decrypt::Error::DeserializeHeader => {
+                    info!("If another program has modified the file, --scan-for-header may be able to find the header.");
+                }
+                domain::decrypt::Error::StrippedHeader => {
+                    info!("The header may be restored with `header restore`, or provided with --header.");
+                }
+                _ => (),
+            }
+            e
+        })?,
+        Some(Recovery { offset, tag, .. }) => {
+            // only the file that contains the header is offset/has its tag replaced
+            let header_reader = header_path
+                .map(|path| RecoveredReader::new(File::open(path)?, offset, tag))
+                .transpose()?
+                .map(RefCell::new);
+            let (input_offset, input_tag) = match header_path {
+                Some(_) => (0, None),
+                None => (offset, tag),
+            };
+            let reader = RefCell::new(RecoveredReader::new(
+                File::open(input).with_context(|| format!("Unable to open: {}", input))?,
+                input_offset,
+                input_tag,
+            )?);
+
+            domain::decrypt::execute(domain::decrypt::Request {
+                header_reader: header_reader.as_ref(),
+                reader: &reader,
+                writer,
+                raw_key,
+                master_key,
+                identity,
+                on_decrypted_header: None,
+                on_file_info,
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn stream_mode(req: Request) -> Result<()> {
+    let Request {
+        input,
+        output,
+        params,
+        identity,
+        pkcs11_uri,
+        verify_key,
+        signature,
+        recovery_code,
+        mnemonic,
+        range,
+        scan_limit,
+        assume,
+        restore_metadata,
+    } = req;
+
     // TODO: It is necessary to raise it to a higher level
     let stor = Arc::new(domain::storage::FileStorage);
 
     // 1. validate and prepare options
-    if input == output {
+    if output == Some(input) {
         return Err(anyhow::anyhow!(
             "Input and output files cannot have the same name."
         ));
     }
 
-    if !overwrite_check(output, params.force)? {
-        exit(0);
+    // with --restore-metadata, the original file is restored into a directory unless an output file is given
+    let restore_into = match output {
+        _ if !restore_metadata => None,
+        Some(output) if Path::new(output).is_dir() => Some(PathBuf::from(output)),
+        Some(_) => None,
+        None => Some(
+            Path::new(input)
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default(),
+        ),
+    };
+
+    if let (Some(output), None) = (output, &restore_into) {
+        if !overwrite_check(output, params.force)? {
+            exit(0);
+        }
     }
 
-    let input_file = stor.read_file(input)?;
-    let header_file = match &params.header_location {
+    let header_path = match &params.header_location {
         HeaderLocation::Embedded => None,
-        HeaderLocation::Detached(path) => Some(stor.read_file(path)?),
+        HeaderLocation::Detached(path) => Some(path.as_str()),
     };
 
-    let raw_key = params.key.get_secret(&PasswordState::Direct)?;
-    let output_file = stor
-        .create_file(output)
-        .or_else(|_| stor.write_file(output))?;
+    // this happens before anything is decrypted, so a forged file is never written out
+    super::sign::verify_before_decrypt(input, header_path, verify_key, signature)?;
 
-    // 2. decrypt file
-    domain::decrypt::execute(domain::decrypt::Request {
-        header_reader: header_file.as_ref().and_then(|h| h.try_reader().ok()),
+    let recovery = if scan_limit.is_some() || assume.is_some() {
+        Some(recover_header(
+            header_path.unwrap_or(input),
+            scan_limit,
+            assume.as_ref(),
+        )?)
+    } else {
+        None
+    };
+
+    // a damaged header is replaced by its copy at the end of the file (if it has one), which the domain does too
+    let backup = match (&recovery, header_path) {
+        (None, None) => domain::header::backup::fallback(
+            &mut File::open(input).with_context(|| format!("Unable to open: {}", input))?,
+        )?,
+        _ => None,
+    };
+    if backup.is_some() {
+        warn!("The header of {} is damaged, so the copy at the end of the file is being used instead.", input);
+        info!("The header may be repaired with `header recover`.");
+    }
+
+    // this is checked before the key is asked for, as the file can't be named without it
+    if restore_metadata {
+        let stored = match (&recovery, &backup) {
+            (Some(recovery), _) => recovery.header.has_field(FILE_INFO_FIELD),
+            (None, Some(backup)) => backup.header.has_field(FILE_INFO_FIELD),
+            (None, None) => peek_header(input, header_path)
+                .is_some_and(|header| header.has_field(FILE_INFO_FIELD)),
+        };
+
+        match (stored, &restore_into) {
+            (false, Some(_)) => {
+                return Err(anyhow::anyhow!(
+                    "{} doesn't contain its original name (it's only stored by `encrypt --store-metadata`), so an output file is needed",
+                    input
+                ))
+            }
+            (false, None) => warn!(
+                "{} doesn't contain its original metadata (it's only stored by `encrypt --store-metadata`), so there's nothing to restore",
+                input
+            ),
+            _ => (),
+        }
+    }
+
+    if let Some(policy) = &params.policy {
+        match (&recovery, &backup) {
+            (Some(recovery), _) => policy.check_header(&recovery.header)?,
+            (None, Some(backup)) => policy.check_header(&backup.header)?,
+            (None, None) => policy.check_file(input, header_path)?,
+        }
+    }
+
+    let input_file = stor.read_file(input)?;
+    let header_file = header_path.map(|path| stor.read_file(path)).transpose()?;
+
+    // token keyslots are tried before any others
+    if let Some(uri) = pkcs11_uri {
+        core::token::register(Box::new(Pkcs11Token::open(uri)?));
+    }
+
+    // the password isn't needed if the recipient's secret key (or a token, or the master key's mnemonic) is used
+    let (raw_key, master_key, identity) = match identity {
+        Some(path) => {
+            let bytes = Protected::new(
+                std::fs::read(path)
+                    .with_context(|| format!("Unable to read the secret key: {path}"))?,
+            );
+            let identity = RecipientSecretKey::from_bytes(bytes.expose())?;
+            (Protected::new(Vec::new()), None, Some(identity))
+        }
+        None if pkcs11_uri.is_some() => (Protected::new(Vec::new()), None, None),
+        None if mnemonic => {
+            let phrase = params.key.get_secret(&PasswordState::Direct)?;
+            let phrase = std::str::from_utf8(phrase.expose())
+                .context("The mnemonic should only contain words from the BIP39 wordlist")?;
+            (
+                Protected::new(Vec::new()),
+                Some(mnemonic_to_master_key(phrase)?),
+                None,
+            )
+        }
+        None if recovery_code => {
+            let code = params.key.get_secret(&PasswordState::Direct)?;
+            let code = std::str::from_utf8(code.expose())
+                .context("The recovery code should only contain letters, digits and dashes")?;
+            (normalize_recovery_code(code)?, None, None)
+        }
+        None => {
+            if params.key.prompts() {
+                let header = match (&recovery, &backup) {
+                    (Some(recovery), _) => Some(&recovery.header),
+                    (None, Some(backup)) => Some(&backup.header),
+                    (None, None) => None,
+                };
+                print_summary(input, header_path, header);
+            }
+
+            let raw_key = match (&recovery, &backup) {
+                (Some(recovery), _) => params
+                    .key
+                    .get_secret_for_header(&PasswordState::Direct, &recovery.header)?,
+                (None, Some(backup)) => params
+                    .key
+                    .get_secret_for_header(&PasswordState::Direct, &backup.header)?,
+                (None, None) => params
+                    .key
+                    .get_secret_for(&PasswordState::Direct, header_path.unwrap_or(input))?,
+            };
+            (raw_key, None, None)
+        }
+    };
+
+    // only part of the plaintext is decrypted, so there's nothing to verify it against afterwards
+    if let Some((start, len)) = range {
+        let header_reader = header_file.as_ref().map(|h| h.try_reader()).transpose()?;
+        let reader = input_file.try_reader()?;
+
+        match output {
+            Some(output) => {
+                let output_file = stor.create_temp_file_beside(output)?;
+                let result = domain::decrypt::execute_range(domain::decrypt::RangeRequest {
+                    header_reader,
+                    reader,
+                    writer: output_file.try_writer()?,
+                    raw_key,
+                    master_key,
+                    identity,
+                    start,
+                    len,
+                });
+                if let Err(e) = result {
+                    stor.remove_file(output_file).ok();
+                    return Err(e.into());
+                }
+
+                stor.flush_file(&output_file)?;
+                stor.persist_file(output_file, output)?;
+            }
+            None => {
+                let stdout = RefCell::new(std::io::stdout().lock());
+                domain::decrypt::execute_range(domain::decrypt::RangeRequest {
+                    header_reader,
+                    reader,
+                    writer: &stdout,
+                    raw_key,
+                    master_key,
+                    identity,
+                    start,
+                    len,
+                })?;
+                stdout
+                    .borrow_mut()
+                    .flush()
+                    .context("Unable to write to stdout")?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    let sources = Sources {
+        input,
+        header_path,
         reader: input_file.try_reader()?,
-        writer: output_file.try_writer()?,
+        header_reader: header_file.as_ref().map(|h| h.try_reader()).transpose()?,
+        recovery,
+    };
+
+    // the output is written to a temporary file, which replaces `output` once it's complete
+    // without an output file (or a directory to restore it into), the plaintext is the only thing that's written to stdout
+    let output_file = match (output, &restore_into) {
+        (_, Some(dir)) => stor.create_temp_file_in(dir)?,
+        (Some(output), None) => stor.create_temp_file_beside(output)?,
+        (None, None) => {
+            let stdout = RefCell::new(std::io::stdout().lock());
+            decrypt_into(sources, &stdout, raw_key, master_key, identity, None)?;
+            stdout
+                .borrow_mut()
+                .flush()
+                .context("Unable to write to stdout")?;
+            return Ok(());
+        }
+    };
+
+    // the file info is decrypted along with the header, before any of the data
+    let info = Rc::new(RefCell::new(None));
+    let on_file_info: Option<OnFileInfoFn> = if restore_metadata {
+        let info = info.clone();
+        Some(Box::new(move |file_info| {
+            *info.borrow_mut() = Some(file_info)
+        }))
+    } else {
+        None
+    };
+
+    // 2. decrypt file
+    let result = decrypt_into(
+        sources,
+        output_file.try_writer()?,
         raw_key,
-        on_decrypted_header: None,
-    })?;
+        master_key,
+        identity,
+        on_file_info,
+    );
+    if let Err(e) = result {
+        stor.remove_file(output_file).ok();
+        return Err(e);
+    }
+
+    let info: Option<FileInfo> = info.take();
+    let output = match (output, &restore_into, &info) {
+        (Some(output), None, _) => output.to_string(),
+        (_, Some(dir), Some(info)) => dir.join(&info.name).to_string_lossy().into_owned(),
+        _ => {
+            stor.remove_file(output_file).ok();
+            return Err(anyhow::anyhow!(
+                "{} doesn't contain its original name, so an output file is needed",
+                input
+            ));
+        }
+    };
 
-    // 3. flush result
+    // the name was only just decrypted, so this couldn't be checked any earlier
+    if restore_into.is_some() && !overwrite_check(&output, params.force)? {
+        stor.remove_file(output_file).ok();
+        exit(0);
+    }
+
+    // 3. flush result, and move it into place
     stor.flush_file(&output_file)?;
+    stor.persist_file(output_file, &output)?;
+
+    if let Some(info) = &info {
+        apply_file_info(&output, info)?;
+        info!("Restored the original metadata of {}", output);
+    }
 
     if params.hash_mode == HashMode::CalculateHash {
         super::hashing::hash_stream(&[input.to_string()])?;
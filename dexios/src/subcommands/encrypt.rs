@@ -1,42 +1,401 @@
 use crate::cli::prompt::overwrite_check;
-use crate::global::states::{EraseMode, HashMode, HeaderLocation, PasswordState};
+use crate::global::pkcs11::Pkcs11Token;
+use crate::global::states::{EraseMode, ForceMode, HashMode, HeaderLocation, Key, PasswordState};
 use crate::global::structs::CryptoParams;
-use anyhow::Result;
-use core::header::{HeaderType, HEADER_VERSION};
-use core::primitives::{Algorithm, Mode};
+use crate::{info, success, warn};
+use anyhow::{Context, Result};
+use core::file_info::FileInfo;
+use core::header::{
+    Field, HashingAlgorithm, HeaderType, HeaderVersion, Metadata, BACKUP_HEADER_FIELD,
+    HEADER_VERSION, MAX_KEYSLOTS, PLACEHOLDER_FIELD,
+};
+use core::key::{generate_recovery_code, normalize_recovery_code};
+use core::primitives::{Algorithm, Compression, Mode, Padding, StreamCounter, BLOCK_SIZE};
+use core::protected::Protected;
+use core::recipient::RecipientPublicKey;
+use core::token::TokenKey;
+use std::cell::RefCell;
+use std::path::Path;
 use std::process::exit;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use domain::storage::Storage;
+use domain::storage::{FileStorage, Storage};
+use domain::template::{Fields, Template};
 
-// this function is for encrypting a file in stream mode
-// it handles any user-facing interactiveness, opening files
-// it creates the stream object and uses the convenience function provided by dexios-core
-pub fn stream_mode(
+pub enum Output {
+    Path(String),
+    // the output is named with this once it's been encrypted (see `domain::template`)
+    Template(Template),
+}
+
+pub struct Request<'a> {
+    pub input: &'a str,
+    pub output: Output,
+    pub params: &'a CryptoParams,
+    pub algorithm: Algorithm,
+    pub mode: Mode,
+    pub compression: Compression,
+    pub block_size: usize,
+    pub padding: Padding,
+    pub convergent: bool,
+    pub recipients: Vec<&'a str>,
+    pub pkcs11_uri: Option<&'a str>,
+    pub extra_keyfiles: Vec<&'a str>,
+    pub recovery_key: bool,
+    pub sign_key: Option<&'a str>,
+    pub mac: bool,
+    pub digest: bool,
+    pub seekable: bool,
+    pub counter: StreamCounter,
+    // this also pads the header, and verifies the output (see `verify_output`)
+    pub paranoid: bool,
+    // a detached header leaves random bytes in its place, rather than nothing
+    pub header_placeholder: bool,
+    // a copy of the header is appended to the file, so it can be recovered if the header is damaged
+    pub backup_header: bool,
+    // the input's name, modification time and permissions are encrypted and stored in the header
+    pub store_metadata: bool,
+    // an older header is written instead (see `--compat`), so older versions of dexios can read the output
+    pub compat: Option<HeaderVersion>,
+}
+
+// `--paranoid` adds up to this many random bytes to the header, so its size doesn't reveal how many recipients there are
+const MAX_HEADER_PADDING: usize = 1024;
+
+// this is stored in the header, so it's possible to tell when (and with which version) a file was encrypted
+pub fn metadata() -> Metadata {
+    Metadata::new(concat!("dexios ", env!("CARGO_PKG_VERSION")))
+}
+
+// this is what `decrypt --restore-metadata` restores, and it's encrypted (unlike the metadata above)
+// permissions are only stored on unix, as they don't map onto other platforms
+fn file_info(input: &str) -> Result<FileInfo> {
+    let metadata = std::fs::metadata(input)
+        .with_context(|| format!("Unable to read the metadata of {input}"))?;
+
+    let name = Path::new(input)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .with_context(|| format!("Unable to store the name of {input}, as it isn't valid UTF-8"))?
+        .to_string();
+
+    #[cfg(unix)]
+    let mode = {
+        use std::os::unix::fs::PermissionsExt;
+        Some(metadata.permissions().mode() & 0o7777)
+    };
+    #[cfg(not(unix))]
+    let mode = None;
+
+    Ok(FileInfo {
+        name,
+        modified: metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok()),
+        mode,
+    })
+}
+
+// this moves a finished output to `move_to` (which may be on another device), and returns where it ended up
+// if `move_to` is a directory, the output keeps its name within it
+// the copy is verified before the original is removed, see `domain::relocate`
+pub fn move_output(output: &str, move_to: &str, force: ForceMode) -> Result<String> {
+    let destination = match Path::new(move_to).is_dir() {
+        true => Path::new(move_to)
+            .join(
+                Path::new(output)
+                    .file_name()
+                    .context("The output has no file name")?,
+            )
+            .to_str()
+            .context("The destination isn't valid UTF-8")?
+            .to_string(),
+        false => move_to.to_string(),
+    };
+
+    if !overwrite_check(&destination, force)? {
+        exit(0);
+    }
+
+    let hash = domain::relocate::execute(
+        Arc::new(domain::storage::FileStorage),
+        domain::relocate::Request {
+            source: output,
+            destination: &destination,
+        },
+    )?;
+    success!(
+        "Moved {} to {} (verified, BLAKE3 {})",
+        output,
+        destination,
+        hash
+    );
+
+    Ok(destination)
+}
+
+// `--paranoid` decrypts the output again, and checks that it matches the input before anything else happens to either of them
+// the key is hashed again, so this is slow, but the input is never erased if the output can't be decrypted
+fn verify_output(
+    stor: &Arc<FileStorage>,
     input: &str,
     output: &str,
-    params: &CryptoParams,
-    algorithm: Algorithm,
+    header: Option<&str>,
+    raw_key: Protected<Vec<u8>>,
 ) -> Result<()> {
+    let mut input_hasher = blake3::Hasher::new();
+    std::io::copy(
+        &mut *stor.read_file(input)?.try_reader()?.borrow_mut(),
+        &mut input_hasher,
+    )
+    .with_context(|| format!("Unable to read {input}"))?;
+
+    let output_file = stor.read_file(output)?;
+    let header_file = header.map(|path| stor.read_file(path)).transpose()?;
+    let output_hasher = RefCell::new(blake3::Hasher::new());
+
+    domain::decrypt::execute(domain::decrypt::Request {
+        header_reader: header_file.as_ref().and_then(|f| f.try_reader().ok()),
+        reader: output_file.try_reader()?,
+        writer: &output_hasher,
+        raw_key,
+        master_key: None,
+        identity: None,
+        on_decrypted_header: None,
+        on_file_info: None,
+    })
+    .context("Unable to decrypt the output, so it couldn't be verified")?;
+
+    if output_hasher.into_inner().finalize() != input_hasher.finalize() {
+        return Err(anyhow::anyhow!(
+            "{} doesn't decrypt to {}, so it may be corrupted",
+            output,
+            input
+        ));
+    }
+
+    success!("Verified that {} decrypts to {}", output, input);
+    Ok(())
+}
+
+// this function is for encrypting a file in stream mode (or derived stream mode)
+// it handles any user-facing interactiveness, opening files
+// it creates the stream object and uses the convenience function provided by dexios-core
+pub fn stream_mode(req: Request) -> Result<()> {
+    let Request {
+        input,
+        output,
+        params,
+        algorithm,
+        mode,
+        compression,
+        block_size,
+        padding,
+        convergent,
+        recipients,
+        pkcs11_uri,
+        extra_keyfiles,
+        recovery_key,
+        sign_key,
+        mac,
+        digest,
+        seekable,
+        counter,
+        paranoid,
+        header_placeholder,
+        backup_header,
+        store_metadata,
+        compat,
+    } = req;
+
     // TODO: It is necessary to raise it to a higher level
     let stor = Arc::new(domain::storage::FileStorage);
 
     // 1. validate and prepare options
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let exists = |path: &str| Path::new(path).exists();
+    let templated = matches!(output, Output::Template(_));
+
+    // if the template needs the hash, this is only a placeholder (it's renamed once the hash is known)
+    let (mut output, template) = match output {
+        Output::Path(path) => (path, None),
+        Output::Template(template) => {
+            let fields = Fields {
+                input,
+                timestamp,
+                hash: "",
+            };
+            let output = template.render(&fields, exists);
+
+            if let Some(parent) = Path::new(&output).parent() {
+                stor.create_dir_all(parent)?;
+            }
+
+            (output, template.needs_hash().then_some(template))
+        }
+    };
+
     if input == output {
         return Err(anyhow::anyhow!(
             "Input and output files cannot have the same name."
         ));
     }
 
-    if !overwrite_check(output, params.force)? {
+    if template.is_none() && !overwrite_check(&output, params.force)? {
         exit(0);
     }
 
+    if convergent {
+        warn!("Convergent encryption is enabled, so encrypting the same file with the same key will always produce the same output.");
+        warn!("Anyone who can see the encrypted files will know which ones are identical.");
+        warn!("Anyone with the key can confirm whether this file contains a guessed plaintext, without decrypting it.");
+    }
+
+    // older headers can't store most of the newer options, so they're refused rather than quietly dropped
+    // V5 is the layout that older versions of dexios read, so anything that they wouldn't understand is refused too
+    let version = compat.unwrap_or(HEADER_VERSION);
+    if version < HeaderVersion::V6 {
+        let legacy = version < HeaderVersion::V5;
+        let unsupported = [
+            (compression != Compression::None, "--compress"),
+            (block_size != BLOCK_SIZE, "--block-size"),
+            (padding != Padding::None, "--pad"),
+            (convergent, "--convergent"),
+            (mode == Mode::DerivedStreamMode, "derived-stream mode"),
+            (!recipients.is_empty(), "--recipient"),
+            (pkcs11_uri.is_some(), "--pkcs11-uri"),
+            (legacy && !extra_keyfiles.is_empty(), "--extra-keyfile"),
+            (legacy && recovery_key, "--recovery-key"),
+            (mac, "--mac"),
+            (digest, "--digest"),
+            (seekable, "--seekable"),
+            (counter == StreamCounter::Be64, "--stream-counter be64"),
+            (matches!(params.key, Key::TwoFactor(..)), "--two-factor"),
+            (
+                matches!(params.key, Key::Keyfiles(_)),
+                "more than one --keyfile",
+            ),
+            (matches!(params.key, Key::Yubikey(_)), "--yubikey"),
+        ];
+        if let Some((_, option)) = unsupported.iter().find(|(used, _)| *used) {
+            return Err(anyhow::anyhow!(
+                "{} isn't supported by {} headers",
+                option,
+                version
+            ));
+        }
+
+        if !matches!(
+            algorithm,
+            Algorithm::XChaCha20Poly1305 | Algorithm::Aes256Gcm | Algorithm::DeoxysII256
+        ) {
+            return Err(anyhow::anyhow!(
+                "{} isn't supported by {} headers (use XChaCha20-Poly1305, AES-256-GCM or Deoxys-II-256)",
+                algorithm,
+                version
+            ));
+        }
+
+        // older versions of dexios only know the KDFs' fixed parameter versions
+        if !legacy
+            && !matches!(
+                params.hashing_algorithm,
+                HashingAlgorithm::Argon2id(1..=3) | HashingAlgorithm::Blake3Balloon(4 | 5)
+            )
+        {
+            return Err(anyhow::anyhow!(
+                "{} isn't supported by {} headers (use the default BLAKE3-Balloon or Argon2id parameters)",
+                params.hashing_algorithm,
+                version
+            ));
+        }
+    }
+
+    // V2-V4 headers don't store the KDF's parameters, so they're fixed by the version
+    let hashing_algorithm = match version {
+        HeaderVersion::V2 => HashingAlgorithm::Argon2id(2),
+        HeaderVersion::V3 => HashingAlgorithm::Argon2id(3),
+        HeaderVersion::V4 => HashingAlgorithm::Blake3Balloon(4),
+        _ => params.hashing_algorithm,
+    };
+
+    if let Some(version) = compat {
+        warn!("Writing a {} header, so that older versions of dexios can read {}. Only use --compat if they must.", version, output);
+        warn!("{} headers don't support any of the newer protections (such as MACs, digests or separate subkeys).", version);
+        if version < HeaderVersion::V5 {
+            warn!(
+                "{} headers only hold one key, so no others can be added later.",
+                version
+            );
+        }
+        if version < HeaderVersion::V4 {
+            warn!("The key is hashed straight into the data's key, so it can't be changed without encrypting the file again.");
+        }
+        if version == HeaderVersion::V2 {
+            warn!("V2 headers aren't authenticated, so changes to the header won't be detected when the file is decrypted.");
+        }
+        if hashing_algorithm != params.hashing_algorithm {
+            info!(
+                "{} headers always use {}, so it's used in place of {}.",
+                version, hashing_algorithm, params.hashing_algorithm
+            );
+        }
+    }
+
+    let recipients = recipients
+        .into_iter()
+        .map(|path| {
+            let bytes = std::fs::read(path)
+                .with_context(|| format!("Unable to read the recipient's public key: {path}"))?;
+            RecipientPublicKey::from_bytes(&bytes)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // the token is challenged up-front, so its wrapping key is ready for the keyslot
+    let tokens = pkcs11_uri
+        .map(|uri| Pkcs11Token::open(uri).and_then(|mut token| TokenKey::generate(&mut token)))
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?;
+
+    // each of these gets its own keyslot, alongside the main key
+    let extra_keyfiles_used = !extra_keyfiles.is_empty();
+    let mut extra_keys = extra_keyfiles
+        .into_iter()
+        .map(|path| Key::Keyfile(path.to_string()).get_secret_with(&PasswordState::Direct, true))
+        .collect::<Result<Vec<_>>>()?;
+
+    // the code is only shown once the file has been encrypted, as it's useless otherwise
+    let recovery_code = recovery_key.then(generate_recovery_code);
+    if let Some(code) = &recovery_code {
+        extra_keys.push(normalize_recovery_code(code.expose())?);
+    }
+
+    if 1 + recipients.len() + tokens.len() + extra_keys.len() > MAX_KEYSLOTS {
+        return Err(anyhow::anyhow!(
+            "There can't be more than {} keyslots, including recipients, tokens, extra keyfiles and the recovery code",
+            MAX_KEYSLOTS
+        ));
+    }
+
+    // this is read early, so that a bad key doesn't waste an encryption
+    let signing_key = sign_key.map(super::sign::read_signing_key).transpose()?;
+    let info = store_metadata.then(|| file_info(input)).transpose()?;
+
+    if let Some(policy) = &params.policy {
+        policy.check_encrypt(&algorithm, &hashing_algorithm)?;
+    }
+
     let input_file = stor.read_file(input)?;
-    let raw_key = params.key.get_secret(&PasswordState::Validate)?;
-    let output_file = stor
-        .create_file(output)
-        .or_else(|_| stor.write_file(output))?;
+    let raw_key = params
+        .key
+        .get_secret_with(&PasswordState::Validate, version >= HeaderVersion::V6)?;
+    let verify_key = paranoid.then(|| raw_key.clone());
+    // the output is written to a temporary file, which replaces `output` once it's complete
+    let output_file = stor.create_temp_file_beside(&output)?;
 
     let header_file = match &params.header_location {
         HeaderLocation::Embedded => None,
@@ -49,6 +408,23 @@ pub fn stream_mode(
         }
     };
 
+    let mut fields = Vec::new();
+    if paranoid {
+        fields.push(Field::random_padding(MAX_HEADER_PADDING));
+    }
+    if header_placeholder && header_file.is_some() {
+        fields.push(Field {
+            tag: PLACEHOLDER_FIELD,
+            value: Vec::new(),
+        });
+    }
+    if backup_header && header_file.is_none() {
+        fields.push(Field {
+            tag: BACKUP_HEADER_FIELD,
+            value: Vec::new(),
+        });
+    }
+
     // 2. encrypt file
     let req = domain::encrypt::Request {
         reader: input_file.try_reader()?,
@@ -56,22 +432,126 @@ pub fn stream_mode(
         header_writer: header_file.as_ref().and_then(|f| f.try_writer().ok()),
         raw_key,
         header_type: HeaderType {
-            version: HEADER_VERSION,
-            mode: Mode::StreamMode,
+            version,
+            mode,
             algorithm,
         },
-        hashing_algorithm: params.hashing_algorithm,
+        hashing_algorithm,
+        compression,
+        block_size,
+        padding,
+        convergent,
+        recipients,
+        tokens,
+        extra_keys,
+        // the timestamp would make convergent output unique, and older headers can't store it
+        metadata: if convergent || version < HeaderVersion::V6 {
+            None
+        } else {
+            Some(metadata())
+        },
+        mac,
+        digest,
+        seekable,
+        // keyfiles are always hashed for new files (passwords are unaffected), unless the header is too old to say so
+        keyfile_hash: version >= HeaderVersion::V6
+            && (matches!(
+                params.key,
+                Key::Keyfile(_) | Key::Keyfiles(_) | Key::TwoFactor(..)
+            ) || extra_keyfiles_used),
+        counter,
+        two_factor: matches!(params.key, Key::TwoFactor(..)),
+        manifest: None,
+        file_info: info,
+        fields,
     };
-    domain::encrypt::execute(req)?;
+    if let Err(e) = domain::encrypt::execute(req) {
+        stor.remove_file(output_file).ok();
+        return Err(e.into());
+    }
 
-    // 3. flush result
+    // 3. flush result, and move it into place
     if let Some(header_file) = header_file {
         stor.flush_file(&header_file)?;
     }
     stor.flush_file(&output_file)?;
 
+    if let Some(template) = template {
+        let hash = domain::hash::execute(
+            domain::hasher::Blake3Hasher::default(),
+            domain::hash::Request {
+                reader: RefCell::new(&mut *output_file.try_reader()?.borrow_mut()),
+            },
+        )?;
+        let fields = Fields {
+            input,
+            timestamp,
+            hash: &hash,
+        };
+        output = template.render(&fields, exists);
+
+        if !overwrite_check(&output, params.force)? {
+            stor.remove_file(output_file).ok();
+            exit(0);
+        }
+
+        if let Some(parent) = Path::new(&output).parent() {
+            stor.create_dir_all(parent)?;
+        }
+    }
+
+    stor.persist_file(output_file, &output)?;
+
+    if let Some(raw_key) = verify_key {
+        let header_path = match &params.header_location {
+            HeaderLocation::Embedded => None,
+            HeaderLocation::Detached(path) => Some(path.as_str()),
+        };
+        verify_output(&stor, input, &output, header_path, raw_key)?;
+    }
+
+    if templated {
+        success!("Encrypted to {}", output);
+    }
+
+    if let Some(code) = recovery_code {
+        warn!("Your recovery code is: {}", code.expose());
+        warn!("It can decrypt the file in place of your key (with `decrypt --recovery`), and it won't be shown again.");
+    }
+
+    if let Some(signing_key) = &signing_key {
+        let header_path = match &params.header_location {
+            HeaderLocation::Embedded => None,
+            HeaderLocation::Detached(path) => Some(path.as_str()),
+        };
+
+        super::sign::sign(
+            &output,
+            header_path,
+            signing_key,
+            &super::sign::default_signature_path(&output),
+            params.force,
+        )?;
+    }
+
+    if let Some(move_to) = &params.move_to {
+        let signed = signing_key
+            .is_some()
+            .then(|| super::sign::default_signature_path(&output));
+        output = move_output(&output, move_to, params.force)?;
+
+        // the signature goes with it, so that it's still found by default
+        if let Some(signature) = signed {
+            move_output(
+                &signature,
+                &super::sign::default_signature_path(&output),
+                params.force,
+            )?;
+        }
+    }
+
     if params.hash_mode == HashMode::CalculateHash {
-        super::hashing::hash_stream(&[output.to_string()])?;
+        super::hashing::hash_stream(&[output.clone()])?;
     }
 
     if let EraseMode::EraseFile(passes) = params.erase {
@@ -80,3 +560,185 @@ pub fn stream_mode(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cipher::Ciphers;
+    use core::primitives::get_nonce_len;
+    use core::stream::DecryptionStreams;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    const KEYFILE_CONTENTS: &[u8] = b"a keyfile for older versions";
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("dexios-compat-{}-{}", name, std::process::id()))
+    }
+
+    fn encrypt(args: &[&str], input: &Path, output: &Path) -> Result<()> {
+        // each test has its own keyfile, as they run in parallel
+        let keyfile = output.with_extension("key");
+        std::fs::write(&keyfile, KEYFILE_CONTENTS).unwrap();
+
+        let mut argv = vec!["dexios", "encrypt", "--compat", "v5"];
+        argv.extend_from_slice(args);
+        argv.extend_from_slice(&[
+            "-k",
+            keyfile.to_str().unwrap(),
+            input.to_str().unwrap(),
+            output.to_str().unwrap(),
+        ]);
+
+        let matches = crate::cli::build().get_matches_from(argv);
+        let result = crate::subcommands::encrypt(matches.subcommand_matches("encrypt").unwrap());
+        std::fs::remove_file(&keyfile).unwrap();
+        result
+    }
+
+    // this only reads what V5 headers held before V6 existed (so it's how older versions of dexios see the file)
+    // the payload is encrypted with the master key, and only the first 32 bytes are authenticated
+    fn decrypt_as_v5(content: &[u8], raw_key: &[u8]) -> Result<Vec<u8>> {
+        let (header, data) = content.split_at(416);
+        anyhow::ensure!(header[..2] == [0xDE, 0x05], "Not a V5 header");
+        anyhow::ensure!(header[4..6] == [0x0C, 0x01], "Not encrypted in stream mode");
+
+        let algorithm = match header[2..4] {
+            [0x0E, 0x01] => Algorithm::XChaCha20Poly1305,
+            [0x0E, 0x02] => Algorithm::Aes256Gcm,
+            [0x0E, 0x03] => Algorithm::DeoxysII256,
+            _ => return Err(anyhow::anyhow!("Unknown algorithm")),
+        };
+
+        let nonce_len = get_nonce_len(&algorithm, &Mode::StreamMode);
+        let nonce = &header[6..6 + nonce_len];
+        anyhow::ensure!(
+            header[6 + nonce_len..32].iter().all(|b| *b == 0),
+            "The header's reserved bytes aren't empty"
+        );
+
+        let keyslot_nonce_len = get_nonce_len(&algorithm, &Mode::MemoryMode);
+        let mut keyslots = Vec::new();
+        for keyslot in header[32..].chunks_exact(96) {
+            if keyslot[0] != 0xDF {
+                continue;
+            }
+
+            let hash_algorithm = match keyslot[..2] {
+                [0xDF, 0xA1] => HashingAlgorithm::Argon2id(1),
+                [0xDF, 0xA2] => HashingAlgorithm::Argon2id(2),
+                [0xDF, 0xA3] => HashingAlgorithm::Argon2id(3),
+                [0xDF, 0xB4] => HashingAlgorithm::Blake3Balloon(4),
+                [0xDF, 0xB5] => HashingAlgorithm::Blake3Balloon(5),
+                _ => return Err(anyhow::anyhow!("Key hashing algorithm not identified")),
+            };
+            anyhow::ensure!(
+                keyslot[2 + 48 + keyslot_nonce_len..74]
+                    .iter()
+                    .chain(&keyslot[90..])
+                    .all(|b| *b == 0),
+                "The keyslot's padding isn't empty"
+            );
+
+            keyslots.push((
+                hash_algorithm,
+                &keyslot[2..50],
+                &keyslot[50..][..keyslot_nonce_len],
+                &keyslot[74..90],
+            ));
+        }
+
+        let master_key = keyslots
+            .into_iter()
+            .find_map(|(hash_algorithm, encrypted_key, keyslot_nonce, salt)| {
+                let key = hash_algorithm
+                    .hash(Protected::new(raw_key.to_vec()), salt.try_into().ok()?)
+                    .ok()?;
+                let cipher = Ciphers::initialize(key, &algorithm).ok()?;
+                cipher.decrypt(keyslot_nonce, encrypted_key).ok()
+            })
+            .context("Unable to decrypt the master key")?;
+
+        let streams = DecryptionStreams::initialize(
+            Protected::new(master_key.try_into().unwrap()),
+            nonce,
+            &algorithm,
+        )?;
+
+        let mut output = Vec::new();
+        streams.decrypt_file(
+            &mut Cursor::new(data),
+            &mut output,
+            &header[..32],
+            BLOCK_SIZE,
+        )?;
+        Ok(output)
+    }
+
+    #[test]
+    fn should_write_v5_files_that_older_versions_can_decrypt() {
+        let input = temp_path("input");
+        let output = temp_path("output");
+        std::fs::write(&input, b"Hello world").unwrap();
+        std::fs::remove_file(&output).ok();
+
+        encrypt(&[], &input, &output).unwrap();
+        let content = std::fs::read(&output).unwrap();
+
+        assert_eq!(content.len(), 416 + 11 + 16);
+        assert_eq!(
+            decrypt_as_v5(&content, KEYFILE_CONTENTS).unwrap(),
+            b"Hello world".to_vec()
+        );
+
+        std::fs::remove_file(&input).unwrap();
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn should_refuse_options_that_v5_headers_cannot_hold() {
+        let input = temp_path("refused-input");
+        let output = temp_path("refused-output");
+        std::fs::write(&input, b"Hello world").unwrap();
+
+        for args in [
+            &["--algorithm", "ascon-128a"][..],
+            &["--algorithm", "aegis-256"],
+            &["--misuse-resistant"],
+            &["--block-size", "64K"],
+            &["--kdf", "scrypt"],
+            &["--kdf-memory", "64"],
+            &["--mac"],
+            &["--digest"],
+        ] {
+            std::fs::remove_file(&output).ok();
+
+            let err = encrypt(args, &input, &output).unwrap_err();
+            assert!(err.to_string().contains("V5 headers"), "{args:?}: {err}");
+            assert!(!output.exists());
+        }
+
+        std::fs::remove_file(&input).unwrap();
+    }
+
+    #[test]
+    fn should_refuse_every_algorithm_with_paranoid() {
+        let parse = |args: &[&str]| {
+            let mut argv = vec!["dexios", "encrypt", "--paranoid"];
+            argv.extend_from_slice(args);
+            argv.extend_from_slice(&["in", "out"]);
+            crate::cli::build().try_get_matches_from(argv)
+        };
+
+        assert!(parse(&[]).is_ok());
+        for args in [
+            &["--algorithm", "xchacha20-poly1305"][..],
+            &["--aes"],
+            &["--aegis"],
+            &["--chacha20"],
+            &["--ascon"],
+        ] {
+            assert!(parse(args).is_err(), "{args:?}");
+        }
+    }
+}
@@ -19,7 +19,16 @@ pub fn hash_stream(files: &[String]) -> Result<()> {
             },
         )?;
 
-        success!("{}: {}", input, hash);
+        // in quiet mode, a lone digest is printed on its own so that it can be captured as-is
+        if crate::global::quiet() {
+            if files.len() == 1 {
+                println!("{hash}");
+            } else {
+                println!("{hash}  {input}");
+            }
+        } else {
+            success!("{}: {}", input, hash);
+        }
     }
 
     Ok(())
@@ -1,19 +1,200 @@
 use std::{
     cell::RefCell,
     fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
 };
 
+use super::audit::json_string;
 use crate::cli::prompt::overwrite_check;
-use crate::global::states::ForceMode;
+use crate::global::states::{ForceMode, Key, PasswordState};
+use crate::{info, success, warn};
 use anyhow::{Context, Result};
 use core::header::HashingAlgorithm;
-use core::header::{Header, HeaderVersion};
+use core::header::{
+    Header, HeaderVersion, Keyslot, BACKUP_HEADER_FIELD, FILE_INFO_FIELD, PADDING_FIELD,
+    PLACEHOLDER_FIELD,
+};
+use core::primitives::Mode;
+use core::recipient::X25519_ENCAPSULATED_KEY_LEN;
 use domain::storage::Storage;
-use domain::utils::hex_encode;
+use domain::utils::{format_timestamp, hex_encode};
+
+// only the first volume of a split file starts with the header (see `domain::split`)
+// so a file that was split is handled through its first volume, and any other volume is refused (rather than having its data overwritten)
+fn first_volume(path: &str) -> Result<String> {
+    if let Some((base, number)) = domain::split::parse_part_path(Path::new(path)) {
+        let first = domain::split::part_path(&base, 1);
+        if number > 1 && first.is_file() {
+            return Err(anyhow::anyhow!(
+                "{} is volume {:03} of a split file, and only the first volume ({}) contains the header",
+                path,
+                number,
+                first.display()
+            ));
+        }
+    } else if !Path::new(path).exists() {
+        let first = domain::split::part_path(Path::new(path), 1);
+        if first.is_file() {
+            info!(
+                "{} was split, so its first volume ({}) is used",
+                path,
+                first.display()
+            );
+            return Ok(first.to_string_lossy().into_owned());
+        }
+    }
+
+    Ok(path.to_string())
+}
+
+// each keyslot's kind, as it's shown in the JSON output
+fn keyslot_kind(keyslot: &Keyslot) -> &'static str {
+    if keyslot.is_recipient() {
+        "recipient"
+    } else if keyslot.is_token() {
+        "token"
+    } else if keyslot.is_metadata_only() {
+        "metadata-only"
+    } else {
+        "key"
+    }
+}
+
+fn json_hex(bytes: &[u8]) -> String {
+    json_string(&hex_encode(bytes))
+}
+
+fn json_or_null<T>(value: Option<T>, f: impl FnOnce(T) -> String) -> String {
+    value.map_or_else(|| "null".to_string(), f)
+}
 
-pub fn details(input: &str) -> Result<()> {
+// this is for scripting, so everything that's printed in the text output is included (and absent values are null, rather than omitted)
+fn print_details_json(header: &Header, aad: &[u8]) {
+    let streaming = header.header_type.mode != Mode::MemoryMode;
+
+    // V1-V3 headers have a single salt, which is shown as a keyslot (without an encrypted master key)
+    let keyslots = match header.header_type.version {
+        HeaderVersion::V1 | HeaderVersion::V2 | HeaderVersion::V3 => {
+            let salt = header.salt.unwrap_or_default();
+            let algorithm = HashingAlgorithm::Argon2id(match header.header_type.version {
+                HeaderVersion::V1 => 1,
+                HeaderVersion::V2 => 2,
+                _ => 3,
+            });
+            vec![format!(
+                "{{\"kind\": \"key\", \"hashing_algorithm\": {}, \"salt\": {}, \"salt_len\": {}, \"encrypted_key\": null, \"nonce\": null, \"nonce_len\": null}}",
+                json_string(&algorithm.to_string()),
+                json_hex(&salt),
+                salt.len()
+            )]
+        }
+        HeaderVersion::V4 | HeaderVersion::V5 | HeaderVersion::V6 => header
+            .keyslots
+            .iter()
+            .flatten()
+            .map(|keyslot| {
+                let hash_algorithm = (!keyslot.is_recipient() && !keyslot.is_token())
+                    .then(|| keyslot.hash_algorithm.to_string());
+                format!(
+                    "{{\"kind\": {}, \"hashing_algorithm\": {}, \"salt\": {}, \"salt_len\": {}, \"encrypted_key\": {}, \"nonce\": {}, \"nonce_len\": {}}}",
+                    json_string(keyslot_kind(keyslot)),
+                    json_or_null(hash_algorithm, |a| json_string(&a)),
+                    json_hex(&keyslot.salt),
+                    keyslot.salt.len(),
+                    json_hex(&keyslot.encrypted_key),
+                    json_hex(&keyslot.nonce),
+                    keyslot.nonce.len()
+                )
+            })
+            .collect(),
+    };
+
+    let fields = header
+        .fields
+        .iter()
+        .map(|field| {
+            format!(
+                "{{\"tag\": {}, \"len\": {}, \"padding\": {}}}",
+                field.tag,
+                field.value.len(),
+                field.tag == PADDING_FIELD
+            )
+        })
+        .collect::<Vec<_>>();
+
+    println!("{{");
+    println!(
+        "  \"version\": {},",
+        json_string(&header.header_type.version.to_string())
+    );
+    println!(
+        "  \"algorithm\": {},",
+        json_string(&header.header_type.algorithm.to_string())
+    );
+    println!(
+        "  \"mode\": {},",
+        json_string(&header.header_type.mode.to_string())
+    );
+    println!("  \"header_size\": {},", header.get_size());
+    println!(
+        "  \"compression\": {},",
+        json_string(&header.compression.to_string())
+    );
+    println!(
+        "  \"padding\": {},",
+        json_string(&header.padding.to_string())
+    );
+    println!("  \"convergent\": {},", header.convergent);
+    println!("  \"mac\": {},", header.mac);
+    println!("  \"digest\": {},", header.digest.is_some());
+    println!("  \"seekable\": {},", header.seekable);
+    println!("  \"keyfile_hash\": {},", header.keyfile_hash);
+    println!("  \"two_factor\": {},", header.two_factor);
+    println!("  \"subkeys\": {},", header.subkeys);
+    println!("  \"manifest\": {},", header.manifest.is_some());
+    println!(
+        "  \"block_size\": {},",
+        json_or_null(streaming.then_some(header.block_size), |b| b.to_string())
+    );
+    println!(
+        "  \"counter\": {},",
+        json_or_null(streaming.then_some(header.counter), |c| json_string(
+            &c.to_string()
+        ))
+    );
+    println!(
+        "  \"created\": {},",
+        json_or_null(header.metadata.as_ref(), |m| m.created.to_string())
+    );
+    println!(
+        "  \"created_with\": {},",
+        json_or_null(header.metadata.as_ref(), |m| json_string(&m.version))
+    );
+    println!("  \"nonce\": {},", json_hex(&header.nonce));
+    println!("  \"nonce_len\": {},", header.nonce.len());
+    println!("  \"aad\": {},", json_hex(aad));
+    println!("  \"fields\": [{}],", fields.join(", "));
+    if keyslots.is_empty() {
+        println!("  \"keyslots\": []");
+    } else {
+        println!("  \"keyslots\": [");
+        for (i, keyslot) in keyslots.iter().enumerate() {
+            println!(
+                "    {}{}",
+                keyslot,
+                if i + 1 < keyslots.len() { "," } else { "" }
+            );
+        }
+        println!("  ]");
+    }
+    println!("}}");
+}
+
+pub fn details(input: &str, json: bool) -> Result<()> {
+    let input = first_volume(input)?;
     let mut input_file =
-        File::open(input).with_context(|| format!("Unable to open input file: {}", input))?;
+        File::open(&input).with_context(|| format!("Unable to open input file: {}", input))?;
 
     let header_result = Header::deserialize(&mut input_file);
 
@@ -25,48 +206,170 @@ pub fn details(input: &str) -> Result<()> {
 
     let (header, aad) = header_result.unwrap();
 
+    if json {
+        print_details_json(&header, &aad);
+        return Ok(());
+    }
+
     println!("Header version: {}", header.header_type.version);
+    println!("Header size: {} bytes", header.get_size());
     println!("Encryption algorithm: {}", header.header_type.algorithm);
     println!("Encryption mode: {}", header.header_type.mode);
-    println!("Encryption nonce: {} (hex)", hex_encode(&header.nonce));
+    println!("Compression: {}", header.compression);
+    println!("Padding: {}", header.padding);
+    if header.convergent {
+        println!("Convergent: yes (identical files encrypted with the same key produce identical output)");
+    }
+    if header.mac {
+        println!("MAC footer: yes (the whole file is authenticated before it's decrypted)");
+    }
+    if header.digest.is_some() {
+        println!("Plaintext digest: yes (the output is verified once it's decrypted)");
+    }
+    if header.seekable {
+        println!("Seekable: yes (any part of the file may be decrypted on its own)");
+    }
+    if header.keyfile_hash {
+        println!("Hashed keyfiles: yes (keyfiles are hashed with BLAKE3 before key derivation)");
+    }
+    if header.two_factor {
+        println!("Two-factor: yes (both a keyfile and a password are needed)");
+    }
+    if header.subkeys {
+        println!("Subkeys: yes (the payload, header and MAC keys are derived separately)");
+    }
+    if header.manifest.is_some() {
+        println!("Manifest: yes (the packed files may be listed with any key, including metadata-only keys)");
+    }
+    if header.header_type.mode != Mode::MemoryMode {
+        println!("Block size: {} KiB", header.block_size / 1024);
+        println!("Stream counter: {}", header.counter);
+    }
+    if let Some(metadata) = &header.metadata {
+        println!("Created: {}", format_timestamp(metadata.created));
+        println!("Created with: {}", metadata.version);
+    }
+    println!(
+        "Encryption nonce: {} (hex, {} bytes)",
+        hex_encode(&header.nonce),
+        header.nonce.len()
+    );
     println!("AAD: {} (hex)", hex_encode(&aad));
 
     match header.header_type.version {
         HeaderVersion::V1 => {
-            println!("Salt: {} (hex)", hex_encode(&header.salt.unwrap()));
+            let salt = header.salt.unwrap();
+            println!("Salt: {} (hex, {} bytes)", hex_encode(&salt), salt.len());
             println!("Hashing Algorithm: {}", HashingAlgorithm::Argon2id(1));
         }
         HeaderVersion::V2 => {
-            println!("Salt: {} (hex)", hex_encode(&header.salt.unwrap()));
+            let salt = header.salt.unwrap();
+            println!("Salt: {} (hex, {} bytes)", hex_encode(&salt), salt.len());
             println!("Hashing Algorithm: {}", HashingAlgorithm::Argon2id(2));
         }
         HeaderVersion::V3 => {
-            println!("Salt: {} (hex)", hex_encode(&header.salt.unwrap()));
+            let salt = header.salt.unwrap();
+            println!("Salt: {} (hex, {} bytes)", hex_encode(&salt), salt.len());
             println!("Hashing Algorithm: {}", HashingAlgorithm::Argon2id(3));
         }
-        HeaderVersion::V4 | HeaderVersion::V5 => {
-            for (i, keyslot) in header.keyslots.unwrap().iter().enumerate() {
+        HeaderVersion::V4 | HeaderVersion::V5 | HeaderVersion::V6 => {
+            for (i, keyslot) in header.keyslots.iter().flatten().enumerate() {
                 println!("Keyslot {}:", i);
-                println!("  Hashing Algorithm: {}", keyslot.hash_algorithm);
-                println!("  Salt: {} (hex)", hex_encode(&keyslot.salt));
+                if let Some(encapsulated_key) = &keyslot.encapsulated_key {
+                    if encapsulated_key.len() == X25519_ENCAPSULATED_KEY_LEN {
+                        println!("  Recipient: X25519");
+                    } else {
+                        println!("  Recipient: X25519 + ML-KEM-768");
+                    }
+                    println!("  Fingerprint: {} (hex)", hex_encode(&keyslot.salt));
+                } else if keyslot.is_token() {
+                    println!("  Hardware Token");
+                    println!("  Challenge: {} (hex)", hex_encode(&keyslot.salt));
+                } else if keyslot.is_metadata_only() {
+                    println!("  Metadata-only (unlocks the header key, not the master key)");
+                    println!("  Hashing Algorithm: {}", keyslot.hash_algorithm);
+                    println!(
+                        "  Salt: {} (hex, {} bytes)",
+                        hex_encode(&keyslot.salt),
+                        keyslot.salt.len()
+                    );
+                } else {
+                    println!("  Hashing Algorithm: {}", keyslot.hash_algorithm);
+                    println!(
+                        "  Salt: {} (hex, {} bytes)",
+                        hex_encode(&keyslot.salt),
+                        keyslot.salt.len()
+                    );
+                }
                 println!(
                     "  Master Key: {} (hex, encrypted)",
                     hex_encode(&keyslot.encrypted_key)
                 );
-                println!("  Master Key Nonce: {} (hex)", hex_encode(&keyslot.nonce));
+                println!(
+                    "  Master Key Nonce: {} (hex, {} bytes)",
+                    hex_encode(&keyslot.nonce),
+                    keyslot.nonce.len()
+                );
             }
         }
     }
 
+    for field in &header.fields {
+        if field.tag == PADDING_FIELD {
+            println!("Header padding: {} random bytes", field.value.len());
+        } else if field.tag == PLACEHOLDER_FIELD {
+            println!(
+                "Detached: yes (the data starts with {} random bytes in place of this header)",
+                header.get_size()
+            );
+        } else if field.tag == BACKUP_HEADER_FIELD {
+            println!("Backup header: yes (a copy is stored at the end of the file)");
+        } else if field.tag == FILE_INFO_FIELD {
+            println!(
+                "File info: yes (the original name, modification time and permissions, encrypted)"
+            );
+        } else {
+            println!(
+                "Unrecognised field: {:#06x} ({} bytes)",
+                field.tag,
+                field.value.len()
+            );
+        }
+    }
+
     Ok(())
 }
 
 // this function reads the header fromthe input file and writes it to the output file
 // it's used for extracting an encrypted file's header for backups and such
 // it implements a check to ensure the header is valid
-pub fn dump(input: &str, output: &str, force: ForceMode) -> Result<()> {
+// it can also be written as text or a QR code, which are easier to keep away from the computer
+pub fn dump(input: &str, output: &str, armor: bool, qr: bool, force: ForceMode) -> Result<()> {
+    use domain::header::dump::Format;
+
     let stor = std::sync::Arc::new(domain::storage::FileStorage);
-    let input_file = stor.read_file(input)?;
+    let input_file = stor.read_file(first_volume(input)?)?;
+
+    let format = match (armor, qr) {
+        (true, _) => Format::Armor,
+        // a PNG is no use in a terminal, so the QR code is drawn there instead
+        (_, true) if output == "-" => Format::QrText,
+        (_, true) => Format::QrPng,
+        _ => Format::Raw,
+    };
+
+    if output == "-" {
+        let stdout = RefCell::new(std::io::stdout().lock());
+        domain::header::dump::execute(domain::header::dump::Request {
+            reader: input_file.try_reader()?,
+            writer: &stdout,
+            format,
+        })?;
+        return stdout
+            .borrow_mut()
+            .flush()
+            .context("Unable to write to stdout");
+    }
 
     if !overwrite_check(output, force)? {
         std::process::exit(0);
@@ -79,6 +382,7 @@ pub fn dump(input: &str, output: &str, force: ForceMode) -> Result<()> {
     let req = domain::header::dump::Request {
         reader: input_file.try_reader()?,
         writer: output_file.try_writer()?,
+        format,
     };
 
     domain::header::dump::execute(req)?;
@@ -93,8 +397,10 @@ pub fn dump(input: &str, output: &str, force: ForceMode) -> Result<()> {
 // this can be used for restoring a dumped header to a file that had it's header stripped
 // this does not work for files encrypted *with* a detached header
 // it implements a check to ensure the header is valid before restoring to a file
-pub fn restore(input: &str, output: &str) -> Result<()> {
+// the input may be a photo of a QR code made by `dump`, but only if `qr_image` is set
+pub fn restore(input: &str, output: &str, qr_image: bool) -> Result<()> {
     let stor = std::sync::Arc::new(domain::storage::FileStorage);
+    let output = &first_volume(output)?;
 
     let input_file = stor.read_file(input)?;
 
@@ -109,6 +415,7 @@ pub fn restore(input: &str, output: &str) -> Result<()> {
     let req = domain::header::restore::Request {
         reader: input_file.try_reader()?,
         writer: &output_file,
+        qr_image,
     };
 
     domain::header::restore::execute(req)?;
@@ -121,6 +428,7 @@ pub fn restore(input: &str, output: &str) -> Result<()> {
 // it can be useful for storing the header separate from the file, to make an attacker's life that little bit harder
 // it implements a check to ensure the header is valid before stripping
 pub fn strip(input: &str) -> Result<()> {
+    let input = &first_volume(input)?;
     let input_file = RefCell::new(
         OpenOptions::new()
             .read(true)
@@ -135,5 +443,73 @@ pub fn strip(input: &str) -> Result<()> {
 
     domain::header::strip::execute(req)?;
 
+    // the copy at the end of the file is left alone, as it's only there for recovery
+    if domain::header::backup::read(&mut *input_file.borrow_mut())?.is_some() {
+        warn!("{} still ends with a copy of its header (see `encrypt --backup-header`), which isn't stripped", input);
+    }
+
+    Ok(())
+}
+
+// this checks that the header is valid, that the key unlocks it and that it matches the encrypted data
+// everything is decrypted to check it, but nothing is written (so it's safe to run on the only copy of a file)
+pub fn verify(input: &str, header_path: Option<&str>, key: &Key) -> Result<()> {
+    let open = |path: &str| {
+        File::open(path)
+            .map(RefCell::new)
+            .with_context(|| format!("Unable to open: {}", path))
+    };
+
+    let input_file = open(input)?;
+    let header_file = header_path.map(open).transpose()?;
+
+    let (header, _) =
+        Header::deserialize(&mut *header_file.as_ref().unwrap_or(&input_file).borrow_mut())
+            .with_context(|| {
+                format!(
+                    "{} failed verification: {} doesn't contain a valid header",
+                    input,
+                    header_path.unwrap_or(input)
+                )
+            })?;
+
+    if key == &Key::User {
+        info!("Please enter your key below");
+    }
+    let raw_key = key.get_secret_for_header(&PasswordState::Direct, &header)?;
+
+    domain::header::verify::execute(domain::header::verify::Request {
+        header_reader: header_file.as_ref(),
+        reader: &input_file,
+        raw_key,
+    })
+    .with_context(|| format!("{} failed verification", input))?;
+
+    success!(
+        "{} passed verification: the header is valid, the key unlocks it, and it matches the encrypted data",
+        input
+    );
+
+    Ok(())
+}
+
+// this writes the copy of the header at the end of the file over the header at the start
+pub fn recover(input: &str) -> Result<()> {
+    let input_file = RefCell::new(
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(input)
+            .with_context(|| format!("Unable to open input file: {}", input))?,
+    );
+
+    let req = domain::header::recover::Request {
+        handle: &input_file,
+    };
+
+    domain::header::recover::execute(req)?;
+
+    success!("Recovered the header of {} from its copy", input);
+
     Ok(())
 }
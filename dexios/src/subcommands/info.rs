@@ -0,0 +1,346 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use core::cipher::Ciphers;
+use core::header::{HeaderVersion, HASHING_ALGORITHMS, HEADER_VERSION};
+use core::os_crypto::{self, CryptoBackend};
+use core::primitives::{get_nonce_len, Algorithm, Mode, ALGORITHMS};
+use core::protected::Protected;
+
+use super::audit::{json_array, json_string};
+use crate::{info, success};
+
+// this is how much data each algorithm encrypts during the micro-benchmark
+const BENCH_SIZE: usize = 1024 * 1024;
+const BENCH_ROUNDS: u32 = 8;
+
+// the crypto backend is chosen at startup, so its benchmark needs to be much quicker
+// 64 KiB is the smallest block size, and every block of that size can be sent to the kernel
+const BACKEND_BENCH_SIZE: usize = 64 * 1024;
+const BACKEND_BENCH_ROUNDS: u32 = 4;
+
+const HEADER_VERSIONS: [HeaderVersion; 6] = [
+    HeaderVersion::V1,
+    HeaderVersion::V2,
+    HeaderVersion::V3,
+    HeaderVersion::V4,
+    HeaderVersion::V5,
+    HeaderVersion::V6,
+];
+
+// these are the CPU features that the AEAD implementations are able to take advantage of
+struct CpuFeatures {
+    aes: bool,
+    clmul: bool,
+    avx2: bool,
+    neon: bool,
+}
+
+impl CpuFeatures {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn detect() -> Self {
+        Self {
+            aes: std::arch::is_x86_feature_detected!("aes"),
+            clmul: std::arch::is_x86_feature_detected!("pclmulqdq"),
+            avx2: std::arch::is_x86_feature_detected!("avx2"),
+            neon: false,
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn detect() -> Self {
+        Self {
+            aes: std::arch::is_aarch64_feature_detected!("aes"),
+            clmul: std::arch::is_aarch64_feature_detected!("pmull"),
+            avx2: false,
+            neon: std::arch::is_aarch64_feature_detected!("neon"),
+        }
+    }
+
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+    fn detect() -> Self {
+        Self {
+            aes: false,
+            clmul: false,
+            avx2: false,
+            neon: false,
+        }
+    }
+
+    // this returns what (if anything) accelerates the algorithm on this machine
+    fn acceleration(&self, algorithm: &Algorithm) -> Option<&'static str> {
+        match algorithm {
+            Algorithm::Aes256Gcm if self.aes && self.clmul => Some("AES + carry-less multiply"),
+            Algorithm::Aes256Gcm | Algorithm::DeoxysII256 | Algorithm::Aegis256 if self.aes => {
+                Some("AES")
+            }
+            Algorithm::XChaCha20Poly1305 | Algorithm::ChaCha20Poly1305 if self.avx2 => Some("AVX2"),
+            Algorithm::XChaCha20Poly1305 | Algorithm::ChaCha20Poly1305 if self.neon => Some("NEON"),
+            _ => None,
+        }
+    }
+}
+
+// these are the CPU features that this binary was compiled to require (rather than detecting them at runtime)
+fn target_features() -> Vec<String> {
+    [
+        ("aes", cfg!(target_feature = "aes")),
+        ("pclmulqdq", cfg!(target_feature = "pclmulqdq")),
+        ("sse2", cfg!(target_feature = "sse2")),
+        ("avx2", cfg!(target_feature = "avx2")),
+        ("neon", cfg!(target_feature = "neon")),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(feature, _)| feature.to_string())
+    .collect()
+}
+
+fn to_strings<T: ToString>(items: &[T]) -> Vec<String> {
+    items.iter().map(ToString::to_string).collect()
+}
+
+// this is for listing things in plain text, where an empty list wouldn't be noticed
+fn list_or_none(items: &[String]) -> String {
+    if items.is_empty() {
+        "none".to_string()
+    } else {
+        items.join(", ")
+    }
+}
+
+fn yes_no(b: bool) -> &'static str {
+    if b {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+// this encrypts `size` bytes `rounds` times, and returns the average throughput in MiB/s
+fn bench(algorithm: &Algorithm, size: usize, rounds: u32) -> Result<f64> {
+    let cipher = Ciphers::initialize(Protected::new([0u8; 32]), algorithm)?;
+    let nonce = vec![0u8; get_nonce_len(algorithm, &Mode::MemoryMode)];
+    let plaintext = vec![0u8; size];
+
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..rounds {
+        let start = Instant::now();
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| anyhow::anyhow!("Unable to encrypt with {}", algorithm))?;
+        elapsed += start.elapsed();
+        drop(ciphertext);
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    let mib = (size as f64 * f64::from(rounds)) / (1024.0 * 1024.0);
+    Ok(mib / elapsed.as_secs_f64().max(f64::EPSILON))
+}
+
+// this selects where AES-256-GCM is computed, for the rest of the process
+// without a preference, the kernel is only used if the CPU can't accelerate AES itself, and it's faster than software
+pub fn select_backend(preference: Option<CryptoBackend>) -> Result<()> {
+    if let Some(backend) = preference {
+        return os_crypto::set_backend(backend);
+    }
+
+    let cpu = CpuFeatures::detect();
+    if cpu.acceleration(&Algorithm::Aes256Gcm).is_some() || !os_crypto::is_available() {
+        return Ok(());
+    }
+
+    let mut throughput = Vec::new();
+    for backend in [CryptoBackend::Software, CryptoBackend::Kernel] {
+        os_crypto::set_backend(backend)?;
+        throughput.push((
+            backend,
+            bench(
+                &Algorithm::Aes256Gcm,
+                BACKEND_BENCH_SIZE,
+                BACKEND_BENCH_ROUNDS,
+            )?,
+        ));
+    }
+
+    let fastest = throughput
+        .iter()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map_or(CryptoBackend::Software, |(backend, _)| *backend);
+    os_crypto::set_backend(fastest)
+}
+
+// this identifies the build, so that tooling can check that the hosts producing and consuming files are compatible
+// unlike `report()`, nothing is benchmarked, so it's quick enough to run before every transfer
+pub fn version(json: bool) {
+    let versions = to_strings(&HEADER_VERSIONS);
+    let algorithms = to_strings(&ALGORITHMS);
+    let kdfs = to_strings(&HASHING_ALGORITHMS);
+    let features = to_strings(core::CORE_FEATURES);
+
+    let mut backends = vec![CryptoBackend::Software.to_string()];
+    if os_crypto::is_available() {
+        backends.push(CryptoBackend::Kernel.to_string());
+    }
+    let key_backends = to_strings(&["password", "keyfile", "recipient", "pkcs11", "yubikey"]);
+
+    let cpu = CpuFeatures::detect();
+    let detected = [
+        ("aes", cpu.aes),
+        ("clmul", cpu.clmul),
+        ("avx2", cpu.avx2),
+        ("neon", cpu.neon),
+    ]
+    .into_iter()
+    .filter(|(_, detected)| *detected)
+    .map(|(feature, _)| feature.to_string())
+    .collect::<Vec<_>>();
+
+    if !json {
+        println!(
+            "dexios {} (dexios-core {})",
+            env!("CARGO_PKG_VERSION"),
+            core::CORE_VERSION
+        );
+        println!(
+            "Header versions: {} ({} is used for new files)",
+            versions.join(", "),
+            HEADER_VERSION
+        );
+        println!("Algorithms: {}", algorithms.join(", "));
+        println!("KDFs: {}", kdfs.join(", "));
+        println!("Compiled features: {}", list_or_none(&features));
+        println!(
+            "AES-256-GCM backends: {} ({} is in use)",
+            backends.join(", "),
+            os_crypto::backend()
+        );
+        println!("Key backends: {}", key_backends.join(", "));
+        println!(
+            "Target: {}/{} (compiled for: {}, detected: {})",
+            std::env::consts::OS,
+            std::env::consts::ARCH,
+            list_or_none(&target_features()),
+            list_or_none(&detected)
+        );
+        return;
+    }
+
+    println!("{{");
+    println!("  \"version\": {},", json_string(env!("CARGO_PKG_VERSION")));
+    println!("  \"core_version\": {},", json_string(core::CORE_VERSION));
+    println!("  \"format\": {{");
+    println!("    \"header_versions\": {},", json_array(&versions));
+    println!(
+        "    \"current_header_version\": {}",
+        json_string(&HEADER_VERSION.to_string())
+    );
+    println!("  }},");
+    println!("  \"features\": {},", json_array(&features));
+    println!("  \"algorithms\": {},", json_array(&algorithms));
+    println!("  \"kdfs\": {},", json_array(&kdfs));
+    println!("  \"backends\": {{");
+    println!("    \"aes_256_gcm\": {},", json_array(&backends));
+    println!(
+        "    \"aes_256_gcm_selected\": {},",
+        json_string(&os_crypto::backend().to_string())
+    );
+    println!("    \"keys\": {}", json_array(&key_backends));
+    println!("  }},");
+    println!("  \"target\": {{");
+    println!("    \"os\": {},", json_string(std::env::consts::OS));
+    println!("    \"arch\": {},", json_string(std::env::consts::ARCH));
+    println!(
+        "    \"compiled_cpu_features\": {},",
+        json_array(&target_features())
+    );
+    println!("    \"detected_cpu_features\": {}", json_array(&detected));
+    println!("  }}");
+    println!("}}");
+}
+
+// this reports what this build of dexios supports, and which algorithm suits this machine best
+pub fn report() -> Result<()> {
+    let cpu = CpuFeatures::detect();
+
+    info!(
+        "Dexios v{} (dexios-core v{}) on {}/{}",
+        env!("CARGO_PKG_VERSION"),
+        core::CORE_VERSION,
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+    info!(
+        "CPU features: AES: {}, CLMUL/PMULL: {}, AVX2: {}, NEON: {}",
+        yes_no(cpu.aes),
+        yes_no(cpu.clmul),
+        yes_no(cpu.avx2),
+        yes_no(cpu.neon)
+    );
+
+    let versions = HEADER_VERSIONS
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    info!(
+        "Header versions: {} ({} is used for new files)",
+        versions.join(", "),
+        HEADER_VERSION
+    );
+
+    let features = if core::CORE_FEATURES.is_empty() {
+        "none".to_string()
+    } else {
+        core::CORE_FEATURES.join(", ")
+    };
+    info!("Compiled features: {}", features);
+
+    info!(
+        "AES-256-GCM backend: {} (kernel crypto API: {})",
+        os_crypto::backend(),
+        if os_crypto::is_available() {
+            "available"
+        } else {
+            "unavailable"
+        }
+    );
+
+    let mut results = Vec::new();
+    for algorithm in &ALGORITHMS {
+        let throughput = bench(algorithm, BENCH_SIZE, BENCH_ROUNDS)?;
+        let acceleration = match cpu.acceleration(algorithm) {
+            _ if *algorithm == Algorithm::Aes256Gcm
+                && os_crypto::backend() == CryptoBackend::Kernel =>
+            {
+                "offloaded to the kernel".to_string()
+            }
+            Some(a) => format!("accelerated ({})", a),
+            None => "software".to_string(),
+        };
+
+        info!("{}: {:.0} MiB/s, {}", algorithm, throughput, acceleration);
+        results.push((*algorithm, throughput));
+    }
+
+    // only audited algorithms are recommended, and AES-256-GCM is only considered if it's hardware accelerated (or offloaded)
+    // XChaCha20-Poly1305 is the default, as it's fast and constant-time everywhere
+    let throughput = |algorithm| {
+        results
+            .iter()
+            .find(|(a, _)| *a == algorithm)
+            .map_or(0.0, |(_, t)| *t)
+    };
+    let aes_accelerated = cpu.acceleration(&Algorithm::Aes256Gcm).is_some()
+        || os_crypto::backend() == CryptoBackend::Kernel;
+    let recommended = if aes_accelerated
+        && throughput(Algorithm::Aes256Gcm) > throughput(Algorithm::XChaCha20Poly1305)
+    {
+        Algorithm::Aes256Gcm
+    } else {
+        Algorithm::XChaCha20Poly1305
+    };
+
+    success!("Recommended algorithm: {}", recommended);
+
+    Ok(())
+}
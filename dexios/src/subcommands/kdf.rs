@@ -0,0 +1,117 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use core::header::{HashingAlgorithm, ARGON2ID_LATEST, HASHING_ALGORITHMS};
+use core::kdf::{Argon2id, Kdf};
+use core::primitives::gen_salt;
+use core::protected::Protected;
+
+use crate::global::config::Config;
+use crate::global::parameters::custom_hashing_algorithm;
+use crate::{info, success};
+
+// this derives a key with every supported KDF (and parameter version), and reports how long each took
+// it gives users an idea of what each choice costs on their own hardware
+pub fn bench() -> Result<()> {
+    for hashing_algorithm in &HASHING_ALGORITHMS {
+        let kdf = hashing_algorithm.kdf()?;
+        let salt = gen_salt();
+        let raw_key = Protected::new(b"dexios kdf benchmark".to_vec());
+
+        let start = Instant::now();
+        let key = kdf.derive(raw_key, &salt)?;
+        let elapsed = start.elapsed();
+        drop(key);
+
+        info!("{}: {:.2?}", kdf, elapsed);
+    }
+
+    Ok(())
+}
+
+// this derives a single key, and returns how long it took
+fn measure(hashing_algorithm: &HashingAlgorithm) -> Result<Duration> {
+    let kdf = hashing_algorithm.kdf()?;
+    let salt = gen_salt();
+    let raw_key = Protected::new(b"dexios kdf calibration".to_vec());
+
+    let start = Instant::now();
+    let key = kdf.derive(raw_key, &salt)?;
+    let elapsed = start.elapsed();
+    drop(key);
+
+    Ok(elapsed)
+}
+
+// this finds KDF parameters that take roughly `target` to derive a key on this machine, similar to `cryptsetup benchmark`
+// the memory is doubled until a key takes at least half of the target, and it's then scaled linearly to hit it
+// if the memory limit (in MiB) is reached first, the iterations are scaled instead (scrypt doesn't have any)
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn calibrate(kdf: Kdf, target: Duration, max_memory: u32, write: bool) -> Result<()> {
+    // argon2id starts from RFC 9106's second recommendation (3 iterations), balloon hashing is designed for a single round
+    let (mut iterations, parallelism) = match kdf {
+        Kdf::Argon2id => (
+            Some(3),
+            Argon2id::from_version(ARGON2ID_LATEST)?.params.p_cost,
+        ),
+        Kdf::Blake3Balloon => (Some(1), 1),
+        Kdf::Scrypt => (None, 1),
+    };
+    let mut memory = 1;
+
+    let hashing_algorithm = |memory, iterations| {
+        custom_hashing_algorithm(kdf, Some(memory), iterations, Some(parallelism))
+    };
+
+    info!(
+        "Calibrating for {:.2?} per key (this may take a while)",
+        target
+    );
+
+    let mut elapsed = measure(&hashing_algorithm(memory, iterations)?)?;
+    while elapsed < target / 2 && memory < max_memory {
+        memory = memory.saturating_mul(2).min(max_memory);
+        elapsed = measure(&hashing_algorithm(memory, iterations)?)?;
+    }
+
+    let scale = target.as_secs_f64() / elapsed.as_secs_f64().max(f64::EPSILON);
+    if memory < max_memory || scale < 1.0 {
+        memory = ((f64::from(memory) * scale) as u32).clamp(1, max_memory);
+        // scrypt's memory is always a power of two, so this shows what's actually used
+        if kdf == Kdf::Scrypt {
+            memory = 1 << (31 - memory.leading_zeros());
+        }
+    } else {
+        iterations = iterations.map(|i| ((f64::from(i) * scale).round() as u32).clamp(1, 255));
+    }
+
+    let chosen = hashing_algorithm(memory, iterations)?;
+    let elapsed = measure(&chosen)?;
+    info!("{}: {:.2?}", chosen, elapsed);
+    info!(
+        "Use these parameters with: --kdf {} --kdf-memory {}{} --kdf-parallelism {}",
+        kdf,
+        memory,
+        iterations.map_or_else(String::new, |i| format!(" --kdf-iterations {i}")),
+        parallelism
+    );
+
+    if write {
+        let path = Config::path().context("Unable to find the config directory")?;
+        // anything else within the config file is kept
+        Config {
+            kdf: Some(kdf),
+            kdf_memory: Some(memory),
+            kdf_iterations: iterations,
+            kdf_parallelism: Some(parallelism),
+            ..Config::load()?
+        }
+        .save(&path)?;
+        success!(
+            "Saved to {} - these will be used whenever no KDF parameters are provided",
+            path.display()
+        );
+    }
+
+    Ok(())
+}
@@ -7,11 +7,26 @@ use core::header::Header;
 use core::header::HeaderVersion;
 use std::cell::RefCell;
 use std::fs::OpenOptions;
-use std::io::Seek;
-
-use crate::info;
+use std::io::{Seek, Write};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::cli::prompt::overwrite_check;
+use crate::global::states::ForceMode;
+use crate::{info, success, warn};
+use core::protected::Protected;
+use core::recipient::RecipientSecretKey;
+use core::signature::SigningSecretKey;
+use domain::storage::Storage;
+use domain::utils::hex_encode;
+use rand::RngCore;
+
+// this lets the domain notice if another process writes to the file during the update
+fn modified(input: &str) -> Option<SystemTime> {
+    std::fs::metadata(input).and_then(|m| m.modified()).ok()
+}
 
-pub fn add(input: &str, params: &KeyManipulationParams) -> Result<()> {
+pub fn add(input: &str, params: &KeyManipulationParams, metadata_only: bool) -> Result<()> {
     let input_file = RefCell::new(
         OpenOptions::new()
             .read(true)
@@ -37,19 +52,29 @@ pub fn add(input: &str, params: &KeyManipulationParams) -> Result<()> {
         info!("Please enter your old key below");
     }
 
-    let raw_key_old = params.key_old.get_secret(&PasswordState::Direct)?;
+    let raw_key_old = params
+        .key_old
+        .get_secret_for_header(&PasswordState::Direct, &header)?;
+
+    if let Some(policy) = &params.policy {
+        policy.check_keyslot(&params.hashing_algorithm)?;
+    }
 
     if params.key_new == Key::User {
         info!("Please enter your new key below");
     }
 
-    let raw_key_new = params.key_new.get_secret(&PasswordState::Validate)?;
+    let raw_key_new = params
+        .key_new
+        .get_secret_for_header(&PasswordState::Validate, &header)?;
 
     domain::key::add::execute(domain::key::add::Request {
         handle: &input_file,
+        modified: Some(&|| modified(input)),
         hash_algorithm: params.hashing_algorithm,
         raw_key_old,
         raw_key_new,
+        metadata_only,
     })?;
 
     Ok(())
@@ -81,16 +106,25 @@ pub fn change(input: &str, params: &KeyManipulationParams) -> Result<()> {
         info!("Please enter your old key below");
     }
 
-    let raw_key_old = params.key_old.get_secret(&PasswordState::Direct)?;
+    let raw_key_old = params
+        .key_old
+        .get_secret_for_header(&PasswordState::Direct, &header)?;
+
+    if let Some(policy) = &params.policy {
+        policy.check_keyslot(&params.hashing_algorithm)?;
+    }
 
     if params.key_new == Key::User {
         info!("Please enter your new key below");
     }
 
-    let raw_key_new = params.key_new.get_secret(&PasswordState::Validate)?;
+    let raw_key_new = params
+        .key_new
+        .get_secret_for_header(&PasswordState::Validate, &header)?;
 
     domain::key::change::execute(domain::key::change::Request {
         handle: &input_file,
+        modified: Some(&|| modified(input)),
         hash_algorithm: params.hashing_algorithm,
         raw_key_old,
         raw_key_new,
@@ -99,7 +133,78 @@ pub fn change(input: &str, params: &KeyManipulationParams) -> Result<()> {
     Ok(())
 }
 
-pub fn delete(input: &str, key_old: &Key) -> Result<()> {
+// this re-encrypts the file with a new master key, and only replaces it once the new one is complete
+pub fn rotate(input: &str, params: &KeyManipulationParams) -> Result<()> {
+    let stor = Arc::new(domain::storage::FileStorage);
+    let input_file = stor.read_file(input)?;
+
+    let (header, _) = Header::deserialize(&mut *input_file.try_reader()?.borrow_mut())?;
+
+    if header.header_type.version < HeaderVersion::V5 {
+        return Err(anyhow::anyhow!(
+            "This function is not supported on header versions below V5"
+        ));
+    }
+
+    if params.key_old == Key::User {
+        info!("Please enter your old key below");
+    }
+
+    let raw_key_old = params
+        .key_old
+        .get_secret_for_header(&PasswordState::Direct, &header)?;
+
+    if let Some(policy) = &params.policy {
+        policy.check_keyslot(&params.hashing_algorithm)?;
+    }
+
+    if params.key_new == Key::User {
+        info!("Please enter your new key below");
+    }
+
+    let raw_key_new = params
+        .key_new
+        .get_secret_for_header(&PasswordState::Validate, &header)?;
+
+    let output_file = stor.create_temp_file_beside(input)?;
+    let result = domain::key::rotate::execute(domain::key::rotate::Request {
+        reader: input_file.try_reader()?,
+        writer: output_file.try_writer()?,
+        raw_key_old,
+        raw_key_new,
+        hash_algorithm: params.hashing_algorithm,
+    });
+
+    let response = match result {
+        Ok(response) => response,
+        Err(e) => {
+            stor.remove_file(output_file).ok();
+            return Err(e.into());
+        }
+    };
+
+    stor.flush_file(&output_file)?;
+    stor.persist_file(output_file, input)?;
+
+    if response.dropped_keyslots > 0 {
+        warn!(
+            "{} other {} removed, as only the new key unlocks the new master key",
+            response.dropped_keyslots,
+            if response.dropped_keyslots == 1 {
+                "keyslot was"
+            } else {
+                "keyslots were"
+            }
+        );
+    }
+
+    success!("Rotated the keys of {}", input);
+
+    Ok(())
+}
+
+// this deletes the keyslot that the key unlocks, or the keyslot at `slot` if one was provided
+pub fn delete(input: &str, key_old: &Key, slot: Option<usize>) -> Result<()> {
     let input_file = RefCell::new(
         OpenOptions::new()
             .read(true)
@@ -125,16 +230,67 @@ pub fn delete(input: &str, key_old: &Key) -> Result<()> {
         info!("Please enter your key below");
     }
 
-    let raw_key_old = key_old.get_secret(&PasswordState::Direct)?;
+    let raw_key_old = key_old.get_secret_for_header(&PasswordState::Direct, &header)?;
 
     domain::key::delete::execute(domain::key::delete::Request {
         handle: &input_file,
+        modified: Some(&|| modified(input)),
         raw_key_old,
+        slot,
     })?;
 
     Ok(())
 }
 
+// this lists every keyslot within the header, without needing a key
+pub fn list(input: &str) -> Result<()> {
+    let input_file = RefCell::new(
+        OpenOptions::new()
+            .read(true)
+            .open(input)
+            .with_context(|| format!("Unable to open input file: {}", input))?,
+    );
+
+    let response = domain::key::list::execute(domain::key::list::Request {
+        handle: &input_file,
+    })?;
+
+    info!(
+        "{} of {} keyslots are in use",
+        response.slots.len(),
+        response.capacity
+    );
+
+    for slot in response.slots {
+        match slot.kind {
+            domain::key::list::Kind::Key(hashing_algorithm) => {
+                println!(
+                    "Keyslot {}: password/keyfile ({})",
+                    slot.index, hashing_algorithm
+                );
+            }
+            domain::key::list::Kind::Recipient(fingerprint) => {
+                println!(
+                    "Keyslot {}: recipient (fingerprint {})",
+                    slot.index,
+                    hex_encode(&fingerprint)
+                );
+            }
+            domain::key::list::Kind::Token => {
+                println!("Keyslot {}: hardware token", slot.index);
+            }
+            domain::key::list::Kind::MetadataOnly => {
+                println!(
+                    "Keyslot {}: metadata-only password/keyfile (can't decrypt)",
+                    slot.index
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
 pub fn verify(input: &str, key: &Key) -> Result<()> {
     let input_file = RefCell::new(
         OpenOptions::new()
@@ -160,7 +316,7 @@ pub fn verify(input: &str, key: &Key) -> Result<()> {
         info!("Please enter your key below");
     }
 
-    let raw_key = key.get_secret(&PasswordState::Direct)?;
+    let raw_key = key.get_secret_for_header(&PasswordState::Direct, &header)?;
 
     domain::key::verify::execute(domain::key::verify::Request {
         handle: &input_file,
@@ -169,3 +325,241 @@ pub fn verify(input: &str, key: &Key) -> Result<()> {
 
     Ok(())
 }
+
+// this unlocks the master key, and prints it as a BIP39 mnemonic that can be written down
+// the mnemonic decrypts the file with `decrypt --mnemonic`, even after every key has been changed
+pub fn export_mnemonic(input: &str, key: &Key) -> Result<()> {
+    let input_file = RefCell::new(
+        OpenOptions::new()
+            .read(true)
+            .open(input)
+            .with_context(|| format!("Unable to open input file: {}", input))?,
+    );
+
+    let (header, _) = Header::deserialize(&mut *input_file.borrow_mut())?;
+
+    if header.header_type.version < HeaderVersion::V5 {
+        return Err(anyhow::anyhow!(
+            "This function is not supported on header versions below V5"
+        ));
+    }
+
+    input_file
+        .borrow_mut()
+        .rewind()
+        .context("Unable to rewind the reader")?;
+
+    if key == &Key::User {
+        info!("Please enter your key below");
+    }
+
+    let raw_key = key.get_secret_for_header(&PasswordState::Direct, &header)?;
+
+    let phrase = domain::key::mnemonic::execute(domain::key::mnemonic::Request {
+        handle: &input_file,
+        raw_key,
+    })?;
+
+    warn!("Anyone with this mnemonic can decrypt the file, regardless of its keys - store it somewhere safe.");
+    println!("{}", phrase.expose());
+
+    Ok(())
+}
+
+// this generates a recipient keypair, and writes the secret key to `output` and the public key to `output.pub`
+// anything encrypted with `--recipient output.pub` can then be decrypted with `--identity output`
+// `x25519` generates a plain X25519 keypair, rather than a hybrid one
+pub fn keypair(output: &str, x25519: bool, force: ForceMode) -> Result<()> {
+    let secret_key = if x25519 {
+        RecipientSecretKey::generate_x25519()
+    } else {
+        RecipientSecretKey::generate()
+    };
+    let public_key = secret_key.public_key();
+
+    write_keypair(
+        output,
+        &secret_key.to_bytes(),
+        &public_key.to_bytes(),
+        &public_key.fingerprint(),
+        force,
+    )
+}
+
+// this generates an Ed25519 signing keypair, in the same layout as `keypair()`
+// files can be signed with `--sign-key output`, and verified with `--verify-key output.pub`
+pub fn signing_keypair(output: &str, force: ForceMode) -> Result<()> {
+    let secret_key = SigningSecretKey::generate();
+    let public_key = secret_key.public_key();
+
+    write_keypair(
+        output,
+        &secret_key.to_bytes(),
+        &public_key.to_bytes(),
+        &public_key.fingerprint(),
+        force,
+    )
+}
+
+// this writes `size` random bytes to `output`, for use with `--keyfile`
+// the fingerprint is a plain BLAKE3 hash of the contents, so it matches `dexios hash` and `b3sum`
+pub fn generate(output: &str, size: usize, fingerprint: bool, force: ForceMode) -> Result<()> {
+    if !overwrite_check(output, force)? {
+        std::process::exit(0);
+    }
+
+    let mut bytes = vec![0u8; size];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let keyfile = Protected::new(bytes);
+
+    write_secret(output, keyfile.expose())
+        .with_context(|| format!("Unable to write the keyfile: {}", output))?;
+
+    success!("Keyfile written to {} ({} bytes)", output, size);
+
+    if fingerprint {
+        info!("Fingerprint: {}", keyfile_fingerprint(keyfile.expose()));
+    }
+
+    Ok(())
+}
+
+// this is the keyfile's plain BLAKE3 hash, so copies of it can be checked with other tools (e.g. `b3sum`)
+fn keyfile_fingerprint(keyfile: &[u8]) -> String {
+    blake3::hash(keyfile).to_hex().to_string()
+}
+
+// secrets should only be readable by their owner
+fn write_secret(output: &str, secret: &[u8]) -> std::io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options.open(output)?;
+
+    // the mode only applies to new files, so an existing one (e.g. with `--force`) is restricted before anything is written to it
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+
+    file.write_all(secret)
+}
+
+fn write_keypair(
+    output: &str,
+    secret_key: &Protected<Vec<u8>>,
+    public_key: &[u8],
+    fingerprint: &[u8],
+    force: ForceMode,
+) -> Result<()> {
+    let public_output = format!("{output}.pub");
+
+    if !overwrite_check(output, force)? || !overwrite_check(&public_output, force)? {
+        std::process::exit(0);
+    }
+
+    write_secret(output, secret_key.expose())
+        .with_context(|| format!("Unable to write the secret key: {}", output))?;
+
+    std::fs::write(&public_output, public_key)
+        .with_context(|| format!("Unable to write the public key: {}", public_output))?;
+
+    success!("Secret key written to {}", output);
+    success!("Public key written to {}", public_output);
+    info!("Fingerprint: {}", hex_encode(fingerprint));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("dexios-generate-{}-{}", name, std::process::id()))
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    fn generate_with(args: &[&str]) -> Result<()> {
+        let mut argv = vec!["dexios", "key", "generate", "--force"];
+        argv.extend_from_slice(args);
+        let matches = crate::cli::build().try_get_matches_from(argv)?;
+        crate::subcommands::key_generate(matches.subcommand_matches("key").unwrap())
+    }
+
+    #[test]
+    fn should_generate_keyfiles_within_the_size_range() {
+        let output = temp_path("size");
+
+        generate_with(&[&output]).unwrap();
+        assert_eq!(std::fs::read(&output).unwrap().len(), 64);
+
+        for size in [16, 1_048_576] {
+            generate_with(&["--size", &size.to_string(), &output]).unwrap();
+            assert_eq!(std::fs::read(&output).unwrap().len(), size);
+        }
+
+        for size in ["15", "1048577", "0"] {
+            assert!(generate_with(&["--size", size, &output]).is_err(), "{size}");
+        }
+
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn should_generate_different_keyfiles() {
+        let output = temp_path("random");
+
+        generate_with(&[&output]).unwrap();
+        let first = std::fs::read(&output).unwrap();
+        generate_with(&[&output]).unwrap();
+        assert_ne!(first, std::fs::read(&output).unwrap());
+
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn should_only_let_the_owner_read_keyfiles() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let output = temp_path("permissions");
+        std::fs::remove_file(&output).ok();
+
+        generate_with(&[&output]).unwrap();
+        let mode = std::fs::metadata(&output).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // a keyfile that's overwritten with `--force` is restricted too
+        std::fs::set_permissions(&output, std::fs::Permissions::from_mode(0o644)).unwrap();
+        generate_with(&[&output]).unwrap();
+        let mode = std::fs::metadata(&output).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn should_fingerprint_keyfiles_with_their_blake3_hash() {
+        let output = temp_path("fingerprint");
+
+        generate_with(&["--fingerprint", &output]).unwrap();
+        let keyfile = std::fs::read(&output).unwrap();
+        assert_eq!(
+            keyfile_fingerprint(&keyfile),
+            blake3::hash(&keyfile).to_hex().as_str()
+        );
+
+        // it's the hash of the whole file, so it's what `b3sum` would print
+        assert_eq!(
+            keyfile_fingerprint(b""),
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+
+        std::fs::remove_file(&output).unwrap();
+    }
+}
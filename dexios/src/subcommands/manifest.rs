@@ -0,0 +1,51 @@
+use anyhow::{Context, Result};
+use core::header::Header;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::Seek;
+
+use crate::global::states::{Key, PasswordState};
+use crate::info;
+
+// this lists the files that were packed into `input`, from the manifest within its header
+// a metadata-only key (see `key add --metadata-only`) is enough, as the archive itself isn't decrypted
+pub fn list(input: &str, key: &Key) -> Result<()> {
+    let input_file = RefCell::new(
+        File::open(input).with_context(|| format!("Unable to open input file: {}", input))?,
+    );
+
+    let (header, _) = Header::deserialize(&mut *input_file.borrow_mut())?;
+    if header.manifest.is_none() {
+        return Err(anyhow::anyhow!(
+            "This file doesn't contain a manifest (it needs to be packed with --manifest)"
+        ));
+    }
+
+    input_file
+        .borrow_mut()
+        .rewind()
+        .context("Unable to rewind the reader")?;
+
+    if key == &Key::User {
+        info!("Please enter your key below");
+    }
+
+    let raw_key = key.get_secret_for_header(&PasswordState::Direct, &header)?;
+
+    let entries = domain::manifest::execute(domain::manifest::Request {
+        handle: &input_file,
+        raw_key,
+    })?;
+
+    info!("{} files were packed", entries.len());
+    for entry in entries {
+        println!(
+            "{}  {:>12}  {}",
+            entry.hash.to_hex(),
+            entry.size,
+            entry.path
+        );
+    }
+
+    Ok(())
+}
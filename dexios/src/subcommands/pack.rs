@@ -1,12 +1,17 @@
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::num::NonZeroU8;
 use std::process::exit;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Result;
-use core::header::{HeaderType, HEADER_VERSION};
+use core::header::{Header, HeaderType, HEADER_VERSION};
 use core::primitives::{Algorithm, Mode};
 
-use crate::global::states::{HashMode, HeaderLocation, PasswordState};
+use crate::global::states::{
+    ForceMode, HashMode, HeaderLocation, Key, PasswordState, PrintMode, ProgressInterval, StatsMode,
+};
 use crate::{
     global::states::EraseSourceDir,
     global::{
@@ -14,7 +19,8 @@ use crate::{
         structs::{CryptoParams, PackParams},
     },
 };
-use domain::storage::Storage;
+use crate::{info, success, warn};
+use domain::storage::{Entry, FileStorage, SkippedEntry, SpecialFiles, Storage};
 
 use crate::cli::prompt::overwrite_check;
 
@@ -26,7 +32,204 @@ pub struct Request<'a> {
     pub algorithm: Algorithm,
 }
 
-// this first indexes the input directory
+fn print_stats(stats: &domain::pack::Stats) {
+    info!(
+        "Packed {} files with {} compression {}",
+        stats.files,
+        stats.jobs,
+        if stats.jobs == 1 { "job" } else { "jobs" }
+    );
+    info!(
+        "Read: {} bytes in {:.2?}",
+        stats.read_bytes, stats.read_time
+    );
+    info!(
+        "Compress: {:.2?} (summed across all jobs)",
+        stats.compress_time
+    );
+    info!(
+        "Archive: {} bytes in {:.2?}",
+        stats.archive_bytes, stats.archive_time
+    );
+    info!("Encrypt: {:.2?}", stats.encrypt_time);
+}
+
+// special files are summarized by their kind, unless verbose mode is asking for every path
+pub fn report_skipped(skipped: &[SkippedEntry], print_mode: &PrintMode) {
+    if skipped.is_empty() {
+        return;
+    }
+
+    if *print_mode == PrintMode::Verbose {
+        for entry in skipped {
+            warn!("Skipped {} ({})", entry.path.display(), entry.kind);
+        }
+    }
+
+    let mut kinds = BTreeMap::new();
+    for entry in skipped {
+        *kinds.entry(entry.kind).or_insert(0usize) += 1;
+    }
+
+    let kinds = kinds
+        .iter()
+        .map(|(kind, count)| format!("{kind}: {count}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    warn!(
+        "Skipped {} special {} ({})",
+        skipped.len(),
+        if skipped.len() == 1 { "file" } else { "files" },
+        kinds
+    );
+}
+
+// verbose mode logs every file, which floods the terminal on large trees
+// summaries are only logged every so often, along with the throughput since the previous one
+pub fn on_progress(print_mode: &PrintMode) -> Option<domain::pack::OnProgressFn> {
+    let interval = match print_mode {
+        PrintMode::Quiet => return None,
+        PrintMode::Verbose => {
+            return Some(Box::new(|progress: &domain::pack::Progress<'_>| {
+                info!("Packed {}", progress.name);
+            }))
+        }
+        PrintMode::Summary(interval) => *interval,
+    };
+
+    let mut last = (Duration::ZERO, 0);
+
+    Some(Box::new(move |progress: &domain::pack::Progress<'_>| {
+        let percent = |files: usize| files * 100 / progress.total.max(1);
+
+        let due = match interval {
+            ProgressInterval::Files(files) => progress.files.is_multiple_of(files.get()),
+            ProgressInterval::Percent(step) => {
+                let step = usize::from(step.get());
+                percent(progress.files) / step != percent(progress.files - 1) / step
+            }
+        };
+
+        if !due && progress.files != progress.total {
+            return;
+        }
+
+        let (elapsed, read_bytes) = last;
+        let seconds = (progress.elapsed - elapsed).as_secs_f64();
+        let throughput = if seconds > 0.0 {
+            (progress.read_bytes - read_bytes) as f64 / seconds / 1_048_576.0
+        } else {
+            0.0
+        };
+
+        info!(
+            "Packed {} of {} files ({}%), {:.1} MiB/s",
+            progress.files,
+            progress.total,
+            percent(progress.files),
+            throughput
+        );
+
+        last = (progress.elapsed, progress.read_bytes);
+    }))
+}
+
+// this replaces each input directory with everything inside of it
+fn expand_dirs(
+    stor: &FileStorage,
+    input_files: Vec<Entry<File>>,
+    req: &Request,
+    skipped: &mut Vec<SkippedEntry>,
+) -> Result<Vec<Entry<File>>> {
+    let compress_files = input_files
+        .into_iter()
+        .flat_map(|file| {
+            if file.is_dir() {
+                // TODO(pleshevskiy): use iterator instead of vec!
+                match stor.read_dir_with(&file, req.pack_params.special_files) {
+                    Ok((files, skipped_files)) => {
+                        skipped.extend(skipped_files);
+                        files.into_iter().map(Ok).collect()
+                    }
+                    Err(err) => vec![Err(err)],
+                }
+            } else {
+                vec![Ok(file)]
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(compress_files)
+}
+
+fn compression_method(compression: &Compression) -> zip::CompressionMethod {
+    match compression {
+        Compression::None => zip::CompressionMethod::Stored,
+        Compression::Zstd => zip::CompressionMethod::Zstd,
+    }
+}
+
+// this only reads a sample of the files, and nothing is written (not even the output file)
+fn estimate(
+    stor: Arc<FileStorage>,
+    compress_files: &[Entry<File>],
+    req: &Request,
+    percent: u8,
+) -> Result<()> {
+    let estimate = domain::pack::estimate::execute(
+        stor,
+        domain::pack::estimate::Request {
+            compress_files,
+            roots: &req.pack_params.roots,
+            compression_method: compression_method(&req.pack_params.compression),
+            algorithm: req.algorithm,
+            percent,
+            jobs: req.pack_params.jobs,
+        },
+    )?;
+
+    info!(
+        "Sampled {} of {} files ({}% of each type)",
+        estimate.sampled_files, estimate.files, percent
+    );
+    for t in &estimate.types {
+        let extension = if t.extension.is_empty() {
+            "(no extension)".to_string()
+        } else {
+            format!(".{}", t.extension)
+        };
+
+        info!(
+            "{}: {} {}, {} bytes, compressed to {:.0}% of their size ({} sampled)",
+            extension,
+            t.files,
+            if t.files == 1 { "file" } else { "files" },
+            t.bytes,
+            t.ratio * 100.0,
+            t.sampled_files
+        );
+    }
+    info!(
+        "Estimated archive: {} bytes ({} bytes once encrypted, excluding the header)",
+        estimate.archive_bytes, estimate.encrypted_bytes
+    );
+    info!(
+        "Estimated time: {:.2?} with {} compression {} (excluding key derivation)",
+        estimate.duration,
+        req.pack_params.jobs,
+        if req.pack_params.jobs.get() == 1 {
+            "job"
+        } else {
+            "jobs"
+        }
+    );
+
+    Ok(())
+}
+
+// this first indexes the input directories (files may be provided too)
+// FIFOs, sockets and devices are skipped (or refused) before they're opened, as opening a FIFO could block forever
+// each input is placed beneath its own prefix within the archive, so several can be merged into one
 // once it has the total number of files/folders, it creates a temporary zip file
 // it compresses all of the files into the temporary archive
 // once compressed, it encrypts the zip file
@@ -42,23 +245,45 @@ pub fn execute(req: &Request) -> Result<()> {
         ));
     }
 
-    if req.input_file.iter().any(|f| PathBuf::from(f).is_file()) {
-        return Err(anyhow::anyhow!("Input path cannot be a file."));
+    // nothing is written when estimating, so there's nothing to overwrite
+    if req.pack_params.estimate.is_none()
+        && !overwrite_check(req.output_file, req.crypto_params.force)?
+    {
+        exit(0);
     }
 
-    if !overwrite_check(req.output_file, req.crypto_params.force)? {
-        exit(0);
+    let mut skipped = Vec::new();
+    let mut input_files = Vec::new();
+    for file_name in req.input_file {
+        let kind = domain::storage::entry_kind(file_name)?;
+        if !kind.is_special() {
+            input_files.push(stor.read_file(file_name)?);
+        } else if req.pack_params.special_files == SpecialFiles::Refuse {
+            return Err(domain::storage::Error::SpecialFile(file_name.into(), kind).into());
+        } else {
+            skipped.push(SkippedEntry {
+                path: file_name.into(),
+                kind,
+            });
+        }
     }
 
-    let input_files = req
-        .input_file
-        .iter()
-        .map(|file_name| stor.read_file(file_name))
-        .collect::<Result<Vec<_>, _>>()?;
-    let raw_key = req.crypto_params.key.get_secret(&PasswordState::Validate)?;
-    let output_file = stor
-        .create_file(req.output_file)
-        .or_else(|_| stor.write_file(req.output_file))?;
+    if let Some(percent) = req.pack_params.estimate {
+        let compress_files = expand_dirs(&stor, input_files, req, &mut skipped)?;
+        report_skipped(&skipped, &req.pack_params.print_mode);
+        return estimate(stor, &compress_files, req, percent);
+    }
+
+    if let Some(policy) = &req.crypto_params.policy {
+        policy.check_encrypt(&req.algorithm, &req.crypto_params.hashing_algorithm)?;
+    }
+
+    let raw_key = req
+        .crypto_params
+        .key
+        .get_secret_with(&PasswordState::Validate, true)?;
+    // the output is written to a temporary file, which replaces the output file once it's complete
+    let output_file = stor.create_temp_file_beside(req.output_file)?;
 
     let header_file = match &req.crypto_params.header_location {
         HeaderLocation::Embedded => None,
@@ -71,31 +296,17 @@ pub fn execute(req: &Request) -> Result<()> {
         }
     };
 
-    let compress_files = input_files
-        .into_iter()
-        .flat_map(|file| {
-            if file.is_dir() {
-                // TODO(pleshevskiy): use iterator instead of vec!
-                match stor.read_dir(&file) {
-                    Ok(files) => files.into_iter().map(Ok).collect(),
-                    Err(err) => vec![Err(err)],
-                }
-            } else {
-                vec![Ok(file)]
-            }
-        })
-        .collect::<Result<Vec<_>, _>>()?;
+    let compress_files = expand_dirs(&stor, input_files, req, &mut skipped)?;
+    report_skipped(&skipped, &req.pack_params.print_mode);
 
-    let compression_method = match req.pack_params.compression {
-        Compression::None => zip::CompressionMethod::Stored,
-        Compression::Zstd => zip::CompressionMethod::Zstd,
-    };
+    let compression_method = compression_method(&req.pack_params.compression);
 
     // 2. compress and encrypt files
-    domain::pack::execute(
+    let result = domain::pack::execute(
         stor.clone(),
         domain::pack::Request {
             compress_files,
+            roots: req.pack_params.roots.clone(),
             compression_method,
             writer: output_file.try_writer()?,
             header_writer: header_file.as_ref().and_then(|f| f.try_writer().ok()),
@@ -106,24 +317,123 @@ pub fn execute(req: &Request) -> Result<()> {
                 algorithm: req.algorithm,
             },
             hashing_algorithm: req.crypto_params.hashing_algorithm,
+            jobs: req.pack_params.jobs,
+            on_stats: if req.pack_params.stats_mode == StatsMode::ShowStats {
+                Some(Box::new(print_stats))
+            } else {
+                None
+            },
+            on_progress: on_progress(&req.pack_params.print_mode),
+            metadata: Some(super::encrypt::metadata()),
+            streams: req.pack_params.streams,
+            filters: req.pack_params.filters.clone(),
+            keyfile_hash: matches!(
+                req.crypto_params.key,
+                Key::Keyfile(_) | Key::Keyfiles(_) | Key::TwoFactor(..)
+            ),
+            counter: req.pack_params.counter,
+            two_factor: matches!(req.crypto_params.key, Key::TwoFactor(..)),
+            manifest: req.pack_params.manifest,
+            base: Vec::new(),
         },
-    )?;
+    );
+    if let Err(e) = result {
+        stor.remove_file(output_file).ok();
+        return Err(e.into());
+    }
 
-    // 3. flush result
+    // 3. flush result, and move it into place
     if let Some(header_file) = header_file {
         stor.flush_file(&header_file)?;
     }
     stor.flush_file(&output_file)?;
+    stor.persist_file(output_file, req.output_file)?;
+
+    let output = match &req.crypto_params.move_to {
+        Some(move_to) => {
+            super::encrypt::move_output(req.output_file, move_to, req.crypto_params.force)?
+        }
+        None => req.output_file.to_string(),
+    };
 
     if req.crypto_params.hash_mode == HashMode::CalculateHash {
-        super::hashing::hash_stream(&[req.output_file.to_string()])?;
+        super::hashing::hash_stream(&[output])?;
     }
 
     if req.pack_params.erase_source == EraseSourceDir::Erase {
         req.input_file.iter().try_for_each(|file_name| {
-            super::erase::secure_erase(file_name, 1, req.crypto_params.force)
+            super::erase::secure_erase(
+                file_name,
+                NonZeroU8::new(1).unwrap(),
+                req.crypto_params.force,
+            )
         })?;
     }
 
     Ok(())
 }
+
+// this compresses the files within `input` again, and the new pack is only moved into place once it's complete
+// the keyslots and manifest are carried over, so the same keys unlock it
+pub fn recompress(
+    input: &str,
+    output: &str,
+    compression: &Compression,
+    level: Option<i32>,
+    key: &Key,
+    force: ForceMode,
+) -> Result<()> {
+    let stor = Arc::new(FileStorage);
+
+    if input == output {
+        return Err(anyhow::anyhow!(
+            "Input and output files cannot have the same name."
+        ));
+    }
+
+    if !overwrite_check(output, force)? {
+        exit(0);
+    }
+
+    let input_file = stor.read_file(input)?;
+    let (header, _) = Header::deserialize(&mut *input_file.try_reader()?.borrow_mut())?;
+
+    if key == &Key::User {
+        info!("Please enter your key below");
+    }
+
+    let raw_key = key.get_secret_for_header(&PasswordState::Direct, &header)?;
+
+    let output_file = stor.create_temp_file_beside(output)?;
+    let result = domain::pack::recompress::execute(domain::pack::recompress::Request {
+        reader: input_file.try_reader()?,
+        writer: output_file.try_writer()?,
+        raw_key,
+        compression_method: compression_method(compression),
+        compression_level: level,
+    });
+
+    let response = match result {
+        Ok(response) => response,
+        Err(e) => {
+            stor.remove_file(output_file).ok();
+            return Err(e.into());
+        }
+    };
+
+    stor.flush_file(&output_file)?;
+    stor.persist_file(output_file, output)?;
+
+    info!(
+        "Archive: {} bytes, previously {} bytes",
+        response.new_archive_bytes, response.old_archive_bytes
+    );
+    success!(
+        "Recompressed {} entries from {} into {}",
+        response.entries,
+        input,
+        output
+    );
+
+    Ok(())
+}
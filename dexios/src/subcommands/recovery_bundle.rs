@@ -0,0 +1,146 @@
+use std::cell::RefCell;
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use core::header::{HeaderType, HEADER_VERSION};
+use core::primitives::{Algorithm, Mode};
+use domain::recovery_bundle::import::Outcome;
+use domain::storage::Storage;
+
+use crate::cli::prompt::overwrite_check;
+use crate::global::states::{HashMode, Key, PasswordState};
+use crate::global::structs::CryptoParams;
+use crate::{info, success, warn};
+
+// this collects the header of every dexios file within the input directory, along with a checksum of its data
+// it's all stored within a single encrypted bundle, so that damaged or stripped headers can be restored later
+pub fn export(
+    input: &str,
+    output: &str,
+    params: &CryptoParams,
+    algorithm: Algorithm,
+) -> Result<()> {
+    let stor = Arc::new(domain::storage::FileStorage);
+
+    // 1. validate and prepare options
+    if !PathBuf::from(input).is_dir() {
+        return Err(anyhow::anyhow!("Input path must be a directory."));
+    }
+
+    if !overwrite_check(output, params.force)? {
+        exit(0);
+    }
+
+    // an older bundle within the input directory shouldn't end up inside of the new one
+    let output_path = std::fs::canonicalize(output).ok();
+    let input_dir = stor.read_file(input)?;
+    let mut entries = stor.read_dir(&input_dir)?;
+    entries
+        .retain(|e| output_path.is_none() || std::fs::canonicalize(e.path()).ok() != output_path);
+    entries.sort_by(|a, b| a.path().cmp(b.path()));
+
+    if let Some(policy) = &params.policy {
+        policy.check_encrypt(&algorithm, &params.hashing_algorithm)?;
+    }
+
+    let raw_key = params.key.get_secret_with(&PasswordState::Validate, true)?;
+    let output_file = stor
+        .create_file(output)
+        .or_else(|_| stor.write_file(output))?;
+
+    // 2. build and encrypt the bundle
+    let bundle =
+        domain::recovery_bundle::export::execute(domain::recovery_bundle::export::Request {
+            writer: output_file.try_writer()?,
+            root: Path::new(input),
+            entries,
+            raw_key,
+            header_type: HeaderType {
+                version: HEADER_VERSION,
+                mode: Mode::StreamMode,
+                algorithm,
+            },
+            hashing_algorithm: params.hashing_algorithm,
+            keyfile_hash: matches!(params.key, Key::Keyfile(_) | Key::Keyfiles(_)),
+        })?;
+
+    // 3. flush result
+    stor.flush_file(&output_file)?;
+
+    for entry in &bundle.entries {
+        let header = entry.parse_header()?;
+        info!(
+            "{}: {} header, {} keyslot(s)",
+            entry.path,
+            header.header_type.version,
+            header.keyslots.map_or(1, |keyslots| keyslots.len())
+        );
+    }
+
+    success!("Exported {} header(s) to {}", bundle.entries.len(), output);
+
+    if params.hash_mode == HashMode::CalculateHash {
+        super::hashing::hash_stream(&[output.to_string()])?;
+    }
+
+    Ok(())
+}
+
+// this decrypts a recovery bundle, and restores any headers within the target directory that are missing or damaged
+// a header is only restored if the data that follows it still matches the bundle's checksum
+// headers that are valid but differ from the bundle (e.g. after `key change`) are left alone, unless `replace_valid` is set
+pub fn import(input: &str, dir: &str, key: &Key, replace_valid: bool) -> Result<()> {
+    let bundle_file = RefCell::new(
+        std::fs::File::open(input)
+            .with_context(|| format!("Unable to open recovery bundle: {}", input))?,
+    );
+
+    let raw_key = key.get_secret_for(&PasswordState::Direct, input)?;
+    let bundle = domain::recovery_bundle::import::read_bundle(&bundle_file, raw_key)?;
+
+    let mut restored = 0;
+
+    for entry in &bundle.entries {
+        let path = Path::new(dir).join(&entry.path);
+
+        let handle = match OpenOptions::new().read(true).write(true).open(&path) {
+            Ok(file) => RefCell::new(file),
+            Err(_) => {
+                warn!("{}: unable to open the file, skipping", path.display());
+                continue;
+            }
+        };
+
+        let outcome =
+            domain::recovery_bundle::import::execute(domain::recovery_bundle::import::Request {
+                handle: &handle,
+                entry,
+                replace_valid,
+            });
+
+        match outcome {
+            Ok(Outcome::Intact) => info!("{}: header is intact", path.display()),
+            Ok(Outcome::Restored) => {
+                restored += 1;
+                success!("{}: header restored", path.display());
+            }
+            Ok(Outcome::Diverged) => warn!(
+                "{}: header is valid but differs from the bundle, use --force to replace it",
+                path.display()
+            ),
+            Err(err) => warn!("{}: {}", path.display(), err),
+        }
+    }
+
+    success!(
+        "Restored {} of {} header(s) from {}",
+        restored,
+        bundle.entries.len(),
+        input
+    );
+
+    Ok(())
+}
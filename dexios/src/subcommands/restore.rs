@@ -0,0 +1,372 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::Seek;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use core::header::Header;
+use core::protected::Protected;
+use domain::storage::{Entry, FileStorage, Storage};
+
+use super::backup::{backup_name, find_backups, read_manifest};
+use crate::cli::prompt::get_answer;
+use crate::global::policy::Policy;
+use crate::global::states::{ForceMode, Key, PasswordState, PrintMode};
+use crate::{info, success, warn};
+
+pub struct Request<'a> {
+    // a backup (to restore the directory as it was then), or the directory that backups are stored in (to restore the newest)
+    pub input: &'a str,
+    pub output: &'a str,
+    pub key: Key,
+    pub policy: Option<Policy>,
+    pub streams: domain::streams::Options,
+    pub print_mode: PrintMode,
+    pub force: ForceMode,
+}
+
+// where each file of the restored backup came from
+#[derive(Default)]
+struct Progress {
+    restored: BTreeMap<String, PathBuf>,
+    skipped: BTreeSet<String>,
+}
+
+impl Progress {
+    fn is_done(&self, path: &str) -> bool {
+        self.restored.contains_key(path) || self.skipped.contains(path)
+    }
+}
+
+// this finds the backups that `input` refers to, oldest first (ending with the one that's being restored)
+fn find_chain(input: &str) -> Result<Vec<PathBuf>> {
+    let path = Path::new(input);
+
+    if path.is_dir() {
+        let names = std::fs::read_dir(path)?
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                entry
+                    .file_name()
+                    .to_str()
+                    .and_then(backup_name)
+                    .map(str::to_string)
+            })
+            .collect::<BTreeSet<_>>();
+
+        return match names.len() {
+            0 => Err(anyhow::anyhow!("No backups were found in {}", input)),
+            1 => Ok(find_backups(&[input], names.iter().next().unwrap())),
+            _ => Err(anyhow::anyhow!(
+                "{} contains backups of several directories ({}), so pass the backup to restore instead",
+                input,
+                names.into_iter().collect::<Vec<_>>().join(", ")
+            )),
+        };
+    }
+
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .with_context(|| format!("Unable to read {}", input))?;
+    let name = backup_name(file_name).with_context(|| {
+        format!(
+            "{} isn't a backup (they're named like `<name>-<date>-<time>.dx`, or end with `.001` if they were split)",
+            input
+        )
+    })?;
+
+    let dir = match path.parent().and_then(Path::to_str) {
+        Some("") | None => ".",
+        Some(dir) => dir,
+    };
+
+    let mut chain = find_backups(&[dir], name);
+    chain.retain(|backup| backup.file_name() <= path.file_name());
+    Ok(chain)
+}
+
+// split backups are joined into a temporary file, as they're only decrypted as a whole
+// a backup always has at least two parts, so if only the first is numbered, the others were named with keyed hashes
+fn open_backup(
+    stor: &Arc<FileStorage>,
+    path: &Path,
+    raw_key: &Protected<Vec<u8>>,
+) -> Result<(Entry<std::fs::File>, bool)> {
+    let Some(base) = path.to_str().and_then(|path| path.strip_suffix(".001")) else {
+        return Ok((stor.read_file(path)?, false));
+    };
+
+    let mut parts = domain::split::find_parts(Path::new(base), None);
+    if parts.len() == 1 {
+        let (header, _) = Header::deserialize(&mut std::fs::File::open(path)?)
+            .with_context(|| format!("Unable to read the header of {}", path.display()))?;
+        let master_key = core::key::decrypt_master_key(raw_key.clone(), &header)
+            .with_context(|| format!("Unable to decrypt the master key of {}", path.display()))?;
+        parts = domain::split::find_parts(
+            Path::new(base),
+            Some(&domain::split::names_key(&master_key)),
+        );
+    }
+
+    let joined = stor.create_temp_file()?;
+    let result = (|| -> Result<()> {
+        let mut writer = joined.try_writer()?.borrow_mut();
+        for part in parts {
+            let mut reader = std::fs::File::open(&part)
+                .with_context(|| format!("Unable to read {}", part.display()))?;
+            std::io::copy(&mut reader, &mut *writer)?;
+        }
+        writer.rewind()?;
+        drop(writer);
+        stor.flush_file(&joined)?;
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        stor.remove_file(joined).ok();
+        return Err(e);
+    }
+    Ok((joined, true))
+}
+
+// this is the file's path within the archive, e.g. `documents/a.txt`
+fn archive_path(output: &Path, full_path: &Path) -> Option<String> {
+    full_path
+        .strip_prefix(output)
+        .ok()?
+        .components()
+        .map(|c| match c {
+            Component::Normal(part) => part.to_str(),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()
+        .map(|parts| parts.join("/"))
+}
+
+struct Pass {
+    backup: PathBuf,
+    // the archive paths that this backup has the restored version of
+    wanted: HashSet<String>,
+    // the newest backup is the only one whose directories are created, so directories that were removed don't return
+    newest: bool,
+}
+
+// this unpacks the files of one backup that haven't been restored yet
+fn unpack(
+    stor: &Arc<FileStorage>,
+    req: &Request,
+    raw_key: Protected<Vec<u8>>,
+    pass: Pass,
+    progress: &Rc<RefCell<Progress>>,
+) -> Result<()> {
+    let (file, joined) = open_backup(stor, &pass.backup, &raw_key)?;
+    let output = PathBuf::from(req.output);
+
+    let on_zip_file = {
+        let output = output.clone();
+        let progress = progress.clone();
+        let force = req.force;
+        let backup = pass.backup.clone();
+
+        Box::new(move |full_path: PathBuf| {
+            let Some(path) = archive_path(&output, &full_path) else {
+                return false;
+            };
+            if !pass.wanted.contains(&path) {
+                // everything in an archive is within its manifest, besides the directories
+                return pass.newest && !progress.borrow().is_done(&path);
+            }
+
+            let mut progress = progress.borrow_mut();
+            if progress.is_done(&path) {
+                return false;
+            }
+
+            // files are only extracted once, so any that exist were there before the restore
+            if full_path.is_file() {
+                let answer = get_answer(
+                    &format!("{} already exists, would you like to overwrite?", path),
+                    true,
+                    force,
+                )
+                .expect("Unable to read answer");
+                if !answer {
+                    warn!("Skipping {}", path);
+                    progress.skipped.insert(path);
+                    return false;
+                }
+            }
+
+            progress.restored.insert(path, backup.clone());
+            true
+        })
+    };
+
+    let result = domain::unpack::execute(
+        stor.clone(),
+        domain::unpack::Request {
+            header_reader: None,
+            reader: file.try_reader()?,
+            output_dir_path: output,
+            raw_key,
+            on_decrypted_header: None,
+            on_archive_info: None,
+            on_zip_file: Some(on_zip_file),
+            streams: req.streams,
+            filters: Vec::new(),
+            limits: domain::unpack::Limits::default(),
+        },
+    );
+
+    if joined {
+        stor.remove_file(file).ok();
+    }
+    result.with_context(|| format!("Unable to restore from {}", pass.backup.display()))
+}
+
+fn hash_file(path: &Path) -> Option<blake3::Hash> {
+    let mut file = std::fs::File::open(path).ok()?;
+    let mut hasher = blake3::Hasher::new();
+    std::io::copy(&mut file, &mut hasher).ok()?;
+    Some(hasher.finalize())
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+// this restores a directory from a backup, and the backups before it
+// each file is extracted from the newest backup that stored the version in the manifest being restored, so
+// going back through the backups gives the same result as unpacking them oldest first, without writing anything twice
+// the backups are decrypted (and so verified) before any of their files are written, and the restored files are then checked against the manifest
+//
+// files that were removed before the backup was made aren't restored
+#[allow(clippy::needless_pass_by_value)]
+pub fn execute(req: Request) -> Result<()> {
+    let stor = Arc::new(FileStorage);
+
+    // 1. find the backups, and the files that the newest one covers
+    let chain = find_chain(req.input)?;
+    let newest = chain
+        .last()
+        .with_context(|| format!("No backups were found for {}", req.input))?
+        .clone();
+
+    if let Some(policy) = &req.policy {
+        for backup in &chain {
+            policy.check_file(&backup.to_string_lossy(), None)?;
+        }
+    }
+
+    let raw_key = req
+        .key
+        .get_secret_for(&PasswordState::Direct, &newest.to_string_lossy())?;
+
+    let target = read_manifest(&newest, raw_key.clone())
+        .with_context(|| format!("Unable to read the manifest of {}", newest.display()))?
+        .into_iter()
+        // the data that's attached to each file is listed too, but it's restored along with the file
+        .filter(|entry| !domain::streams::is_sidecar(&entry.path))
+        .map(|entry| (entry.path, entry.hash))
+        .collect::<BTreeMap<_, _>>();
+
+    info!(
+        "Restoring {} {} from {}",
+        target.len(),
+        if target.len() == 1 { "file" } else { "files" },
+        file_name(&newest)
+    );
+
+    // 2. go back through the backups, until every file has been restored
+    let progress = Rc::new(RefCell::new(Progress::default()));
+    for backup in chain.iter().rev() {
+        let is_newest = *backup == newest;
+        if !is_newest && target.keys().all(|path| progress.borrow().is_done(path)) {
+            break;
+        }
+
+        let manifest = read_manifest(backup, raw_key.clone())
+            .with_context(|| format!("Unable to read the manifest of {}", backup.display()))?;
+        let wanted = manifest
+            .into_iter()
+            .filter(|entry| target.get(&entry.path) == Some(&entry.hash))
+            .filter(|entry| !progress.borrow().is_done(&entry.path))
+            .map(|entry| entry.path)
+            .collect::<HashSet<_>>();
+
+        if wanted.is_empty() && !is_newest {
+            continue;
+        }
+
+        let before = progress.borrow().restored.len();
+        unpack(
+            &stor,
+            &req,
+            raw_key.clone(),
+            Pass {
+                backup: backup.clone(),
+                wanted,
+                newest: is_newest,
+            },
+            &progress,
+        )?;
+
+        let count = progress.borrow().restored.len() - before;
+        info!(
+            "Restored {} {} from {}",
+            count,
+            if count == 1 { "file" } else { "files" },
+            file_name(backup)
+        );
+    }
+
+    // 3. check the restored files against the manifest, and report on each of them
+    let progress = progress.borrow();
+    let mut missing = 0;
+    let mut different = 0;
+    for (path, hash) in &target {
+        if progress.skipped.contains(path) {
+            continue;
+        }
+
+        let Some(backup) = progress.restored.get(path) else {
+            warn!("Missing: {} (it isn't stored in any of the backups)", path);
+            missing += 1;
+            continue;
+        };
+
+        if hash_file(&Path::new(req.output).join(path)).as_ref() != Some(hash) {
+            warn!("Differs: {} (from {})", path, file_name(backup));
+            different += 1;
+        } else if req.print_mode == PrintMode::Verbose {
+            info!("Restored {} (from {})", path, file_name(backup));
+        }
+    }
+
+    if missing > 0 || different > 0 {
+        return Err(anyhow::anyhow!(
+            "The restore is incomplete ({} missing, {} different)",
+            missing,
+            different
+        ));
+    }
+
+    success!(
+        "Restored {} {} to {} ({} skipped)",
+        progress.restored.len(),
+        if progress.restored.len() == 1 {
+            "file"
+        } else {
+            "files"
+        },
+        req.output,
+        progress.skipped.len()
+    );
+
+    Ok(())
+}
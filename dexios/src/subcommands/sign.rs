@@ -0,0 +1,132 @@
+use std::path::Path;
+use std::process::exit;
+
+use anyhow::{Context, Result};
+use core::protected::Protected;
+use core::signature::{Signature, SigningPublicKey, SigningSecretKey};
+use domain::storage::Storage;
+use domain::utils::hex_encode;
+
+use crate::cli::prompt::overwrite_check;
+use crate::global::states::ForceMode;
+use crate::{info, success, warn};
+
+// this is where signatures are written to/read from by default
+pub fn default_signature_path(input: &str) -> String {
+    format!("{input}.sig")
+}
+
+pub fn read_signing_key(path: &str) -> Result<SigningSecretKey> {
+    let bytes = Protected::new(
+        std::fs::read(path).with_context(|| format!("Unable to read the signing key: {path}"))?,
+    );
+    SigningSecretKey::from_bytes(bytes.expose())
+}
+
+pub fn read_verify_key(path: &str) -> Result<SigningPublicKey> {
+    let bytes = std::fs::read(path)
+        .with_context(|| format!("Unable to read the signer's public key: {path}"))?;
+    SigningPublicKey::from_bytes(&bytes)
+}
+
+// this signs `input` (and its detached header, if there is one) and writes the signature to `output`
+pub fn sign(
+    input: &str,
+    header: Option<&str>,
+    signing_key: &SigningSecretKey,
+    output: &str,
+    force: ForceMode,
+) -> Result<()> {
+    let stor = domain::storage::FileStorage;
+
+    if !overwrite_check(output, force)? {
+        exit(0);
+    }
+
+    let input_file = stor.read_file(input)?;
+    let header_file = header.map(|path| stor.read_file(path)).transpose()?;
+
+    let signature = domain::sign::execute(domain::sign::Request {
+        reader: input_file.try_reader()?,
+        header_reader: header_file.as_ref().and_then(|h| h.try_reader().ok()),
+        signing_key,
+    })?;
+
+    std::fs::write(output, signature.to_bytes())
+        .with_context(|| format!("Unable to write the signature: {output}"))?;
+
+    success!("Signature written to {}", output);
+
+    Ok(())
+}
+
+// this checks that `input` (and its detached header, if there is one) was signed by the owner of `public_key`
+pub fn verify(
+    input: &str,
+    header: Option<&str>,
+    public_key: &SigningPublicKey,
+    signature_path: &str,
+) -> Result<()> {
+    let stor = domain::storage::FileStorage;
+
+    let bytes = std::fs::read(signature_path)
+        .with_context(|| format!("Unable to read the signature: {signature_path}"))?;
+    let signature = Signature::from_bytes(&bytes)?;
+
+    let input_file = stor.read_file(input)?;
+    let header_file = header.map(|path| stor.read_file(path)).transpose()?;
+
+    domain::verify_signature::execute(domain::verify_signature::Request {
+        reader: input_file.try_reader()?,
+        header_reader: header_file.as_ref().and_then(|h| h.try_reader().ok()),
+        signature: &signature,
+        public_key,
+    })
+    .with_context(|| {
+        format!(
+            "Signature verification failed (signed by {})",
+            hex_encode(&signature.signer().fingerprint())
+        )
+    })?;
+
+    success!(
+        "Good signature from {}",
+        hex_encode(&public_key.fingerprint())
+    );
+
+    Ok(())
+}
+
+// this is used before decrypting
+// if a public key was provided, the signature must be valid - otherwise, the user is just told that a signature exists
+pub fn verify_before_decrypt(
+    input: &str,
+    header: Option<&str>,
+    verify_key: Option<&str>,
+    signature_path: Option<&str>,
+) -> Result<()> {
+    let default_path = default_signature_path(input);
+
+    match verify_key {
+        Some(key_path) => {
+            let public_key = read_verify_key(key_path)?;
+            verify(
+                input,
+                header,
+                &public_key,
+                signature_path.unwrap_or(&default_path),
+            )
+        }
+        None => {
+            let path = signature_path.unwrap_or(&default_path);
+            if Path::new(path).is_file() {
+                warn!(
+                    "{} is signed, but the signature has not been verified.",
+                    input
+                );
+                info!("Use --verify-key with the signer's public key to verify it.");
+            }
+            Ok(())
+        }
+    }
+}
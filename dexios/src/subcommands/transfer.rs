@@ -0,0 +1,167 @@
+use std::cell::RefCell;
+use std::io::{BufReader, BufWriter};
+use std::net::{TcpListener, TcpStream};
+use std::process::exit;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use core::header::{HashingAlgorithm, BLAKE3BALLOON_LATEST};
+use core::key::generate_passphrase;
+use core::primitives::Algorithm;
+use core::protected::Protected;
+use domain::storage::Storage;
+use domain::transfer::pake::{self, Role};
+
+use crate::cli::prompt::overwrite_check;
+use crate::global::states::{ForceMode, Key, PasswordState};
+use crate::{info, success, warn};
+
+// number of words in a generated transfer code
+// the key exchange only allows a single guess per connection, so it doesn't need to be long
+const CODE_WORDS: i32 = 3;
+
+pub struct ReceiveRequest<'a> {
+    pub output: &'a str,
+    pub address: &'a str,
+    pub use_code: bool,
+    pub keep_encrypted: bool,
+    pub key: &'a Key,
+    pub force: ForceMode,
+}
+
+// this encrypts the input file and streams it straight to the peer, so nothing is written to disk
+// the peer needs to already be listening (with `dexios receive`)
+// if a code is provided, the key is agreed with the peer instead
+pub fn send(
+    input: &str,
+    address: &str,
+    code: Option<&str>,
+    key: &Key,
+    hashing_algorithm: HashingAlgorithm,
+    algorithm: Algorithm,
+) -> Result<()> {
+    // TODO: It is necessary to raise it to a higher level
+    let stor = Arc::new(domain::storage::FileStorage);
+
+    let input_file = stor.read_file(input)?;
+    let raw_key = match code {
+        Some(_) => None,
+        None => Some(key.get_secret(&PasswordState::Validate)?),
+    };
+
+    info!("Connecting to {}", address);
+    let stream =
+        TcpStream::connect(address).with_context(|| format!("Unable to connect to {address}"))?;
+
+    let raw_key = match (code, raw_key) {
+        (Some(code), _) => pake::handshake(&RefCell::new(&stream), code.as_bytes(), Role::Sender)?,
+        (None, Some(raw_key)) => raw_key,
+        (None, None) => unreachable!(),
+    };
+
+    domain::transfer::send::execute(domain::transfer::send::Request {
+        reader: input_file.try_reader()?,
+        writer: &RefCell::new(BufWriter::new(&stream)),
+        raw_key,
+        algorithm,
+        hashing_algorithm,
+    })?;
+
+    success!("Sent {} to {}", input, address);
+
+    Ok(())
+}
+
+// this waits for a single peer to connect, and decrypts whatever it sends into the output file
+// keys are requested before listening, so the sender is never left waiting on a prompt
+//
+// with a code, the key is agreed with the sender - if the file is kept encrypted,
+// it's then re-keyed so that it opens with the user's own key
+pub fn receive(req: &ReceiveRequest) -> Result<()> {
+    // TODO: It is necessary to raise it to a higher level
+    let stor = Arc::new(domain::storage::FileStorage);
+
+    if !overwrite_check(req.output, req.force)? {
+        exit(0);
+    }
+
+    let raw_key = match (req.use_code, req.keep_encrypted) {
+        (false, _) => Some(req.key.get_secret(&PasswordState::Direct)?),
+        (true, true) => {
+            if req.key == &Key::User {
+                info!("Please enter the key that the received file should be stored with");
+            }
+            Some(req.key.get_secret(&PasswordState::Validate)?)
+        }
+        (true, false) => None,
+    };
+
+    let code = req.use_code.then(|| generate_passphrase(&CODE_WORDS));
+
+    let listener = TcpListener::bind(req.address)
+        .with_context(|| format!("Unable to listen on {}", req.address))?;
+    info!("Waiting for a peer on {}", listener.local_addr()?);
+
+    if let Some(code) = &code {
+        warn!("Your transfer code is: {}", code.expose());
+    }
+
+    let (stream, peer) = listener.accept()?;
+    info!("Receiving from {}", peer);
+
+    let session_key = match &code {
+        Some(code) => Some(pake::handshake(
+            &RefCell::new(&stream),
+            code.expose().as_bytes(),
+            Role::Receiver,
+        )?),
+        None => None,
+    };
+
+    // the output is only replaced once everything has been received (and re-keyed)
+    let output_file = stor.create_temp_file_beside(req.output)?;
+
+    let transfer_key = match (&session_key, &raw_key) {
+        (Some(session_key), _) => Protected::new(session_key.expose().clone()),
+        (None, Some(raw_key)) => Protected::new(raw_key.expose().clone()),
+        (None, None) => unreachable!(),
+    };
+
+    let res = domain::transfer::receive::execute(domain::transfer::receive::Request {
+        reader: &RefCell::new(BufReader::new(&stream)),
+        writer: output_file.try_writer()?,
+        raw_key: transfer_key,
+        keep_encrypted: req.keep_encrypted,
+        on_decrypted_header: None,
+    })
+    .map_err(anyhow::Error::from)
+    .and_then(|()| match (session_key, raw_key) {
+        (Some(session_key), Some(raw_key)) if req.keep_encrypted => {
+            let handle = output_file.try_writer()?;
+            std::io::Seek::rewind(&mut *handle.borrow_mut())?;
+
+            domain::key::change::execute(domain::key::change::Request {
+                handle,
+                modified: None,
+                raw_key_old: session_key,
+                raw_key_new: raw_key,
+                hash_algorithm: HashingAlgorithm::Blake3Balloon(BLAKE3BALLOON_LATEST),
+            })
+            .map_err(anyhow::Error::from)
+        }
+        _ => Ok(()),
+    });
+
+    if let Err(e) = res {
+        // don't leave a partially received file behind
+        stor.remove_file(output_file).ok();
+        return Err(e);
+    }
+
+    stor.flush_file(&output_file)?;
+    stor.persist_file(output_file, req.output)?;
+
+    success!("Received {} from {}", req.output, peer);
+
+    Ok(())
+}
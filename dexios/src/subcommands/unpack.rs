@@ -9,30 +9,43 @@ use crate::global::{
     states::{HeaderLocation, PasswordState, PrintMode},
     structs::CryptoParams,
 };
-use crate::{info, warn};
-use std::path::PathBuf;
+use crate::{info, success, warn};
+use std::path::{Component, Path, PathBuf};
 
 // this first decrypts the input file to a temporary zip file
 // it then unpacks that temporary zip file to the target directory
 // once finished, it erases the temporary file to avoid any residual data
 #[allow(clippy::module_name_repetitions)]
 #[allow(clippy::needless_pass_by_value)]
+#[allow(clippy::too_many_arguments)]
 pub fn unpack(
     input: &str,  // encrypted zip file
     output: &str, // directory
     print_mode: PrintMode,
-    params: CryptoParams, // params for decrypt function
+    params: CryptoParams,                  // params for decrypt function
+    verify_against: Option<&str>, // the directory that was packed, to compare with once unpacked
+    streams: domain::streams::Options, // the attached data to restore, if it was packed
+    filters: Vec<domain::filters::Filter>, // these transform matching files before they're written
+    limits: domain::unpack::Limits, // these guard against zip bombs
 ) -> Result<()> {
     // TODO: It is necessary to raise it to a higher level
     let stor = Arc::new(domain::storage::FileStorage);
 
-    let input_file = stor.read_file(input)?;
-    let header_file = match &params.header_location {
+    let header_path = match &params.header_location {
         HeaderLocation::Embedded => None,
-        HeaderLocation::Detached(path) => Some(stor.read_file(path)?),
+        HeaderLocation::Detached(path) => Some(path.as_str()),
     };
 
-    let raw_key = params.key.get_secret(&PasswordState::Direct)?;
+    if let Some(policy) = &params.policy {
+        policy.check_file(input, header_path)?;
+    }
+
+    let input_file = stor.read_file(input)?;
+    let header_file = header_path.map(|path| stor.read_file(path)).transpose()?;
+
+    let raw_key = params
+        .key
+        .get_secret_for(&PasswordState::Direct, header_path.unwrap_or(input))?;
 
     domain::unpack::execute(
         stor,
@@ -43,6 +56,9 @@ pub fn unpack(
             raw_key,
             on_decrypted_header: None,
             on_archive_info: None,
+            streams,
+            filters,
+            limits,
             on_zip_file: Some(Box::new(move |file_path| {
                 let file_name = file_path
                     .file_name()
@@ -77,5 +93,52 @@ pub fn unpack(
         super::hashing::hash_stream(&[input.to_string()])?;
     }
 
+    if let Some(original) = verify_against {
+        verify(original, output)?;
+    }
+
     Ok(())
 }
+
+// files are extracted to the same (relative) path that they were packed from
+// so the original directory should be found at that path, within the output directory
+fn verify(original: &str, output: &str) -> Result<()> {
+    let mut unpacked = PathBuf::from(output);
+    unpacked.extend(
+        Path::new(original)
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_))),
+    );
+
+    let report = domain::compare::execute(
+        Arc::new(domain::storage::FileStorage),
+        domain::compare::Request {
+            original_dir_path: PathBuf::from(original),
+            target_dir_path: unpacked.clone(),
+        },
+    )?;
+
+    if report.is_identical() {
+        success!("{} matches {}", unpacked.display(), original);
+        return Ok(());
+    }
+
+    for path in &report.missing {
+        warn!("Missing: {}", path.display());
+    }
+    for path in &report.extra {
+        warn!("Extra: {}", path.display());
+    }
+    for path in &report.different {
+        warn!("Differs: {}", path.display());
+    }
+
+    Err(anyhow::anyhow!(
+        "{} does not match {} ({} missing, {} extra, {} different)",
+        unpacked.display(),
+        original,
+        report.missing.len(),
+        report.extra.len(),
+        report.different.len()
+    ))
+}
@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use core::header::{HashingAlgorithm, Header, HEADER_VERSION};
+use core::protected::Protected;
+use domain::storage::{Entry, FileStorage, Storage};
+
+use crate::global::policy::Policy;
+use crate::global::states::{Key, PasswordState};
+use crate::{info, success};
+
+pub struct Request<'a> {
+    pub inputs: &'a [String],
+    pub key: &'a Key,
+    pub hashing_algorithm: HashingAlgorithm,
+    pub policy: Option<&'a Policy>,
+}
+
+// a header's key may need to be hashed differently, depending on whether its keyfile was hashed and whether it requires two factors
+type KeyForm = (bool, bool);
+
+// directories are searched for dexios files, while files that are named directly must have a valid header
+fn find_files(
+    stor: &FileStorage,
+    inputs: &[String],
+) -> Result<Vec<(Entry<std::fs::File>, Header)>> {
+    let mut files = Vec::new();
+
+    for input in inputs {
+        let entry = stor.read_file(input)?;
+        if !entry.is_dir() {
+            let (header, _) = Header::deserialize(&mut *entry.try_reader()?.borrow_mut())
+                .with_context(|| format!("Unable to read the header of {}", input))?;
+            files.push((entry, header));
+            continue;
+        }
+
+        let mut entries = stor.read_dir(&entry)?;
+        entries.retain(|e| !e.is_dir());
+        entries.sort_by(|a, b| a.path().cmp(b.path()));
+
+        for entry in entries {
+            let Ok((header, _)) = Header::deserialize(&mut *entry.try_reader()?.borrow_mut())
+            else {
+                continue;
+            };
+            files.push((entry, header));
+        }
+    }
+
+    Ok(files)
+}
+
+// this upgrades every file (and every dexios file within each directory) to the latest header version, in place
+// each file is only replaced once its upgraded copy is complete
+// the key is only asked for once, unless some of the files need it in another form (e.g. with a hashed keyfile)
+pub fn upgrade(req: &Request<'_>) -> Result<()> {
+    let stor = Arc::new(FileStorage);
+
+    if let Some(policy) = req.policy {
+        policy.check_keyslot(&req.hashing_algorithm)?;
+    }
+
+    let mut files = find_files(&stor, req.inputs)?;
+    let latest = files
+        .iter()
+        .filter(|(_, header)| header.header_type.version >= HEADER_VERSION)
+        .count();
+    files.retain(|(_, header)| header.header_type.version < HEADER_VERSION);
+
+    if latest > 0 {
+        info!(
+            "Skipping {} {} already using {}",
+            latest,
+            if latest == 1 {
+                "file that's"
+            } else {
+                "files that are"
+            },
+            HEADER_VERSION
+        );
+    }
+
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    if req.key.prompts() {
+        info!("Please enter your key below");
+    }
+
+    let mut secrets: Vec<(KeyForm, Protected<Vec<u8>>)> = Vec::new();
+    for (input_file, header) in &files {
+        let form = (header.keyfile_hash, header.two_factor);
+        let raw_key = match secrets.iter().find(|(f, _)| *f == form) {
+            Some((_, secret)) => secret.clone(),
+            None => {
+                let secret = req
+                    .key
+                    .get_secret_for_header(&PasswordState::Direct, header)?;
+                secrets.push((form, secret.clone()));
+                secret
+            }
+        };
+
+        let path = input_file.path();
+        let output_file = stor.create_temp_file_beside(path)?;
+        let result = domain::upgrade::execute(domain::upgrade::Request {
+            reader: input_file.try_reader()?,
+            writer: output_file.try_writer()?,
+            raw_key,
+            hash_algorithm: req.hashing_algorithm,
+        });
+
+        let response = match result {
+            Ok(response) => response,
+            Err(e) => {
+                stor.remove_file(output_file).ok();
+                return Err(e).with_context(|| format!("Unable to upgrade {}", path.display()));
+            }
+        };
+
+        stor.flush_file(&output_file)?;
+        stor.persist_file(output_file, path)?;
+
+        success!(
+            "{} {} from {} to {}",
+            if response.rewrapped {
+                "Rewrapped"
+            } else {
+                "Re-encrypted"
+            },
+            path.display(),
+            response.version,
+            HEADER_VERSION
+        );
+    }
+
+    Ok(())
+}